@@ -0,0 +1,395 @@
+//! Drive and GCS clients for use outside of sync_app: configured through a
+//! builder, taking plain paths and ids and returning the api types.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), anyhow::Error> {
+//! use gdrive_lib::client::DriveClient;
+//!
+//! let drive = DriveClient::builder()
+//!     .secret_file("/home/user/.config/gdrive/client_secrets.json")
+//!     .token_dir("/home/user/.gdrive")
+//!     .session("user@gmail.com")
+//!     .build()
+//!     .await?;
+//! let token = drive.start_page_token().await?;
+//! for file in drive.list_files().await? {
+//!     println!("{:?}", file.name);
+//! }
+//! let (changes, _next_token) = drive.changes_since(token).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use url::Url;
+
+use crate::{
+    drive_v3_types::{Change, File},
+    exponential_retry_counted,
+    gcs_instance::GcsInstance,
+    gdrive_instance::GDriveInstance,
+    storage_v1_types::{Bucket, Object},
+    transfer_stats::TransferStats,
+};
+
+#[derive(Debug, Default, Clone)]
+pub struct DriveClientBuilder {
+    secret_file: Option<PathBuf>,
+    token_dir: Option<PathBuf>,
+    session: Option<StackString>,
+    ca_bundle: Option<PathBuf>,
+    page_size: Option<i32>,
+    max_keys: Option<usize>,
+    metadata_ttl: Option<Duration>,
+}
+
+impl DriveClientBuilder {
+    /// OAuth client secret (installed application) of the Drive api
+    #[must_use]
+    pub fn secret_file(mut self, secret_file: impl Into<PathBuf>) -> Self {
+        self.secret_file = Some(secret_file.into());
+        self
+    }
+
+    /// Directory the session's token is stored in
+    #[must_use]
+    pub fn token_dir(mut self, token_dir: impl Into<PathBuf>) -> Self {
+        self.token_dir = Some(token_dir.into());
+        self
+    }
+
+    /// Name of the token, usually the account's email address
+    #[must_use]
+    pub fn session(mut self, session: impl Into<StackString>) -> Self {
+        self.session = Some(session.into());
+        self
+    }
+
+    /// Pem file of certificates trusted on top of the system roots
+    #[must_use]
+    pub fn ca_bundle(mut self, ca_bundle: impl Into<PathBuf>) -> Self {
+        self.ca_bundle = Some(ca_bundle.into());
+        self
+    }
+
+    #[must_use]
+    pub fn page_size(mut self, page_size: i32) -> Self {
+        self.page_size = Some(page_size);
+        self
+    }
+
+    /// Stop listing after this many files
+    #[must_use]
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    /// How long file metadata is cached
+    #[must_use]
+    pub fn metadata_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata_ttl = Some(ttl);
+        self
+    }
+
+    /// # Errors
+    /// Return error if a required setting is missing or authentication fails
+    pub async fn build(self) -> Result<DriveClient, Error> {
+        let secret_file = self
+            .secret_file
+            .ok_or_else(|| format_err!("DriveClient needs a secret_file"))?;
+        let token_dir = self
+            .token_dir
+            .ok_or_else(|| format_err!("DriveClient needs a token_dir"))?;
+        let session = self
+            .session
+            .ok_or_else(|| format_err!("DriveClient needs a session"))?;
+        let mut instance = GDriveInstance::new_with_ca_bundle(
+            &token_dir,
+            &secret_file,
+            &session,
+            self.ca_bundle.as_deref(),
+        )
+        .await?;
+        if let Some(page_size) = self.page_size {
+            instance = instance.with_page_size(page_size);
+        }
+        if let Some(max_keys) = self.max_keys {
+            instance = instance.with_max_keys(max_keys);
+        }
+        if let Some(ttl) = self.metadata_ttl {
+            instance = instance.with_metadata_ttl(ttl);
+        }
+        Ok(DriveClient { instance })
+    }
+}
+
+/// Google Drive client, every call is rate limited and retried with backoff
+#[derive(Clone, Debug)]
+pub struct DriveClient {
+    instance: GDriveInstance,
+}
+
+impl DriveClient {
+    #[must_use]
+    pub fn builder() -> DriveClientBuilder {
+        DriveClientBuilder::default()
+    }
+
+    /// The underlying instance, for calls the client doesn't wrap
+    #[must_use]
+    pub fn instance(&self) -> &GDriveInstance {
+        &self.instance
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn probe(&self) -> Result<(), Error> {
+        self.instance.probe().await
+    }
+
+    /// Every file not in the trash, folders excluded
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_files(&self) -> Result<Vec<File>, Error> {
+        self.instance.get_all_files(false).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_folders(&self) -> Result<Vec<File>, Error> {
+        self.instance.get_all_files(true).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn metadata(&self, id: &str) -> Result<File, Error> {
+        self.instance.get_file_metadata(id).await
+    }
+
+    /// Token to pass to `changes_since` to get the changes from now on
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn start_page_token(&self) -> Result<usize, Error> {
+        self.instance.get_start_page_token().await
+    }
+
+    /// Changes since `start_page_token`, along with the token to pass next
+    /// time
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn changes_since(
+        &self,
+        start_page_token: usize,
+    ) -> Result<(Vec<Change>, usize), Error> {
+        let next_token = self.instance.get_start_page_token().await?;
+        self.instance.start_page_token.store(Some(start_page_token));
+        let changes = self.instance.get_all_changes().await?;
+        Ok((changes, next_token))
+    }
+
+    /// Download file `id` to `local`, google documents are exported to an
+    /// open format
+    /// # Errors
+    /// Return error if api call fails or the file can't be exported
+    pub async fn download(&self, id: &str, local: &Path) -> Result<(), Error> {
+        let metadata = self.metadata(id).await?;
+        self.instance.download(id, local, &metadata.mime_type).await
+    }
+
+    /// Upload `local` into folder `parent_id`
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn upload(&self, local: &Path, parent_id: &str) -> Result<File, Error> {
+        let url = Url::from_file_path(local)
+            .map_err(|()| format_err!("{} is not an absolute path", local.display()))?;
+        exponential_retry_counted(TransferStats::global(), || {
+            self.instance.upload(&url, parent_id)
+        })
+        .await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn create_folder(&self, name: &str, parent_id: &str) -> Result<File, Error> {
+        self.instance.create_folder(name, parent_id).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn trash(&self, id: &str) -> Result<(), Error> {
+        self.instance.move_to_trash(id).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn delete(&self, id: &str) -> Result<(), Error> {
+        self.instance.delete_permanently(id).await
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GcsClientBuilder {
+    key_file: Option<PathBuf>,
+    token_dir: Option<PathBuf>,
+    session: Option<StackString>,
+    ca_bundle: Option<PathBuf>,
+}
+
+impl GcsClientBuilder {
+    /// Service account key of the Storage api
+    #[must_use]
+    pub fn key_file(mut self, key_file: impl Into<PathBuf>) -> Self {
+        self.key_file = Some(key_file.into());
+        self
+    }
+
+    /// Directory the session's token is stored in
+    #[must_use]
+    pub fn token_dir(mut self, token_dir: impl Into<PathBuf>) -> Self {
+        self.token_dir = Some(token_dir.into());
+        self
+    }
+
+    /// Name of the token
+    #[must_use]
+    pub fn session(mut self, session: impl Into<StackString>) -> Self {
+        self.session = Some(session.into());
+        self
+    }
+
+    /// Pem file of certificates trusted on top of the system roots
+    #[must_use]
+    pub fn ca_bundle(mut self, ca_bundle: impl Into<PathBuf>) -> Self {
+        self.ca_bundle = Some(ca_bundle.into());
+        self
+    }
+
+    /// # Errors
+    /// Return error if a required setting is missing or authentication fails
+    pub async fn build(self) -> Result<GcsClient, Error> {
+        let key_file = self
+            .key_file
+            .ok_or_else(|| format_err!("GcsClient needs a key_file"))?;
+        let token_dir = self
+            .token_dir
+            .ok_or_else(|| format_err!("GcsClient needs a token_dir"))?;
+        let session = self
+            .session
+            .ok_or_else(|| format_err!("GcsClient needs a session"))?;
+        let instance = GcsInstance::new_with_ca_bundle(
+            &token_dir,
+            &key_file,
+            &session,
+            self.ca_bundle.as_deref(),
+        )
+        .await?;
+        Ok(GcsClient { instance })
+    }
+}
+
+/// Google Cloud Storage client, every call is rate limited and retried with
+/// backoff
+#[derive(Clone, Debug)]
+pub struct GcsClient {
+    instance: GcsInstance,
+}
+
+impl GcsClient {
+    #[must_use]
+    pub fn builder() -> GcsClientBuilder {
+        GcsClientBuilder::default()
+    }
+
+    /// The underlying instance, for calls the client doesn't wrap
+    #[must_use]
+    pub fn instance(&self) -> &GcsInstance {
+        &self.instance
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn probe(&self, bucket: &str) -> Result<(), Error> {
+        self.instance.probe(bucket).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_buckets(&self, project: &str) -> Result<Vec<Bucket>, Error> {
+        self.instance.get_list_of_buckets(project).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_objects(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<Object>, Error> {
+        self.instance.get_list_of_keys(bucket, prefix).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn download(&self, bucket: &str, key: &str, local: &Path) -> Result<(), Error> {
+        let local = local.to_string_lossy();
+        self.instance.download(bucket, key, &local).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn upload(&self, local: &Path, bucket: &str, key: &str) -> Result<(), Error> {
+        let local = local.to_string_lossy();
+        self.instance.upload(&local, bucket, key).await
+    }
+
+    /// Server side copy of `gs://<bucket>/<key>` `source`, returns the md5 of
+    /// the copy
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn copy(
+        &self,
+        source: &Url,
+        bucket: &str,
+        key: &str,
+    ) -> Result<Option<String>, Error> {
+        self.instance.copy_key(source, bucket, key).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn delete(&self, bucket: &str, key: &str) -> Result<(), Error> {
+        self.instance.delete_key(bucket, key).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::client::{DriveClient, GcsClient};
+
+    #[tokio::test]
+    async fn test_builder_requires_settings() -> Result<(), Error> {
+        let result = DriveClient::builder()
+            .secret_file("/tmp/client_secrets.json")
+            .session("user@gmail.com")
+            .build()
+            .await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().to_string(),
+            "DriveClient needs a token_dir"
+        );
+        let result = GcsClient::builder().token_dir("/tmp").build().await;
+        assert_eq!(
+            result.map(|_| ()).unwrap_err().to_string(),
+            "GcsClient needs a key_file"
+        );
+        Ok(())
+    }
+}
@@ -1,5 +1,7 @@
+#[cfg(feature = "postgres")]
 use bytes::BytesMut;
 use derive_more::{Deref, DerefMut, From, Into};
+#[cfg(feature = "postgres")]
 use postgres_types::{FromSql, IsNull, ToSql, Type};
 use serde::{Deserialize, Serialize};
 use std::fmt;
@@ -104,6 +106,7 @@ mod iso8601 {
     }
 }
 
+#[cfg(feature = "postgres")]
 impl FromSql<'_> for DateTimeWrapper {
     fn from_sql(
         type_: &Type,
@@ -117,6 +120,7 @@ impl FromSql<'_> for DateTimeWrapper {
     }
 }
 
+#[cfg(feature = "postgres")]
 impl ToSql for DateTimeWrapper {
     fn to_sql(
         &self,
@@ -0,0 +1,285 @@
+use anyhow::{format_err, Error};
+use parking_lot::Mutex;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{
+    env::var,
+    fmt,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use tokio::time::sleep;
+
+/// Faults injected into requests below the retry loop, read from
+/// `SYNC_APP_FAULT_*` env variables, e.g. `SYNC_APP_FAULT_ERROR_RATE=0.2
+/// SYNC_APP_FAULT_SEED=7`.  Only honoured by test builds and builds with the
+/// `fault_injection` feature.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FaultConfig {
+    /// Delay added before every request
+    pub latency_ms: u64,
+    /// Up to this much more delay, drawn per request
+    pub jitter_ms: u64,
+    /// Fraction of requests failing before they are sent
+    pub error_rate: f64,
+    /// Fraction of transfers cut off half way: only the first half of the
+    /// bytes go over the wire and the attempt fails
+    pub truncate_rate: f64,
+    /// The first `fail_first` requests fail whatever `error_rate` is, so
+    /// that a retry is sure to be needed
+    pub fail_first: usize,
+    /// The first `truncate_first` transfers are cut off whatever
+    /// `truncate_rate` is
+    pub truncate_first: usize,
+    /// Seed of the draws, the same seed and order of requests fail the same
+    /// requests
+    pub seed: u64,
+}
+
+fn env_var<T>(name: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    match var(format!("SYNC_APP_FAULT_{name}")) {
+        Ok(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|e| format_err!("Invalid SYNC_APP_FAULT_{name} {value}: {e}")),
+        Err(_) => Ok(None),
+    }
+}
+
+impl FaultConfig {
+    /// `None` unless some fault is configured
+    /// # Errors
+    /// Return error if a variable doesn't parse
+    pub fn from_env() -> Result<Option<Self>, Error> {
+        let config = Self {
+            latency_ms: env_var("LATENCY_MS")?.unwrap_or(0),
+            jitter_ms: env_var("JITTER_MS")?.unwrap_or(0),
+            error_rate: env_var("ERROR_RATE")?.unwrap_or(0.0),
+            truncate_rate: env_var("TRUNCATE_RATE")?.unwrap_or(0.0),
+            fail_first: env_var("FAIL_FIRST")?.unwrap_or(0),
+            truncate_first: env_var("TRUNCATE_FIRST")?.unwrap_or(0),
+            seed: env_var("SEED")?.unwrap_or(0),
+        };
+        Ok(if config.is_enabled() {
+            Some(config)
+        } else {
+            None
+        })
+    }
+
+    #[must_use]
+    pub fn is_enabled(&self) -> bool {
+        self.latency_ms > 0
+            || self.jitter_ms > 0
+            || self.error_rate > 0.0
+            || self.truncate_rate > 0.0
+            || self.fail_first > 0
+            || self.truncate_first > 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+    Error,
+    Truncate,
+}
+
+impl FaultKind {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Truncate => "truncation",
+        }
+    }
+}
+
+impl fmt::Display for FaultKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Error returned in place of the request's result, tests can tell it apart
+/// from real failures with `downcast_ref`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectedFault {
+    pub op: &'static str,
+    pub kind: FaultKind,
+    /// Index of the request (or transfer) among all those drawn by the same
+    /// injector
+    pub call: usize,
+}
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "injected {} in {} (call {})",
+            self.kind, self.op, self.call
+        )
+    }
+}
+
+impl std::error::Error for InjectedFault {}
+
+/// Draws the delay and fate of each request from a seeded generator
+#[derive(Debug)]
+pub struct FaultInjector {
+    config: FaultConfig,
+    rng: Mutex<StdRng>,
+    calls: AtomicUsize,
+    transfers: AtomicUsize,
+}
+
+#[cfg(any(test, feature = "fault_injection"))]
+static FAULT_INJECTOR: once_cell::sync::Lazy<Option<FaultInjector>> =
+    once_cell::sync::Lazy::new(|| match FaultConfig::from_env() {
+        Ok(config) => config.map(|config| {
+            log::warn!("injecting faults {config:?}");
+            FaultInjector::new(config)
+        }),
+        Err(e) => {
+            log::warn!("not injecting faults: {e}");
+            None
+        }
+    });
+
+impl FaultInjector {
+    #[must_use]
+    pub fn new(config: FaultConfig) -> Self {
+        Self {
+            rng: Mutex::new(StdRng::seed_from_u64(config.seed)),
+            config,
+            calls: AtomicUsize::new(0),
+            transfers: AtomicUsize::new(0),
+        }
+    }
+
+    /// The injector configured by `SYNC_APP_FAULT_*`, always `None` in
+    /// release builds without the `fault_injection` feature
+    #[must_use]
+    pub fn global() -> Option<&'static Self> {
+        #[cfg(any(test, feature = "fault_injection"))]
+        return FAULT_INJECTOR.as_ref();
+        #[cfg(not(any(test, feature = "fault_injection")))]
+        return None;
+    }
+
+    #[must_use]
+    pub fn calls(&self) -> usize {
+        self.calls.load(Ordering::SeqCst)
+    }
+
+    /// Sleep for the request's latency, then fail it if its draw came up,
+    /// returns the index of the request
+    /// # Errors
+    /// Return `InjectedFault` for a failing request
+    pub async fn before(&self, op: &'static str) -> Result<usize, Error> {
+        let call = self.calls.fetch_add(1, Ordering::SeqCst);
+        let (delay, fail) = {
+            let mut rng = self.rng.lock();
+            let jitter = if self.config.jitter_ms > 0 {
+                rng.gen_range(0..=self.config.jitter_ms)
+            } else {
+                0
+            };
+            let fail = rng.gen_bool(self.config.error_rate.clamp(0.0, 1.0));
+            (
+                self.config.latency_ms + jitter,
+                fail || call < self.config.fail_first,
+            )
+        };
+        if delay > 0 {
+            sleep(Duration::from_millis(delay)).await;
+        }
+        if fail {
+            return Err(InjectedFault {
+                op,
+                kind: FaultKind::Error,
+                call,
+            }
+            .into());
+        }
+        Ok(call)
+    }
+
+    /// How many of the `length` bytes about to be transferred get through,
+    /// with the fault to return once they have, `None` for a transfer left
+    /// alone
+    #[must_use]
+    pub fn truncate(&self, op: &'static str, length: u64) -> Option<(u64, InjectedFault)> {
+        let call = self.transfers.fetch_add(1, Ordering::SeqCst);
+        let cut = self
+            .rng
+            .lock()
+            .gen_bool(self.config.truncate_rate.clamp(0.0, 1.0));
+        if (cut || call < self.config.truncate_first) && length > 1 {
+            let fault = InjectedFault {
+                op,
+                kind: FaultKind::Truncate,
+                call,
+            };
+            Some((length / 2, fault))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::fault_injection::{FaultConfig, FaultInjector, FaultKind, InjectedFault};
+
+    #[tokio::test]
+    async fn test_fault_injector_is_deterministic() -> Result<(), Error> {
+        let config = FaultConfig {
+            error_rate: 0.5,
+            fail_first: 2,
+            seed: 1234,
+            ..FaultConfig::default()
+        };
+        let outcomes = |faults: FaultInjector| async move {
+            let mut outcomes = Vec::new();
+            for _ in 0..20 {
+                outcomes.push(faults.before("request").await.is_ok());
+            }
+            outcomes
+        };
+        let first = outcomes(FaultInjector::new(config.clone())).await;
+        let second = outcomes(FaultInjector::new(config)).await;
+        assert_eq!(first, second);
+        assert!(!first[0] && !first[1]);
+        assert!(first.iter().any(|ok| *ok));
+        assert!(first.iter().any(|ok| !*ok));
+
+        let faults = FaultInjector::new(FaultConfig {
+            fail_first: 1,
+            truncate_first: 1,
+            ..FaultConfig::default()
+        });
+        let e = faults.before("request").await.unwrap_err();
+        assert_eq!(
+            e.downcast_ref::<InjectedFault>(),
+            Some(&InjectedFault {
+                op: "request",
+                kind: FaultKind::Error,
+                call: 0
+            })
+        );
+        assert_eq!(faults.before("request").await?, 1);
+        assert_eq!(faults.calls(), 2);
+        let (length, fault) = faults.truncate("upload", 10).unwrap();
+        assert_eq!(length, 5);
+        assert_eq!(fault.kind, FaultKind::Truncate);
+        assert_eq!(faults.truncate("upload", 10), None);
+        assert!(!FaultConfig::default().is_enabled());
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
 use anyhow::{format_err, Error};
 use async_google_apis_common as common;
 use common::{
-    yup_oauth2::{self, hyper, ServiceAccountAuthenticator},
-    DownloadResult, TlsClient,
+    yup_oauth2::{self, ServiceAccountAuthenticator},
+    DownloadResult,
 };
 use log::debug;
 use once_cell::sync::Lazy;
@@ -17,26 +17,19 @@ use stdout_channel::rate_limiter::RateLimiter;
 use tokio::fs::{self, create_dir_all};
 
 use crate::{
-    exponential_retry,
+    exponential_retry_counted,
     storage_v1_types::{
-        Bucket, BucketsListParams, BucketsService, Object, ObjectsCopyParams, ObjectsDeleteParams,
-        ObjectsGetParams, ObjectsInsertParams, ObjectsListParams, ObjectsService, StorageParams,
-        StorageParamsAlt,
+        Bucket, BucketsGetParams, BucketsListParams, BucketsService, Object, ObjectsCopyParams,
+        ObjectsDeleteParams, ObjectsGetParams, ObjectsInsertParams, ObjectsListParams,
+        ObjectsService, StorageParams, StorageParamsAlt,
     },
+    tls::https_client,
+    transfer_stats::TransferStats,
 };
 use url::Url;
 
 static GCSINSTANCE_TEST_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
-fn https_client() -> TlsClient {
-    let conn = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .build();
-    hyper::Client::builder().build(conn)
-}
-
 #[derive(Clone)]
 pub struct GcsInstance {
     buckets: Arc<BucketsService>,
@@ -57,9 +50,22 @@ impl GcsInstance {
         gcs_token_path: &Path,
         gcs_secret_file: &Path,
         session_name: &str,
+    ) -> Result<Self, Error> {
+        Self::new_with_ca_bundle(gcs_token_path, gcs_secret_file, session_name, None).await
+    }
+
+    /// Instance trusting the certificates in `ca_bundle` on top of the system
+    /// roots
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn new_with_ca_bundle(
+        gcs_token_path: &Path,
+        gcs_secret_file: &Path,
+        session_name: &str,
+        ca_bundle: Option<&Path>,
     ) -> Result<Self, Error> {
         debug!("{:?}", gcs_secret_file);
-        let https = https_client();
+        let https = https_client(ca_bundle)?;
         let sec = yup_oauth2::read_service_account_key(gcs_secret_file).await?;
 
         let token_file = gcs_token_path.join(format_sstr!("{session_name}.json"));
@@ -94,6 +100,18 @@ impl GcsInstance {
         GCSINSTANCE_TEST_MUTEX.lock()
     }
 
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn probe(&self, bucket: &str) -> Result<(), Error> {
+        let params = BucketsGetParams {
+            bucket: bucket.into(),
+            ..BucketsGetParams::default()
+        };
+        self.rate_limit.acquire().await;
+        self.buckets.get(&params).await?;
+        Ok(())
+    }
+
     /// # Errors
     /// Return error if api call fails
     pub async fn get_list_of_keys(
@@ -114,7 +132,7 @@ impl GcsInstance {
         let mut output = Vec::new();
         loop {
             params.page_token = npt.take();
-            let result = exponential_retry(|| async {
+            let result = exponential_retry_counted(TransferStats::gcs(), || async {
                 self.rate_limit.acquire().await;
                 self.objects.list(&params).await
             })
@@ -133,6 +151,8 @@ impl GcsInstance {
         Ok(output)
     }
 
+    /// Run `callback` on each key as the pages come in, listing stops once
+    /// `callback` returns `false`
     /// # Errors
     /// Return error if api call fails
     pub async fn process_list_of_keys<T>(
@@ -142,7 +162,7 @@ impl GcsInstance {
         callback: T,
     ) -> Result<(), Error>
     where
-        T: Fn(&Object) -> Result<(), Error> + Send + Sync,
+        T: Fn(&Object) -> Result<bool, Error> + Send + Sync,
     {
         let mut params = ObjectsListParams {
             bucket: bucket.into(),
@@ -156,14 +176,16 @@ impl GcsInstance {
         let mut npt = None;
         loop {
             params.page_token = npt.take();
-            let result = exponential_retry(|| async {
+            let result = exponential_retry_counted(TransferStats::gcs(), || async {
                 self.rate_limit.acquire().await;
                 self.objects.list(&params).await
             })
             .await?;
             if let Some(items) = result.items.as_ref() {
                 for item in items {
-                    callback(item)?;
+                    if !callback(item)? {
+                        return Ok(());
+                    }
                 }
             } else {
                 break;
@@ -195,7 +217,7 @@ impl GcsInstance {
             object: key_name.into(),
             ..ObjectsGetParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::gcs(), || async {
             self.rate_limit.acquire().await;
             let mut f = fs::File::create(fname).await?;
             let mut download = self.objects.get(&params).await?;
@@ -222,7 +244,7 @@ impl GcsInstance {
             ..ObjectsInsertParams::default()
         };
         let obj = Object::default();
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::gcs(), || async {
             let f = fs::File::open(fname).await?;
             self.rate_limit.acquire().await;
             self.objects
@@ -253,7 +275,7 @@ impl GcsInstance {
             destination_object: key_to.into(),
             ..ObjectsCopyParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::gcs(), || async {
             self.rate_limit.acquire().await;
             let obj = Object::default();
             let result = self.objects.copy(&params, &obj).await?;
@@ -270,7 +292,7 @@ impl GcsInstance {
             object: key_name.into(),
             ..ObjectsDeleteParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::gcs(), || async {
             self.rate_limit.acquire().await;
             self.objects.delete(&params).await.map_err(Into::into)
         })
@@ -288,7 +310,7 @@ impl GcsInstance {
         let mut output = Vec::new();
         loop {
             params.page_token = npt.take();
-            let result = exponential_retry(|| async {
+            let result = exponential_retry_counted(TransferStats::gcs(), || async {
                 self.rate_limit.acquire().await;
                 self.buckets.list(&params).await
             })
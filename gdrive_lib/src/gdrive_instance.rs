@@ -1,54 +1,74 @@
 use anyhow::{format_err, Error};
 use async_google_apis_common as common;
 use common::{
-    yup_oauth2::{self, hyper, InstalledFlowAuthenticator},
-    DownloadResult, TlsClient,
+    yup_oauth2::{
+        self,
+        authenticator::Authenticator,
+        hyper::{
+            body::{to_bytes, HttpBody},
+            client::HttpConnector,
+            header::{
+                HeaderMap, AUTHORIZATION, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, LOCATION,
+                RANGE,
+            },
+            Body, Request, Response, StatusCode,
+        },
+        InstalledFlowAuthenticator,
+    },
+    ApiError, DownloadResult, TlsClient,
 };
 use crossbeam::atomic::AtomicCell;
 use futures::future::try_join_all;
+use hyper_rustls::HttpsConnector;
 use itertools::Itertools;
 use log::debug;
 use maplit::{hashmap, hashset};
+use md5::{Digest, Md5};
 use mime::Mime;
 use once_cell::sync::Lazy;
-use percent_encoding::percent_decode;
+use percent_encoding::{percent_decode, utf8_percent_encode, NON_ALPHANUMERIC};
 use stack_string::{format_sstr, StackString};
 
 use std::{
     collections::{HashMap, HashSet},
-    ffi::OsStr,
+    ffi::{OsStr, OsString},
     fmt::{self, Debug, Formatter},
     future::Future,
+    io::{Read, SeekFrom},
     path::{Path, PathBuf},
     string::ToString,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
 };
 use stdout_channel::rate_limiter::RateLimiter;
 use tokio::{
     fs::{self, create_dir_all},
-    io::AsyncReadExt,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    task::spawn_blocking,
 };
 use url::Url;
 
 use crate::{
+    date_time_wrapper::DateTimeWrapper,
     directory_info::DirectoryInfo,
     drive_v3_types::{
-        Change, ChangesGetStartPageTokenParams, ChangesListParams, ChangesService, DriveParams,
-        DriveParamsAlt, DriveScopes, File, FileList, FilesCreateParams, FilesDeleteParams,
-        FilesExportParams, FilesGetParams, FilesListParams, FilesService, FilesUpdateParams,
+        AboutGetParams, AboutService, Change, ChangesGetStartPageTokenParams, ChangesListParams,
+        ChangesService, DriveParams, DriveParamsAlt, DriveScopes, File, FileList,
+        FilesCreateParams, FilesDeleteParams, FilesExportParams, FilesGetParams, FilesListParams,
+        FilesService, FilesUpdateParams,
     },
-    exponential_retry,
+    exponential_retry_counted,
+    fault_injection::FaultInjector,
+    metadata_cache::MetadataCache,
+    page_size::AdaptivePageSize,
+    tls::https_client,
+    token_file,
+    transfer_stats::TransferStats,
 };
 
-fn https_client() -> TlsClient {
-    let conn = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_native_roots()
-        .https_only()
-        .enable_http1()
-        .build();
-    hyper::Client::builder().build(conn)
-}
-
 static MIME_TYPES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
     hashmap! {
         "application/vnd.google-apps.document" => "application/vnd.oasis.opendocument.text",
@@ -58,6 +78,21 @@ static MIME_TYPES: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
         "application/vnd.google-apps.site" => "text/plain",
     }
 });
+/// Extension given to the local copy of a file exported as each type of
+/// `MIME_TYPES`, so that the local name round trips to the same key
+static EXPORT_EXTENSIONS: Lazy<HashMap<&'static str, &'static str>> = Lazy::new(|| {
+    hashmap! {
+        "application/vnd.oasis.opendocument.text" => "odt",
+        "application/pdf" => "pdf",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "image/png" => "png",
+        "text/plain" => "txt",
+    }
+});
+/// Mime type of shortcuts, files standing in for another file or folder
+/// that `shortcutDetails` points to
+const SHORTCUT_MIME_TYPE: &str = "application/vnd.google-apps.shortcut";
+
 static UNEXPORTABLE_MIME_TYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     hashset! {
         "application/vnd.google-apps.form",
@@ -66,12 +101,49 @@ static UNEXPORTABLE_MIME_TYPES: Lazy<HashSet<&'static str>> = Lazy::new(|| {
     }
 });
 
+/// Media downloads request ranges of at most this many bytes, an interrupted
+/// download resumes from the end of its `.part` file
+const DOWNLOAD_CHUNK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Uploads are sent in chunks of this many bytes (a multiple of the 256KiB the
+/// api requires), an interrupted upload resumes after the last chunk received
+const UPLOAD_CHUNK_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Status line of a response, `HTTP/1.1 200 OK\r\n`
+const STATUS_LINE_SIZE: u64 = 17;
+
+/// The drive api reports missing files as a 404 with reason `notFound`
+fn is_not_found(e: &Error) -> bool {
+    e.chain().any(|e| match e.downcast_ref::<ApiError>() {
+        Some(ApiError::HTTPResponseError(status, body)) => {
+            *status == StatusCode::NOT_FOUND || body.contains("\"notFound\"")
+        }
+        _ => false,
+    })
+}
+
+/// State of a resumable upload session, see `GDriveInstance::upload_status`
+#[derive(Debug)]
+pub enum UploadStatus {
+    /// Bytes received so far
+    Active(u64),
+    Complete(Box<File>),
+    /// Sessions are dropped a week after they are created
+    Expired,
+}
+
+type GDriveAuth = Authenticator<HttpsConnector<HttpConnector>>;
+
 #[derive(Clone)]
 pub struct GDriveInstance {
+    client: TlsClient,
+    auth: Arc<GDriveAuth>,
     files: Arc<FilesService>,
     changes: Arc<ChangesService>,
-    page_size: i32,
+    about: Arc<AboutService>,
+    page_size: AdaptivePageSize,
     max_keys: Option<usize>,
+    metadata_cache: MetadataCache,
     session_name: StackString,
     pub start_page_token_filename: PathBuf,
     pub start_page_token: Arc<AtomicCell<Option<usize>>>,
@@ -91,10 +163,23 @@ impl GDriveInstance {
         gdrive_token_path: &Path,
         gdrive_secret_file: &Path,
         session_name: &str,
+    ) -> Result<Self, Error> {
+        Self::new_with_ca_bundle(gdrive_token_path, gdrive_secret_file, session_name, None).await
+    }
+
+    /// Instance trusting the certificates in `ca_bundle` on top of the system
+    /// roots
+    /// # Errors
+    /// Return error if intialization fails
+    pub async fn new_with_ca_bundle(
+        gdrive_token_path: &Path,
+        gdrive_secret_file: &Path,
+        session_name: &str,
+        ca_bundle: Option<&Path>,
     ) -> Result<Self, Error> {
         let fname = gdrive_token_path.join(format_sstr!("{session_name}_start_page_token"));
         debug!("{:?}", gdrive_secret_file);
-        let https = https_client();
+        let https = https_client(ca_bundle)?;
         let sec = yup_oauth2::read_application_secret(gdrive_secret_file).await?;
 
         let token_file = gdrive_token_path.join(format_sstr!("{session_name}.json"));
@@ -121,16 +206,23 @@ impl GDriveInstance {
         let mut files = FilesService::new(https.clone(), auth.clone());
         files.set_scopes(scopes.clone());
 
-        let mut changes = ChangesService::new(https, auth);
-        changes.set_scopes(scopes);
+        let mut changes = ChangesService::new(https.clone(), auth.clone());
+        changes.set_scopes(scopes.clone());
+
+        let mut about = AboutService::new(https.clone(), auth.clone());
+        about.set_scopes(scopes);
 
         let start_page_token = Self::read_start_page_token(&fname).await?;
 
         Ok(Self {
+            client: https,
+            auth,
             files: Arc::new(files),
             changes: Arc::new(changes),
-            page_size: 400,
+            about: Arc::new(about),
+            page_size: AdaptivePageSize::new(400),
             max_keys: None,
+            metadata_cache: MetadataCache::default(),
             session_name: session_name.into(),
             start_page_token: Arc::new(AtomicCell::new(start_page_token)),
             start_page_token_filename: fname,
@@ -146,10 +238,21 @@ impl GDriveInstance {
 
     #[must_use]
     pub fn with_page_size(mut self, page_size: i32) -> Self {
-        self.page_size = page_size;
+        self.page_size = AdaptivePageSize::new(page_size);
+        self
+    }
+
+    #[must_use]
+    pub fn with_metadata_ttl(mut self, ttl: Duration) -> Self {
+        self.metadata_cache = MetadataCache::new(ttl);
         self
     }
 
+    #[must_use]
+    pub fn metadata_cache(&self) -> &MetadataCache {
+        &self.metadata_cache
+    }
+
     /// # Errors
     /// Return error if intialization fails
     pub async fn read_start_page_token_from_file(&self) -> Result<(), Error> {
@@ -158,6 +261,21 @@ impl GDriveInstance {
         Ok(())
     }
 
+    /// Cheap call to verify that the api is reachable and the token is valid
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn probe(&self) -> Result<(), Error> {
+        let params = AboutGetParams {
+            drive_params: Some(DriveParams {
+                fields: Some("user".into()),
+                ..DriveParams::default()
+            }),
+        };
+        self.rate_limit.acquire().await;
+        self.about.get(&params).await?;
+        Ok(())
+    }
+
     async fn get_filelist(
         &self,
         page_token: Option<&StackString>,
@@ -178,18 +296,17 @@ impl GDriveInstance {
             "md5Checksum",
             "fileExtension",
             "webContentLink",
+            "shortcutDetails",
         ];
         let fields = format!("nextPageToken,files({})", fields.join(","));
         let p = DriveParams {
             fields: Some(fields),
             ..DriveParams::default()
         };
-        debug!("page_size {}", self.page_size);
         let mut params = FilesListParams {
             drive_params: Some(p),
             corpora: Some("user".into()),
             spaces: Some("drive".into()),
-            page_size: Some(self.page_size),
             page_token: page_token.map(Into::into),
             ..FilesListParams::default()
         };
@@ -212,13 +329,50 @@ impl GDriveInstance {
         debug!("query {}", query);
         params.q = Some(query);
 
-        exponential_retry(|| async {
+        // The page size is re-read on every attempt, so that a page that
+        // keeps failing is retried with a smaller page size
+        exponential_retry_counted(TransferStats::global(), || async {
+            let page_size = self.page_size.get();
+            debug!("page_size {page_size}");
+            let params = FilesListParams {
+                page_size: Some(page_size),
+                ..params.clone()
+            };
             self.rate_limit.acquire().await;
-            self.files.list(&params).await
+            match self.files.list(&params).await {
+                Ok(filelist) => {
+                    self.page_size.success();
+                    Ok(filelist)
+                }
+                Err(e) => {
+                    self.page_size.failure();
+                    Err(e)
+                }
+            }
         })
         .await
     }
 
+    #[must_use]
+    pub fn get_max_keys(&self) -> Option<usize> {
+        self.max_keys
+    }
+
+    /// Fetch a single page of files, returns the files along with the token
+    /// for the next page (if any)
+    /// # Errors
+    /// Return error if `get_filelist` fails
+    pub async fn get_files_page(
+        &self,
+        page_token: Option<&StackString>,
+        get_folders: bool,
+    ) -> Result<(Vec<File>, Option<StackString>), Error> {
+        let filelist = self.get_filelist(page_token, get_folders, None).await?;
+        let files = filelist.files.unwrap_or_default();
+        debug!("got files {}", files.len());
+        Ok((files, filelist.next_page_token.map(Into::into)))
+    }
+
     /// # Errors
     /// Return error if `get_filelist` fails
     pub async fn get_all_files(&self, get_folders: bool) -> Result<Vec<File>, Error> {
@@ -262,6 +416,110 @@ impl GDriveInstance {
             .await
     }
 
+    /// Id of the file `finfo` points to if it's a shortcut
+    #[must_use]
+    pub fn shortcut_target(finfo: &File) -> Option<&str> {
+        if finfo.mime_type.as_deref() != Some(SHORTCUT_MIME_TYPE) {
+            return None;
+        }
+        finfo
+            .shortcut_details
+            .as_ref()
+            .and_then(|d| d.target_id.as_deref())
+    }
+
+    /// Shortcuts pointing at any of `target_ids`, a change to a target
+    /// doesn't show up in the change list as a change to its shortcuts
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn get_shortcuts_to(&self, target_ids: &[StackString]) -> Result<Vec<File>, Error> {
+        let fields = [
+            "name",
+            "id",
+            "size",
+            "mimeType",
+            "owners",
+            "parents",
+            "trashed",
+            "modifiedTime",
+            "createdTime",
+            "viewedByMeTime",
+            "md5Checksum",
+            "fileExtension",
+            "webContentLink",
+            "shortcutDetails",
+        ];
+        let fields = format!("nextPageToken,files({})", fields.join(","));
+        let mut shortcuts = Vec::new();
+        for chunk in target_ids.chunks(50) {
+            let targets = chunk
+                .iter()
+                .map(|id| format_sstr!("shortcutDetails.targetId = '{id}'"))
+                .join(" or ");
+            let query = format_sstr!(
+                "mimeType = '{SHORTCUT_MIME_TYPE}' and trashed = false and ({targets})"
+            );
+            let mut page_token: Option<String> = None;
+            loop {
+                let params = FilesListParams {
+                    drive_params: Some(DriveParams {
+                        fields: Some(fields.clone()),
+                        ..DriveParams::default()
+                    }),
+                    corpora: Some("user".into()),
+                    spaces: Some("drive".into()),
+                    page_token: page_token.clone(),
+                    q: Some(query.to_string()),
+                    ..FilesListParams::default()
+                };
+                let filelist = exponential_retry_counted(TransferStats::global(), || async {
+                    self.rate_limit.acquire().await;
+                    self.files.list(&params).await
+                })
+                .await?;
+                shortcuts.extend(filelist.files.unwrap_or_default());
+                page_token = filelist.next_page_token;
+                if page_token.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(shortcuts)
+    }
+
+    /// A shortcut with the content metadata (mime type, size, md5,
+    /// modification time) of its target, so that it's listed as a copy of the
+    /// target at the shortcut's own path.  The id stays the shortcut's, a
+    /// download follows it to the target.  `None` for shortcuts to folders
+    /// and to files that no longer exist.
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn resolve_shortcut(&self, shortcut: &File) -> Result<Option<File>, Error> {
+        let target_id = match Self::shortcut_target(shortcut) {
+            Some(target_id) => target_id,
+            None => return Ok(None),
+        };
+        let target = match self.get_file_metadata(target_id).await {
+            Ok(target) => target,
+            Err(e) if is_not_found(&e) => {
+                debug!("shortcut {:?} to missing {target_id}", shortcut.id);
+                return Ok(None);
+            }
+            Err(e) => return Err(e),
+        };
+        if target.mime_type.as_deref() == Some("application/vnd.google-apps.folder") {
+            debug!("skipping shortcut {:?} to folder {target_id}", shortcut.id);
+            return Ok(None);
+        }
+        Ok(Some(File {
+            mime_type: target.mime_type,
+            size: target.size,
+            md5_checksum: target.md5_checksum,
+            modified_time: target.modified_time,
+            ..shortcut.clone()
+        }))
+    }
+
     /// # Errors
     /// Return error if `from_object` fails
     #[allow(clippy::manual_filter_map)]
@@ -270,8 +528,16 @@ impl GDriveInstance {
         flist: &[File],
         directory_map: &HashMap<StackString, DirectoryInfo>,
     ) -> Result<Vec<GDriveInfo>, Error> {
+        let mut resolved = Vec::new();
+        for f in flist {
+            if Self::shortcut_target(f).is_some() {
+                resolved.extend(self.resolve_shortcut(f).await?);
+            }
+        }
         let futures = flist
             .iter()
+            .filter(|f| Self::shortcut_target(f).is_none())
+            .chain(resolved.iter())
             .filter(|f| {
                 if let Some(owners) = f.owners.as_ref() {
                     if owners.is_empty() {
@@ -292,6 +558,8 @@ impl GDriveInstance {
         try_join_all(futures).await
     }
 
+    /// Run `callback` on each file as the pages come in, listing stops once
+    /// `callback` returns `false`
     /// # Errors
     /// Return error if `get_filelist` fails
     pub async fn process_list_of_keys<T, U>(
@@ -301,7 +569,7 @@ impl GDriveInstance {
     ) -> Result<(), Error>
     where
         T: Fn(File) -> U,
-        U: Future<Output = Result<(), Error>>,
+        U: Future<Output = Result<bool, Error>>,
     {
         let mut n_processed = 0;
         let mut page_token: Option<StackString> = None;
@@ -312,7 +580,9 @@ impl GDriveInstance {
 
             if let Some(files) = filelist.files.take() {
                 for f in files {
-                    callback(f).await?;
+                    if !callback(f).await? {
+                        return Ok(());
+                    }
                     n_processed += 1;
                 }
             }
@@ -331,12 +601,36 @@ impl GDriveInstance {
         Ok(())
     }
 
+    /// Metadata lookups go through `metadata_cache`, ids the api reported as
+    /// missing fail without another request until the entry expires
     /// # Errors
     /// Return error if api call fails
     pub async fn get_file_metadata(&self, id: &str) -> Result<File, Error> {
+        if let Some(cached) = self.metadata_cache.get(id) {
+            return cached.ok_or_else(|| format_err!("File {id} not found (cached)"));
+        }
+        match self.fetch_file_metadata(id).await {
+            Ok(f) => {
+                self.metadata_cache.insert(id, f.clone());
+                Ok(f)
+            }
+            Err(e) => {
+                if is_not_found(&e) {
+                    self.metadata_cache.insert_missing(id);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    async fn fetch_file_metadata(&self, id: &str) -> Result<File, Error> {
         let p = DriveParams {
             alt: Some(DriveParamsAlt::Json),
-            fields: Some("id,name,parents,mimeType,webContentLink".into()),
+            fields: Some(
+                "id,name,parents,mimeType,webContentLink,size,md5Checksum,modifiedTime,\
+                 shortcutDetails"
+                    .into(),
+            ),
             ..DriveParams::default()
         };
         let params = FilesGetParams {
@@ -344,7 +638,7 @@ impl GDriveInstance {
             file_id: id.into(),
             ..FilesGetParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             if let DownloadResult::Response(f) = self.files.get(&params).await?.do_it(None).await? {
                 Ok(f)
@@ -365,14 +659,20 @@ impl GDriveInstance {
             .file_name()
             .map(OsStr::to_string_lossy)
             .ok_or_else(|| format_err!("Failed to convert string"))?;
+        self.create_folder(&directory_name, parentid).await
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn create_folder(&self, name: &str, parentid: &str) -> Result<File, Error> {
         let new_file = File {
-            name: Some(directory_name.into_owned()),
+            name: Some(name.to_string()),
             mime_type: Some("application/vnd.google-apps.folder".to_string()),
             parents: Some(vec![parentid.to_string()]),
             ..File::default()
         };
         let params = FilesCreateParams::default();
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             self.files.create(&params, &new_file).await
         })
@@ -382,10 +682,23 @@ impl GDriveInstance {
     /// # Errors
     /// Return error if api call fails
     pub async fn upload(&self, local: &Url, parentid: &str) -> Result<File, Error> {
+        let session_uri = self.create_upload_session(local, parentid).await?;
+        self.resume_upload(&session_uri, local).await
+    }
+
+    /// Start a resumable upload of `local` into `parentid`, the returned
+    /// session uri can be passed to `resume_upload` by a later process
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn create_upload_session(
+        &self,
+        local: &Url,
+        parentid: &str,
+    ) -> Result<StackString, Error> {
         let file_path = local
             .to_file_path()
             .map_err(|e| format_err!("No file path {e:?}"))?;
-        let file_obj = fs::File::open(&file_path).await?;
+        let size = fs::metadata(&file_path).await?.len();
         let mime: Mime = "application/octet-stream"
             .parse()
             .map_err(|e| format_err!("bad mimetype {e:?}"))?;
@@ -399,18 +712,108 @@ impl GDriveInstance {
             mime_type: Some(mime.to_string()),
             ..File::default()
         };
+        let body = serde_json::to_vec(&new_file)?;
+
+        exponential_retry_counted(TransferStats::global(), || async {
+            let token = self.auth.token(&[DriveScopes::Drive]).await?;
+            let token = token
+                .token()
+                .ok_or_else(|| format_err!("No access token"))?;
+            let request = Request::post(
+                "https://www.googleapis.com/upload/drive/v3/files?uploadType=resumable",
+            )
+            .header(AUTHORIZATION, format_sstr!("Bearer {token}").as_str())
+            .header(CONTENT_TYPE, "application/json; charset=UTF-8")
+            .header("X-Upload-Content-Type", mime.as_ref())
+            .header("X-Upload-Content-Length", size)
+            .body(Body::from(body.clone()))?;
 
-        let params = FilesCreateParams {
-            ..FilesCreateParams::default()
-        };
+            self.rate_limit.acquire().await;
+            let response = self.send(request).await?;
+            if !response.status().is_success() {
+                return Err(format_err!(
+                    "Failed to start upload of {local}: {}",
+                    response.status()
+                ));
+            }
+            let session_uri = response
+                .headers()
+                .get(LOCATION)
+                .ok_or_else(|| format_err!("No upload session for {local}"))?
+                .to_str()?;
+            Ok(session_uri.into())
+        })
+        .await
+    }
 
+    /// Ask the api how much of a `size` byte upload it has received
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn upload_status(&self, session_uri: &str, size: u64) -> Result<UploadStatus, Error> {
+        let request = Request::put(session_uri)
+            .header(CONTENT_RANGE, format_sstr!("bytes */{size}").as_str())
+            .header(CONTENT_LENGTH, 0)
+            .body(Body::empty())?;
         self.rate_limit.acquire().await;
-        let upload = self
-            .files
-            .create_resumable_upload(&params, &new_file)
-            .await?;
-        let resp = upload.upload_file(file_obj).await?;
-        Ok(resp)
+        let response = self.send(request).await?;
+        upload_response(response).await
+    }
+
+    /// Send the rest of `local` to an upload session created by
+    /// `create_upload_session`, starting after the bytes it already has
+    /// # Errors
+    /// Return error if api call fails or the session has expired
+    pub async fn resume_upload(&self, session_uri: &str, local: &Url) -> Result<File, Error> {
+        let file_path = local
+            .to_file_path()
+            .map_err(|e| format_err!("No file path {e:?}"))?;
+        let size = fs::metadata(&file_path).await?.len();
+        resumable_upload(
+            local,
+            size,
+            || self.upload_status(session_uri, size),
+            |offset, end| self.upload_chunk(session_uri, &file_path, offset, end, size),
+            FaultInjector::global(),
+        )
+        .await
+    }
+
+    /// Send the bytes of `file_path` from `offset` up to `end`
+    async fn upload_chunk(
+        &self,
+        session_uri: &str,
+        file_path: &Path,
+        offset: u64,
+        end: u64,
+        size: u64,
+    ) -> Result<UploadStatus, Error> {
+        let mut f = fs::File::open(file_path).await?;
+        f.seek(SeekFrom::Start(offset)).await?;
+        let mut buf = Vec::with_capacity((end - offset) as usize);
+        f.take(end - offset).read_to_end(&mut buf).await?;
+        let content_range = if buf.is_empty() {
+            format_sstr!("bytes */{size}")
+        } else {
+            format_sstr!("bytes {offset}-{}/{size}", offset + buf.len() as u64 - 1)
+        };
+        let request = Request::put(session_uri)
+            .header(CONTENT_RANGE, content_range.as_str())
+            .header(CONTENT_LENGTH, buf.len())
+            .body(Body::from(buf))?;
+        self.rate_limit.acquire().await;
+        let response = self.send(request).await?;
+        upload_response(response).await
+    }
+
+    /// `client.request` with the request and the response headers counted
+    /// in `TransferStats`, the caller counts the response body as it reads it
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>, Error> {
+        let stats = TransferStats::global();
+        let body_size = request.body().size_hint().exact().unwrap_or(0);
+        stats.sent(request_head_size(&request) + body_size);
+        let response = self.client.request(request).await?;
+        stats.received(STATUS_LINE_SIZE + header_size(response.headers()));
+        Ok(response)
     }
 
     pub fn is_unexportable<T: AsRef<str>>(mime_type: &Option<T>) -> bool {
@@ -419,23 +822,72 @@ impl GDriveInstance {
         })
     }
 
+    /// Export mime type and local extension of a google-native file
+    pub fn export_format<T: AsRef<str>>(
+        mime_type: &Option<T>,
+    ) -> Option<(&'static str, &'static str)> {
+        let export_type = MIME_TYPES.get::<str>(mime_type.as_ref()?.as_ref())?;
+        let extension = EXPORT_EXTENSIONS.get(export_type)?;
+        Some((export_type, extension))
+    }
+
+    /// Name of the local copy of `finfo`: exported files get the extension of
+    /// the export type unless the name already ends with it
+    #[must_use]
+    pub fn export_name(finfo: &File) -> Option<StackString> {
+        let name = finfo.name.as_ref()?;
+        match Self::export_format(&finfo.mime_type) {
+            Some((_, extension))
+                if !name
+                    .to_lowercase()
+                    .ends_with(format_sstr!(".{extension}").as_str()) =>
+            {
+                Some(format_sstr!("{name}.{extension}"))
+            }
+            _ => Some(name.into()),
+        }
+    }
+
+    /// Export into a temporary file unique to this process, so concurrent
+    /// runs never write the same file, and move it to `local` once complete.
+    /// Returns the md5sum of the exported bytes.
     /// # Errors
     /// Return error if api call fails
-    pub async fn export(&self, gdriveid: &str, local: &Path, mime_type: &str) -> Result<(), Error> {
+    pub async fn export(
+        &self,
+        gdriveid: &str,
+        local: &Path,
+        mime_type: &str,
+    ) -> Result<StackString, Error> {
         let params = FilesExportParams {
             file_id: gdriveid.into(),
             mime_type: mime_type.into(),
             ..FilesExportParams::default()
         };
-        let mut outfile = fs::File::create(local).await?;
+        let temporary = export_path(local);
+        let mut outfile = fs::File::create(&temporary).await?;
 
         self.rate_limit.acquire().await;
-        self.files
-            .export(&params)
-            .await?
-            .do_it(Some(&mut outfile))
-            .await?;
-        Ok(())
+        let result: Result<_, Error> = async {
+            self.files
+                .export(&params)
+                .await?
+                .do_it(Some(&mut outfile))
+                .await?;
+            Ok(())
+        }
+        .await;
+        drop(outfile);
+        if let Err(e) = result {
+            fs::remove_file(&temporary).await?;
+            return Err(e);
+        }
+        let md5sum = {
+            let temporary = temporary.clone();
+            spawn_blocking(move || file_md5sum(&temporary)).await??
+        };
+        fs::rename(&temporary, local).await?;
+        Ok(md5sum)
     }
 
     /// # Errors
@@ -470,38 +922,144 @@ impl GDriveInstance {
             .copied();
 
         if let Some(t) = export_type {
-            self.export(gdriveid, local, t).await
+            self.export(gdriveid, local, t).await.map(|_| ())
         } else {
-            let p = DriveParams {
-                alt: Some(DriveParamsAlt::Media),
-                ..DriveParams::default()
-            };
-            let params = FilesGetParams {
-                drive_params: Some(p),
-                file_id: gdriveid.into(),
-                supports_all_drives: Some(false),
-                ..FilesGetParams::default()
+            self.download_media(gdriveid, local).await
+        }
+    }
+
+    /// Download into `<local>.part` a range at a time, resuming from whatever
+    /// an earlier attempt left there, and move it to `local` once its md5
+    /// matches `md5Checksum`
+    async fn download_media(&self, gdriveid: &str, local: &Path) -> Result<(), Error> {
+        let media = self.get_media_info(gdriveid).await?;
+        let size: u64 = media
+            .size
+            .as_deref()
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(0);
+        let partial = partial_path(local);
+
+        let mut offset = fs::metadata(&partial).await.map_or(0, |m| m.len());
+        if offset > size {
+            fs::remove_file(&partial).await?;
+            offset = 0;
+        } else if offset > 0 {
+            debug!("resuming {gdriveid} at {offset} of {size} bytes");
+        }
+        if size == 0 {
+            fs::File::create(&partial).await?;
+        }
+        while offset < size {
+            let end = (offset + DOWNLOAD_CHUNK_SIZE).min(size) - 1;
+            offset = exponential_retry_counted(TransferStats::global(), || {
+                self.download_range(gdriveid, &partial, end)
+            })
+            .await?;
+        }
+
+        if let Some(expected) = &media.md5_checksum {
+            let md5sum = {
+                let partial = partial.clone();
+                spawn_blocking(move || file_md5sum(&partial)).await??
             };
-            let mut outfile = fs::File::create(&local).await?;
+            if !md5sum.eq_ignore_ascii_case(expected) {
+                fs::remove_file(&partial).await?;
+                return Err(format_err!(
+                    "Checksum mismatch downloading {gdriveid}: expected {expected} got {md5sum}"
+                ));
+            }
+        }
+        fs::rename(&partial, local).await?;
+        Ok(())
+    }
+
+    /// Append the bytes from the current end of `partial` up to and including
+    /// `end`, returns the new length of `partial`
+    async fn download_range(&self, gdriveid: &str, partial: &Path, end: u64) -> Result<u64, Error> {
+        let mut outfile = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(partial)
+            .await?;
+        let mut offset = outfile.metadata().await?.len();
+
+        let token = self.auth.token(&[DriveScopes::Drive]).await?;
+        let token = token
+            .token()
+            .ok_or_else(|| format_err!("No access token"))?;
+        let uri = format_sstr!(
+            "https://www.googleapis.com/drive/v3/files/{}?alt=media",
+            utf8_percent_encode(gdriveid, NON_ALPHANUMERIC)
+        );
+        let request = Request::get(uri.as_str())
+            .header(AUTHORIZATION, format_sstr!("Bearer {token}").as_str())
+            .header(RANGE, format_sstr!("bytes={offset}-{end}").as_str())
+            .body(Body::empty())?;
 
+        self.rate_limit.acquire().await;
+        let mut response = self.send(request).await?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {}
+            StatusCode::OK => {
+                // range ignored, the whole file follows
+                outfile.set_len(0).await?;
+                offset = 0;
+            }
+            status => return Err(format_err!("Failed to download {gdriveid}: {status}")),
+        }
+        // an injected truncation keeps the first bytes of the range in
+        // `partial` and fails, the retry resumes after them
+        let start = offset;
+        let cut = FaultInjector::global()
+            .and_then(|faults| faults.truncate("download", (end + 1).saturating_sub(start)));
+        while let Some(chunk) = response.body_mut().data().await {
+            let mut chunk = chunk?;
+            TransferStats::global().received(chunk.len() as u64);
+            if let Some((length, fault)) = &cut {
+                let room = (start + length).saturating_sub(offset);
+                if chunk.len() as u64 >= room {
+                    chunk.truncate(room as usize);
+                    outfile.write_all(&chunk).await?;
+                    outfile.flush().await?;
+                    return Err(fault.clone().into());
+                }
+            }
+            outfile.write_all(&chunk).await?;
+            offset += chunk.len() as u64;
+        }
+        outfile.flush().await?;
+        Ok(offset)
+    }
+
+    async fn get_media_info(&self, gdriveid: &str) -> Result<File, Error> {
+        let p = DriveParams {
+            alt: Some(DriveParamsAlt::Json),
+            fields: Some("size,md5Checksum".into()),
+            ..DriveParams::default()
+        };
+        let params = FilesGetParams {
+            drive_params: Some(p),
+            file_id: gdriveid.into(),
+            supports_all_drives: Some(false),
+            ..FilesGetParams::default()
+        };
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
-            if let DownloadResult::Downloaded = self
-                .files
-                .get(&params)
-                .await?
-                .do_it(Some(&mut outfile))
-                .await?
-            {
-                Ok(())
+            if let DownloadResult::Response(f) = self.files.get(&params).await?.do_it(None).await? {
+                Ok(f)
             } else {
-                Err(format_err!("Failed to download"))
+                Err(format_err!("Failed to get metadata"))
             }
-        }
+        })
+        .await
     }
 
     /// # Errors
     /// Return error if api call fails
     pub async fn move_to_trash(&self, id: &str) -> Result<(), Error> {
+        self.metadata_cache.invalidate(id);
         let f = File {
             trashed: Some(true),
             ..File::default()
@@ -511,7 +1069,7 @@ impl GDriveInstance {
             supports_all_drives: Some(false),
             ..FilesUpdateParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             self.files.update(&params, &f).await?;
             Ok(())
@@ -522,12 +1080,13 @@ impl GDriveInstance {
     /// # Errors
     /// Return error if api call fails
     pub async fn delete_permanently(&self, id: &str) -> Result<(), Error> {
+        self.metadata_cache.invalidate(id);
         let params = FilesDeleteParams {
             file_id: id.into(),
             supports_all_drives: Some(false),
             ..FilesDeleteParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             self.files.delete(&params).await
         })
@@ -555,12 +1114,14 @@ impl GDriveInstance {
             add_parents: Some(parent.into()),
             ..FilesUpdateParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             self.files.update(&params, &file).await?;
             Ok(())
         })
-        .await
+        .await?;
+        self.metadata_cache.invalidate(id);
+        Ok(())
     }
 
     /// # Errors
@@ -673,8 +1234,8 @@ impl GDriveInstance {
         dirmap: &HashMap<StackString, DirectoryInfo>,
     ) -> Result<Vec<StackString>, Error> {
         let mut fullpath = Vec::new();
-        if let Some(name) = finfo.name.as_ref() {
-            fullpath.push(name.clone().into());
+        if let Some(name) = Self::export_name(finfo) {
+            fullpath.push(name);
         }
         let mut pid: Option<StackString> = finfo
             .parents
@@ -744,13 +1305,46 @@ impl GDriveInstance {
         Ok(None)
     }
 
+    /// Split the folders of `directory` into the id of the deepest one that
+    /// already exists and the names of those below it that still have to be
+    /// created, the id is `None` if not even the first folder exists
+    #[must_use]
+    pub fn get_missing_directories(
+        directory: &Url,
+        dir_name_map: &HashMap<StackString, Vec<DirectoryInfo>>,
+    ) -> (Option<StackString>, Vec<StackString>) {
+        let mut parent_id: Option<StackString> = None;
+        let mut missing: Vec<StackString> = Vec::new();
+        if let Some(segments) = directory.path_segments() {
+            for seg in segments.filter(|s| !s.is_empty()) {
+                let name: StackString = percent_decode(seg.as_bytes())
+                    .decode_utf8_lossy()
+                    .as_ref()
+                    .into();
+                if missing.is_empty() {
+                    let matching = dir_name_map.get(&name).and_then(|candidates| {
+                        candidates.iter().find(|d| {
+                            parent_id.is_none() || (d.parentid.is_some() && d.parentid == parent_id)
+                        })
+                    });
+                    if let Some(d) = matching {
+                        parent_id = Some(d.directory_id.clone());
+                        continue;
+                    }
+                }
+                missing.push(name);
+            }
+        }
+        (parent_id, missing)
+    }
+
     /// # Errors
     /// Return error if api call fails
     pub async fn get_start_page_token(&self) -> Result<usize, Error> {
         let params = ChangesGetStartPageTokenParams {
             ..ChangesGetStartPageTokenParams::default()
         };
-        exponential_retry(|| async {
+        exponential_retry_counted(TransferStats::global(), || async {
             self.rate_limit.acquire().await;
             if let Some(start_page_token) = self
                 .changes
@@ -768,27 +1362,22 @@ impl GDriveInstance {
         .await
     }
 
+    /// Atomic, locked write, see `token_file::write_token`
     /// # Errors
-    /// Return error if api call fails
+    /// Return error if the write fails or the stored token is newer
     pub async fn store_start_page_token(&self, path: &Path) -> Result<(), Error> {
-        if let Some(start_page_token) = self.start_page_token.load().as_ref() {
-            let buf = StackString::from_display(start_page_token);
-            fs::write(path, buf).await?;
+        if let Some(start_page_token) = self.start_page_token.load() {
+            let path = path.to_path_buf();
+            spawn_blocking(move || token_file::write_token(&path, start_page_token)).await??;
         }
         Ok(())
     }
 
     /// # Errors
-    /// Return error if api call fails
+    /// Return error if the token can't be read or parsed
     pub async fn read_start_page_token(path: &Path) -> Result<Option<usize>, Error> {
-        if !path.exists() {
-            return Ok(None);
-        }
-        let mut f = fs::File::open(path).await?;
-        let mut buf = String::new();
-        f.read_to_string(&mut buf).await?;
-        let start_page_token = buf.parse()?;
-        Ok(Some(start_page_token))
+        let path = path.to_path_buf();
+        spawn_blocking(move || token_file::read_token(&path)).await?
     }
 
     /// # Errors
@@ -814,6 +1403,7 @@ impl GDriveInstance {
                 "md5Checksum",
                 "fileExtension",
                 "webContentLink",
+                "shortcutDetails",
             ]
             .join(",");
             let fields = format!(
@@ -833,7 +1423,7 @@ impl GDriveInstance {
                     restrict_to_my_drive: Some(true),
                     include_removed: Some(true),
                     supports_all_drives: Some(false),
-                    page_size: Some(self.page_size),
+                    page_size: Some(self.page_size.get()),
                     ..ChangesListParams::default()
                 };
                 self.rate_limit.acquire().await;
@@ -868,7 +1458,7 @@ pub struct GDriveInfo {
     pub urlname: Url,
     pub md5sum: Option<StackString>,
     pub sha1sum: Option<StackString>,
-    pub filestat: (u32, u32),
+    pub filestat: (DateTimeWrapper, i64),
     pub serviceid: StackString,
     pub servicesession: StackString,
 }
@@ -881,17 +1471,14 @@ impl GDriveInfo {
         gdrive: &GDriveInstance,
         directory_map: &HashMap<StackString, DirectoryInfo>,
     ) -> Result<Self, Error> {
-        let filename = item
-            .name
-            .as_ref()
-            .ok_or_else(|| format_err!("No filename"))?;
+        let filename =
+            GDriveInstance::export_name(item).ok_or_else(|| format_err!("No filename"))?;
         let md5sum = item.md5_checksum.as_ref().and_then(|x| x.parse().ok());
         let st_mtime = item
             .modified_time
             .as_ref()
-            .ok_or_else(|| format_err!("No last modified"))?
-            .unix_timestamp();
-        let size: u32 = item.size.as_ref().and_then(|x| x.parse().ok()).unwrap_or(0);
+            .ok_or_else(|| format_err!("No last modified"))?;
+        let size: i64 = item.size.as_ref().and_then(|x| x.parse().ok()).unwrap_or(0);
         let serviceid = item.id.as_ref().ok_or_else(|| format_err!("No ID"))?.into();
         let servicesession = gdrive.session_name.parse()?;
 
@@ -911,12 +1498,12 @@ impl GDriveInfo {
         })?;
 
         let finfo = Self {
-            filename: filename.into(),
+            filename,
             filepath,
             urlname,
             md5sum,
             sha1sum: None,
-            filestat: (st_mtime as u32, size),
+            filestat: (*st_mtime, size),
             serviceid,
             servicesession,
         };
@@ -938,3 +1525,223 @@ impl GDriveInfo {
         Self::from_object(&file, gdrive, directory_map).await
     }
 }
+
+/// Send the rest of a `size` byte upload, `status` asks the api how much it
+/// has received and `chunk(offset, end)` sends the bytes in between.  A failed
+/// attempt asks for the status again before it is retried, so a chunk cut off
+/// part way resumes from wherever the api stopped.
+async fn resumable_upload<S, SF, C, CF>(
+    local: &Url,
+    size: u64,
+    status: S,
+    chunk: C,
+    faults: Option<&FaultInjector>,
+) -> Result<File, Error>
+where
+    S: Fn() -> SF,
+    SF: Future<Output = Result<UploadStatus, Error>>,
+    C: Fn(u64, u64) -> CF,
+    CF: Future<Output = Result<UploadStatus, Error>>,
+{
+    let stats = TransferStats::global();
+    let mut current = exponential_retry_counted(stats, &status).await?;
+    if let UploadStatus::Active(offset) = &current {
+        if *offset > 0 {
+            debug!("resuming upload of {local} at {offset} of {size} bytes");
+        }
+    }
+    loop {
+        match current {
+            UploadStatus::Complete(f) => return Ok(*f),
+            UploadStatus::Expired => {
+                return Err(format_err!("Upload session of {local} expired"));
+            }
+            UploadStatus::Active(offset) => {
+                let retrying = AtomicBool::new(false);
+                let (status, chunk, retrying) = (&status, &chunk, &retrying);
+                current = exponential_retry_counted(stats, || async move {
+                    let offset = if retrying.swap(true, Ordering::SeqCst) {
+                        match status().await? {
+                            UploadStatus::Active(received) => received,
+                            finished => return Ok(finished),
+                        }
+                    } else {
+                        offset
+                    };
+                    let end = (offset + UPLOAD_CHUNK_SIZE).min(size);
+                    if let Some((length, fault)) =
+                        faults.and_then(|faults| faults.truncate("upload", end - offset))
+                    {
+                        chunk(offset, offset + length).await?;
+                        return Err(fault.into());
+                    }
+                    chunk(offset, end).await
+                })
+                .await?;
+                if let UploadStatus::Active(received) = &current {
+                    if *received <= offset {
+                        return Err(format_err!(
+                            "Upload of {local} made no progress at {offset} of {size} bytes"
+                        ));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A 308 asks for the rest of the upload, its `Range` header (absent if
+/// nothing was kept) covers the bytes received so far
+async fn upload_response(response: Response<Body>) -> Result<UploadStatus, Error> {
+    match response.status() {
+        StatusCode::PERMANENT_REDIRECT => {
+            let received = response
+                .headers()
+                .get(RANGE)
+                .and_then(|r| r.to_str().ok())
+                .and_then(received_bytes)
+                .unwrap_or(0);
+            Ok(UploadStatus::Active(received))
+        }
+        StatusCode::OK | StatusCode::CREATED => {
+            let body = to_bytes(response.into_body()).await?;
+            TransferStats::global().received(body.len() as u64);
+            Ok(UploadStatus::Complete(Box::new(serde_json::from_slice(
+                &body,
+            )?)))
+        }
+        StatusCode::NOT_FOUND | StatusCode::GONE => Ok(UploadStatus::Expired),
+        status => Err(format_err!("Upload failed: {status}")),
+    }
+}
+
+/// Size of `headers` as sent in http/1.1 (`name: value\r\n`)
+fn header_size(headers: &HeaderMap) -> u64 {
+    headers
+        .iter()
+        .map(|(name, value)| (name.as_str().len() + value.len() + 4) as u64)
+        .sum()
+}
+
+/// Request line and headers of `request`
+fn request_head_size<B>(request: &Request<B>) -> u64 {
+    let line = request.method().as_str().len() + request.uri().to_string().len() + 12;
+    line as u64 + header_size(request.headers())
+}
+
+/// Number of bytes covered by a `Range: bytes=0-<last>` header
+fn received_bytes(range: &str) -> Option<u64> {
+    let (_, last) = range.strip_prefix("bytes=")?.split_once('-')?;
+    last.parse::<u64>().ok().map(|last| last + 1)
+}
+
+/// Where `local` is downloaded to until it's complete
+fn partial_path(local: &Path) -> PathBuf {
+    let mut name = local
+        .file_name()
+        .map_or_else(OsString::new, OsStr::to_os_string);
+    name.push(".part");
+    local.with_file_name(name)
+}
+
+fn export_path(local: &Path) -> PathBuf {
+    let mut name = local
+        .file_name()
+        .map_or_else(OsString::new, OsStr::to_os_string);
+    name.push(format_sstr!(".{}.export", std::process::id()).as_str());
+    local.with_file_name(name)
+}
+
+fn file_md5sum(path: &Path) -> Result<StackString, Error> {
+    let mut f = std::fs::File::open(path)?;
+    let mut hasher = Md5::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format_sstr!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use async_google_apis_common::{yup_oauth2::hyper::StatusCode, ApiError};
+    use parking_lot::Mutex;
+    use stack_string::format_sstr;
+    use std::{ffi::OsStr, path::Path};
+    use url::Url;
+
+    use crate::{
+        drive_v3_types::File,
+        fault_injection::{FaultConfig, FaultInjector},
+        gdrive_instance::{
+            export_path, is_not_found, received_bytes, resumable_upload, UploadStatus,
+        },
+    };
+
+    #[test]
+    fn test_is_not_found() {
+        let e: Error = ApiError::HTTPResponseError(StatusCode::NOT_FOUND, "".into()).into();
+        assert!(is_not_found(&e));
+        let body = r#"{"error": {"errors": [{"reason": "notFound"}], "code": 404}}"#;
+        let e: Error = ApiError::HTTPResponseError(StatusCode::BAD_REQUEST, body.into()).into();
+        assert!(is_not_found(&e.context("get file")));
+        let e: Error = ApiError::HTTPResponseError(StatusCode::FORBIDDEN, "".into()).into();
+        assert!(!is_not_found(&e));
+        assert!(!is_not_found(&format_err!("file 404.txt failed")));
+    }
+
+    #[test]
+    fn test_export_path() {
+        let local = Path::new("/tmp/docs/report.docx");
+        let export = export_path(local);
+        assert_eq!(export.parent(), local.parent());
+        assert_eq!(
+            export.file_name().and_then(OsStr::to_str),
+            Some(format_sstr!("report.docx.{}.export", std::process::id()).as_str())
+        );
+    }
+
+    #[test]
+    fn test_received_bytes() {
+        assert_eq!(received_bytes("bytes=0-99"), Some(100));
+        assert_eq!(received_bytes("bytes=0-0"), Some(1));
+        assert_eq!(received_bytes("bytes=0-"), None);
+        assert_eq!(received_bytes("0-99"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resumable_upload_resumes_after_truncation() -> Result<(), Error> {
+        let data: Vec<u8> = (0..100).collect();
+        let size = data.len() as u64;
+        let received = Mutex::new(Vec::new());
+        let status = || async {
+            let received = received.lock().len() as u64;
+            if received == size {
+                Ok(UploadStatus::Complete(Box::new(File::default())))
+            } else {
+                Ok(UploadStatus::Active(received))
+            }
+        };
+        let chunk = |offset: u64, end: u64| {
+            let mut received = received.lock();
+            assert_eq!(received.len() as u64, offset);
+            received.extend_from_slice(&data[offset as usize..end as usize]);
+            status()
+        };
+        let faults = FaultInjector::new(FaultConfig {
+            truncate_first: 1,
+            ..FaultConfig::default()
+        });
+        let local = Url::parse("file:///tmp/upload.txt")?;
+
+        resumable_upload(&local, size, status, chunk, Some(&faults)).await?;
+        assert_eq!(received.lock().as_slice(), data.as_slice());
+        assert_eq!(faults.truncate("upload", size), None);
+        Ok(())
+    }
+}
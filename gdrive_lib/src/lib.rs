@@ -10,12 +10,19 @@
 #![allow(clippy::doc_lazy_continuation)]
 #![allow(clippy::assigning_clones)]
 
+pub mod client;
 pub mod date_time_wrapper;
 pub mod directory_info;
 pub mod drive_v3_types;
+pub mod fault_injection;
 pub mod gcs_instance;
 pub mod gdrive_instance;
+pub mod metadata_cache;
+pub mod page_size;
 pub mod storage_v1_types;
+pub mod tls;
+pub mod token_file;
+pub mod transfer_stats;
 
 use anyhow::Error;
 use rand::{
@@ -25,17 +32,56 @@ use rand::{
 use std::future::Future;
 use tokio::time::{sleep, Duration};
 
+use crate::fault_injection::FaultInjector;
+
 /// # Errors
 /// Returns error if timeout is reached
 pub async fn exponential_retry<T, U, F>(f: T) -> Result<U, Error>
 where
     T: Fn() -> F,
     F: Future<Output = Result<U, Error>>,
+{
+    retry_loop(f, FaultInjector::global(), || {}).await
+}
+
+/// `exponential_retry` counting each retry in `stats`, for callers that
+/// know which service they're talking to
+/// # Errors
+/// Returns error if timeout is reached
+pub async fn exponential_retry_counted<T, U, F>(
+    stats: &transfer_stats::TransferStats,
+    f: T,
+) -> Result<U, Error>
+where
+    T: Fn() -> F,
+    F: Future<Output = Result<U, Error>>,
+{
+    retry_loop(f, FaultInjector::global(), || stats.retry()).await
+}
+
+/// Faults are injected per attempt, so an injected error is retried like any
+/// other failed request
+async fn retry_loop<T, U, F, R>(
+    f: T,
+    faults: Option<&FaultInjector>,
+    on_retry: R,
+) -> Result<U, Error>
+where
+    T: Fn() -> F,
+    F: Future<Output = Result<U, Error>>,
+    R: Fn(),
 {
     let mut timeout: f64 = 1.0;
     let range = Uniform::from(0..1000);
     loop {
-        match f().await {
+        let result = match faults {
+            Some(faults) => match faults.before("request").await {
+                Ok(_) => f().await,
+                Err(e) => Err(e),
+            },
+            None => f().await,
+        };
+        match result {
             Ok(resp) => return Ok(resp),
             Err(err) => {
                 sleep(Duration::from_millis((timeout * 1000.0) as u64)).await;
@@ -43,7 +89,45 @@ where
                 if timeout >= 64.0 {
                     return Err(err);
                 }
+                on_retry();
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::{
+        fault_injection::{FaultConfig, FaultInjector},
+        retry_loop,
+    };
+
+    #[tokio::test]
+    async fn test_retry_loop_retries_injected_faults() -> Result<(), Error> {
+        let faults = FaultInjector::new(FaultConfig {
+            fail_first: 1,
+            ..FaultConfig::default()
+        });
+        let sent = AtomicUsize::new(0);
+        let retries = AtomicUsize::new(0);
+        let result = retry_loop(
+            || async {
+                sent.fetch_add(1, Ordering::SeqCst);
+                Ok(42)
+            },
+            Some(&faults),
+            || {
+                retries.fetch_add(1, Ordering::SeqCst);
+            },
+        )
+        .await?;
+        assert_eq!(result, 42);
+        assert_eq!(faults.calls(), 2);
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(retries.load(Ordering::SeqCst), 1);
+        Ok(())
+    }
+}
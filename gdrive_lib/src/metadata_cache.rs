@@ -0,0 +1,109 @@
+use parking_lot::Mutex;
+use stack_string::StackString;
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::drive_v3_types::File;
+
+/// Short lived cache of `files.get` results keyed by file id, a `None` entry
+/// records an id the api reported as missing so it isn't requested again.
+/// Walking up parents during a big sync otherwise fetches the same few
+/// directories over and over.
+#[derive(Clone, Debug)]
+pub struct MetadataCache {
+    entries: Arc<Mutex<HashMap<StackString, (Instant, Option<File>)>>>,
+    ttl: Duration,
+}
+
+impl Default for MetadataCache {
+    fn default() -> Self {
+        Self::new(Duration::from_secs(300))
+    }
+}
+
+impl MetadataCache {
+    #[must_use]
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    /// `Some(None)` means the id is known to be missing
+    #[must_use]
+    pub fn get(&self, id: &str) -> Option<Option<File>> {
+        let mut entries = self.entries.lock();
+        match entries.get(id) {
+            Some((inserted, file)) if inserted.elapsed() < self.ttl => Some(file.clone()),
+            Some(_) => {
+                entries.remove(id);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&self, id: &str, file: File) {
+        self.entries
+            .lock()
+            .insert(id.into(), (Instant::now(), Some(file)));
+    }
+
+    pub fn insert_missing(&self, id: &str) {
+        self.entries
+            .lock()
+            .insert(id.into(), (Instant::now(), None));
+    }
+
+    pub fn invalidate(&self, id: &str) {
+        self.entries.lock().remove(id);
+    }
+
+    /// Ids currently cached as missing
+    #[must_use]
+    pub fn missing_ids(&self) -> Vec<StackString> {
+        self.entries
+            .lock()
+            .iter()
+            .filter(|(_, (inserted, file))| file.is_none() && inserted.elapsed() < self.ttl)
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+    use std::time::Duration;
+
+    use crate::{drive_v3_types::File, metadata_cache::MetadataCache};
+
+    #[test]
+    fn test_metadata_cache() {
+        let cache = MetadataCache::new(Duration::from_secs(60));
+        assert!(cache.get("abc").is_none());
+        cache.insert(
+            "abc",
+            File {
+                name: Some("test.txt".into()),
+                ..File::default()
+            },
+        );
+        let file = cache.get("abc").unwrap().unwrap();
+        assert_eq!(file.name.as_deref(), Some("test.txt"));
+        cache.insert_missing("def");
+        assert!(cache.get("def").unwrap().is_none());
+        assert_eq!(cache.missing_ids(), vec![StackString::from("def")]);
+        cache.invalidate("abc");
+        assert!(cache.get("abc").is_none());
+
+        let cache = MetadataCache::new(Duration::from_secs(0));
+        cache.insert_missing("def");
+        assert!(cache.get("def").is_none());
+        assert!(cache.missing_ids().is_empty());
+    }
+}
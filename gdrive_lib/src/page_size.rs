@@ -0,0 +1,97 @@
+use std::sync::{
+    atomic::{AtomicI32, AtomicUsize, Ordering},
+    Arc,
+};
+
+pub const MIN_PAGE_SIZE: i32 = 10;
+
+/// Failures in a row before the page size is halved
+const FAILURES_BEFORE_SHRINK: usize = 2;
+
+/// Page size for list requests that shrinks when requests keep failing and
+/// grows back towards `max_page_size` as they succeed, large pages are the
+/// ones most likely to time out / 500 on the drive api
+#[derive(Clone, Debug)]
+pub struct AdaptivePageSize {
+    current: Arc<AtomicI32>,
+    failures: Arc<AtomicUsize>,
+    max_page_size: i32,
+}
+
+impl AdaptivePageSize {
+    #[must_use]
+    pub fn new(max_page_size: i32) -> Self {
+        let max_page_size = max_page_size.max(MIN_PAGE_SIZE);
+        Self {
+            current: Arc::new(AtomicI32::new(max_page_size)),
+            failures: Arc::new(AtomicUsize::new(0)),
+            max_page_size,
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self) -> i32 {
+        self.current.load(Ordering::SeqCst)
+    }
+
+    #[must_use]
+    pub fn max_page_size(&self) -> i32 {
+        self.max_page_size
+    }
+
+    /// Grow by a quarter after each successful request
+    pub fn success(&self) {
+        self.failures.store(0, Ordering::SeqCst);
+        let max_page_size = self.max_page_size;
+        self.current
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                Some((n + (n / 4).max(1)).min(max_page_size))
+            })
+            .ok();
+    }
+
+    /// Halve the page size after repeated failures
+    pub fn failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::SeqCst) + 1;
+        if failures >= FAILURES_BEFORE_SHRINK {
+            self.failures.store(0, Ordering::SeqCst);
+            self.current
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    Some((n / 2).max(MIN_PAGE_SIZE))
+                })
+                .ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::page_size::{AdaptivePageSize, MIN_PAGE_SIZE};
+
+    #[test]
+    fn test_adaptive_page_size() {
+        let page_size = AdaptivePageSize::new(1000);
+        assert_eq!(page_size.get(), 1000);
+
+        page_size.failure();
+        assert_eq!(page_size.get(), 1000);
+        page_size.failure();
+        assert_eq!(page_size.get(), 500);
+
+        // a success in between resets the failure count
+        page_size.failure();
+        page_size.success();
+        page_size.failure();
+        assert_eq!(page_size.get(), 625);
+
+        for _ in 0..20 {
+            page_size.failure();
+        }
+        assert_eq!(page_size.get(), MIN_PAGE_SIZE);
+
+        for _ in 0..50 {
+            page_size.success();
+        }
+        assert_eq!(page_size.get(), 1000);
+    }
+}
@@ -0,0 +1,159 @@
+use anyhow::{format_err, Error};
+use async_google_apis_common::{
+    yup_oauth2::hyper::{self, service::Service, Uri},
+    TlsClient,
+};
+use rustls::{Certificate, ClientConfig, RootCertStore};
+use std::{
+    fs::File,
+    future::Future,
+    io::BufReader,
+    path::Path,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use url::Url;
+
+/// Longest proxy response to a `CONNECT` read before giving up
+const MAX_CONNECT_RESPONSE: usize = 8192;
+
+/// Client config trusting the system roots plus every certificate in the pem
+/// file `ca_bundle`
+/// # Errors
+/// Return error if the system roots or `ca_bundle` can't be loaded
+pub fn tls_config(ca_bundle: &Path) -> Result<ClientConfig, Error> {
+    let mut roots = RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs()? {
+        roots.add(&Certificate(cert.0))?;
+    }
+    let f = File::open(ca_bundle)
+        .map_err(|e| format_err!("Can't read {}: {e}", ca_bundle.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(f))?;
+    if certs.is_empty() {
+        return Err(format_err!("No certificates in {}", ca_bundle.display()));
+    }
+    for cert in certs {
+        roots.add(&Certificate(cert))?;
+    }
+    Ok(ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth())
+}
+
+/// # Errors
+/// Return error if `ca_bundle` is set and can't be loaded
+pub fn https_client(ca_bundle: Option<&Path>) -> Result<TlsClient, Error> {
+    let builder = hyper_rustls::HttpsConnectorBuilder::new();
+    let builder = match ca_bundle {
+        Some(ca_bundle) => builder.with_tls_config(tls_config(ca_bundle)?),
+        None => builder.with_native_roots(),
+    };
+    let conn = builder.https_only().enable_http1().build();
+    Ok(hyper::Client::builder().build(conn))
+}
+
+/// Connector opening each connection as a `CONNECT` tunnel through the http
+/// proxy `proxy`, wrap it in an `HttpsConnector` for tls to the target
+#[derive(Debug, Clone)]
+pub struct ProxyConnector {
+    proxy: Url,
+}
+
+impl ProxyConnector {
+    #[must_use]
+    pub fn new(proxy: Url) -> Self {
+        Self { proxy }
+    }
+
+    async fn tunnel(proxy: Url, dst: Uri) -> Result<TcpStream, Error> {
+        let host = dst.host().ok_or_else(|| format_err!("No host in {dst}"))?;
+        let default_port = if dst.scheme_str() == Some("http") {
+            80
+        } else {
+            443
+        };
+        let port = dst.port_u16().unwrap_or(default_port);
+        let proxy_host = proxy
+            .host_str()
+            .ok_or_else(|| format_err!("No host in proxy {proxy}"))?;
+        let proxy_port = proxy.port_or_known_default().unwrap_or(80);
+        let mut stream = TcpStream::connect((proxy_host, proxy_port)).await?;
+        let request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n\r\n");
+        stream.write_all(request.as_bytes()).await?;
+        // byte by byte, anything after the headers belongs to the tunnel
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() > MAX_CONNECT_RESPONSE {
+                return Err(format_err!("Proxy {proxy} response too long"));
+            }
+            let byte = stream.read_u8().await?;
+            response.push(byte);
+        }
+        let response = String::from_utf8_lossy(&response);
+        let status_line = response.lines().next().unwrap_or("");
+        if status_line.split_whitespace().nth(1) != Some("200") {
+            return Err(format_err!(
+                "Proxy {proxy} refused {host}:{port}: {status_line}"
+            ));
+        }
+        Ok(stream)
+    }
+}
+
+impl Service<Uri> for ProxyConnector {
+    type Response = TcpStream;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<TcpStream, Error>> + Send>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        Box::pin(Self::tunnel(self.proxy.clone(), dst))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use async_google_apis_common::yup_oauth2::hyper::service::Service;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use crate::tls::ProxyConnector;
+
+    #[tokio::test]
+    async fn test_proxy_connector() -> Result<(), Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await?;
+            let mut request = Vec::new();
+            while !request.ends_with(b"\r\n\r\n") {
+                request.push(stream.read_u8().await?);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nhello")
+                .await?;
+            Ok::<_, Error>(String::from_utf8(request)?)
+        });
+        let mut connector = ProxyConnector::new(format!("http://{addr}").parse()?);
+        let mut stream = connector
+            .call("https://storage.googleapis.com/".parse()?)
+            .await?;
+        let mut greeting = [0u8; 5];
+        stream.read_exact(&mut greeting).await?;
+        assert_eq!(&greeting, b"hello");
+        let request = proxy.await??;
+        assert!(request.starts_with("CONNECT storage.googleapis.com:443 HTTP/1.1\r\n"));
+        Ok(())
+    }
+}
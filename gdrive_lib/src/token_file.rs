@@ -0,0 +1,130 @@
+use anyhow::{format_err, Error};
+use fs2::FileExt;
+use std::{
+    ffi::OsString,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    process,
+};
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name: OsString = path.as_os_str().into();
+    name.push(suffix);
+    name.into()
+}
+
+fn lock(path: &Path, exclusive: bool) -> Result<File, Error> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(false)
+        .open(with_suffix(path, ".lock"))?;
+    if exclusive {
+        lock.lock_exclusive()?;
+    } else {
+        lock.lock_shared()?;
+    }
+    Ok(lock)
+}
+
+fn read_unlocked(path: &Path) -> Result<Option<usize>, Error> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let buf = fs::read_to_string(path)?;
+    let token = buf
+        .trim()
+        .parse()
+        .map_err(|e| format_err!("Invalid start_page_token in {}: {e}", path.display()))?;
+    Ok(Some(token))
+}
+
+fn check_regression(path: &Path, stored: Option<usize>, token: usize) -> Result<(), Error> {
+    match stored {
+        Some(stored) if stored > token => Err(format_err!(
+            "start_page_token regression in {}: stored token {stored} is newer than {token}, \
+             another process using this session already advanced it",
+            path.display()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// # Errors
+/// Return error if the token can't be read or parsed
+pub fn read_token(path: &Path) -> Result<Option<usize>, Error> {
+    let _lock = lock(path, false)?;
+    read_unlocked(path)
+}
+
+/// Start page token files are shared by every process using the same gdrive
+/// session.  The token goes to a temporary file that is renamed into place
+/// while holding an exclusive lock on `<path>.lock`, so a reader never sees
+/// a partial token and two writers can't interleave.  Tokens only move
+/// forward, a token older than the stored one means another process already
+/// got further and is refused.
+/// # Errors
+/// Return error if the write fails or `token` is older than the stored token
+pub fn write_token(path: &Path, token: usize) -> Result<(), Error> {
+    let _lock = lock(path, true)?;
+    check_regression(path, read_unlocked(path)?, token)?;
+    let tmp = with_suffix(path, &format!(".tmp.{}", process::id()));
+    let mut f = File::create(&tmp)?;
+    write!(f, "{token}")?;
+    f.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+/// Move the token written to `new` into `path`, nothing to do if `new`
+/// doesn't exist (e.g. another process already promoted it)
+/// # Errors
+/// Return error if the rename fails or the token in `new` is older than the
+/// one in `path`
+pub fn promote_token(new: &Path, path: &Path) -> Result<(), Error> {
+    let _lock = lock(path, true)?;
+    let token = match read_token(new)? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+    check_regression(path, read_unlocked(path)?, token)?;
+    fs::rename(new, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::fs;
+
+    use crate::token_file::{promote_token, read_token, write_token};
+
+    #[test]
+    fn test_token_file() -> Result<(), Error> {
+        let dir = std::env::temp_dir().join(format!("token_file_test_{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let path = dir.join("ddboline@gmail.com_start_page_token");
+        let new = dir.join("ddboline@gmail.com_start_page_token.new");
+
+        assert_eq!(read_token(&path)?, None);
+        write_token(&path, 100)?;
+        assert_eq!(read_token(&path)?, Some(100));
+        write_token(&path, 100)?;
+        assert!(write_token(&path, 99).is_err());
+        assert_eq!(read_token(&path)?, Some(100));
+
+        write_token(&new, 150)?;
+        promote_token(&new, &path)?;
+        assert_eq!(read_token(&path)?, Some(150));
+        assert!(!new.exists());
+        promote_token(&new, &path)?;
+
+        write_token(&new, 120)?;
+        assert!(promote_token(&new, &path).is_err());
+        assert_eq!(read_token(&path)?, Some(150));
+
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
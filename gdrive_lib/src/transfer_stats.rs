@@ -0,0 +1,95 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TRANSFER_STATS: TransferStats = TransferStats::new();
+static GCS_TRANSFER_STATS: TransferStats = TransferStats::new();
+
+/// Bytes on the wire (headers included) of the uploads and downloads made by
+/// `GDriveInstance` itself, the calls through the generated api types only
+/// count their retries (see `exponential_retry_counted`)
+#[derive(Debug, Default)]
+pub struct TransferStats {
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    requests: AtomicU64,
+    retries: AtomicU64,
+}
+
+/// Totals of `TransferStats` at one point
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransferTotals {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub requests: u64,
+    pub retries: u64,
+}
+
+impl TransferStats {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            bytes_sent: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            requests: AtomicU64::new(0),
+            retries: AtomicU64::new(0),
+        }
+    }
+
+    /// The counters shared by the whole process
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &TRANSFER_STATS
+    }
+
+    /// Counters of `GcsInstance`, only its retries are counted
+    #[must_use]
+    pub fn gcs() -> &'static Self {
+        &GCS_TRANSFER_STATS
+    }
+
+    /// A request of `bytes` was sent
+    pub fn sent(&self, bytes: u64) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn received(&self, bytes: u64) {
+        self.bytes_received.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn retry(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn totals(&self) -> TransferTotals {
+        TransferTotals {
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            bytes_received: self.bytes_received.load(Ordering::Relaxed),
+            requests: self.requests.load(Ordering::Relaxed),
+            retries: self.retries.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::transfer_stats::{TransferStats, TransferTotals};
+
+    #[test]
+    fn test_transfer_stats() {
+        let stats = TransferStats::new();
+        stats.sent(100);
+        stats.sent(50);
+        stats.received(1000);
+        stats.retry();
+        assert_eq!(
+            stats.totals(),
+            TransferTotals {
+                bytes_sent: 150,
+                bytes_received: 1000,
+                requests: 2,
+                retries: 1,
+            }
+        );
+    }
+}
@@ -0,0 +1 @@
+ALTER TABLE file_sync_config ADD COLUMN tags TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,7 @@
+ALTER TABLE file_sync_config ADD COLUMN enabled BOOLEAN NOT NULL DEFAULT true;
+
+CREATE TABLE maintenance_mode (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    enabled BOOLEAN NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
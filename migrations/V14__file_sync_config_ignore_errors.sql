@@ -0,0 +1 @@
+ALTER TABLE file_sync_config ADD COLUMN ignore_errors TEXT[] NOT NULL DEFAULT '{}';
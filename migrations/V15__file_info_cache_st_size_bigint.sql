@@ -0,0 +1 @@
+ALTER TABLE file_info_cache ALTER COLUMN filestat_st_size TYPE BIGINT;
@@ -0,0 +1 @@
+ALTER TABLE file_info_cache ALTER COLUMN filestat_st_mtime TYPE TIMESTAMP WITH TIME ZONE USING to_timestamp(filestat_st_mtime);
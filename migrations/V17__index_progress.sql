@@ -0,0 +1,11 @@
+CREATE TABLE index_progress (
+    servicetype TEXT NOT NULL,
+    servicesession TEXT NOT NULL,
+    baseurl TEXT NOT NULL,
+    continuation_token TEXT,
+    change_token TEXT,
+    number_flushed BIGINT NOT NULL DEFAULT 0,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicetype, servicesession, baseurl)
+);
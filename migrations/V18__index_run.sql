@@ -0,0 +1,13 @@
+CREATE TABLE index_run (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    servicetype TEXT NOT NULL,
+    servicesession TEXT NOT NULL,
+    baseurl TEXT NOT NULL,
+    started_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    finished_at TIMESTAMP WITH TIME ZONE,
+    complete BOOLEAN NOT NULL DEFAULT false,
+    number_updated BIGINT NOT NULL DEFAULT 0,
+    number_cached BIGINT NOT NULL DEFAULT 0
+);
+
+CREATE INDEX index_run_session_idx ON index_run (servicetype, servicesession, started_at);
@@ -0,0 +1,13 @@
+CREATE TABLE sync_jobs (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    job_type TEXT NOT NULL,
+    name TEXT,
+    email TEXT,
+    status TEXT NOT NULL DEFAULT 'queued',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    started_at TIMESTAMP WITH TIME ZONE,
+    finished_at TIMESTAMP WITH TIME ZONE,
+    error TEXT
+);
+
+CREATE INDEX sync_jobs_status_idx ON sync_jobs (status, created_at);
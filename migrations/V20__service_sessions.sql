@@ -0,0 +1,15 @@
+CREATE TABLE service_sessions (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    servicetype TEXT NOT NULL,
+    servicesession TEXT NOT NULL,
+    account TEXT NOT NULL,
+    endpoint TEXT NOT NULL DEFAULT '',
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    UNIQUE (servicetype, servicesession)
+);
+
+ALTER TABLE file_info_cache ADD COLUMN service_session_id UUID REFERENCES service_sessions (id);
+ALTER TABLE directory_info_cache ADD COLUMN service_session_id UUID REFERENCES service_sessions (id);
+
+CREATE INDEX file_info_cache_service_session_idx ON file_info_cache (service_session_id);
+CREATE INDEX directory_info_cache_service_session_idx ON directory_info_cache (service_session_id);
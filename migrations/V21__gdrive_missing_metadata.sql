@@ -0,0 +1,6 @@
+CREATE TABLE gdrive_missing_metadata (
+    servicesession TEXT NOT NULL,
+    gdriveid TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicesession, gdriveid)
+);
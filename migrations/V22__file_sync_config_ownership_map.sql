@@ -0,0 +1 @@
+ALTER TABLE file_sync_config ADD COLUMN ownership_map TEXT[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,10 @@
+CREATE TABLE sync_event (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    src_url TEXT NOT NULL,
+    dst_url TEXT NOT NULL,
+    bytes BIGINT NOT NULL DEFAULT 0,
+    error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX sync_event_created_at_idx ON sync_event (created_at);
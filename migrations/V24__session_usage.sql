@@ -0,0 +1,10 @@
+CREATE TABLE session_usage (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    servicetype TEXT NOT NULL,
+    servicesession TEXT NOT NULL,
+    total_size BIGINT NOT NULL,
+    file_count BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX session_usage_session_idx ON session_usage (servicetype, servicesession, created_at);
@@ -0,0 +1,9 @@
+CREATE TABLE gdrive_exclusion (
+    servicesession TEXT NOT NULL,
+    gdriveid TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    mime_type TEXT,
+    reason TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicesession, gdriveid)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE gdrive_export (
+    servicesession TEXT NOT NULL,
+    gdriveid TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    mime_type TEXT NOT NULL,
+    export_mime_type TEXT NOT NULL,
+    extension TEXT NOT NULL,
+    last_modified TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicesession, gdriveid)
+);
@@ -0,0 +1,12 @@
+CREATE TABLE run_parameters (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    job_id UUID REFERENCES sync_jobs (id) ON DELETE CASCADE,
+    action TEXT NOT NULL,
+    arguments JSONB NOT NULL,
+    settings JSONB NOT NULL,
+    version TEXT NOT NULL,
+    hostname TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX run_parameters_created_at_idx ON run_parameters (created_at);
@@ -0,0 +1,23 @@
+ALTER TABLE file_sync_config ADD COLUMN conflict_policy TEXT;
+
+CREATE TABLE sync_history (
+    url0 TEXT NOT NULL,
+    url1 TEXT NOT NULL,
+    md5sum TEXT,
+    filestat_st_size BIGINT NOT NULL,
+    synced_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (url0, url1)
+);
+
+CREATE TABLE sync_conflict (
+    src_url TEXT NOT NULL,
+    dst_url TEXT NOT NULL,
+    src_size BIGINT NOT NULL,
+    dst_size BIGINT NOT NULL,
+    src_mtime TIMESTAMP WITH TIME ZONE NOT NULL,
+    dst_mtime TIMESTAMP WITH TIME ZONE NOT NULL,
+    policy TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    resolved_at TIMESTAMP WITH TIME ZONE,
+    PRIMARY KEY (src_url, dst_url)
+);
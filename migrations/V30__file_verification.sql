@@ -0,0 +1,6 @@
+CREATE TABLE file_verification (
+    cache_id UUID PRIMARY KEY REFERENCES file_info_cache (id) ON DELETE CASCADE,
+    verified_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX file_verification_verified_at_idx ON file_verification (verified_at);
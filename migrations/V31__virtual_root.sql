@@ -0,0 +1,8 @@
+CREATE TABLE virtual_root (
+    name TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    src_url TEXT NOT NULL,
+    prefix TEXT NOT NULL,
+    PRIMARY KEY (name, position),
+    UNIQUE (name, prefix)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE encrypted_file (
+    encrypted_url TEXT PRIMARY KEY,
+    clear_url TEXT NOT NULL UNIQUE,
+    clear_md5sum TEXT,
+    clear_size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
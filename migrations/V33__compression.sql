@@ -0,0 +1,11 @@
+ALTER TABLE file_sync_config ADD COLUMN compression TEXT;
+ALTER TABLE file_sync_config ADD COLUMN compression_min_size BIGINT;
+
+CREATE TABLE compressed_file (
+    url TEXT PRIMARY KEY,
+    codec TEXT NOT NULL,
+    original_size BIGINT NOT NULL,
+    original_md5sum TEXT,
+    compressed_size BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,10 @@
+CREATE TABLE sync_schedule (
+    name TEXT PRIMARY KEY,
+    cron TEXT NOT NULL,
+    paused BOOLEAN NOT NULL DEFAULT false,
+    next_run_at TIMESTAMP WITH TIME ZONE,
+    last_run_at TIMESTAMP WITH TIME ZONE,
+    last_status TEXT,
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,2 @@
+ALTER TABLE file_sync_cache ADD COLUMN priority INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX file_sync_cache_priority_idx ON file_sync_cache (priority DESC, created_at);
@@ -0,0 +1,8 @@
+ALTER TABLE file_sync_config ADD COLUMN dst_layout TEXT;
+
+CREATE TABLE layout_mapping (
+    src_url TEXT NOT NULL,
+    dst_url TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (src_url, dst_url)
+);
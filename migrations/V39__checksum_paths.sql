@@ -0,0 +1,17 @@
+CREATE INDEX file_info_cache_md5sum_idx ON file_info_cache (md5sum) WHERE deleted_at IS NULL;
+CREATE INDEX file_info_cache_sha1sum_idx ON file_info_cache (sha1sum) WHERE deleted_at IS NULL;
+CREATE INDEX file_info_cache_modified_at_idx ON file_info_cache (modified_at);
+
+-- Read only mapping of checksums to paths for external dedup / search tools
+CREATE VIEW checksum_paths AS
+    SELECT md5sum, sha1sum, filestat_st_size AS size, filestat_st_mtime AS mtime,
+           servicetype, servicesession, urlname, modified_at
+    FROM file_info_cache
+    WHERE deleted_at IS NULL AND (md5sum IS NOT NULL OR sha1sum IS NOT NULL);
+
+CREATE TABLE checksum_webhook (
+    url TEXT PRIMARY KEY,
+    notified_until TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    last_error TEXT,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
@@ -0,0 +1,12 @@
+-- Uploads in progress (s3 multipart upload id, drive resumable session uri),
+-- a restarted run resumes the upload of the same file instead of starting over
+CREATE TABLE resumable_transfer (
+    src_url TEXT NOT NULL,
+    dst_url TEXT NOT NULL,
+    servicetype TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    file_size BIGINT NOT NULL,
+    file_mtime BIGINT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (src_url, dst_url)
+);
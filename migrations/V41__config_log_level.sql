@@ -0,0 +1 @@
+ALTER TABLE file_sync_config ADD COLUMN log_level TEXT;
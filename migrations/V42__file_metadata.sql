@@ -0,0 +1,22 @@
+-- Metadata read from the files of local directories after indexing, the size
+-- and mtime they had then decide whether they are read again
+CREATE TABLE file_metadata (
+    servicesession TEXT NOT NULL,
+    urlname TEXT NOT NULL,
+    filestat_st_size BIGINT NOT NULL,
+    filestat_st_mtime TIMESTAMP WITH TIME ZONE NOT NULL,
+    mime_type TEXT,
+    width INTEGER,
+    height INTEGER,
+    duration_secs DOUBLE PRECISION,
+    taken_at TIMESTAMP WITH TIME ZONE,
+    camera TEXT,
+    exif JSONB,
+    thumbnail_path TEXT,
+    error TEXT,
+    extracted_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicesession, urlname)
+);
+
+CREATE INDEX file_metadata_mime_type_idx ON file_metadata (mime_type);
+CREATE INDEX file_metadata_taken_at_idx ON file_metadata (taken_at);
@@ -0,0 +1,15 @@
+-- Bytes moved by each run per backend: the file bytes copied and, where the
+-- client can measure them, the bytes on the wire including retries and headers
+CREATE TABLE run_transfer_bytes (
+    run_id UUID NOT NULL REFERENCES run_parameters (id) ON DELETE CASCADE,
+    servicetype TEXT NOT NULL,
+    logical_bytes BIGINT NOT NULL DEFAULT 0,
+    bytes_sent BIGINT NOT NULL DEFAULT 0,
+    bytes_received BIGINT NOT NULL DEFAULT 0,
+    requests BIGINT NOT NULL DEFAULT 0,
+    retries BIGINT NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (run_id, servicetype)
+);
+
+CREATE INDEX run_transfer_bytes_created_at_idx ON run_transfer_bytes (created_at);
@@ -0,0 +1,7 @@
+-- S3 storage class of cached objects, archived ones (GLACIER, DEEP_ARCHIVE)
+-- have to be restored before they can be downloaded
+ALTER TABLE file_info_cache ADD COLUMN storage_class TEXT;
+
+-- Storage class of files uploaded to an s3 dst_url, the bucket default when
+-- unset
+ALTER TABLE file_sync_config ADD COLUMN storage_class TEXT;
@@ -0,0 +1,8 @@
+-- Server side encryption of cached S3 objects, the etags of SSE-KMS objects
+-- aren't md5s
+ALTER TABLE file_info_cache ADD COLUMN sse TEXT;
+
+-- Server side encryption (AES256, aws:kms) and KMS key of files uploaded to
+-- an s3 dst_url, the [s3.<bucket>] setting when unset
+ALTER TABLE file_sync_config ADD COLUMN sse TEXT;
+ALTER TABLE file_sync_config ADD COLUMN sse_kms_key_id TEXT;
@@ -0,0 +1,19 @@
+-- Optimistic concurrency of config edits, an update only applies if the row
+-- still has the version it was read at
+ALTER TABLE file_sync_config ADD COLUMN version INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE file_sync_config ADD COLUMN updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+
+-- Settings of a config before and after each edit, kept after the config is
+-- removed
+CREATE TABLE file_sync_config_audit (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    config_id UUID NOT NULL,
+    name TEXT,
+    version INTEGER NOT NULL,
+    changed_by TEXT NOT NULL,
+    old_values JSONB NOT NULL,
+    new_values JSONB NOT NULL,
+    changed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+
+CREATE INDEX file_sync_config_audit_config_id_idx ON file_sync_config_audit (config_id, changed_at);
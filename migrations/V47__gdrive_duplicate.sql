@@ -0,0 +1,11 @@
+-- Files sharing a path with another file of the same drive folder, cached
+-- under the path with their id appended to the name so that they don't
+-- collide
+CREATE TABLE gdrive_duplicate (
+    servicesession TEXT NOT NULL,
+    gdriveid TEXT NOT NULL,
+    urlname TEXT NOT NULL,
+    disambiguated_urlname TEXT NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (servicesession, gdriveid)
+);
@@ -0,0 +1,5 @@
+-- Drive-side modifiedTime of the last export of a google-native file and
+-- the md5sum of the exported bytes, the export is skipped while neither
+-- changed
+ALTER TABLE gdrive_export ADD COLUMN source_modified TIMESTAMP WITH TIME ZONE;
+ALTER TABLE gdrive_export ADD COLUMN export_md5sum TEXT;
@@ -0,0 +1,6 @@
+-- Urlname of the last file sent to a webhook, files sharing its
+-- notified_until timestamp are paged on (modified_at, urlname)
+ALTER TABLE checksum_webhook ADD COLUMN notified_urlname TEXT NOT NULL DEFAULT '';
+
+DROP INDEX file_info_cache_modified_at_idx;
+CREATE INDEX file_info_cache_modified_at_idx ON file_info_cache (modified_at, urlname);
@@ -0,0 +1,3 @@
+-- How a run ended: succeeded, failed, cancelled or partial (stopped by
+-- --max-runtime with transfers left in the queue), NULL while it runs
+ALTER TABLE run_parameters ADD COLUMN status TEXT;
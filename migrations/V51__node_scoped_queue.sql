@@ -0,0 +1,9 @@
+-- Copies from or to a local file are only taken by workers on the node
+-- that has it, NULL for copies between remote backends
+ALTER TABLE file_sync_cache ADD COLUMN node_id TEXT;
+
+-- The last copy of a pair with a local file is kept per node, '' for pairs
+-- between remote backends
+ALTER TABLE sync_history ADD COLUMN node_id TEXT NOT NULL DEFAULT '';
+ALTER TABLE sync_history DROP CONSTRAINT sync_history_pkey;
+ALTER TABLE sync_history ADD PRIMARY KEY (node_id, url0, url1);
@@ -0,0 +1,3 @@
+-- Where KeepBoth is copying the losing file of a conflict, the pair is only
+-- recorded in sync_history once that copy is done
+ALTER TABLE sync_conflict ADD COLUMN copy_url TEXT;
@@ -0,0 +1,3 @@
+-- Copy on the destination side that follow_moves queued to be moved to
+-- dst_url instead of copying src_url again, NULL for plain copies
+ALTER TABLE file_sync_cache ADD COLUMN move_from TEXT;
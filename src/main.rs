@@ -1,9 +1,9 @@
 use anyhow::Error;
-use sync_app_lib::sync_opts::SyncOpts;
+use sync_app_lib::{log_routing::init_logger, sync_opts::SyncOpts};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    env_logger::init();
+    init_logger()?;
     let stdout = SyncOpts::process_args().await?;
     stdout.close().await?;
     Ok(())
@@ -1,7 +1,8 @@
 use sync_app_http::app::start_app;
+use sync_app_lib::log_routing::init_logger;
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
+    init_logger().unwrap();
     start_app().await.unwrap();
 }
@@ -1,5 +1,6 @@
 use anyhow::Error;
 use deadqueue::unlimited::Queue;
+use futures::TryStreamExt;
 use log::error;
 use reqwest::{Client, ClientBuilder};
 use rweb::{
@@ -13,17 +14,26 @@ use std::{net::SocketAddr, sync::Arc, time};
 use tokio::{sync::Mutex, task::JoinHandle, time::interval};
 
 use sync_app_lib::{
-    calendar_sync::CalendarSync, config::Config, garmin_sync::GarminSync, movie_sync::MovieSync,
-    pgpool::PgPool, security_sync::SecuritySync, sync_opts::SyncOpts, weather_sync::WeatherSync,
+    calendar_sync::CalendarSync, config::Config, garmin_sync::GarminSync, models::SyncJob,
+    movie_sync::MovieSync, pgpool::PgPool, query_stats::QueryStats, schema::ensure_schema,
+    security_sync::SecuritySync, sync_opts::SyncOpts, weather_sync::WeatherSync,
 };
 
 use super::{
     errors::error_response,
+    events::events_path,
     logged_user::{fill_from_db, get_secrets, SyncMesg},
+    requests::{resume_job, run_due_schedules},
     routes::{
-        delete_cache_entry, garmin_scripts_js, list_sync_cache, proc_all, process_cache_entry,
-        remove, sync_all, sync_calendar, sync_frontpage, sync_garmin, sync_movie, sync_name,
-        sync_podcasts, sync_security, sync_weather, user,
+        api_add_checksum_webhook, api_cache, api_cancel_job, api_checksum_duplicates,
+        api_checksum_webhooks, api_checksums, api_checksums_since, api_config, api_file_metadata,
+        api_job, api_remove_checksum_webhook, api_running_jobs, api_status, api_sync_name,
+        api_transfer_bytes, api_update_config, cache_bulk, config_audit, delete_cache_entry,
+        enable_sync_config, garmin_scripts_js, get_maintenance_mode, list_sync_cache,
+        list_sync_config, list_sync_jobs, list_sync_schedules, pause_sync_schedule, proc_all,
+        process_cache_entry, query_stats, remove, requeue, session_trend, set_maintenance_mode,
+        set_sync_schedule, sync_activity, sync_all, sync_calendar, sync_frontpage, sync_garmin,
+        sync_movie, sync_name, sync_podcasts, sync_security, sync_weather, user,
     },
 };
 
@@ -78,6 +88,8 @@ pub async fn start_app() -> Result<(), Error> {
     let config = Config::init_config()?;
     get_secrets(&config.secret_path, &config.jwt_secret_path).await?;
     let pool = PgPool::new(&config.database_url)?;
+    QueryStats::global().set_slow_threshold(time::Duration::from_millis(config.slow_query_ms));
+    ensure_schema(&pool, !config.no_migrate).await?;
 
     tokio::task::spawn(update_db(pool.clone()));
 
@@ -91,9 +103,39 @@ fn get_sync_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
     let sync_name_path = sync_name(app.clone()).boxed();
     let proc_all_path = proc_all(app.clone()).boxed();
     let process_cache_entry_path = process_cache_entry(app.clone()).boxed();
+    let requeue_path = requeue(app.clone()).boxed();
     let remove_path = remove(app.clone()).boxed();
     let list_sync_cache_path = list_sync_cache(app.clone()).boxed();
+    let list_sync_config_path = list_sync_config(app.clone()).boxed();
+    let list_sync_jobs_path = list_sync_jobs(app.clone()).boxed();
+    let sync_activity_path = sync_activity(app.clone()).boxed();
+    let session_trend_path = session_trend(app.clone()).boxed();
+    let query_stats_path = query_stats().boxed();
+    let api_sync_name_path = api_sync_name(app.clone()).boxed();
+    let api_job_path = api_job(app.clone()).boxed();
+    let api_status_path = api_status(app.clone()).boxed();
+    let api_cache_path = api_cache(app.clone()).boxed();
+    let api_running_jobs_path = api_running_jobs().boxed();
+    let api_cancel_job_path = api_cancel_job().boxed();
+    let api_checksums_path = api_checksums(app.clone()).boxed();
+    let api_checksums_since_path = api_checksums_since(app.clone()).boxed();
+    let api_checksum_duplicates_path = api_checksum_duplicates(app.clone()).boxed();
+    let api_checksum_webhooks_path = api_checksum_webhooks(app.clone()).boxed();
+    let api_add_checksum_webhook_path = api_add_checksum_webhook(app.clone()).boxed();
+    let api_remove_checksum_webhook_path = api_remove_checksum_webhook(app.clone()).boxed();
+    let api_file_metadata_path = api_file_metadata(app.clone()).boxed();
+    let api_transfer_bytes_path = api_transfer_bytes(app.clone()).boxed();
+    let list_sync_schedules_path = list_sync_schedules(app.clone()).boxed();
+    let set_sync_schedule_path = set_sync_schedule(app.clone()).boxed();
+    let pause_sync_schedule_path = pause_sync_schedule(app.clone()).boxed();
+    let enable_sync_config_path = enable_sync_config(app.clone()).boxed();
+    let api_config_path = api_config(app.clone()).boxed();
+    let api_update_config_path = api_update_config(app.clone()).boxed();
+    let config_audit_path = config_audit(app.clone()).boxed();
+    let get_maintenance_mode_path = get_maintenance_mode(app.clone()).boxed();
+    let set_maintenance_mode_path = set_maintenance_mode(app.clone()).boxed();
     let delete_cache_entry_path = delete_cache_entry(app.clone()).boxed();
+    let cache_bulk_path = cache_bulk(app.clone()).boxed();
     let sync_garmin_path = sync_garmin(app.clone()).boxed();
     let sync_movie_path = sync_movie(app.clone()).boxed();
     let sync_calendar_path = sync_calendar(app.clone()).boxed();
@@ -107,9 +149,39 @@ fn get_sync_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
         .or(sync_name_path)
         .or(proc_all_path)
         .or(process_cache_entry_path)
+        .or(requeue_path)
         .or(remove_path)
         .or(list_sync_cache_path)
+        .or(list_sync_config_path)
+        .or(list_sync_jobs_path)
+        .or(sync_activity_path)
+        .or(session_trend_path)
+        .or(query_stats_path)
+        .or(api_sync_name_path)
+        .or(api_job_path)
+        .or(api_status_path)
+        .or(api_cache_path)
+        .or(api_running_jobs_path)
+        .or(api_cancel_job_path)
+        .or(api_checksums_path)
+        .or(api_checksums_since_path)
+        .or(api_checksum_duplicates_path)
+        .or(api_checksum_webhooks_path)
+        .or(api_add_checksum_webhook_path)
+        .or(api_remove_checksum_webhook_path)
+        .or(api_file_metadata_path)
+        .or(api_transfer_bytes_path)
+        .or(list_sync_schedules_path)
+        .or(set_sync_schedule_path)
+        .or(pause_sync_schedule_path)
+        .or(enable_sync_config_path)
+        .or(api_config_path)
+        .or(api_update_config_path)
+        .or(config_audit_path)
+        .or(get_maintenance_mode_path)
+        .or(set_maintenance_mode_path)
         .or(delete_cache_entry_path)
+        .or(cache_bulk_path)
         .or(sync_garmin_path)
         .or(sync_movie_path)
         .or(sync_calendar_path)
@@ -120,6 +192,40 @@ fn get_sync_path(app: &AppState) -> BoxedFilter<(impl Reply,)> {
         .boxed()
 }
 
+/// Pick up jobs that were queued or running when the process last exited
+async fn resume_jobs(app: AppState) {
+    let jobs: Vec<SyncJob> = match SyncJob::get_interrupted(&app.db).await {
+        Ok(stream) => match stream.try_collect().await {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                error!("Failed to load interrupted jobs {e}");
+                return;
+            }
+        },
+        Err(e) => {
+            error!("Failed to load interrupted jobs {e}");
+            return;
+        }
+    };
+    for job in jobs {
+        let id = job.id;
+        if let Err(e) = resume_job(job, &app.db, &app.config, &app.locks).await {
+            error!("Failure resuming job {id} {e}");
+        }
+    }
+}
+
+/// Check `sync_schedule` once a minute and run the configs that are due
+async fn run_scheduler(app: AppState) {
+    let mut i = interval(time::Duration::from_secs(60));
+    loop {
+        i.tick().await;
+        if let Err(e) = run_due_schedules(&app.db, &app.config, &app.locks).await {
+            error!("Failure running schedules {e}");
+        }
+    }
+}
+
 async fn run_app(config: Config, pool: PgPool) -> Result<(), Error> {
     async fn run_queue(app: AppState) {
         loop {
@@ -154,6 +260,8 @@ async fn run_app(config: Config, pool: PgPool) -> Result<(), Error> {
     };
 
     tokio::task::spawn(run_queue(app.clone()));
+    tokio::task::spawn(resume_jobs(app.clone()));
+    tokio::task::spawn(run_scheduler(app.clone()));
 
     let (spec, sync_path) = openapi::spec()
         .info(Info {
@@ -182,6 +290,7 @@ async fn run_app(config: Config, pool: PgPool) -> Result<(), Error> {
     let routes = sync_path
         .or(spec_json_path)
         .or(spec_yaml_path)
+        .or(events_path())
         .recover(error_response);
     let addr: SocketAddr = format_sstr!("127.0.0.1:{port}").parse()?;
     rweb::serve(routes).bind(addr).await;
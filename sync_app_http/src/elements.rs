@@ -3,8 +3,15 @@ use dioxus::prelude::{
     VirtualDom,
 };
 
-use stack_string::StackString;
-use sync_app_lib::models::{FileSyncCache, FileSyncConfig};
+use stack_string::{format_sstr, StackString};
+use std::collections::HashMap;
+use sync_app_lib::{
+    models::{
+        FileSyncCache, FileSyncConfig, FileSyncConfigAudit, SessionUsage, SyncActivity, SyncJob,
+    },
+    usage_trend::{format_size, UsageTrend},
+};
+use time::{macros::format_description, Duration, OffsetDateTime};
 
 use crate::errors::ServiceError as Error;
 
@@ -43,6 +50,11 @@ fn IndexElement(conf_list: Vec<FileSyncConfig>, entries: Vec<FileSyncCache>) ->
         let id = v.id;
         let src = &v.src_url;
         let dst = &v.dst_url;
+        let lane = if v.is_interactive() {
+            "(interactive) "
+        } else {
+            ""
+        };
 
         rsx! {
             div {
@@ -60,7 +72,7 @@ fn IndexElement(conf_list: Vec<FileSyncConfig>, entries: Vec<FileSyncCache>) ->
                     "onclick": "deleteEntry('{src}',
                     '{id}')"
                 },
-                "{src} {dst}",
+                "{lane}{src} {dst}",
                 input {
                     "type": "button",
                     name: "DelDst",
@@ -134,6 +146,30 @@ fn IndexElement(conf_list: Vec<FileSyncConfig>, entries: Vec<FileSyncCache>) ->
                     "onclick": "weatherSync();",
                     "Weather Sync"
                 }
+                button {
+                    "type": "submit",
+                    name: "list_jobs",
+                    "onclick": "listJobs();",
+                    "Jobs"
+                }
+                button {
+                    "type": "submit",
+                    name: "list_activity",
+                    "onclick": "listActivity();",
+                    "Activity"
+                }
+                button {
+                    "type": "submit",
+                    name: "list_trend",
+                    "onclick": "listTrend();",
+                    "Trend"
+                }
+                button {
+                    "type": "submit",
+                    name: "list_audit",
+                    "onclick": "listAudit();",
+                    "Audit"
+                }
                 button {
                     name: "garminconnectoutput",
                     id: "garminconnectoutput",
@@ -176,3 +212,329 @@ fn TextElement(text: StackString) -> Element {
         }
     }
 }
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn jobs_body(jobs: Vec<SyncJob>) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(JobsElement, JobsElementProps { jobs });
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer.render_to(&mut buffer, &app)?;
+    Ok(buffer)
+}
+
+#[component]
+fn JobsElement(jobs: Vec<SyncJob>) -> Element {
+    let rows = jobs.iter().enumerate().map(|(idx, job)| {
+        let name = job.name.as_ref().map_or("", StackString::as_str);
+        let email = job.email.as_ref().map_or("", StackString::as_str);
+        let duration = job
+            .duration()
+            .map_or_else(String::new, |d| format!("{:.1}s", d.as_seconds_f64()));
+        let error = job.error.as_ref().map_or("", StackString::as_str);
+        let cancel = if job.status == "running" {
+            let id = job.id;
+            rsx! {
+                input {
+                    "type": "button",
+                    name: "Cancel",
+                    value: "Cancel",
+                    "onclick": "cancelJob('{id}')"
+                }
+            }
+        } else {
+            rsx! {}
+        };
+        rsx! {
+            tr {
+                key: "job-key-{idx}",
+                td {"{job.created_at}"},
+                td {"{job.job_type}"},
+                td {"{name}"},
+                td {"{email}"},
+                td {"{job.status} ", {cancel}},
+                td {"{duration}"},
+                td {"{error}"},
+            }
+        }
+    });
+    rsx! {
+        table {
+            "border": "1",
+            thead {
+                tr {
+                    th {"Created"},
+                    th {"Job"},
+                    th {"Name"},
+                    th {"User"},
+                    th {"Status"},
+                    th {"Duration"},
+                    th {"Error"},
+                }
+            },
+            tbody {
+                {rows}
+            }
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn activity_body(
+    configs: Vec<FileSyncConfig>,
+    activity: Vec<SyncActivity>,
+    days: i32,
+) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(
+        ActivityElement,
+        ActivityElementProps {
+            configs,
+            activity,
+            days,
+        },
+    );
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer.render_to(&mut buffer, &app)?;
+    Ok(buffer)
+}
+
+/// Cell color: grey without transfers, red if anything failed, otherwise a
+/// green getting darker by orders of magnitude of bytes copied
+fn activity_color(activity: Option<&SyncActivity>) -> &'static str {
+    match activity {
+        None => "#ebedf0",
+        Some(a) if a.failures > 0 => "#d73a49",
+        Some(a) if a.bytes < 1_000_000 => "#9be9a8",
+        Some(a) if a.bytes < 100_000_000 => "#40c463",
+        Some(a) if a.bytes < 1_000_000_000 => "#30a14e",
+        Some(_) => "#216e39",
+    }
+}
+
+#[component]
+fn ActivityElement(
+    configs: Vec<FileSyncConfig>,
+    activity: Vec<SyncActivity>,
+    days: i32,
+) -> Element {
+    let today = OffsetDateTime::now_utc().date();
+    let dates: Vec<StackString> = (0..i64::from(days))
+        .rev()
+        .filter_map(|d| {
+            (today - Duration::days(d))
+                .format(format_description!("[year]-[month]-[day]"))
+                .ok()
+                .map(Into::into)
+        })
+        .collect();
+    let activity: HashMap<(&str, &str), &SyncActivity> = activity
+        .iter()
+        .map(|a| ((a.config.as_str(), a.day.as_str()), a))
+        .collect();
+    let rows = configs.iter().enumerate().map(|(idx, conf)| {
+        let config = conf
+            .name
+            .clone()
+            .unwrap_or_else(|| format_sstr!("{} {}", conf.src_url, conf.dst_url));
+        let cells = dates.iter().enumerate().map(|(jdx, day)| {
+            let entry = activity.get(&(config.as_str(), day.as_str())).copied();
+            let color = activity_color(entry);
+            let title = entry.map_or_else(
+                || format_sstr!("{day}: no transfers"),
+                |a| {
+                    format_sstr!(
+                        "{day}: {} transfers, {} bytes, {} failures",
+                        a.transfers,
+                        a.bytes,
+                        a.failures
+                    )
+                },
+            );
+            rsx! {
+                td {
+                    key: "activity-cell-{idx}-{jdx}",
+                    title: "{title}",
+                    style: "background-color: {color}; width: 10px; height: 10px;",
+                }
+            }
+        });
+        rsx! {
+            tr {
+                key: "activity-key-{idx}",
+                td {"{config}"},
+                td {"{conf.last_run}"},
+                {cells}
+            }
+        }
+    });
+    let first = dates.first().cloned().unwrap_or_default();
+    let last = dates.last().cloned().unwrap_or_default();
+    rsx! {
+        table {
+            "border": "0",
+            thead {
+                tr {
+                    th {"Config"},
+                    th {"Last Run"},
+                    th {
+                        colspan: "{days}",
+                        "{first} - {last}"
+                    },
+                }
+            },
+            tbody {
+                {rows}
+            }
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn trend_body(history: Vec<SessionUsage>) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(TrendElement, TrendElementProps { history });
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer.render_to(&mut buffer, &app)?;
+    Ok(buffer)
+}
+
+const CHART_WIDTH: f64 = 600.0;
+const CHART_HEIGHT: f64 = 120.0;
+
+/// svg polyline points of total size over time, scaled to the chart
+fn trend_points(history: &[&SessionUsage]) -> StackString {
+    let times: Vec<f64> = history
+        .iter()
+        .map(|u| u.created_at.unix_timestamp() as f64)
+        .collect();
+    let (t0, t1) = times
+        .iter()
+        .fold((f64::MAX, f64::MIN), |(lo, hi), t| (lo.min(*t), hi.max(*t)));
+    let max_size = history
+        .iter()
+        .map(|u| u.total_size)
+        .max()
+        .unwrap_or(0)
+        .max(1) as f64;
+    let points: Vec<String> = history
+        .iter()
+        .zip(times.iter())
+        .map(|(u, t)| {
+            let x = if t1 > t0 {
+                CHART_WIDTH * (t - t0) / (t1 - t0)
+            } else {
+                0.0
+            };
+            let y = CHART_HEIGHT * (1.0 - u.total_size as f64 / max_size);
+            format!("{x:.1},{y:.1}")
+        })
+        .collect();
+    points.join(" ").into()
+}
+
+#[component]
+fn TrendElement(history: Vec<SessionUsage>) -> Element {
+    let trends = UsageTrend::from_history(&history);
+    let charts = trends.iter().enumerate().map(|(idx, trend)| {
+        let session: Vec<&SessionUsage> = history
+            .iter()
+            .filter(|u| {
+                u.servicetype == trend.last.servicetype
+                    && u.servicesession == trend.last.servicesession
+            })
+            .collect();
+        let points = trend_points(&session);
+        let title = format_sstr!(
+            "{} {} {} / {} files",
+            trend.last.servicetype,
+            trend.last.servicesession,
+            format_size(trend.last.total_size),
+            trend.last.file_count
+        );
+        rsx! {
+            div {
+                key: "trend-key-{idx}",
+                h4 {"{title}"},
+                svg {
+                    width: "{CHART_WIDTH}",
+                    height: "{CHART_HEIGHT}",
+                    style: "border: 1px solid #ccc;",
+                    polyline {
+                        points: "{points}",
+                        fill: "none",
+                        stroke: "#30a14e",
+                        stroke_width: "2",
+                    }
+                },
+                p {"{trend}"},
+            }
+        }
+    });
+    rsx! {
+        div {
+            {charts}
+        }
+    }
+}
+
+/// # Errors
+/// Returns error if formatting fails
+pub fn audit_body(entries: Vec<FileSyncConfigAudit>) -> Result<String, Error> {
+    let mut app = VirtualDom::new_with_props(AuditElement, AuditElementProps { entries });
+    app.rebuild_in_place();
+    let mut renderer = dioxus_ssr::Renderer::default();
+    let mut buffer = String::new();
+    renderer.render_to(&mut buffer, &app)?;
+    Ok(buffer)
+}
+
+/// One row per changed setting of each edit
+#[component]
+fn AuditElement(entries: Vec<FileSyncConfigAudit>) -> Element {
+    let rows = entries.iter().enumerate().flat_map(|(idx, entry)| {
+        let name = entry.name.as_ref().map_or("", StackString::as_str);
+        entry
+            .changes()
+            .into_iter()
+            .enumerate()
+            .map(move |(cidx, change)| {
+                rsx! {
+                    tr {
+                        key: "audit-key-{idx}-{cidx}",
+                        td {"{entry.changed_at}"},
+                        td {"{name}"},
+                        td {"{entry.version}"},
+                        td {"{entry.changed_by}"},
+                        td {"{change.field}"},
+                        td {"{change.old}"},
+                        td {"{change.new}"},
+                    }
+                }
+            })
+    });
+    rsx! {
+        table {
+            "border": "1",
+            thead {
+                tr {
+                    th {"Changed"},
+                    th {"Config"},
+                    th {"Version"},
+                    th {"User"},
+                    th {"Setting"},
+                    th {"Old"},
+                    th {"New"},
+                }
+            },
+            tbody {
+                {rows}
+            }
+        }
+    }
+}
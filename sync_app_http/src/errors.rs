@@ -32,6 +32,8 @@ pub enum ServiceError {
     InternalServerError,
     #[error("BadRequest: {0}")]
     BadRequest(StackString),
+    #[error("Conflict: {0}")]
+    Conflict(StackString),
     #[error("Unauthorized")]
     Unauthorized,
     #[error("Anyhow error {0}")]
@@ -83,6 +85,10 @@ pub async fn error_response(err: Rejection) -> Result<Box<dyn Reply>, Infallible
                 code = StatusCode::BAD_REQUEST;
                 message = msg.as_str();
             }
+            ServiceError::Conflict(msg) => {
+                code = StatusCode::CONFLICT;
+                message = msg.as_str();
+            }
             ServiceError::Unauthorized => {
                 return Ok(Box::new(login_html()));
             }
@@ -131,6 +137,7 @@ impl ResponseEntity for ServiceError {
             (StatusCode::NOT_FOUND, "Not Found"),
             (StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error"),
             (StatusCode::BAD_REQUEST, "Bad Request"),
+            (StatusCode::CONFLICT, "Conflict"),
             (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed"),
         ];
 
@@ -161,6 +168,10 @@ mod test {
         let resp = error_response(err).await?.into_response();
         assert_eq!(resp.status().as_u16(), 400);
 
+        let err = ServiceError::Conflict("TEST CONFLICT".into()).into();
+        let resp = error_response(err).await?.into_response();
+        assert_eq!(resp.status().as_u16(), 409);
+
         let err = ServiceError::InternalServerError.into();
         let resp = error_response(err).await?.into_response();
         assert_eq!(resp.status().as_u16(), 500);
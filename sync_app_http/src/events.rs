@@ -0,0 +1,213 @@
+use futures::{stream, Future};
+use log::debug;
+use once_cell::sync::Lazy;
+use rweb::{
+    filters::{sse, BoxedFilter},
+    Filter, Reply,
+};
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{convert::Infallible, time::Duration};
+use stdout_channel::MockStdout;
+use tokio::{
+    sync::broadcast::{self, error::RecvError},
+    time::interval,
+};
+use uuid::Uuid;
+
+use sync_app_lib::progress::ProgressChannel;
+
+use crate::logged_user::LoggedUser;
+
+static JOB_EVENTS: Lazy<JobEvents> = Lazy::new(JobEvents::new);
+
+/// Events kept for subscribers that fall behind, older ones are dropped
+const EVENT_CAPACITY: usize = 1024;
+
+/// How often the output of a running job is checked for new lines
+const FORWARD_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobEventKind {
+    /// The job changed status, the message is the new status
+    Status,
+    /// A line of output of the job
+    Log,
+    /// The index and transfer counters while the job runs
+    Progress,
+}
+
+impl JobEventKind {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Status => "status",
+            Self::Log => "log",
+            Self::Progress => "progress",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JobEvent {
+    pub job_id: Uuid,
+    pub kind: JobEventKind,
+    pub message: StackString,
+}
+
+impl JobEvent {
+    fn to_sse(&self) -> sse::Event {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        sse::Event::default().event(self.kind.to_str()).data(data)
+    }
+}
+
+/// Where jobs run by the http app publish their status changes, output and
+/// progress, served on `/sync/api/events`.  Events sent while nobody is
+/// subscribed are dropped.
+pub struct JobEvents {
+    sender: broadcast::Sender<JobEvent>,
+}
+
+impl JobEvents {
+    fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        Self { sender }
+    }
+
+    /// The channel shared by the whole process
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &JOB_EVENTS
+    }
+
+    pub fn send(&self, job_id: Uuid, kind: JobEventKind, message: impl Into<StackString>) {
+        let event = JobEvent {
+            job_id,
+            kind,
+            message: message.into(),
+        };
+        self.sender.send(event).ok();
+    }
+
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<JobEvent> {
+        self.sender.subscribe()
+    }
+
+    /// Send the lines added to `mock_stdout` since the first `sent`
+    async fn forward_lines(
+        &self,
+        job_id: Uuid,
+        mock_stdout: &MockStdout<StackString>,
+        sent: &mut usize,
+    ) {
+        let lines = mock_stdout.lock().await;
+        for line in lines.iter().skip(*sent) {
+            self.send(job_id, JobEventKind::Log, line.clone());
+        }
+        *sent = lines.len();
+    }
+}
+
+/// Run `future`, the body of job `job_id` writing its output to
+/// `mock_stdout`, and publish the output lines and progress as they come in
+/// rather than only once the job is done
+pub async fn stream_output<F, T>(
+    job_id: Uuid,
+    mock_stdout: &MockStdout<StackString>,
+    future: F,
+) -> T
+where
+    F: Future<Output = T>,
+{
+    let events = JobEvents::global();
+    let mut progress = ProgressChannel::global().subscribe();
+    let mut ticker = interval(FORWARD_INTERVAL);
+    let mut sent = 0;
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            result = &mut future => {
+                events.forward_lines(job_id, mock_stdout, &mut sent).await;
+                return result;
+            }
+            _ = ticker.tick() => {
+                events.forward_lines(job_id, mock_stdout, &mut sent).await;
+                if progress.has_changed().unwrap_or(false) {
+                    let current = progress.borrow_and_update().to_string();
+                    events.send(job_id, JobEventKind::Progress, current);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JobEventsRequest {
+    /// Only follow this job
+    pub job_id: Option<Uuid>,
+}
+
+/// `/sync/api/events`, a server-sent event stream of `JobEvent`s named after
+/// their kind, optionally limited to one job
+#[must_use]
+pub fn events_path() -> BoxedFilter<(impl Reply,)> {
+    rweb::path!("sync" / "api" / "events")
+        .and(rweb::path::end())
+        .and(rweb::get())
+        .and(LoggedUser::filter())
+        .and(rweb::query::<JobEventsRequest>())
+        .map(|_: LoggedUser, query: JobEventsRequest| {
+            let job_id = query.job_id;
+            let receiver = JobEvents::global().subscribe();
+            let events = stream::unfold(receiver, move |mut receiver| async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) => {
+                            if job_id.map_or(true, |id| id == event.job_id) {
+                                return Some((Ok::<_, Infallible>(event.to_sse()), receiver));
+                            }
+                        }
+                        Err(RecvError::Lagged(skipped)) => {
+                            debug!("event subscriber skipped {skipped}");
+                        }
+                        Err(RecvError::Closed) => return None,
+                    }
+                }
+            });
+            sse::reply(sse::keep_alive().stream(events))
+        })
+        .boxed()
+}
+
+#[cfg(test)]
+mod test {
+    use stack_string::StackString;
+    use stdout_channel::MockStdout;
+    use uuid::Uuid;
+
+    use crate::events::{stream_output, JobEventKind, JobEvents};
+
+    #[tokio::test]
+    async fn test_stream_output() {
+        let job_id = Uuid::new_v4();
+        let mut receiver = JobEvents::global().subscribe();
+        let mock_stdout: MockStdout<StackString> = MockStdout::new();
+        let result = stream_output(job_id, &mock_stdout, async {
+            mock_stdout.lock().await.push("first".into());
+            mock_stdout.lock().await.push("second".into());
+            42
+        })
+        .await;
+        assert_eq!(result, 42);
+        let mut lines = Vec::new();
+        while let Ok(event) = receiver.try_recv() {
+            if event.job_id == job_id && event.kind == JobEventKind::Log {
+                lines.push(event.message.to_string());
+            }
+        }
+        assert_eq!(lines, vec!["first", "second"]);
+    }
+}
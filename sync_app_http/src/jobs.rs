@@ -0,0 +1,102 @@
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use sync_app_lib::models::SyncJob;
+
+static JOB_MANAGER: Lazy<JobManager> = Lazy::new(JobManager::default);
+
+/// A job currently executing in this process
+#[derive(Clone, Debug)]
+pub struct RunningJob {
+    pub job: SyncJob,
+    pub token: CancellationToken,
+}
+
+/// Registry of the jobs running in this process, keyed by `sync_jobs.id`,
+/// so that they can be listed and cancelled over http
+#[derive(Default)]
+pub struct JobManager {
+    jobs: Mutex<HashMap<Uuid, RunningJob>>,
+}
+
+impl JobManager {
+    /// The registry shared by the whole process
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &JOB_MANAGER
+    }
+
+    /// Add `job`, the returned token is cancelled by `cancel`
+    pub fn register(&self, job: &SyncJob) -> CancellationToken {
+        let token = CancellationToken::new();
+        let running = RunningJob {
+            job: job.clone(),
+            token: token.clone(),
+        };
+        self.jobs.lock().insert(job.id, running);
+        token
+    }
+
+    pub fn remove(&self, id: Uuid) {
+        self.jobs.lock().remove(&id);
+    }
+
+    /// Running jobs, oldest first
+    #[must_use]
+    pub fn list(&self) -> Vec<RunningJob> {
+        let mut jobs: Vec<_> = self.jobs.lock().values().cloned().collect();
+        jobs.sort_by_key(|j| j.job.created_at);
+        jobs
+    }
+
+    #[must_use]
+    pub fn get(&self, id: Uuid) -> Option<RunningJob> {
+        self.jobs.lock().get(&id).cloned()
+    }
+
+    /// Ask job `id` to stop, it does so before its next file, returns false
+    /// if no such job is running
+    pub fn cancel(&self, id: Uuid) -> bool {
+        self.jobs.lock().get(&id).map_or(false, |j| {
+            j.token.cancel();
+            true
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use time::OffsetDateTime;
+    use uuid::Uuid;
+
+    use sync_app_lib::models::SyncJob;
+
+    use crate::jobs::JobManager;
+
+    #[test]
+    fn test_job_manager() {
+        let manager = JobManager::default();
+        let job = SyncJob {
+            id: Uuid::new_v4(),
+            job_type: "sync".into(),
+            name: None,
+            email: None,
+            status: "running".into(),
+            created_at: OffsetDateTime::now_utc().into(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+        };
+        let token = manager.register(&job);
+        assert_eq!(manager.list().len(), 1);
+        assert!(!token.is_cancelled());
+        assert!(manager.cancel(job.id));
+        assert!(token.is_cancelled());
+        manager.remove(job.id);
+        assert!(manager.get(job.id).is_none());
+        assert!(!manager.cancel(job.id));
+    }
+}
@@ -10,6 +10,8 @@
 pub mod app;
 pub mod elements;
 pub mod errors;
+pub mod events;
+pub mod jobs;
 pub mod logged_user;
 pub mod requests;
 pub mod routes;
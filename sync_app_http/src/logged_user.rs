@@ -21,13 +21,17 @@ use tokio::task::spawn;
 use url::Url;
 use uuid::Uuid;
 
-use sync_app_lib::{config::Config, models::AuthorizedUsers, pgpool::PgPool};
+use sync_app_lib::{
+    config::Config,
+    models::{AuthorizedUsers, MaintenanceMode, SyncJob},
+    pgpool::PgPool,
+};
 
 use crate::{
-    app::AppState,
+    app::{AccessLocks, AppState},
     errors::ServiceError as Error,
     requests::{
-        CalendarSyncRequest, GarminSyncRequest, MovieSyncRequest, SyncPodcastsRequest,
+        run_job, CalendarSyncRequest, GarminSyncRequest, MovieSyncRequest, SyncPodcastsRequest,
         SyncSecurityRequest, SyncWeatherRequest,
     },
 };
@@ -156,12 +160,13 @@ impl LoggedUser {
                 SyncSession::default(),
             )
             .await?;
+            let job = SyncJob::create(key.to_str(), None, Some(&self.email), &data.db).await?;
             let mesg = SyncMesg::new(self, key);
             data.queue.push((
                 mesg.clone(),
                 spawn({
                     let data = data.clone();
-                    async move { mesg.process_mesg(data).await.map_err(Into::into) }
+                    async move { mesg.process_mesg(data, job).await.map_err(Into::into) }
                 }),
             ));
         }
@@ -311,6 +316,42 @@ impl SyncKey {
     }
 }
 
+impl FromStr for SyncKey {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::all_keys()
+            .iter()
+            .find(|k| k.to_str() == s)
+            .copied()
+            .ok_or_else(|| Error::BadRequest(format_sstr!("Invalid key {s}")))
+    }
+}
+
+impl SyncKey {
+    /// # Errors
+    /// Return error if the sync fails
+    pub async fn handle(
+        self,
+        pool: &PgPool,
+        locks: &AccessLocks,
+    ) -> Result<Vec<StackString>, Error> {
+        if MaintenanceMode::is_enabled(pool).await? {
+            return Ok(vec![format_sstr!(
+                "Maintenance mode enabled, skipping {}",
+                self.to_str()
+            )]);
+        }
+        match self {
+            Self::SyncGarmin => (GarminSyncRequest {}).handle(locks).await,
+            Self::SyncMovie => (MovieSyncRequest {}).handle(locks, pool).await,
+            Self::SyncCalendar => (CalendarSyncRequest {}).handle(locks).await,
+            Self::SyncPodcast => (SyncPodcastsRequest {}).handle(locks).await,
+            Self::SyncSecurity => (SyncSecurityRequest {}).handle(locks).await,
+            Self::SyncWeather => (SyncWeatherRequest {}).handle(locks).await,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct SyncMesg {
     pub user: LoggedUser,
@@ -323,21 +364,14 @@ impl SyncMesg {
         Self { user, key }
     }
 
-    async fn process_mesg(self, app: AppState) -> Result<(), Error> {
+    async fn process_mesg(self, app: AppState, job: SyncJob) -> Result<(), Error> {
         debug!(
             "start {} for {} {}",
             self.key.to_str(),
             self.user.email,
             self.user.session
         );
-        let lines = match self.key {
-            SyncKey::SyncGarmin => (GarminSyncRequest {}).handle(&app.locks).await,
-            SyncKey::SyncMovie => (MovieSyncRequest {}).handle(&app.locks).await,
-            SyncKey::SyncCalendar => (CalendarSyncRequest {}).handle(&app.locks).await,
-            SyncKey::SyncPodcast => (SyncPodcastsRequest {}).handle(&app.locks).await,
-            SyncKey::SyncSecurity => (SyncSecurityRequest {}).handle(&app.locks).await,
-            SyncKey::SyncWeather => (SyncWeatherRequest {}).handle(&app.locks).await,
-        }?;
+        let lines = run_job(job, &app.db, self.key.handle(&app.db, &app.locks)).await?;
         debug!(
             "finished {} for {} {}, {} lines",
             self.key.to_str(),
@@ -1,17 +1,45 @@
-use log::debug;
+use futures::TryStreamExt;
+use log::{debug, error, info};
 use rweb::Schema;
-use rweb_helper::UuidWrapper;
+use rweb_helper::{DateTimeType, UuidWrapper};
 use serde::{Deserialize, Serialize};
-use stack_string::StackString;
-use std::path::Path;
+use stack_string::{format_sstr, StackString};
+use std::{future::Future, path::Path, sync::Arc};
 use stdout_channel::{MockStdout, StdoutChannel};
-use tokio::process::Command;
+use time::{Duration, OffsetDateTime};
+use tokio::{process::Command, task::spawn};
+use url::Url;
+use uuid::Uuid;
 
 use sync_app_lib::{
-    config::Config, file_sync::FileSyncAction, models::FileSyncCache, pgpool::PgPool,
+    byte_accounting::RunMeter,
+    cache_edit::{BulkAction, CacheFilter},
+    cancellation::with_cancellation,
+    config::Config,
+    config_audit::VersionConflict,
+    cron::CronSchedule,
+    file_list_gdrive::GDriveSessions,
+    file_sync::FileSyncAction,
+    models::{
+        ChecksumDuplicate, ChecksumPath, ChecksumWebhook, DailyTransferBytes, FileInfoCache,
+        FileMetadata, FileSyncCache, FileSyncConfig, FileSyncConfigAudit, MaintenanceMode,
+        RunParameters, RunTransferBytes, SessionUsage, SyncActivity, SyncJob, SyncJobStatus,
+        SyncSchedule,
+    },
+    partial::{mark_partial, track_partial},
+    pgpool::PgPool,
+    progress::{Progress, ProgressChannel},
+    storage_class::parse_storage_class,
+    url_wrapper::validate_url,
 };
 
-use crate::{app::AccessLocks, errors::ServiceError as Error};
+use crate::{
+    app::AccessLocks,
+    errors::ServiceError as Error,
+    events::{stream_output, JobEventKind, JobEvents},
+    jobs::{JobManager, RunningJob},
+    logged_user::SyncKey,
+};
 
 pub struct SyncRequest {
     pub action: FileSyncAction,
@@ -19,6 +47,7 @@ pub struct SyncRequest {
 }
 
 impl SyncRequest {
+    /// Record the request as a `sync_jobs` row and run it
     /// # Errors
     /// Return error if db query fails
     pub async fn process(
@@ -26,15 +55,90 @@ impl SyncRequest {
         pool: &PgPool,
         config: &Config,
         locks: &AccessLocks,
+    ) -> Result<Vec<StackString>, Error> {
+        let job = SyncJob::create(
+            self.action.to_str(),
+            self.name.as_ref().map(StackString::as_str),
+            None,
+            pool,
+        )
+        .await?;
+        let job_id = job.id;
+        run_job(job, pool, self.run(job_id, pool, config, locks)).await
+    }
+
+    /// Record the request as a `sync_jobs` row and run it in the background,
+    /// the returned job can be polled on `/sync/api/job/{id}`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn spawn(
+        self,
+        pool: &PgPool,
+        config: &Config,
+        locks: &Arc<AccessLocks>,
+    ) -> Result<SyncJob, Error> {
+        let job = SyncJob::create(
+            self.action.to_str(),
+            self.name.as_ref().map(StackString::as_str),
+            None,
+            pool,
+        )
+        .await?;
+        let queued = job.clone();
+        let pool = pool.clone();
+        let config = config.clone();
+        let locks = locks.clone();
+        spawn(async move {
+            let job_id = job.id;
+            let future = self.run(job_id, &pool, &config, &locks);
+            if let Err(e) = run_job(job, &pool, future).await {
+                error!("job {job_id} failed {e}");
+            }
+        });
+        Ok(queued)
+    }
+
+    async fn run(
+        &self,
+        job_id: Uuid,
+        pool: &PgPool,
+        config: &Config,
+        locks: &AccessLocks,
     ) -> Result<Vec<StackString>, Error> {
         let mut sync = locks.sync.lock().await;
         sync.action = self.action;
         sync.urls = Vec::new();
         sync.name.clone_from(&self.name);
+        // jobs run one at a time under the sync lock, so the byte counters
+        // of the process only move for this one
+        let run_id = sync
+            .record_run(config, Some(job_id), sync.arguments(), pool)
+            .await;
+        let run_meter = run_id.map(RunMeter::start);
         let mock_stdout = MockStdout::new();
         let stdout = StdoutChannel::with_mock_stdout(mock_stdout.clone(), mock_stdout.clone());
-        sync.process_sync_opts(config, pool, &stdout).await?;
-        stdout.close().await?;
+        let (result, partial) = track_partial(stream_output(job_id, &mock_stdout, async {
+            GDriveSessions::scope(sync.process_sync_opts(config, pool, &stdout)).await?;
+            stdout.close().await?;
+            Ok::<_, Error>(())
+        }))
+        .await;
+        if let Some(run_meter) = run_meter {
+            if let Err(e) = run_meter.finish(pool).await {
+                error!("failed to record transfer bytes {e}");
+            }
+        }
+        if let Some(run_id) = run_id {
+            let status = SyncJobStatus::of_run(result.is_err(), partial);
+            if let Err(e) = RunParameters::set_status(run_id, status, pool).await {
+                error!("failed to record run status {e}");
+            }
+        }
+        if partial {
+            // for the status of the job in `run_job`
+            mark_partial();
+        }
+        result?;
         let mut output = Vec::new();
         while let Some(line) = mock_stdout.lock().await.pop() {
             output.push(line);
@@ -44,6 +148,370 @@ impl SyncRequest {
     }
 }
 
+/// Track `future` through the status transitions of `job`, while it runs
+/// the job is listed by `JobManager` and can be cancelled from there
+/// # Errors
+/// Return error if db query fails or `future` fails
+pub async fn run_job<F>(
+    mut job: SyncJob,
+    pool: &PgPool,
+    future: F,
+) -> Result<Vec<StackString>, Error>
+where
+    F: Future<Output = Result<Vec<StackString>, Error>>,
+{
+    let events = JobEvents::global();
+    let manager = JobManager::global();
+    job.set_running(pool).await?;
+    events.send(job.id, JobEventKind::Status, "running");
+    let token = manager.register(&job);
+    let (result, partial) = track_partial(with_cancellation(token.clone(), future)).await;
+    manager.remove(job.id);
+    if token.is_cancelled() && result.is_err() {
+        job.set_cancelled(pool).await?;
+        events.send(job.id, JobEventKind::Status, "cancelled");
+        return result;
+    }
+    let error = result.as_ref().err().map(|e| format_sstr!("{e}"));
+    job.set_finished(error.clone(), partial, pool).await?;
+    match error {
+        Some(e) => events.send(job.id, JobEventKind::Status, format_sstr!("failed {e}")),
+        None => events.send(job.id, JobEventKind::Status, job.status.clone()),
+    }
+    result
+}
+
+/// Re-run a job that was interrupted by a restart, every job type re-reads
+/// its state from scratch so running it twice is harmless
+/// # Errors
+/// Return error if db query fails or the job fails
+pub async fn resume_job(
+    job: SyncJob,
+    pool: &PgPool,
+    config: &Config,
+    locks: &AccessLocks,
+) -> Result<Vec<StackString>, Error> {
+    info!("resuming job {} {}", job.id, job.job_type);
+    if let Ok(key) = job.job_type.parse::<SyncKey>() {
+        return run_job(job, pool, key.handle(pool, locks)).await;
+    }
+    let action: FileSyncAction = job.job_type.parse()?;
+    let req = SyncRequest {
+        action,
+        name: job.name.clone(),
+    };
+    let job_id = job.id;
+    run_job(job, pool, req.run(job_id, pool, config, locks)).await
+}
+
+/// Run the `sync` of every due schedule, one at a time, and record the
+/// outcome.  Nothing runs while maintenance mode is enabled, the schedules
+/// stay due until it is turned off.
+/// # Errors
+/// Return error if db query fails
+pub async fn run_due_schedules(
+    pool: &PgPool,
+    config: &Config,
+    locks: &AccessLocks,
+) -> Result<(), Error> {
+    if MaintenanceMode::is_enabled(pool).await? {
+        debug!("maintenance mode enabled, not running schedules");
+        return Ok(());
+    }
+    for mut schedule in SyncSchedule::get_due(OffsetDateTime::now_utc(), pool).await? {
+        info!("running schedule {} {}", schedule.name, schedule.cron);
+        let started = OffsetDateTime::now_utc();
+        let req = SyncRequest {
+            action: FileSyncAction::Sync,
+            name: Some(schedule.name.clone()),
+        };
+        let result = req.process(pool, config, locks).await;
+        schedule.last_run_at = Some(started.into());
+        match result {
+            Ok(_) => {
+                schedule.last_status = Some("success".into());
+                schedule.last_error = None;
+            }
+            Err(e) => {
+                error!("schedule {} failed {e}", schedule.name);
+                schedule.last_status = Some("failed".into());
+                schedule.last_error = Some(format_sstr!("{e}"));
+            }
+        }
+        schedule.next_run_at = schedule.next_run(OffsetDateTime::now_utc())?;
+        schedule.record_run(pool).await?;
+    }
+    Ok(())
+}
+
+/// A `sync_jobs` row as returned by the json api
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "SyncJobInfo")]
+pub struct SyncJobInfo {
+    #[schema(description = "Job ID")]
+    pub id: UuidWrapper,
+    #[schema(description = "Action or sync type")]
+    pub job_type: StackString,
+    #[schema(description = "Config Name")]
+    pub name: Option<StackString>,
+    #[schema(description = "queued, running, succeeded, failed or cancelled")]
+    pub status: StackString,
+    #[schema(description = "Created At")]
+    pub created_at: DateTimeType,
+    #[schema(description = "Started At")]
+    pub started_at: Option<DateTimeType>,
+    #[schema(description = "Finished At")]
+    pub finished_at: Option<DateTimeType>,
+    #[schema(description = "Error")]
+    pub error: Option<StackString>,
+}
+
+impl From<SyncJob> for SyncJobInfo {
+    fn from(job: SyncJob) -> Self {
+        Self {
+            id: job.id.into(),
+            job_type: job.job_type,
+            name: job.name,
+            status: job.status,
+            created_at: job.created_at.to_offsetdatetime().into(),
+            started_at: job.started_at.map(|t| t.to_offsetdatetime().into()),
+            finished_at: job.finished_at.map(|t| t.to_offsetdatetime().into()),
+            error: job.error,
+        }
+    }
+}
+
+/// A job running in this process as returned by the json api
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "RunningJobInfo")]
+pub struct RunningJobInfo {
+    #[schema(description = "Job")]
+    pub job: SyncJobInfo,
+    #[schema(description = "Cancellation Requested")]
+    pub cancelled: bool,
+    #[schema(description = "Files Scanned by this Process")]
+    pub files_scanned: u64,
+    #[schema(description = "Files Transferred by this Process")]
+    pub files_transferred: u64,
+    #[schema(description = "Bytes Transferred by this Process")]
+    pub bytes_transferred: u64,
+    #[schema(description = "Url being Indexed or Copied")]
+    pub current: Option<StackString>,
+}
+
+impl RunningJobInfo {
+    fn new(running: RunningJob, progress: &Progress) -> Self {
+        Self {
+            job: running.job.into(),
+            cancelled: running.token.is_cancelled(),
+            files_scanned: progress.files_scanned,
+            files_transferred: progress.files_transferred,
+            bytes_transferred: progress.bytes_transferred,
+            current: progress.current.clone(),
+        }
+    }
+
+    /// The jobs registered with `JobManager`, the counters are those of the
+    /// whole process (syncs take `AccessLocks::sync` so only one runs at a
+    /// time)
+    #[must_use]
+    pub fn list() -> Vec<Self> {
+        let progress = ProgressChannel::global().get();
+        JobManager::global()
+            .list()
+            .into_iter()
+            .map(|running| Self::new(running, &progress))
+            .collect()
+    }
+
+    /// Cancel job `id`, `None` if it isn't running in this process
+    #[must_use]
+    pub fn cancel(id: Uuid) -> Option<Self> {
+        let running = JobManager::global().get(id)?;
+        running.token.cancel();
+        Some(Self::new(running, &ProgressChannel::global().get()))
+    }
+}
+
+/// A queued copy as returned by the json api
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "SyncCacheEntry")]
+pub struct SyncCacheEntry {
+    #[schema(description = "Entry ID")]
+    pub id: UuidWrapper,
+    #[schema(description = "Source Url")]
+    pub src_url: StackString,
+    #[schema(description = "Destination Url")]
+    pub dst_url: StackString,
+    #[schema(description = "Queued At")]
+    pub created_at: DateTimeType,
+    #[schema(description = "Priority, higher is transferred first")]
+    pub priority: i32,
+}
+
+impl From<FileSyncCache> for SyncCacheEntry {
+    fn from(entry: FileSyncCache) -> Self {
+        Self {
+            id: entry.id.into(),
+            src_url: entry.src_url,
+            dst_url: entry.dst_url,
+            created_at: entry.created_at.to_offsetdatetime().into(),
+            priority: entry.priority,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct SyncCacheListRequest {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl SyncCacheListRequest {
+    /// Queued copies, highest priority first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<SyncCacheEntry>, Error> {
+        FileSyncCache::get_cache_list_window(pool, self.offset, Some(self.limit.unwrap_or(1000)))
+            .await?
+            .map_ok(Into::into)
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Number and total size of the cached files of one session
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "SessionCount")]
+pub struct SessionCount {
+    #[schema(description = "Service Type")]
+    pub servicetype: StackString,
+    #[schema(description = "Service Session")]
+    pub servicesession: StackString,
+    #[schema(description = "Number of Files")]
+    pub file_count: i64,
+    #[schema(description = "Total Size in Bytes")]
+    pub total_size: i64,
+}
+
+/// Overall state of the service for dashboards and scripts
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "SyncStatus")]
+pub struct SyncStatus {
+    #[schema(description = "Maintenance Mode Enabled")]
+    pub maintenance: bool,
+    #[schema(description = "Number of Queued Copies")]
+    pub queued: i64,
+    #[schema(description = "Number of Queued Interactive Copies")]
+    pub queued_interactive: i64,
+    #[schema(description = "Cached Files per Session")]
+    pub sessions: Vec<SessionCount>,
+    #[schema(description = "Jobs Queued or Running")]
+    pub jobs: Vec<SyncJobInfo>,
+}
+
+impl SyncStatus {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(pool: &PgPool) -> Result<Self, Error> {
+        let maintenance = MaintenanceMode::is_enabled(pool).await?;
+        let (queued, queued_interactive) = FileSyncCache::get_cache_list(pool)
+            .await?
+            .try_fold((0, 0), |(total, interactive), entry| async move {
+                Ok((total + 1, interactive + i64::from(entry.is_interactive())))
+            })
+            .await?;
+        let sessions = FileInfoCache::get_session_sizes(pool)
+            .await?
+            .into_iter()
+            .map(|s| SessionCount {
+                servicetype: s.servicetype,
+                servicesession: s.servicesession,
+                file_count: s.file_count,
+                total_size: s.total_size,
+            })
+            .collect();
+        // every job of this process is either running or waiting for the
+        // sync lock, the same rows a restart would resume
+        let jobs = SyncJob::get_interrupted(pool)
+            .await?
+            .map_ok(Into::into)
+            .try_collect()
+            .await?;
+        Ok(Self {
+            maintenance,
+            queued,
+            queued_interactive,
+            sessions,
+            jobs,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct SyncJobListRequest {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl SyncJobListRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<SyncJob>, Error> {
+        SyncJob::get_recent(pool, self.offset, Some(self.limit.unwrap_or(100)))
+            .await?
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncActivityRequest {
+    pub days: Option<i32>,
+}
+
+impl SyncActivityRequest {
+    /// All configs, so ones without any activity still get a row, and their
+    /// per day activity
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(
+        &self,
+        pool: &PgPool,
+    ) -> Result<(Vec<FileSyncConfig>, Vec<SyncActivity>), Error> {
+        let configs = FileSyncConfig::get_config_list_by_tags(pool, &[], None, None)
+            .await?
+            .try_collect()
+            .await?;
+        let activity = SyncActivity::get_by_day(self.days(), pool).await?;
+        Ok((configs, activity))
+    }
+
+    #[must_use]
+    pub fn days(&self) -> i32 {
+        self.days.unwrap_or(90)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SessionTrendRequest {
+    pub servicesession: Option<StackString>,
+    pub days: Option<i64>,
+}
+
+impl SessionTrendRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<SessionUsage>, Error> {
+        let since = OffsetDateTime::now_utc() - Duration::days(self.days.unwrap_or(90));
+        SessionUsage::get_history(self.servicesession.as_deref(), since, pool)
+            .await
+            .map_err(Into::into)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Schema)]
 pub struct SyncEntryDeleteRequest {
     pub id: UuidWrapper,
@@ -59,6 +527,280 @@ impl SyncEntryDeleteRequest {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct SyncConfigListRequest {
+    pub tag: Option<StackString>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl SyncConfigListRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<FileSyncConfig>, Error> {
+        let tags: Vec<StackString> = self.tag.iter().cloned().collect();
+        FileSyncConfig::get_config_list_by_tags(pool, &tags, self.offset, self.limit)
+            .await?
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncConfigEnableRequest {
+    pub name: StackString,
+    pub enabled: bool,
+    #[schema(description = "Version the Change is Based On")]
+    pub version: Option<i32>,
+}
+
+impl SyncConfigEnableRequest {
+    /// # Errors
+    /// Return error if the config changed since `version` or db query fails
+    pub async fn handle(&self, pool: &PgPool, changed_by: &str) -> Result<(), Error> {
+        let mut conf = get_config(pool, &self.name).await?;
+        conf.enabled = self.enabled;
+        if let Some(version) = self.version {
+            conf.version = version;
+        }
+        conf.update_settings(changed_by, pool)
+            .await
+            .map_err(conflict_error)?;
+        Ok(())
+    }
+}
+
+async fn get_config(pool: &PgPool, name: &str) -> Result<FileSyncConfig, Error> {
+    FileSyncConfig::get_by_name(pool, name)
+        .await?
+        .ok_or_else(|| Error::BadRequest("No config".into()))
+}
+
+/// `VersionConflict` is answered with 409
+fn conflict_error(e: anyhow::Error) -> Error {
+    match e.downcast::<VersionConflict>() {
+        Ok(conflict) => Error::Conflict(format_sstr!("{conflict}")),
+        Err(e) => e.into(),
+    }
+}
+
+/// A config as returned by the json api, `version` has to be passed back
+/// with an update
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "SyncConfigEntry")]
+pub struct SyncConfigEntry {
+    #[schema(description = "Config Name")]
+    pub name: Option<StackString>,
+    #[schema(description = "Source Url")]
+    pub src_url: StackString,
+    #[schema(description = "Destination Url")]
+    pub dst_url: StackString,
+    #[schema(description = "Enabled")]
+    pub enabled: bool,
+    #[schema(description = "Tags")]
+    pub tags: Vec<StackString>,
+    #[schema(description = "Maximum Index Age (seconds)")]
+    pub max_index_age: Option<i64>,
+    #[schema(description = "S3 Storage Class")]
+    pub storage_class: Option<StackString>,
+    #[schema(description = "Version")]
+    pub version: i32,
+    #[schema(description = "Updated At")]
+    pub updated_at: DateTimeType,
+    #[schema(description = "Last Run")]
+    pub last_run: DateTimeType,
+}
+
+impl From<FileSyncConfig> for SyncConfigEntry {
+    fn from(conf: FileSyncConfig) -> Self {
+        Self {
+            name: conf.name,
+            src_url: conf.src_url,
+            dst_url: conf.dst_url,
+            enabled: conf.enabled,
+            tags: conf.tags,
+            max_index_age: conf.max_index_age,
+            storage_class: conf.storage_class,
+            version: conf.version,
+            updated_at: conf.updated_at.to_offsetdatetime().into(),
+            last_run: conf.last_run.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncConfigGetRequest {
+    #[schema(description = "Config Name")]
+    pub name: StackString,
+}
+
+impl SyncConfigGetRequest {
+    /// # Errors
+    /// Return error if the config doesn't exist or db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<SyncConfigEntry, Error> {
+        get_config(pool, &self.name).await.map(Into::into)
+    }
+}
+
+/// Change settings of a config, fields left out keep their value
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncConfigUpdateRequest {
+    #[schema(description = "Config Name")]
+    pub name: StackString,
+    #[schema(description = "Version the Change is Based On")]
+    pub version: i32,
+    #[schema(description = "Enabled")]
+    pub enabled: Option<bool>,
+    #[schema(description = "Comma Separated Tags")]
+    pub tags: Option<StackString>,
+    #[schema(description = "Maximum Index Age (seconds), 0 to always index")]
+    pub max_index_age: Option<i64>,
+    #[schema(description = "S3 Storage Class, empty for the bucket default")]
+    pub storage_class: Option<StackString>,
+}
+
+impl SyncConfigUpdateRequest {
+    /// Apply the change if the config still has `version`, returns the
+    /// updated config
+    /// # Errors
+    /// Return error if a setting is invalid, the config changed since
+    /// `version` or db query fails
+    pub async fn handle(&self, pool: &PgPool, changed_by: &str) -> Result<SyncConfigEntry, Error> {
+        let mut conf = get_config(pool, &self.name).await?;
+        conf.version = self.version;
+        if let Some(enabled) = self.enabled {
+            conf.enabled = enabled;
+        }
+        if let Some(tags) = &self.tags {
+            conf.tags = tags
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(Into::into)
+                .collect();
+        }
+        if let Some(max_index_age) = self.max_index_age {
+            conf.max_index_age = if max_index_age > 0 {
+                Some(max_index_age)
+            } else {
+                None
+            };
+        }
+        if let Some(storage_class) = &self.storage_class {
+            conf.storage_class = if storage_class.is_empty() {
+                None
+            } else {
+                Some(
+                    parse_storage_class(storage_class)
+                        .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?,
+                )
+            };
+        }
+        conf.update_settings(changed_by, pool)
+            .await
+            .map(Into::into)
+            .map_err(conflict_error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct ConfigAuditRequest {
+    #[schema(description = "Config ID")]
+    pub config_id: Option<UuidWrapper>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl ConfigAuditRequest {
+    /// Config edits, newest first, the last 100 unless a limit is given
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<FileSyncConfigAudit>, Error> {
+        FileSyncConfigAudit::get_recent(
+            pool,
+            self.config_id.map(Into::into),
+            self.offset,
+            Some(self.limit.unwrap_or(100)),
+        )
+        .await
+        .map_err(Into::into)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncScheduleRequest {
+    #[schema(description = "Config Name")]
+    pub name: StackString,
+    #[schema(description = "Cron Expression (UTC)")]
+    pub cron: StackString,
+}
+
+impl SyncScheduleRequest {
+    /// Create the schedule of the config `name`, or change its cron
+    /// expression
+    /// # Errors
+    /// Return error if the config doesn't exist, the expression is invalid or
+    /// db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<SyncSchedule, Error> {
+        FileSyncConfig::get_by_name(pool, &self.name)
+            .await?
+            .ok_or_else(|| Error::BadRequest("No config".into()))?;
+        let cron: CronSchedule = self
+            .cron
+            .parse()
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        SyncSchedule::new(&self.name, &cron, OffsetDateTime::now_utc())
+            .upsert(pool)
+            .await?;
+        SyncSchedule::get_by_name(&self.name, pool)
+            .await?
+            .ok_or_else(|| Error::BadRequest("No schedule".into()))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncSchedulePauseRequest {
+    #[schema(description = "Config Name")]
+    pub name: StackString,
+    pub paused: bool,
+}
+
+impl SyncSchedulePauseRequest {
+    /// Resuming a schedule computes its next run from now, runs missed while
+    /// paused are skipped
+    /// # Errors
+    /// Return error if the schedule doesn't exist or db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut schedule = SyncSchedule::get_by_name(&self.name, pool)
+            .await?
+            .ok_or_else(|| Error::BadRequest("No schedule".into()))?;
+        schedule.paused = self.paused;
+        schedule.next_run_at = if self.paused {
+            None
+        } else {
+            schedule.next_run(OffsetDateTime::now_utc())?
+        };
+        schedule.set_paused(pool).await?;
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct MaintenanceModeRequest {
+    pub enabled: bool,
+}
+
+impl MaintenanceModeRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<(), Error> {
+        MaintenanceMode::set_enabled(pool, self.enabled)
+            .await
+            .map_err(Into::into)
+    }
+}
+
 pub struct GarminSyncRequest {}
 
 impl GarminSyncRequest {
@@ -79,12 +821,16 @@ pub struct MovieSyncRequest {}
 impl MovieSyncRequest {
     /// # Errors
     /// Return error if db query fails
-    pub async fn handle(&self, locks: &AccessLocks) -> Result<Vec<StackString>, Error> {
+    pub async fn handle(
+        &self,
+        locks: &AccessLocks,
+        pool: &PgPool,
+    ) -> Result<Vec<StackString>, Error> {
         locks
             .movie
             .lock()
             .await
-            .run_sync()
+            .run_sync(pool)
             .await
             .map_err(Into::into)
     }
@@ -121,7 +867,8 @@ impl SyncRemoveRequest {
         pool: &PgPool,
     ) -> Result<Vec<StackString>, Error> {
         let mut sync = locks.sync.lock().await;
-        let url = self.url.parse()?;
+        let url =
+            validate_url(self.url.parse()?).map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
         sync.action = FileSyncAction::Delete;
         sync.urls = vec![url];
         let mock_stdout = MockStdout::new();
@@ -137,6 +884,74 @@ impl SyncRemoveRequest {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncRequeueRequest {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+}
+
+impl SyncRequeueRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, config: &Config, pool: &PgPool) -> Result<(), Error> {
+        let src_url = validate_url(self.src_url.parse()?)
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        let dst_url = validate_url(self.dst_url.parse()?)
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        // queued by hand, so it goes ahead of the nightly bulk entries
+        FileSyncCache::cache_sync(
+            pool,
+            src_url.as_str(),
+            dst_url.as_str(),
+            FileSyncCache::PRIORITY_INTERACTIVE,
+            &config.node_id(),
+        )
+        .await
+        .map_err(Into::into)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct SyncCacheBulkRequest {
+    /// Url prefix or glob matched against source and destination urls
+    pub pattern: StackString,
+    /// `delete`, `approve` or `requeue`
+    pub action: StackString,
+    /// Only count the matching entries
+    pub dry_run: Option<bool>,
+}
+
+impl SyncCacheBulkRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn process(
+        &self,
+        locks: &AccessLocks,
+        pool: &PgPool,
+        config: &Config,
+    ) -> Result<Vec<StackString>, Error> {
+        let action: BulkAction = self
+            .action
+            .parse()
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        let filter =
+            CacheFilter::new(&self.pattern).map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        let _guard = locks.sync.lock().await;
+        let mock_stdout = MockStdout::new();
+        let stdout = StdoutChannel::with_mock_stdout(mock_stdout.clone(), mock_stdout.clone());
+        filter
+            .apply(action, self.dry_run.unwrap_or(false), config, pool, &stdout)
+            .await?;
+        stdout.close().await?;
+        let mut output = Vec::new();
+        while let Some(line) = mock_stdout.lock().await.pop() {
+            output.push(line);
+        }
+        output.reverse();
+        Ok(output)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Schema)]
 pub struct SyncEntryProcessRequest {
     pub id: UuidWrapper,
@@ -233,3 +1048,298 @@ impl SyncWeatherRequest {
             .map_err(Into::into)
     }
 }
+
+/// A cached file and its checksums, for external dedup / search tools
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "ChecksumEntry")]
+pub struct ChecksumEntry {
+    #[schema(description = "MD5 Sum")]
+    pub md5sum: Option<StackString>,
+    #[schema(description = "SHA1 Sum")]
+    pub sha1sum: Option<StackString>,
+    #[schema(description = "Size in Bytes")]
+    pub size: i64,
+    #[schema(description = "Modification Time")]
+    pub mtime: DateTimeType,
+    #[schema(description = "Service Type")]
+    pub servicetype: StackString,
+    #[schema(description = "Service Session")]
+    pub servicesession: StackString,
+    #[schema(description = "Url")]
+    pub urlname: StackString,
+    #[schema(description = "Last Changed in the Cache")]
+    pub modified_at: DateTimeType,
+}
+
+impl From<ChecksumPath> for ChecksumEntry {
+    fn from(entry: ChecksumPath) -> Self {
+        Self {
+            md5sum: entry.md5sum,
+            sha1sum: entry.sha1sum,
+            size: entry.size,
+            mtime: entry.mtime.to_offsetdatetime().into(),
+            servicetype: entry.servicetype,
+            servicesession: entry.servicesession,
+            urlname: entry.urlname,
+            modified_at: entry.modified_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct ChecksumRequest {
+    #[schema(description = "MD5 or SHA1 Sum")]
+    pub checksum: StackString,
+}
+
+impl ChecksumRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<ChecksumEntry>, Error> {
+        let paths = ChecksumPath::get_by_checksum(&self.checksum, pool).await?;
+        Ok(paths.into_iter().map(Into::into).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct ChecksumSinceRequest {
+    #[schema(description = "Only Files Changed After (RFC3339)")]
+    pub since: Option<DateTimeType>,
+    #[schema(description = "Urlname of the Last File Changed at Since")]
+    pub after: Option<StackString>,
+    pub limit: Option<usize>,
+}
+
+impl ChecksumSinceRequest {
+    /// Files changed after `since` (all files if not given) oldest first, a
+    /// consumer passes the `modified_at` and `urlname` of the last entry as
+    /// `since` and `after` to get the next page
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<ChecksumEntry>, Error> {
+        let since = self.since.map_or(OffsetDateTime::UNIX_EPOCH, Into::into);
+        let after = self.after.as_ref().map_or("", StackString::as_str);
+        let paths = ChecksumPath::get_since(since, after, self.limit.unwrap_or(1000), pool).await?;
+        Ok(paths.into_iter().map(Into::into).collect())
+    }
+}
+
+/// An md5sum shared by several cached files
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "ChecksumDuplicateEntry")]
+pub struct ChecksumDuplicateEntry {
+    #[schema(description = "MD5 Sum")]
+    pub md5sum: StackString,
+    #[schema(description = "Size in Bytes")]
+    pub size: i64,
+    #[schema(description = "Number of Files")]
+    pub count: i64,
+}
+
+impl From<ChecksumDuplicate> for ChecksumDuplicateEntry {
+    fn from(entry: ChecksumDuplicate) -> Self {
+        Self {
+            md5sum: entry.md5sum,
+            size: entry.size,
+            count: entry.count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct ChecksumDuplicatesRequest {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl ChecksumDuplicatesRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<ChecksumDuplicateEntry>, Error> {
+        let duplicates =
+            ChecksumPath::get_duplicates(self.offset, Some(self.limit.unwrap_or(1000)), pool)
+                .await?;
+        Ok(duplicates.into_iter().map(Into::into).collect())
+    }
+}
+
+/// A url POSTed `{"checksums": [...]}` for the files indexed since its last
+/// notification
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "ChecksumWebhookEntry")]
+pub struct ChecksumWebhookEntry {
+    #[schema(description = "Webhook Url")]
+    pub url: StackString,
+    #[schema(description = "Files Changed Up To This Time Were Sent")]
+    pub notified_until: DateTimeType,
+    #[schema(description = "Error of the Last Attempt")]
+    pub last_error: Option<StackString>,
+    #[schema(description = "Registered At")]
+    pub created_at: DateTimeType,
+}
+
+impl From<ChecksumWebhook> for ChecksumWebhookEntry {
+    fn from(webhook: ChecksumWebhook) -> Self {
+        Self {
+            url: webhook.url,
+            notified_until: webhook.notified_until.to_offsetdatetime().into(),
+            last_error: webhook.last_error,
+            created_at: webhook.created_at.to_offsetdatetime().into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema)]
+pub struct ChecksumWebhookRequest {
+    #[schema(description = "Webhook Url")]
+    pub url: StackString,
+}
+
+impl ChecksumWebhookRequest {
+    /// # Errors
+    /// Return error if the url isn't http(s) or db query fails
+    pub async fn register(&self, pool: &PgPool) -> Result<(), Error> {
+        let url: Url = self
+            .url
+            .parse()
+            .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::BadRequest(format_sstr!("Not an http url {url}")));
+        }
+        ChecksumWebhook::insert(url.as_str(), pool)
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn unregister(&self, pool: &PgPool) -> Result<bool, Error> {
+        let removed = ChecksumWebhook::delete(&self.url, pool).await?;
+        Ok(removed > 0)
+    }
+}
+
+/// Metadata extracted from a local file, for search and previews
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "FileMetadataEntry")]
+pub struct FileMetadataEntry {
+    #[schema(description = "Url")]
+    pub urlname: StackString,
+    #[schema(description = "Size in Bytes")]
+    pub size: i64,
+    #[schema(description = "Mime Type")]
+    pub mime_type: Option<StackString>,
+    #[schema(description = "Width in Pixels")]
+    pub width: Option<i32>,
+    #[schema(description = "Height in Pixels")]
+    pub height: Option<i32>,
+    #[schema(description = "Duration in Seconds")]
+    pub duration_secs: Option<f64>,
+    #[schema(description = "Time Taken")]
+    pub taken_at: Option<DateTimeType>,
+    #[schema(description = "Camera")]
+    pub camera: Option<StackString>,
+    #[schema(description = "EXIF Tags (JSON)")]
+    pub exif: Option<StackString>,
+    #[schema(description = "Thumbnail Path")]
+    pub thumbnail_path: Option<StackString>,
+}
+
+impl From<FileMetadata> for FileMetadataEntry {
+    fn from(entry: FileMetadata) -> Self {
+        Self {
+            urlname: entry.urlname,
+            size: entry.filestat_st_size,
+            mime_type: entry.mime_type,
+            width: entry.width,
+            height: entry.height,
+            duration_secs: entry.duration_secs,
+            taken_at: entry.taken_at.map(|t| t.to_offsetdatetime().into()),
+            camera: entry.camera,
+            exif: entry.exif.map(|e| format_sstr!("{e}")),
+            thumbnail_path: entry.thumbnail_path,
+        }
+    }
+}
+
+/// Bytes moved per day to and from one backend
+#[derive(Serialize, Debug, Schema)]
+#[schema(component = "TransferBytesEntry")]
+pub struct TransferBytesEntry {
+    #[schema(description = "Day (UTC)")]
+    pub day: StackString,
+    #[schema(description = "Service Type")]
+    pub servicetype: StackString,
+    #[schema(description = "Bytes of the Files Copied")]
+    pub logical_bytes: i64,
+    #[schema(description = "Bytes Sent (Headers and Retries Included)")]
+    pub bytes_sent: i64,
+    #[schema(description = "Bytes Received (Headers and Retries Included)")]
+    pub bytes_received: i64,
+    #[schema(description = "Number of Requests")]
+    pub requests: i64,
+    #[schema(description = "Number of Retries")]
+    pub retries: i64,
+}
+
+impl From<DailyTransferBytes> for TransferBytesEntry {
+    fn from(entry: DailyTransferBytes) -> Self {
+        Self {
+            day: entry.day,
+            servicetype: entry.servicetype,
+            logical_bytes: entry.logical_bytes,
+            bytes_sent: entry.bytes_sent,
+            bytes_received: entry.bytes_received,
+            requests: entry.requests,
+            retries: entry.retries,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct TransferBytesRequest {
+    #[schema(description = "Number of Days (default 31)")]
+    pub days: Option<i64>,
+}
+
+impl TransferBytesRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<TransferBytesEntry>, Error> {
+        let since = OffsetDateTime::now_utc() - Duration::days(self.days.unwrap_or(31));
+        let entries = RunTransferBytes::get_daily(since, pool).await?;
+        Ok(entries.into_iter().map(Into::into).collect())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Schema, Default)]
+pub struct FileMetadataRequest {
+    #[schema(description = "Mime Type Prefix, e.g. image/ or video/mp4")]
+    pub mime_type: Option<StackString>,
+    #[schema(description = "Camera")]
+    pub camera: Option<StackString>,
+    #[schema(description = "Taken After (RFC3339)")]
+    pub taken_after: Option<DateTimeType>,
+    #[schema(description = "Taken Before (RFC3339)")]
+    pub taken_before: Option<DateTimeType>,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+}
+
+impl FileMetadataRequest {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn handle(&self, pool: &PgPool) -> Result<Vec<FileMetadataEntry>, Error> {
+        let entries = FileMetadata::search(
+            self.mime_type.as_ref().map(StackString::as_str),
+            self.camera.as_ref().map(StackString::as_str),
+            self.taken_after.map(Into::into),
+            self.taken_before.map(Into::into),
+            self.offset,
+            Some(self.limit.unwrap_or(100)),
+            pool,
+        )
+        .await?;
+        Ok(entries.into_iter().map(Into::into).collect())
+    }
+}
@@ -6,17 +6,34 @@ use rweb_helper::{
 use stack_string::{format_sstr, StackString};
 use std::convert::Infallible;
 
+use time::OffsetDateTime;
+use uuid::Uuid;
+
 use sync_app_lib::{
     file_sync::FileSyncAction,
-    models::{FileSyncCache, FileSyncConfig},
+    models::{
+        ChecksumWebhook, ConfigRunSummary, FileSyncCache, FileSyncConfig, SyncJob, SyncSchedule,
+    },
+    query_stats::QueryStats,
+    run_summary::{next_run_column, status_column},
 };
 
 use super::{
     app::AppState,
-    elements::{index_body, text_body},
+    elements::{activity_body, audit_body, index_body, jobs_body, text_body, trend_body},
     errors::ServiceError as Error,
     logged_user::{LoggedUser, SyncKey},
-    requests::{SyncEntryDeleteRequest, SyncEntryProcessRequest, SyncRemoveRequest, SyncRequest},
+    requests::{
+        ChecksumDuplicateEntry, ChecksumDuplicatesRequest, ChecksumEntry, ChecksumRequest,
+        ChecksumSinceRequest, ChecksumWebhookEntry, ChecksumWebhookRequest, ConfigAuditRequest,
+        FileMetadataEntry, FileMetadataRequest, MaintenanceModeRequest, RunningJobInfo,
+        SessionTrendRequest, SyncActivityRequest, SyncCacheBulkRequest, SyncCacheEntry,
+        SyncCacheListRequest, SyncConfigEnableRequest, SyncConfigEntry, SyncConfigGetRequest,
+        SyncConfigListRequest, SyncConfigUpdateRequest, SyncEntryDeleteRequest,
+        SyncEntryProcessRequest, SyncJobInfo, SyncJobListRequest, SyncRemoveRequest, SyncRequest,
+        SyncRequeueRequest, SyncSchedulePauseRequest, SyncScheduleRequest, SyncStatus,
+        TransferBytesEntry, TransferBytesRequest,
+    },
 };
 
 pub type WarpResult<T> = Result<T, Rejection>;
@@ -28,15 +45,11 @@ struct IndexResponse(HtmlBase<String, Error>);
 
 #[get("/sync/index.html")]
 pub async fn sync_frontpage(
+    query: Query<SyncConfigListRequest>,
     #[filter = "LoggedUser::filter"] _: LoggedUser,
     #[data] data: AppState,
 ) -> WarpResult<IndexResponse> {
-    let conf_list: Vec<FileSyncConfig> = FileSyncConfig::get_config_list(&data.db)
-        .await
-        .map_err(Into::<Error>::into)?
-        .try_collect()
-        .await
-        .map_err(Into::<Error>::into)?;
+    let conf_list = query.into_inner().handle(&data.db).await?;
     let entries: Vec<FileSyncCache> = FileSyncCache::get_cache_list(&data.db)
         .await
         .map_err(Into::<Error>::into)?
@@ -124,6 +137,439 @@ pub async fn list_sync_cache(
     Ok(HtmlBase::new(body).into())
 }
 
+#[derive(RwebResponse)]
+#[response(description = "List Sync Configs")]
+struct ListSyncConfigResponse(HtmlBase<String, Error>);
+
+#[get("/sync/list_sync_config")]
+pub async fn list_sync_config(
+    query: Query<SyncConfigListRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ListSyncConfigResponse> {
+    let configs = query.into_inner().handle(&data.db).await?;
+    let ids: Vec<_> = configs.iter().map(|v| v.id).collect();
+    let summaries = ConfigRunSummary::get_by_ids(&ids, &data.db)
+        .await
+        .map_err(Into::<Error>::into)?;
+    let now = OffsetDateTime::now_utc();
+    let entries: Vec<_> = configs
+        .into_iter()
+        .map(|v| {
+            format_sstr!(
+                "{} {} {} {} {} {} {} {}",
+                v.name.unwrap_or_default(),
+                if v.enabled { "enabled" } else { "disabled" },
+                v.tags.join(","),
+                v.last_run,
+                status_column(summaries.get(&v.id), now),
+                next_run_column(summaries.get(&v.id), now),
+                v.src_url,
+                v.dst_url
+            )
+        })
+        .collect();
+    let body = entries.join("\n");
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "List Sync Jobs")]
+struct ListSyncJobsResponse(HtmlBase<String, Error>);
+
+#[get("/sync/list_sync_jobs")]
+pub async fn list_sync_jobs(
+    query: Query<SyncJobListRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ListSyncJobsResponse> {
+    let jobs = query.into_inner().handle(&data.db).await?;
+    let body = jobs_body(jobs)?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Activity")]
+struct SyncActivityResponse(HtmlBase<String, Error>);
+
+#[get("/sync/activity")]
+pub async fn sync_activity(
+    query: Query<SyncActivityRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<SyncActivityResponse> {
+    let query = query.into_inner();
+    let (configs, activity) = query.handle(&data.db).await?;
+    let body = activity_body(configs, activity, query.days())?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Session Usage Trend")]
+struct SessionTrendResponse(HtmlBase<String, Error>);
+
+#[get("/sync/trend")]
+pub async fn session_trend(
+    query: Query<SessionTrendRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<SessionTrendResponse> {
+    let history = query.into_inner().handle(&data.db).await?;
+    let body = trend_body(history)?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Schedules")]
+struct SyncSchedulesResponse(HtmlBase<String, Error>);
+
+#[get("/sync/schedules")]
+pub async fn list_sync_schedules(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<SyncSchedulesResponse> {
+    let schedules = SyncSchedule::get_all(&data.db)
+        .await
+        .map_err(Into::<Error>::into)?;
+    let lines: Vec<_> = schedules.iter().map(|s| format_sstr!("{s}")).collect();
+    Ok(HtmlBase::new(lines.join("\n")).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Set Sync Schedule")]
+struct SetSyncScheduleResponse(HtmlBase<StackString, Error>);
+
+#[post("/sync/schedule")]
+pub async fn set_sync_schedule(
+    query: Query<SyncScheduleRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<SetSyncScheduleResponse> {
+    let schedule = query.into_inner().handle(&data.db).await?;
+    Ok(HtmlBase::new(format_sstr!("{schedule}")).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Pause Sync Schedule")]
+struct PauseSyncScheduleResponse(HtmlBase<&'static str, Error>);
+
+#[post("/sync/schedule/pause")]
+pub async fn pause_sync_schedule(
+    query: Query<SyncSchedulePauseRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<PauseSyncScheduleResponse> {
+    query.into_inner().handle(&data.db).await?;
+    Ok(HtmlBase::new("Finished").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Query Timings")]
+struct QueryStatsResponse(HtmlBase<String, Error>);
+
+#[get("/sync/query_stats")]
+pub async fn query_stats(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+) -> WarpResult<QueryStatsResponse> {
+    let lines = QueryStats::global().report();
+    Ok(HtmlBase::new(lines.join("\n")).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Started Sync Job", status = "CREATED")]
+struct ApiSyncResponse(JsonBase<SyncJobInfo, Error>);
+
+#[post("/sync/api/sync/{name}")]
+pub async fn api_sync_name(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+    name: StackString,
+) -> WarpResult<ApiSyncResponse> {
+    FileSyncConfig::get_by_name(&data.db, &name)
+        .await
+        .map_err(Into::<Error>::into)?
+        .ok_or_else(|| Error::BadRequest(format_sstr!("No config {name}")))?;
+    let req = SyncRequest {
+        action: FileSyncAction::Sync,
+        name: Some(name),
+    };
+    let job: SyncJobInfo = req.spawn(&data.db, &data.config, &data.locks).await?.into();
+    Ok(JsonBase::new(job).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Job")]
+struct ApiJobResponse(JsonBase<SyncJobInfo, Error>);
+
+#[get("/sync/api/job/{id}")]
+pub async fn api_job(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+    id: StackString,
+) -> WarpResult<ApiJobResponse> {
+    let id: Uuid = id
+        .parse()
+        .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+    let job: SyncJobInfo = SyncJob::get_by_id(id, &data.db)
+        .await
+        .map_err(Into::<Error>::into)?
+        .ok_or_else(|| Error::BadRequest("No job".into()))?
+        .into();
+    Ok(JsonBase::new(job).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Status")]
+struct ApiStatusResponse(JsonBase<SyncStatus, Error>);
+
+#[get("/sync/api/status")]
+pub async fn api_status(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiStatusResponse> {
+    let status = SyncStatus::get(&data.db).await?;
+    Ok(JsonBase::new(status).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Queued Copies")]
+struct ApiCacheResponse(JsonBase<Vec<SyncCacheEntry>, Error>);
+
+#[get("/sync/api/cache")]
+pub async fn api_cache(
+    query: Query<SyncCacheListRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiCacheResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Running Jobs")]
+struct ApiRunningJobsResponse(JsonBase<Vec<RunningJobInfo>, Error>);
+
+#[get("/sync/api/jobs/running")]
+pub async fn api_running_jobs(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+) -> WarpResult<ApiRunningJobsResponse> {
+    Ok(JsonBase::new(RunningJobInfo::list()).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Cancelled Job", status = "ACCEPTED")]
+struct ApiCancelJobResponse(JsonBase<RunningJobInfo, Error>);
+
+#[post("/sync/api/job/{id}/cancel")]
+pub async fn api_cancel_job(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    id: StackString,
+) -> WarpResult<ApiCancelJobResponse> {
+    let id: Uuid = id
+        .parse()
+        .map_err(|e| Error::BadRequest(format_sstr!("{e}")))?;
+    let job = RunningJobInfo::cancel(id)
+        .ok_or_else(|| Error::BadRequest(format_sstr!("Job {id} is not running")))?;
+    Ok(JsonBase::new(job).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Files with a Checksum")]
+struct ApiChecksumsResponse(JsonBase<Vec<ChecksumEntry>, Error>);
+
+#[get("/sync/api/checksums")]
+pub async fn api_checksums(
+    query: Query<ChecksumRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiChecksumsResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Files Changed Since")]
+struct ApiChecksumsSinceResponse(JsonBase<Vec<ChecksumEntry>, Error>);
+
+#[get("/sync/api/checksums/since")]
+pub async fn api_checksums_since(
+    query: Query<ChecksumSinceRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiChecksumsSinceResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Duplicate Checksums")]
+struct ApiChecksumDuplicatesResponse(JsonBase<Vec<ChecksumDuplicateEntry>, Error>);
+
+#[get("/sync/api/checksums/duplicates")]
+pub async fn api_checksum_duplicates(
+    query: Query<ChecksumDuplicatesRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiChecksumDuplicatesResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Checksum Webhooks")]
+struct ApiChecksumWebhooksResponse(JsonBase<Vec<ChecksumWebhookEntry>, Error>);
+
+#[get("/sync/api/checksums/webhooks")]
+pub async fn api_checksum_webhooks(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiChecksumWebhooksResponse> {
+    let webhooks = ChecksumWebhook::get_all(&data.db)
+        .await
+        .map_err(Into::<Error>::into)?;
+    let entries = webhooks.into_iter().map(Into::into).collect();
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Registered Checksum Webhook", status = "CREATED")]
+struct ApiAddChecksumWebhookResponse(HtmlBase<&'static str, Error>);
+
+#[post("/sync/api/checksums/webhooks")]
+pub async fn api_add_checksum_webhook(
+    query: Query<ChecksumWebhookRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiAddChecksumWebhookResponse> {
+    query.into_inner().register(&data.db).await?;
+    Ok(HtmlBase::new("Finished").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Removed Checksum Webhook")]
+struct ApiRemoveChecksumWebhookResponse(HtmlBase<&'static str, Error>);
+
+#[delete("/sync/api/checksums/webhooks")]
+pub async fn api_remove_checksum_webhook(
+    query: Query<ChecksumWebhookRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiRemoveChecksumWebhookResponse> {
+    let request = query.into_inner();
+    if request.unregister(&data.db).await? {
+        Ok(HtmlBase::new("Finished").into())
+    } else {
+        Err(Error::BadRequest(format_sstr!("No webhook {}", request.url)).into())
+    }
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Bytes Transferred per Day")]
+struct ApiTransferBytesResponse(JsonBase<Vec<TransferBytesEntry>, Error>);
+
+#[get("/sync/api/transfer_bytes")]
+pub async fn api_transfer_bytes(
+    query: Query<TransferBytesRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiTransferBytesResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "File Metadata")]
+struct ApiFileMetadataResponse(JsonBase<Vec<FileMetadataEntry>, Error>);
+
+#[get("/sync/api/file_metadata")]
+pub async fn api_file_metadata(
+    query: Query<FileMetadataRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiFileMetadataResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entries).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Enable Sync Config")]
+struct EnableSyncConfigResponse(HtmlBase<&'static str, Error>);
+
+#[post("/sync/enable_config")]
+pub async fn enable_sync_config(
+    query: Query<SyncConfigEnableRequest>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<EnableSyncConfigResponse> {
+    query.into_inner().handle(&data.db, &user.email).await?;
+    Ok(HtmlBase::new("Finished").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Sync Config")]
+struct ApiConfigResponse(JsonBase<SyncConfigEntry, Error>);
+
+#[get("/sync/api/config")]
+pub async fn api_config(
+    query: Query<SyncConfigGetRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiConfigResponse> {
+    let entry = query.into_inner().handle(&data.db).await?;
+    Ok(JsonBase::new(entry).into())
+}
+
+#[post("/sync/api/config")]
+pub async fn api_update_config(
+    query: Query<SyncConfigUpdateRequest>,
+    #[filter = "LoggedUser::filter"] user: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ApiConfigResponse> {
+    let entry = query.into_inner().handle(&data.db, &user.email).await?;
+    Ok(JsonBase::new(entry).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Config Audit Trail")]
+struct ConfigAuditResponse(HtmlBase<String, Error>);
+
+#[get("/sync/config_audit")]
+pub async fn config_audit(
+    query: Query<ConfigAuditRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<ConfigAuditResponse> {
+    let entries = query.into_inner().handle(&data.db).await?;
+    let body = audit_body(entries)?;
+    Ok(HtmlBase::new(body).into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Maintenance Mode")]
+struct MaintenanceModeResponse(HtmlBase<&'static str, Error>);
+
+#[get("/sync/maintenance")]
+pub async fn get_maintenance_mode(
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<MaintenanceModeResponse> {
+    let enabled = MaintenanceMode::is_enabled(&data.db)
+        .await
+        .map_err(Into::<Error>::into)?;
+    let body = if enabled { "enabled" } else { "disabled" };
+    Ok(HtmlBase::new(body).into())
+}
+
+#[post("/sync/maintenance")]
+pub async fn set_maintenance_mode(
+    query: Query<MaintenanceModeRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<MaintenanceModeResponse> {
+    query.into_inner().handle(&data.db).await?;
+    Ok(HtmlBase::new("Finished").into())
+}
+
 #[derive(RwebResponse)]
 #[response(description = "Process Entry")]
 struct ProcessEntryResponse(HtmlBase<&'static str, Error>);
@@ -141,6 +587,37 @@ pub async fn process_cache_entry(
     Ok(HtmlBase::new("Finished").into())
 }
 
+#[derive(RwebResponse)]
+#[response(description = "Requeue Entry")]
+struct RequeueEntryResponse(HtmlBase<&'static str, Error>);
+
+#[post("/sync/requeue")]
+pub async fn requeue(
+    query: Query<SyncRequeueRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<RequeueEntryResponse> {
+    query.into_inner().handle(&data.config, &data.db).await?;
+    Ok(HtmlBase::new("Finished").into())
+}
+
+#[derive(RwebResponse)]
+#[response(description = "Bulk Edit Cache Entries")]
+struct CacheBulkResponse(HtmlBase<String, Error>);
+
+#[post("/sync/cache_bulk")]
+pub async fn cache_bulk(
+    query: Query<SyncCacheBulkRequest>,
+    #[filter = "LoggedUser::filter"] _: LoggedUser,
+    #[data] data: AppState,
+) -> WarpResult<CacheBulkResponse> {
+    let lines = query
+        .into_inner()
+        .process(&data.locks, &data.db, &data.config)
+        .await?;
+    Ok(HtmlBase::new(lines.join("\n")).into())
+}
+
 #[derive(RwebResponse)]
 #[response(description = "Delete Cache Entry")]
 struct DeleteEntryResponse(HtmlBase<&'static str, Error>);
@@ -0,0 +1,260 @@
+use anyhow::Error;
+use aws_sdk_s3::config::{
+    interceptors::{
+        BeforeDeserializationInterceptorContextRef, BeforeTransmitInterceptorContextRef,
+    },
+    ConfigBag, Intercept, RuntimeComponents,
+};
+use aws_smithy_runtime_api::{box_error::BoxError, client::retries::RequestAttempts};
+use log::info;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use stack_string::StackString;
+use std::{collections::BTreeMap, fmt};
+use uuid::Uuid;
+
+use gdrive_lib::transfer_stats::TransferStats;
+
+use crate::{models::RunTransferBytes, pgpool::PgPool, usage_trend::format_size};
+
+static BYTE_ACCOUNTING: Lazy<ByteAccounting> = Lazy::new(ByteAccounting::default);
+
+/// Status line of a response, `HTTP/1.1 200 OK\r\n`
+const STATUS_LINE_SIZE: u64 = 17;
+
+/// Bytes moved for one backend: `logical_bytes` are the bytes of the files
+/// copied, the rest what went over the wire to get them there (requests,
+/// headers and retries included), 0 where the client can't measure it
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ByteUsage {
+    pub logical_bytes: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub requests: u64,
+    pub retries: u64,
+}
+
+impl ByteUsage {
+    /// What was added since `earlier`
+    #[must_use]
+    pub fn since(&self, earlier: &Self) -> Self {
+        Self {
+            logical_bytes: self.logical_bytes.saturating_sub(earlier.logical_bytes),
+            bytes_sent: self.bytes_sent.saturating_sub(earlier.bytes_sent),
+            bytes_received: self.bytes_received.saturating_sub(earlier.bytes_received),
+            requests: self.requests.saturating_sub(earlier.requests),
+            retries: self.retries.saturating_sub(earlier.retries),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl fmt::Display for ByteUsage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "files {} sent {} received {} requests {} retries {}",
+            format_size(self.logical_bytes as i64),
+            format_size(self.bytes_sent as i64),
+            format_size(self.bytes_received as i64),
+            self.requests,
+            self.retries,
+        )
+    }
+}
+
+/// Byte counters of this process per servicetype.  S3 is measured by
+/// `ByteCounter`, drive by `gdrive_lib::transfer_stats`, gcs only by its
+/// retries (`TransferStats::gcs`), for the other backends only the file
+/// bytes are known.
+#[derive(Debug, Default)]
+pub struct ByteAccounting {
+    usage: Mutex<BTreeMap<&'static str, ByteUsage>>,
+}
+
+impl ByteAccounting {
+    /// The counters shared by the whole process
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &BYTE_ACCOUNTING
+    }
+
+    fn update(&self, servicetype: &'static str, f: impl FnOnce(&mut ByteUsage)) {
+        f(self.usage.lock().entry(servicetype).or_default());
+    }
+
+    /// A file of `bytes` was copied to or from `servicetype`
+    pub fn logical(&self, servicetype: &'static str, bytes: i64) {
+        self.update(servicetype, |u| u.logical_bytes += bytes.max(0) as u64);
+    }
+
+    pub fn sent(&self, servicetype: &'static str, bytes: u64, retry: bool) {
+        self.update(servicetype, |u| {
+            u.bytes_sent += bytes;
+            u.requests += 1;
+            u.retries += u64::from(retry);
+        });
+    }
+
+    pub fn received(&self, servicetype: &'static str, bytes: u64) {
+        self.update(servicetype, |u| u.bytes_received += bytes);
+    }
+
+    /// Totals so far
+    #[must_use]
+    pub fn snapshot(&self) -> BTreeMap<StackString, ByteUsage> {
+        let mut snapshot: BTreeMap<StackString, ByteUsage> = self
+            .usage
+            .lock()
+            .iter()
+            .map(|(k, v)| ((*k).into(), *v))
+            .collect();
+        let gdrive = TransferStats::global().totals();
+        let entry = snapshot.entry("gdrive".into()).or_default();
+        entry.bytes_sent += gdrive.bytes_sent;
+        entry.bytes_received += gdrive.bytes_received;
+        entry.requests += gdrive.requests;
+        entry.retries += gdrive.retries;
+        let gcs = TransferStats::gcs().totals();
+        snapshot.entry("gs".into()).or_default().retries += gcs.retries;
+        snapshot.retain(|_, v| !v.is_empty());
+        snapshot
+    }
+}
+
+/// Size of the request line and headers of an sdk request
+fn head_size<'a>(line: usize, headers: impl Iterator<Item = (&'a str, &'a str)>) -> u64 {
+    let headers: usize = headers.map(|(k, v)| k.len() + v.len() + 4).sum();
+    (line + headers + 2) as u64
+}
+
+/// Interceptor of the s3 client counting each attempt of each request in
+/// `ByteAccounting`, response bodies by their `Content-Length`
+#[derive(Debug, Clone, Copy)]
+pub struct ByteCounter {
+    servicetype: &'static str,
+}
+
+impl ByteCounter {
+    #[must_use]
+    pub fn new(servicetype: &'static str) -> Self {
+        Self { servicetype }
+    }
+}
+
+impl Intercept for ByteCounter {
+    fn name(&self) -> &'static str {
+        "ByteCounter"
+    }
+
+    fn read_before_transmit(
+        &self,
+        context: &BeforeTransmitInterceptorContextRef<'_>,
+        _: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let request = context.request();
+        let line = request.method().len() + request.uri().len() + 11;
+        let size = head_size(line, request.headers().iter())
+            + request.body().content_length().unwrap_or(0);
+        let retry = cfg
+            .load::<RequestAttempts>()
+            .map_or(false, |a| a.attempts() > 1);
+        ByteAccounting::global().sent(self.servicetype, size, retry);
+        Ok(())
+    }
+
+    fn read_before_deserialization(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _: &RuntimeComponents,
+        _: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let response = context.response();
+        let body: u64 = response
+            .headers()
+            .get("content-length")
+            .and_then(|l| l.parse().ok())
+            .unwrap_or(0);
+        let size = STATUS_LINE_SIZE + head_size(0, response.headers().iter()) + body;
+        ByteAccounting::global().received(self.servicetype, size);
+        Ok(())
+    }
+}
+
+/// Counters at the start of a run, `finish` stores what the run added
+#[derive(Debug)]
+pub struct RunMeter {
+    run_id: Uuid,
+    start: BTreeMap<StackString, ByteUsage>,
+}
+
+impl RunMeter {
+    #[must_use]
+    pub fn start(run_id: Uuid) -> Self {
+        Self {
+            run_id,
+            start: ByteAccounting::global().snapshot(),
+        }
+    }
+
+    /// Store the bytes moved since `start` in `run_transfer_bytes`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn finish(self, pool: &PgPool) -> Result<(), Error> {
+        for (servicetype, usage) in ByteAccounting::global().snapshot() {
+            let usage = match self.start.get(&servicetype) {
+                Some(start) => usage.since(start),
+                None => usage,
+            };
+            if usage.is_empty() {
+                continue;
+            }
+            info!("{servicetype} {usage}");
+            RunTransferBytes::new(self.run_id, &servicetype, &usage)
+                .insert(pool)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::byte_accounting::{ByteAccounting, ByteUsage};
+
+    #[test]
+    fn test_byte_usage() {
+        let accounting = ByteAccounting::default();
+        accounting.logical("ssh", 1000);
+        accounting.sent("ssh", 1100, false);
+        accounting.sent("ssh", 1100, true);
+        accounting.received("ssh", 200);
+        let usage = accounting.snapshot()["ssh"];
+        assert_eq!(
+            usage,
+            ByteUsage {
+                logical_bytes: 1000,
+                bytes_sent: 2200,
+                bytes_received: 200,
+                requests: 2,
+                retries: 1,
+            }
+        );
+        let earlier = ByteUsage {
+            logical_bytes: 500,
+            bytes_sent: 1100,
+            requests: 1,
+            ..ByteUsage::default()
+        };
+        assert_eq!(
+            usage.since(&earlier).to_string(),
+            "files 500 B sent 1.1 KiB received 200 B requests 1 retries 1"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::{format_err, Error};
+use futures::{future, TryStreamExt};
+use stack_string::{format_sstr, StackString};
+use std::{fmt, str::FromStr};
+use stdout_channel::StdoutChannel;
+use uuid::Uuid;
+
+use crate::{
+    config::Config, file_sync::FileSync, ignore_errors::glob_match, models::FileSyncCache,
+    pgpool::PgPool,
+};
+
+/// Number of matching entries listed in a preview
+const PREVIEW_LENGTH: usize = 20;
+
+/// What to do with the pending entries selected by a `CacheFilter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BulkAction {
+    /// drop the entries from the queue
+    Delete,
+    /// copy the entries right away
+    Approve,
+    /// move the entries to the back of the queue
+    Requeue,
+}
+
+impl BulkAction {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Delete => "delete",
+            Self::Approve => "approve",
+            Self::Requeue => "requeue",
+        }
+    }
+}
+
+impl fmt::Display for BulkAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for BulkAction {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delete" => Ok(Self::Delete),
+            "approve" => Ok(Self::Approve),
+            "requeue" => Ok(Self::Requeue),
+            _ => Err(format_err!("Invalid bulk action {s}")),
+        }
+    }
+}
+
+/// Selects `file_sync_cache` entries whose source or destination url matches
+/// a glob (`*` and `?`), a pattern without wildcards is a url prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheFilter(StackString);
+
+impl CacheFilter {
+    /// # Errors
+    /// Return error if `pattern` is empty, an empty pattern would select the
+    /// whole queue
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        if pattern.is_empty() {
+            return Err(format_err!("Empty pattern"));
+        }
+        if pattern.contains(&['*', '?'][..]) {
+            Ok(Self(pattern.into()))
+        } else {
+            Ok(Self(format_sstr!("{pattern}*")))
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, entry: &FileSyncCache) -> bool {
+        glob_match(&self.0, &entry.src_url) || glob_match(&self.0, &entry.dst_url)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_matching(&self, pool: &PgPool) -> Result<Vec<FileSyncCache>, Error> {
+        FileSyncCache::get_cache_list(pool)
+            .await?
+            .try_filter(|entry| future::ready(self.matches(entry)))
+            .try_collect()
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Apply `action` to every matching entry, with `dry_run` only the count
+    /// and the first few matches are reported.  Returns the number of
+    /// matching entries.
+    /// # Errors
+    /// Return error if db query fails, or with `Approve` if any copy fails
+    pub async fn apply(
+        &self,
+        action: BulkAction,
+        dry_run: bool,
+        config: &Config,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<usize, Error> {
+        let entries = self.get_matching(pool).await?;
+        let count = entries.len();
+        if dry_run {
+            for entry in entries.iter().take(PREVIEW_LENGTH) {
+                stdout.send(format_sstr!("{} {}", entry.src_url, entry.dst_url));
+            }
+            if count > PREVIEW_LENGTH {
+                stdout.send(format_sstr!("... and {} more", count - PREVIEW_LENGTH));
+            }
+            stdout.send(format_sstr!("would {action} {count} entries"));
+            return Ok(count);
+        }
+        let ids: Vec<Uuid> = entries.iter().map(|entry| entry.id).collect();
+        let affected = match action {
+            BulkAction::Delete => FileSyncCache::delete_by_ids(&ids, pool).await?,
+            BulkAction::Requeue => FileSyncCache::requeue_by_ids(&ids, pool).await?,
+            BulkAction::Approve => {
+                let entries = FileSyncCache::take_by_ids(&ids, pool).await?;
+                let taken = entries.len();
+                FileSync::new(config.clone())
+                    .process_cache_entries(entries, pool, stdout)
+                    .await?;
+                taken
+            }
+        };
+        stdout.send(format_sstr!("{action} {affected} entries"));
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use uuid::Uuid;
+
+    use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
+    use crate::{
+        cache_edit::{BulkAction, CacheFilter},
+        models::FileSyncCache,
+    };
+
+    fn entry(src_url: &str, dst_url: &str) -> FileSyncCache {
+        FileSyncCache {
+            id: Uuid::new_v4(),
+            src_url: src_url.into(),
+            dst_url: dst_url.into(),
+            created_at: DateTimeWrapper::now(),
+            priority: FileSyncCache::PRIORITY_BULK,
+            node_id: None,
+            move_from: None,
+        }
+    }
+
+    #[test]
+    fn test_cache_filter() -> Result<(), Error> {
+        let photo = entry(
+            "file:///home/ddboline/photos/a.jpg",
+            "s3://backup/photos/a.jpg",
+        );
+        let doc = entry(
+            "gdrive://ddboline@gmail.com/My%20Drive/notes.txt",
+            "file:///home/ddboline/gdrive/notes.txt",
+        );
+
+        let filter = CacheFilter::new("file:///home/ddboline/photos/")?;
+        assert!(filter.matches(&photo));
+        assert!(!filter.matches(&doc));
+
+        let filter = CacheFilter::new("file:///home/ddboline/*")?;
+        assert!(filter.matches(&photo));
+        assert!(filter.matches(&doc));
+
+        let filter = CacheFilter::new("*.jp?")?;
+        assert!(filter.matches(&photo));
+        assert!(!filter.matches(&doc));
+
+        assert!(CacheFilter::new("").is_err());
+        assert_eq!("approve".parse::<BulkAction>()?, BulkAction::Approve);
+        assert!("approve_all".parse::<BulkAction>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use time::{macros::format_description, Date, OffsetDateTime, PrimitiveDateTime};
+
+use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
+use crate::calendar_sync::{CalendarCache, CalendarList};
+
+/// Content lines longer than this many octets are folded (RFC 5545 3.1)
+const MAX_LINE_OCTETS: usize = 75;
+
+fn escape_text(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            ';' => escaped.push_str("\\;"),
+            ',' => escaped.push_str("\\,"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape_text(s: &str) -> StackString {
+    let mut unescaped = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n' | 'N') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => {}
+            }
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped.into()
+}
+
+fn push_line(ics: &mut String, line: &str) {
+    let mut octets = 0;
+    for c in line.chars() {
+        if octets + c.len_utf8() > MAX_LINE_OCTETS {
+            ics.push_str("\r\n ");
+            octets = 1;
+        }
+        ics.push(c);
+        octets += c.len_utf8();
+    }
+    ics.push_str("\r\n");
+}
+
+/// Join folded lines back into content lines
+fn unfold(contents: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(rest) = line.strip_prefix(&[' ', '\t'][..]) {
+            if let Some(last) = lines.last_mut() {
+                last.push_str(rest);
+                continue;
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line.into());
+        }
+    }
+    lines
+}
+
+fn format_datetime(dt: DateTimeWrapper) -> StackString {
+    let dt = dt.to_offset(time::UtcOffset::UTC);
+    format_sstr!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        dt.year(),
+        u8::from(dt.month()),
+        dt.day(),
+        dt.hour(),
+        dt.minute(),
+        dt.second()
+    )
+}
+
+/// Parse a DATE or DATE-TIME value.  There's no timezone database here, so
+/// floating times and times with a `TZID` parameter are taken as UTC.
+fn parse_datetime(value: &str) -> Result<OffsetDateTime, Error> {
+    let value = value.trim_end_matches('Z');
+    if value.len() == 8 {
+        let date = Date::parse(value, format_description!("[year][month][day]"))?;
+        return Ok(date.midnight().assume_utc());
+    }
+    let dt = PrimitiveDateTime::parse(
+        value,
+        format_description!("[year][month][day]T[hour][minute][second]"),
+    )?;
+    Ok(dt.assume_utc())
+}
+
+/// Render the events of `calendar` as an iCalendar file
+#[must_use]
+pub fn calendar_to_ics(calendar: &CalendarList, events: &[&CalendarCache]) -> String {
+    let mut ics = String::new();
+    push_line(&mut ics, "BEGIN:VCALENDAR");
+    push_line(&mut ics, "VERSION:2.0");
+    push_line(&mut ics, "PRODID:-//ddboline//sync_app_rust//EN");
+    let name = calendar
+        .gcal_name
+        .as_ref()
+        .unwrap_or(&calendar.calendar_name);
+    push_line(&mut ics, &format!("X-WR-CALNAME:{}", escape_text(name)));
+    if let Some(timezone) = &calendar.gcal_timezone {
+        push_line(
+            &mut ics,
+            &format!("X-WR-TIMEZONE:{}", escape_text(timezone)),
+        );
+    }
+    for event in events {
+        push_line(&mut ics, "BEGIN:VEVENT");
+        push_line(
+            &mut ics,
+            &format!("UID:{}@{}", escape_text(&event.event_id), calendar.gcal_id),
+        );
+        push_line(
+            &mut ics,
+            &format!("DTSTAMP:{}", format_datetime(event.last_modified)),
+        );
+        push_line(
+            &mut ics,
+            &format!("LAST-MODIFIED:{}", format_datetime(event.last_modified)),
+        );
+        push_line(
+            &mut ics,
+            &format!("DTSTART:{}", format_datetime(event.event_start_time)),
+        );
+        push_line(
+            &mut ics,
+            &format!("DTEND:{}", format_datetime(event.event_end_time)),
+        );
+        push_line(
+            &mut ics,
+            &format!("SUMMARY:{}", escape_text(&event.event_name)),
+        );
+        if let Some(description) = &event.event_description {
+            push_line(
+                &mut ics,
+                &format!("DESCRIPTION:{}", escape_text(description)),
+            );
+        }
+        if let Some(location) = &event.event_location_name {
+            push_line(&mut ics, &format!("LOCATION:{}", escape_text(location)));
+        }
+        if let (Some(lat), Some(lon)) = (event.event_location_lat, event.event_location_lon) {
+            push_line(&mut ics, &format!("GEO:{lat};{lon}"));
+        }
+        if let Some(url) = &event.event_url {
+            push_line(&mut ics, &format!("URL:{url}"));
+        }
+        push_line(&mut ics, "END:VEVENT");
+    }
+    push_line(&mut ics, "END:VCALENDAR");
+    ics
+}
+
+#[derive(Default)]
+struct EventBuilder {
+    uid: Option<StackString>,
+    start: Option<OffsetDateTime>,
+    end: Option<OffsetDateTime>,
+    summary: Option<StackString>,
+    description: Option<StackString>,
+    location: Option<StackString>,
+    geo: Option<(f64, f64)>,
+    url: Option<StackString>,
+    last_modified: Option<OffsetDateTime>,
+}
+
+impl EventBuilder {
+    fn build(self, gcal_id: &str) -> Result<CalendarCache, Error> {
+        let event_id = self.uid.ok_or_else(|| format_err!("VEVENT without UID"))?;
+        let start = self
+            .start
+            .ok_or_else(|| format_err!("VEVENT {event_id} without DTSTART"))?;
+        Ok(CalendarCache {
+            gcal_id: gcal_id.into(),
+            event_id,
+            event_start_time: start.into(),
+            event_end_time: self.end.unwrap_or(start).into(),
+            event_url: self.url,
+            event_name: self.summary.unwrap_or_default(),
+            event_description: self.description,
+            event_location_name: self.location,
+            event_location_lat: self.geo.map(|(lat, _)| lat),
+            event_location_lon: self.geo.map(|(_, lon)| lon),
+            last_modified: self
+                .last_modified
+                .map_or_else(DateTimeWrapper::now, Into::into),
+        })
+    }
+}
+
+/// Events of an iCalendar feed as `calendar_cache` rows of `gcal_id`
+/// # Errors
+/// Return error if an event is missing its UID or start, or has an invalid
+/// date
+pub fn ics_to_events(gcal_id: &str, contents: &str) -> Result<Vec<CalendarCache>, Error> {
+    let mut events = Vec::new();
+    let mut current: Option<EventBuilder> = None;
+    // depth of components (e.g. VALARM) nested inside the current event
+    let mut nested = 0;
+    for line in unfold(contents) {
+        let (name, value) = match line.split_once(':') {
+            Some(x) => x,
+            None => continue,
+        };
+        let name = name.split(';').next().unwrap_or(name).to_uppercase();
+        if current.is_some() && value != "VEVENT" {
+            match name.as_str() {
+                "BEGIN" => nested += 1,
+                "END" => nested -= 1,
+                _ => {}
+            }
+            if nested > 0 || name == "END" {
+                continue;
+            }
+        }
+        match (name.as_str(), current.as_mut()) {
+            ("BEGIN", _) if value == "VEVENT" => current = Some(EventBuilder::default()),
+            ("END", Some(_)) if value == "VEVENT" => {
+                if let Some(event) = current.take() {
+                    events.push(event.build(gcal_id)?);
+                }
+            }
+            ("UID", Some(event)) => event.uid = Some(unescape_text(value)),
+            ("DTSTART", Some(event)) => event.start = Some(parse_datetime(value)?),
+            ("DTEND", Some(event)) => event.end = Some(parse_datetime(value)?),
+            ("SUMMARY", Some(event)) => event.summary = Some(unescape_text(value)),
+            ("DESCRIPTION", Some(event)) => event.description = Some(unescape_text(value)),
+            ("LOCATION", Some(event)) => event.location = Some(unescape_text(value)),
+            ("URL", Some(event)) => event.url = Some(value.into()),
+            ("GEO", Some(event)) => {
+                event.geo = value
+                    .split_once(';')
+                    .and_then(|(lat, lon)| Some((lat.parse().ok()?, lon.parse().ok()?)));
+            }
+            ("LAST-MODIFIED", Some(event)) => event.last_modified = Some(parse_datetime(value)?),
+            ("DTSTAMP", Some(event)) if event.last_modified.is_none() => {
+                event.last_modified = Some(parse_datetime(value)?);
+            }
+            _ => {}
+        }
+    }
+    Ok(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+
+    use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
+    use crate::{
+        calendar_ics::{calendar_to_ics, ics_to_events},
+        calendar_sync::{CalendarCache, CalendarList},
+    };
+
+    #[test]
+    fn test_ics_round_trip() -> Result<(), Error> {
+        let calendar = CalendarList {
+            calendar_name: "ddboline".into(),
+            gcal_id: "ddboline@gmail.com".into(),
+            gcal_name: Some("Daniel's Calendar".into()),
+            gcal_description: None,
+            gcal_location: None,
+            gcal_timezone: Some("America/New_York".into()),
+            sync: true,
+            last_modified: DateTimeWrapper::now(),
+            edit: true,
+            display: true,
+        };
+        let event = CalendarCache {
+            gcal_id: calendar.gcal_id.clone(),
+            event_id: "abc123".into(),
+            event_start_time: datetime!(2023-06-01 14:00 UTC).into(),
+            event_end_time: datetime!(2023-06-01 15:30 UTC).into(),
+            event_url: None,
+            event_name: "Lunch; with friends, maybe".into(),
+            event_description: Some(format!("line one\nline two {}", "x".repeat(100)).into()),
+            event_location_name: Some("Central Park".into()),
+            event_location_lat: Some(40.78),
+            event_location_lon: Some(-73.97),
+            last_modified: datetime!(2023-05-01 00:00 UTC).into(),
+        };
+        let ics = calendar_to_ics(&calendar, &[&event]);
+        assert!(ics.lines().all(|l| l.len() <= 76));
+        assert!(ics.contains("SUMMARY:Lunch\\; with friends\\, maybe\r\n"));
+        assert!(ics.contains("DTSTART:20230601T140000Z\r\n"));
+
+        let events = ics_to_events("feed", &ics)?;
+        assert_eq!(events.len(), 1);
+        let parsed = &events[0];
+        assert_eq!(parsed.gcal_id.as_str(), "feed");
+        assert_eq!(parsed.event_id.as_str(), "abc123@ddboline@gmail.com");
+        assert_eq!(parsed.event_name, event.event_name);
+        assert_eq!(parsed.event_description, event.event_description);
+        assert_eq!(parsed.event_start_time, event.event_start_time);
+        assert_eq!(parsed.event_end_time, event.event_end_time);
+        assert_eq!(parsed.event_location_lat, Some(40.78));
+        assert_eq!(parsed.last_modified, event.last_modified);
+
+        let feed = "BEGIN:VCALENDAR\nBEGIN:VEVENT\nUID:holiday-1\nDTSTART;VALUE=DATE:20231225\n\
+                    SUMMARY:Christmas\nBEGIN:VALARM\nDESCRIPTION:Reminder\nEND:VALARM\n\
+                    END:VEVENT\nEND:VCALENDAR\n";
+        let events = ics_to_events("holidays", feed)?;
+        assert_eq!(*events[0].event_start_time, datetime!(2023-12-25 00:00 UTC));
+        assert_eq!(events[0].event_end_time, events[0].event_start_time);
+        assert_eq!(events[0].event_description, None);
+        Ok(())
+    }
+}
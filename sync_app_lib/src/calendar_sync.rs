@@ -1,16 +1,32 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use log::debug;
 use postgres_query::FromSqlRow;
+use reqwest::header::HeaderMap;
 use serde::{Deserialize, Serialize};
 use stack_string::{format_sstr, StackString};
 use std::{
     collections::HashMap,
+    env::temp_dir,
     fmt::{self, Debug},
 };
+use tokio::fs::{create_dir_all, write};
+use url::Url;
 
 use gdrive_lib::date_time_wrapper::DateTimeWrapper;
 
-use crate::{config::Config, sync_client::SyncClient};
+use crate::{
+    calendar_ics::{calendar_to_ics, ics_to_events},
+    config::Config,
+    encryption::Encryption,
+    file_info::FileInfo,
+    file_list::FileList,
+    file_service::FileService,
+    file_sync::FileSync,
+    http_client::AuthenticatedClient,
+    pgpool::PgPool,
+    reqwest_session::ReqwestSession,
+    sync_client::SyncClient,
+};
 
 #[derive(FromSqlRow, Clone, Debug, Serialize, Deserialize)]
 pub struct CalendarList {
@@ -49,6 +65,7 @@ impl fmt::Display for CalendarCache {
 
 pub struct CalendarSync {
     client: SyncClient,
+    config: Config,
 }
 
 impl CalendarSync {
@@ -56,7 +73,8 @@ impl CalendarSync {
     /// Returns error if creation of client fails
     pub fn new(config: Config) -> Result<Self, Error> {
         Ok(Self {
-            client: SyncClient::new(config, "/usr/bin/calendar-app-rust")?,
+            client: SyncClient::new(config.clone(), "/usr/bin/calendar-app-rust")?,
+            config,
         })
     }
 
@@ -66,6 +84,7 @@ impl CalendarSync {
     pub async fn run_sync(&self) -> Result<Vec<StackString>, Error> {
         self.client.init("calendar", "calendar-sync").await?;
         let mut output = Vec::new();
+        output.extend(self.import_ics_feeds().await?);
         let results = self
             .run_single_sync_calendar_list(
                 "calendar/calendar_list",
@@ -100,11 +119,128 @@ impl CalendarSync {
             .await?;
         output.extend_from_slice(&results);
 
+        output.extend(self.export_ics().await?);
+
         self.client.shutdown().await?;
 
         Ok(output)
     }
 
+    /// Import every `[ics_feed.<calendar_name>]` into the local calendar
+    /// tables, only events not already present are written.  The feed's
+    /// calendar is created (not synced to google) the first time.
+    /// # Errors
+    /// Return error if a feed can't be fetched or parsed
+    pub async fn import_ics_feeds(&self) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
+        if self.config.backends.ics_feed.is_empty() {
+            return Ok(output);
+        }
+        let session = ReqwestSession::new(true, &self.config.proxy_config("sync_client"))?;
+        let calendars: Vec<CalendarList> =
+            self.client.get_local("calendar_list", None, None).await?;
+        let events: HashMap<StackString, CalendarCache> = self
+            .client
+            .get_local::<CalendarCache>("calendar_cache", None, None)
+            .await?
+            .into_iter()
+            .map(|event| (format_sstr!("{event}"), event))
+            .collect();
+        for (name, feed) in &self.config.backends.ics_feed {
+            if !calendars.iter().any(|c| &c.gcal_id == name) {
+                let calendar = CalendarList {
+                    calendar_name: name.clone(),
+                    gcal_id: name.clone(),
+                    gcal_name: Some(name.clone()),
+                    gcal_description: None,
+                    gcal_location: None,
+                    gcal_timezone: None,
+                    sync: false,
+                    last_modified: DateTimeWrapper::now(),
+                    edit: false,
+                    display: true,
+                };
+                self.client
+                    .put_local("calendar_list", &[calendar], None)
+                    .await?;
+            }
+            let url: Url = feed.url.clone().into();
+            let contents = session
+                .get(&url, &HeaderMap::new())
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+            let feed_events: HashMap<StackString, CalendarCache> = ics_to_events(name, &contents)?
+                .into_iter()
+                .map(|event| (format_sstr!("{event}"), event))
+                .collect();
+            let new_events = Self::combine_maps(&feed_events, &events);
+            output.push(format_sstr!(
+                "ics_feed {name} {} events {} new",
+                feed_events.len(),
+                new_events.len()
+            ));
+            self.client
+                .put_local("calendar_cache", &new_events, None)
+                .await?;
+        }
+        Ok(output)
+    }
+
+    /// Write each displayed calendar to `calendar_ics_url` as
+    /// `<calendar_name>.ics`, copied by the file sync engine so the
+    /// destination can be any supported backend
+    /// # Errors
+    /// Return error if the export or copy fails
+    pub async fn export_ics(&self) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
+        let mut destination: Url = match &self.config.calendar_ics_url {
+            Some(url) => url.clone().into(),
+            None => return Ok(output),
+        };
+        if !destination.path().ends_with('/') {
+            let path = format_sstr!("{}/", destination.path());
+            destination.set_path(&path);
+        }
+        let calendars: Vec<CalendarList> =
+            self.client.get_local("calendar_list", None, None).await?;
+        let events: Vec<CalendarCache> =
+            self.client.get_local("calendar_cache", None, None).await?;
+        let pool = PgPool::new(&self.config.database_url)?;
+        let directory = temp_dir().join("calendar_ics");
+        create_dir_all(&directory).await?;
+        let encryption = Encryption::from_config(&self.config)?;
+        for calendar in calendars.iter().filter(|c| c.display) {
+            let calendar_events: Vec<_> = events
+                .iter()
+                .filter(|e| e.gcal_id == calendar.gcal_id)
+                .collect();
+            let filename = format_sstr!("{}.ics", calendar.calendar_name);
+            let local = directory.join(filename.as_str());
+            write(&local, calendar_to_ics(calendar, &calendar_events)).await?;
+
+            let local_url =
+                Url::from_file_path(&local).map_err(|e| format_err!("No file url {e:?}"))?;
+            let remote_url = destination.join(&filename)?;
+            let finfo0 = FileInfo::from_url(&local_url)?;
+            let finfo1 = FileInfo::from_url(&remote_url)?;
+            let flist_url = if finfo1.servicetype == FileService::Local {
+                &local_url
+            } else {
+                &remote_url
+            };
+            let flist = FileList::from_url(flist_url, &self.config, &pool).await?;
+            FileSync::copy_object(&(*flist), &finfo0, &finfo1, encryption.as_ref()).await?;
+            output.push(format_sstr!(
+                "ics {} {} events -> {remote_url}",
+                calendar.calendar_name,
+                calendar_events.len()
+            ));
+        }
+        Ok(output)
+    }
+
     #[allow(clippy::similar_names)]
     async fn run_single_sync_calendar_list<T>(
         &self,
@@ -0,0 +1,60 @@
+use std::future::Future;
+use thiserror::Error;
+use tokio::task_local;
+use tokio_util::sync::CancellationToken;
+
+task_local! {
+    static CANCELLATION: CancellationToken;
+}
+
+/// Returned by `check_cancelled` once the token of the running job has been
+/// cancelled
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("Cancelled")]
+pub struct Cancelled;
+
+/// Run `f` so that the indexing and transfers inside it stop between files
+/// once `token` is cancelled, tasks spawned by `f` don't see the token
+pub async fn with_cancellation<F: Future>(token: CancellationToken, f: F) -> F::Output {
+    CANCELLATION.scope(token, f).await
+}
+
+/// Whether the current job has been cancelled, always false outside of
+/// `with_cancellation`
+#[must_use]
+pub fn is_cancelled() -> bool {
+    CANCELLATION
+        .try_with(CancellationToken::is_cancelled)
+        .unwrap_or(false)
+}
+
+/// # Errors
+/// Return `Cancelled` if the current job has been cancelled
+pub fn check_cancelled() -> Result<(), Cancelled> {
+    if is_cancelled() {
+        Err(Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_util::sync::CancellationToken;
+
+    use crate::cancellation::{check_cancelled, is_cancelled, with_cancellation, Cancelled};
+
+    #[tokio::test]
+    async fn test_cancellation() {
+        assert!(!is_cancelled());
+        let token = CancellationToken::new();
+        let result = with_cancellation(token.clone(), async {
+            check_cancelled()?;
+            token.cancel();
+            check_cancelled()
+        })
+        .await;
+        assert_eq!(result, Err(Cancelled));
+        assert!(!is_cancelled());
+    }
+}
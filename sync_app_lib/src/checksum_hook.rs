@@ -0,0 +1,112 @@
+use anyhow::Error;
+use log::{debug, warn};
+use reqwest::Response;
+use serde::Serialize;
+use stack_string::format_sstr;
+
+use crate::{
+    config::Config,
+    http_client::build_client,
+    models::{ChecksumPath, ChecksumWebhook},
+    pgpool::PgPool,
+};
+
+/// Checksums sent in one request
+const WEBHOOK_BATCH: usize = 1000;
+
+/// Body POSTed to a checksum webhook
+#[derive(Serialize, Debug)]
+pub struct ChecksumEvent<'a> {
+    pub checksums: &'a [ChecksumPath],
+}
+
+/// Next batch after the last file `webhook` was sent
+async fn next_batch(webhook: &ChecksumWebhook, pool: &PgPool) -> Result<Vec<ChecksumPath>, Error> {
+    ChecksumPath::get_since(
+        webhook.notified_until.to_offsetdatetime(),
+        &webhook.notified_urlname,
+        WEBHOOK_BATCH,
+        pool,
+    )
+    .await
+}
+
+/// POST the files that got a checksum since each webhook was last notified,
+/// returns the number of files sent.  A webhook that fails keeps its
+/// position and records the error, it's retried after the next index.
+/// # Errors
+/// Return error if db query fails
+pub async fn notify_checksum_webhooks(config: &Config, pool: &PgPool) -> Result<usize, Error> {
+    let webhooks = ChecksumWebhook::get_all(pool).await?;
+    if webhooks.is_empty() {
+        return Ok(0);
+    }
+    let client = build_client(false, &config.proxy_config("webhook"))?;
+    let mut sent = 0;
+    for mut webhook in webhooks {
+        loop {
+            let checksums = next_batch(&webhook, pool).await?;
+            let (last, last_urlname) = match checksums.last() {
+                Some(c) => (c.modified_at, c.urlname.clone()),
+                None => break,
+            };
+            let result = client
+                .post(webhook.url.as_str())
+                .json(&ChecksumEvent {
+                    checksums: &checksums,
+                })
+                .send()
+                .await
+                .and_then(Response::error_for_status);
+            if let Err(e) = result {
+                warn!("checksum webhook {} failed {e}", webhook.url);
+                webhook.last_error = Some(format_sstr!("{e}"));
+                webhook.update(pool).await?;
+                break;
+            }
+            debug!("sent {} checksums to {}", checksums.len(), webhook.url);
+            sent += checksums.len();
+            webhook.notified_until = last;
+            webhook.notified_urlname = last_urlname;
+            webhook.last_error = None;
+            webhook.update(pool).await?;
+        }
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use serde_json::json;
+    use time::macros::datetime;
+
+    use crate::{checksum_hook::ChecksumEvent, models::ChecksumPath};
+
+    #[test]
+    fn test_checksum_event() -> Result<(), Error> {
+        let checksums = [ChecksumPath {
+            md5sum: Some("d41d8cd98f00b204e9800998ecf8427e".into()),
+            sha1sum: None,
+            size: 0,
+            mtime: datetime!(2024-01-02 03:04:05 UTC).into(),
+            servicetype: "local".into(),
+            servicesession: "host:/home/me".into(),
+            urlname: "file:///home/me/empty".into(),
+            modified_at: datetime!(2024-01-02 03:04:05 UTC).into(),
+        }];
+        let value = serde_json::to_value(ChecksumEvent {
+            checksums: &checksums,
+        })?;
+        assert_eq!(
+            value["checksums"][0]["md5sum"],
+            json!("d41d8cd98f00b204e9800998ecf8427e")
+        );
+        assert_eq!(
+            value["checksums"][0]["urlname"],
+            json!("file:///home/me/empty")
+        );
+        assert_eq!(value["checksums"][0]["sha1sum"], json!(null));
+        Ok(())
+    }
+}
@@ -0,0 +1,190 @@
+use anyhow::{format_err, Error};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression as GzLevel};
+use std::{
+    fmt,
+    fs::File,
+    io::{copy, BufReader, BufWriter, Write},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    file_info::FileInfo,
+    file_service::FileService,
+    models::{CompressedFile, FileSyncConfig},
+    pgpool::PgPool,
+};
+
+/// Level used for zstd, favours speed since uploads are usually the
+/// bottleneck
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Zstd,
+    Gzip,
+}
+
+impl Codec {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Zstd => "zstd",
+            Self::Gzip => "gzip",
+        }
+    }
+
+    /// # Errors
+    /// Return error if reading / writing fails
+    pub fn compress_file(self, src: &Path, dst: &Path) -> Result<u64, Error> {
+        let mut reader = BufReader::new(File::open(src)?);
+        let writer = BufWriter::new(File::create(dst)?);
+        match self {
+            Self::Zstd => {
+                let mut encoder = zstd::Encoder::new(writer, ZSTD_LEVEL)?;
+                copy(&mut reader, &mut encoder)?;
+                encoder.finish()?.flush()?;
+            }
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(writer, GzLevel::default());
+                copy(&mut reader, &mut encoder)?;
+                encoder.finish()?.flush()?;
+            }
+        }
+        Ok(dst.metadata()?.len())
+    }
+
+    /// # Errors
+    /// Return error if reading / writing fails or `src` isn't valid
+    pub fn decompress_file(self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let reader = BufReader::new(File::open(src)?);
+        let mut writer = BufWriter::new(File::create(dst)?);
+        match self {
+            Self::Zstd => {
+                copy(&mut zstd::Decoder::new(reader)?, &mut writer)?;
+            }
+            Self::Gzip => {
+                copy(&mut GzDecoder::new(reader), &mut writer)?;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for Codec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zstd" => Ok(Self::Zstd),
+            "gzip" | "gz" => Ok(Self::Gzip),
+            _ => Err(format_err!("Unknown codec {s}, expected zstd or gzip")),
+        }
+    }
+}
+
+/// Compression set on a config, files of at least `min_size` bytes written
+/// to its cloud `dst_url` are compressed with `codec` under their own name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compression {
+    pub codec: Codec,
+    pub min_size: i64,
+}
+
+impl Compression {
+    /// Targets uploads to which may be compressed
+    #[must_use]
+    pub fn applies_to(servicetype: FileService) -> bool {
+        matches!(
+            servicetype,
+            FileService::S3 | FileService::GDrive | FileService::GCS
+        )
+    }
+
+    /// # Errors
+    /// Return error if the stored codec is invalid
+    pub fn from_config(config: &FileSyncConfig) -> Result<Option<Self>, Error> {
+        match &config.compression {
+            Some(codec) => Ok(Some(Self {
+                codec: codec.parse()?,
+                min_size: config.compression_min_size.unwrap_or(0),
+            })),
+            None => Ok(None),
+        }
+    }
+
+    /// Compression for uploading `size` bytes to `url`, `None` if the
+    /// config of `url` doesn't compress or the file is too small
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn for_upload(url: &str, size: i64, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let compression = match FileSyncConfig::get_compressed_dst(url, pool).await? {
+            Some(config) => Self::from_config(&config)?,
+            None => None,
+        };
+        Ok(compression.filter(|c| size >= c.min_size))
+    }
+}
+
+/// `finfo` with the size and md5sum of the original file if it was uploaded
+/// compressed and hasn't been replaced since, so that `compare_objects`
+/// compares the original content
+/// # Errors
+/// Return error if db query fails
+pub async fn original_info(finfo: FileInfo, pool: &PgPool) -> Result<FileInfo, Error> {
+    if !Compression::applies_to(finfo.servicetype) {
+        return Ok(finfo);
+    }
+    match CompressedFile::get_by_url(finfo.urlname.as_str(), pool).await? {
+        Some(entry) if entry.compressed_size == finfo.filestat.st_size => {
+            let mut inner = finfo.inner().clone();
+            inner.filestat.st_size = entry.original_size;
+            inner.md5sum = entry
+                .original_md5sum
+                .as_ref()
+                .map(|m| m.parse())
+                .transpose()?;
+            Ok(FileInfo::from_inner(inner))
+        }
+        _ => Ok(finfo),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use std::env::temp_dir;
+    use uuid::Uuid;
+
+    use crate::compression::Codec;
+
+    #[test]
+    fn test_codec_round_trip() -> Result<(), Error> {
+        let dir = temp_dir().join(format_sstr!("compression_{}", Uuid::new_v4()).as_str());
+        std::fs::create_dir_all(&dir)?;
+        let clear = dir.join("clear");
+        let data = "some very compressible text ".repeat(1000);
+        std::fs::write(&clear, &data)?;
+        for codec in &[Codec::Zstd, Codec::Gzip] {
+            let compressed = dir.join(codec.to_str());
+            let decompressed = dir.join("decompressed");
+            let size = codec.compress_file(&clear, &compressed)?;
+            assert!(size < data.len() as u64);
+            codec.decompress_file(&compressed, &decompressed)?;
+            assert_eq!(std::fs::read_to_string(&decompressed)?, data);
+            let parsed: Codec = codec.to_str().parse()?;
+            assert_eq!(parsed, *codec);
+        }
+        assert!("lz4".parse::<Codec>().is_err());
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+use serde_json::Value;
+use stack_string::{format_sstr, StackString};
+use std::{env::var, fmt};
+
+/// Returned by `FileSyncConfig::update_settings` when the stored config no
+/// longer has the version the edit was based on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VersionConflict {
+    pub name: StackString,
+    pub expected: i32,
+    /// `None` if the config was removed
+    pub current: Option<i32>,
+}
+
+impl fmt::Display for VersionConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.current {
+            Some(current) => write!(
+                f,
+                "config {} was changed, version {current} is not {}",
+                self.name, self.expected
+            ),
+            None => write!(f, "config {} was removed", self.name),
+        }
+    }
+}
+
+impl std::error::Error for VersionConflict {}
+
+/// `changed_by` of config edits made from the command line, the login of
+/// whoever runs it
+#[must_use]
+pub fn cli_user() -> StackString {
+    let user = var("USER").unwrap_or_else(|_| "unknown".into());
+    format_sstr!("cli:{user}")
+}
+
+/// One setting changed by a config edit
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigChange {
+    pub field: StackString,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// Fields of the json objects `old` and `new` whose values differ, in field
+/// order
+#[must_use]
+pub fn config_changes(old: &Value, new: &Value) -> Vec<ConfigChange> {
+    let (old, new) = match (old.as_object(), new.as_object()) {
+        (Some(old), Some(new)) => (old, new),
+        _ => return Vec::new(),
+    };
+    let mut fields: Vec<&String> = old.keys().chain(new.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let old = old.get(field).cloned().unwrap_or(Value::Null);
+            let new = new.get(field).cloned().unwrap_or(Value::Null);
+            if old == new {
+                None
+            } else {
+                Some(ConfigChange {
+                    field: field.as_str().into(),
+                    old,
+                    new,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use serde_json::json;
+
+    use crate::config_audit::{config_changes, VersionConflict};
+
+    #[test]
+    fn test_config_changes() {
+        let old = json!({"enabled": true, "tags": ["a"], "log_level": null, "name": "photos"});
+        let new =
+            json!({"enabled": false, "tags": ["a", "b"], "log_level": null, "name": "photos"});
+        let changes = config_changes(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[0].field.as_str(), "enabled");
+        assert_eq!(changes[0].old, json!(true));
+        assert_eq!(changes[0].new, json!(false));
+        assert_eq!(changes[1].field.as_str(), "tags");
+        assert!(config_changes(&new, &new).is_empty());
+    }
+
+    #[test]
+    fn test_version_conflict_downcast() {
+        let conflict = VersionConflict {
+            name: "photos".into(),
+            expected: 3,
+            current: Some(4),
+        };
+        let e: Error = conflict.clone().into();
+        assert_eq!(
+            e.to_string(),
+            "config photos was changed, version 4 is not 3"
+        );
+        assert_eq!(e.downcast_ref::<VersionConflict>(), Some(&conflict));
+    }
+}
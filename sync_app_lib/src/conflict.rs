@@ -0,0 +1,252 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{fmt, path::Path, str::FromStr};
+use time::{Date, Duration};
+use url::Url;
+
+use crate::{
+    file_info::{FileInfo, FileStat},
+    models::SyncHistory,
+};
+
+/// How a pair of files that both changed since their last sync is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictPolicy {
+    /// The file with the newer mtime is copied over the other
+    NewerWins,
+    /// The larger file is copied over the smaller
+    LargerWins,
+    /// The newer file is copied over the other, the older is kept next to
+    /// the newer under a `.conflict-<date>` name
+    KeepBoth,
+    /// Nothing is copied until the conflict is resolved by hand
+    #[default]
+    Manual,
+}
+
+impl ConflictPolicy {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::NewerWins => "newer-wins",
+            Self::LargerWins => "larger-wins",
+            Self::KeepBoth => "keep-both",
+            Self::Manual => "manual",
+        }
+    }
+
+    /// What to do about the conflicting `finfo0` (source side) and `finfo1`
+    /// (destination side), `clock_skew` is how far the source's clock runs
+    /// ahead of the destination's
+    #[must_use]
+    pub fn resolve(
+        self,
+        finfo0: &FileInfo,
+        finfo1: &FileInfo,
+        mtime_tolerance: Duration,
+        clock_skew: Duration,
+    ) -> Resolution {
+        let stat0 = finfo0.filestat.with_clock_offset(clock_skew);
+        let newer1 = finfo1.filestat.is_newer_than(&stat0, mtime_tolerance);
+        match self {
+            Self::NewerWins if newer1 => Resolution::Reverse,
+            Self::NewerWins => Resolution::Forward,
+            Self::LargerWins if finfo1.filestat.st_size > finfo0.filestat.st_size => {
+                Resolution::Reverse
+            }
+            Self::LargerWins => Resolution::Forward,
+            Self::KeepBoth => Resolution::KeepBoth { forward: !newer1 },
+            Self::Manual => Resolution::Skip,
+        }
+    }
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for ConflictPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newer-wins" | "newer_wins" => Ok(Self::NewerWins),
+            "larger-wins" | "larger_wins" => Ok(Self::LargerWins),
+            "keep-both" | "keep_both" => Ok(Self::KeepBoth),
+            "manual" => Ok(Self::Manual),
+            _ => Err(format_err!("Invalid conflict policy {s}")),
+        }
+    }
+}
+
+/// Outcome of `ConflictPolicy::resolve`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// Copy the source side over the destination
+    Forward,
+    /// Copy the destination side over the source
+    Reverse,
+    /// Copy the loser next to the winner under a conflict name, the winner
+    /// (the source side if `forward`) replaces the loser on the next run
+    KeepBoth { forward: bool },
+    /// Leave both alone
+    Skip,
+}
+
+/// Whether `finfo` no longer holds what was synced in `history`, compared by
+/// md5sum where both have one and by size otherwise
+#[must_use]
+pub fn changed_since(history: &SyncHistory, finfo: &FileInfo) -> bool {
+    match (&history.md5sum, &finfo.md5sum) {
+        (Some(md5sum), Some(current)) => md5sum.as_str() != current.as_str(),
+        _ => history.filestat_st_size != finfo.filestat.st_size,
+    }
+}
+
+/// `photo.jpg` becomes `photo.conflict-2024-01-02.jpg`
+#[must_use]
+pub fn conflict_name(filename: &str, date: Date) -> StackString {
+    let path = Path::new(filename);
+    let stem = path
+        .file_stem()
+        .map_or_else(|| filename.into(), |s| s.to_string_lossy());
+    match path.extension() {
+        Some(ext) => format_sstr!("{stem}.conflict-{date}.{}", ext.to_string_lossy()),
+        None => format_sstr!("{stem}.conflict-{date}"),
+    }
+}
+
+/// Where `KeepBoth` keeps the losing file: next to `winner`, under the
+/// conflict name of `loser`
+/// # Errors
+/// Return error if `winner`'s url can't hold a path
+pub fn conflict_copy(winner: &FileInfo, loser: &FileInfo, date: Date) -> Result<FileInfo, Error> {
+    let filename = conflict_name(&loser.filename, date);
+    let mut url: Url = winner.urlname.0.clone();
+    url.path_segments_mut()
+        .map_err(|()| format_err!("{} has no path", winner.urlname))?
+        .pop()
+        .push(&filename);
+    let filepath = winner.filepath.with_file_name(filename.as_str());
+    Ok(FileInfo::new(
+        filename,
+        filepath.into(),
+        url.into(),
+        None,
+        None,
+        FileStat::default(),
+        winner.servicesession.clone().into(),
+        winner.servicetype,
+        winner.servicesession.clone(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::{macros::date, macros::datetime, Duration};
+
+    use crate::{
+        conflict::{changed_since, conflict_copy, conflict_name, ConflictPolicy, Resolution},
+        file_info::{fixture_info, FileInfo, FileStat},
+        file_service::FileService,
+        models::SyncHistory,
+    };
+
+    fn finfo(url: &str, size: i64, hour: u8) -> Result<FileInfo, Error> {
+        let mtime = datetime!(2024-01-01 00:00:00 UTC) + Duration::hours(i64::from(hour));
+        fixture_info(
+            url,
+            url.trim_start_matches("file://"),
+            FileStat::new(mtime, size),
+            None,
+            "session",
+            FileService::Local,
+            "session",
+        )
+    }
+
+    #[test]
+    fn test_conflict_policy_resolve() -> Result<(), Error> {
+        let older_larger = finfo("file:///tmp/a/notes.txt", 200, 1)?;
+        let newer_smaller = finfo("file:///tmp/b/notes.txt", 100, 2)?;
+        let tolerance = Duration::seconds(1);
+        let skew = Duration::ZERO;
+        let resolve =
+            |policy: ConflictPolicy| policy.resolve(&older_larger, &newer_smaller, tolerance, skew);
+        assert_eq!(resolve(ConflictPolicy::NewerWins), Resolution::Reverse);
+        assert_eq!(resolve(ConflictPolicy::LargerWins), Resolution::Forward);
+        assert_eq!(
+            resolve(ConflictPolicy::KeepBoth),
+            Resolution::KeepBoth { forward: false }
+        );
+        assert_eq!(resolve(ConflictPolicy::Manual), Resolution::Skip);
+        // the source's clock running two hours fast makes it the older file
+        assert_eq!(
+            ConflictPolicy::NewerWins.resolve(
+                &newer_smaller,
+                &older_larger,
+                tolerance,
+                Duration::hours(2)
+            ),
+            Resolution::Reverse
+        );
+        for policy in [
+            ConflictPolicy::NewerWins,
+            ConflictPolicy::LargerWins,
+            ConflictPolicy::KeepBoth,
+            ConflictPolicy::Manual,
+        ] {
+            assert_eq!(policy.to_str().parse::<ConflictPolicy>()?, policy);
+        }
+        assert!("oldest-wins".parse::<ConflictPolicy>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_changed_since() -> Result<(), Error> {
+        let f = finfo("file:///tmp/a/notes.txt", 100, 1)?;
+        let history = SyncHistory {
+            url0: "file:///tmp/a/notes.txt".into(),
+            url1: "file:///tmp/b/notes.txt".into(),
+            md5sum: None,
+            filestat_st_size: 100,
+            synced_at: datetime!(2024-01-01 00:00:00 UTC).into(),
+            node_id: "".into(),
+        };
+        assert!(!changed_since(&history, &f));
+        let history = SyncHistory {
+            filestat_st_size: 50,
+            ..history
+        };
+        assert!(changed_since(&history, &f));
+        Ok(())
+    }
+
+    #[test]
+    fn test_conflict_name() -> Result<(), Error> {
+        let day = date!(2024 - 01 - 02);
+        assert_eq!(
+            conflict_name("photo.jpg", day),
+            "photo.conflict-2024-01-02.jpg"
+        );
+        assert_eq!(
+            conflict_name("Makefile", day),
+            "Makefile.conflict-2024-01-02"
+        );
+
+        let winner = finfo("file:///tmp/a/photo.jpg", 10, 2)?;
+        let loser = finfo("file:///tmp/b/photo.jpg", 20, 1)?;
+        let copy = conflict_copy(&winner, &loser, day)?;
+        assert_eq!(
+            copy.urlname.as_str(),
+            "file:///tmp/a/photo.conflict-2024-01-02.jpg"
+        );
+        assert_eq!(
+            copy.filepath.to_string_lossy(),
+            "/tmp/a/photo.conflict-2024-01-02.jpg"
+        );
+        Ok(())
+    }
+}
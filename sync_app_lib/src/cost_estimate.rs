@@ -0,0 +1,244 @@
+use anyhow::Error;
+use log::warn;
+use stack_string::{format_sstr, StackString};
+use std::collections::BTreeMap;
+use url::Url;
+
+use crate::{
+    config::{ConfigInner, PricingSection},
+    file_info::FileInfo,
+    file_service::FileService,
+    models::{FileInfoCache, FileSyncCache, PendingTransfer, SessionSize},
+    pgpool::PgPool,
+    usage_trend::format_size,
+};
+
+const BYTES_PER_GB: f64 = 1_073_741_824.0;
+
+/// Pricing label of a session, `s3` sessions (buckets) with a configured
+/// storage class are labelled `s3:<class>`
+#[must_use]
+pub fn pricing_label(config: &ConfigInner, servicetype: &str, servicesession: &str) -> StackString {
+    if servicetype == FileService::S3.to_str() {
+        if let Some(storage_class) = config.s3_config(servicesession).storage_class {
+            return format_sstr!("{servicetype}:{storage_class}");
+        }
+    }
+    servicetype.into()
+}
+
+/// Prices for `label`, falling back to the bare servicetype
+#[must_use]
+pub fn pricing(config: &ConfigInner, label: &str) -> Option<PricingSection> {
+    let pricing = &config.backends.pricing;
+    pricing
+        .get(label)
+        .or_else(|| {
+            label
+                .split_once(':')
+                .and_then(|(servicetype, _)| pricing.get(servicetype))
+        })
+        .copied()
+}
+
+fn gigabytes(bytes: i64) -> f64 {
+    bytes as f64 / BYTES_PER_GB
+}
+
+fn format_cost(cost: Option<f64>) -> StackString {
+    cost.map_or_else(|| "unpriced".into(), |c| format_sstr!("${c:.2}"))
+}
+
+/// Monthly storage cost of one session
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageCost {
+    pub size: SessionSize,
+    pub label: StackString,
+    pub monthly: Option<f64>,
+}
+
+/// Queued copies from one pricing label to another
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TransferCost {
+    pub transfers: usize,
+    pub bytes: i64,
+    pub egress: Option<f64>,
+    pub added_monthly: Option<f64>,
+}
+
+/// Storage cost of everything in the cache plus what approving the pending
+/// queue would add.  Sessions / labels without a `[pricing]` section are
+/// listed as unpriced rather than counted as free.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct CostEstimate {
+    pub storage: Vec<StorageCost>,
+    pub transfers: BTreeMap<(StackString, StackString), TransferCost>,
+}
+
+impl CostEstimate {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn from_db(config: &ConfigInner, pool: &PgPool) -> Result<Self, Error> {
+        let sizes = FileInfoCache::get_session_sizes(pool).await?;
+        let pending = FileSyncCache::get_pending_sizes(pool).await?;
+        Ok(Self::new(config, sizes, &pending))
+    }
+
+    #[must_use]
+    pub fn new(config: &ConfigInner, sizes: Vec<SessionSize>, pending: &[PendingTransfer]) -> Self {
+        let storage = sizes
+            .into_iter()
+            .map(|size| {
+                let label = pricing_label(config, &size.servicetype, &size.servicesession);
+                let monthly = pricing(config, &label)
+                    .and_then(|p| p.storage_per_gb_month)
+                    .map(|price| price * gigabytes(size.total_size));
+                StorageCost {
+                    size,
+                    label,
+                    monthly,
+                }
+            })
+            .collect();
+
+        let mut transfers: BTreeMap<(StackString, StackString), TransferCost> = BTreeMap::new();
+        for entry in pending {
+            let labels = [&entry.src_url, &entry.dst_url].map(|url| {
+                let finfo = url
+                    .parse::<Url>()
+                    .map_err(Error::from)
+                    .and_then(|url| FileInfo::from_url(&url));
+                match finfo {
+                    Ok(finfo) => Some(pricing_label(
+                        config,
+                        finfo.servicetype.to_str(),
+                        finfo.servicesession.as_str(),
+                    )),
+                    Err(e) => {
+                        warn!("can't price {url} {e}");
+                        None
+                    }
+                }
+            });
+            if let [Some(src), Some(dst)] = labels {
+                let gb = gigabytes(entry.size);
+                let egress = pricing(config, &src)
+                    .and_then(|p| p.egress_per_gb)
+                    .map(|price| price * gb);
+                let added_monthly = pricing(config, &dst)
+                    .and_then(|p| p.storage_per_gb_month)
+                    .map(|price| price * gb);
+                let cost = transfers.entry((src, dst)).or_insert_with(|| TransferCost {
+                    egress: Some(0.0),
+                    added_monthly: Some(0.0),
+                    ..TransferCost::default()
+                });
+                cost.transfers += 1;
+                cost.bytes += entry.size;
+                cost.egress = cost.egress.zip(egress).map(|(a, b)| a + b);
+                cost.added_monthly = cost.added_monthly.zip(added_monthly).map(|(a, b)| a + b);
+            }
+        }
+        Self { storage, transfers }
+    }
+
+    #[must_use]
+    pub fn report(&self) -> Vec<StackString> {
+        let mut lines = Vec::new();
+        let mut total = 0.0;
+        for cost in &self.storage {
+            total += cost.monthly.unwrap_or(0.0);
+            lines.push(format_sstr!(
+                "storage {} {} ({}) {} {} files {}/month",
+                cost.size.servicetype,
+                cost.size.servicesession,
+                cost.label,
+                format_size(cost.size.total_size),
+                cost.size.file_count,
+                format_cost(cost.monthly),
+            ));
+        }
+        lines.push(format_sstr!("total storage ${total:.2}/month"));
+        let (mut egress, mut added) = (0.0, 0.0);
+        for ((src, dst), cost) in &self.transfers {
+            egress += cost.egress.unwrap_or(0.0);
+            added += cost.added_monthly.unwrap_or(0.0);
+            lines.push(format_sstr!(
+                "pending {src} -> {dst} {} files {} egress {} added storage {}/month",
+                cost.transfers,
+                format_size(cost.bytes),
+                format_cost(cost.egress),
+                format_cost(cost.added_monthly),
+            ));
+        }
+        lines.push(format_sstr!(
+            "total pending egress ${egress:.2} added storage ${added:.2}/month"
+        ));
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::{
+        config::{BackendSections, ConfigInner},
+        cost_estimate::{pricing_label, CostEstimate},
+        models::{PendingTransfer, SessionSize},
+    };
+
+    #[test]
+    fn test_cost_estimate() -> Result<(), Error> {
+        let contents = r#"
+            [s3.archive-bucket]
+            storage_class = "GLACIER"
+
+            [pricing.s3]
+            storage_per_gb_month = 0.02
+            egress_per_gb = 0.1
+
+            [pricing."s3:GLACIER"]
+            storage_per_gb_month = 0.004
+        "#;
+        let conf = ConfigInner {
+            backends: BackendSections::from_toml(contents, Vec::new())?,
+            ..ConfigInner::default()
+        };
+        assert_eq!(
+            pricing_label(&conf, "s3", "archive-bucket").as_str(),
+            "s3:GLACIER"
+        );
+        assert_eq!(pricing_label(&conf, "s3", "other-bucket").as_str(), "s3");
+
+        let sizes = vec![
+            SessionSize {
+                servicetype: "s3".into(),
+                servicesession: "archive-bucket".into(),
+                total_size: 100 << 30,
+                file_count: 10,
+            },
+            SessionSize {
+                servicetype: "local".into(),
+                servicesession: "ddboline".into(),
+                total_size: 10 << 30,
+                file_count: 5,
+            },
+        ];
+        let pending = vec![PendingTransfer {
+            src_url: "s3://other-bucket/a.tar".into(),
+            dst_url: "s3://archive-bucket/a.tar".into(),
+            size: 10 << 30,
+        }];
+        let estimate = CostEstimate::new(&conf, sizes, &pending);
+        assert_eq!(estimate.storage[0].monthly, Some(0.4));
+        assert_eq!(estimate.storage[1].monthly, None);
+        let report = estimate.report();
+        assert_eq!(report[2].as_str(), "total storage $0.40/month");
+        assert_eq!(
+            report[3].as_str(),
+            "pending s3 -> s3:GLACIER 1 files 10.0 GiB egress $1.00 added storage $0.04/month"
+        );
+        Ok(())
+    }
+}
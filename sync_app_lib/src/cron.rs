@@ -0,0 +1,200 @@
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use std::{fmt, str::FromStr};
+use time::{Date, Duration, OffsetDateTime, PrimitiveDateTime, Time, UtcOffset};
+
+/// A five field cron expression, `minute hour day-of-month month
+/// day-of-week`, evaluated in UTC.  Fields take `*`, values, ranges
+/// `a-b`, steps `*/n` / `a-b/n` and comma separated lists, day-of-week 0
+/// and 7 are both Sunday.  `@hourly`, `@daily`, `@weekly` and `@monthly`
+/// are accepted as well.  As with cron, when both day fields are
+/// restricted a day matching either one is run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    expr: StackString,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+/// Bitmask of the values of `field` within `min..=max`, and whether the
+/// field is unrestricted (starts with `*`)
+fn parse_field(field: &str, min: u8, max: u8) -> Result<(u64, bool), Error> {
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u8>()?),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format_err!("Invalid step in {field}"));
+        }
+        let (lo, hi) = if range == "*" {
+            (min, max)
+        } else if let Some((lo, hi)) = range.split_once('-') {
+            (lo.parse()?, hi.parse()?)
+        } else {
+            let value: u8 = range.parse()?;
+            (value, if step > 1 { max } else { value })
+        };
+        if lo < min || hi > max || lo > hi {
+            return Err(format_err!("{part} out of range {min}-{max}"));
+        }
+        for value in (lo..=hi).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok((mask, field.starts_with('*')))
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: Date) -> bool {
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().number_days_from_sunday()) != 0;
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    /// First time strictly after `after` matching the schedule, `None` if
+    /// there's none within five years (e.g. `0 0 31 2 *`)
+    #[must_use]
+    pub fn next_after(&self, after: OffsetDateTime) -> Option<OffsetDateTime> {
+        let after = after.to_offset(UtcOffset::UTC);
+        let start = PrimitiveDateTime::new(
+            after.date(),
+            Time::from_hms(after.hour(), after.minute(), 0).ok()?,
+        )
+        .assume_utc();
+        let mut t = start + Duration::minutes(1);
+        let limit = start + Duration::days(5 * 366);
+        while t < limit {
+            if self.months & (1 << u8::from(t.month())) == 0 {
+                let (year, month) = match t.month().next() {
+                    time::Month::January => (t.year() + 1, time::Month::January),
+                    month => (t.year(), month),
+                };
+                let date = Date::from_calendar_date(year, month, 1).ok()?;
+                t = PrimitiveDateTime::new(date, Time::MIDNIGHT).assume_utc();
+            } else if !self.matches_day(t.date()) {
+                t = PrimitiveDateTime::new(t.date().next_day()?, Time::MIDNIGHT).assume_utc();
+            } else if self.hours & (1 << t.hour()) == 0 {
+                t = t - Duration::minutes(i64::from(t.minute())) + Duration::hours(1);
+            } else if self.minutes & (1 << t.minute()) == 0 {
+                t += Duration::minutes(1);
+            } else {
+                return Some(t);
+            }
+        }
+        None
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expanded = match s.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            s => s,
+        };
+        let fields: Vec<_> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format_err!("Expected 5 fields in cron expression {s}"));
+        }
+        let (minutes, _) = parse_field(fields[0], 0, 59)?;
+        let (hours, _) = parse_field(fields[1], 0, 23)?;
+        let (days, any_day) = parse_field(fields[2], 1, 31)?;
+        let (months, _) = parse_field(fields[3], 1, 12)?;
+        let (mut weekdays, any_weekday) = parse_field(fields[4], 0, 7)?;
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays & !(1 << 7)) | 1;
+        }
+        Ok(Self {
+            expr: s.trim().into(),
+            minutes,
+            hours,
+            days,
+            months,
+            weekdays,
+            any_day,
+            any_weekday,
+        })
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+
+    use crate::cron::CronSchedule;
+
+    #[test]
+    fn test_cron_next_after() -> Result<(), Error> {
+        let now = datetime!(2024-03-09 12:34:56 UTC);
+
+        let every_15: CronSchedule = "*/15 * * * *".parse()?;
+        assert_eq!(
+            every_15.next_after(now),
+            Some(datetime!(2024-03-09 12:45 UTC))
+        );
+
+        let nightly: CronSchedule = "30 2 * * *".parse()?;
+        assert_eq!(
+            nightly.next_after(now),
+            Some(datetime!(2024-03-10 02:30 UTC))
+        );
+
+        // 2024-03-09 is a Saturday
+        let weekdays: CronSchedule = "0 9 * * 1-5".parse()?;
+        assert_eq!(
+            weekdays.next_after(now),
+            Some(datetime!(2024-03-11 09:00 UTC))
+        );
+
+        let monthly: CronSchedule = "@monthly".parse()?;
+        assert_eq!(
+            monthly.next_after(datetime!(2024-12-15 00:00 UTC)),
+            Some(datetime!(2025-01-01 00:00 UTC))
+        );
+
+        let sunday: CronSchedule = "0 0 * * 7".parse()?;
+        assert_eq!(
+            sunday.next_after(now),
+            Some(datetime!(2024-03-10 00:00 UTC))
+        );
+
+        // either day field matches when both are restricted
+        let either: CronSchedule = "0 0 15 * 1".parse()?;
+        assert_eq!(
+            either.next_after(now),
+            Some(datetime!(2024-03-11 00:00 UTC))
+        );
+
+        let never: CronSchedule = "0 0 31 2 *".parse()?;
+        assert_eq!(never.next_after(now), None);
+
+        assert!("* * *".parse::<CronSchedule>().is_err());
+        assert!("60 * * * *".parse::<CronSchedule>().is_err());
+        assert!("*/0 * * * *".parse::<CronSchedule>().is_err());
+        assert_eq!(nightly.to_string(), "30 2 * * *");
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+use anyhow::{format_err, Error};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+use tokio::task::spawn_blocking;
+
+/// Files are split in blocks of about `sqrt(size)`, within these bounds
+const MIN_BLOCK_SIZE: usize = 2048;
+const MAX_BLOCK_SIZE: usize = 1 << 20;
+/// Unmatched data goes into the delta in pieces of at most this size
+const MAX_LITERAL: usize = 1 << 20;
+
+const DELTA_MAGIC: &[u8; 4] = b"SADL";
+const OP_COPY: u8 = 0;
+const OP_DATA: u8 = 1;
+const OP_END: u8 = 2;
+
+/// Power of two block size close to `sqrt(len)`
+#[must_use]
+pub fn block_size(len: u64) -> usize {
+    let mut size = MIN_BLOCK_SIZE;
+    while size < MAX_BLOCK_SIZE && (size as u64) * (size as u64) < len {
+        size *= 2;
+    }
+    size
+}
+
+/// rsync's rolling checksum, the sums of the bytes of the window and of
+/// their running sums, each mod 2^16
+#[derive(Debug, Default, Clone, Copy)]
+struct Rolling {
+    a: u32,
+    b: u32,
+    len: u32,
+}
+
+impl Rolling {
+    fn new(block: &[u8]) -> Self {
+        let mut rolling = Self::default();
+        for &x in block {
+            rolling.a = rolling.a.wrapping_add(x.into());
+            rolling.b = rolling.b.wrapping_add(rolling.a);
+            rolling.len += 1;
+        }
+        rolling
+    }
+
+    /// Slide the window one byte, dropping `out` and appending `next`
+    fn rotate(self, out: u8, next: u8) -> Self {
+        let a = self.a.wrapping_sub(out.into()).wrapping_add(next.into());
+        let b = self
+            .b
+            .wrapping_sub(self.len.wrapping_mul(out.into()))
+            .wrapping_add(a);
+        Self {
+            a,
+            b,
+            len: self.len,
+        }
+    }
+
+    /// Drop `out` from the front of the window, at the end of the file
+    fn roll_out(self, out: u8) -> Self {
+        Self {
+            a: self.a.wrapping_sub(out.into()),
+            b: self.b.wrapping_sub(self.len.wrapping_mul(out.into())),
+            len: self.len - 1,
+        }
+    }
+
+    fn digest(self) -> u32 {
+        (self.a & 0xffff) | (self.b << 16)
+    }
+}
+
+fn strong_sum(block: &[u8]) -> StackString {
+    format_sstr!("{:x}", Md5::digest(block))
+}
+
+/// Fill `buf` from `reader`, short only at the end of the file
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match reader.read(&mut buf[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(total)
+}
+
+/// Block checksums of the copy of a file the receiving side already has
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub block_size: usize,
+    pub file_size: u64,
+    /// Rolling checksum and md5 of each block
+    pub blocks: Vec<(u32, StackString)>,
+}
+
+impl Signature {
+    /// # Errors
+    /// Return error if `reader` fails
+    pub fn from_reader(mut reader: impl Read, block_size: usize) -> Result<Self, Error> {
+        let mut buf = vec![0u8; block_size];
+        let mut signature = Self {
+            block_size,
+            file_size: 0,
+            blocks: Vec::new(),
+        };
+        loop {
+            let n = read_full(&mut reader, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            let block = &buf[..n];
+            signature
+                .blocks
+                .push((Rolling::new(block).digest(), strong_sum(block)));
+            signature.file_size += n as u64;
+            if n < block_size {
+                break;
+            }
+        }
+        Ok(signature)
+    }
+
+    fn block_len(&self, index: usize) -> u64 {
+        let offset = (index * self.block_size) as u64;
+        self.file_size
+            .saturating_sub(offset)
+            .min(self.block_size as u64)
+    }
+}
+
+/// Bytes of the new file found in the old one and sent as data
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeltaSummary {
+    pub matched_bytes: u64,
+    pub literal_bytes: u64,
+}
+
+impl fmt::Display for DeltaSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "sent {} of {} bytes",
+            self.literal_bytes,
+            self.matched_bytes + self.literal_bytes
+        )
+    }
+}
+
+/// Writes the delta ops, consecutive block copies are merged into one
+struct DeltaWriter<W: Write> {
+    out: W,
+    pending: Option<(u64, u64)>,
+    summary: DeltaSummary,
+}
+
+impl<W: Write> DeltaWriter<W> {
+    fn new(mut out: W, block_size: usize) -> Result<Self, Error> {
+        out.write_all(DELTA_MAGIC)?;
+        out.write_all(&(block_size as u64).to_le_bytes())?;
+        Ok(Self {
+            out,
+            pending: None,
+            summary: DeltaSummary::default(),
+        })
+    }
+
+    fn flush_copy(&mut self) -> Result<(), Error> {
+        if let Some((index, count)) = self.pending.take() {
+            self.out.write_all(&[OP_COPY])?;
+            self.out.write_all(&index.to_le_bytes())?;
+            self.out.write_all(&count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn copy(&mut self, index: usize, len: u64) -> Result<(), Error> {
+        let index = index as u64;
+        self.summary.matched_bytes += len;
+        match &mut self.pending {
+            Some((start, count)) if *start + *count == index => {
+                *count += 1;
+                Ok(())
+            }
+            _ => {
+                self.flush_copy()?;
+                self.pending = Some((index, 1));
+                Ok(())
+            }
+        }
+    }
+
+    fn data(&mut self, data: &[u8]) -> Result<(), Error> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.flush_copy()?;
+        self.out.write_all(&[OP_DATA])?;
+        self.out.write_all(&(data.len() as u64).to_le_bytes())?;
+        self.out.write_all(data)?;
+        self.summary.literal_bytes += data.len() as u64;
+        Ok(())
+    }
+
+    fn finish(mut self, md5: &[u8], len: u64) -> Result<DeltaSummary, Error> {
+        self.flush_copy()?;
+        self.out.write_all(&[OP_END])?;
+        self.out.write_all(md5)?;
+        self.out.write_all(&len.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.summary)
+    }
+}
+
+fn find_block(
+    signature: &Signature,
+    table: &HashMap<u32, Vec<usize>>,
+    weak: u32,
+    window: &[u8],
+) -> Option<usize> {
+    let candidates = table.get(&weak)?;
+    let strong = strong_sum(window);
+    candidates.iter().copied().find(|&index| {
+        signature.block_len(index) == window.len() as u64 && signature.blocks[index].1 == strong
+    })
+}
+
+/// Write the ops turning the file described by `signature` into the
+/// contents of `src`: copies of its blocks found anywhere in `src` (at any
+/// byte offset, using the rolling checksum) and the data in between
+/// # Errors
+/// Return error if reading `src` or writing `out` fails
+pub fn write_delta(
+    signature: &Signature,
+    mut src: impl Read,
+    out: impl Write,
+) -> Result<DeltaSummary, Error> {
+    let block_size = signature.block_size;
+    if block_size == 0 {
+        return Err(format_err!("Invalid signature block size"));
+    }
+    let mut table: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (index, (weak, _)) in signature.blocks.iter().enumerate() {
+        table.entry(*weak).or_default().push(index);
+    }
+    let mut writer = DeltaWriter::new(out, block_size)?;
+    let mut hasher = Md5::new();
+    let mut total = 0u64;
+    let chunk = block_size + MAX_LITERAL;
+
+    let mut buf: Vec<u8> = Vec::new();
+    let mut start = 0;
+    let mut literal = 0;
+    let mut eof = false;
+    let mut rolling: Option<Rolling> = None;
+    loop {
+        if !eof && buf.len() - start <= block_size {
+            buf.drain(..literal);
+            start -= literal;
+            literal = 0;
+            let len = buf.len();
+            buf.resize(len + chunk, 0);
+            let n = read_full(&mut src, &mut buf[len..])?;
+            buf.truncate(len + n);
+            hasher.update(&buf[len..]);
+            total += n as u64;
+            eof = n < chunk;
+        }
+        if start >= buf.len() {
+            break;
+        }
+        let end = (start + block_size).min(buf.len());
+        let window = &buf[start..end];
+        let current = *rolling.get_or_insert_with(|| Rolling::new(window));
+        if let Some(index) = find_block(signature, &table, current.digest(), window) {
+            writer.data(&buf[literal..start])?;
+            writer.copy(index, window.len() as u64)?;
+            start = end;
+            literal = start;
+            rolling = None;
+        } else {
+            let out = buf[start];
+            rolling = Some(if end < buf.len() {
+                current.rotate(out, buf[end])
+            } else {
+                current.roll_out(out)
+            });
+            start += 1;
+            if start - literal >= MAX_LITERAL {
+                writer.data(&buf[literal..start])?;
+                literal = start;
+            }
+        }
+    }
+    writer.data(&buf[literal..start])?;
+    writer.finish(&hasher.finalize(), total)
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64, Error> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+struct HashingWriter<W: Write> {
+    out: W,
+    hasher: Md5,
+    len: u64,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.out.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        self.len += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Rebuild the new file from the old one (`base`) and a delta, returns the
+/// number of bytes written
+/// # Errors
+/// Return error if the delta is malformed or the result doesn't match the
+/// checksum recorded in it
+pub fn apply_delta(
+    mut base: impl Read + Seek,
+    mut delta: impl Read,
+    out: impl Write,
+) -> Result<u64, Error> {
+    let mut magic = [0u8; 4];
+    delta.read_exact(&mut magic)?;
+    if &magic != DELTA_MAGIC {
+        return Err(format_err!("Not a delta file"));
+    }
+    let block_size = read_u64(&mut delta)?;
+    let mut out = HashingWriter {
+        out,
+        hasher: Md5::new(),
+        len: 0,
+    };
+    loop {
+        let mut op = [0u8; 1];
+        delta.read_exact(&mut op)?;
+        match op[0] {
+            OP_COPY => {
+                let index = read_u64(&mut delta)?;
+                let count = read_u64(&mut delta)?;
+                base.seek(SeekFrom::Start(index * block_size))?;
+                let expected = count * block_size;
+                let copied = io::copy(&mut (&mut base).take(expected), &mut out)?;
+                if copied < expected.saturating_sub(block_size) {
+                    return Err(format_err!("Delta refers past the end of the file"));
+                }
+            }
+            OP_DATA => {
+                let len = read_u64(&mut delta)?;
+                let copied = io::copy(&mut (&mut delta).take(len), &mut out)?;
+                if copied != len {
+                    return Err(format_err!("Truncated delta"));
+                }
+            }
+            OP_END => {
+                let mut md5 = [0u8; 16];
+                delta.read_exact(&mut md5)?;
+                let len = read_u64(&mut delta)?;
+                out.flush()?;
+                if out.len != len || out.hasher.finalize().as_slice() != &md5[..] {
+                    return Err(format_err!("Checksum mismatch after applying delta"));
+                }
+                return Ok(len);
+            }
+            op => return Err(format_err!("Invalid delta op {op}")),
+        }
+    }
+}
+
+/// Signature of a local file
+/// # Errors
+/// Return error if the file can't be read
+pub async fn file_signature(path: &Path) -> Result<Signature, Error> {
+    let path = path.to_path_buf();
+    spawn_blocking(move || {
+        let file = File::open(&path)?;
+        let len = file.metadata()?.len();
+        Signature::from_reader(BufReader::new(file), block_size(len))
+    })
+    .await?
+}
+
+/// Delta of local file `src` against `signature`, written to `delta`
+/// # Errors
+/// Return error if `src` can't be read or `delta` can't be written
+pub async fn file_delta(
+    signature: Signature,
+    src: &Path,
+    delta: &Path,
+) -> Result<DeltaSummary, Error> {
+    let src = src.to_path_buf();
+    let delta = delta.to_path_buf();
+    spawn_blocking(move || {
+        let src = BufReader::new(File::open(src)?);
+        let out = BufWriter::new(File::create(delta)?);
+        write_delta(&signature, src, out)
+    })
+    .await?
+}
+
+fn patch_tmp_path(path: &Path) -> Result<PathBuf, Error> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format_err!("No file name {}", path.display()))?;
+    let name = format_sstr!(".{}.delta-tmp", name.to_string_lossy());
+    Ok(path.with_file_name(name.as_str()))
+}
+
+/// Apply `delta` to local file `path`, the result is written next to it and
+/// renamed over it so `path` is left untouched if anything fails
+/// # Errors
+/// Return error if the delta can't be applied
+pub async fn patch_file(path: &Path, delta: &Path) -> Result<u64, Error> {
+    let path = path.to_path_buf();
+    let delta = delta.to_path_buf();
+    spawn_blocking(move || {
+        let tmp = patch_tmp_path(&path)?;
+        let result = patch_blocking(&path, &delta, &tmp);
+        if result.is_err() {
+            fs::remove_file(&tmp).ok();
+        }
+        result
+    })
+    .await?
+}
+
+fn patch_blocking(path: &Path, delta: &Path, tmp: &Path) -> Result<u64, Error> {
+    let base = BufReader::new(File::open(path)?);
+    let delta = BufReader::new(File::open(delta)?);
+    let mut out = BufWriter::new(File::create(tmp)?);
+    let len = apply_delta(base, delta, &mut out)?;
+    out.flush()?;
+    fs::set_permissions(tmp, fs::metadata(path)?.permissions())?;
+    fs::rename(tmp, path)?;
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use rand::{thread_rng, RngCore};
+    use std::io::Cursor;
+
+    use crate::delta::{apply_delta, block_size, write_delta, Rolling, Signature};
+
+    fn roundtrip(old: &[u8], new: &[u8], block_size: usize) -> Result<(Vec<u8>, u64), Error> {
+        let signature = Signature::from_reader(old, block_size)?;
+        let mut delta = Vec::new();
+        let summary = write_delta(&signature, new, &mut delta)?;
+        let mut result = Vec::new();
+        apply_delta(Cursor::new(old), delta.as_slice(), &mut result)?;
+        Ok((result, summary.literal_bytes))
+    }
+
+    #[test]
+    fn test_rolling_checksum() {
+        let data = b"the quick brown fox jumps over the lazy dog";
+        let mut rolling = Rolling::new(&data[..16]);
+        for i in 0..data.len() - 16 {
+            rolling = rolling.rotate(data[i], data[i + 16]);
+            assert_eq!(
+                rolling.digest(),
+                Rolling::new(&data[i + 1..i + 17]).digest()
+            );
+        }
+        let start = data.len() - 16;
+        for i in start..data.len() - 1 {
+            rolling = rolling.roll_out(data[i]);
+            assert_eq!(rolling.digest(), Rolling::new(&data[i + 1..]).digest());
+        }
+    }
+
+    #[test]
+    fn test_delta_roundtrip() -> Result<(), Error> {
+        assert_eq!(block_size(0), 2048);
+        assert_eq!(block_size(100 * 1024 * 1024), 16384);
+
+        let mut old = vec![0u8; 100_000];
+        thread_rng().fill_bytes(&mut old);
+
+        let (result, literal) = roundtrip(&old, &old, 1024)?;
+        assert_eq!(result, old);
+        assert_eq!(literal, 0);
+
+        // insert in the middle, shifting every later block by 5 bytes
+        let mut new = old.clone();
+        new.splice(50_000..50_000, b"hello".iter().copied());
+        new.truncate(new.len() - 300);
+        new.extend_from_slice(b"tail");
+        let (result, literal) = roundtrip(&old, &new, 1024)?;
+        assert_eq!(result, new);
+        assert!(literal < 3 * 1024, "{literal}");
+
+        let (result, _) = roundtrip(&old, b"", 1024)?;
+        assert!(result.is_empty());
+        let (result, literal) = roundtrip(b"", &new, 1024)?;
+        assert_eq!(result, new);
+        assert_eq!(literal, new.len() as u64);
+
+        let signature = Signature::from_reader(old.as_slice(), 1024)?;
+        let mut delta = Vec::new();
+        write_delta(&signature, new.as_slice(), &mut delta)?;
+        let mut other = old.clone();
+        other[0] ^= 1;
+        let mut result = Vec::new();
+        assert!(apply_delta(Cursor::new(other), delta.as_slice(), &mut result).is_err());
+        Ok(())
+    }
+}
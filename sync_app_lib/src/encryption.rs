@@ -0,0 +1,430 @@
+use aes_gcm::{
+    aead::{
+        generic_array::GenericArray,
+        stream::{DecryptorBE32, EncryptorBE32},
+        KeyInit,
+    },
+    Aes256Gcm, Key,
+};
+use anyhow::{format_err, Error};
+use hmac::{Hmac, Mac};
+use log::debug;
+use percent_encoding::percent_decode_str;
+use rand::RngCore;
+use sha2::Sha256;
+use stack_string::{format_sstr, StackString};
+use std::{
+    env::temp_dir,
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+use tokio::{
+    fs::{create_dir_all, remove_dir_all},
+    task::spawn_blocking,
+};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    file_info::{FileInfo, FileInfoTrait},
+    file_list::FileListTrait,
+    file_service::FileService,
+    models::EncryptedFile,
+    pgpool::PgPool,
+};
+
+/// First bytes of every encrypted file, followed by the stream nonce
+const MAGIC: &[u8; 8] = b"SYNCENC1";
+/// Nonce of the STREAM construction, the aes-gcm nonce less the 5 bytes of
+/// counter and last block flag
+const NONCE_SIZE: usize = 7;
+/// Plaintext bytes per authenticated chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_SIZE: usize = 16;
+
+/// Client side encryption of files written to cloud targets, AES-256-GCM in
+/// 64KiB chunks keyed by `config.encryption_key_file` (32 bytes, raw or hex)
+#[derive(Clone)]
+pub struct Encryption {
+    key: Key<Aes256Gcm>,
+    obfuscate_filenames: bool,
+}
+
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Encryption")
+    }
+}
+
+impl Encryption {
+    /// # Errors
+    /// Return error if the key isn't 32 bytes
+    pub fn new(key: &[u8], obfuscate_filenames: bool) -> Result<Self, Error> {
+        if key.len() != 32 {
+            return Err(format_err!("Encryption key must be 32 bytes"));
+        }
+        Ok(Self {
+            key: Key::<Aes256Gcm>::clone_from_slice(key),
+            obfuscate_filenames,
+        })
+    }
+
+    /// `None` unless `encryption_key_file` is set
+    /// # Errors
+    /// Return error if the key file can't be read or is invalid
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Error> {
+        let path = match &config.encryption_key_file {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let data = std::fs::read(path)?;
+        let text = std::str::from_utf8(&data).map(str::trim).unwrap_or("");
+        let key = if text.len() == 64 && text.bytes().all(|b| b.is_ascii_hexdigit()) {
+            (0..32)
+                .map(|i| u8::from_str_radix(&text[2 * i..2 * i + 2], 16))
+                .collect::<Result<Vec<u8>, _>>()?
+        } else {
+            data
+        };
+        Self::new(&key, config.obfuscate_filenames).map(Some)
+    }
+
+    /// Targets whose content is encrypted
+    #[must_use]
+    pub fn applies_to(servicetype: FileService) -> bool {
+        matches!(
+            servicetype,
+            FileService::S3 | FileService::GDrive | FileService::GCS
+        )
+    }
+
+    /// Deterministic name of `name` on the target, the same file always maps
+    /// to the same object so re-uploads replace it
+    #[must_use]
+    pub fn obfuscate_name(&self, name: &str) -> StackString {
+        let mut mac =
+            <Hmac<Sha256> as Mac>::new_from_slice(self.key.as_slice()).expect("any key size");
+        mac.update(name.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest[..16].iter().map(|b| format!("{b:02x}")).collect();
+        format_sstr!("{hex}.enc")
+    }
+
+    /// Where `clear_url` is written, with obfuscation the last segment is
+    /// replaced by a keyed hash of the whole url
+    /// # Errors
+    /// Return error if `clear_url` has no path
+    pub fn encrypted_url(&self, clear_url: &Url) -> Result<Url, Error> {
+        if !self.obfuscate_filenames {
+            return Ok(clear_url.clone());
+        }
+        let name = self.obfuscate_name(clear_url.as_str());
+        let mut url = clear_url.clone();
+        url.path_segments_mut()
+            .map_err(|()| format_err!("{clear_url} has no path"))?
+            .pop()
+            .push(&name);
+        Ok(url)
+    }
+
+    /// # Errors
+    /// Return error if reading / writing fails
+    pub fn encrypt_file(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let mut nonce = [0u8; NONCE_SIZE];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut encryptor =
+            EncryptorBE32::from_aead(Aes256Gcm::new(&self.key), GenericArray::from_slice(&nonce));
+        let mut reader = BufReader::new(File::open(src)?);
+        let mut writer = BufWriter::new(File::create(dst)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&nonce)?;
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            let n = read_chunk(&mut reader, &mut buf)?;
+            if n == CHUNK_SIZE && !reader.fill_buf()?.is_empty() {
+                let chunk = encryptor
+                    .encrypt_next(&buf[..n])
+                    .map_err(|_| format_err!("Failed to encrypt {}", src.display()))?;
+                writer.write_all(&chunk)?;
+            } else {
+                let chunk = encryptor
+                    .encrypt_last(&buf[..n])
+                    .map_err(|_| format_err!("Failed to encrypt {}", src.display()))?;
+                writer.write_all(&chunk)?;
+                break;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if reading / writing fails or the file was not
+    /// encrypted with this key
+    pub fn decrypt_file(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let mut reader = BufReader::new(File::open(src)?);
+        let mut header = [0u8; MAGIC.len() + NONCE_SIZE];
+        reader.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(format_err!("{} is not encrypted", src.display()));
+        }
+        let mut decryptor = DecryptorBE32::from_aead(
+            Aes256Gcm::new(&self.key),
+            GenericArray::from_slice(&header[MAGIC.len()..]),
+        );
+        let mut writer = BufWriter::new(File::create(dst)?);
+        let mut buf = vec![0u8; CHUNK_SIZE + TAG_SIZE];
+        loop {
+            let n = read_chunk(&mut reader, &mut buf)?;
+            if n == buf.len() && !reader.fill_buf()?.is_empty() {
+                let chunk = decryptor
+                    .decrypt_next(&buf[..n])
+                    .map_err(|_| format_err!("Failed to decrypt {}", src.display()))?;
+                writer.write_all(&chunk)?;
+            } else {
+                let chunk = decryptor
+                    .decrypt_last(&buf[..n])
+                    .map_err(|_| format_err!("Failed to decrypt {}", src.display()))?;
+                writer.write_all(&chunk)?;
+                break;
+            }
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Encrypt the local `finfo0` into a scratch file and upload that to
+    /// the target of `finfo1`, skipped when the same content was already
+    /// written there
+    /// # Errors
+    /// Return error if encryption, the upload or db query fails
+    pub async fn copy_to(
+        &self,
+        flist: &dyn FileListTrait,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        let pool = flist.get_pool();
+        let clear_url = finfo1.urlname.as_str();
+        let encrypted_url = self.encrypted_url(&finfo1.urlname)?;
+        let clear_md5sum = finfo0
+            .md5sum
+            .as_ref()
+            .map(|m| StackString::from(m.as_str()));
+        if let Some(existing) = EncryptedFile::get_by_clear_url(clear_url, pool).await? {
+            if existing.encrypted_url == encrypted_url.as_str()
+                && existing.clear_size == finfo0.filestat.st_size
+                && clear_md5sum.is_some()
+                && existing.clear_md5sum == clear_md5sum
+            {
+                debug!("{clear_url} already encrypted as {encrypted_url}");
+                return Ok(());
+            }
+        }
+        let filename = encrypted_url
+            .path_segments()
+            .and_then(Iterator::last)
+            .ok_or_else(|| format_err!("No filename in {encrypted_url}"))?;
+        let scratch = scratch_dir();
+        create_dir_all(&scratch).await?;
+        let encrypted_path = scratch.join(filename);
+        let result = async {
+            let encryption = self.clone();
+            let src = finfo0.filepath.to_path_buf();
+            let dst = encrypted_path.clone();
+            spawn_blocking(move || encryption.encrypt_file(&src, &dst)).await??;
+            let mut local = finfo0.inner().clone();
+            local.filename = filename.into();
+            local.filepath = encrypted_path.clone().into();
+            let mut remote = finfo1.inner().clone();
+            remote.filename = filename.into();
+            remote.urlname = encrypted_url.clone().into();
+            flist
+                .copy_to(&FileInfo::from_inner(local), &FileInfo::from_inner(remote))
+                .await
+        }
+        .await;
+        remove_dir_all(&scratch).await?;
+        result?;
+        EncryptedFile {
+            encrypted_url: encrypted_url.as_str().into(),
+            clear_url: clear_url.into(),
+            clear_md5sum,
+            clear_size: finfo0.filestat.st_size,
+            created_at: time::OffsetDateTime::now_utc().into(),
+        }
+        .upsert(pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Download `finfo0` if it was written by `copy_to` (by either its
+    /// clear or encrypted url) and decrypt it to `finfo1`, returns false
+    /// for files that were never encrypted
+    /// # Errors
+    /// Return error if the download, decryption or db query fails
+    pub async fn copy_from(
+        &self,
+        flist: &dyn FileListTrait,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<bool, Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        let pool = flist.get_pool();
+        let url = finfo0.urlname.as_str();
+        let entry = match EncryptedFile::get_by_encrypted_url(url, pool).await? {
+            Some(entry) => entry,
+            None => match EncryptedFile::get_by_clear_url(url, pool).await? {
+                Some(entry) => entry,
+                None => return Ok(false),
+            },
+        };
+        let encrypted_url: Url = entry.encrypted_url.parse()?;
+        let remote = if encrypted_url.as_str() == url {
+            finfo0.clone()
+        } else {
+            let session = flist.get_servicesession().as_str();
+            match FileInfo::from_database(pool, &encrypted_url, session).await? {
+                Some(f) => f,
+                None => FileInfo::from_url(&encrypted_url)?,
+            }
+        };
+        let scratch = scratch_dir();
+        create_dir_all(&scratch).await?;
+        let encrypted_path = scratch.join("encrypted");
+        let result = async {
+            let mut local = finfo1.inner().clone();
+            local.filepath = encrypted_path.clone().into();
+            local.md5sum = None;
+            flist
+                .copy_from(&remote, &FileInfo::from_inner(local))
+                .await?;
+            if let Some(parent) = finfo1.filepath.parent() {
+                create_dir_all(parent).await?;
+            }
+            let encryption = self.clone();
+            let src = encrypted_path.clone();
+            let dst = finfo1.filepath.to_path_buf();
+            spawn_blocking(move || encryption.decrypt_file(&src, &dst)).await?
+        }
+        .await;
+        remove_dir_all(&scratch).await?;
+        result.map(|()| true)
+    }
+}
+
+/// Size of the encrypted file of `clear_size` bytes: magic, nonce and a
+/// tag per chunk, an empty file is one empty chunk
+#[must_use]
+pub fn encrypted_size(clear_size: i64) -> i64 {
+    let chunk_size = CHUNK_SIZE as i64;
+    let chunks = ((clear_size + chunk_size - 1) / chunk_size).max(1);
+    (MAGIC.len() + NONCE_SIZE) as i64 + clear_size + chunks * TAG_SIZE as i64
+}
+
+/// `finfo` with the url, name, size and md5sum of the clear file if it was
+/// written by `Encryption::copy_to` and hasn't been replaced since, so that
+/// comparisons see the clear file, as `compression::original_info` does
+/// for compressed uploads
+/// # Errors
+/// Return error if db query fails
+pub async fn clear_info(finfo: FileInfo, pool: &PgPool) -> Result<FileInfo, Error> {
+    if !Encryption::applies_to(finfo.servicetype) {
+        return Ok(finfo);
+    }
+    match EncryptedFile::get_by_encrypted_url(finfo.urlname.as_str(), pool).await? {
+        Some(entry) if encrypted_size(entry.clear_size) == finfo.filestat.st_size => {
+            let clear_url: Url = entry.clear_url.parse()?;
+            let filename: StackString = clear_url
+                .path_segments()
+                .and_then(Iterator::last)
+                .map(|s| percent_decode_str(s).decode_utf8_lossy().as_ref().into())
+                .ok_or_else(|| format_err!("No filename in {clear_url}"))?;
+            let mut inner = finfo.inner().clone();
+            inner.filepath = inner.filepath.0.with_file_name(filename.as_str()).into();
+            inner.filename = filename;
+            inner.urlname = clear_url.into();
+            inner.filestat.st_size = entry.clear_size;
+            inner.md5sum = entry.clear_md5sum.as_ref().map(|m| m.parse()).transpose()?;
+            Ok(FileInfo::from_inner(inner))
+        }
+        _ => Ok(finfo),
+    }
+}
+
+/// Fresh directory for the intermediate files of a transfer
+pub(crate) fn scratch_dir() -> PathBuf {
+    temp_dir().join(format_sstr!("sync_app_encryption_{}", Uuid::new_v4()).as_str())
+}
+
+fn read_chunk(reader: &mut impl Read, buf: &mut [u8]) -> Result<usize, Error> {
+    let mut n = 0;
+    while n < buf.len() {
+        let m = reader.read(&mut buf[n..])?;
+        if m == 0 {
+            break;
+        }
+        n += m;
+    }
+    Ok(n)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use std::env::temp_dir;
+    use url::Url;
+    use uuid::Uuid;
+
+    use crate::encryption::{encrypted_size, Encryption, CHUNK_SIZE};
+
+    #[test]
+    fn test_encrypt_decrypt_file() -> Result<(), Error> {
+        let encryption = Encryption::new(&[7u8; 32], true)?;
+        let dir = temp_dir().join(format_sstr!("encryption_{}", Uuid::new_v4()).as_str());
+        std::fs::create_dir_all(&dir)?;
+        for size in &[0, 100, CHUNK_SIZE, 2 * CHUNK_SIZE + 17] {
+            let clear = dir.join("clear");
+            let encrypted = dir.join("encrypted");
+            let decrypted = dir.join("decrypted");
+            let data: Vec<u8> = (0..*size).map(|i| (i % 251) as u8).collect();
+            std::fs::write(&clear, &data)?;
+            encryption.encrypt_file(&clear, &encrypted)?;
+            assert_ne!(std::fs::read(&encrypted)?, data);
+            assert_eq!(
+                encrypted.metadata()?.len() as i64,
+                encrypted_size(*size as i64)
+            );
+            encryption.decrypt_file(&encrypted, &decrypted)?;
+            assert_eq!(std::fs::read(&decrypted)?, data);
+
+            let other = Encryption::new(&[8u8; 32], true)?;
+            assert!(other.decrypt_file(&encrypted, &decrypted).is_err());
+        }
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_url() -> Result<(), Error> {
+        let encryption = Encryption::new(&[7u8; 32], true)?;
+        let clear: Url = "s3://backup/photos/2024/a.jpg".parse()?;
+        let encrypted = encryption.encrypted_url(&clear)?;
+        assert!(encrypted.as_str().starts_with("s3://backup/photos/2024/"));
+        assert!(encrypted.as_str().ends_with(".enc"));
+        assert!(!encrypted.as_str().contains("a.jpg"));
+        assert_eq!(encryption.encrypted_url(&clear)?, encrypted);
+
+        let plain = Encryption::new(&[7u8; 32], false)?;
+        assert_eq!(plain.encrypted_url(&clear)?, clear);
+        assert!(Encryption::new(&[7u8; 16], false).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::Serialize;
+use stack_string::StackString;
+use std::{
+    os::unix::fs::FileTypeExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::{
+    fs,
+    io::{AsyncWrite, AsyncWriteExt},
+    net::{unix::pipe, UnixStream},
+    sync::mpsc::{channel, Receiver, Sender},
+    task::{spawn, JoinHandle},
+    time::timeout,
+};
+
+use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
+use crate::config::Config;
+
+/// Events buffered for the writer, further events are dropped while it's
+/// full so that a slow or stuck reader never holds up a sync
+const EVENT_BUFFER: usize = 1024;
+
+/// How long `EventHook::close` waits for buffered events to be written
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+static EVENT_WRITER: Lazy<Mutex<Option<EventWriter>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEventType {
+    Queued,
+    Copied,
+    Failed,
+    Deleted,
+}
+
+/// One line written to `event_socket`
+#[derive(Serialize, Debug, Clone)]
+pub struct HookEvent<'a> {
+    pub event: HookEventType,
+    pub timestamp: DateTimeWrapper,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub src_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dst_url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<&'a str>,
+}
+
+impl<'a> HookEvent<'a> {
+    #[must_use]
+    pub fn queued(src_url: &'a str, dst_url: &'a str) -> Self {
+        Self::new(HookEventType::Queued, Some(src_url), Some(dst_url))
+    }
+
+    /// `Copied` or, if `error` is set, `Failed`
+    #[must_use]
+    pub fn copied(src_url: &'a str, dst_url: &'a str, bytes: i64, error: Option<&'a str>) -> Self {
+        let event_type = if error.is_some() {
+            HookEventType::Failed
+        } else {
+            HookEventType::Copied
+        };
+        Self {
+            bytes: Some(bytes),
+            error,
+            ..Self::new(event_type, Some(src_url), Some(dst_url))
+        }
+    }
+
+    /// `Deleted` or, if `error` is set, `Failed`
+    #[must_use]
+    pub fn deleted(url: &'a str, error: Option<&'a str>) -> Self {
+        let event_type = if error.is_some() {
+            HookEventType::Failed
+        } else {
+            HookEventType::Deleted
+        };
+        Self {
+            error,
+            ..Self::new(event_type, Some(url), None)
+        }
+    }
+
+    fn new(event: HookEventType, src_url: Option<&'a str>, dst_url: Option<&'a str>) -> Self {
+        Self {
+            event,
+            timestamp: DateTimeWrapper::now(),
+            src_url,
+            dst_url,
+            bytes: None,
+            error: None,
+        }
+    }
+}
+
+struct EventWriter {
+    path: PathBuf,
+    sender: Sender<StackString>,
+    task: JoinHandle<()>,
+}
+
+/// Writes sync events as JSON lines to the unix socket or named pipe at
+/// `config.event_socket`.  Delivery is best effort: nothing is written while
+/// no one is listening and events are dropped rather than slowing down the
+/// sync.
+pub struct EventHook;
+
+impl EventHook {
+    pub fn emit(config: &Config, event: &HookEvent) {
+        let path = match &config.event_socket {
+            Some(path) => path,
+            None => return,
+        };
+        let line = match serde_json::to_string(event) {
+            Ok(line) => StackString::from(line),
+            Err(e) => {
+                debug!("failed to serialize event {e}");
+                return;
+            }
+        };
+        let mut writer = EVENT_WRITER.lock();
+        if writer
+            .as_ref()
+            .map_or(true, |w| &w.path != path || w.sender.is_closed())
+        {
+            *writer = Some(EventWriter::new(path));
+        }
+        if let Some(writer) = writer.as_ref() {
+            if let Err(e) = writer.sender.try_send(line) {
+                debug!("dropping event {e}");
+            }
+        }
+    }
+
+    /// Wait (a bounded time) for buffered events to be written, call before
+    /// exiting
+    pub async fn close() {
+        let writer = EVENT_WRITER.lock().take();
+        if let Some(EventWriter { sender, task, .. }) = writer {
+            drop(sender);
+            if timeout(CLOSE_TIMEOUT, task).await.is_err() {
+                debug!("timed out writing events");
+            }
+        }
+    }
+}
+
+impl EventWriter {
+    fn new(path: &Path) -> Self {
+        let (sender, receiver) = channel(EVENT_BUFFER);
+        let task = spawn(write_events(path.to_path_buf(), receiver));
+        Self {
+            path: path.to_path_buf(),
+            sender,
+            task,
+        }
+    }
+}
+
+async fn write_events(path: PathBuf, mut receiver: Receiver<StackString>) {
+    let mut writer = None;
+    while let Some(line) = receiver.recv().await {
+        if writer.is_none() {
+            match open_writer(&path).await {
+                Ok(w) => writer = Some(w),
+                Err(e) => {
+                    debug!("event socket {} unavailable {e}", path.display());
+                    continue;
+                }
+            }
+        }
+        if let Some(w) = writer.as_mut() {
+            if let Err(e) = write_line(w, &line).await {
+                debug!("event socket {} closed {e}", path.display());
+                writer = None;
+            }
+        }
+    }
+}
+
+async fn open_writer(path: &Path) -> Result<Box<dyn AsyncWrite + Send + Unpin>, Error> {
+    let file_type = fs::metadata(path).await?.file_type();
+    if file_type.is_socket() {
+        Ok(Box::new(UnixStream::connect(path).await?))
+    } else if file_type.is_fifo() {
+        // fails with ENXIO until a reader has the pipe open
+        Ok(Box::new(pipe::OpenOptions::new().open_sender(path)?))
+    } else {
+        Err(format_err!(
+            "{} is neither a socket nor a named pipe",
+            path.display()
+        ))
+    }
+}
+
+async fn write_line(writer: &mut (dyn AsyncWrite + Send + Unpin), line: &str) -> Result<(), Error> {
+    writer.write_all(line.as_bytes()).await?;
+    writer.write_all(b"\n").await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use serde_json::{json, Value};
+
+    use crate::event_hook::HookEvent;
+
+    #[test]
+    fn test_hook_event_json() -> Result<(), Error> {
+        let event = HookEvent::copied("file:///tmp/a.txt", "s3://bucket/a.txt", 12, None);
+        let value: Value = serde_json::to_value(&event)?;
+        assert_eq!(value["event"], json!("copied"));
+        assert_eq!(value["bytes"], json!(12));
+        assert!(value.get("error").is_none());
+        assert!(value["timestamp"].is_string());
+
+        let event = HookEvent::deleted("s3://bucket/a.txt", Some("access denied"));
+        let value: Value = serde_json::to_value(&event)?;
+        assert_eq!(value["event"], json!("failed"));
+        assert_eq!(value["src_url"], json!("s3://bucket/a.txt"));
+        assert_eq!(value["error"], json!("access denied"));
+        assert!(value.get("dst_url").is_none());
+
+        let line = serde_json::to_string(&HookEvent::queued("file:///a", "gdrive://x/a"))?;
+        assert!(!line.contains('\n'));
+        Ok(())
+    }
+}
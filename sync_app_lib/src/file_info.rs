@@ -1,29 +1,67 @@
 use anyhow::{format_err, Error};
 use derive_more::{Deref, From, Into};
 use serde::{Deserialize, Serialize};
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 use std::{
     convert::{TryFrom, TryInto},
     fmt::Debug,
     str::FromStr,
     sync::Arc,
 };
+use time::{Duration, OffsetDateTime};
 use url::Url;
 use uuid::Uuid;
 
 use gdrive_lib::date_time_wrapper::DateTimeWrapper;
 
 use crate::{
-    file_info_gcs::FileInfoGcs, file_info_gdrive::FileInfoGDrive, file_info_local::FileInfoLocal,
-    file_info_s3::FileInfoS3, file_info_ssh::FileInfoSSH, file_service::FileService, map_parse,
+    file_info_gcs::FileInfoGcs, file_info_gdrive::FileInfoGDrive, file_info_ipfs::FileInfoIpfs,
+    file_info_local::FileInfoLocal, file_info_s3::FileInfoS3, file_info_sftp::FileInfoSftp,
+    file_info_smb::FileInfoSmb, file_info_ssh::FileInfoSSH, file_service::FileService, map_parse,
     models::FileInfoCache, path_buf_wrapper::PathBufWrapper, pgpool::PgPool,
     url_wrapper::UrlWrapper,
 };
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileStat {
-    pub st_mtime: u32,
-    pub st_size: u32,
+    pub st_mtime: DateTimeWrapper,
+    pub st_size: i64,
+}
+
+impl Default for FileStat {
+    fn default() -> Self {
+        Self::new(OffsetDateTime::UNIX_EPOCH, 0)
+    }
+}
+
+impl FileStat {
+    /// `st_mtime` is truncated to millisecond precision, which is what every
+    /// backend (and the `file_info_cache` table) can represent
+    #[must_use]
+    pub fn new(st_mtime: OffsetDateTime, st_size: i64) -> Self {
+        let nanos = st_mtime.nanosecond() / 1_000_000 * 1_000_000;
+        let st_mtime = st_mtime.replace_nanosecond(nanos).unwrap_or(st_mtime);
+        Self {
+            st_mtime: st_mtime.into(),
+            st_size,
+        }
+    }
+
+    /// Move `st_mtime` from a clock running `offset` ahead of the local clock
+    /// onto the local clock
+    #[must_use]
+    pub fn with_clock_offset(self, offset: Duration) -> Self {
+        Self {
+            st_mtime: (self.st_mtime.to_offsetdatetime() - offset).into(),
+            st_size: self.st_size,
+        }
+    }
+
+    /// True if `self` was modified more than `tolerance` after `other`
+    #[must_use]
+    pub fn is_newer_than(&self, other: &Self, tolerance: Duration) -> bool {
+        self.st_mtime.to_offsetdatetime() - other.st_mtime.to_offsetdatetime() > tolerance
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, Into, From, Deref)]
@@ -92,6 +130,63 @@ impl FromStr for ServiceSession {
     }
 }
 
+impl ServiceSession {
+    /// Session of the local directory `path` on the machine `node`
+    /// # Errors
+    /// Return error if `node` or `path` is empty
+    pub fn local(node: &str, path: &str) -> Result<Self, Error> {
+        if node.is_empty() || path.is_empty() {
+            Err(format_err!("Local session needs a node and a path"))
+        } else {
+            Ok(Self(format_sstr!("{node}:{path}")))
+        }
+    }
+
+    /// Node and path of a local session, sessions created before they were
+    /// scoped by node are only a path and have no node
+    #[must_use]
+    pub fn local_parts(&self) -> (Option<&str>, &str) {
+        if self.0.starts_with('/') || self.0.starts_with('\\') {
+            (None, self.0.as_str())
+        } else {
+            match self.0.split_once(':') {
+                Some((node, path)) => (Some(node), path),
+                None => (None, self.0.as_str()),
+            }
+        }
+    }
+
+    /// Split the session into the account it authenticates as and the
+    /// endpoint it points at. Url style sessions (ssh, sftp, smb) carry both,
+    /// local sessions a node and a path and bucket / drive sessions only an
+    /// account.
+    #[must_use]
+    pub fn identity(&self, servicetype: FileService) -> (StackString, StackString) {
+        match servicetype {
+            FileService::Local => {
+                let (node, path) = self.local_parts();
+                (node.unwrap_or("").into(), path.into())
+            }
+            FileService::SSH | FileService::SFTP | FileService::SMB => match Url::parse(&self.0) {
+                Ok(url) => {
+                    let host = url.host_str().unwrap_or("");
+                    let mut account = if url.username().is_empty() {
+                        StackString::from(host)
+                    } else {
+                        format_sstr!("{}@{host}", url.username())
+                    };
+                    if let Some(port) = url.port() {
+                        account.push_str(&format_sstr!(":{port}"));
+                    }
+                    (account, url.path().into())
+                }
+                Err(_) => (self.0.clone(), StackString::new()),
+            },
+            _ => (self.0.clone(), StackString::new()),
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileInfoInner {
     pub filename: StackString,
@@ -188,6 +283,9 @@ impl FileInfo {
             "gs" => FileInfoGcs::from_url(url).map(FileInfoTrait::into_finfo),
             "gdrive" => FileInfoGDrive::from_url(url).map(FileInfoTrait::into_finfo),
             "ssh" => FileInfoSSH::from_url(url).map(FileInfoTrait::into_finfo),
+            "ipfs" => FileInfoIpfs::from_url(url).map(FileInfoTrait::into_finfo),
+            "sftp" => FileInfoSftp::from_url(url).map(FileInfoTrait::into_finfo),
+            "smb" => FileInfoSmb::from_url(url).map(FileInfoTrait::into_finfo),
             _ => Err(format_err!("Bad scheme")),
         }
     }
@@ -225,8 +323,8 @@ impl TryFrom<&FileInfoCache> for FileInfo {
             md5sum: map_parse(&item.md5sum)?,
             sha1sum: map_parse(&item.sha1sum)?,
             filestat: FileStat {
-                st_mtime: item.filestat_st_mtime as u32,
-                st_size: item.filestat_st_size as u32,
+                st_mtime: item.filestat_st_mtime,
+                st_size: item.filestat_st_size,
             },
             serviceid: item.serviceid.as_str().into(),
             servicetype: item.servicetype.parse()?,
@@ -246,8 +344,8 @@ impl TryFrom<FileInfoCache> for FileInfo {
             md5sum: map_parse(&item.md5sum)?,
             sha1sum: map_parse(&item.sha1sum)?,
             filestat: FileStat {
-                st_mtime: item.filestat_st_mtime as u32,
-                st_size: item.filestat_st_size as u32,
+                st_mtime: item.filestat_st_mtime,
+                st_size: item.filestat_st_size,
             },
             serviceid: item.serviceid.as_str().into(),
             servicetype: item.servicetype.parse()?,
@@ -281,14 +379,16 @@ impl From<&FileInfo> for FileInfoCache {
             urlname: item.urlname.as_str().into(),
             md5sum: item.md5sum.as_ref().map(|m| m.0.clone()),
             sha1sum: item.sha1sum.as_ref().map(|s| s.0.clone()),
-            filestat_st_mtime: item.filestat.st_mtime as i32,
-            filestat_st_size: item.filestat.st_size as i32,
+            filestat_st_mtime: item.filestat.st_mtime,
+            filestat_st_size: item.filestat.st_size,
             serviceid: item.serviceid.0.clone(),
             servicetype: item.servicetype.to_str().into(),
             servicesession: item.servicesession.0.clone(),
             created_at: DateTimeWrapper::now(),
             deleted_at: None,
             modified_at: DateTimeWrapper::now(),
+            storage_class: None,
+            sse: None,
         }
     }
 }
@@ -313,11 +413,44 @@ pub async fn cache_file_info(pool: &PgPool, finfo: FileInfo) -> Result<FileInfoC
     Ok(cache)
 }
 
+/// `FileInfo` fixture for tests, named after the last component of
+/// `filepath`
+/// # Errors
+/// Return error if `url`, `md5sum` or `servicesession` don't parse
+#[cfg(test)]
+pub(crate) fn fixture_info(
+    url: &str,
+    filepath: &str,
+    filestat: FileStat,
+    md5sum: Option<&str>,
+    serviceid: &str,
+    servicetype: FileService,
+    servicesession: &str,
+) -> Result<FileInfo, Error> {
+    let url: Url = url.parse()?;
+    let filename = filepath.rsplit('/').next().unwrap_or(filepath);
+    Ok(FileInfo::new(
+        filename.into(),
+        filepath.into(),
+        url.into(),
+        md5sum.map(str::parse).transpose()?,
+        None,
+        filestat,
+        serviceid.into(),
+        servicetype,
+        servicesession.parse()?,
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use stack_string::StackString;
+    use time::{macros::datetime, Duration};
 
-    use crate::file_info::{map_parse, ServiceSession};
+    use crate::{
+        file_info::{map_parse, FileStat, ServiceSession},
+        file_service::FileService,
+    };
 
     #[test]
     fn test_map_parse() {
@@ -329,4 +462,50 @@ mod tests {
             Some(ServiceSession("test_sessionname".into()))
         );
     }
+
+    #[test]
+    fn test_service_session_identity() {
+        let session: ServiceSession = "ssh://ubuntu@cloud.ddboline.net:2222/home/ubuntu"
+            .parse()
+            .unwrap();
+        let (account, endpoint) = session.identity(FileService::SSH);
+        assert_eq!(account.as_str(), "ubuntu@cloud.ddboline.net:2222");
+        assert_eq!(endpoint.as_str(), "/home/ubuntu");
+
+        let session: ServiceSession = "ddboline@gmail.com".parse().unwrap();
+        let (account, endpoint) = session.identity(FileService::GDrive);
+        assert_eq!(account.as_str(), "ddboline@gmail.com");
+        assert_eq!(endpoint.as_str(), "");
+
+        let session: ServiceSession = "/home/ddboline/Documents".parse().unwrap();
+        let (account, endpoint) = session.identity(FileService::Local);
+        assert_eq!(account.as_str(), "");
+        assert_eq!(endpoint.as_str(), "/home/ddboline/Documents");
+
+        let session = ServiceSession::local("nas", "/home/ddboline/Documents").unwrap();
+        assert_eq!(session.as_str(), "nas:/home/ddboline/Documents");
+        assert_eq!(
+            session.local_parts(),
+            (Some("nas"), "/home/ddboline/Documents")
+        );
+        let (account, endpoint) = session.identity(FileService::Local);
+        assert_eq!(account.as_str(), "nas");
+        assert_eq!(endpoint.as_str(), "/home/ddboline/Documents");
+        assert!(ServiceSession::local("", "/tmp").is_err());
+    }
+
+    #[test]
+    fn test_filestat_mtime() {
+        let stat0 = FileStat::new(datetime!(2023-03-12 07:00:00.123456 -05:00), 100);
+        let stat1 = FileStat::new(datetime!(2023-03-12 12:00:01.123 UTC), 100);
+        assert_eq!(
+            stat0.st_mtime.to_offsetdatetime(),
+            datetime!(2023-03-12 12:00:00.123 UTC)
+        );
+        assert!(stat1.is_newer_than(&stat0, Duration::milliseconds(500)));
+        assert!(!stat1.is_newer_than(&stat0, Duration::seconds(2)));
+        assert!(!stat0.is_newer_than(&stat1, Duration::ZERO));
+        let stat1 = stat1.with_clock_offset(Duration::seconds(1));
+        assert!(!stat1.is_newer_than(&stat0, Duration::ZERO));
+    }
 }
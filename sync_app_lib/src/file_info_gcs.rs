@@ -90,9 +90,8 @@ impl FileInfoGcs {
         let md5sum = item.md5_hash.and_then(|m| m.trim_matches('"').parse().ok());
         let st_mtime = item
             .updated
-            .as_ref()
             .ok_or_else(|| format_err!("No last modified"))?
-            .unix_timestamp();
+            .to_offsetdatetime();
         let size = item.size.ok_or_else(|| format_err!("No file size"))?;
         let st_size = size.parse()?;
         let buf = format_sstr!("gs://{bucket}/{key}");
@@ -107,10 +106,7 @@ impl FileInfoGcs {
             fileurl.into(),
             md5sum,
             None,
-            FileStat {
-                st_mtime: st_mtime as u32,
-                st_size,
-            },
+            FileStat::new(st_mtime, st_size),
             serviceid,
             FileService::GCS,
             servicesession,
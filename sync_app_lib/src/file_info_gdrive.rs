@@ -87,10 +87,7 @@ impl FileInfoGDrive {
             item.urlname.into(),
             md5sum,
             None,
-            FileStat {
-                st_mtime: item.filestat.0,
-                st_size: item.filestat.1,
-            },
+            FileStat::new(item.filestat.0.to_offsetdatetime(), item.filestat.1),
             serviceid,
             FileService::GDrive,
             servicesession,
@@ -107,7 +104,11 @@ mod tests {
     use std::{collections::HashMap, path::Path};
     use url::Url;
 
-    use gdrive_lib::gdrive_instance::{GDriveInfo, GDriveInstance};
+    use gdrive_lib::{
+        date_time_wrapper::DateTimeWrapper,
+        drive_v3_types::File,
+        gdrive_instance::{GDriveInfo, GDriveInstance},
+    };
 
     use crate::{
         config::Config, file_info::FileInfoTrait, file_info_gdrive::FileInfoGDrive,
@@ -125,6 +126,39 @@ mod tests {
         assert_eq!(finfo.get_finfo().servicetype, FileService::GDrive);
     }
 
+    #[test]
+    fn test_export_name() {
+        let doc = File {
+            name: Some("notes".into()),
+            mime_type: Some("application/vnd.google-apps.document".into()),
+            ..File::default()
+        };
+        assert_eq!(
+            GDriveInstance::export_format(&doc.mime_type),
+            Some(("application/vnd.oasis.opendocument.text", "odt"))
+        );
+        assert_eq!(
+            GDriveInstance::export_name(&doc).as_deref(),
+            Some("notes.odt")
+        );
+        let sheet = File {
+            name: Some("budget.XLSX".into()),
+            mime_type: Some("application/vnd.google-apps.spreadsheet".into()),
+            ..File::default()
+        };
+        assert_eq!(
+            GDriveInstance::export_name(&sheet).as_deref(),
+            Some("budget.XLSX")
+        );
+        let pdf = File {
+            name: Some("paper".into()),
+            mime_type: Some("application/pdf".into()),
+            ..File::default()
+        };
+        assert_eq!(GDriveInstance::export_format(&pdf.mime_type), None);
+        assert_eq!(GDriveInstance::export_name(&pdf).as_deref(), Some("paper"));
+    }
+
     #[test]
     #[ignore]
     fn test_file_info_from_object() {
@@ -136,7 +170,7 @@ mod tests {
                 .unwrap(),
             md5sum: Some("afde42b3861d522796faeb33a9eaec8a".into()),
             sha1sum: None,
-            filestat: (123, 123),
+            filestat: (DateTimeWrapper::now(), 5_000_000_000),
             serviceid: "1REd76oJ6YheyjF2R9Il0E8xbjalgpNgG".into(),
             servicesession: "ddboline@gmail.com".into(),
         };
@@ -147,6 +181,7 @@ mod tests {
             finfo.get_finfo().serviceid.as_str(),
             "1REd76oJ6YheyjF2R9Il0E8xbjalgpNgG"
         );
+        assert_eq!(finfo.get_finfo().filestat.st_size, 5_000_000_000);
     }
 
     #[tokio::test]
@@ -0,0 +1,138 @@
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use std::path::Path;
+use url::Url;
+
+use crate::{
+    file_info::{FileInfo, FileInfoTrait, FileStat, Md5Sum, ServiceId, ServiceSession, Sha1Sum},
+    file_service::FileService,
+    ipfs_instance::IpfsEntry,
+};
+
+/// Ipfs is experimental, it's registered as an extension service rather than
+/// a builtin variant
+/// # Errors
+/// Return error if registration fails
+pub fn ipfs_service() -> Result<FileService, Error> {
+    FileService::register_extension("ipfs")
+}
+
+#[derive(Debug, Clone)]
+pub struct FileInfoIpfs(pub FileInfo);
+
+impl FileInfoIpfs {
+    /// # Errors
+    /// Return error if init fails
+    pub fn from_url(url: &Url) -> Result<Self, Error> {
+        if url.scheme() == "ipfs" {
+            let filepath = Path::new(url.path());
+            let filename = filepath
+                .file_name()
+                .ok_or_else(|| format_err!("Parse failure"))?
+                .to_string_lossy()
+                .into_owned()
+                .into();
+            let finfo = FileInfo::new(
+                filename,
+                filepath.to_path_buf().into(),
+                url.clone().into(),
+                None,
+                None,
+                FileStat::default(),
+                ServiceId::default(),
+                ipfs_service()?,
+                ServiceSession::default(),
+            );
+            Ok(Self(finfo))
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+
+    /// The CID of the entry is used as the serviceid
+    /// # Errors
+    /// Return error if init fails
+    pub fn from_entry(
+        baseurl: &Url,
+        mfs_path: &str,
+        entry: &IpfsEntry,
+        servicesession: ServiceSession,
+    ) -> Result<Self, Error> {
+        let mut url = baseurl.clone();
+        url.set_path(mfs_path);
+        let filepath = Path::new(mfs_path);
+        let serviceid: StackString = entry.hash.clone();
+        let finfo = FileInfo::new(
+            entry.name.clone(),
+            filepath.to_path_buf().into(),
+            url.into(),
+            None,
+            None,
+            FileStat {
+                st_size: entry.size,
+                ..FileStat::default()
+            },
+            serviceid.into(),
+            ipfs_service()?,
+            servicesession,
+        );
+        Ok(Self(finfo))
+    }
+}
+
+impl FileInfoTrait for FileInfoIpfs {
+    fn get_finfo(&self) -> &FileInfo {
+        &self.0
+    }
+
+    fn into_finfo(self) -> FileInfo {
+        self.0
+    }
+
+    fn get_md5(&self) -> Option<Md5Sum> {
+        self.0.md5sum.clone()
+    }
+
+    fn get_sha1(&self) -> Option<Sha1Sum> {
+        self.0.sha1sum.clone()
+    }
+
+    fn get_stat(&self) -> FileStat {
+        self.0.filestat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::{
+        file_info::FileInfoTrait,
+        file_info_ipfs::{ipfs_service, FileInfoIpfs},
+        ipfs_instance::IpfsEntry,
+    };
+
+    #[test]
+    fn test_file_info_ipfs() -> Result<(), Error> {
+        let url: Url = "ipfs://localhost:5001/backup/a/b.txt".parse()?;
+        let finfo = FileInfoIpfs::from_url(&url)?;
+        assert_eq!(&finfo.get_finfo().filename, "b.txt");
+        assert_eq!(finfo.get_finfo().servicetype, ipfs_service()?);
+        assert_eq!(finfo.get_finfo().servicetype.to_str(), "ipfs");
+
+        let baseurl: Url = "ipfs://localhost:5001/backup".parse()?;
+        let entry = IpfsEntry {
+            name: "b.txt".into(),
+            entry_type: 0,
+            size: 12,
+            hash: "bafkreia".into(),
+        };
+        let finfo =
+            FileInfoIpfs::from_entry(&baseurl, "/backup/a/b.txt", &entry, "localhost".parse()?)?;
+        assert_eq!(finfo.get_finfo().urlname.as_str(), url.as_str());
+        assert_eq!(finfo.get_finfo().serviceid.as_str(), "bafkreia");
+        assert_eq!(finfo.get_stat().st_size, 12);
+        Ok(())
+    }
+}
@@ -4,7 +4,6 @@ use std::{
     fs,
     fs::{File, Metadata},
     path::Path,
-    time::SystemTime,
 };
 use url::Url;
 use walkdir::DirEntry;
@@ -93,16 +92,10 @@ fn get_sha1sum_impl(path: &Path) -> Result<String, Error> {
 fn get_stat_impl(p: &Path) -> Result<FileStat, Error> {
     let metadata = fs::metadata(p)?;
 
-    let modified = metadata
-        .modified()?
-        .duration_since(SystemTime::UNIX_EPOCH)?
-        .as_secs() as i64;
+    let modified = metadata.modified()?;
     let size = metadata.len();
 
-    Ok(FileStat {
-        st_mtime: modified as u32,
-        st_size: size as u32,
-    })
+    Ok(FileStat::new(modified.into(), size as i64))
 }
 
 impl FileInfoLocal {
@@ -125,15 +118,9 @@ impl FileInfoLocal {
             .into();
         let filestat = {
             let metadata = metadata.ok_or_else(|| format_err!("No metadata"))?;
-            let modified = metadata
-                .modified()?
-                .duration_since(SystemTime::UNIX_EPOCH)?
-                .as_secs() as i64;
+            let modified = metadata.modified()?;
             let size = metadata.len();
-            FileStat {
-                st_mtime: modified as u32,
-                st_size: size as u32,
-            }
+            FileStat::new(modified.into(), size as i64)
         };
         let serviceid = serviceid.ok_or_else(|| format_err!("No service id"))?;
         let servicesession = servicesession.ok_or_else(|| format_err!("No servicesession"))?;
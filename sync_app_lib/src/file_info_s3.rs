@@ -1,12 +1,14 @@
 use anyhow::{format_err, Error};
-use aws_sdk_s3::types::Object;
+use aws_sdk_s3::types::{Object, ObjectStorageClass};
 use stack_string::{format_sstr, StackString};
-use std::{convert::TryInto, path::Path};
+use std::path::Path;
+use time::OffsetDateTime;
 use url::Url;
 
 use crate::{
     file_info::{FileInfo, FileInfoTrait, FileStat, Md5Sum, Sha1Sum},
     file_service::FileService,
+    s3_instance::is_kms_sse,
 };
 
 #[derive(Debug, Default, Clone)]
@@ -91,11 +93,11 @@ impl FileInfoS3 {
             .last_modified
             .as_ref()
             .ok_or_else(|| format_err!("No last modified"))?;
-        let st_mtime = last_modified.as_secs_f64() as i64;
-        let size: u32 = item
-            .size
-            .ok_or_else(|| format_err!("No size"))?
-            .try_into()?;
+        let st_mtime = OffsetDateTime::from_unix_timestamp_nanos(
+            i128::from(last_modified.secs()) * 1_000_000_000
+                + i128::from(last_modified.subsec_nanos()),
+        )?;
+        let size = item.size.ok_or_else(|| format_err!("No size"))?;
         let fileurl = format_sstr!("s3://{bucket}/{key}");
         let fileurl: Url = fileurl.parse()?;
         let id_str: StackString = bucket.into();
@@ -108,10 +110,7 @@ impl FileInfoS3 {
             fileurl.into(),
             md5sum,
             None,
-            FileStat {
-                st_mtime: st_mtime as u32,
-                st_size: size,
-            },
+            FileStat::new(st_mtime, size),
             serviceid,
             FileService::S3,
             servicesession,
@@ -119,17 +118,61 @@ impl FileInfoS3 {
 
         Ok(Self(finfo))
     }
+
+    /// Use the md5 stored as object metadata in place of an etag that isn't
+    /// an md5
+    #[must_use]
+    pub fn with_sidecar_md5(self, md5sum: Option<&str>) -> Self {
+        if let Some(md5sum) = md5sum.and_then(|m| m.parse().ok()) {
+            let mut inner = self.0.inner().clone();
+            inner.md5sum = Some(md5sum);
+            Self(FileInfo::from_inner(inner))
+        } else {
+            self
+        }
+    }
+
+    /// Drop the md5 taken from the etag of an SSE-KMS object, which is
+    /// random looking hex of the right length
+    #[must_use]
+    pub fn with_sse(self, sse: Option<&str>) -> Self {
+        if sse.map_or(false, is_kms_sse) {
+            let mut inner = self.0.inner().clone();
+            inner.md5sum = None;
+            Self(FileInfo::from_inner(inner))
+        } else {
+            self
+        }
+    }
+}
+
+/// Multipart uploads have etags of the form `<md5 of the part md5s>-<parts>`
+#[must_use]
+pub fn is_multipart_etag(e_tag: &str) -> bool {
+    e_tag.trim_matches('"').contains('-')
+}
+
+/// Storage class of a listed object, s3 leaves it out for `STANDARD`
+#[must_use]
+pub fn object_storage_class(item: &Object) -> StackString {
+    item.storage_class
+        .as_ref()
+        .map_or("STANDARD", ObjectStorageClass::as_str)
+        .into()
 }
 
 #[cfg(test)]
 mod tests {
     use aws_sdk_s3::{
         primitives::DateTime,
-        types::{Object, Owner},
+        types::{Object, ObjectStorageClass, Owner},
     };
     use time::macros::datetime;
 
-    use crate::{file_info::FileInfoTrait, file_info_s3::FileInfoS3};
+    use crate::{
+        file_info::FileInfoTrait,
+        file_info_s3::{is_multipart_etag, object_storage_class, FileInfoS3},
+    };
 
     #[test]
     fn test_file_info_s3() {
@@ -151,4 +194,58 @@ mod tests {
         );
         assert_eq!(&finfo.get_finfo().filename, "test_key");
     }
+
+    #[test]
+    fn test_file_info_s3_multipart() {
+        let e_tag = r#""d41d8cd98f00b204e9800998ecf8427e-3""#;
+        assert!(is_multipart_etag(e_tag));
+        assert!(!is_multipart_etag(r#""6f90ebdaabef92a9f76be131037f593b""#));
+        let test_object = Object::builder()
+            .e_tag(e_tag)
+            .key("test_key")
+            .last_modified(DateTime::from_secs(0))
+            .size(100)
+            .build();
+
+        let finfo = FileInfoS3::from_object("test_bucket", test_object).unwrap();
+        assert!(finfo.get_finfo().md5sum.is_none());
+        let finfo = finfo.with_sidecar_md5(Some("6f90ebdaabef92a9f76be131037f593b"));
+        assert_eq!(
+            finfo.get_finfo().md5sum.as_ref().map(|m| m.as_str()),
+            Some("6f90ebdaabef92a9f76be131037f593b")
+        );
+    }
+
+    #[test]
+    fn test_file_info_s3_kms() {
+        let e_tag = r#""6f90ebdaabef92a9f76be131037f593b""#;
+        let test_object = Object::builder()
+            .e_tag(e_tag)
+            .key("test_key")
+            .last_modified(DateTime::from_secs(0))
+            .size(100)
+            .build();
+
+        let finfo = FileInfoS3::from_object("test_bucket", test_object).unwrap();
+        let finfo = finfo.with_sse(Some("AES256"));
+        assert!(finfo.get_finfo().md5sum.is_some());
+        let finfo = finfo.with_sse(Some("aws:kms"));
+        assert!(finfo.get_finfo().md5sum.is_none());
+        let finfo = finfo.with_sidecar_md5(Some("d41d8cd98f00b204e9800998ecf8427e"));
+        assert_eq!(
+            finfo.get_finfo().md5sum.as_ref().map(|m| m.as_str()),
+            Some("d41d8cd98f00b204e9800998ecf8427e")
+        );
+    }
+
+    #[test]
+    fn test_object_storage_class() {
+        let object = Object::builder().key("test_key").build();
+        assert_eq!(object_storage_class(&object).as_str(), "STANDARD");
+        let object = Object::builder()
+            .key("test_key")
+            .storage_class(ObjectStorageClass::DeepArchive)
+            .build();
+        assert_eq!(object_storage_class(&object).as_str(), "DEEP_ARCHIVE");
+    }
 }
@@ -0,0 +1,117 @@
+use anyhow::{format_err, Error};
+use percent_encoding::percent_decode_str;
+use std::path::Path;
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::{
+    file_info::{FileInfo, FileInfoTrait, FileStat, Md5Sum, ServiceId, ServiceSession, Sha1Sum},
+    file_service::FileService,
+    sftp_instance::SftpStat,
+};
+
+#[derive(Debug, Clone)]
+pub struct FileInfoSftp(pub FileInfo);
+
+impl FileInfoSftp {
+    /// # Errors
+    /// Return error if init fails
+    pub fn from_url(url: &Url) -> Result<Self, Error> {
+        if url.scheme() == "sftp" {
+            let path = percent_decode_str(url.path()).decode_utf8_lossy();
+            let filepath = Path::new(path.as_ref());
+            let filename = filepath
+                .file_name()
+                .ok_or_else(|| format_err!("Parse failure"))?
+                .to_string_lossy()
+                .into_owned()
+                .into();
+            let finfo = FileInfo::new(
+                filename,
+                filepath.to_path_buf().into(),
+                url.clone().into(),
+                None,
+                None,
+                FileStat::default(),
+                ServiceId::default(),
+                FileService::SFTP,
+                ServiceSession::default(),
+            );
+            Ok(Self(finfo))
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+
+    /// Entry for a file listed over sftp, there are no checksums so changes
+    /// are detected by size and mtime alone
+    /// # Errors
+    /// Return error if the url is invalid or the mtime is out of range
+    pub fn from_stat(
+        url: &Url,
+        stat: SftpStat,
+        servicesession: ServiceSession,
+    ) -> Result<Self, Error> {
+        let mut finfo = Self::from_url(url)?.0.inner().clone();
+        let mtime = OffsetDateTime::from_unix_timestamp(stat.mtime as i64)?;
+        finfo.filestat = FileStat::new(mtime, stat.size as i64);
+        finfo.serviceid = servicesession.as_str().into();
+        finfo.servicesession = servicesession;
+        Ok(Self(FileInfo::from_inner(finfo)))
+    }
+}
+
+impl FileInfoTrait for FileInfoSftp {
+    fn get_finfo(&self) -> &FileInfo {
+        &self.0
+    }
+
+    fn into_finfo(self) -> FileInfo {
+        self.0
+    }
+
+    fn get_md5(&self) -> Option<Md5Sum> {
+        self.0.md5sum.clone()
+    }
+
+    fn get_sha1(&self) -> Option<Sha1Sum> {
+        self.0.sha1sum.clone()
+    }
+
+    fn get_stat(&self) -> FileStat {
+        self.0.filestat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::{
+        file_info::FileInfoTrait, file_info_sftp::FileInfoSftp, file_service::FileService,
+        sftp_instance::SftpStat,
+    };
+
+    #[test]
+    fn test_file_info_sftp() -> Result<(), Error> {
+        let url: Url = "sftp://ubuntu@cloud.ddboline.net/home/ubuntu/a%20b.txt".parse()?;
+        let finfo = FileInfoSftp::from_url(&url)?;
+        assert_eq!(&finfo.get_finfo().filename, "a b.txt");
+        assert_eq!(finfo.get_finfo().servicetype, FileService::SFTP);
+        assert_eq!(
+            finfo.get_finfo().filepath.to_string_lossy(),
+            "/home/ubuntu/a b.txt"
+        );
+
+        let stat = SftpStat {
+            size: 1234,
+            mtime: 1_700_000_000,
+        };
+        let finfo = FileInfoSftp::from_stat(&url, stat, "ubuntu@cloud.ddboline.net".parse()?)?;
+        assert_eq!(finfo.get_stat().st_size, 1234);
+        assert_eq!(finfo.get_stat().st_mtime.unix_timestamp(), 1_700_000_000);
+        assert_eq!(finfo.get_md5(), None);
+        Ok(())
+    }
+}
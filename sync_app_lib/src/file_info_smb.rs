@@ -0,0 +1,100 @@
+use anyhow::{format_err, Error};
+use percent_encoding::percent_decode_str;
+use std::path::Path;
+use url::Url;
+
+use crate::{
+    file_info::{FileInfo, FileInfoTrait, FileStat, Md5Sum, ServiceId, ServiceSession, Sha1Sum},
+    file_service::FileService,
+};
+
+#[derive(Debug, Clone)]
+pub struct FileInfoSmb(pub FileInfo);
+
+impl FileInfoSmb {
+    /// # Errors
+    /// Return error if init fails
+    pub fn from_url(url: &Url) -> Result<Self, Error> {
+        if url.scheme() == "smb" {
+            let path = percent_decode_str(url.path()).decode_utf8_lossy();
+            let filepath = Path::new(path.as_ref());
+            let filename = filepath
+                .file_name()
+                .ok_or_else(|| format_err!("Parse failure"))?
+                .to_string_lossy()
+                .into_owned()
+                .into();
+            let finfo = FileInfo::new(
+                filename,
+                filepath.to_path_buf().into(),
+                url.clone().into(),
+                None,
+                None,
+                FileStat::default(),
+                ServiceId::default(),
+                FileService::SMB,
+                ServiceSession::default(),
+            );
+            Ok(Self(finfo))
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+
+    /// Convert the info for a file read through the gvfs mount point into an
+    /// smb entry, checksums and stat are kept
+    #[must_use]
+    pub fn from_mounted(finfo: &FileInfo, url: Url, servicesession: ServiceSession) -> Self {
+        let mut inner = finfo.inner().clone();
+        let path = percent_decode_str(url.path()).decode_utf8_lossy();
+        inner.filepath = Path::new(path.as_ref()).to_path_buf().into();
+        inner.urlname = url.into();
+        inner.serviceid = servicesession.as_str().into();
+        inner.servicetype = FileService::SMB;
+        inner.servicesession = servicesession;
+        Self(FileInfo::from_inner(inner))
+    }
+}
+
+impl FileInfoTrait for FileInfoSmb {
+    fn get_finfo(&self) -> &FileInfo {
+        &self.0
+    }
+
+    fn into_finfo(self) -> FileInfo {
+        self.0
+    }
+
+    fn get_md5(&self) -> Option<Md5Sum> {
+        self.0.md5sum.clone()
+    }
+
+    fn get_sha1(&self) -> Option<Sha1Sum> {
+        self.0.sha1sum.clone()
+    }
+
+    fn get_stat(&self) -> FileStat {
+        self.0.filestat
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::{file_info::FileInfoTrait, file_info_smb::FileInfoSmb, file_service::FileService};
+
+    #[test]
+    fn test_file_info_smb() -> Result<(), Error> {
+        let url: Url = "smb://user@fileserver/Media/Movies/a%20b.mkv".parse()?;
+        let finfo = FileInfoSmb::from_url(&url)?;
+        assert_eq!(&finfo.get_finfo().filename, "a b.mkv");
+        assert_eq!(finfo.get_finfo().servicetype, FileService::SMB);
+        assert_eq!(
+            finfo.get_finfo().filepath.to_string_lossy(),
+            "/Media/Movies/a b.mkv"
+        );
+        Ok(())
+    }
+}
@@ -1,36 +1,190 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
 use futures::TryStreamExt;
-use log::info;
+use log::{info, warn};
 use stack_string::{format_sstr, StackString};
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     convert::TryInto,
     fmt::Debug,
-    fs::rename,
     ops::Deref,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
 };
 use stdout_channel::StdoutChannel;
+use time::Duration;
 use url::Url;
 use uuid::Uuid;
 
-use gdrive_lib::directory_info::DirectoryInfo;
+use gdrive_lib::{directory_info::DirectoryInfo, token_file};
 
 use crate::{
+    cancellation::check_cancelled,
     config::Config,
     file_info::{FileInfo, FileInfoKeyType, FileInfoTrait, ServiceSession},
     file_list_gcs::FileListGcs,
     file_list_gdrive::FileListGDrive,
+    file_list_ipfs::FileListIpfs,
     file_list_local::FileListLocal,
     file_list_s3::FileListS3,
+    file_list_sftp::FileListSftp,
+    file_list_smb::FileListSmb,
     file_list_ssh::FileListSSH,
     file_service::FileService,
-    models::{DirectoryInfoCache, FileInfoCache},
+    metadata_extract::extract_metadata,
+    models::{DirectoryInfoCache, FileInfoCache, IndexRun, ServiceSessionEntry, SessionUsage},
+    ownership::Owner,
     pgpool::PgPool,
+    progress::ProgressChannel,
 };
 
+/// Offset / limit for `print_list`, entries are counted as they stream past
+/// so that nothing has to be buffered and listing can stop early
+#[derive(Clone, Debug, Default)]
+pub struct ListWindow {
+    offset: usize,
+    limit: Option<usize>,
+    seen: Arc<AtomicUsize>,
+}
+
+impl ListWindow {
+    #[must_use]
+    pub fn new(offset: Option<usize>, limit: Option<usize>) -> Self {
+        Self {
+            offset: offset.unwrap_or(0),
+            limit,
+            seen: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    #[must_use]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Count the next entry, true if it falls inside the window
+    pub fn admit(&self) -> bool {
+        let idx = self.seen.fetch_add(1, Ordering::SeqCst);
+        idx >= self.offset && self.limit.map_or(true, |l| idx < self.offset + l)
+    }
+
+    /// True once every entry in the window has been seen
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.limit.map_or(false, |l| {
+            self.seen.load(Ordering::SeqCst) >= self.offset + l
+        })
+    }
+}
+
+/// Urls looked up at once by `CachedEntries::take`
+pub const CACHED_ENTRIES_BATCH: usize = 1000;
+
+/// Cached entries an index run compares its listing against.  Up to
+/// `filemap_max_entries` they're loaded into memory, beyond that they're
+/// looked up from the db a batch at a time so that indexing millions of
+/// files doesn't need memory for all of them.
+pub struct CachedEntries<'a> {
+    flist: &'a dyn FileListTrait,
+    memory: Option<HashMap<StackString, FileInfoCache>>,
+}
+
+impl<'a> CachedEntries<'a> {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn load(flist: &'a dyn FileListTrait) -> Result<CachedEntries<'a>, Error> {
+        let session = flist.get_servicesession().as_str();
+        let stype = flist.get_servicetype().to_str();
+        let pool = flist.get_pool();
+        let count = FileInfoCache::count_cached(session, stype, pool, false).await?;
+        let memory = if count as usize > flist.get_config().filemap_max_entries {
+            info!("{count} cached entries for {session}, looking them up from the db");
+            None
+        } else {
+            let entries = FileInfoCache::get_all_cached(session, stype, pool, false)
+                .await?
+                .map_ok(|f| (f.urlname.clone(), f))
+                .try_collect()
+                .await?;
+            Some(entries)
+        };
+        Ok(Self { flist, memory })
+    }
+
+    /// Cached entries of `urls` (at most `CACHED_ENTRIES_BATCH` at a time
+    /// make sense), in memory they're taken out so that only the entries
+    /// never listed are left
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn take(
+        &mut self,
+        urls: &[StackString],
+    ) -> Result<HashMap<StackString, FileInfoCache>, Error> {
+        if let Some(memory) = &mut self.memory {
+            return Ok(urls
+                .iter()
+                .filter_map(|url| memory.remove_entry(url))
+                .collect());
+        }
+        let entries = FileInfoCache::get_by_urlnames(
+            urls,
+            self.flist.get_servicesession().as_str(),
+            self.flist.get_servicetype().to_str(),
+            self.flist.get_pool(),
+        )
+        .await?;
+        Ok(entries
+            .into_iter()
+            .map(|f| (f.urlname.clone(), f))
+            .collect())
+    }
+
+    /// Mark deleted every entry whose file `exists` says is gone, in memory
+    /// only the entries never taken are checked, from the db all of them
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_missing<F>(self, exists: F) -> Result<usize, Error>
+    where
+        F: Fn(&FileInfoCache) -> bool,
+    {
+        let pool = self.flist.get_pool();
+        let mut number_deleted = 0;
+        if let Some(memory) = self.memory {
+            for missing in memory.into_values() {
+                if !exists(&missing) {
+                    missing.delete(pool).await?;
+                    number_deleted += 1;
+                }
+            }
+        } else {
+            let mut entries = Box::pin(
+                FileInfoCache::get_all_cached(
+                    self.flist.get_servicesession().as_str(),
+                    self.flist.get_servicetype().to_str(),
+                    pool,
+                    false,
+                )
+                .await?,
+            );
+            while let Some(entry) = entries.try_next().await? {
+                if !exists(&entry) {
+                    entry.delete(pool).await?;
+                    number_deleted += 1;
+                }
+            }
+        }
+        Ok(number_deleted)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct FileList {
     baseurl: Url,
@@ -94,9 +248,40 @@ impl FileList {
                 let flist = FileListSSH::from_url(url, config, pool).await?;
                 Ok(Box::new(flist))
             }
+            "sftp" => {
+                let flist = FileListSftp::from_url(url, config, pool)?;
+                Ok(Box::new(flist))
+            }
+            "smb" => {
+                let flist = FileListSmb::from_url(url, config, pool)?;
+                Ok(Box::new(flist))
+            }
+            "ipfs" => {
+                let flist = FileListIpfs::from_url(url, config, pool)?;
+                Ok(Box::new(flist))
+            }
             _ => Err(format_err!("Bad scheme")),
         }
     }
+
+    /// Same as `from_url`, but a local url with an entry in `snapshots` (see
+    /// `FileSyncConfig::get_snapshot_paths`) is indexed from its snapshot
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn from_url_with_snapshots(
+        url: &Url,
+        snapshots: &HashMap<Url, PathBuf>,
+        config: &Config,
+        pool: &PgPool,
+    ) -> Result<Box<dyn FileListTrait>, Error> {
+        match snapshots.get(url) {
+            Some(snapshot) if url.scheme() == "file" => {
+                let flist = FileListLocal::from_url(url, config, pool)?.with_snapshot(snapshot);
+                Ok(Box::new(flist))
+            }
+            _ => Self::from_url(url, config, pool).await,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -138,6 +323,12 @@ pub trait FileListTrait: Send + Sync + Debug {
         panic!("not implemented for {:?} {:?}", finfo0, finfo1);
     }
 
+    /// Whether `move_file` renames files on the backend itself, sync
+    /// follows renamed files with it instead of copying them again
+    fn supports_move(&self) -> bool {
+        false
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
@@ -150,20 +341,120 @@ pub trait FileListTrait: Send + Sync + Debug {
         panic!("not implemented for {:?}", finfo);
     }
 
+    /// Move the file to the backend's trash instead of deleting it,
+    /// backends without a trash refuse, delete those with `--hard`
+    async fn trash(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        Err(format_err!(
+            "No trash for {}, use --hard to delete {}",
+            self.get_servicetype(),
+            finfo.get_finfo().urlname
+        ))
+    }
+
+    /// Owner of the file, `None` for backends without unix ownership
+    async fn get_owner(&self, _: &dyn FileInfoTrait) -> Result<Option<Owner>, Error> {
+        Ok(None)
+    }
+
+    async fn set_owner(&self, _: &dyn FileInfoTrait, _: &Owner) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Make sure the destination directory `directory` exists, called once
+    /// per directory before its files are copied
+    async fn create_directory(&self, _: &Url) -> Result<(), Error> {
+        Ok(())
+    }
+
     /// Return updated FileInfo entries
     async fn update_file_cache(&self) -> Result<usize, Error>;
 
-    async fn print_list(&self, _: &StdoutChannel<StackString>) -> Result<(), Error> {
+    /// Run `update_file_cache`, recording the run in `index_run` so that
+    /// `compare_lists` can tell whether the cache can be trusted. The
+    /// session is registered in `service_sessions` and the new cache rows
+    /// linked to it
+    /// # Errors
+    /// Return error if `update_file_cache` or db query fails
+    async fn index(&self) -> Result<usize, Error> {
+        check_cancelled()?;
+        let pool = self.get_pool();
+        let mut run = IndexRun::start(
+            self.get_servicetype().to_str(),
+            self.get_servicesession().as_str(),
+            self.get_baseurl().as_str(),
+            pool,
+        )
+        .await?;
+        ProgressChannel::global().current(self.get_baseurl().as_str());
+        let result = self.update_file_cache().await;
+        match &result {
+            Ok(number_updated) => run.finish(true, *number_updated, pool).await?,
+            Err(_) => run.finish(false, 0, pool).await?,
+        }
+        // rows written before a failure are linked as well
+        let servicetype = self.get_servicetype();
+        let (account, endpoint) = self.get_servicesession().identity(servicetype);
+        ServiceSessionEntry::get_or_create(
+            servicetype.to_str(),
+            self.get_servicesession().as_str(),
+            &account,
+            &endpoint,
+            pool,
+        )
+        .await?
+        .link_cache(pool)
+        .await?;
+        let number_updated = result?;
+        SessionUsage::record(
+            servicetype.to_str(),
+            self.get_servicesession().as_str(),
+            pool,
+        )
+        .await?;
+        if let Err(e) = extract_metadata(self, pool).await {
+            warn!("metadata extraction failed {e}");
+        }
+        Ok(number_updated)
+    }
+
+    /// Cheap check that the backend is reachable, run before any listing
+    async fn probe(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Offset of the backend's clock relative to the local clock, applied to
+    /// mtimes before comparing them
+    async fn clock_offset(&self) -> Result<Duration, Error> {
+        Ok(Duration::ZERO)
+    }
+
+    async fn print_list(
+        &self,
+        _: &StdoutChannel<StackString>,
+        _: &ListWindow,
+    ) -> Result<(), Error> {
         unimplemented!()
     }
 
+    /// Every file under the base url read straight from the backend, the
+    /// cache is neither read nor written.  Used by `--no-db` copies
+    async fn list_files(&self) -> Result<Vec<FileInfo>, Error> {
+        Err(format_err!(
+            "Listing {} without the database isn't supported",
+            self.get_servicetype()
+        ))
+    }
+
     /// # Errors
     /// Return error if init fails
     fn cleanup(&self) -> Result<(), Error> {
         if self.get_servicetype() == FileService::GDrive {
             let config = &self.get_config();
             let token_str = format_sstr!("{}_start_page_token", self.get_servicesession().as_str());
-            let fname = config.gdrive_token_path.join(token_str);
+            let fname = config
+                .gdrive_config(self.get_servicesession().as_str())
+                .token_path
+                .join(token_str);
             let ext = fname
                 .extension()
                 .ok_or_else(|| format_err!("No extension"))?
@@ -171,11 +462,7 @@ pub trait FileListTrait: Send + Sync + Debug {
             let ext_str = format_sstr!("{ext}.new");
             let start_page_path = fname.with_extension(ext_str);
             info!("{:?} {:?}", start_page_path, fname);
-            if start_page_path.exists() {
-                rename(&start_page_path, &fname).map_err(Into::into)
-            } else {
-                Ok(())
-            }
+            token_file::promote_token(&start_page_path, &fname)
         } else {
             Ok(())
         }
@@ -390,3 +677,80 @@ pub fn group_urls(url_list: &[Url]) -> HashMap<StackString, Vec<Url>> {
         h
     })
 }
+
+/// Copies keyed on the destination's parent directory (with trailing `/`),
+/// sorted so that all files going into one directory are processed together
+#[must_use]
+pub fn group_by_directory(pairs: Vec<(Url, Url)>) -> BTreeMap<StackString, Vec<(Url, Url)>> {
+    let mut groups: BTreeMap<StackString, Vec<(Url, Url)>> = BTreeMap::new();
+    for (src, dst) in pairs {
+        let directory = dst
+            .as_str()
+            .rsplit_once('/')
+            .map_or(dst.as_str(), |(d, _)| d);
+        groups
+            .entry(format_sstr!("{directory}/"))
+            .or_default()
+            .push((src, dst));
+    }
+    for pairs in groups.values_mut() {
+        pairs.sort_by(|(_, a), (_, b)| a.as_str().cmp(b.as_str()));
+    }
+    groups
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::file_list::{group_by_directory, ListWindow};
+
+    #[test]
+    fn test_group_by_directory() -> Result<(), Error> {
+        let pairs: Vec<(Url, Url)> = [
+            (
+                "file:///home/a/x/2.txt",
+                "gdrive://session/My%20Drive/x/2.txt",
+            ),
+            (
+                "file:///home/a/y/1.txt",
+                "gdrive://session/My%20Drive/y/1.txt",
+            ),
+            (
+                "file:///home/a/x/1.txt",
+                "gdrive://session/My%20Drive/x/1.txt",
+            ),
+        ]
+        .iter()
+        .map(|(src, dst)| -> Result<(Url, Url), Error> { Ok((src.parse()?, dst.parse()?)) })
+        .collect::<Result<_, Error>>()?;
+        let groups = group_by_directory(pairs);
+        let keys: Vec<_> = groups.keys().map(|k| k.as_str()).collect();
+        assert_eq!(
+            keys,
+            vec![
+                "gdrive://session/My%20Drive/x/",
+                "gdrive://session/My%20Drive/y/"
+            ]
+        );
+        let files: Vec<_> = groups["gdrive://session/My%20Drive/x/"]
+            .iter()
+            .map(|(_, dst)| dst.path())
+            .collect();
+        assert_eq!(files, vec!["/My%20Drive/x/1.txt", "/My%20Drive/x/2.txt"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_window() {
+        let window = ListWindow::new(Some(2), Some(3));
+        let admitted: Vec<_> = (0..5).map(|_| window.admit()).collect();
+        assert_eq!(admitted, vec![false, false, true, true, true]);
+        assert!(window.is_done());
+
+        let window = ListWindow::new(None, None);
+        assert!((0..100).all(|_| window.admit()));
+        assert!(!window.is_done());
+    }
+}
@@ -2,7 +2,7 @@ use anyhow::{format_err, Error};
 use async_trait::async_trait;
 use checksums::{hash_file, Algorithm};
 use futures::TryStreamExt;
-use log::{debug, info};
+use log::{debug, info, warn};
 use stack_string::{format_sstr, StackString};
 use std::{
     collections::HashMap,
@@ -18,10 +18,11 @@ use crate::{
     config::Config,
     file_info::{FileInfoTrait, ServiceSession},
     file_info_gcs::FileInfoGcs,
-    file_list::{FileList, FileListTrait},
+    file_list::{FileList, FileListTrait, ListWindow},
     file_service::FileService,
     models::FileInfoCache,
     pgpool::PgPool,
+    progress::ProgressChannel,
 };
 
 #[derive(Debug, Clone)]
@@ -46,7 +47,7 @@ impl FileListGcs {
             bucket.parse()?,
             pool.clone(),
         );
-        let gcs = GcsInstance::new(&config.gcs_token_path, &config.gcs_secret_file, bucket).await?;
+        let gcs = Self::gcs_instance(config, bucket).await?;
 
         Ok(Self { flist, gcs })
     }
@@ -65,15 +66,29 @@ impl FileListGcs {
                 bucket.parse()?,
                 pool.clone(),
             );
-            let config = config.clone();
-            let gcs =
-                GcsInstance::new(&config.gcs_token_path, &config.gcs_secret_file, bucket).await?;
+            let gcs = Self::gcs_instance(config, bucket).await?;
 
             Ok(Self { flist, gcs })
         } else {
             Err(format_err!("Wrong scheme"))
         }
     }
+
+    /// Client trusting the `[proxy.gcs]` (or global) ca bundle, the google
+    /// api client can't go through a proxy so a configured one is ignored
+    async fn gcs_instance(config: &Config, bucket: &str) -> Result<GcsInstance, Error> {
+        let proxy = config.proxy_config("gcs");
+        if let Some(url) = &proxy.url {
+            warn!("gcs doesn't support proxies, connecting to {bucket} without {url}");
+        }
+        GcsInstance::new_with_ca_bundle(
+            &config.gcs_token_path,
+            &config.gcs_secret_file,
+            bucket,
+            proxy.ca_bundle.as_deref(),
+        )
+        .await
+    }
 }
 
 #[async_trait]
@@ -101,6 +116,10 @@ impl FileListTrait for FileListGcs {
         &self.flist.pool
     }
 
+    async fn probe(&self) -> Result<(), Error> {
+        self.gcs.probe(self.get_servicesession().as_str()).await
+    }
+
     async fn update_file_cache(&self) -> Result<usize, Error> {
         let bucket = self
             .get_baseurl()
@@ -122,7 +141,9 @@ impl FileListTrait for FileListGcs {
         .await?;
         debug!("expected {}", cached_urls.len());
 
-        for object in self.gcs.get_list_of_keys(bucket, Some(prefix)).await? {
+        let objects = self.gcs.get_list_of_keys(bucket, Some(prefix)).await?;
+        ProgressChannel::global().scanned(objects.len());
+        for object in objects {
             let info: FileInfoCache = FileInfoGcs::from_object(bucket, object)?
                 .into_finfo()
                 .into();
@@ -144,7 +165,11 @@ impl FileListTrait for FileListGcs {
         Ok(number_updated)
     }
 
-    async fn print_list(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
         let bucket = self
             .get_baseurl()
             .host_str()
@@ -153,10 +178,11 @@ impl FileListTrait for FileListGcs {
 
         self.gcs
             .process_list_of_keys(bucket, Some(prefix), |i| {
-                let key = i.name.as_ref().map_or_else(|| "", String::as_str);
-                let buf = format_sstr!("gs://{bucket}/{key}");
-                stdout.send(buf);
-                Ok(())
+                if window.admit() {
+                    let key = i.name.as_ref().map_or_else(|| "", String::as_str);
+                    stdout.send(format_sstr!("gs://{bucket}/{key}"));
+                }
+                Ok(!window.is_done())
             })
             .await
     }
@@ -231,6 +257,10 @@ impl FileListTrait for FileListGcs {
         }
     }
 
+    fn supports_move(&self) -> bool {
+        true
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
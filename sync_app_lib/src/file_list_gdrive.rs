@@ -1,38 +1,259 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
 use futures::TryStreamExt;
-use log::debug;
+use log::{debug, info, warn};
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use stack_string::{format_sstr, StackString};
-use std::{collections::HashMap, fs::create_dir_all, path::Path, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, metadata},
+    future::Future,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use stdout_channel::StdoutChannel;
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{Mutex, RwLock},
+    task::spawn_blocking,
+    task_local,
+    time::sleep,
+};
 use url::Url;
 
 use gdrive_lib::{
+    date_time_wrapper::DateTimeWrapper,
     directory_info::DirectoryInfo,
-    gdrive_instance::{GDriveInfo, GDriveInstance},
+    drive_v3_types::{Change, File},
+    gdrive_instance::{GDriveInfo, GDriveInstance, UploadStatus},
 };
 
 use crate::{
     config::Config,
     file_info::{FileInfo, FileInfoTrait, ServiceSession},
     file_info_gdrive::FileInfoGDrive,
-    file_list::{FileList, FileListTrait},
+    file_info_local::FileInfoLocal,
+    file_list::{FileList, FileListTrait, ListWindow},
     file_service::FileService,
-    models::FileInfoCache,
+    gdrive_duplicates::{plan_duplicates, url_filename},
+    models::{
+        ExclusionReason, FileInfoCache, GDriveDuplicate, GDriveExclusion, GDriveExport,
+        GDriveMissingMetadata, IndexProgress, ResumableTransfer,
+    },
     pgpool::PgPool,
+    progress::ProgressChannel,
 };
 
+const FOLDER_MIME_TYPE: &str = "application/vnd.google-apps.folder";
+
+/// Cache updates from applying a change list to the cached entries of a
+/// session, kept free of any api or db access so it can be tested against
+/// synthetic change feeds
+#[derive(Debug, Default, Clone)]
+pub struct ChangeApplication {
+    /// Ids whose entries are removed: files removed from the drive and the
+    /// previous location of moved files
+    pub delete_ids: Vec<StackString>,
+    /// Ids of files moved to the drive's trash, their entries are kept as
+    /// tombstones so the other side of a sync can follow the deletion
+    pub trash_ids: Vec<StackString>,
+    /// Entries inserted or updated, after `delete_ids` are removed
+    pub upserts: Vec<FileInfoCache>,
+}
+
+impl ChangeApplication {
+    /// Ids of the files a change list removes, ids of the files it trashes
+    /// and the files it changes
+    #[must_use]
+    pub fn split_changes(chlist: &[Change]) -> (Vec<StackString>, Vec<StackString>, Vec<File>) {
+        let mut removed = Vec::new();
+        let mut trashed = Vec::new();
+        let mut changed = Vec::new();
+        for ch in chlist {
+            match &ch.file {
+                Some(f) if f.trashed == Some(true) => {
+                    if let Some(file_id) = f.id.as_ref().or(ch.file_id.as_ref()) {
+                        trashed.push(file_id.as_str().into());
+                    }
+                }
+                Some(f) => changed.push(f.clone()),
+                None => {
+                    if let Some(file_id) = &ch.file_id {
+                        removed.push(file_id.as_str().into());
+                    }
+                }
+            }
+        }
+        (removed, trashed, changed)
+    }
+
+    /// Merge the `removed` and `trashed` ids and the changed files of a
+    /// change list (`flist`, already converted) with the `cached` entries
+    /// keyed by url.  Files cached at the same url with the same size and
+    /// checksum are skipped, files cached at a different url were moved so
+    /// their old entry is removed.
+    #[must_use]
+    pub fn new(
+        removed: Vec<StackString>,
+        trashed: Vec<StackString>,
+        flist: Vec<FileInfo>,
+        cached: &HashMap<StackString, FileInfoCache>,
+    ) -> Self {
+        let cached_urls_by_id: HashMap<&str, &str> = cached
+            .values()
+            .filter(|f| f.deleted_at.is_none())
+            .map(|f| (f.serviceid.as_str(), f.urlname.as_str()))
+            .collect();
+        let mut delete_ids: Vec<StackString> = Vec::new();
+        let mut deleted: HashSet<StackString> = HashSet::new();
+        for id in removed {
+            if deleted.insert(id.clone()) {
+                delete_ids.push(id);
+            }
+        }
+        let mut trash_ids: Vec<StackString> = Vec::new();
+        for id in trashed {
+            if !deleted.contains(&id) && cached_urls_by_id.contains_key(id.as_str()) {
+                deleted.insert(id.clone());
+                trash_ids.push(id);
+            }
+        }
+        let mut upserts = Vec::new();
+        for f in flist {
+            let info: FileInfoCache = f.into();
+            if !deleted.contains(&info.serviceid) {
+                if let Some(existing) = cached.get(&info.urlname) {
+                    if existing.deleted_at.is_none()
+                        && existing.filestat_st_size == info.filestat_st_size
+                        && (existing.md5sum.is_none()
+                            || info.md5sum.is_none()
+                            || existing.md5sum == info.md5sum)
+                    {
+                        continue;
+                    }
+                }
+                if let Some(old_url) = cached_urls_by_id.get(info.serviceid.as_str()) {
+                    if *old_url != info.urlname.as_str() {
+                        deleted.insert(info.serviceid.clone());
+                        delete_ids.push(info.serviceid.clone());
+                    }
+                }
+            }
+            upserts.push(info);
+        }
+        Self {
+            delete_ids,
+            trash_ids,
+            upserts,
+        }
+    }
+}
+
+task_local! {
+    static GDRIVE_SESSIONS: GDriveSessions;
+}
+
+/// Sessions shared by every `FileListGDrive` created inside
+/// `GDriveSessions::scope`, so that syncing several urls of one account
+/// authenticates, loads the directory map and lists changes only once
+#[derive(Clone, Default)]
+pub struct GDriveSessions(Arc<Mutex<HashMap<StackString, GDriveSession>>>);
+
+impl GDriveSessions {
+    /// Run `f` with a fresh set of shared sessions, they're dropped with it
+    pub async fn scope<F: Future>(f: F) -> F::Output {
+        GDRIVE_SESSIONS.scope(Self::default(), f).await
+    }
+}
+
+#[derive(Clone)]
+struct GDriveSession {
+    gdrive: GDriveInstance,
+    directory_map: Arc<RwLock<HashMap<StackString, DirectoryInfo>>>,
+    root_directory: Arc<RwLock<Option<StackString>>>,
+    create_lock: Arc<Mutex<()>>,
+    shared: Option<Arc<SharedState>>,
+}
+
+impl GDriveSession {
+    fn new(gdrive: GDriveInstance, shared: bool) -> Self {
+        let shared = if shared {
+            Some(Arc::new(SharedState {
+                initial_token: gdrive.start_page_token.load(),
+                directory_map_loaded: AtomicBool::new(false),
+                changes: Mutex::new(None),
+            }))
+        } else {
+            None
+        };
+        Self {
+            gdrive,
+            directory_map: Arc::new(RwLock::new(HashMap::new())),
+            root_directory: Arc::new(RwLock::new(None)),
+            create_lock: Arc::new(Mutex::new(())),
+            shared,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SharedState {
+    /// Change token when the session was created, later lists would otherwise
+    /// see the token already advanced by the first one to index
+    initial_token: Option<usize>,
+    /// Set once the directory map has been fetched from the api
+    directory_map_loaded: AtomicBool,
+    /// New start page token and the changes since `initial_token`
+    changes: Mutex<Option<(usize, Arc<Vec<Change>>)>>,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileListGDrive {
     pub flist: FileList,
     pub gdrive: GDriveInstance,
     pub directory_map: Arc<RwLock<HashMap<StackString, DirectoryInfo>>>,
     pub root_directory: Arc<RwLock<Option<StackString>>>,
+    create_lock: Arc<Mutex<()>>,
+    shared: Option<Arc<SharedState>>,
 }
 
 impl FileListGDrive {
+    fn from_session(flist: FileList, session: GDriveSession) -> Self {
+        Self {
+            flist,
+            gdrive: session.gdrive,
+            directory_map: session.directory_map,
+            root_directory: session.root_directory,
+            create_lock: session.create_lock,
+            shared: session.shared,
+        }
+    }
+
+    /// The session shared within the current `GDriveSessions::scope`, or a new
+    /// unshared one outside of it
+    async fn session(
+        config: &Config,
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<GDriveSession, Error> {
+        if let Ok(sessions) = GDRIVE_SESSIONS.try_with(Clone::clone) {
+            let mut sessions = sessions.0.lock().await;
+            if let Some(session) = sessions.get(servicesession) {
+                return Ok(session.clone());
+            }
+            let gdrive = Self::gdrive_instance(config, servicesession, pool).await?;
+            let session = GDriveSession::new(gdrive, true);
+            sessions.insert(servicesession.into(), session.clone());
+            Ok(session)
+        } else {
+            let gdrive = Self::gdrive_instance(config, servicesession, pool).await?;
+            Ok(GDriveSession::new(gdrive, false))
+        }
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn new(
@@ -53,19 +274,8 @@ impl FileListGDrive {
             pool.clone(),
         );
 
-        let gdrive = GDriveInstance::new(
-            &config.gdrive_token_path,
-            &config.gdrive_secret_file,
-            flist.servicesession.as_str(),
-        )
-        .await?;
-
-        Ok(Self {
-            flist,
-            gdrive,
-            directory_map: Arc::new(RwLock::new(HashMap::new())),
-            root_directory: Arc::new(RwLock::new(None)),
-        })
+        let session = Self::session(config, flist.servicesession.as_str(), pool).await?;
+        Ok(Self::from_session(flist, session))
     }
 
     /// # Errors
@@ -92,21 +302,8 @@ impl FileListGDrive {
                 pool.clone(),
             );
 
-            let config = config.clone();
-            let servicesession = flist.servicesession.as_ref();
-            let gdrive = GDriveInstance::new(
-                &config.gdrive_token_path,
-                &config.gdrive_secret_file,
-                servicesession,
-            )
-            .await?;
-
-            Ok(Self {
-                flist,
-                gdrive,
-                directory_map: Arc::new(RwLock::new(HashMap::new())),
-                root_directory: Arc::new(RwLock::new(None)),
-            })
+            let session = Self::session(config, flist.servicesession.as_str(), pool).await?;
+            Ok(Self::from_session(flist, session))
         } else {
             Err(format_err!("Wrong scheme"))
         }
@@ -115,6 +312,12 @@ impl FileListGDrive {
     /// # Errors
     /// Return error if db query fails
     pub async fn set_directory_map(&self, use_cache: bool) -> Result<(), Error> {
+        if let Some(shared) = &self.shared {
+            // kept current by `get_or_create_directory`
+            if shared.directory_map_loaded.load(Ordering::SeqCst) {
+                return Ok(());
+            }
+        }
         let (dmap, root_dir) = if use_cache {
             let dlist = self.load_directory_info_cache().await?;
             self.get_directory_map_cache(dlist)
@@ -127,18 +330,116 @@ impl FileListGDrive {
         }
         *self.directory_map.write().await = dmap;
         *self.root_directory.write().await = root_dir;
+        if let Some(shared) = &self.shared {
+            if !use_cache {
+                shared.directory_map_loaded.store(true, Ordering::SeqCst);
+            }
+        }
         Ok(())
     }
 
+    /// Upload whose session uri is kept in `resumable_transfer` until it
+    /// completes, so that a run killed part way through continues after the
+    /// bytes already received
+    async fn resumable_upload(
+        &self,
+        local_url: &Url,
+        dst_url: &Url,
+        parent_id: &str,
+    ) -> Result<File, Error> {
+        let pool = self.get_pool();
+        let local_path = local_url
+            .to_file_path()
+            .map_err(|e| format_err!("failure {e:?}"))?;
+        let metadata = metadata(&local_path)?;
+        let mut resumed = None;
+        if let Some(transfer) = ResumableTransfer::get(local_url, dst_url, pool).await? {
+            if transfer.is_current(&metadata) {
+                match self
+                    .gdrive
+                    .upload_status(&transfer.session_id, metadata.len())
+                    .await
+                {
+                    Ok(UploadStatus::Expired) => debug!("upload session of {dst_url} expired"),
+                    Ok(_) => resumed = Some(transfer),
+                    Err(e) => warn!("can't resume upload of {dst_url} {e}"),
+                }
+            }
+        }
+        let transfer = if let Some(transfer) = resumed {
+            info!("resuming upload of {dst_url}");
+            transfer
+        } else {
+            let session_uri = self
+                .gdrive
+                .create_upload_session(local_url, parent_id)
+                .await?;
+            let transfer = ResumableTransfer::new(
+                local_url,
+                dst_url,
+                FileService::GDrive.to_str(),
+                &session_uri,
+                &metadata,
+            );
+            transfer.insert(pool).await?;
+            transfer
+        };
+        let file = self
+            .gdrive
+            .resume_upload(&transfer.session_id, local_url)
+            .await?;
+        transfer.delete(pool).await?;
+        Ok(file)
+    }
+
+    /// Id of the folder `directory`, missing folders along the way are
+    /// created and added to both the directory map and its db cache so that
+    /// later uploads (and `set_directory_map(true)`) see them
+    /// # Errors
+    /// Return error if api call or db query fails
+    pub async fn get_or_create_directory(&self, directory: &Url) -> Result<StackString, Error> {
+        let _guard = self.create_lock.lock().await;
+        let (parent_id, missing) = {
+            let directory_map = self.directory_map.read().await;
+            let dnamemap = GDriveInstance::get_directory_name_map(&directory_map);
+            GDriveInstance::get_missing_directories(directory, &dnamemap)
+        };
+        let mut parent_id = parent_id.ok_or_else(|| format_err!("No parent id!"))?;
+        for name in missing {
+            debug!("create folder {name} in {parent_id}");
+            let folder = self.gdrive.create_folder(&name, &parent_id).await?;
+            let directory_id: StackString = folder
+                .id
+                .ok_or_else(|| format_err!("No id for new folder {name}"))?
+                .into();
+            let dinfo = DirectoryInfo {
+                directory_id: directory_id.clone(),
+                directory_name: name,
+                parentid: Some(parent_id),
+            };
+            let created: HashMap<_, _> = [(directory_id.clone(), dinfo.clone())].into();
+            self.cache_directory_map(&created, &None).await?;
+            self.directory_map
+                .write()
+                .await
+                .insert(directory_id.clone(), dinfo);
+            parent_id = directory_id;
+        }
+        Ok(parent_id)
+    }
+
     #[must_use]
     pub fn max_keys(mut self, max_keys: usize) -> Self {
         self.gdrive = self.gdrive.with_max_keys(max_keys);
         self
     }
 
+    /// Entries of the listed files, those recorded as duplicates are moved to
+    /// their disambiguated path
     fn convert_gdriveinfo_to_file_info(
         &self,
         flist: &[GDriveInfo],
+        duplicates: &HashMap<StackString, GDriveDuplicate>,
     ) -> Result<Vec<FileInfo>, Error> {
         let flist: Result<Vec<_>, Error> = flist
             .par_iter()
@@ -155,38 +456,335 @@ impl FileListGDrive {
             .map(|f| {
                 let mut inner = f.inner().clone();
                 inner.servicesession = self.get_servicesession().clone();
-                FileInfo::from_inner(inner)
+                if let Some(duplicate) = duplicates
+                    .get(inner.serviceid.as_str())
+                    .filter(|d| d.urlname.as_str() == inner.urlname.as_str())
+                {
+                    let filename = url_filename(&duplicate.disambiguated_urlname)?;
+                    inner.urlname = duplicate.disambiguated_urlname.parse()?;
+                    inner.filepath = inner.filepath.0.with_file_name(filename.as_str()).into();
+                    inner.filename = filename;
+                }
+                Ok(FileInfo::from_inner(inner))
             })
-            .collect();
+            .collect::<Result<_, Error>>()?;
         Ok(flist)
     }
 
-    async fn get_all_files(&self) -> Result<Vec<FileInfo>, Error> {
-        let directory_map = self.directory_map.read().await;
-        let flist: Vec<_> = self.gdrive.get_all_file_info(false, &directory_map).await?;
+    /// Give every file but one sharing its path with another file its own
+    /// path, and their plain name back to files that don't share it anymore,
+    /// see `plan_duplicates`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn disambiguate_duplicates(&self) -> Result<usize, Error> {
+        let pool = self.get_pool();
+        let servicesession = self.get_servicesession().as_str();
+        let servicetype = self.get_servicetype().to_str();
+        let existing = GDriveDuplicate::get_map(servicesession, pool).await?;
+        let shared = GDriveDuplicate::find_shared_paths(servicesession, pool).await?;
+        let plan = plan_duplicates(servicesession, &shared, &existing)?;
+        for d in &plan.removed {
+            FileInfoCache::rename_by_id(
+                &d.gdriveid,
+                servicesession,
+                servicetype,
+                &d.disambiguated_urlname,
+                &d.urlname,
+                &url_filename(&d.urlname)?,
+                pool,
+            )
+            .await?;
+            d.delete(pool).await?;
+        }
+        for d in &plan.added {
+            warn!(
+                "{} shares its path with another file, cached as {}",
+                d.urlname, d.disambiguated_urlname
+            );
+            FileInfoCache::rename_by_id(
+                &d.gdriveid,
+                servicesession,
+                servicetype,
+                &d.urlname,
+                &d.disambiguated_urlname,
+                &url_filename(&d.disambiguated_urlname)?,
+                pool,
+            )
+            .await?;
+            d.upsert(pool).await?;
+        }
+        Ok(plan.added.len())
+    }
 
-        let flist = self.convert_gdriveinfo_to_file_info(&flist)?;
+    /// Instance with the metadata cache seeded from the ids recorded as
+    /// missing in earlier runs
+    async fn gdrive_instance(
+        config: &Config,
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<GDriveInstance, Error> {
+        let gdrive_config = config.gdrive_config(servicesession);
+        // the google api client can't go through a proxy, only the ca bundle
+        // applies
+        let proxy = config.proxy_config("gdrive");
+        if let Some(url) = &proxy.url {
+            warn!("gdrive doesn't support proxies, connecting without {url}");
+        }
+        let gdrive = GDriveInstance::new_with_ca_bundle(
+            &gdrive_config.token_path,
+            &gdrive_config.secret_file,
+            servicesession,
+            proxy.ca_bundle.as_deref(),
+        )
+        .await?
+        .with_metadata_ttl(Duration::from_secs(gdrive_config.metadata_ttl));
+        for gdriveid in GDriveMissingMetadata::get_recent(
+            servicesession,
+            config.gdrive_missing_metadata_ttl,
+            pool,
+        )
+        .await?
+        {
+            gdrive.metadata_cache().insert_missing(&gdriveid);
+        }
+        Ok(gdrive)
+    }
 
-        Ok(flist)
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn save_missing_metadata(&self) -> Result<(), Error> {
+        let servicesession = self.get_servicesession().as_str();
+        for gdriveid in self.gdrive.metadata_cache().missing_ids() {
+            GDriveMissingMetadata::insert(servicesession, &gdriveid, self.get_pool()).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop excluded files from a listing, files found to be unexportable
+    /// are recorded as exclusions the first time they're seen.  Exported
+    /// files have their export type and local extension recorded.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn filter_exclusions(
+        &self,
+        files: Vec<File>,
+        excluded: &mut HashSet<StackString>,
+    ) -> Result<Vec<File>, Error> {
+        let servicesession = self.get_servicesession().as_str();
+        let mut result = Vec::with_capacity(files.len());
+        let mut exports = Vec::new();
+        for f in files {
+            let gdriveid = match f.id.as_deref() {
+                Some(gdriveid) => gdriveid,
+                None => {
+                    result.push(f);
+                    continue;
+                }
+            };
+            if excluded.contains(gdriveid) {
+                continue;
+            }
+            if GDriveInstance::is_unexportable(&f.mime_type)
+                && f.mime_type.as_deref() != Some(FOLDER_MIME_TYPE)
+            {
+                debug!("exclude unexportable {gdriveid} {:?}", f.name);
+                GDriveExclusion::new(
+                    servicesession,
+                    gdriveid,
+                    f.name.as_deref().unwrap_or(""),
+                    f.mime_type.as_deref(),
+                    ExclusionReason::Unexportable,
+                )
+                .upsert(self.get_pool())
+                .await?;
+                excluded.insert(gdriveid.into());
+                continue;
+            }
+            if let (Some(mime_type), Some((export_mime_type, extension)), Some(filename)) = (
+                f.mime_type.as_deref(),
+                GDriveInstance::export_format(&f.mime_type),
+                GDriveInstance::export_name(&f),
+            ) {
+                exports.push(GDriveExport {
+                    servicesession: servicesession.into(),
+                    gdriveid: gdriveid.into(),
+                    filename,
+                    mime_type: mime_type.into(),
+                    export_mime_type: export_mime_type.into(),
+                    extension: extension.into(),
+                    last_modified: DateTimeWrapper::now(),
+                    source_modified: None,
+                    export_md5sum: None,
+                });
+            }
+            result.push(f);
+        }
+        GDriveExport::upsert_all(&exports, self.get_pool()).await?;
+        Ok(result)
+    }
+
+    /// Full listing, flushed into the cache page by page.  An
+    /// `IndexProgress` marker records the last page token flushed along with
+    /// the change token taken before the listing began, so that an
+    /// interrupted listing resumes instead of starting over.
+    async fn index_all_files(&self) -> Result<(usize, usize), Error> {
+        let pool = self.get_pool();
+        let config = self.get_config();
+        let servicetype = self.get_servicetype().to_str();
+        let servicesession = self.get_servicesession().as_str();
+        let baseurl = self.get_baseurl().as_str();
+
+        let mut progress = if let Some(progress) =
+            IndexProgress::get(servicetype, servicesession, baseurl, pool)
+                .await?
+                .filter(|p| p.change_token.is_some())
+        {
+            info!(
+                "resuming listing of {baseurl}, {} already flushed",
+                progress.number_flushed
+            );
+            progress
+        } else {
+            self.clear_file_list().await?;
+            let mut progress = IndexProgress::new(servicetype, servicesession, baseurl);
+            let start_page_token = self.gdrive.get_start_page_token().await?;
+            progress.change_token = Some(StackString::from_display(start_page_token));
+            progress.flush(None, 0, pool).await?;
+            progress
+        };
+        let start_page_token: usize = progress
+            .change_token
+            .as_ref()
+            .ok_or_else(|| format_err!("No change token"))?
+            .parse()?;
+
+        let mut excluded = GDriveExclusion::get_ids(servicesession, pool).await?;
+        let duplicates = GDriveDuplicate::get_map(servicesession, pool).await?;
+        let max_keys = self.gdrive.get_max_keys();
+        let mut page_token = progress.continuation_token.clone();
+        let mut number_updated = 0;
+        let mut number_listed = 0;
+        let mut pending = 0;
+        loop {
+            let (files, next_page_token) =
+                match self.gdrive.get_files_page(page_token.as_ref(), false).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        // checkpoint so that the next run picks up at this page
+                        progress
+                            .flush(page_token.as_ref().map(StackString::as_str), pending, pool)
+                            .await?;
+                        return Err(e);
+                    }
+                };
+            let number_files = files.len();
+            ProgressChannel::global().scanned(number_files);
+            let files = self.filter_exclusions(files, &mut excluded).await?;
+            let flist = {
+                let directory_map = self.directory_map.read().await;
+                self.gdrive
+                    .convert_file_list_to_gdrive_info(&files, &directory_map)
+                    .await?
+            };
+            for f in self.convert_gdriveinfo_to_file_info(&flist, &duplicates)? {
+                let info: FileInfoCache = f.into();
+                number_updated += info.upsert(pool).await?;
+            }
+            pending += number_files;
+            number_listed += number_files;
+            page_token = next_page_token;
+            if page_token.is_none() || max_keys.map_or(false, |n| number_listed >= n) {
+                break;
+            }
+            if pending >= config.index_flush_size {
+                progress
+                    .flush(page_token.as_ref().map(StackString::as_str), pending, pool)
+                    .await?;
+                pending = 0;
+            }
+            if config.list_page_delay_ms > 0 {
+                sleep(Duration::from_millis(config.list_page_delay_ms)).await;
+            }
+        }
+        progress.finish(pool).await?;
+        Ok((start_page_token, number_updated))
+    }
+
+    /// Whether to index from the change list rather than listing every file
+    fn has_change_token(&self) -> bool {
+        match &self.shared {
+            Some(shared) => shared.initial_token.is_some(),
+            None => self.gdrive.start_page_token.load().is_some(),
+        }
+    }
+
+    /// New start page token and the changes since the current one, within a
+    /// shared session they're only fetched by the first list to ask
+    async fn get_changes(&self) -> Result<(usize, Arc<Vec<Change>>), Error> {
+        let shared = match &self.shared {
+            Some(shared) => shared,
+            None => {
+                let start_page_token = self.gdrive.get_start_page_token().await?;
+                let chlist = self.gdrive.get_all_changes().await?;
+                return Ok((start_page_token, Arc::new(chlist)));
+            }
+        };
+        let mut changes = shared.changes.lock().await;
+        if let Some((start_page_token, chlist)) = changes.as_ref() {
+            return Ok((*start_page_token, chlist.clone()));
+        }
+        let start_page_token = self.gdrive.get_start_page_token().await?;
+        let chlist = Arc::new(self.gdrive.get_all_changes().await?);
+        *changes = Some((start_page_token, chlist.clone()));
+        Ok((start_page_token, chlist))
     }
 
-    async fn get_all_changes(&self) -> Result<(Vec<StackString>, Vec<FileInfo>), Error> {
-        let chlist: Vec<_> = self.gdrive.get_all_changes().await?;
-        let delete_list = chlist
+    /// New start page token, ids of removed and of trashed files and the
+    /// changed files
+    async fn get_all_changes(
+        &self,
+    ) -> Result<(usize, Vec<StackString>, Vec<StackString>, Vec<FileInfo>), Error> {
+        let (start_page_token, chlist) = self.get_changes().await?;
+        let (delete_list, mut trash_list, mut flist) = ChangeApplication::split_changes(&chlist);
+        // shortcuts are cached with the metadata of their target, follow
+        // the changes of their targets
+        let target_ids: Vec<StackString> = flist
             .iter()
-            .filter_map(|ch| match ch.file {
-                Some(_) => None,
-                None => ch.file_id.clone().map(Into::into),
-            })
+            .filter(|f| GDriveInstance::shortcut_target(f).is_none())
+            .filter_map(|f| f.id.as_deref().map(Into::into))
+            .collect();
+        let changed_ids: HashSet<_> = flist.iter().filter_map(|f| f.id.clone()).collect();
+        for shortcut in self.gdrive.get_shortcuts_to(&target_ids).await? {
+            if shortcut
+                .id
+                .as_ref()
+                .map_or(true, |id| changed_ids.contains(id))
+            {
+                continue;
+            }
+            flist.push(shortcut);
+        }
+        let gone_ids: Vec<StackString> = delete_list
+            .iter()
+            .chain(trash_list.iter())
+            .cloned()
             .collect();
-        let flist: Vec<_> = chlist.into_iter().filter_map(|ch| ch.file).collect();
+        for shortcut in self.gdrive.get_shortcuts_to(&gone_ids).await? {
+            if let Some(id) = shortcut.id {
+                trash_list.push(id.into());
+            }
+        }
+        let servicesession = self.get_servicesession().as_str();
+        let mut excluded = GDriveExclusion::get_ids(servicesession, self.get_pool()).await?;
+        let duplicates = GDriveDuplicate::get_map(servicesession, self.get_pool()).await?;
+        let flist = self.filter_exclusions(flist, &mut excluded).await?;
         let directory_map = self.directory_map.read().await;
         let flist = self
             .gdrive
             .convert_file_list_to_gdrive_info(&flist, &directory_map)
             .await?;
-        let flist = self.convert_gdriveinfo_to_file_info(&flist)?;
-        Ok((delete_list, flist))
+        let flist = self.convert_gdriveinfo_to_file_info(&flist, &duplicates)?;
+        Ok((start_page_token, delete_list, trash_list, flist))
     }
 }
 
@@ -215,58 +813,69 @@ impl FileListTrait for FileListGDrive {
         &self.flist.pool
     }
 
+    async fn probe(&self) -> Result<(), Error> {
+        self.gdrive.probe().await
+    }
+
     async fn update_file_cache(&self) -> Result<usize, Error> {
         let mut number_updated = 0;
         self.set_directory_map(false).await?;
-        let start_page_token = self.gdrive.get_start_page_token().await?;
 
-        let (dlist, flist) = if self.gdrive.start_page_token.load().is_some() {
-            self.get_all_changes().await?
-        } else {
-            {
-                self.clear_file_list().await?;
-                (Vec::new(), self.get_all_files().await?)
-            }
-        };
+        let start_page_token = if self.has_change_token() {
+            let (start_page_token, dlist, tlist, flist) = self.get_all_changes().await?;
 
-        debug!("delete {} insert {}", dlist.len(), flist.len());
+            let pool = self.get_pool();
 
-        let pool = self.get_pool();
-
-        for dfid in &dlist {
-            FileInfoCache::delete_by_id(
-                dfid,
+            let cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
                 self.get_servicesession().as_str(),
                 self.get_servicetype().to_str(),
                 pool,
+                false,
             )
+            .await?
+            .map_ok(|f| (f.urlname.clone(), f))
+            .try_collect()
             .await?;
-        }
+            debug!("expected {}", cached_urls.len());
 
-        let cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
-            self.get_servicesession().as_str(),
-            self.get_servicetype().to_str(),
-            pool,
-            false,
-        )
-        .await?
-        .map_ok(|f| (f.urlname.clone(), f))
-        .try_collect()
-        .await?;
-        debug!("expected {}", cached_urls.len());
+            ProgressChannel::global().scanned(dlist.len() + tlist.len() + flist.len());
+            let changes = ChangeApplication::new(dlist, tlist, flist, &cached_urls);
+            debug!(
+                "delete {} trash {} insert {}",
+                changes.delete_ids.len(),
+                changes.trash_ids.len(),
+                changes.upserts.len()
+            );
 
-        for f in flist {
-            let info: FileInfoCache = f.into();
-            if let Some(existing) = cached_urls.get(&info.urlname) {
-                if existing.deleted_at.is_none()
-                    && existing.filestat_st_size == info.filestat_st_size
-                {
-                    continue;
-                }
+            for dfid in &changes.delete_ids {
+                FileInfoCache::delete_by_id(
+                    dfid,
+                    self.get_servicesession().as_str(),
+                    self.get_servicetype().to_str(),
+                    pool,
+                )
+                .await?;
             }
-            number_updated += info.upsert(pool).await?;
-        }
+            for tfid in &changes.trash_ids {
+                FileInfoCache::tombstone_by_id(
+                    tfid,
+                    self.get_servicesession().as_str(),
+                    self.get_servicetype().to_str(),
+                    pool,
+                )
+                .await?;
+            }
+            for info in changes.upserts {
+                number_updated += info.upsert(pool).await?;
+            }
+            start_page_token
+        } else {
+            let (start_page_token, updated) = self.index_all_files().await?;
+            number_updated += updated;
+            start_page_token
+        };
 
+        self.disambiguate_duplicates().await?;
         self.gdrive.start_page_token.store(Some(start_page_token));
 
         let ext = self
@@ -281,11 +890,16 @@ impl FileListTrait for FileListGDrive {
             .with_extension(format_sstr!("{ext}.new"));
 
         self.gdrive.store_start_page_token(&start_page_path).await?;
+        self.save_missing_metadata().await?;
 
         Ok(number_updated)
     }
 
-    async fn print_list(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
         self.set_directory_map(false).await?;
         let directory_map = self.directory_map.read().await;
         let dnamemap = GDriveInstance::get_directory_name_map(&directory_map);
@@ -308,13 +922,18 @@ impl FileListTrait for FileListGDrive {
                     let gdrive = gdrive.clone();
                     let directory_map = directory_map.clone();
                     async move {
+                        if window.is_done() {
+                            return Ok(false);
+                        }
                         if let Ok(finfo) = GDriveInfo::from_object(&i, &gdrive, &directory_map)
                             .await
                             .and_then(FileInfoGDrive::from_gdriveinfo)
                         {
-                            stdout.send(format_sstr!("{}\n", finfo.get_finfo().urlname));
+                            if window.admit() {
+                                stdout.send(format_sstr!("{}\n", finfo.get_finfo().urlname));
+                            }
                         }
-                        Ok(())
+                        Ok(!window.is_done())
                     }
                 })
                 .await
@@ -340,16 +959,73 @@ impl FileListTrait for FileListGDrive {
                 create_dir_all(parent_dir)?;
             }
             let gdriveid = finfo0.serviceid.as_str();
-            let gfile = self.gdrive.get_file_metadata(gdriveid).await?;
+            let servicesession = self.get_servicesession().as_str();
+            let pool = self.get_pool();
+            if GDriveExclusion::is_excluded(servicesession, gdriveid, pool).await? {
+                debug!("excluded {gdriveid}");
+                self.remove_by_id(gdriveid).await?;
+                return Ok(());
+            }
+            let mut gfile = self.gdrive.get_file_metadata(gdriveid).await?;
+            // a shortcut is downloaded from its target
+            let download_id: StackString = match GDriveInstance::shortcut_target(&gfile) {
+                Some(target_id) => {
+                    debug!("shortcut {gdriveid} to {target_id}");
+                    let target_id: StackString = target_id.into();
+                    gfile = self.gdrive.get_file_metadata(&target_id).await?;
+                    target_id
+                }
+                None => gdriveid.into(),
+            };
             debug!("{:?}", gfile.mime_type);
             if GDriveInstance::is_unexportable(&gfile.mime_type) {
                 debug!("unexportable");
+                GDriveExclusion::new(
+                    servicesession,
+                    gdriveid,
+                    &finfo0.filename,
+                    gfile.mime_type.as_deref(),
+                    ExclusionReason::Unexportable,
+                )
+                .upsert(pool)
+                .await?;
                 self.remove_by_id(gdriveid).await?;
                 debug!("removed from database");
                 return Ok(());
             }
+            // exports have no checksum to compare, they're redone only when
+            // the file changed on the drive or the local copy changed
+            if let Some((export_mime_type, _)) = GDriveInstance::export_format(&gfile.mime_type) {
+                let export = GDriveExport::get_by_id(servicesession, gdriveid, pool).await?;
+                if let Some(export) = &export {
+                    let local = FileInfoLocal::from_url(&finfo1.urlname)?;
+                    let local_md5sum = spawn_blocking(move || local.get_md5()).await?;
+                    if export.is_current(
+                        gfile.modified_time,
+                        local_md5sum.as_deref().map(StackString::as_str),
+                    ) {
+                        debug!("export of {gdriveid} unchanged");
+                        return Ok(());
+                    }
+                }
+                let md5sum = self
+                    .gdrive
+                    .export(&download_id, local_path, export_mime_type)
+                    .await?;
+                if export.is_some() {
+                    GDriveExport::set_exported(
+                        servicesession,
+                        gdriveid,
+                        gfile.modified_time,
+                        &md5sum,
+                        pool,
+                    )
+                    .await?;
+                }
+                return Ok(());
+            }
             self.gdrive
-                .download(gdriveid, local_path, &gfile.mime_type)
+                .download(&download_id, local_path, &gfile.mime_type)
                 .await
         } else {
             Err(format_err!(
@@ -373,12 +1049,10 @@ impl FileListTrait for FileListGDrive {
             let local_url =
                 Url::from_file_path(local_file).map_err(|e| format_err!("failure {e:?}"))?;
 
-            let remote_url = finfo1.urlname.clone();
-            let directory_map = self.directory_map.read().await;
-            let dnamemap = GDriveInstance::get_directory_name_map(&directory_map);
-            let parent_id = GDriveInstance::get_parent_id(&remote_url, &dnamemap)?
-                .ok_or_else(|| format_err!("No parent id!"))?;
-            self.gdrive.upload(&local_url, &parent_id).await?;
+            let remote_directory = finfo1.urlname.join(".")?;
+            let parent_id = self.get_or_create_directory(&remote_directory).await?;
+            self.resumable_upload(&local_url, &finfo1.urlname, &parent_id)
+                .await?;
             Ok(())
         } else {
             Err(format_err!(
@@ -389,6 +1063,16 @@ impl FileListTrait for FileListGDrive {
         }
     }
 
+    async fn create_directory(&self, directory: &Url) -> Result<(), Error> {
+        self.set_directory_map(true).await?;
+        self.get_or_create_directory(directory).await?;
+        Ok(())
+    }
+
+    fn supports_move(&self) -> bool {
+        true
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
@@ -424,27 +1108,248 @@ impl FileListTrait for FileListGDrive {
             Err(format_err!("Wrong service type"))
         }
     }
+
+    async fn trash(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo().clone();
+        self.set_directory_map(true).await?;
+        if finfo.servicetype == FileService::GDrive {
+            self.gdrive.move_to_trash(finfo.serviceid.as_str()).await
+        } else {
+            Err(format_err!("Wrong service type"))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
     use log::debug;
-    use stack_string::format_sstr;
+    use stack_string::{format_sstr, StackString};
     use std::{
         collections::HashMap,
         convert::TryInto,
         path::{Path, PathBuf},
     };
+    use time::macros::datetime;
     use tokio::fs::remove_file;
 
-    use gdrive_lib::gdrive_instance::GDriveInstance;
+    use gdrive_lib::{
+        drive_v3_types::{Change, File},
+        gdrive_instance::GDriveInstance,
+    };
 
     use crate::{
-        config::Config, file_info::FileInfo, file_list::FileListTrait,
-        file_list_gdrive::FileListGDrive, pgpool::PgPool,
+        config::Config,
+        file_info::{fixture_info, FileInfo, FileStat},
+        file_list::FileListTrait,
+        file_list_gdrive::{ChangeApplication, FileListGDrive},
+        file_service::FileService,
+        models::FileInfoCache,
+        pgpool::PgPool,
     };
 
+    /// A file as the fixtures see it: drive id, path under `My Drive`, size
+    /// and md5sum
+    type FixtureFile = (&'static str, &'static str, i64, &'static str);
+
+    /// A synthetic change feed applied to a cache, with the ids expected to be
+    /// removed and the paths expected to be upserted
+    struct ChangeFixture {
+        name: &'static str,
+        cached: Vec<FixtureFile>,
+        changes: Vec<Change>,
+        expected_deletes: Vec<&'static str>,
+        expected_trashes: Vec<&'static str>,
+        expected_upserts: Vec<&'static str>,
+    }
+
+    fn fixture_file((id, path, size, md5sum): FixtureFile) -> FileInfo {
+        fixture_info(
+            &format_sstr!("gdrive://ddboline@gmail.com/My%20Drive/{path}"),
+            path,
+            FileStat::new(datetime!(2024-01-01 00:00:00 UTC), size),
+            Some(md5sum),
+            id,
+            FileService::GDrive,
+            "ddboline@gmail.com",
+        )
+        .unwrap()
+    }
+
+    /// File created or modified, its `name` carries the whole path so that
+    /// the fixtures don't need a directory map
+    fn changed((id, path, size, md5sum): FixtureFile) -> Change {
+        Change {
+            file_id: Some(id.into()),
+            file: Some(File {
+                id: Some(id.into()),
+                name: Some(path.into()),
+                size: Some(size.to_string()),
+                md5_checksum: Some(md5sum.into()),
+                ..File::default()
+            }),
+            ..Change::default()
+        }
+    }
+
+    fn removed(id: &str) -> Change {
+        Change {
+            file_id: Some(id.into()),
+            removed: Some(true),
+            ..Change::default()
+        }
+    }
+
+    fn trashed(file: FixtureFile) -> Change {
+        let mut change = changed(file);
+        if let Some(f) = change.file.as_mut() {
+            f.trashed = Some(true);
+        }
+        change
+    }
+
+    fn apply_fixture(fixture: &ChangeFixture) -> ChangeApplication {
+        let cached: HashMap<StackString, FileInfoCache> = fixture
+            .cached
+            .iter()
+            .map(|f| {
+                let info: FileInfoCache = fixture_file(*f).into();
+                (info.urlname.clone(), info)
+            })
+            .collect();
+        let (removed, trashed, changed) = ChangeApplication::split_changes(&fixture.changes);
+        let flist = changed
+            .iter()
+            .map(|f| {
+                fixture_file((
+                    f.id.as_deref().unwrap(),
+                    f.name.as_deref().unwrap(),
+                    f.size.as_deref().unwrap().parse().unwrap(),
+                    f.md5_checksum.as_deref().unwrap(),
+                ))
+            })
+            .collect();
+        ChangeApplication::new(removed, trashed, flist, &cached)
+    }
+
+    const A: FixtureFile = ("id_a", "a.txt", 10, "0cc175b9c0f1b6a831c399e269772661");
+    const A_MODIFIED: FixtureFile = ("id_a", "a.txt", 12, "187ef4436122d1cc2f40dc2b92f0eba0");
+    const A_SAME_SIZE: FixtureFile = ("id_a", "a.txt", 10, "900150983cd24fb0d6963f7d28e17f72");
+    const A_MOVED: FixtureFile = ("id_a", "docs/a.txt", 10, "0cc175b9c0f1b6a831c399e269772661");
+    const B: FixtureFile = ("id_b", "b.txt", 20, "92eb5ffee6ae2fec3ad71c777531578f");
+
+    fn change_fixtures() -> Vec<ChangeFixture> {
+        vec![
+            ChangeFixture {
+                name: "create",
+                cached: vec![A],
+                changes: vec![changed(B)],
+                expected_deletes: vec![],
+                expected_trashes: vec![],
+                expected_upserts: vec!["b.txt"],
+            },
+            ChangeFixture {
+                name: "unchanged",
+                cached: vec![A, B],
+                changes: vec![changed(A)],
+                expected_deletes: vec![],
+                expected_trashes: vec![],
+                expected_upserts: vec![],
+            },
+            ChangeFixture {
+                name: "modify",
+                cached: vec![A, B],
+                changes: vec![changed(A_MODIFIED)],
+                expected_deletes: vec![],
+                expected_trashes: vec![],
+                expected_upserts: vec!["a.txt"],
+            },
+            ChangeFixture {
+                name: "modify same size",
+                cached: vec![A],
+                changes: vec![changed(A_SAME_SIZE)],
+                expected_deletes: vec![],
+                expected_trashes: vec![],
+                expected_upserts: vec!["a.txt"],
+            },
+            ChangeFixture {
+                name: "move",
+                cached: vec![A, B],
+                changes: vec![changed(A_MOVED)],
+                expected_deletes: vec!["id_a"],
+                expected_trashes: vec![],
+                expected_upserts: vec!["docs/a.txt"],
+            },
+            ChangeFixture {
+                name: "delete",
+                cached: vec![A, B],
+                changes: vec![removed("id_b"), removed("id_b")],
+                expected_deletes: vec!["id_b"],
+                expected_trashes: vec![],
+                expected_upserts: vec![],
+            },
+            ChangeFixture {
+                name: "delete and recreate",
+                cached: vec![A],
+                changes: vec![removed("id_a"), changed(A)],
+                expected_deletes: vec!["id_a"],
+                expected_trashes: vec![],
+                expected_upserts: vec!["a.txt"],
+            },
+            ChangeFixture {
+                // trashed files still come with their metadata, their entries
+                // are tombstoned rather than updated
+                name: "trash",
+                cached: vec![A, B],
+                changes: vec![trashed(B)],
+                expected_deletes: vec![],
+                expected_trashes: vec!["id_b"],
+                expected_upserts: vec![],
+            },
+            ChangeFixture {
+                name: "trash uncached",
+                cached: vec![A],
+                changes: vec![trashed(B)],
+                expected_deletes: vec![],
+                expected_trashes: vec![],
+                expected_upserts: vec![],
+            },
+            ChangeFixture {
+                name: "trash then remove",
+                cached: vec![A, B],
+                changes: vec![removed("id_b"), trashed(B)],
+                expected_deletes: vec!["id_b"],
+                expected_trashes: vec![],
+                expected_upserts: vec![],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_change_application_fixtures() {
+        for fixture in change_fixtures() {
+            let application = apply_fixture(&fixture);
+            let deletes: Vec<_> = application
+                .delete_ids
+                .iter()
+                .map(StackString::as_str)
+                .collect();
+            assert_eq!(deletes, fixture.expected_deletes, "{}", fixture.name);
+            let trashes: Vec<_> = application
+                .trash_ids
+                .iter()
+                .map(StackString::as_str)
+                .collect();
+            assert_eq!(trashes, fixture.expected_trashes, "{}", fixture.name);
+            let upserts: Vec<_> = application
+                .upserts
+                .iter()
+                .map(|f| f.filepath.as_str())
+                .collect();
+            assert_eq!(upserts, fixture.expected_upserts, "{}", fixture.name);
+        }
+    }
+
     struct TempStartPageToken {
         new: PathBuf,
     }
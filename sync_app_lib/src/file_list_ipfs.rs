@@ -0,0 +1,246 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use log::{debug, warn};
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, path::Path};
+use stdout_channel::StdoutChannel;
+use url::Url;
+
+use crate::{
+    config::Config,
+    file_info::{FileInfo, FileInfoTrait, ServiceSession},
+    file_info_ipfs::{ipfs_service, FileInfoIpfs},
+    file_list::{FileList, FileListTrait, ListWindow},
+    file_service::FileService,
+    ipfs_instance::IpfsInstance,
+    models::FileInfoCache,
+    pgpool::PgPool,
+    progress::ProgressChannel,
+};
+
+pub const DEFAULT_IPFS_PORT: u16 = 5001;
+
+/// Publish-only mirror of a local tree into the mutable file system (MFS) of
+/// an ipfs node, urls look like `ipfs://<host>:<api port>/<mfs path>`.
+/// Every file is pinned when it's added, the CID is kept as the serviceid,
+/// and unpinned once no cached entry refers to it any more.
+#[derive(Debug, Clone)]
+pub struct FileListIpfs {
+    pub flist: FileList,
+    pub ipfs: IpfsInstance,
+}
+
+impl FileListIpfs {
+    /// # Errors
+    /// Return error if db query fails
+    pub fn from_url(url: &Url, config: &Config, pool: &PgPool) -> Result<Self, Error> {
+        if url.scheme() == "ipfs" {
+            let basepath = Path::new(url.path()).to_path_buf();
+            let host = url.host_str().ok_or_else(|| format_err!("Parse error"))?;
+            let port = url.port().unwrap_or(DEFAULT_IPFS_PORT);
+            let session = if port == DEFAULT_IPFS_PORT {
+                host.into()
+            } else {
+                format_sstr!("{host}:{port}")
+            };
+            let flist = FileList::new(
+                url.clone(),
+                basepath,
+                config.clone(),
+                ipfs_service()?,
+                session.parse()?,
+                pool.clone(),
+            );
+            let ipfs = IpfsInstance::new(host, port)?;
+            Ok(Self { flist, ipfs })
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+
+    fn mfs_path(&self) -> &str {
+        match self.get_baseurl().path() {
+            "" => "/",
+            path => path,
+        }
+    }
+
+    /// Unpin `cid`, no longer published at `urlname`, unless another live
+    /// entry (a copy of the same content) still refers to it
+    async fn unpin_unused(&self, cid: &str, urlname: &str) -> Result<(), Error> {
+        if cid.is_empty() {
+            return Ok(());
+        }
+        let shared = FileInfoCache::count_by_id(
+            cid,
+            self.get_servicesession().as_str(),
+            self.get_servicetype().to_str(),
+            urlname,
+            self.get_pool(),
+        )
+        .await?;
+        if shared > 0 {
+            debug!("{cid} is still used by {shared} entries, keep the pin");
+            return Ok(());
+        }
+        self.ipfs.pin_rm(cid).await
+    }
+}
+
+#[async_trait]
+impl FileListTrait for FileListIpfs {
+    fn get_baseurl(&self) -> &Url {
+        self.flist.get_baseurl()
+    }
+    fn set_baseurl(&mut self, baseurl: Url) {
+        self.flist.set_baseurl(baseurl);
+    }
+    fn get_basepath(&self) -> &Path {
+        &self.flist.basepath
+    }
+    fn get_servicetype(&self) -> FileService {
+        self.flist.servicetype
+    }
+    fn get_servicesession(&self) -> &ServiceSession {
+        &self.flist.servicesession
+    }
+    fn get_config(&self) -> &Config {
+        &self.flist.config
+    }
+
+    fn get_pool(&self) -> &PgPool {
+        &self.flist.pool
+    }
+
+    async fn probe(&self) -> Result<(), Error> {
+        self.ipfs.probe().await
+    }
+
+    /// Entries whose CID is unchanged keep their cached checksum and mtime,
+    /// entries that are no longer pinned are dropped from the cache so that
+    /// they get published again.
+    async fn update_file_cache(&self) -> Result<usize, Error> {
+        let mut number_updated = 0;
+
+        let pool = self.get_pool();
+        let mut cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
+            self.get_servicesession().as_str(),
+            self.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .map_ok(|f| (f.urlname.clone(), f))
+        .try_collect()
+        .await?;
+        debug!("expected {}", cached_urls.len());
+
+        let entries = self.ipfs.files_ls_recursive(self.mfs_path()).await?;
+        ProgressChannel::global().scanned(entries.len());
+        for (path, entry) in entries {
+            if !self.ipfs.is_pinned(&entry.hash).await? {
+                warn!("{path} {} is not pinned", entry.hash);
+                continue;
+            }
+            let info: FileInfoCache = FileInfoIpfs::from_entry(
+                self.get_baseurl(),
+                &path,
+                &entry,
+                self.get_servicesession().clone(),
+            )?
+            .into_finfo()
+            .into();
+            if let Some(existing) = cached_urls.remove(&info.urlname) {
+                if existing.deleted_at.is_none() && existing.serviceid == info.serviceid {
+                    continue;
+                }
+            }
+            number_updated += info.upsert(pool).await?;
+        }
+        for (_, missing) in cached_urls {
+            if missing.deleted_at.is_some() {
+                continue;
+            }
+            missing.delete(pool).await?;
+        }
+        Ok(number_updated)
+    }
+
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
+        for (path, entry) in self.ipfs.files_ls_recursive(self.mfs_path()).await? {
+            if window.is_done() {
+                break;
+            }
+            if !window.admit() {
+                continue;
+            }
+            let mut url = self.get_baseurl().clone();
+            url.set_path(&path);
+            stdout.send(format_sstr!("{url} {}", entry.hash));
+        }
+        Ok(())
+    }
+
+    async fn copy_from(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        _: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        Err(format_err!(
+            "ipfs is publish only, can't copy {}",
+            finfo0.get_finfo().urlname
+        ))
+    }
+
+    async fn copy_to(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype == FileService::Local && finfo1.servicetype == ipfs_service()? {
+            let local_path = finfo0.filepath.canonicalize()?;
+            let mfs_path = finfo1.urlname.path();
+            let cid = self.ipfs.add(&local_path).await?;
+            let replaced = self.ipfs.files_cp(&cid, mfs_path).await?;
+
+            // Record the CID along with the local checksum and mtime, ipfs
+            // doesn't provide either
+            let mut finfo = finfo1.inner().clone();
+            finfo.md5sum.clone_from(&finfo0.md5sum);
+            finfo.filestat = finfo0.filestat;
+            finfo.serviceid = cid.into();
+            finfo.servicesession = self.get_servicesession().clone();
+            let info: FileInfoCache = (&FileInfo::from_inner(finfo)).into();
+            info.upsert(self.get_pool()).await?;
+            if let Some(replaced) = replaced.filter(|replaced| *replaced != cid) {
+                self.unpin_unused(&replaced, finfo1.urlname.as_str())
+                    .await?;
+            }
+            Ok(())
+        } else {
+            Err(format_err!(
+                "Invalid types {} {}",
+                finfo0.servicetype,
+                finfo1.servicetype
+            ))
+        }
+    }
+
+    async fn delete(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if finfo.servicetype == ipfs_service()? {
+            self.ipfs.files_rm(finfo.urlname.path()).await?;
+            self.unpin_unused(&finfo.serviceid, finfo.urlname.as_str())
+                .await
+        } else {
+            Err(format_err!("Wrong service type"))
+        }
+    }
+}
@@ -1,30 +1,43 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
-use futures::TryStreamExt;
 use log::{debug, error};
-use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use stack_string::StackString;
-use std::{collections::HashMap, path::Path};
+use stack_string::{format_sstr, StackString};
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use stdout_channel::StdoutChannel;
+use time::OffsetDateTime;
 use tokio::{
     fs::{copy, create_dir_all, remove_file, rename},
+    process::Command,
+    sync::Semaphore,
     task::{spawn, spawn_blocking, JoinHandle},
 };
 use url::Url;
-use walkdir::WalkDir;
+use walkdir::{DirEntry, WalkDir};
 
 use crate::{
     config::Config,
-    file_info::{FileInfoTrait, ServiceSession},
+    file_info::{FileInfo, FileInfoTrait, FileStat, ServiceSession},
     file_info_local::FileInfoLocal,
-    file_list::{FileList, FileListTrait},
+    file_list::{CachedEntries, FileList, FileListTrait, ListWindow, CACHED_ENTRIES_BATCH},
     file_service::FileService,
     models::FileInfoCache,
+    ownership::Owner,
     pgpool::PgPool,
+    progress::ProgressChannel,
+    trash::move_to_local_trash,
 };
 
 #[derive(Debug, Clone)]
-pub struct FileListLocal(pub FileList);
+pub struct FileListLocal {
+    pub flist: FileList,
+    /// Read-only snapshot of the base directory, when set files are indexed
+    /// from the snapshot but recorded under their live urls
+    pub snapshot: Option<PathBuf>,
+}
 
 impl FileListLocal {
     /// # Errors
@@ -34,7 +47,7 @@ impl FileListLocal {
         let basestr = basepath.to_string_lossy();
         let baseurl = Url::from_file_path(basepath.clone())
             .map_err(|e| format_err!("Failed to parse url {e:?}"))?;
-        let session = basestr.parse()?;
+        let session = ServiceSession::local(&config.node_id(), &basestr)?;
         let flist = FileList::new(
             baseurl,
             basepath,
@@ -43,7 +56,10 @@ impl FileListLocal {
             session,
             pool.clone(),
         );
-        Ok(Self(flist))
+        Ok(Self {
+            flist,
+            snapshot: None,
+        })
     }
 
     /// # Errors
@@ -53,66 +69,180 @@ impl FileListLocal {
             let path = url
                 .to_file_path()
                 .map_err(|e| format_err!("Parse failure {e:?}"))?;
+            let path = canonical_basepath(&path);
+            let baseurl =
+                Url::from_file_path(&path).map_err(|e| format_err!("Failed to parse url {e:?}"))?;
             let basestr = path.to_string_lossy();
-            let session = basestr.parse()?;
+            let session = ServiceSession::local(&config.node_id(), &basestr)?;
             let flist = FileList::new(
-                url.clone(),
+                baseurl,
                 path,
                 config.clone(),
                 FileService::Local,
                 session,
                 pool.clone(),
             );
-            Ok(Self(flist))
+            Ok(Self {
+                flist,
+                snapshot: None,
+            })
         } else {
             Err(format_err!("Wrong scheme"))
         }
     }
+
+    /// Index from `snapshot`, a point-in-time copy of the base directory
+    /// (e.g. `/data/.zfs/snapshot/nightly/photos` for `/data/photos`)
+    #[must_use]
+    pub fn with_snapshot(mut self, snapshot: &Path) -> Self {
+        self.snapshot = Some(canonical_basepath(snapshot));
+        self
+    }
+
+    /// Compare a batch of listed files against their cache entries, new and
+    /// changed files are hashed (at most `checksum_workers` at a time) and
+    /// upserted before the next batch is listed
+    async fn update_batch(
+        &self,
+        cached: &mut CachedEntries<'_>,
+        batch: impl Iterator<Item = (DirEntry, PathBuf, Url)>,
+        checksum_workers: &Arc<Semaphore>,
+    ) -> Result<usize, Error> {
+        let batch: Vec<_> = batch.collect();
+        if batch.is_empty() {
+            return Ok(0);
+        }
+        ProgressChannel::global().scanned(batch.len());
+        let urls: Vec<StackString> = batch.iter().map(|(_, _, u)| u.as_str().into()).collect();
+        let mut existing_entries = cached.take(&urls).await?;
+        let servicesession = self.get_servicesession();
+        let mut tasks = Vec::new();
+        for (entry, filepath, fileurl) in batch {
+            let metadata = entry.metadata()?;
+            // truncated to milliseconds like the cached mtime
+            let filestat = FileStat::new(metadata.modified()?.into(), metadata.len() as i64);
+            if let Some(existing) = existing_entries.remove(fileurl.as_str()) {
+                if existing.deleted_at.is_none()
+                    && existing.filestat_st_size == filestat.st_size
+                    && existing.filestat_st_mtime == filestat.st_mtime
+                    && (self.snapshot.is_none() || Path::new(&existing.filepath) == filepath)
+                {
+                    continue;
+                }
+            }
+            debug!("not in db {fileurl}");
+            let pool = self.get_pool().clone();
+            let servicesession = servicesession.clone();
+            let checksum_workers = checksum_workers.clone();
+            let task: JoinHandle<Result<usize, Error>> = spawn(async move {
+                let permit = checksum_workers.acquire_owned().await?;
+                let info = spawn_blocking(move || {
+                    FileInfoLocal::from_direntry(
+                        &entry,
+                        Some(servicesession.as_str().into()),
+                        Some(servicesession),
+                    )
+                })
+                .await??;
+                drop(permit);
+
+                let mut info: FileInfoCache = info.into_finfo().into();
+                info.urlname = fileurl.as_str().into();
+                info.upsert(&pool).await
+            });
+            tasks.push(task);
+        }
+        debug!("tasks {}", tasks.len());
+        let mut number_updated = 0;
+        for task in tasks {
+            number_updated += task.await??;
+        }
+        Ok(number_updated)
+    }
+}
+
+/// Live path of `path` inside `snapshot`
+fn live_path(basepath: &Path, snapshot: &Path, path: &Path) -> Option<PathBuf> {
+    path.strip_prefix(snapshot).ok().map(|p| basepath.join(p))
+}
+
+/// Path inside `snapshot` of the live `path`
+fn snapshot_path(basepath: &Path, snapshot: &Path, path: &Path) -> Option<PathBuf> {
+    path.strip_prefix(basepath).ok().map(|p| snapshot.join(p))
+}
+
+/// Resolve symlinks and `.`/`..` components so the same directory always
+/// maps to the same cache session, falling back to a lexical cleanup when the
+/// path doesn't exist yet.
+#[must_use]
+pub fn canonical_basepath(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .unwrap_or_else(|_| path.components().collect())
 }
 
 #[async_trait]
 impl FileListTrait for FileListLocal {
     fn get_baseurl(&self) -> &Url {
-        self.0.get_baseurl()
+        self.flist.get_baseurl()
     }
     fn set_baseurl(&mut self, baseurl: Url) {
-        self.0.set_baseurl(baseurl);
+        self.flist.set_baseurl(baseurl);
     }
 
     fn get_basepath(&self) -> &Path {
-        &self.0.basepath
+        &self.flist.basepath
     }
     fn get_servicetype(&self) -> FileService {
-        self.0.servicetype
+        self.flist.servicetype
     }
     fn get_servicesession(&self) -> &ServiceSession {
-        &self.0.servicesession
+        &self.flist.servicesession
     }
     fn get_config(&self) -> &Config {
-        &self.0.config
+        &self.flist.config
     }
     fn get_pool(&self) -> &PgPool {
-        &self.0.pool
+        &self.flist.pool
+    }
+
+    async fn probe(&self) -> Result<(), Error> {
+        let basepath = self.get_basepath().to_path_buf();
+        if let Some(snapshot) = self.snapshot.clone() {
+            return spawn_blocking(move || {
+                if snapshot.is_dir() {
+                    Ok(())
+                } else {
+                    Err(format_err!("snapshot {} is missing", snapshot.display()))
+                }
+            })
+            .await?;
+        }
+        // a destination that doesn't exist yet is created by the first copy,
+        // as long as its parent is there
+        spawn_blocking(move || match basepath.metadata() {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => match basepath.parent() {
+                Some(parent) if parent.is_dir() => Ok(()),
+                _ => Err(format_err!("{} is not accessible {e}", basepath.display())),
+            },
+            Err(e) => Err(format_err!("{} is not accessible {e}", basepath.display())),
+        })
+        .await?
     }
 
     async fn update_file_cache(&self) -> Result<usize, Error> {
-        let servicesession = self.get_servicesession().clone();
-        let basedir = self.get_baseurl().path();
+        let basepath = self.get_basepath();
+        let snapshot = self.snapshot.as_deref();
 
-        let wdir = WalkDir::new(basedir).same_file_system(true);
-        let mut tasks = Vec::new();
-        let pool = self.get_pool();
-        let mut cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
-            self.get_servicesession().as_str(),
-            self.get_servicetype().to_str(),
-            pool,
-            false,
-        )
-        .await?
-        .map_ok(|f| (f.urlname.clone(), f))
-        .try_collect()
-        .await?;
-        debug!("expected {}", cached_urls.len());
+        let wdir = match snapshot {
+            Some(snapshot) => WalkDir::new(snapshot),
+            None => WalkDir::new(self.get_baseurl().path()),
+        }
+        .same_file_system(true);
+        let checksum_workers = Arc::new(Semaphore::new(self.get_config().checksum_workers.max(1)));
+        let mut cached = CachedEntries::load(self).await?;
+        let mut batch = Vec::new();
+        let mut number_updated = 0;
         for entry in wdir {
             let entry = entry?;
             let filepath = entry.path().canonicalize().inspect_err(|e| {
@@ -121,68 +251,98 @@ impl FileListTrait for FileListLocal {
             if filepath.is_dir() {
                 continue;
             }
-            let fileurl = Url::from_file_path(filepath.clone())
-                .map_err(|e| format_err!("Failed to parse url {e:?}"))?;
-            let metadata = entry.metadata()?;
-            let size = metadata.len() as i32;
-            if let Some(existing) = cached_urls.remove(fileurl.as_str()) {
-                if existing.deleted_at.is_none() && existing.filestat_st_size == size {
-                    continue;
+            let livepath = match snapshot {
+                Some(snapshot) => {
+                    if let Some(livepath) = live_path(basepath, snapshot, &filepath) {
+                        livepath
+                    } else {
+                        error!(
+                            "{} resolves outside of {}, skipping",
+                            entry.path().display(),
+                            snapshot.display()
+                        );
+                        continue;
+                    }
                 }
+                None => filepath.clone(),
+            };
+            let fileurl = Url::from_file_path(livepath)
+                .map_err(|e| format_err!("Failed to parse url {e:?}"))?;
+            batch.push((entry, filepath, fileurl));
+            if batch.len() >= CACHED_ENTRIES_BATCH {
+                number_updated += self
+                    .update_batch(&mut cached, batch.drain(..), &checksum_workers)
+                    .await?;
             }
-            debug!("not in db {fileurl}");
-            let pool = pool.clone();
-            let servicesession = servicesession.clone();
-            let task: JoinHandle<Result<usize, Error>> = spawn(async move {
-                let info = spawn_blocking(move || {
-                    FileInfoLocal::from_direntry(
-                        &entry,
-                        Some(servicesession.as_str().into()),
-                        Some(servicesession),
-                    )
-                })
-                .await??;
-
-                let info: FileInfoCache = info.into_finfo().into();
-                info.upsert(&pool).await
-            });
-            tasks.push(task);
-        }
-        for (_, missing) in cached_urls {
-            if missing.deleted_at.is_some() || Path::new(&missing.filepath).exists() {
-                continue;
-            }
-            missing.delete(pool).await?;
-        }
-        debug!("tasks {}", tasks.len());
-        let mut number_updated = 0;
-        for task in tasks {
-            number_updated += task.await??;
         }
+        number_updated += self
+            .update_batch(&mut cached, batch.drain(..), &checksum_workers)
+            .await?;
+        cached
+            .delete_missing(|missing| {
+                let path = match snapshot {
+                    Some(snapshot) => missing
+                        .urlname
+                        .parse::<Url>()
+                        .ok()
+                        .and_then(|u| u.to_file_path().ok())
+                        .and_then(|p| snapshot_path(basepath, snapshot, &p)),
+                    None => Some(PathBuf::from(missing.filepath.as_str())),
+                };
+                path.map_or(false, |p| p.exists())
+            })
+            .await?;
         Ok(number_updated)
     }
 
-    async fn print_list(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
         let local_list = self.clone();
         let stdout = stdout.clone();
+        let window = window.clone();
         spawn_blocking(move || {
             let basedir = local_list.get_baseurl().path();
 
             let wdir = WalkDir::new(basedir).same_file_system(true).max_depth(1);
 
-            let entries: Vec<_> = wdir.into_iter().filter_map(Result::ok).collect();
-
-            entries
-                .into_par_iter()
-                .map(|entry| {
-                    if let Ok(filepath) = entry.path().canonicalize() {
+            for entry in wdir.into_iter().filter_map(Result::ok) {
+                if window.is_done() {
+                    break;
+                }
+                if let Ok(filepath) = entry.path().canonicalize() {
+                    if window.admit() {
                         let filepath_str = filepath.to_string_lossy();
                         let filepath_str: StackString = filepath_str.as_ref().into();
                         stdout.send(filepath_str);
                     }
-                    Ok(())
-                })
-                .collect()
+                }
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn list_files(&self) -> Result<Vec<FileInfo>, Error> {
+        let basedir = self.get_basepath().to_path_buf();
+        let servicesession = self.get_servicesession().clone();
+        spawn_blocking(move || {
+            let mut finfos = Vec::new();
+            for entry in WalkDir::new(basedir).same_file_system(true) {
+                let entry = entry?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                let finfo = FileInfoLocal::from_direntry(
+                    &entry,
+                    Some(servicesession.as_str().into()),
+                    Some(servicesession.clone()),
+                )?;
+                finfos.push(finfo.into_finfo());
+            }
+            Ok(finfos)
         })
         .await?
     }
@@ -224,6 +384,20 @@ impl FileListTrait for FileListLocal {
         self.copy_from(finfo0, finfo1).await
     }
 
+    async fn create_directory(&self, directory: &Url) -> Result<(), Error> {
+        let path = directory
+            .to_file_path()
+            .map_err(|e| format_err!("No file path {e:?}"))?;
+        if !path.exists() {
+            create_dir_all(&path).await?;
+        }
+        Ok(())
+    }
+
+    fn supports_move(&self) -> bool {
+        true
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
@@ -253,23 +427,109 @@ impl FileListTrait for FileListLocal {
         }
         Ok(())
     }
+
+    async fn trash(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if finfo.servicetype != FileService::Local {
+            return Err(format_err!("Wrong service type"));
+        } else if finfo.filepath.exists() {
+            let today = OffsetDateTime::now_utc().date();
+            move_to_local_trash(&self.get_config().trash_dir, today, &finfo.filepath).await?;
+        }
+        Ok(())
+    }
+
+    async fn get_owner(&self, finfo: &dyn FileInfoTrait) -> Result<Option<Owner>, Error> {
+        let finfo = finfo.get_finfo();
+        let output = Command::new("stat")
+            .args(["-c", "%U:%G"])
+            .arg(&finfo.filepath.0)
+            .output()
+            .await?;
+        if output.status.success() {
+            StackString::from_utf8_lossy(&output.stdout)
+                .parse()
+                .map(Some)
+        } else {
+            Err(format_err!(
+                "stat {} failed",
+                finfo.filepath.to_string_lossy()
+            ))
+        }
+    }
+
+    async fn set_owner(&self, finfo: &dyn FileInfoTrait, owner: &Owner) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if Command::new("chown")
+            .arg(format_sstr!("{owner}").as_str())
+            .arg(&finfo.filepath.0)
+            .status()
+            .await?
+            .success()
+        {
+            Ok(())
+        } else {
+            Err(format_err!(
+                "chown {owner} {} failed",
+                finfo.filepath.to_string_lossy()
+            ))
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use anyhow::Error;
+    use anyhow::{format_err, Error};
     use log::{debug, info};
     use stack_string::format_sstr;
-    use std::{collections::HashMap, path::PathBuf};
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+    };
     use url::Url;
 
     use crate::{
         config::Config,
-        file_list_local::{FileListLocal, FileListTrait},
+        file_list_local::{
+            canonical_basepath, live_path, snapshot_path, FileListLocal, FileListTrait,
+        },
         file_service::FileService,
         pgpool::PgPool,
     };
 
+    #[test]
+    fn test_canonical_basepath() -> Result<(), Error> {
+        let expected = PathBuf::from("src").canonicalize()?;
+        assert_eq!(canonical_basepath(&PathBuf::from("./src/")), expected);
+        assert_eq!(canonical_basepath(&PathBuf::from("src/../src")), expected);
+        assert_eq!(
+            canonical_basepath(&PathBuf::from("/nonexistent/./path/")),
+            PathBuf::from("/nonexistent/path")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_path_translation() {
+        let basepath = Path::new("/data/photos");
+        let snapshot = Path::new("/data/.zfs/snapshot/nightly/photos");
+        let live = Path::new("/data/photos/2023/img 01.jpg");
+        let snap = Path::new("/data/.zfs/snapshot/nightly/photos/2023/img 01.jpg");
+        assert_eq!(
+            snapshot_path(basepath, snapshot, live).as_deref(),
+            Some(snap)
+        );
+        assert_eq!(live_path(basepath, snapshot, snap).as_deref(), Some(live));
+        assert_eq!(
+            live_path(basepath, snapshot, Path::new("/data/photos/a.jpg")),
+            None
+        );
+        assert_eq!(
+            snapshot_path(basepath, snapshot, Path::new("/data/other/a.jpg")),
+            None
+        );
+    }
+
     #[test]
     #[ignore]
     fn create_conf() -> Result<(), Error> {
@@ -288,6 +548,24 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    #[ignore]
+    async fn test_probe() -> Result<(), Error> {
+        let config = Config::init_config()?;
+        let pool = PgPool::new(&config.database_url)?;
+        let src = PathBuf::from("src").canonicalize()?;
+        for (basepath, reachable) in [
+            (src.clone(), true),
+            (src.join("not_created_yet"), true),
+            (PathBuf::from("/nonexistent/not_created_yet"), false),
+        ] {
+            let url = Url::from_file_path(&basepath).map_err(|e| format_err!("{e:?}"))?;
+            let flist = FileListLocal::from_url(&url, &config, &pool)?;
+            assert_eq!(flist.probe().await.is_ok(), reachable, "{url}");
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_fill_file_list() -> Result<(), Error> {
@@ -1,26 +1,37 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
+use aws_smithy_runtime::client::http::hyper_014::HyperClientBuilder;
 use aws_types::region::Region;
-use futures::TryStreamExt;
-use log::{debug, info};
+use futures::{future, TryStreamExt};
+use log::{debug, info, warn};
+use percent_encoding::percent_decode_str;
 use stack_string::{format_sstr, StackString};
 use std::{
-    collections::HashMap,
-    fs::{create_dir_all, remove_file},
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, metadata, remove_file},
     path::Path,
+    sync::Arc,
+    time::Duration,
 };
 use stdout_channel::StdoutChannel;
+use time::OffsetDateTime;
+use tokio::time::sleep;
 use url::Url;
 
+use gdrive_lib::tls::{tls_config, ProxyConnector};
+
 use crate::{
     config::Config,
-    file_info::{FileInfoTrait, ServiceSession},
-    file_info_s3::FileInfoS3,
-    file_list::{FileList, FileListTrait},
+    file_info::{FileInfo, FileInfoTrait, ServiceSession},
+    file_info_s3::{is_multipart_etag, object_storage_class, FileInfoS3},
+    file_list::{FileList, FileListTrait, ListWindow},
     file_service::FileService,
-    models::FileInfoCache,
+    models::{FileInfoCache, FileSyncConfig, IndexProgress, ResumableTransfer},
     pgpool::PgPool,
-    s3_instance::S3Instance,
+    progress::ProgressChannel,
+    s3_instance::{is_kms_sse, S3Instance, UploadOptions, SSE_NONE},
+    storage_class::{is_archived, ArchivedObject, RestoreState},
+    trash::{is_s3_trash_key, s3_trash_key, unused_trash_key},
 };
 
 #[derive(Debug, Clone)]
@@ -45,10 +56,7 @@ impl FileListS3 {
             bucket.parse()?,
             pool.clone(),
         );
-        let region: String = config.aws_region_name.as_str().into();
-        let region = Region::new(region);
-        let sdk_config = aws_config::from_env().region(region).load().await;
-        let s3 = S3Instance::new(&sdk_config);
+        let s3 = Self::s3_instance(config, bucket).await?;
 
         Ok(Self { flist, s3 })
     }
@@ -67,10 +75,7 @@ impl FileListS3 {
                 bucket.parse()?,
                 pool.clone(),
             );
-            let region: String = config.aws_region_name.as_str().into();
-            let region = Region::new(region);
-            let sdk_config = aws_config::from_env().region(region).load().await;
-            let s3 = S3Instance::new(&sdk_config);
+            let s3 = Self::s3_instance(config, bucket).await?;
 
             Ok(Self { flist, s3 })
         } else {
@@ -78,11 +83,312 @@ impl FileListS3 {
         }
     }
 
+    /// Compute the md5 of every cached object under the base url that
+    /// doesn't have one (multipart uploads by other tools) by ranged
+    /// download, store it as object metadata so that other indexers pick it
+    /// up and update the cache, returns the number of objects updated
+    /// # Errors
+    /// Return error if db query or api call fails
+    pub async fn backfill_checksums(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<usize, Error> {
+        let bucket = self
+            .get_baseurl()
+            .host_str()
+            .ok_or_else(|| format_err!("Parse error"))?;
+        let bucket_url = format_sstr!("s3://{bucket}/");
+        let prefix = self.get_baseurl().path().trim_start_matches('/');
+        let pool = self.get_pool();
+        let missing: Vec<FileInfoCache> = FileInfoCache::get_all_cached(
+            self.get_servicesession().as_str(),
+            self.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .try_filter(|f| {
+            future::ready(
+                f.md5sum.is_none()
+                    && f.urlname
+                        .trim_start_matches(bucket_url.as_str())
+                        .starts_with(prefix),
+            )
+        })
+        .try_collect()
+        .await?;
+        let mut number_updated = 0;
+        for mut entry in missing {
+            let key = entry.urlname.trim_start_matches(bucket_url.as_str());
+            let size = entry.filestat_st_size.max(0) as u64;
+            let md5sum = self.s3.compute_md5(bucket, key, size).await?;
+            if let Err(e) = self.s3.set_md5_metadata(bucket, key, size, &md5sum).await {
+                warn!("only caching md5 of {}: {e}", entry.urlname);
+            }
+            stdout.send(format_sstr!("{} {md5sum}", entry.urlname));
+            entry.md5sum = Some(md5sum);
+            number_updated += entry.upsert(pool).await?;
+        }
+        Ok(number_updated)
+    }
+
+    /// Client using the `[s3.<bucket>]` (or `[s3.default]`) config section
+    /// and going through the `[proxy.s3]` (or global) proxy, trusting its ca
+    /// bundle
+    async fn s3_instance(config: &Config, bucket: &str) -> Result<S3Instance, Error> {
+        let s3_config = config.s3_config(bucket);
+        let region: String = s3_config.region.as_str().into();
+        let mut loader = aws_config::from_env().region(Region::new(region));
+        if let Some(profile) = &s3_config.profile {
+            loader = loader.profile_name(profile.as_str());
+        }
+        if let Some(endpoint_url) = &s3_config.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url.as_str());
+        }
+        let proxy = config.proxy_config("s3");
+        if proxy.url.is_some() || proxy.ca_bundle.is_some() {
+            let builder = hyper_rustls::HttpsConnectorBuilder::new();
+            let builder = match &proxy.ca_bundle {
+                Some(ca_bundle) => builder.with_tls_config(tls_config(ca_bundle)?),
+                None => builder.with_native_roots(),
+            };
+            let builder = builder.https_or_http().enable_http1();
+            let client = match &proxy.url {
+                Some(url) => HyperClientBuilder::new()
+                    .build(builder.wrap_connector(ProxyConnector::new(url.clone()))),
+                None => HyperClientBuilder::new().build(builder.build()),
+            };
+            loader = loader.http_client(client);
+        }
+        let sdk_config = loader.load().await;
+        let s3 = if s3_config.force_path_style {
+            S3Instance::new_path_style(&sdk_config)
+        } else {
+            S3Instance::new(&sdk_config)
+        };
+        Ok(s3
+            .with_requester_pays(s3_config.requester_pays)
+            .with_multipart(
+                config.s3_multipart_threshold,
+                config.s3_part_size,
+                config.s3_transfer_concurrency,
+            )
+            .with_progress(Arc::new(|key, done, total| {
+                debug!("{key} {done} / {total} bytes");
+            })))
+    }
+
     #[must_use]
     pub fn max_keys(mut self, max_keys: i32) -> Self {
         self.s3 = self.s3.max_keys(max_keys);
         self
     }
+
+    /// Multipart upload whose id is kept in `resumable_transfer` until it
+    /// completes, so that a run killed part way through picks up the parts
+    /// already sent rather than leaving them orphaned
+    async fn resumable_upload(
+        &self,
+        local_path: &Path,
+        src_url: &Url,
+        dst_url: &Url,
+        bucket: &str,
+        key: &str,
+        options: &UploadOptions,
+    ) -> Result<(), Error> {
+        let pool = self.get_pool();
+        let metadata = metadata(local_path)?;
+        let mut resumed = None;
+        if let Some(transfer) = ResumableTransfer::get(src_url, dst_url, pool).await? {
+            if transfer.is_current(&metadata) {
+                match self.s3.list_parts(bucket, key, &transfer.session_id).await {
+                    Ok(parts) => {
+                        info!("resuming upload of {dst_url} with {} parts", parts.len());
+                        resumed = Some((transfer, parts));
+                    }
+                    Err(e) => warn!("can't resume upload of {dst_url} {e}"),
+                }
+            } else if let Err(e) = self
+                .s3
+                .abort_multipart_upload(bucket, key, &transfer.session_id)
+                .await
+            {
+                warn!("failed to abort upload of {dst_url} {e}");
+            }
+        }
+        let (transfer, parts) = if let Some(resumed) = resumed {
+            resumed
+        } else {
+            let upload_id = self
+                .s3
+                .create_multipart_upload(local_path, bucket, key, options)
+                .await?;
+            let transfer = ResumableTransfer::new(
+                src_url,
+                dst_url,
+                FileService::S3.to_str(),
+                &upload_id,
+                &metadata,
+            );
+            transfer.insert(pool).await?;
+            (transfer, Vec::new())
+        };
+        self.s3
+            .finish_multipart_upload(
+                local_path,
+                metadata.len(),
+                bucket,
+                key,
+                &transfer.session_id,
+                &parts,
+            )
+            .await?;
+        transfer.delete(pool).await
+    }
+
+    /// Storage class and encryption of an upload to `remote_url`, each taken
+    /// from a config whose `dst_url` contains it if set there, otherwise
+    /// from `[s3.<bucket>]`
+    async fn upload_options(&self, remote_url: &Url, bucket: &str) -> Result<UploadOptions, Error> {
+        let s3_config = self.get_config().s3_config(bucket);
+        let conf =
+            FileSyncConfig::get_upload_options_dst(remote_url.as_str(), self.get_pool()).await?;
+        let (storage_class, sse) = match conf {
+            Some(conf) => (
+                conf.storage_class,
+                conf.sse.map(|sse| (sse, conf.sse_kms_key_id)),
+            ),
+            None => (None, None),
+        };
+        let (sse, sse_kms_key_id) = match sse {
+            Some((sse, sse_kms_key_id)) => (Some(sse), sse_kms_key_id),
+            None => (s3_config.sse, s3_config.sse_kms_key_id),
+        };
+        Ok(UploadOptions {
+            storage_class: storage_class.or(s3_config.storage_class),
+            sse,
+            sse_kms_key_id,
+        })
+    }
+
+    /// Whether objects in the bucket may be encrypted with SSE-KMS, by the
+    /// `[s3.<bucket>]` setting or a config uploading to it
+    async fn may_use_kms(&self, bucket: &str) -> Result<bool, Error> {
+        if self
+            .get_config()
+            .s3_config(bucket)
+            .sse
+            .as_deref()
+            .map_or(false, is_kms_sse)
+        {
+            return Ok(true);
+        }
+        FileSyncConfig::has_kms_dst(&format_sstr!("s3://{bucket}/"), self.get_pool()).await
+    }
+
+    /// Fail with `ArchivedObject` if the cache has the object in an archive
+    /// storage class and no restored copy is readable yet.  With
+    /// `s3_restore_days` set a restore is requested unless one is already
+    /// running, otherwise the object is skipped.
+    async fn check_archived(&self, finfo: &FileInfo, bucket: &str, key: &str) -> Result<(), Error> {
+        let cached = FileInfoCache::get_by_urlname(
+            &finfo.urlname,
+            finfo.servicesession.as_str(),
+            self.get_pool(),
+        )
+        .await?;
+        if !cached
+            .as_ref()
+            .and_then(|c| c.storage_class.as_deref())
+            .map_or(false, is_archived)
+        {
+            return Ok(());
+        }
+        let (storage_class, mut state) = self.s3.get_restore_state(bucket, key).await?;
+        if !is_archived(&storage_class) || state == RestoreState::Restored {
+            return Ok(());
+        }
+        let config = self.get_config();
+        if let Some(days) = config.s3_restore_days {
+            if state == RestoreState::NotRequested {
+                info!(
+                    "requesting {} restore of {} for {days} days",
+                    config.s3_restore_tier, finfo.urlname
+                );
+                self.s3
+                    .restore_object(bucket, key, days, &config.s3_restore_tier)
+                    .await?;
+                state = RestoreState::InProgress;
+            }
+        }
+        Err(ArchivedObject {
+            url: finfo.urlname.as_str().into(),
+            storage_class,
+            state,
+        }
+        .into())
+    }
+
+    /// Abort the multipart uploads under the baseurl started before
+    /// `started_before` and forget the ones `resumable_upload` was tracking,
+    /// returns the urls and upload ids aborted
+    /// # Errors
+    /// Return error if api call or db query fails
+    pub async fn abort_stale_uploads(
+        &self,
+        started_before: OffsetDateTime,
+        dry_run: bool,
+    ) -> Result<Vec<StackString>, Error> {
+        let pool = self.get_pool();
+        let bucket = self
+            .get_baseurl()
+            .host_str()
+            .ok_or_else(|| format_err!("No bucket"))?;
+        let key_prefix = self.get_baseurl().path().trim_start_matches('/');
+        let key_prefix: StackString = if key_prefix.is_empty() || key_prefix.ends_with('/') {
+            key_prefix.into()
+        } else {
+            format_sstr!("{key_prefix}/")
+        };
+        let prefix = format_sstr!("s3://{bucket}/");
+        let url_prefix = format_sstr!("{prefix}{key_prefix}");
+        let mut tracked: HashMap<StackString, ResumableTransfer> =
+            ResumableTransfer::get_started_before(FileService::S3.to_str(), started_before, pool)
+                .await?
+                .into_iter()
+                .filter(|t| t.dst_url.starts_with(url_prefix.as_str()))
+                .map(|t| (t.session_id.clone(), t))
+                .collect();
+        let mut aborted = Vec::new();
+        let key_prefix = Some(key_prefix.as_str()).filter(|p| !p.is_empty());
+        for upload in self.s3.list_multipart_uploads(bucket, key_prefix).await? {
+            let (key, upload_id) = match (&upload.key, &upload.upload_id) {
+                (Some(key), Some(upload_id)) => (key, upload_id),
+                _ => continue,
+            };
+            let transfer = tracked.remove(upload_id.as_str());
+            let initiated = upload.initiated.map_or(i64::MAX, |t| t.secs());
+            if initiated >= started_before.unix_timestamp() {
+                continue;
+            }
+            if !dry_run {
+                self.s3
+                    .abort_multipart_upload(bucket, key, upload_id)
+                    .await?;
+                if let Some(transfer) = transfer {
+                    transfer.delete(pool).await?;
+                }
+            }
+            aborted.push(format_sstr!("{prefix}{key} {upload_id}"));
+        }
+        if !dry_run {
+            // uploads completed or aborted by something else
+            for transfer in tracked.values() {
+                transfer.delete(pool).await?;
+            }
+        }
+        Ok(aborted)
+    }
 }
 
 #[async_trait]
@@ -110,6 +416,12 @@ impl FileListTrait for FileListS3 {
         &self.flist.pool
     }
 
+    async fn probe(&self) -> Result<(), Error> {
+        self.s3
+            .head_bucket(self.get_servicesession().as_str())
+            .await
+    }
+
     async fn update_file_cache(&self) -> Result<usize, Error> {
         let bucket = self
             .get_baseurl()
@@ -118,6 +430,7 @@ impl FileListTrait for FileListS3 {
         let prefix = self.get_baseurl().path().trim_start_matches('/');
         let mut number_updated = 0;
         let pool = self.get_pool();
+        let config = self.get_config();
         let mut cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
             self.get_servicesession().as_str(),
             self.get_servicetype().to_str(),
@@ -129,28 +442,131 @@ impl FileListTrait for FileListS3 {
         .try_collect()
         .await?;
         debug!("expected {}", cached_urls.len());
+        // etags of SSE-KMS objects aren't md5s either, so where KMS may be
+        // used every new and changed object is looked up
+        let kms = self.may_use_kms(bucket).await?;
+        // deleted objects are only indexed if the baseurl is in the trash
+        let index_trash = is_s3_trash_key(&format_sstr!("{prefix}/"));
 
-        for object in self.s3.get_list_of_keys(bucket, Some(prefix)).await? {
-            let info: FileInfoCache = FileInfoS3::from_object(bucket, object)?.into_finfo().into();
-            if let Some(existing) = cached_urls.remove(&info.urlname) {
-                if existing.deleted_at.is_none()
-                    && existing.filestat_st_size == info.filestat_st_size
-                {
+        // Pick up where an interrupted listing left off, keys are listed in
+        // lexicographic order so everything up to the marker is already cached
+        let mut progress = IndexProgress::get(
+            self.get_servicetype().to_str(),
+            self.get_servicesession().as_str(),
+            self.get_baseurl().as_str(),
+            pool,
+        )
+        .await?
+        .unwrap_or_else(|| {
+            IndexProgress::new(
+                self.get_servicetype().to_str(),
+                self.get_servicesession().as_str(),
+                self.get_baseurl().as_str(),
+            )
+        });
+        let resume_marker = progress.continuation_token.clone();
+        if let Some(marker) = &resume_marker {
+            info!("resuming listing of {bucket} after {marker}");
+        }
+        let mut marker: Option<String> = resume_marker.as_ref().map(Into::into);
+        let mut max_keys = self.s3.get_max_keys();
+        let mut pending = 0;
+        loop {
+            let (objects, mut next_marker) = self
+                .s3
+                .list_keys_page(bucket, Some(prefix), marker.as_deref(), max_keys)
+                .await?;
+            if let Some(n) = max_keys {
+                let n = n - objects.len() as i32;
+                if n <= 0 {
+                    next_marker.take();
+                }
+                max_keys.replace(n);
+            }
+            ProgressChannel::global().scanned(objects.len());
+            for object in objects {
+                let multipart = object.e_tag.as_deref().map_or(false, is_multipart_etag);
+                let key = object.key.clone().unwrap_or_default();
+                if is_s3_trash_key(&key) && !index_trash {
                     continue;
                 }
+                let storage_class = object_storage_class(&object);
+                let mut finfo = FileInfoS3::from_object(bucket, object)?;
+                pending += 1;
+                if let Some(existing) = cached_urls.remove(finfo.get_finfo().urlname.as_str()) {
+                    if existing.deleted_at.is_none()
+                        && existing.filestat_st_size == finfo.get_finfo().filestat.st_size
+                        && existing.storage_class.as_ref() == Some(&storage_class)
+                        && (!kms || existing.sse.is_some())
+                    {
+                        continue;
+                    }
+                }
+                // only new and changed objects are looked up, one HEAD each
+                let mut sse = None;
+                if kms || (multipart && !config.s3_ignore_multipart_etags) {
+                    let (md5sum, object_sse) = self.s3.get_md5_metadata(bucket, &key).await?;
+                    finfo = finfo
+                        .with_sse(object_sse.as_deref())
+                        .with_sidecar_md5(md5sum.as_deref());
+                    sse = Some(object_sse.unwrap_or_else(|| SSE_NONE.into()));
+                }
+                let mut info: FileInfoCache = finfo.into_finfo().into();
+                info.storage_class = Some(storage_class);
+                info.sse = sse;
+                number_updated += info.upsert(pool).await?;
+            }
+            marker = next_marker;
+            if marker.is_none() {
+                break;
+            }
+            if pending >= config.index_flush_size {
+                progress.flush(marker.as_deref(), pending, pool).await?;
+                pending = 0;
+            }
+            if config.list_page_delay_ms > 0 {
+                sleep(Duration::from_millis(config.list_page_delay_ms)).await;
             }
-            number_updated += info.upsert(pool).await?;
         }
-        for (_, missing) in cached_urls {
+        let bucket_url = format_sstr!("s3://{bucket}/");
+        for (url, missing) in cached_urls {
             if missing.deleted_at.is_some() {
                 continue;
             }
+            // entries before the resume point weren't listed in this pass, the
+            // marker is a raw key while urlnames are percent-encoded
+            if let Some(resume_marker) = &resume_marker {
+                let key = percent_decode_str(url.trim_start_matches(bucket_url.as_str()))
+                    .decode_utf8_lossy();
+                if key.as_ref() <= resume_marker.as_str() {
+                    continue;
+                }
+            }
             missing.delete(pool).await?;
         }
+        progress.finish(pool).await?;
         Ok(number_updated)
     }
 
-    async fn print_list(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+    async fn list_files(&self) -> Result<Vec<FileInfo>, Error> {
+        let bucket = self
+            .get_baseurl()
+            .host_str()
+            .ok_or_else(|| format_err!("Parse error"))?;
+        let prefix = self.get_baseurl().path().trim_start_matches('/');
+        self.s3
+            .get_list_of_keys(bucket, Some(prefix))
+            .await?
+            .into_iter()
+            .map(|object| FileInfoS3::from_object(bucket, object).map(FileInfoTrait::into_finfo))
+            .collect()
+    }
+
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
         let bucket = self
             .get_baseurl()
             .host_str()
@@ -159,10 +575,11 @@ impl FileListTrait for FileListS3 {
 
         self.s3
             .process_list_of_keys(bucket, Some(prefix), |i| {
-                let key = i.key.as_ref().map_or_else(|| "", String::as_str);
-                let buf = format_sstr!("s3://{bucket}/{key}");
-                stdout.send(buf);
-                Ok(())
+                if window.admit() {
+                    let key = i.key.as_ref().map_or_else(|| "", String::as_str);
+                    stdout.send(format_sstr!("s3://{bucket}/{key}"));
+                }
+                Ok(!window.is_done())
             })
             .await
     }
@@ -188,6 +605,7 @@ impl FileListTrait for FileListS3 {
                 .host_str()
                 .ok_or_else(|| format_err!("No bucket"))?;
             let key = remote_url.path().trim_start_matches('/');
+            self.check_archived(finfo0, bucket, key).await?;
             if Path::new(local_file.as_ref()).exists() {
                 remove_file(local_file.as_ref())?;
             }
@@ -224,7 +642,20 @@ impl FileListTrait for FileListS3 {
                 .host_str()
                 .ok_or_else(|| format_err!("No bucket"))?;
             let key = remote_url.path().trim_start_matches('/');
-            self.s3.upload(&local_file, bucket, key).await
+            let options = self.upload_options(remote_url, bucket).await?;
+            if self.s3.is_multipart(metadata(&local_path)?.len()) {
+                self.resumable_upload(
+                    &local_path,
+                    &finfo0.urlname,
+                    remote_url,
+                    bucket,
+                    key,
+                    &options,
+                )
+                .await
+            } else {
+                self.s3.upload(&local_file, bucket, key, &options).await
+            }
         } else {
             Err(format_err!(
                 "Invalid types {} {}",
@@ -234,6 +665,10 @@ impl FileListTrait for FileListS3 {
         }
     }
 
+    fn supports_move(&self) -> bool {
+        true
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
@@ -269,6 +704,28 @@ impl FileListTrait for FileListS3 {
             Err(format_err!("Wrong service type"))
         }
     }
+
+    async fn trash(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if finfo.servicetype == FileService::S3 {
+            let url = &finfo.urlname;
+            let bucket = url.host_str().ok_or_else(|| format_err!("No bucket"))?;
+            let key = url.path();
+            let trash_key = s3_trash_key(OffsetDateTime::now_utc().date(), key);
+            let existing: HashSet<StackString> = self
+                .s3
+                .get_list_of_keys(bucket, Some(&trash_key))
+                .await?
+                .into_iter()
+                .filter_map(|object| object.key.map(Into::into))
+                .collect();
+            let trash_key = unused_trash_key(&trash_key, |k| existing.contains(k));
+            self.s3.copy_key(url, bucket, &trash_key).await?;
+            self.s3.delete_key(bucket, key).await
+        } else {
+            Err(format_err!("Wrong service type"))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,294 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use log::debug;
+use percent_encoding::percent_decode_str;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+use stdout_channel::StdoutChannel;
+use tokio::fs::create_dir_all;
+use url::Url;
+
+use crate::{
+    config::Config,
+    file_info::{FileInfoTrait, ServiceSession},
+    file_info_sftp::FileInfoSftp,
+    file_list::{FileList, FileListTrait, ListWindow},
+    file_service::FileService,
+    models::FileInfoCache,
+    pgpool::PgPool,
+    progress::ProgressChannel,
+    sftp_instance::SftpInstance,
+    ssh_instance::ssh_url_parts,
+};
+
+/// `sftp://` urls, the same remote as `ssh://` but listed and transferred
+/// over the sftp subsystem, without `sync-app-rust` on the remote.  `ssh://`
+/// urls keep using ssh / scp and the remote binary.
+#[derive(Debug, Clone)]
+pub struct FileListSftp {
+    pub flist: FileList,
+    pub sftp: SftpInstance,
+}
+
+impl FileListSftp {
+    /// # Errors
+    /// Return error if url isn't a valid sftp url
+    pub fn from_url(url: &Url, config: &Config, pool: &PgPool) -> Result<Self, Error> {
+        if url.scheme() == "sftp" {
+            let basepath = remote_path(url);
+            let (user, host, url_port) = ssh_url_parts(url)?;
+            let ssh_config = config.ssh_config(&host);
+            let port = url_port.or(ssh_config.port).unwrap_or(22);
+            let user = if user.is_empty() {
+                ssh_config.user.clone().unwrap_or_default()
+            } else {
+                user
+            };
+            let sftp = SftpInstance::new(&user, &host, port, ssh_config.identity_file);
+            let mut authority = url_host(url)?;
+            if !user.is_empty() {
+                authority = format_sstr!("{user}@{authority}");
+            }
+            if port != 22 {
+                authority.push_str(&format_sstr!(":{port}"));
+            }
+            let session = format_sstr!("sftp://{authority}{}", url.path());
+            let flist = FileList::new(
+                url.clone(),
+                basepath,
+                config.clone(),
+                FileService::SFTP,
+                session.parse()?,
+                pool.clone(),
+            );
+            Ok(Self { flist, sftp })
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+}
+
+fn remote_path(url: &Url) -> PathBuf {
+    let path = percent_decode_str(url.path()).decode_utf8_lossy();
+    Path::new(path.as_ref()).to_path_buf()
+}
+
+fn url_from_remote_path(baseurl: &Url, path: &Path) -> Url {
+    let mut url = baseurl.clone();
+    url.set_path(&path.to_string_lossy());
+    url
+}
+
+/// Host as it appears in the url, ipv6 addresses keep their brackets
+fn url_host(url: &Url) -> Result<StackString, Error> {
+    url.host()
+        .map(|h| format_sstr!("{h}"))
+        .ok_or_else(|| format_err!("No host in {url}"))
+}
+
+#[async_trait]
+impl FileListTrait for FileListSftp {
+    fn get_baseurl(&self) -> &Url {
+        self.flist.get_baseurl()
+    }
+    fn set_baseurl(&mut self, baseurl: Url) {
+        self.flist.set_baseurl(baseurl);
+    }
+    fn get_basepath(&self) -> &Path {
+        &self.flist.basepath
+    }
+    fn get_servicetype(&self) -> FileService {
+        self.flist.servicetype
+    }
+    fn get_servicesession(&self) -> &ServiceSession {
+        &self.flist.servicesession
+    }
+    fn get_config(&self) -> &Config {
+        &self.flist.config
+    }
+
+    fn get_pool(&self) -> &PgPool {
+        &self.flist.pool
+    }
+
+    async fn probe(&self) -> Result<(), Error> {
+        self.sftp.probe(self.get_basepath()).await
+    }
+
+    async fn update_file_cache(&self) -> Result<usize, Error> {
+        let pool = self.get_pool();
+        let mut cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
+            self.get_servicesession().as_str(),
+            self.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .map_ok(|f| (f.urlname.clone(), f))
+        .try_collect()
+        .await?;
+        debug!("expected {}", cached_urls.len());
+
+        let mut number_updated = 0;
+        let entries = self.sftp.walk(self.get_basepath()).await?;
+        ProgressChannel::global().scanned(entries.len());
+        for (path, stat) in entries {
+            let fileurl = url_from_remote_path(self.get_baseurl(), &path);
+            let finfo = FileInfoSftp::from_stat(&fileurl, stat, self.get_servicesession().clone())?;
+            let info: FileInfoCache = finfo.into_finfo().into();
+            if let Some(existing) = cached_urls.remove(&info.urlname) {
+                if existing.deleted_at.is_none()
+                    && existing.filestat_st_size == info.filestat_st_size
+                    && existing.filestat_st_mtime == info.filestat_st_mtime
+                {
+                    continue;
+                }
+            }
+            debug!("not in db {fileurl}");
+            number_updated += info.upsert(pool).await?;
+        }
+        for (_, missing) in cached_urls {
+            if missing.deleted_at.is_some() {
+                continue;
+            }
+            missing.delete(pool).await?;
+        }
+        Ok(number_updated)
+    }
+
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
+        for path in self.sftp.list_directory(self.get_basepath()).await? {
+            if window.is_done() {
+                break;
+            }
+            if !window.admit() {
+                continue;
+            }
+            stdout.send(format_sstr!(
+                "{}",
+                url_from_remote_path(self.get_baseurl(), &path)
+            ));
+        }
+        Ok(())
+    }
+
+    async fn copy_from(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype == FileService::SFTP && finfo1.servicetype == FileService::Local {
+            let parent_dir = finfo1
+                .filepath
+                .parent()
+                .ok_or_else(|| format_err!("No parent directory"))?;
+            if !parent_dir.exists() {
+                create_dir_all(&parent_dir).await?;
+            }
+            self.sftp
+                .download(&remote_path(&finfo0.urlname), &finfo1.filepath)
+                .await
+        } else {
+            Err(format_err!(
+                "Invalid types {} {}",
+                finfo0.servicetype,
+                finfo1.servicetype
+            ))
+        }
+    }
+
+    async fn copy_to(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype == FileService::Local && finfo1.servicetype == FileService::SFTP {
+            self.sftp
+                .upload(&finfo0.filepath, &remote_path(&finfo1.urlname))
+                .await
+        } else {
+            Err(format_err!(
+                "Invalid types {} {}",
+                finfo0.servicetype,
+                finfo1.servicetype
+            ))
+        }
+    }
+
+    async fn create_directory(&self, directory: &Url) -> Result<(), Error> {
+        self.sftp.create_dir_all(&remote_path(directory)).await
+    }
+
+    fn supports_move(&self) -> bool {
+        true
+    }
+
+    async fn move_file(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype != FileService::SFTP || finfo1.servicetype != FileService::SFTP {
+            return Ok(());
+        }
+        let url0 = &finfo0.urlname;
+        let url1 = &finfo1.urlname;
+        if url0.username() != url1.username()
+            || url0.host_str() != url1.host_str()
+            || url0.port() != url1.port()
+        {
+            return Ok(());
+        }
+        self.sftp
+            .rename(&remote_path(url0), &remote_path(url1))
+            .await
+    }
+
+    async fn delete(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if finfo.servicetype == FileService::SFTP {
+            self.sftp.remove_file(&remote_path(&finfo.urlname)).await
+        } else {
+            Err(format_err!("Wrong service type"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use url::Url;
+
+    use crate::file_list_sftp::{remote_path, url_from_remote_path};
+
+    #[test]
+    fn test_sftp_url_paths() {
+        let baseurl: Url = "sftp://ubuntu@cloud.ddboline.net:2222/home/ubuntu/My%20Files"
+            .parse()
+            .unwrap();
+        assert_eq!(remote_path(&baseurl), Path::new("/home/ubuntu/My Files"));
+        let url = url_from_remote_path(&baseurl, Path::new("/home/ubuntu/My Files/a#b.txt"));
+        assert_eq!(
+            url.as_str(),
+            "sftp://ubuntu@cloud.ddboline.net:2222/home/ubuntu/My%20Files/a%23b.txt"
+        );
+        assert_eq!(
+            remote_path(&url),
+            Path::new("/home/ubuntu/My Files/a#b.txt")
+        );
+    }
+}
@@ -0,0 +1,277 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use log::debug;
+use percent_encoding::percent_decode_str;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, path::Path};
+use stdout_channel::StdoutChannel;
+use tokio::{
+    fs::{copy, create_dir_all, remove_file, rename},
+    task::spawn_blocking,
+};
+use url::Url;
+use walkdir::{DirEntry, WalkDir};
+
+use crate::{
+    config::Config,
+    file_info::{FileInfoTrait, FileStat, ServiceSession},
+    file_info_local::FileInfoLocal,
+    file_info_smb::FileInfoSmb,
+    file_list::{FileList, FileListTrait, ListWindow},
+    file_service::FileService,
+    models::FileInfoCache,
+    pgpool::PgPool,
+    progress::ProgressChannel,
+    smb_instance::SmbInstance,
+};
+
+#[derive(Debug, Clone)]
+pub struct FileListSmb {
+    pub flist: FileList,
+    pub smb: SmbInstance,
+}
+
+impl FileListSmb {
+    /// # Errors
+    /// Return error if db query fails
+    pub fn from_url(url: &Url, config: &Config, pool: &PgPool) -> Result<Self, Error> {
+        if url.scheme() == "smb" {
+            let smb = SmbInstance::from_url(url)?;
+            let basepath = percent_decode_str(url.path()).decode_utf8_lossy();
+            let basepath = Path::new(basepath.as_ref()).to_path_buf();
+            let flist = FileList::new(
+                url.clone(),
+                basepath,
+                config.clone(),
+                FileService::SMB,
+                smb.share_url().parse()?,
+                pool.clone(),
+            );
+            Ok(Self { flist, smb })
+        } else {
+            Err(format_err!("Wrong scheme"))
+        }
+    }
+}
+
+#[async_trait]
+impl FileListTrait for FileListSmb {
+    fn get_baseurl(&self) -> &Url {
+        self.flist.get_baseurl()
+    }
+    fn set_baseurl(&mut self, baseurl: Url) {
+        self.flist.set_baseurl(baseurl);
+    }
+    fn get_basepath(&self) -> &Path {
+        &self.flist.basepath
+    }
+    fn get_servicetype(&self) -> FileService {
+        self.flist.servicetype
+    }
+    fn get_servicesession(&self) -> &ServiceSession {
+        &self.flist.servicesession
+    }
+    fn get_config(&self) -> &Config {
+        &self.flist.config
+    }
+
+    fn get_pool(&self) -> &PgPool {
+        &self.flist.pool
+    }
+
+    async fn probe(&self) -> Result<(), Error> {
+        self.smb.mount().await.map(|_| ())
+    }
+
+    async fn update_file_cache(&self) -> Result<usize, Error> {
+        self.smb.mount().await?;
+        let servicesession = self.get_servicesession().clone();
+        let pool = self.get_pool();
+        let mut cached_urls: HashMap<StackString, _> = FileInfoCache::get_all_cached(
+            self.get_servicesession().as_str(),
+            self.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .map_ok(|f| (f.urlname.clone(), f))
+        .try_collect()
+        .await?;
+        debug!("expected {}", cached_urls.len());
+
+        // the share is a network mount, walk it off the executor
+        let smb = self.smb.clone();
+        let basepath = self.smb.local_path(self.get_baseurl())?;
+        let entries: Vec<(DirEntry, Url, FileStat)> = spawn_blocking(move || {
+            let mut entries = Vec::new();
+            for entry in WalkDir::new(basepath) {
+                let entry = entry?;
+                if entry.file_type().is_dir() {
+                    continue;
+                }
+                let metadata = entry.metadata()?;
+                let filestat = FileStat::new(metadata.modified()?.into(), metadata.len() as i64);
+                let fileurl = smb.url_from_local_path(entry.path())?;
+                entries.push((entry, fileurl, filestat));
+            }
+            Ok::<_, Error>(entries)
+        })
+        .await??;
+
+        let mut number_updated = 0;
+        for (entry, fileurl, filestat) in entries {
+            ProgressChannel::global().scanned(1);
+            if let Some(existing) = cached_urls.remove(fileurl.as_str()) {
+                if existing.deleted_at.is_none()
+                    && existing.filestat_st_size == filestat.st_size
+                    && existing.filestat_st_mtime == filestat.st_mtime
+                {
+                    continue;
+                }
+            }
+            debug!("not in db {fileurl}");
+            let servicesession = servicesession.clone();
+            let local = spawn_blocking(move || {
+                FileInfoLocal::from_direntry(
+                    &entry,
+                    Some(servicesession.as_str().into()),
+                    Some(servicesession),
+                )
+            })
+            .await??;
+            let info: FileInfoCache = FileInfoSmb::from_mounted(
+                local.get_finfo(),
+                fileurl,
+                self.get_servicesession().clone(),
+            )
+            .into_finfo()
+            .into();
+            number_updated += info.upsert(pool).await?;
+        }
+        for (_, missing) in cached_urls {
+            if missing.deleted_at.is_some() {
+                continue;
+            }
+            missing.delete(pool).await?;
+        }
+        Ok(number_updated)
+    }
+
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
+        self.smb.mount().await?;
+        let smb = self.smb.clone();
+        let basepath = self.smb.local_path(self.get_baseurl())?;
+        let stdout = stdout.clone();
+        let window = window.clone();
+        spawn_blocking(move || {
+            for entry in WalkDir::new(basepath).max_depth(1) {
+                if window.is_done() {
+                    break;
+                }
+                let entry = entry?;
+                if !window.admit() {
+                    continue;
+                }
+                let url = smb.url_from_local_path(entry.path())?;
+                stdout.send(format_sstr!("{url}"));
+            }
+            Ok(())
+        })
+        .await?
+    }
+
+    async fn copy_from(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype == FileService::SMB && finfo1.servicetype == FileService::Local {
+            self.smb.mount().await?;
+            let remote_file = self.smb.local_path(&finfo0.urlname)?;
+            let local_file = &finfo1.filepath;
+            let parent_dir = local_file
+                .parent()
+                .ok_or_else(|| format_err!("No parent directory"))?;
+            if !parent_dir.exists() {
+                create_dir_all(&parent_dir).await?;
+            }
+            copy(&remote_file, &local_file).await?;
+            Ok(())
+        } else {
+            Err(format_err!(
+                "Invalid types {} {}",
+                finfo0.servicetype,
+                finfo1.servicetype
+            ))
+        }
+    }
+
+    async fn copy_to(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype == FileService::Local && finfo1.servicetype == FileService::SMB {
+            self.smb.mount().await?;
+            let remote_file = self.smb.local_path(&finfo1.urlname)?;
+            let parent_dir = remote_file
+                .parent()
+                .ok_or_else(|| format_err!("No parent directory"))?;
+            if !parent_dir.exists() {
+                create_dir_all(&parent_dir).await?;
+            }
+            copy(&finfo0.filepath, &remote_file).await?;
+            Ok(())
+        } else {
+            Err(format_err!(
+                "Invalid types {} {}",
+                finfo0.servicetype,
+                finfo1.servicetype
+            ))
+        }
+    }
+
+    fn supports_move(&self) -> bool {
+        true
+    }
+
+    async fn move_file(
+        &self,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        let finfo0 = finfo0.get_finfo();
+        let finfo1 = finfo1.get_finfo();
+        if finfo0.servicetype != FileService::SMB || finfo1.servicetype != FileService::SMB {
+            return Ok(());
+        }
+        self.smb.mount().await?;
+        let path0 = self.smb.local_path(&finfo0.urlname)?;
+        let path1 = self.smb.local_path(&finfo1.urlname)?;
+        rename(&path0, &path1).await?;
+        Ok(())
+    }
+
+    async fn delete(&self, finfo: &dyn FileInfoTrait) -> Result<(), Error> {
+        let finfo = finfo.get_finfo();
+        if finfo.servicetype == FileService::SMB {
+            self.smb.mount().await?;
+            let path = self.smb.local_path(&finfo.urlname)?;
+            if path.exists() {
+                remove_file(&path).await?;
+            }
+            Ok(())
+        } else {
+            Err(format_err!("Wrong service type"))
+        }
+    }
+}
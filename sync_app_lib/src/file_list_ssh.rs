@@ -1,22 +1,28 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
 use futures::TryStreamExt;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 use rand::{thread_rng, RngCore};
 use stack_string::{format_sstr, StackString};
 use std::{collections::HashMap, fs::create_dir_all, path::Path};
 use stdout_channel::StdoutChannel;
-use tokio::{fs::remove_file, process::Command};
+use time::Duration;
+use tokio::{
+    fs::{remove_file, write},
+    process::Command,
+};
 use url::Url;
 
 use crate::{
     config::Config,
+    delta::{file_delta, file_signature, patch_file, DeltaSummary, Signature},
     file_info::{FileInfo, FileInfoInner, FileInfoTrait, ServiceSession},
-    file_list::{FileList, FileListTrait},
+    file_list::{FileList, FileListTrait, ListWindow},
     file_service::FileService,
     models::FileInfoCache,
+    ownership::Owner,
     pgpool::PgPool,
-    ssh_instance::SSHInstance,
+    ssh_instance::{ssh_url_parts, SSHInstance},
 };
 
 #[derive(Clone, Debug)]
@@ -31,14 +37,20 @@ impl FileListSSH {
     pub async fn from_url(url: &Url, config: &Config, pool: &PgPool) -> Result<Self, Error> {
         if url.scheme() == "ssh" {
             let basepath = Path::new(url.path()).to_path_buf();
-            let host = url.host_str().ok_or_else(|| format_err!("Parse error"))?;
-            let port = url.port().unwrap_or(22);
+            let hostname = url.host_str().ok_or_else(|| format_err!("Parse error"))?;
+            let (user, ssh_host, url_port) = ssh_url_parts(url)?;
+            let ssh_config = config.ssh_config(&ssh_host);
+            let port = url_port.or(ssh_config.port).unwrap_or(22);
             let host = if port == 22 {
-                host.into()
+                hostname.into()
+            } else {
+                format_sstr!("{hostname}:{port}")
+            };
+            let username = if url.username().is_empty() {
+                ssh_config.user.as_ref().map_or("", StackString::as_str)
             } else {
-                format_sstr!("{host}:{port}")
+                url.username()
             };
-            let username = url.username();
 
             let session = format_sstr!("ssh://{username}@{host}{}", basepath.to_string_lossy());
             let flist = FileList::new(
@@ -49,14 +61,135 @@ impl FileListSSH {
                 session.parse()?,
                 pool.clone(),
             );
-            let url = url.clone();
-            let ssh = SSHInstance::from_url(&url).await?;
+            let user = if user.is_empty() {
+                ssh_config.user.clone().unwrap_or_default()
+            } else {
+                user
+            };
+            let ssh = SSHInstance::new(&user, &ssh_host, port).await;
 
             Ok(Self { flist, ssh })
         } else {
             Err(format_err!("Wrong scheme"))
         }
     }
+
+    fn use_delta(&self, size: i64) -> bool {
+        let min_size = self.get_config().ssh_delta_min_size;
+        min_size > 0 && size >= min_size as i64
+    }
+
+    fn tmp_file(&self, ext: &str) -> StackString {
+        let tmp_prefix = self.ssh.host.replace(':', "_");
+        let randint = thread_rng().next_u32();
+        format_sstr!("/tmp/{tmp_prefix}_{randint}.{ext}")
+    }
+
+    /// `file://` url of a remote path, shell-quoted for use in an ssh command
+    fn remote_file_url(path: &str) -> Result<StackString, Error> {
+        let url = Url::from_file_path(path).map_err(|()| format_err!("Invalid path {path}"))?;
+        Ok(shell_quote(url.as_str()))
+    }
+
+    /// Update the remote `path1` to match local `path0`: the signature of the
+    /// remote copy is fetched, the delta computed locally is sent over and
+    /// applied by `sync-app-rust patch` on the remote host
+    async fn delta_copy_to(&self, path0: &Path, path1: &str) -> Result<DeltaSummary, Error> {
+        let url1 = Self::remote_file_url(path1)?;
+        let command = format_sstr!("sync-app-rust signature --no-db -u {url1}");
+        let output = self.ssh.run_command_stream_stdout(&command).await?;
+        let line = output
+            .lines()
+            .rev()
+            .find(|l| l.starts_with('{'))
+            .ok_or_else(|| format_err!("No signature for {path1}"))?;
+        let signature: Signature = serde_json::from_str(line)?;
+
+        let tmp_file = self.tmp_file("delta");
+        let summary = file_delta(signature, path0, Path::new(tmp_file.as_str())).await;
+        let result = match summary {
+            Ok(summary) => {
+                let result = self.apply_remote_delta(path1, &tmp_file).await;
+                result.map(|()| summary)
+            }
+            Err(e) => Err(e),
+        };
+        remove_file(tmp_file.as_str()).await.ok();
+        result
+    }
+
+    async fn apply_remote_delta(&self, path1: &str, tmp_file: &str) -> Result<(), Error> {
+        self.ssh
+            .run_scp(tmp_file, &self.ssh.get_ssh_str(tmp_file))
+            .await?;
+        let url1 = Self::remote_file_url(path1)?;
+        let tmp_file = shell_quote(tmp_file);
+        let command = format_sstr!("sync-app-rust patch --no-db -u {url1} -f {tmp_file}");
+        let result = self.ssh.run_command_ssh(&command).await;
+        let command = format_sstr!("rm -f {tmp_file}");
+        self.ssh.run_command_ssh(&command).await?;
+        result
+    }
+
+    /// Update local `path1` to match the remote `path0`: the signature of the
+    /// local copy is sent over, the delta computed on the remote host by
+    /// `sync-app-rust delta` is fetched and applied locally
+    async fn delta_copy_from(&self, path0: &str, path1: &Path) -> Result<u64, Error> {
+        let signature = file_signature(path1).await?;
+        let sig_file = self.tmp_file("sig");
+        let delta_file = self.tmp_file("delta");
+        write(sig_file.as_str(), serde_json::to_vec(&signature)?).await?;
+        let result = self.fetch_remote_delta(path0, &sig_file, &delta_file).await;
+        remove_file(sig_file.as_str()).await.ok();
+        let result = match result {
+            Ok(()) => patch_file(path1, Path::new(delta_file.as_str())).await,
+            Err(e) => Err(e),
+        };
+        remove_file(delta_file.as_str()).await.ok();
+        result
+    }
+
+    async fn fetch_remote_delta(
+        &self,
+        path0: &str,
+        sig_file: &str,
+        delta_file: &str,
+    ) -> Result<(), Error> {
+        self.ssh
+            .run_scp(sig_file, &self.ssh.get_ssh_str(sig_file))
+            .await?;
+        let url0 = Self::remote_file_url(path0)?;
+        let delta_url = Self::remote_file_url(delta_file)?;
+        let sig_arg = shell_quote(sig_file);
+        let command =
+            format_sstr!("sync-app-rust delta --no-db -u {url0} -u {delta_url} -f {sig_arg}");
+        let result = self.ssh.run_command_stream_stdout(&command).await;
+        if result.is_ok() {
+            self.ssh
+                .run_scp(&self.ssh.get_ssh_str(delta_file), delta_file)
+                .await?;
+        }
+        let command = format_sstr!("rm -f {sig_arg} {}", shell_quote(delta_file));
+        self.ssh.run_command_ssh(&command).await?;
+        let summary = result?;
+        debug!("delta {path0} {}", summary.trim());
+        Ok(())
+    }
+}
+
+/// Wrap `arg` in single quotes so the remote shell passes it through as one
+/// word, embedded quotes become `'\''`
+fn shell_quote(arg: &str) -> StackString {
+    let mut quoted = StackString::from("'");
+    for c in arg.chars() {
+        if c == '\'' {
+            quoted.push_str(r"'\''");
+        } else {
+            quoted.push(c);
+        }
+    }
+    quoted.push('\'');
+    quoted
 }
 
 #[async_trait]
@@ -104,6 +237,16 @@ impl FileListTrait for FileListSSH {
                 create_dir_all(parent_dir)?;
             }
 
+            if self.use_delta(finfo0.filestat.st_size) && finfo1.filepath.exists() {
+                match self.delta_copy_from(&path0, &finfo1.filepath).await {
+                    Ok(bytes) => {
+                        info!("delta copy {url0} {bytes} bytes");
+                        return Ok(());
+                    }
+                    Err(e) => warn!("delta copy of {url0} failed, copying whole file: {e}"),
+                }
+            }
+
             self.ssh
                 .run_scp(
                     &self.ssh.get_ssh_str(&path0),
@@ -141,6 +284,16 @@ impl FileListTrait for FileListSSH {
             let command = format_sstr!("mkdir -p {parent_dir}");
             self.ssh.run_command_ssh(&command).await?;
 
+            if self.use_delta(finfo0.filestat.st_size) && finfo1.filestat.st_size > 0 {
+                match self.delta_copy_to(&finfo0.filepath, &path1).await {
+                    Ok(summary) => {
+                        info!("delta copy {url1} {summary}");
+                        return Ok(());
+                    }
+                    Err(e) => warn!("delta copy to {url1} failed, copying whole file: {e}"),
+                }
+            }
+
             self.ssh
                 .run_scp(
                     finfo0.filepath.to_string_lossy().as_ref(),
@@ -156,6 +309,10 @@ impl FileListTrait for FileListSSH {
         }
     }
 
+    fn supports_move(&self) -> bool {
+        true
+    }
+
     async fn move_file(
         &self,
         finfo0: &dyn FileInfoTrait,
@@ -187,14 +344,45 @@ impl FileListTrait for FileListSSH {
         self.ssh.run_command_ssh(&command).await
     }
 
+    async fn get_owner(&self, finfo: &dyn FileInfoTrait) -> Result<Option<Owner>, Error> {
+        let url = &finfo.get_finfo().urlname;
+        let path = Path::new(url.path()).to_string_lossy().replace(' ', r"\ ");
+        let command = format_sstr!("stat -c %U:%G {path}");
+        self.ssh
+            .run_command_stream_stdout(&command)
+            .await?
+            .parse()
+            .map(Some)
+    }
+
+    async fn set_owner(&self, finfo: &dyn FileInfoTrait, owner: &Owner) -> Result<(), Error> {
+        let url = &finfo.get_finfo().urlname;
+        let path = Path::new(url.path()).to_string_lossy().replace(' ', r"\ ");
+        let command = format_sstr!("chown {owner} {path}");
+        self.ssh.run_command_ssh(&command).await
+    }
+
+    async fn probe(&self) -> Result<(), Error> {
+        self.ssh.probe().await
+    }
+
+    async fn clock_offset(&self) -> Result<Duration, Error> {
+        let offset = self.ssh.clock_offset().await?;
+        let threshold = Duration::milliseconds(self.get_config().clock_skew_warning_ms);
+        if offset.abs() > threshold {
+            warn!(
+                "clock on {} is off by {:.3}s",
+                self.ssh.host,
+                offset.as_seconds_f64()
+            );
+        }
+        Ok(offset)
+    }
+
     async fn update_file_cache(&self) -> Result<usize, Error> {
         let path = self.get_basepath().to_string_lossy();
-        let user_host = self.ssh.get_ssh_username_host();
-        let user_host = user_host
-            .iter()
-            .last()
-            .ok_or_else(|| format_err!("No hostname"))?;
-        let url_prefix = format_sstr!("ssh://{user_host}");
+        let url_prefix = format_sstr!("ssh://{}", self.ssh.get_url_authority());
+        let tmp_prefix = self.ssh.host.replace(':', "_");
         let baseurl = self.get_baseurl().clone();
         let command = format_sstr!(r#"sync-app-rust index -u file://{path}"#);
         self.ssh.run_command_stream_stdout(&command).await?;
@@ -228,7 +416,7 @@ impl FileListTrait for FileListSSH {
                 debug!("expected {}", cached_urls.len());
 
                 let randint = thread_rng().next_u32();
-                let tmp_file = format_sstr!("/tmp/{user_host}_{randint}.json");
+                let tmp_file = format_sstr!("/tmp/{tmp_prefix}_{randint}.json");
                 let command = format_sstr!(
                     r#"sync-app-rust ser -u file://{path} -f {tmp_file} && gzip {tmp_file}"#
                 );
@@ -308,9 +496,19 @@ impl FileListTrait for FileListSSH {
         }
     }
 
-    async fn print_list(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+    async fn print_list(
+        &self,
+        stdout: &StdoutChannel<StackString>,
+        window: &ListWindow,
+    ) -> Result<(), Error> {
         let path = self.get_basepath().to_string_lossy();
-        let command = format_sstr!("sync-app-rust ls -u file://{path}");
+        let mut command = format_sstr!("sync-app-rust ls -u file://{path}");
+        if window.offset() > 0 {
+            command.push_str(&format_sstr!(" --offset {}", window.offset()));
+        }
+        if let Some(limit) = window.limit() {
+            command.push_str(&format_sstr!(" --limit {limit}"));
+        }
         stdout.send(&command);
         self.ssh.run_command_print_stdout(&command).await
     }
@@ -328,11 +526,24 @@ mod tests {
     use url::Url;
 
     use crate::{
-        config::Config, file_info_local::FileInfoLocal, file_info_ssh::FileInfoSSH,
-        file_list::FileListTrait, file_list_ssh::FileListSSH, file_service::FileService,
+        config::Config,
+        file_info_local::FileInfoLocal,
+        file_info_ssh::FileInfoSSH,
+        file_list::FileListTrait,
+        file_list_ssh::{shell_quote, FileListSSH},
+        file_service::FileService,
         pgpool::PgPool,
     };
 
+    #[test]
+    fn test_shell_quote() -> Result<(), Error> {
+        assert_eq!(shell_quote("/tmp/a b.txt").as_str(), "'/tmp/a b.txt'");
+        assert_eq!(shell_quote("it's $HOME").as_str(), r"'it'\''s $HOME'");
+        let url = FileListSSH::remote_file_url("/home/user/a b;rm -rf x.txt")?;
+        assert_eq!(url.as_str(), "'file:///home/user/a%20b;rm%20-rf%20x.txt'");
+        Ok(())
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_file_list_ssh_conf_from_url() -> Result<(), Error> {
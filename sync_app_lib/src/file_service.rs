@@ -1,8 +1,15 @@
 use anyhow::{format_err, Error};
-use serde::{Deserialize, Serialize};
-use std::{fmt, str::FromStr};
+use once_cell::sync::Lazy;
+use parking_lot::RwLock;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::{collections::HashSet, fmt, str::FromStr};
 
-#[derive(Copy, Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+// Experimental backends that live in this crate are registered up front so
+// that cached entries can be parsed before the backend itself is used
+static EXTENSIONS: Lazy<RwLock<HashSet<&'static str>>> =
+    Lazy::new(|| RwLock::new(["ipfs"].iter().copied().collect()));
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
 pub enum FileService {
     Local,
     GCS,
@@ -10,6 +17,11 @@ pub enum FileService {
     OneDrive,
     S3,
     SSH,
+    SFTP,
+    SMB,
+    /// Experimental backend identified by its url scheme, must be registered
+    /// with `FileService::register_extension` before it can be parsed
+    Extension(&'static str),
 }
 
 impl Default for FileService {
@@ -29,7 +41,13 @@ impl FromStr for FileService {
             "s3" => Ok(Self::S3),
             "gs" => Ok(Self::GCS),
             "ssh" => Ok(Self::SSH),
-            _ => Err(format_err!("Failed to parse FileService")),
+            "sftp" => Ok(Self::SFTP),
+            "smb" => Ok(Self::SMB),
+            _ => EXTENSIONS
+                .read()
+                .get(s)
+                .map(|scheme| Self::Extension(scheme))
+                .ok_or_else(|| format_err!("Failed to parse FileService")),
         }
     }
 }
@@ -44,6 +62,60 @@ impl FileService {
             Self::S3 => "s3",
             Self::GCS => "gs",
             Self::SSH => "ssh",
+            Self::SFTP => "sftp",
+            Self::SMB => "smb",
+            Self::Extension(scheme) => scheme,
+        }
+    }
+
+    /// True for object stores whose listings come back sorted by the
+    /// UTF-8 bytes of their keys
+    #[must_use]
+    pub fn lists_in_byte_order(self) -> bool {
+        matches!(self, Self::S3 | Self::GCS)
+    }
+
+    /// Register an experimental service under `scheme`, the scheme is what
+    /// gets stored in the `servicetype` column.
+    /// # Errors
+    /// Return error if `scheme` is a builtin service or not a valid url scheme
+    pub fn register_extension(scheme: &str) -> Result<Self, Error> {
+        if let Ok(service) = scheme.parse::<Self>() {
+            return match service {
+                Self::Extension(_) => Ok(service),
+                _ => Err(format_err!("{scheme} is a builtin service")),
+            };
+        }
+        let valid = scheme
+            .chars()
+            .next()
+            .map_or(false, |c| c.is_ascii_lowercase())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || "+-.".contains(c));
+        if !valid {
+            return Err(format_err!("Invalid scheme {scheme}"));
+        }
+        let mut extensions = EXTENSIONS.write();
+        let scheme: &'static str = match extensions.get(scheme) {
+            Some(scheme) => scheme,
+            None => Box::leak(scheme.to_string().into_boxed_str()),
+        };
+        extensions.insert(scheme);
+        Ok(Self::Extension(scheme))
+    }
+
+    fn variant_name(self) -> &'static str {
+        match self {
+            Self::Local => "Local",
+            Self::GCS => "GCS",
+            Self::GDrive => "GDrive",
+            Self::OneDrive => "OneDrive",
+            Self::S3 => "S3",
+            Self::SSH => "SSH",
+            Self::SFTP => "SFTP",
+            Self::SMB => "SMB",
+            Self::Extension(scheme) => scheme,
         }
     }
 }
@@ -53,3 +125,64 @@ impl fmt::Display for FileService {
         f.write_str(self.to_str())
     }
 }
+
+// Builtin services keep the variant names used by the derived impls so that
+// existing serialized data is still readable, extensions use their scheme.
+impl Serialize for FileService {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(self.variant_name())
+    }
+}
+
+impl<'de> Deserialize<'de> for FileService {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s = String::deserialize(deserializer)?;
+        match s.as_str() {
+            "Local" => Ok(Self::Local),
+            "GCS" => Ok(Self::GCS),
+            "GDrive" => Ok(Self::GDrive),
+            "OneDrive" => Ok(Self::OneDrive),
+            "S3" => Ok(Self::S3),
+            "SSH" => Ok(Self::SSH),
+            "SFTP" => Ok(Self::SFTP),
+            "SMB" => Ok(Self::SMB),
+            _ => s.parse().map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::file_service::FileService;
+
+    #[test]
+    fn test_register_extension() -> Result<(), Error> {
+        assert!("ipfs-test".parse::<FileService>().is_err());
+        let service = FileService::register_extension("ipfs-test")?;
+        assert_eq!(service, FileService::Extension("ipfs-test"));
+        assert_eq!(service.to_str(), "ipfs-test");
+        assert_eq!("ipfs-test".parse::<FileService>()?, service);
+        assert_eq!(FileService::register_extension("ipfs-test")?, service);
+        assert!(FileService::register_extension("s3").is_err());
+        assert!(FileService::register_extension("Not A Scheme").is_err());
+
+        let ser = serde_json::to_string(&service)?;
+        assert_eq!(ser, r#""ipfs-test""#);
+        assert_eq!(serde_json::from_str::<FileService>(&ser)?, service);
+        let ser = serde_json::to_string(&FileService::GDrive)?;
+        assert_eq!(ser, r#""GDrive""#);
+        assert_eq!(
+            serde_json::from_str::<FileService>(&ser)?,
+            FileService::GDrive
+        );
+        Ok(())
+    }
+}
@@ -1,25 +1,64 @@
 use anyhow::{format_err, Error};
 use fmt::Debug;
-use futures::{future::try_join_all, TryStreamExt};
-use log::debug;
+use futures::{
+    future::{self, join_all},
+    stream, StreamExt, TryStreamExt,
+};
+use log::{debug, error, info, warn};
+use once_cell::sync::OnceCell;
 use smallvec::{smallvec, SmallVec};
+use stack_string::{format_sstr, StackString};
 use std::{
-    collections::HashMap,
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
     convert::{From, TryInto},
     fmt,
     path::{Path, PathBuf},
     str::FromStr,
-    sync::Arc,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration as StdDuration, Instant},
+};
+use stdout_channel::{rate_limiter::RateLimiter, StdoutChannel};
+use time::{macros::format_description, Date, Duration, OffsetDateTime};
+use tokio::{
+    fs::{create_dir_all, remove_dir_all},
+    task::spawn_blocking,
+    time::sleep,
 };
 use url::Url;
 
+use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
 use crate::{
+    byte_accounting::ByteAccounting,
+    cancellation::{check_cancelled, is_cancelled, Cancelled},
+    compression::{original_info, Codec, Compression},
     config::Config,
+    conflict::{changed_since, conflict_copy, ConflictPolicy, Resolution},
+    encryption::{clear_info, scratch_dir, Encryption},
+    event_hook::{EventHook, HookEvent},
     file_info::{FileInfo, FileInfoKeyType, FileInfoTrait, FileStat},
-    file_list::{group_urls, replace_basepath, replace_baseurl, FileList, FileListTrait},
+    file_list::{
+        group_by_directory, group_urls, remove_baseurl, replace_basepath, replace_baseurl,
+        FileList, FileListTrait,
+    },
     file_service::FileService,
-    models::{CandidateIds, FileInfoCache, FileSyncCache},
+    ignore_errors::IgnoreRules,
+    layout::DestinationLayout,
+    log_routing::{with_log_scope, LogRoutes},
+    models::{
+        CandidateIds, CompressedFile, EncryptedFile, FileInfoCache, FileSyncCache, FileSyncConfig,
+        IndexRun, LayoutMapping, MaintenanceMode, SyncConflict, SyncEvent, SyncHistory,
+    },
+    move_detection::match_moves,
+    ownership::{OwnershipMap, OwnershipMaps},
+    partial::mark_partial,
+    path_validation::{case_collisions, PathRules, PathViolation},
     pgpool::PgPool,
+    progress::ProgressChannel,
+    provider_health::ProviderHealth,
+    storage_class::ArchivedObject,
+    trash::DeletionPolicy,
 };
 
 #[derive(Debug, PartialEq, Clone, Copy, Eq)]
@@ -43,6 +82,57 @@ pub enum FileSyncAction {
     SyncWeather,
     SyncAll,
     RunMigrations,
+    Restore,
+    DedupCache,
+    TagConfig,
+    EnableConfig,
+    DisableConfig,
+    MaintenanceOn,
+    MaintenanceOff,
+    Requeue,
+    IgnoreErrors,
+    TransferWorker,
+    MigrateSessions,
+    RenameSession,
+    SelfTest,
+    MapOwner,
+    Trend,
+    CostEstimate,
+    Exclusions,
+    ExportExclusions,
+    ImportExclusions,
+    CacheDelete,
+    CacheApprove,
+    CacheRequeue,
+    Retention,
+    ShowRuns,
+    Snapshot,
+    ConflictPolicy,
+    Conflicts,
+    ResolveConflict,
+    BackfillChecksums,
+    Verify,
+    VirtualRoot,
+    ScopeSessions,
+    PurgeTrash,
+    Compression,
+    Watch,
+    MaxIndexAge,
+    DeletionPolicy,
+    LinkFarm,
+    PruneGenerations,
+    DstLayout,
+    Signature,
+    Delta,
+    Patch,
+    ExportHashes,
+    ImportHashes,
+    AbortStaleUploads,
+    LogLevel,
+    StorageClass,
+    Fsck,
+    Sse,
+    GDriveDuplicates,
 }
 
 impl FromStr for FileSyncAction {
@@ -69,11 +159,169 @@ impl FromStr for FileSyncAction {
             "sync_weather" => Ok(Self::SyncWeather),
             "sync_all" => Ok(Self::SyncAll),
             "run-migrations" => Ok(Self::RunMigrations),
+            "restore" => Ok(Self::Restore),
+            "dedup_cache" => Ok(Self::DedupCache),
+            "tag" | "tag_config" => Ok(Self::TagConfig),
+            "enable" | "enable_config" => Ok(Self::EnableConfig),
+            "disable" | "disable_config" => Ok(Self::DisableConfig),
+            "maintenance_on" => Ok(Self::MaintenanceOn),
+            "maintenance_off" => Ok(Self::MaintenanceOff),
+            "requeue" => Ok(Self::Requeue),
+            "ignore_errors" => Ok(Self::IgnoreErrors),
+            "transfer-worker" | "transfer_worker" => Ok(Self::TransferWorker),
+            "migrate_sessions" => Ok(Self::MigrateSessions),
+            "rename_session" => Ok(Self::RenameSession),
+            "selftest" | "self_test" => Ok(Self::SelfTest),
+            "map_owner" | "ownership" => Ok(Self::MapOwner),
+            "trend" => Ok(Self::Trend),
+            "cost-estimate" | "cost_estimate" => Ok(Self::CostEstimate),
+            "exclusions" => Ok(Self::Exclusions),
+            "export_exclusions" => Ok(Self::ExportExclusions),
+            "import_exclusions" => Ok(Self::ImportExclusions),
+            "cache_delete" => Ok(Self::CacheDelete),
+            "cache_approve" => Ok(Self::CacheApprove),
+            "cache_requeue" => Ok(Self::CacheRequeue),
+            "retention" => Ok(Self::Retention),
+            "show_runs" => Ok(Self::ShowRuns),
+            "snapshot" => Ok(Self::Snapshot),
+            "conflict_policy" => Ok(Self::ConflictPolicy),
+            "conflicts" => Ok(Self::Conflicts),
+            "resolve_conflict" => Ok(Self::ResolveConflict),
+            "backfill_checksums" => Ok(Self::BackfillChecksums),
+            "verify" => Ok(Self::Verify),
+            "virtual_root" => Ok(Self::VirtualRoot),
+            "scope_sessions" => Ok(Self::ScopeSessions),
+            "purge_trash" => Ok(Self::PurgeTrash),
+            "compression" => Ok(Self::Compression),
+            "watch" => Ok(Self::Watch),
+            "max_index_age" => Ok(Self::MaxIndexAge),
+            "deletion_policy" => Ok(Self::DeletionPolicy),
+            "link_farm" => Ok(Self::LinkFarm),
+            "prune_generations" => Ok(Self::PruneGenerations),
+            "dst_layout" => Ok(Self::DstLayout),
+            "signature" => Ok(Self::Signature),
+            "delta" => Ok(Self::Delta),
+            "patch" => Ok(Self::Patch),
+            "export-hashes" | "export_hashes" => Ok(Self::ExportHashes),
+            "import-hashes" | "import_hashes" => Ok(Self::ImportHashes),
+            "abort-stale-uploads" | "abort_stale_uploads" => Ok(Self::AbortStaleUploads),
+            "log_level" => Ok(Self::LogLevel),
+            "storage_class" => Ok(Self::StorageClass),
+            "fsck" => Ok(Self::Fsck),
+            "sse" => Ok(Self::Sse),
+            "gdrive_duplicates" => Ok(Self::GDriveDuplicates),
             _ => Err(format_err!("Parse failure")),
         }
     }
 }
 
+impl FileSyncAction {
+    /// Actions that can run with `--no-db`, `FileListSSH` runs the delta
+    /// actions on the remote host
+    #[must_use]
+    pub fn is_stateless(self) -> bool {
+        matches!(
+            self,
+            Self::Copy | Self::List | Self::Signature | Self::Delta | Self::Patch
+        )
+    }
+
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Index => "index",
+            Self::Sync => "sync",
+            Self::Process => "process",
+            Self::Copy => "copy",
+            Self::List => "list",
+            Self::Delete => "delete",
+            Self::Move => "move",
+            Self::Count => "count",
+            Self::Serialize => "serialize",
+            Self::AddConfig => "add_config",
+            Self::ShowConfig => "show_config",
+            Self::ShowCache => "show_cache",
+            Self::SyncGarmin => "sync_garmin",
+            Self::SyncMovie => "sync_movie",
+            Self::SyncCalendar => "sync_calendar",
+            Self::SyncSecurity => "sync_security",
+            Self::SyncWeather => "sync_weather",
+            Self::SyncAll => "sync_all",
+            Self::RunMigrations => "run-migrations",
+            Self::Restore => "restore",
+            Self::DedupCache => "dedup_cache",
+            Self::TagConfig => "tag_config",
+            Self::EnableConfig => "enable_config",
+            Self::DisableConfig => "disable_config",
+            Self::MaintenanceOn => "maintenance_on",
+            Self::MaintenanceOff => "maintenance_off",
+            Self::Requeue => "requeue",
+            Self::IgnoreErrors => "ignore_errors",
+            Self::TransferWorker => "transfer-worker",
+            Self::MigrateSessions => "migrate_sessions",
+            Self::RenameSession => "rename_session",
+            Self::SelfTest => "selftest",
+            Self::MapOwner => "map_owner",
+            Self::Trend => "trend",
+            Self::CostEstimate => "cost-estimate",
+            Self::Exclusions => "exclusions",
+            Self::ExportExclusions => "export_exclusions",
+            Self::ImportExclusions => "import_exclusions",
+            Self::CacheDelete => "cache_delete",
+            Self::CacheApprove => "cache_approve",
+            Self::CacheRequeue => "cache_requeue",
+            Self::Retention => "retention",
+            Self::ShowRuns => "show_runs",
+            Self::Snapshot => "snapshot",
+            Self::ConflictPolicy => "conflict_policy",
+            Self::Conflicts => "conflicts",
+            Self::ResolveConflict => "resolve_conflict",
+            Self::BackfillChecksums => "backfill_checksums",
+            Self::Verify => "verify",
+            Self::VirtualRoot => "virtual_root",
+            Self::ScopeSessions => "scope_sessions",
+            Self::PurgeTrash => "purge_trash",
+            Self::Compression => "compression",
+            Self::Watch => "watch",
+            Self::MaxIndexAge => "max_index_age",
+            Self::DeletionPolicy => "deletion_policy",
+            Self::LinkFarm => "link_farm",
+            Self::PruneGenerations => "prune_generations",
+            Self::DstLayout => "dst_layout",
+            Self::Signature => "signature",
+            Self::Delta => "delta",
+            Self::Patch => "patch",
+            Self::ExportHashes => "export-hashes",
+            Self::ImportHashes => "import-hashes",
+            Self::AbortStaleUploads => "abort-stale-uploads",
+            Self::LogLevel => "log_level",
+            Self::StorageClass => "storage_class",
+            Self::Fsck => "fsck",
+            Self::Sse => "sse",
+            Self::GDriveDuplicates => "gdrive_duplicates",
+        }
+    }
+
+    /// Actions that are run automatically (cron, web ui), these are skipped
+    /// while maintenance mode is enabled
+    #[must_use]
+    pub fn is_automated(self) -> bool {
+        matches!(
+            self,
+            Self::Index
+                | Self::Sync
+                | Self::Process
+                | Self::SyncGarmin
+                | Self::SyncMovie
+                | Self::SyncCalendar
+                | Self::SyncSecurity
+                | Self::SyncWeather
+                | Self::SyncAll
+                | Self::Verify
+        )
+    }
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum FileSyncMode {
     OutputFile(PathBuf),
@@ -108,21 +356,98 @@ impl From<&str> for FileSyncMode {
 #[derive(Default, Debug)]
 pub struct FileSync {
     pub config: Config,
+    health: ProviderHealth,
+    deadline: Option<Instant>,
+    /// Start of the run, names the per config log files of every batch
+    run: Option<OffsetDateTime>,
+    deferred: AtomicUsize,
+    encryption: OnceCell<Option<Encryption>>,
 }
 
 impl FileSync {
     #[must_use]
     pub fn new(config: Config) -> Self {
-        Self { config }
+        let health = ProviderHealth::from_config(&config);
+        Self {
+            config,
+            health,
+            run: Some(OffsetDateTime::now_utc()),
+            ..Self::default()
+        }
+    }
+
+    /// Stop starting transfers at `deadline`, transfers already running are
+    /// finished and the rest of the entries put back in the queue
+    #[must_use]
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    fn past_deadline(&self) -> bool {
+        self.deadline.map_or(false, |d| Instant::now() >= d)
+    }
+
+    /// Encryption of the config, the key is read on first use
+    fn encryption(&self) -> Result<Option<&Encryption>, Error> {
+        self.encryption
+            .get_or_try_init(|| Encryption::from_config(&self.config))
+            .map(Option::as_ref)
+    }
+
+    /// Entries put back in the queue because the deadline passed
+    #[must_use]
+    pub fn deferred(&self) -> usize {
+        self.deferred.load(Ordering::SeqCst)
     }
 
+    /// Urls already indexed under the baseurl of `flist`, only loaded when
+    /// the backend is case-insensitive
+    async fn case_insensitive_urls(
+        flist: &dyn FileListTrait,
+        pool: &PgPool,
+    ) -> Result<Vec<StackString>, Error> {
+        let case_insensitive = PathRules::for_service(
+            flist.get_servicetype(),
+            flist.get_config().windows_safe_paths,
+        )
+        .map_or(false, |rules| rules.case_insensitive);
+        if !case_insensitive {
+            return Ok(Vec::new());
+        }
+        let baseurl = flist.get_baseurl().as_str();
+        FileInfoCache::get_all_cached(
+            flist.get_servicesession().as_str(),
+            flist.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .try_filter_map(
+            |f| async move { Ok(Some(f.urlname).filter(|url| url.starts_with(baseurl))) },
+        )
+        .try_collect()
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Queue copies for everything that differs between the two lists,
+    /// copies whose destination path the target can't store, or that would
+    /// overwrite a file whose name differs only in case, are skipped and
+    /// returned instead.  `conf` is the config syncing the two, `None` for
+    /// urls given on the command line.
     /// # Errors
-    /// Return error if db query fails
+    /// Return error if db query fails or the config's policy or layout is
+    /// invalid
     pub async fn compare_lists(
         flist0: &dyn FileListTrait,
         flist1: &dyn FileListTrait,
+        conf: Option<&FileSyncConfig>,
         pool: &PgPool,
-    ) -> Result<(), Error> {
+    ) -> Result<Vec<PathViolation>, Error> {
+        let max_index_age = conf.and_then(|c| c.max_index_age);
+        Self::check_index_run(flist0, max_index_age, pool).await?;
+        Self::check_index_run(flist1, max_index_age, pool).await?;
         let count0 = FileInfoCache::count_cached(
             flist0.get_servicesession().as_str(),
             flist0.get_servicetype().to_str(),
@@ -142,6 +467,33 @@ impl FileSync {
             flist0.get_baseurl(),
             flist1.get_baseurl(),
         );
+        let mtime_tolerance = Duration::milliseconds(flist0.get_config().mtime_tolerance_ms);
+        let clock_skew = flist0.clock_offset().await? - flist1.clock_offset().await?;
+        let node_id = flist0.get_config().node_id();
+        let policy = conf
+            .map(FileSyncConfig::get_conflict_policy)
+            .transpose()?
+            .unwrap_or_default();
+        let layout = conf
+            .map(FileSyncConfig::get_dst_layout)
+            .transpose()?
+            .unwrap_or_default();
+        if layout != DestinationLayout::Flat {
+            if count0 == 0 {
+                warn!("nothing indexed for {}", flist0.get_baseurl());
+                return Self::queue_copies(flist0, flist1, Vec::new(), Vec::new(), pool).await;
+            }
+            return Self::compare_partitioned(
+                flist0,
+                flist1,
+                layout,
+                policy,
+                mtime_tolerance,
+                clock_skew,
+                pool,
+            )
+            .await;
+        }
         let mut list_a_not_b: Vec<(FileInfo, FileInfo)> = Vec::new();
         let mut list_b_not_a: Vec<(FileInfo, FileInfo)> = Vec::new();
 
@@ -149,6 +501,7 @@ impl FileSync {
             flist0.get_baseurl().as_str(),
             flist1.get_baseurl().as_str(),
             flist0.get_servicesession().as_str(),
+            flist1.get_servicesession().as_str(),
             pool,
         )
         .await?
@@ -163,6 +516,13 @@ impl FileSync {
                 return Err(format_err!("{baseurl1} not in {url1}"));
             }
             let finfo0: FileInfo = finfo0.try_into()?;
+            // written under an obfuscated name, see `Encryption`
+            if let Some(finfo1) = Self::encrypted_counterpart(flist1, &url1, pool).await? {
+                if Self::compare_objects(&finfo0, &finfo1, mtime_tolerance, clock_skew) {
+                    list_a_not_b.push((finfo0, finfo1));
+                }
+                continue;
+            }
             let finfo1: FileInfo = FileInfo::new(
                 finfo0.filename.clone(),
                 path1.into(),
@@ -192,11 +552,29 @@ impl FileSync {
         for CandidateIds { f0id, f1id } in candidates {
             if let Some(finfo0) = FileInfoCache::get_by_id(f0id, pool).await? {
                 if let Some(finfo1) = FileInfoCache::get_by_id(f1id, pool).await? {
-                    let finfo0: FileInfo = finfo0.try_into()?;
-                    let finfo1: FileInfo = finfo1.try_into()?;
-                    if Self::compare_objects(&finfo0, &finfo1) {
-                        list_a_not_b.push((finfo0, finfo1));
-                    }
+                    let finfo0 = clear_info(finfo0.try_into()?, pool).await?;
+                    let finfo1 = clear_info(finfo1.try_into()?, pool).await?;
+                    let finfo0 = original_info(finfo0, pool).await?;
+                    let finfo1 = original_info(finfo1, pool).await?;
+                    let resolution = Self::resolve_pair(
+                        &finfo0,
+                        &finfo1,
+                        policy,
+                        mtime_tolerance,
+                        clock_skew,
+                        &node_id,
+                        pool,
+                    )
+                    .await?;
+                    Self::push_resolution(
+                        resolution,
+                        finfo0,
+                        finfo1,
+                        &mut list_a_not_b,
+                        &mut list_b_not_a,
+                        pool,
+                    )
+                    .await?;
                 }
             }
         }
@@ -205,12 +583,15 @@ impl FileSync {
             flist1.get_baseurl().as_str(),
             flist0.get_baseurl().as_str(),
             flist1.get_servicesession().as_str(),
+            flist0.get_servicesession().as_str(),
             pool,
         )
         .await?
         {
-            let path1 = Path::new(&finfo1.filepath);
-            let url1 = &finfo1.urlname.parse()?;
+            let encrypted_url = finfo1.urlname.clone();
+            let finfo1 = clear_info(finfo1.try_into()?, pool).await?;
+            let path1 = &finfo1.filepath;
+            let url1 = &finfo1.urlname;
             let baseurl0 = flist0.get_baseurl();
             let baseurl1 = flist1.get_baseurl();
             let url0 = replace_baseurl(url1, baseurl1, baseurl0)?;
@@ -218,6 +599,15 @@ impl FileSync {
             if !url0.as_str().contains(baseurl0.as_str()) {
                 return Err(format_err!("{baseurl0} not in {url1}"));
             }
+            // an obfuscated object whose clear file is still there was
+            // compared above
+            if url1.as_str() != encrypted_url.as_str()
+                && FileInfo::from_database(pool, &url0, flist0.get_servicesession().as_str())
+                    .await?
+                    .is_some()
+            {
+                continue;
+            }
             let finfo0 = FileInfo::new(
                 finfo1.filename.clone(),
                 path0.into(),
@@ -229,22 +619,476 @@ impl FileSync {
                 flist0.get_servicetype(),
                 flist0.get_servicesession().clone(),
             );
-            let finfo1: FileInfo = finfo1.try_into()?;
             debug!("ba {:?} {:?}", finfo0, finfo1);
             list_b_not_a.push((finfo1, finfo0));
         }
         debug!("ab {} ba {}", list_a_not_b.len(), list_b_not_a.len());
+        Self::queue_copies(flist0, flist1, list_a_not_b, list_b_not_a, pool).await
+    }
+
+    /// Which way a file present on both sides goes: forward if it differs and
+    /// was never synced, otherwise by which side changed since the last sync
+    /// (see `SyncHistory`), conflicts are recorded and settled by `policy`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn resolve_pair(
+        finfo0: &FileInfo,
+        finfo1: &FileInfo,
+        policy: ConflictPolicy,
+        mtime_tolerance: Duration,
+        clock_skew: Duration,
+        node_id: &str,
+        pool: &PgPool,
+    ) -> Result<Resolution, Error> {
+        let history = SyncHistory::get(
+            finfo0.urlname.as_str(),
+            finfo1.urlname.as_str(),
+            node_id,
+            pool,
+        )
+        .await?;
+        let history = match history {
+            Some(history) => history,
+            None => {
+                if Self::compare_objects(finfo0, finfo1, mtime_tolerance, clock_skew) {
+                    return Ok(Resolution::Forward);
+                }
+                return Ok(Resolution::Skip);
+            }
+        };
+        let resolution = match (
+            changed_since(&history, finfo0),
+            changed_since(&history, finfo1),
+        ) {
+            (true, false) => Resolution::Forward,
+            (false, true) => Resolution::Reverse,
+            (false, false) => Resolution::Skip,
+            (true, true) => {
+                let resolution = policy.resolve(finfo0, finfo1, mtime_tolerance, clock_skew);
+                warn!("conflict {} {} ({policy})", finfo0.urlname, finfo1.urlname);
+                SyncConflict {
+                    src_url: finfo0.urlname.as_str().into(),
+                    dst_url: finfo1.urlname.as_str().into(),
+                    src_size: finfo0.filestat.st_size,
+                    dst_size: finfo1.filestat.st_size,
+                    src_mtime: finfo0.filestat.st_mtime,
+                    dst_mtime: finfo1.filestat.st_mtime,
+                    policy: policy.to_str().into(),
+                    created_at: DateTimeWrapper::now(),
+                    resolved_at: if resolution == Resolution::Skip {
+                        None
+                    } else {
+                        Some(DateTimeWrapper::now())
+                    },
+                    copy_url: None,
+                }
+                .upsert(pool)
+                .await?;
+                resolution
+            }
+        };
+        Ok(resolution)
+    }
+
+    /// Queue the copy `resolution` calls for on the matching list, for
+    /// `KeepBoth` the copy setting the loser aside is noted on the conflict
+    /// so that the pair is only recorded as synced once it's done
+    /// # Errors
+    /// Return error if db query fails or the conflict copy has no valid url
+    pub async fn push_resolution(
+        resolution: Resolution,
+        finfo0: FileInfo,
+        finfo1: FileInfo,
+        list_a_not_b: &mut Vec<(FileInfo, FileInfo)>,
+        list_b_not_a: &mut Vec<(FileInfo, FileInfo)>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        match resolution {
+            Resolution::Forward => list_a_not_b.push((finfo0, finfo1)),
+            Resolution::Reverse => list_b_not_a.push((finfo1, finfo0)),
+            Resolution::KeepBoth { forward } => {
+                let (winner, loser) = if forward {
+                    (&finfo0, &finfo1)
+                } else {
+                    (&finfo1, &finfo0)
+                };
+                let today = OffsetDateTime::now_utc().date();
+                let copy = conflict_copy(winner, loser, today)?;
+                // the winner replaces the loser on the first run after the
+                // loser has been copied aside (see `copy_cache_entry`)
+                SyncConflict::set_copy(
+                    finfo0.urlname.as_str(),
+                    finfo1.urlname.as_str(),
+                    copy.urlname.as_str(),
+                    pool,
+                )
+                .await?;
+                if forward {
+                    list_b_not_a.push((finfo1, copy));
+                } else {
+                    list_a_not_b.push((finfo0, copy));
+                }
+            }
+            Resolution::Skip => {}
+        }
+        Ok(())
+    }
+
+    /// Clear view (see `clear_info`) of the object `clear_url` was written
+    /// to under an obfuscated name, if it's still there unchanged
+    async fn encrypted_counterpart(
+        flist: &dyn FileListTrait,
+        clear_url: &Url,
+        pool: &PgPool,
+    ) -> Result<Option<FileInfo>, Error> {
+        if !Encryption::applies_to(flist.get_servicetype()) {
+            return Ok(None);
+        }
+        let entry = match EncryptedFile::get_by_clear_url(clear_url.as_str(), pool).await? {
+            Some(entry) if entry.encrypted_url != clear_url.as_str() => entry,
+            _ => return Ok(None),
+        };
+        let encrypted_url: Url = entry.encrypted_url.parse()?;
+        let session = flist.get_servicesession().as_str();
+        match FileInfo::from_database(pool, &encrypted_url, session).await? {
+            Some(finfo) => {
+                let finfo = clear_info(finfo, pool).await?;
+                Ok(Some(finfo).filter(|f| f.urlname.as_str() == clear_url.as_str()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// One way comparison for a config whose destination is partitioned by
+    /// mtime: each source file is matched with where it was placed before
+    /// (see `LayoutMapping`), or where `layout` puts it now.  Files on both
+    /// sides are resolved against `SyncHistory` like a flat comparison, files
+    /// only on the destination are never copied back, they can't be mapped
+    /// to a source path.  New placements are recorded once the copies are
+    /// queued.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn compare_partitioned(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        layout: DestinationLayout,
+        policy: ConflictPolicy,
+        mtime_tolerance: Duration,
+        clock_skew: Duration,
+        pool: &PgPool,
+    ) -> Result<Vec<PathViolation>, Error> {
+        let baseurl0 = flist0.get_baseurl();
+        let baseurl1 = flist1.get_baseurl();
+        let node_id = flist0.get_config().node_id();
+        let mappings =
+            LayoutMapping::get_mappings(baseurl0.as_str(), baseurl1.as_str(), pool).await?;
+        let mut existing: HashMap<StackString, FileInfoCache> = FileInfoCache::get_all_cached(
+            flist1.get_servicesession().as_str(),
+            flist1.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .map_ok(|f| (f.urlname.clone(), f))
+        .try_collect()
+        .await?;
+        let sources: Vec<FileInfoCache> = FileInfoCache::get_all_cached(
+            flist0.get_servicesession().as_str(),
+            flist0.get_servicetype().to_str(),
+            pool,
+            false,
+        )
+        .await?
+        .try_filter(|f| future::ready(f.urlname.starts_with(baseurl0.as_str())))
+        .try_collect()
+        .await?;
+
+        let mut list_a_not_b: Vec<(FileInfo, FileInfo)> = Vec::new();
+        let mut list_b_not_a: Vec<(FileInfo, FileInfo)> = Vec::new();
+        let mut new_src_urls: Vec<StackString> = Vec::new();
+        let mut new_dst_urls: Vec<StackString> = Vec::new();
+        for entry in sources {
+            let url0: Url = entry.urlname.parse()?;
+            let finfo0: FileInfo = entry.try_into()?;
+            let url1 = match mappings.get(url0.as_str()) {
+                Some(dst) => dst.parse()?,
+                None => {
+                    let url1 = layout.destination_url(
+                        &url0,
+                        baseurl0,
+                        baseurl1,
+                        finfo0.filestat.st_mtime.to_offsetdatetime(),
+                    )?;
+                    new_src_urls.push(url0.as_str().into());
+                    new_dst_urls.push(url1.as_str().into());
+                    url1
+                }
+            };
+            match existing.remove(url1.as_str()) {
+                Some(finfo1) => {
+                    let finfo1 = original_info(finfo1.try_into()?, pool).await?;
+                    let resolution = Self::resolve_pair(
+                        &finfo0,
+                        &finfo1,
+                        policy,
+                        mtime_tolerance,
+                        clock_skew,
+                        &node_id,
+                        pool,
+                    )
+                    .await?;
+                    Self::push_resolution(
+                        resolution,
+                        finfo0,
+                        finfo1,
+                        &mut list_a_not_b,
+                        &mut list_b_not_a,
+                        pool,
+                    )
+                    .await?;
+                }
+                None => {
+                    let finfo1 = FileInfo::from_url(&url1)?;
+                    debug!("ab {} {}", finfo0.urlname, finfo1.urlname);
+                    list_a_not_b.push((finfo0, finfo1));
+                }
+            }
+        }
+        debug!(
+            "ab {} ba {} ({layout})",
+            list_a_not_b.len(),
+            list_b_not_a.len()
+        );
+        let violations =
+            Self::queue_copies(flist0, flist1, list_a_not_b, list_b_not_a, pool).await?;
+        LayoutMapping::insert_batch(&new_src_urls, &new_dst_urls, pool).await?;
+        Ok(violations)
+    }
+
+    /// Queue the copies found by a comparison, skipping (and returning) those
+    /// whose destination path isn't valid on the target, and those already
+    /// covered by a queued move (see `follow_moves`)
+    async fn queue_copies(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        list_a_not_b: Vec<(FileInfo, FileInfo)>,
+        list_b_not_a: Vec<(FileInfo, FileInfo)>,
+        pool: &PgPool,
+    ) -> Result<Vec<PathViolation>, Error> {
         if list_a_not_b.is_empty() && list_b_not_a.is_empty() {
-            flist0.cleanup().and_then(|()| flist1.cleanup())
+            flist0.cleanup().and_then(|()| flist1.cleanup())?;
+            Ok(Vec::new())
         } else {
-            for (f0, f1) in list_a_not_b.into_iter().chain(list_b_not_a.into_iter()) {
-                FileSyncCache::cache_sync(pool, f0.urlname.as_str(), f1.urlname.as_str()).await?;
+            let config = flist0.get_config();
+            let windows_safe_paths = config.windows_safe_paths;
+            let mut violations = Vec::new();
+            let moving: HashSet<StackString> = FileSyncCache::get_moves(pool)
+                .await?
+                .into_iter()
+                .flat_map(|entry| entry.move_from.into_iter().chain(Some(entry.dst_url)))
+                .collect();
+            for (copies, target) in vec![(list_a_not_b, flist1), (list_b_not_a, flist0)] {
+                let existing = Self::case_insensitive_urls(target, pool).await?;
+                let mut colliding = HashSet::new();
+                for (index, violation) in case_collisions(
+                    &copies,
+                    existing.iter().map(StackString::as_str),
+                    windows_safe_paths,
+                ) {
+                    warn!("{violation}");
+                    colliding.insert(index);
+                    violations.push(violation);
+                }
+                for (index, (f0, f1)) in copies.into_iter().enumerate() {
+                    if colliding.contains(&index)
+                        || moving.contains(f0.urlname.as_str())
+                        || moving.contains(f1.urlname.as_str())
+                    {
+                        continue;
+                    }
+                    if let Some(violation) = PathViolation::check(&f0, &f1, windows_safe_paths) {
+                        warn!("{violation}");
+                        violations.push(violation);
+                        continue;
+                    }
+                    FileSyncCache::cache_sync(
+                        pool,
+                        f0.urlname.as_str(),
+                        f1.urlname.as_str(),
+                        FileSyncCache::PRIORITY_BULK,
+                        &config.node_id(),
+                    )
+                    .await?;
+                    EventHook::emit(
+                        config,
+                        &HookEvent::queued(f0.urlname.as_str(), f1.urlname.as_str()),
+                    );
+                }
+            }
+            Ok(violations)
+        }
+    }
+
+    /// Refuse (or warn, if `refuse_stale_index` is unset) when the last index
+    /// of `flist` didn't complete or is older than `index_max_age_secs` (or
+    /// the config's `max_index_age` if larger), a stale cache would queue
+    /// copies over newer files
+    /// # Errors
+    /// Return error if db query fails or the index is stale
+    pub async fn check_index_run(
+        flist: &dyn FileListTrait,
+        max_index_age: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let config = flist.get_config();
+        let max_age = config.index_max_age_secs.max(max_index_age.unwrap_or(0));
+        let baseurl = flist.get_baseurl();
+        let problem = match IndexRun::get_last(
+            flist.get_servicetype().to_str(),
+            flist.get_servicesession().as_str(),
+            baseurl.as_str(),
+            pool,
+        )
+        .await?
+        {
+            None => {
+                warn!("no index run recorded for {baseurl}");
+                return Ok(());
             }
+            Some(run) if !run.complete => {
+                format_sstr!(
+                    "last index of {baseurl} started {} is incomplete",
+                    run.started_at
+                )
+            }
+            Some(run) => {
+                let age = OffsetDateTime::now_utc() - run.started_at.to_offsetdatetime();
+                if age <= Duration::seconds(max_age) {
+                    return Ok(());
+                }
+                format_sstr!(
+                    "last index of {baseurl} is stale, {}s old",
+                    age.whole_seconds()
+                )
+            }
+        };
+        if config.refuse_stale_index {
+            Err(format_err!("{problem}, refusing to compare"))
+        } else {
+            warn!("{problem}");
             Ok(())
         }
     }
 
-    pub fn compare_objects<T, U>(finfo0: &T, finfo1: &U) -> bool
+    /// Whether `flist` needs indexing before a sync, a side whose last
+    /// complete index is younger than `max_index_age` seconds is trusted as
+    /// is
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn needs_index(
+        flist: &dyn FileListTrait,
+        max_index_age: Option<i64>,
+        pool: &PgPool,
+    ) -> Result<bool, Error> {
+        let max_age = match max_index_age {
+            Some(max_age) => max_age,
+            None => return Ok(true),
+        };
+        let last = IndexRun::get_last(
+            flist.get_servicetype().to_str(),
+            flist.get_servicesession().as_str(),
+            flist.get_baseurl().as_str(),
+            pool,
+        )
+        .await?;
+        Ok(!last.map_or(false, |run| {
+            run.is_fresh(max_age, OffsetDateTime::now_utc())
+        }))
+    }
+
+    /// Recursive one-shot copy of `flist0` onto `flist1` with both sides
+    /// listed and compared in memory, nothing is read from or written to the
+    /// database so sync history, conflict policies and per config
+    /// compression / encryption don't apply.  Returns the number of files
+    /// copied
+    /// # Errors
+    /// Return error if either listing fails or any copy fails
+    pub async fn copy_stateless(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<usize, Error> {
+        let t0 = flist0.get_servicetype();
+        let t1 = flist1.get_servicetype();
+        if t0 != FileService::Local && t1 != FileService::Local {
+            return Err(format_err!(
+                "Can't copy {t0} to {t1}, one side must be local"
+            ));
+        }
+        let mtime_tolerance = Duration::milliseconds(flist0.get_config().mtime_tolerance_ms);
+        let clock_skew = flist0.clock_offset().await? - flist1.clock_offset().await?;
+        let baseurl0 = flist0.get_baseurl();
+        let baseurl1 = flist1.get_baseurl();
+        let mut existing: HashMap<StackString, FileInfo> = flist1
+            .list_files()
+            .await?
+            .into_iter()
+            .map(|f| (f.urlname.as_str().into(), f))
+            .collect();
+        let mut copies = Vec::new();
+        for finfo0 in flist0.list_files().await? {
+            let url1 = replace_baseurl(&finfo0.urlname, baseurl0, baseurl1)?;
+            let finfo1 = match existing.remove(url1.as_str()) {
+                Some(finfo1) => {
+                    if !Self::compare_objects(&finfo0, &finfo1, mtime_tolerance, clock_skew) {
+                        continue;
+                    }
+                    finfo1
+                }
+                None => {
+                    let path1 = replace_basepath(
+                        &finfo0.filepath,
+                        flist0.get_basepath(),
+                        flist1.get_basepath(),
+                    );
+                    FileInfo::new(
+                        finfo0.filename.clone(),
+                        path1.into(),
+                        url1.into(),
+                        None,
+                        None,
+                        FileStat::default(),
+                        flist1.get_servicesession().clone().into(),
+                        t1,
+                        flist1.get_servicesession().clone(),
+                    )
+                }
+            };
+            copies.push((finfo0, finfo1));
+        }
+        for (finfo0, finfo1) in &copies {
+            debug!("copy {} {}", finfo0.urlname, finfo1.urlname);
+            let flist = if t1 == FileService::Local {
+                flist0
+            } else {
+                flist1
+            };
+            Self::copy_plain(flist, finfo0, finfo1).await?;
+            stdout.send(format_sstr!("{} {}", finfo0.urlname, finfo1.urlname));
+        }
+        Ok(copies.len())
+    }
+
+    /// True if `finfo0` should be copied over `finfo1`, differences in mtime
+    /// smaller than `mtime_tolerance` are treated as clock skew and ignored.
+    /// `clock_skew` is how far the clock of `finfo0`'s host runs ahead of
+    /// `finfo1`'s host
+    pub fn compare_objects<T, U>(
+        finfo0: &T,
+        finfo1: &U,
+        mtime_tolerance: Duration,
+        clock_skew: Duration,
+    ) -> bool
     where
         T: FileInfoTrait + Send + Sync,
         U: FileInfoTrait + Send + Sync,
@@ -267,7 +1111,11 @@ impl FileSync {
         if is_export {
             do_update = false;
         }
-        if finfo0.filestat.st_mtime > finfo1.filestat.st_mtime {
+        if finfo0
+            .filestat
+            .with_clock_offset(clock_skew)
+            .is_newer_than(&finfo1.filestat, mtime_tolerance)
+        {
             do_update = true;
         }
         if finfo0.filestat.st_size != finfo1.filestat.st_size && !is_export {
@@ -295,83 +1143,480 @@ impl FileSync {
         do_update
     }
 
+    /// Copy every entry in the sync cache, copy failures are collected and
+    /// reported once all entries have been processed.
     /// # Errors
-    /// Return error if db query fails
-    pub async fn process_sync_cache(&self, pool: &PgPool) -> Result<(), Error> {
-        let proc_map: Result<HashMap<_, _>, Error> = FileSyncCache::get_cache_list(pool)
-            .await?
-            .map_err(Into::into)
-            .try_fold(HashMap::new(), |mut h: HashMap<_, Vec<_>>, v| async move {
-                let u0: Url = v.src_url.parse()?;
-                let u1: Url = v.dst_url.parse()?;
-                v.delete_cache_entry(pool).await?;
-                h.entry(u0).or_default().push(u1);
-                Ok(h)
-            })
-            .await;
-        let proc_map = Arc::new(proc_map?);
+    /// Return error if db query fails or any copy fails
+    pub async fn process_sync_cache(
+        &self,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        let entries = FileSyncCache::take_batch(pool, None, &self.config.node_id()).await?;
+        self.process_cache_entries(entries, pool, stdout).await
+    }
 
-        let key_list: Vec<_> = proc_map.keys().cloned().collect();
+    /// Copy every entry, the entries must already have been removed from the
+    /// queue.  Entries of a higher priority are all copied before any of a
+    /// lower one.  Within a priority entries are grouped by destination
+    /// directory so each directory is created and looked up only once.
+    /// Gdrive directories are processed concurrently with at most
+    /// `gdrive_parent_concurrency` uploads each, other directories one at a
+    /// time.  Transfers to and from remote backends are throttled by
+    /// `ProviderHealth`.  Each directory is logged with the `LogScope` of the
+    /// config it belongs to.  Queued moves (see `follow_moves`) are done
+    /// first, one that fails is copied instead.  Once the deadline set by `with_deadline` has
+    /// passed no new copy is started, the remaining entries are queued again.
+    /// After a cancellation no new directory is started, those already being
+    /// copied are finished before `Cancelled` is returned.
+    /// # Errors
+    /// Return error if db query fails or any copy fails
+    pub async fn process_cache_entries(
+        &self,
+        entries: Vec<FileSyncCache>,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        let mut lanes: BTreeMap<Reverse<i32>, Vec<(Url, Url)>> = BTreeMap::new();
+        let mut moves = Vec::new();
+        for v in entries {
+            let u0: Url = v.src_url.parse()?;
+            let u1: Url = v.dst_url.parse()?;
+            match v.move_from {
+                Some(move_from) => moves.push((u0, u1, move_from, v.priority)),
+                None => lanes.entry(Reverse(v.priority)).or_default().push((u0, u1)),
+            }
+        }
+        let ignore_rules = IgnoreRules::from_db(pool).await?;
+        let ownership = OwnershipMaps::from_db(pool).await?;
+        let run = self.run.unwrap_or_else(OffsetDateTime::now_utc);
+        let log_routes = LogRoutes::from_db(&self.config, run, pool).await?;
+        let mut failures: Vec<(Url, Error)> = Vec::new();
 
-        for urls in group_urls(&key_list).values() {
-            if let Some(u0) = urls.first() {
-                let futures = urls.iter().map(|key| {
-                    let key = key.clone();
-                    let proc_map = proc_map.clone();
-                    let u0 = u0.clone();
-                    async move {
-                        if let Some(vals) = proc_map.get(&key) {
-                            let flist0 = FileList::from_url(&u0, &self.config, pool).await?;
-                            for val in vals {
-                                let flist1 = FileList::from_url(val, &self.config, pool).await?;
-                                let finfo0 = match FileInfo::from_database(
-                                    pool,
-                                    &key,
-                                    flist0.get_servicesession().as_str(),
-                                )
-                                .await?
-                                {
-                                    Some(f) => f,
-                                    None => FileInfo::from_url(&key)?,
-                                };
-                                let finfo1 = match FileInfo::from_database(
-                                    pool,
-                                    val,
-                                    flist1.get_servicesession().as_str(),
-                                )
-                                .await?
-                                {
-                                    Some(f) => f,
-                                    None => FileInfo::from_url(val)?,
-                                };
-                                debug!("copy {} {}", key, val);
-                                if finfo1.servicetype == FileService::Local {
-                                    Self::copy_object(&(*flist0), &finfo0, &finfo1).await?;
-                                    flist0.cleanup()?;
-                                } else {
-                                    Self::copy_object(&(*flist1), &finfo0, &finfo1).await?;
-                                    flist1.cleanup()?;
-                                }
-                            }
-                        }
-                        Ok(())
+        // a move that can't be done is copied along with the other entries
+        for (src, dst, move_from, priority) in moves {
+            check_cancelled()?;
+            if self.past_deadline() {
+                match FileSyncCache::cache_move(
+                    pool,
+                    src.as_str(),
+                    dst.as_str(),
+                    &move_from,
+                    priority,
+                    &self.config.node_id(),
+                )
+                .await
+                {
+                    Ok(()) => {
+                        self.deferred.fetch_add(1, Ordering::SeqCst);
                     }
-                });
-                let result: Result<Vec<()>, Error> = try_join_all(futures).await;
-                result?;
+                    Err(e) => failures.push((src, e)),
+                }
+                continue;
+            }
+            match self.move_cache_entry(&dst, &move_from, pool).await {
+                Ok(()) => stdout.send(format_sstr!("moved {move_from} {dst}")),
+                Err(e) => {
+                    warn!("moving {move_from} failed, copying instead {e}");
+                    lanes.entry(Reverse(priority)).or_default().push((src, dst));
+                }
+            }
+        }
+
+        for (Reverse(priority), pairs) in lanes {
+            debug!("copy {} entries of priority {priority}", pairs.len());
+            // gdrive folders are filled concurrently (each with a limited
+            // number of uploads), everything else `sync_parallel_pairs`
+            // directories at a time, `ProviderHealth` caps the transfers
+            // per backend across all of them
+            let (gdrive, other): (Vec<_>, Vec<_>) = group_by_directory(pairs)
+                .into_iter()
+                .partition(|(directory, _)| directory.starts_with("gdrive://"));
+            let futures = other.iter().map(|(directory, pairs)| async {
+                check_cancelled()?;
+                let failures = with_log_scope(
+                    log_routes.scope_for(directory),
+                    self.copy_directory_failures(directory, pairs, priority, &ownership, pool),
+                )
+                .await;
+                Ok::<_, Cancelled>(failures)
+            });
+            let results: Vec<_> = stream::iter(futures)
+                .buffer_unordered(self.config.sync_parallel_pairs.max(1))
+                .collect()
+                .await;
+            for result in results {
+                failures.extend(result?);
+            }
+            check_cancelled()?;
+            let futures = gdrive.iter().map(|(directory, pairs)| {
+                with_log_scope(
+                    log_routes.scope_for(directory),
+                    self.copy_directory_failures(directory, pairs, priority, &ownership, pool),
+                )
+            });
+            failures.extend(join_all(futures).await.into_iter().flatten());
+        }
+        // files skipped by a cancellation are queued again by the next `sync`
+        check_cancelled()?;
+        let deferred = self.deferred();
+        if deferred > 0 {
+            mark_partial();
+            stdout.send(format_sstr!(
+                "partial: out of time, {deferred} transfers left in the queue"
+            ));
+        }
+        report_failures("copy", failures, &ignore_rules, stdout)
+    }
+
+    async fn copy_directory_failures(
+        &self,
+        directory: &str,
+        pairs: &[(Url, Url)],
+        priority: i32,
+        ownership: &OwnershipMaps,
+        pool: &PgPool,
+    ) -> Vec<(Url, Error)> {
+        if self.past_deadline() {
+            let futures = pairs
+                .iter()
+                .map(|(src, dst)| self.defer(src, dst, priority, pool));
+            return join_all(futures).await.into_iter().flatten().collect();
+        }
+        debug!("copy {} files into {directory}", pairs.len());
+        match self
+            .copy_directory(directory, pairs, priority, ownership, pool)
+            .await
+        {
+            Ok(failures) => failures,
+            Err(e) => {
+                error!("failed to set up {directory} {e}");
+                pairs
+                    .iter()
+                    .map(|(src, _)| (src.clone(), format_err!("{directory}: {e}")))
+                    .collect()
             }
         }
+    }
+
+    /// Move the copy `move_from` on the destination side to `dst`, and its
+    /// cache entry along with it
+    async fn move_cache_entry(
+        &self,
+        dst: &Url,
+        move_from: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let flist1 = FileList::from_url(dst, &self.config, pool).await?;
+        let url: Url = move_from.parse()?;
+        let old = FileInfoCache::get_by_urlname(&url, flist1.get_servicesession().as_str(), pool)
+            .await?
+            .ok_or_else(|| format_err!("{move_from} is no longer cached"))?;
+        let key = old.get_key();
+        let old: FileInfo = old.try_into()?;
+        let new = FileInfo::from_url(dst)?;
+        let mut inner = old.inner().clone();
+        inner.filename.clone_from(&new.filename);
+        inner.filepath.clone_from(&new.filepath);
+        inner.urlname.clone_from(&new.urlname);
+        let finfo1 = FileInfo::from_inner(inner);
+        let directory = dst
+            .as_str()
+            .rsplit_once('/')
+            .map_or(dst.as_str(), |(d, _)| d);
+        flist1
+            .create_directory(&format_sstr!("{directory}/").parse()?)
+            .await?;
+        flist1.move_file(&old, &finfo1).await?;
+        if let Some(key) = key {
+            key.delete_cache_entry(pool).await?;
+        }
+        let entry: FileInfoCache = (&finfo1).into();
+        entry.insert(pool).await?;
         Ok(())
     }
 
+    /// Put a copy not started by the deadline back in the queue
+    async fn defer(
+        &self,
+        src: &Url,
+        dst: &Url,
+        priority: i32,
+        pool: &PgPool,
+    ) -> Option<(Url, Error)> {
+        match FileSyncCache::cache_sync(
+            pool,
+            src.as_str(),
+            dst.as_str(),
+            priority,
+            &self.config.node_id(),
+        )
+        .await
+        {
+            Ok(()) => {
+                self.deferred.fetch_add(1, Ordering::SeqCst);
+                None
+            }
+            Err(e) => Some((src.clone(), e)),
+        }
+    }
+
+    /// Copy all `pairs` going into `directory`: the destination file list is
+    /// set up and the directory created once, source file lists once per
+    /// scheme, then the files are copied concurrently.  Files not started
+    /// by the deadline are queued again with `priority`.
+    async fn copy_directory(
+        &self,
+        directory: &str,
+        pairs: &[(Url, Url)],
+        priority: i32,
+        ownership: &OwnershipMaps,
+        pool: &PgPool,
+    ) -> Result<Vec<(Url, Error)>, Error> {
+        let dst = match pairs.first() {
+            Some((_, dst)) => dst,
+            None => return Ok(Vec::new()),
+        };
+        let flist1 = FileList::from_url(dst, &self.config, pool).await?;
+        flist1.create_directory(&directory.parse()?).await?;
+        let mut sources: HashMap<&str, Box<dyn FileListTrait>> = HashMap::new();
+        for (src, _) in pairs {
+            if !sources.contains_key(src.scheme()) {
+                let flist0 = FileList::from_url(src, &self.config, pool).await?;
+                sources.insert(src.scheme(), flist0);
+            }
+        }
+        let futures = pairs.iter().map(|(src, dst)| {
+            let flist0 = &sources[src.scheme()];
+            let flist1 = &flist1;
+            async move {
+                if is_cancelled() {
+                    return None;
+                }
+                if self.past_deadline() {
+                    return self.defer(src, dst, priority, pool).await;
+                }
+                // the remote side of the copy is the one doing the transfer
+                let service = if flist1.get_servicetype() == FileService::Local {
+                    flist0.get_servicetype()
+                } else {
+                    flist1.get_servicetype()
+                };
+                let permit = self.health.acquire(service).await;
+                ProgressChannel::global().current(src.as_str());
+                let result = self
+                    .copy_cache_entry(&(**flist0), &(**flist1), src, dst, ownership, pool)
+                    .await;
+                self.health.record(service, result.as_ref().err());
+                drop(permit);
+                // archived s3 objects are retried once their restore completes
+                if let Some(archived) = result
+                    .as_ref()
+                    .err()
+                    .and_then(|e| e.downcast_ref::<ArchivedObject>())
+                {
+                    if !archived.is_pending() {
+                        warn!("skipping {archived}");
+                        return None;
+                    }
+                    info!("{archived}, queued again");
+                    return FileSyncCache::cache_sync(
+                        pool,
+                        src.as_str(),
+                        dst.as_str(),
+                        priority,
+                        &self.config.node_id(),
+                    )
+                    .await
+                    .err()
+                    .map(|e| (src.clone(), e));
+                }
+                let (bytes, error) = match &result {
+                    Ok(bytes) => {
+                        ProgressChannel::global().transferred(*bytes);
+                        ByteAccounting::global().logical(service.to_str(), *bytes);
+                        (*bytes, None)
+                    }
+                    Err(e) => (0, Some(format_sstr!("{e}"))),
+                };
+                EventHook::emit(
+                    &self.config,
+                    &HookEvent::copied(src.as_str(), dst.as_str(), bytes, error.as_deref()),
+                );
+                if let Err(e) =
+                    SyncEvent::insert(src.as_str(), dst.as_str(), bytes, error.as_deref(), pool)
+                        .await
+                {
+                    error!("failed to record sync event {e}");
+                }
+                result.err().map(|e| (src.clone(), e))
+            }
+        });
+        let limit = if flist1.get_servicetype() == FileService::GDrive {
+            self.config.gdrive_parent_concurrency.max(1)
+        } else {
+            pairs.len().max(1)
+        };
+        Ok(stream::iter(futures)
+            .buffer_unordered(limit)
+            .filter_map(|x| async move { x })
+            .collect()
+            .await)
+    }
+
+    /// Consume the `file_sync_cache` queue written by `sync`, so that indexing
+    /// and transfers can run on different hosts.  Polls every
+    /// `transfer_poll_interval` seconds, with `once` it returns as soon as
+    /// the queue is empty, with a deadline once it has passed.
     /// # Errors
     /// Return error if db query fails
-    pub async fn delete_files(&self, urls: &[Url], pool: &PgPool) -> Result<(), Error> {
+    pub async fn run_transfer_worker(
+        &self,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+        once: bool,
+    ) -> Result<(), Error> {
+        let poll_interval = StdDuration::from_secs(self.config.transfer_poll_interval);
+        loop {
+            if self.past_deadline() {
+                mark_partial();
+                stdout.send(format_sstr!("partial: out of time, stopping"));
+                return Ok(());
+            }
+            let entries = if MaintenanceMode::is_enabled(pool).await? {
+                debug!("maintenance mode enabled, not taking transfers");
+                Vec::new()
+            } else {
+                FileSyncCache::take_batch(
+                    pool,
+                    Some(self.config.transfer_batch_size),
+                    &self.config.node_id(),
+                )
+                .await?
+            };
+            if entries.is_empty() {
+                if once {
+                    return Ok(());
+                }
+                sleep(poll_interval).await;
+                continue;
+            }
+            stdout.send(format_sstr!("transfer {} entries", entries.len()));
+            if let Err(e) = self.process_cache_entries(entries, pool, stdout).await {
+                error!("transfer batch failed {e}");
+            }
+        }
+    }
+
+    async fn copy_cache_entry(
+        &self,
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        key: &Url,
+        val: &Url,
+        ownership: &OwnershipMaps,
+        pool: &PgPool,
+    ) -> Result<i64, Error> {
+        let finfo0 =
+            match FileInfo::from_database(pool, key, flist0.get_servicesession().as_str()).await? {
+                Some(f) => f,
+                None => FileInfo::from_url(key)?,
+            };
+        let finfo1 =
+            match FileInfo::from_database(pool, val, flist1.get_servicesession().as_str()).await? {
+                Some(f) => f,
+                None => FileInfo::from_url(val)?,
+            };
+        debug!("copy {} {}", key, val);
+        let encryption = self.encryption()?;
+        if finfo1.servicetype == FileService::Local {
+            Self::copy_object(flist0, &finfo0, &finfo1, encryption).await?;
+            flist0.cleanup()?;
+        } else {
+            Self::copy_object(&(*flist1), &finfo0, &finfo1, encryption).await?;
+            flist1.cleanup()?;
+        }
+        if let Some(map) = ownership.get(key, val) {
+            Self::apply_ownership(flist0, &(*flist1), &finfo0, &finfo1, &map).await;
+        }
+        SyncHistory::record(
+            key.as_str(),
+            val.as_str(),
+            finfo0.md5sum.as_ref().map(|m| m.as_str()),
+            finfo0.filestat.st_size,
+            &self.config.node_id(),
+            pool,
+        )
+        .await?;
+        SyncConflict::resolve(key.as_str(), val.as_str(), pool).await?;
+        // a `KeepBoth` loser is now safe to overwrite
+        if let Some(conflict) = SyncConflict::take_copy(key.as_str(), val.as_str(), pool).await? {
+            SyncHistory::record(
+                conflict.src_url.as_str(),
+                conflict.dst_url.as_str(),
+                finfo0.md5sum.as_ref().map(|m| m.as_str()),
+                finfo0.filestat.st_size,
+                &self.config.node_id(),
+                pool,
+            )
+            .await?;
+        }
+        Ok(finfo0.filestat.st_size)
+    }
+
+    /// Give the copy the translated owner of the original, trying each
+    /// candidate from `map.translate` in turn. A failure here only logs, the
+    /// copy itself already succeeded.
+    async fn apply_ownership(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        finfo0: &FileInfo,
+        finfo1: &FileInfo,
+        map: &OwnershipMap,
+    ) {
+        let owner = match flist0.get_owner(finfo0).await {
+            Ok(Some(owner)) => owner,
+            Ok(None) => return,
+            Err(e) => {
+                warn!("failed to get owner of {} {e}", finfo0.urlname);
+                return;
+            }
+        };
+        for candidate in map.translate(&owner) {
+            match flist1.set_owner(finfo1, &candidate).await {
+                Ok(()) => {
+                    debug!("{} owner {owner} -> {candidate}", finfo1.urlname);
+                    return;
+                }
+                Err(e) => debug!("{} owner {candidate} failed {e}", finfo1.urlname),
+            }
+        }
+        warn!("failed to map owner {owner} onto {}", finfo1.urlname);
+    }
+
+    /// Delete files in batches of `config.delete_batch_size`, limited to
+    /// `config.delete_rate_limit` delete calls per second.  A summary of
+    /// the pending deletions is sent to stdout before anything is removed,
+    /// failures are collected and reported after all batches have run.
+    /// Files are moved to the backend's trash (see `FileListTrait::trash`)
+    /// unless `hard` is set.
+    /// # Errors
+    /// Return error if db query fails or any deletion fails
+    pub async fn delete_files(
+        &self,
+        urls: &[Url],
+        hard: bool,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
         let all_urls: Vec<Url> = if urls.is_empty() {
+            let node_id = self.config.node_id();
+            let node_id = &node_id;
             let proc_list: Result<Vec<SmallVec<[Url; 2]>>, Error> =
                 FileSyncCache::get_cache_list(pool)
                     .await?
                     .map_err(Into::into)
+                    .try_filter(|v| future::ready(v.runs_on(node_id)))
                     .and_then(|v| async move {
                         let u0: Url = v.src_url.parse()?;
                         let u1: Url = v.dst_url.parse()?;
@@ -384,48 +1629,471 @@ impl FileSync {
             urls.to_vec()
         };
 
+        let batch_size = self.config.delete_batch_size.max(1);
+        let rate_limit = RateLimiter::new(self.config.delete_rate_limit.max(1), 1000);
+
+        let ignore_rules = IgnoreRules::from_db(pool).await?;
+        let mut failures: Vec<(Url, Error)> = Vec::new();
+        let mut number_deleted = 0;
+
         for urls in group_urls(&all_urls).values() {
-            let flist = Arc::new(FileList::from_url(&urls[0], &self.config, pool).await?);
-            let fdict = Arc::new(flist.get_file_list_dict(
+            let flist = FileList::from_url(&urls[0], &self.config, pool).await?;
+            let fdict = flist.get_file_list_dict(
                 &flist.load_file_list(false).await?,
                 FileInfoKeyType::UrlName,
-            ));
+            );
 
-            let futures = urls.iter().map(|url| {
-                let flist = flist.clone();
-                let fdict = fdict.clone();
-                async move {
+            let finfos: Result<Vec<_>, Error> = urls
+                .iter()
+                .map(|url| {
                     let finfo = if let Some(f) = fdict.get(url.as_str()) {
                         f.clone()
                     } else {
                         FileInfo::from_url(url)?
                     };
+                    Ok((url.clone(), finfo))
+                })
+                .collect();
+            let finfos = finfos?;
+
+            for line in delete_summary(&finfos) {
+                stdout.send(line);
+            }
 
-                    debug!("delete {:?}", finfo);
-                    flist.delete(&finfo).await
+            for batch in finfos.chunks(batch_size) {
+                let futures = batch.iter().map(|(url, finfo)| {
+                    let flist = &flist;
+                    let rate_limit = &rate_limit;
+                    async move {
+                        rate_limit.acquire().await;
+                        debug!("delete {:?}", finfo);
+                        let result = if hard {
+                            flist.delete(finfo).await
+                        } else {
+                            flist.trash(finfo).await
+                        };
+                        (url, result)
+                    }
+                });
+                for (url, result) in join_all(futures).await {
+                    let error = result.as_ref().err().map(|e| format_sstr!("{e}"));
+                    EventHook::emit(
+                        &self.config,
+                        &HookEvent::deleted(url.as_str(), error.as_deref()),
+                    );
+                    match result {
+                        Ok(()) => number_deleted += 1,
+                        Err(e) => failures.push((url.clone(), e)),
+                    }
                 }
-            });
-            let results: Result<Vec<()>, Error> = try_join_all(futures).await;
-            results?;
+            }
         }
-        Ok(())
+        let verb = if hard { "deleted" } else { "trashed" };
+        stdout.send(format_sstr!("{verb} {number_deleted}"));
+        report_failures("delete", failures, &ignore_rules, stdout)
+    }
+
+    /// Apply the deletion policy of `conf`, the config syncing `flist0` and
+    /// `flist1`, to files tombstoned on either side (e.g. moved to the gdrive
+    /// trash): their copy on the other side is trashed or deleted, then
+    /// tombstoned as well so it's only handled once.  Copies waiting to be
+    /// moved by a queued move are left alone.  With the default `keep`
+    /// nothing is removed.  Returns the number of copies removed.
+    /// # Errors
+    /// Return error if db query fails, the policy is invalid or any deletion
+    /// fails
+    pub async fn propagate_deletions(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        conf: Option<&FileSyncConfig>,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<usize, Error> {
+        let policy = conf
+            .map(FileSyncConfig::get_deletion_policy)
+            .transpose()?
+            .unwrap_or_default();
+        if policy == DeletionPolicy::Keep {
+            return Ok(0);
+        }
+        let verb = if policy == DeletionPolicy::Delete {
+            "deleted"
+        } else {
+            "trashed"
+        };
+        let config = flist0.get_config();
+        let ignore_rules = IgnoreRules::from_db(pool).await?;
+        let mut failures: Vec<(Url, Error)> = Vec::new();
+        let mut number_deleted = 0;
+        let moving: HashSet<StackString> = FileSyncCache::get_moves(pool)
+            .await?
+            .into_iter()
+            .filter_map(|entry| entry.move_from)
+            .collect();
+        for (deleted, target) in [(flist0, flist1), (flist1, flist0)] {
+            let entries = FileInfoCache::get_deleted_counterparts(
+                deleted.get_baseurl().as_str(),
+                target.get_baseurl().as_str(),
+                deleted.get_servicesession().as_str(),
+                target.get_servicesession().as_str(),
+                pool,
+            )
+            .await?;
+            for entry in entries {
+                if moving.contains(&entry.urlname) {
+                    continue;
+                }
+                let key = entry.get_key();
+                let finfo: FileInfo = entry.try_into()?;
+                let url: Url = finfo.urlname.clone().into();
+                let result = match policy {
+                    DeletionPolicy::Trash => target.trash(&finfo).await,
+                    DeletionPolicy::Delete => target.delete(&finfo).await,
+                    DeletionPolicy::Keep => Ok(()),
+                };
+                let error = result.as_ref().err().map(|e| format_sstr!("{e}"));
+                EventHook::emit(config, &HookEvent::deleted(url.as_str(), error.as_deref()));
+                match result {
+                    Ok(()) => {
+                        if let Some(key) = key {
+                            key.delete_cache_entry(pool).await?;
+                        }
+                        stdout.send(format_sstr!("{verb} {url}"));
+                        number_deleted += 1;
+                    }
+                    Err(e) => failures.push((url, e)),
+                }
+            }
+        }
+        report_failures("delete", failures, &ignore_rules, stdout)?;
+        Ok(number_deleted)
+    }
+
+    /// Follow files renamed or moved on one side, tombstoned at their old
+    /// path and new at another (see `match_moves`): a move of their copy on
+    /// the other side is queued in `file_sync_cache`, carried out by
+    /// `process_cache_entries` with `move_file` instead of copying the file
+    /// again and removing the old copy with `propagate_deletions`.  Nothing
+    /// is moved with the deletion policy `keep`, which leaves the old copy
+    /// in place, or when the other side can't move files.  Returns the
+    /// number of moves queued.
+    /// # Errors
+    /// Return error if db query fails or the policy of `conf` is invalid
+    pub async fn follow_moves(
+        flist0: &dyn FileListTrait,
+        flist1: &dyn FileListTrait,
+        conf: Option<&FileSyncConfig>,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<usize, Error> {
+        let policy = conf
+            .map(FileSyncConfig::get_deletion_policy)
+            .transpose()?
+            .unwrap_or_default();
+        if policy == DeletionPolicy::Keep {
+            return Ok(0);
+        }
+        let config = flist0.get_config();
+        let mut queued: HashSet<StackString> = FileSyncCache::get_moves(pool)
+            .await?
+            .into_iter()
+            .filter_map(|entry| entry.move_from)
+            .collect();
+        let mut number_queued = 0;
+        for (moved, target) in [(flist0, flist1), (flist1, flist0)] {
+            if !target.supports_move() {
+                continue;
+            }
+            let baseurl0 = moved.get_baseurl();
+            let baseurl1 = target.get_baseurl();
+            let mut counterparts: HashMap<StackString, FileInfoCache> = HashMap::new();
+            for entry in FileInfoCache::get_deleted_counterparts(
+                baseurl0.as_str(),
+                baseurl1.as_str(),
+                moved.get_servicesession().as_str(),
+                target.get_servicesession().as_str(),
+                pool,
+            )
+            .await?
+            {
+                let url: Url = entry.urlname.parse()?;
+                counterparts.insert(remove_baseurl(&url, baseurl1), entry);
+            }
+            if counterparts.is_empty() {
+                continue;
+            }
+            let deleted = FileInfoCache::get_deleted_with_counterparts(
+                baseurl0.as_str(),
+                baseurl1.as_str(),
+                moved.get_servicesession().as_str(),
+                target.get_servicesession().as_str(),
+                pool,
+            )
+            .await?
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<FileInfo>, Error>>()?;
+            let new = FileInfoCache::get_new_entries(
+                baseurl0.as_str(),
+                baseurl1.as_str(),
+                moved.get_servicesession().as_str(),
+                target.get_servicesession().as_str(),
+                pool,
+            )
+            .await?
+            .into_iter()
+            .map(TryInto::try_into)
+            .collect::<Result<Vec<FileInfo>, Error>>()?;
+
+            for (d, n) in match_moves(&deleted, &new) {
+                let old = match counterparts.get(&remove_baseurl(&deleted[d].urlname, baseurl0)) {
+                    Some(old) => old,
+                    None => continue,
+                };
+                if !queued.insert(old.urlname.clone()) {
+                    continue;
+                }
+                let url1 = replace_baseurl(&new[n].urlname, baseurl0, baseurl1)?;
+                FileSyncCache::cache_move(
+                    pool,
+                    new[n].urlname.as_str(),
+                    url1.as_str(),
+                    old.urlname.as_str(),
+                    FileSyncCache::PRIORITY_BULK,
+                    &config.node_id(),
+                )
+                .await?;
+                EventHook::emit(
+                    config,
+                    &HookEvent::queued(new[n].urlname.as_str(), url1.as_str()),
+                );
+                stdout.send(format_sstr!("queued move {} {url1}", old.urlname));
+                number_queued += 1;
+            }
+        }
+        Ok(number_queued)
     }
 
+    /// Assemble the latest version of each file at or before `as_of` from a
+    /// backup laid out as `<baseurl>/<YYYY-MM-DD>/<relative path>`, and copy
+    /// it to `<dst_url>/<relative path>`.  When the backup also has
+    /// `<baseurl>/<YYYY-MM-DD>.manifest` files, the newest manifest at or
+    /// before `as_of` lists the files to restore (see `restore_versions`),
+    /// so files deleted since aren't brought back.  Returns the list of
+    /// `(source, destination)` pairs, nothing is copied when `dry_run` is set.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn restore_as_of(
+        &self,
+        src_url: &Url,
+        dst_url: &Url,
+        as_of: Date,
+        dry_run: bool,
+        pool: &PgPool,
+    ) -> Result<Vec<(Url, Url)>, Error> {
+        let flist0 = FileList::from_url(src_url, &self.config, pool).await?;
+        let flist1 = FileList::from_url(dst_url, &self.config, pool).await?;
+        let encryption = self.encryption()?;
+
+        let mut versions: HashMap<StackString, BTreeMap<Date, FileInfoCache>> = HashMap::new();
+        let mut manifest: Option<(Date, FileInfoCache)> = None;
+        for entry in flist0.load_file_list(false).await? {
+            let url0: Url = entry.urlname.parse()?;
+            let relpath = remove_baseurl(&url0, src_url);
+            if let Some((date, path)) = split_dated_prefix(&relpath) {
+                if date <= as_of {
+                    versions.entry(path.into()).or_default().insert(date, entry);
+                }
+            } else if let Some(date) = manifest_date(&relpath) {
+                if date <= as_of && manifest.as_ref().map_or(true, |(d, _)| *d < date) {
+                    manifest = Some((date, entry));
+                }
+            }
+        }
+        let manifest = match manifest {
+            Some((_, entry)) => {
+                let finfo: FileInfo = entry.try_into()?;
+                Some(Self::read_manifest(&(*flist0), &finfo, encryption).await?)
+            }
+            None => None,
+        };
+
+        let mut restored: Vec<(Url, Url)> = Vec::new();
+        for (path, entry) in restore_versions(&versions, manifest.as_deref()) {
+            let finfo0: FileInfo = entry.clone().try_into()?;
+            let url1 = Url::parse(&format_sstr!(
+                "{}/{path}",
+                dst_url.as_str().trim_end_matches('/')
+            ))?;
+            let finfo1 = FileInfo::from_url(&url1)?;
+            if !dry_run {
+                debug!("restore {} {}", finfo0.urlname, url1);
+                if finfo1.servicetype == FileService::Local {
+                    Self::copy_object(&(*flist0), &finfo0, &finfo1, encryption).await?;
+                } else {
+                    Self::copy_object(&(*flist1), &finfo0, &finfo1, encryption).await?;
+                }
+            }
+            restored.push((finfo0.urlname.clone().into(), url1));
+        }
+        restored.sort();
+        Ok(restored)
+    }
+
+    /// Contents of the restore manifest `finfo`, fetched into a scratch
+    /// directory unless it's a local file
+    async fn read_manifest(
+        flist: &dyn FileListTrait,
+        finfo: &FileInfo,
+        encryption: Option<&Encryption>,
+    ) -> Result<String, Error> {
+        if finfo.servicetype == FileService::Local {
+            return tokio::fs::read_to_string(&finfo.filepath)
+                .await
+                .map_err(Into::into);
+        }
+        let scratch = scratch_dir();
+        create_dir_all(&scratch).await?;
+        let local_path = scratch.join(finfo.filename.as_str());
+        let result = async {
+            let local_url = Url::from_file_path(&local_path)
+                .map_err(|()| format_err!("Invalid path {local_path:?}"))?;
+            let local = FileInfo::from_url(&local_url)?;
+            Self::copy_object(flist, finfo, &local, encryption).await?;
+            tokio::fs::read_to_string(&local_path)
+                .await
+                .map_err(Into::into)
+        }
+        .await;
+        remove_dir_all(&scratch).await?;
+        result
+    }
+
+    /// Copy between a local file and any backend.  Uploads to a cloud
+    /// target whose config sets compression are compressed first (see
+    /// `Compression`) and downloads of compressed objects decompressed,
+    /// with `encryption` (the config's key, see `Encryption::from_config`)
+    /// content going to or coming from cloud targets passes through it
     /// # Errors
     /// Return error if db query fails
     pub async fn copy_object(
         flist: &dyn FileListTrait,
         finfo0: &dyn FileInfoTrait,
         finfo1: &dyn FileInfoTrait,
+        encryption: Option<&Encryption>,
+    ) -> Result<(), Error> {
+        let f0 = finfo0.get_finfo();
+        let f1 = finfo1.get_finfo();
+        let pool = flist.get_pool();
+
+        if f0.servicetype == FileService::Local && Compression::applies_to(f1.servicetype) {
+            let url = f1.urlname.as_str();
+            let compression = match Compression::for_upload(url, f0.filestat.st_size, pool).await? {
+                Some(compression) => compression,
+                None => {
+                    CompressedFile::delete(url, pool).await?;
+                    return Self::transfer_object(flist, finfo0, finfo1, encryption).await;
+                }
+            };
+            let scratch = scratch_dir();
+            create_dir_all(&scratch).await?;
+            let compressed_path = scratch.join(f0.filename.as_str());
+            let result = async {
+                let src = f0.filepath.to_path_buf();
+                let dst = compressed_path.clone();
+                let codec = compression.codec;
+                let compressed_size =
+                    spawn_blocking(move || codec.compress_file(&src, &dst)).await??;
+                let mut local = f0.inner().clone();
+                local.filepath = compressed_path.clone().into();
+                local.filestat.st_size = compressed_size as i64;
+                local.md5sum = None;
+                Self::transfer_object(flist, &FileInfo::from_inner(local), finfo1, encryption)
+                    .await?;
+                Ok::<_, Error>(compressed_size as i64)
+            }
+            .await;
+            remove_dir_all(&scratch).await?;
+            let compressed_size = result?;
+            debug!(
+                "compressed {url} {} -> {compressed_size}",
+                f0.filestat.st_size
+            );
+            CompressedFile {
+                url: url.into(),
+                codec: compression.codec.to_str().into(),
+                original_size: f0.filestat.st_size,
+                original_md5sum: f0.md5sum.as_ref().map(|m| m.as_str().into()),
+                compressed_size,
+                created_at: DateTimeWrapper::now(),
+            }
+            .upsert(pool)
+            .await?;
+            return Ok(());
+        }
+        if f1.servicetype == FileService::Local && Compression::applies_to(f0.servicetype) {
+            if let Some(entry) = CompressedFile::get_by_url(f0.urlname.as_str(), pool).await? {
+                let codec: Codec = entry.codec.parse()?;
+                let scratch = scratch_dir();
+                create_dir_all(&scratch).await?;
+                let compressed_path = scratch.join(f1.filename.as_str());
+                let result = async {
+                    let mut local = f1.inner().clone();
+                    local.filepath = compressed_path.clone().into();
+                    local.md5sum = None;
+                    Self::transfer_object(flist, finfo0, &FileInfo::from_inner(local), encryption)
+                        .await?;
+                    if let Some(parent) = f1.filepath.parent() {
+                        create_dir_all(parent).await?;
+                    }
+                    let src = compressed_path.clone();
+                    let dst = f1.filepath.to_path_buf();
+                    spawn_blocking(move || codec.decompress_file(&src, &dst)).await?
+                }
+                .await;
+                remove_dir_all(&scratch).await?;
+                return result;
+            }
+        }
+        Self::transfer_object(flist, finfo0, finfo1, encryption).await
+    }
+
+    async fn transfer_object(
+        flist: &dyn FileListTrait,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+        encryption: Option<&Encryption>,
     ) -> Result<(), Error> {
         let t0 = finfo0.get_finfo().servicetype;
         let t1 = finfo1.get_finfo().servicetype;
 
         debug!("copy from {:?} to {:?} using {:?}", t0, t1, flist);
 
-        if t1 == FileService::Local {
+        if let Some(encryption) = encryption {
+            if t0 == FileService::Local && Encryption::applies_to(t1) {
+                return encryption.copy_to(flist, finfo0, finfo1).await;
+            } else if t1 == FileService::Local
+                && Encryption::applies_to(t0)
+                && encryption.copy_from(flist, finfo0, finfo1).await?
+            {
+                return Ok(());
+            }
+        }
+        Self::copy_plain(flist, finfo0, finfo1).await
+    }
+
+    /// Copy without compression, encryption or any database access, `flist`
+    /// is the list of the non-local side
+    /// # Errors
+    /// Return error if neither side is local or the copy fails
+    pub async fn copy_plain(
+        flist: &dyn FileListTrait,
+        finfo0: &dyn FileInfoTrait,
+        finfo1: &dyn FileInfoTrait,
+    ) -> Result<(), Error> {
+        if finfo1.get_finfo().servicetype == FileService::Local {
             flist.copy_from(finfo0, finfo1).await
-        } else if t0 == FileService::Local {
+        } else if finfo0.get_finfo().servicetype == FileService::Local {
             flist.copy_to(finfo0, finfo1).await
         } else {
             Err(format_err!("Invalid request"))
@@ -433,6 +2101,116 @@ impl FileSync {
     }
 }
 
+/// Failures matching a config's ignore rules are only logged at debug level
+/// and are not counted as failures, the number suppressed is reported.
+fn report_failures(
+    label: &str,
+    failures: Vec<(Url, Error)>,
+    ignore_rules: &IgnoreRules,
+    stdout: &StdoutChannel<StackString>,
+) -> Result<(), Error> {
+    let (suppressed, failures): (Vec<_>, Vec<_>) = failures
+        .into_iter()
+        .partition(|(url, e)| ignore_rules.is_ignored(url, e));
+    for (url, e) in &suppressed {
+        debug!("suppressed {label} failure {url} {e}");
+    }
+    for (url, e) in &failures {
+        error!("failed to {label} {url} {e}");
+        stdout.send(format_sstr!("failed to {label} {url} {e}"));
+    }
+    stdout.send(format_sstr!(
+        "{label} suppressed {} failed {}",
+        suppressed.len(),
+        failures.len()
+    ));
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format_err!("Failed to {label} {} files", failures.len()))
+    }
+}
+
+fn delete_summary(finfos: &[(Url, FileInfo)]) -> Vec<StackString> {
+    let counts: BTreeMap<(&str, StackString), usize> =
+        finfos.iter().fold(BTreeMap::new(), |mut h, (url, finfo)| {
+            let directory = url
+                .as_str()
+                .rsplit_once('/')
+                .map_or(url.as_str(), |(d, _)| d);
+            *h.entry((finfo.servicetype.to_str(), directory.into()))
+                .or_default() += 1;
+            h
+        });
+    counts
+        .into_iter()
+        .map(|((servicetype, directory), count)| format_sstr!("{servicetype} {directory} {count}"))
+        .collect()
+}
+
+/// Date of a `<YYYY-MM-DD>.manifest` restore manifest at the top of a
+/// dated backup
+fn manifest_date(relpath: &str) -> Option<Date> {
+    let prefix = relpath.strip_suffix(".manifest")?;
+    Date::parse(prefix, format_description!("[year]-[month]-[day]")).ok()
+}
+
+/// Version of each file to restore, out of the dated `versions` of each
+/// relative path (all at or before the date restored).  Without a manifest
+/// that's the newest version of every path.  A manifest lists one relative
+/// path per line, restored at its newest version, or
+/// `<YYYY-MM-DD>/<relative path>` to restore that version; paths it doesn't
+/// list aren't restored.
+fn restore_versions<'a, T>(
+    versions: &'a HashMap<StackString, BTreeMap<Date, T>>,
+    manifest: Option<&str>,
+) -> Vec<(&'a str, &'a T)> {
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => {
+            return versions
+                .iter()
+                .filter_map(|(path, dated)| Some((path.as_str(), dated.values().next_back()?)))
+                .collect();
+        }
+    };
+    let mut restore = Vec::new();
+    for line in manifest.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (date, path) = match split_dated_prefix(line) {
+            Some((date, path)) => (Some(date), path),
+            None => (None, line),
+        };
+        let version = versions.get_key_value(path).and_then(|(path, dated)| {
+            let version = match date {
+                Some(date) => dated.get(&date),
+                None => dated.values().next_back(),
+            };
+            version.map(|version| (path.as_str(), version))
+        });
+        match version {
+            Some(version) => restore.push(version),
+            None => warn!("no version of {line} to restore"),
+        }
+    }
+    restore
+}
+
+fn split_dated_prefix(relpath: &str) -> Option<(Date, &str)> {
+    let mut iter = relpath.splitn(2, '/');
+    let prefix = iter.next()?;
+    let path = iter.next()?;
+    let date = Date::parse(prefix, format_description!("[year]-[month]-[day]")).ok()?;
+    if path.is_empty() {
+        None
+    } else {
+        Some((date, path))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Error;
@@ -442,9 +2220,21 @@ mod tests {
     };
     use futures::{future, TryStreamExt};
     use log::debug;
-    use stack_string::format_sstr;
-    use std::{collections::HashMap, convert::TryInto, env::current_dir, path::Path};
-    use time::macros::datetime;
+    use stack_string::{format_sstr, StackString};
+    use std::{
+        collections::{BTreeMap, HashMap},
+        convert::TryInto,
+        env::{current_dir, temp_dir},
+        path::Path,
+        time::{Duration as StdDuration, Instant},
+    };
+    use stdout_channel::StdoutChannel;
+    use time::{
+        macros::{date, datetime},
+        Date, Duration,
+    };
+    use url::Url;
+    use uuid::Uuid;
 
     use crate::{
         config::Config,
@@ -454,11 +2244,168 @@ mod tests {
         file_list::FileListTrait,
         file_list_local::FileListLocal,
         file_list_s3::FileListS3,
-        file_sync::FileSync,
-        models::{FileInfoCache, FileSyncCache},
+        file_sync::{
+            delete_summary, manifest_date, restore_versions, split_dated_prefix, FileSync,
+            FileSyncAction,
+        },
+        models::{FileInfoCache, FileSyncCache, IndexRun},
         pgpool::PgPool,
     };
 
+    #[test]
+    fn test_deadline() {
+        let fsync = FileSync::new(Config::default());
+        assert!(!fsync.past_deadline());
+        let fsync = fsync.with_deadline(Some(Instant::now()));
+        assert!(fsync.past_deadline());
+        let fsync = fsync.with_deadline(Some(Instant::now() + StdDuration::from_secs(3600)));
+        assert!(!fsync.past_deadline());
+        assert_eq!(fsync.deferred(), 0);
+    }
+
+    #[test]
+    fn test_delete_summary() -> Result<(), Error> {
+        let finfos: Result<Vec<_>, Error> = [
+            "file:///tmp/a/b.txt",
+            "file:///tmp/a/c.txt",
+            "file:///tmp/d/e.txt",
+        ]
+        .iter()
+        .map(|u| {
+            let url: Url = u.parse()?;
+            let finfo = FileInfo::from_url(&url)?;
+            Ok((url, finfo))
+        })
+        .collect();
+        let summary = delete_summary(&finfos?);
+        let summary: Vec<_> = summary.iter().map(StackString::as_str).collect();
+        assert_eq!(
+            summary,
+            vec!["local file:///tmp/a 2", "local file:///tmp/d 1"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_file_sync_action_to_str() -> Result<(), Error> {
+        for action in [
+            FileSyncAction::Index,
+            FileSyncAction::Sync,
+            FileSyncAction::Process,
+            FileSyncAction::RunMigrations,
+            FileSyncAction::IgnoreErrors,
+            FileSyncAction::MigrateSessions,
+            FileSyncAction::RenameSession,
+            FileSyncAction::SelfTest,
+            FileSyncAction::MapOwner,
+            FileSyncAction::Trend,
+            FileSyncAction::CostEstimate,
+            FileSyncAction::Exclusions,
+            FileSyncAction::ExportExclusions,
+            FileSyncAction::ImportExclusions,
+            FileSyncAction::CacheDelete,
+            FileSyncAction::CacheApprove,
+            FileSyncAction::CacheRequeue,
+            FileSyncAction::Retention,
+            FileSyncAction::ShowRuns,
+            FileSyncAction::Snapshot,
+            FileSyncAction::ConflictPolicy,
+            FileSyncAction::Conflicts,
+            FileSyncAction::ResolveConflict,
+            FileSyncAction::BackfillChecksums,
+            FileSyncAction::Verify,
+            FileSyncAction::VirtualRoot,
+            FileSyncAction::ScopeSessions,
+            FileSyncAction::PurgeTrash,
+            FileSyncAction::Compression,
+            FileSyncAction::Watch,
+            FileSyncAction::MaxIndexAge,
+            FileSyncAction::DeletionPolicy,
+            FileSyncAction::LinkFarm,
+            FileSyncAction::PruneGenerations,
+            FileSyncAction::DstLayout,
+            FileSyncAction::Signature,
+            FileSyncAction::Delta,
+            FileSyncAction::Patch,
+            FileSyncAction::ExportHashes,
+            FileSyncAction::ImportHashes,
+            FileSyncAction::AbortStaleUploads,
+            FileSyncAction::LogLevel,
+            FileSyncAction::StorageClass,
+            FileSyncAction::Fsck,
+            FileSyncAction::Sse,
+            FileSyncAction::GDriveDuplicates,
+        ] {
+            let parsed: FileSyncAction = action.to_str().parse()?;
+            assert_eq!(parsed, action);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_split_dated_prefix() {
+        let (date, path) = split_dated_prefix("2023-04-05/dir/file.txt").unwrap();
+        assert_eq!(date, date!(2023 - 04 - 05));
+        assert_eq!(path, "dir/file.txt");
+        assert_eq!(split_dated_prefix("2023-04-05/"), None);
+        assert_eq!(split_dated_prefix("dir/file.txt"), None);
+    }
+
+    #[test]
+    fn test_restore_versions() {
+        assert_eq!(
+            manifest_date("2023-04-05.manifest"),
+            Some(date!(2023 - 04 - 05))
+        );
+        assert_eq!(manifest_date("2023-04-05/file.manifest"), None);
+        assert_eq!(manifest_date("2023-04-05"), None);
+
+        let mut versions: HashMap<StackString, BTreeMap<Date, i32>> = HashMap::new();
+        versions
+            .entry("a.txt".into())
+            .or_default()
+            .extend([(date!(2023 - 04 - 01), 1), (date!(2023 - 04 - 03), 2)]);
+        versions
+            .entry("dir/b.txt".into())
+            .or_default()
+            .insert(date!(2023 - 04 - 02), 3);
+
+        let mut restore = restore_versions(&versions, None);
+        restore.sort_unstable();
+        assert_eq!(restore, vec![("a.txt", &2), ("dir/b.txt", &3)]);
+
+        let manifest = "# 2023-04-04\n2023-04-01/a.txt\n\nmissing.txt\n";
+        assert_eq!(
+            restore_versions(&versions, Some(manifest)),
+            vec![("a.txt", &1)]
+        );
+        let manifest = "a.txt\n2023-04-03/dir/b.txt\n";
+        assert_eq!(
+            restore_versions(&versions, Some(manifest)),
+            vec![("a.txt", &2)]
+        );
+    }
+
+    #[test]
+    fn test_index_run_is_fresh() {
+        let now = datetime!(2024-03-09 12:00:00 UTC);
+        let mut run = IndexRun {
+            id: Uuid::new_v4(),
+            servicetype: "local".into(),
+            servicesession: "host:/data".into(),
+            baseurl: "file:///data".into(),
+            started_at: (now - Duration::hours(2)).into(),
+            finished_at: Some((now - Duration::hours(1)).into()),
+            complete: true,
+            number_updated: 0,
+            number_cached: 0,
+        };
+        assert!(run.is_fresh(3 * 3600, now));
+        assert!(!run.is_fresh(3600, now));
+        run.complete = false;
+        assert!(!run.is_fresh(3 * 3600, now));
+    }
+
     #[test]
     fn test_compare_objects() -> Result<(), Error> {
         let filepath = Path::new("src/file_sync.rs").canonicalize()?;
@@ -468,11 +2415,17 @@ mod tests {
         debug!("{:?}", finfo0);
         let mut finfo1 = finfo0.0.inner().clone();
         finfo1.md5sum = Some("51e3cc2c6f64d24ff55fae262325edee".parse()?);
-        finfo1.filestat.st_mtime += 100;
+        finfo1.filestat.st_mtime =
+            (finfo1.filestat.st_mtime.to_offsetdatetime() + Duration::seconds(100)).into();
         finfo1.filestat.st_size += 100;
         let finfo1 = FileInfoLocal(FileInfo::from_inner(finfo1));
         debug!("{:?}", finfo1);
-        assert!(FileSync::compare_objects(&finfo0, &finfo1));
+        assert!(FileSync::compare_objects(
+            &finfo0,
+            &finfo1,
+            Duration::seconds(2),
+            Duration::ZERO,
+        ));
 
         let test_owner = Owner::builder().display_name("me").id("8675309").build();
         let last_modified = datetime!(2019-05-01 00:00:00 +00:00);
@@ -487,7 +2440,81 @@ mod tests {
 
         let finfo2 = FileInfoS3::from_object("test_bucket", test_object)?;
         debug!("{:?}", finfo2);
-        assert!(FileSync::compare_objects(&finfo0, &finfo2));
+        assert!(FileSync::compare_objects(
+            &finfo0,
+            &finfo2,
+            Duration::seconds(2),
+            Duration::ZERO,
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_copy_stateless() -> Result<(), Error> {
+        let config = Config::new();
+        let pool = PgPool::new("")?;
+        let dir = temp_dir().join(format_sstr!("stateless_{}", Uuid::new_v4()).as_str());
+        let src = dir.join("src");
+        let dst = dir.join("dst");
+        tokio::fs::create_dir_all(src.join("sub")).await?;
+        tokio::fs::create_dir_all(&dst).await?;
+        tokio::fs::write(src.join("a.txt"), b"a").await?;
+        tokio::fs::write(src.join("sub").join("b.txt"), b"b").await?;
+
+        let flist0 = FileListLocal::new(&src, &config, &pool)?;
+        let flist1 = FileListLocal::new(&dst, &config, &pool)?;
+        let stdout = StdoutChannel::new();
+        assert_eq!(
+            FileSync::copy_stateless(&flist0, &flist1, &stdout).await?,
+            2
+        );
+        assert_eq!(tokio::fs::read(dst.join("sub").join("b.txt")).await?, b"b");
+        assert_eq!(
+            FileSync::copy_stateless(&flist0, &flist1, &stdout).await?,
+            0
+        );
+
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_take_batch_priority() -> Result<(), Error> {
+        let config = Config::init_config()?;
+        let pool = PgPool::new(&config.database_url)?;
+        let bulk = "file:///tmp/test_take_batch_priority/bulk.txt";
+        let interactive = "file:///tmp/test_take_batch_priority/interactive.txt";
+        let dst = "s3://test_bucket/test_take_batch_priority.txt";
+        let node = config.node_id();
+        FileSyncCache::cache_sync(&pool, bulk, dst, FileSyncCache::PRIORITY_BULK, &node).await?;
+        FileSyncCache::cache_sync(
+            &pool,
+            interactive,
+            dst,
+            FileSyncCache::PRIORITY_INTERACTIVE,
+            &node,
+        )
+        .await?;
+
+        let entries = FileSyncCache::take_batch(&pool, Some(1), "other_node").await?;
+        assert!(entries.iter().all(|e| e.dst_url != dst));
+        let entries = FileSyncCache::take_batch(&pool, Some(1), &node).await?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].src_url, interactive);
+        assert_eq!(entries[0].node_id.as_ref(), Some(&node));
+        assert!(entries[0].is_interactive());
+
+        let remaining: Vec<_> = FileSyncCache::get_cache_list(&pool)
+            .await?
+            .try_filter(|v| future::ready(v.dst_url == dst))
+            .try_collect()
+            .await?;
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].src_url, bulk);
+        for entry in remaining {
+            entry.delete_cache_entry(&pool).await?;
+        }
         Ok(())
     }
 
@@ -518,7 +2545,7 @@ mod tests {
         let flist1 = FileListS3::new("test_bucket", &config, &pool).await?;
         flist1.clear_file_list().await?;
 
-        FileSync::compare_lists(&flist0, &flist1, &pool).await?;
+        FileSync::compare_lists(&flist0, &flist1, None, &pool).await?;
 
         let cache_list: HashMap<_, _> = FileSyncCache::get_cache_list(&pool)
             .await?
@@ -581,7 +2608,7 @@ mod tests {
 
         let flist1 = FileListS3::new("test_bucket", &config, &pool).await?;
 
-        FileSync::compare_lists(&flist0, &flist1, &pool).await?;
+        FileSync::compare_lists(&flist0, &flist1, None, &pool).await?;
 
         let cache_list: HashMap<_, _> = FileSyncCache::get_cache_list(&pool)
             .await?
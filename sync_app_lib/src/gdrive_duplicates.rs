@@ -0,0 +1,217 @@
+use anyhow::{format_err, Error};
+use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+use percent_encoding::percent_decode_str;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+use url::Url;
+
+use crate::models::{GDriveDuplicate, SharedPath};
+
+/// Google drive lets a folder hold any number of files of the same name, the
+/// cache keeps them apart by appending the drive id to the name: `report.pdf`
+/// of id `1AbC` becomes `report~1AbC.pdf`
+#[must_use]
+pub fn disambiguated_name(filename: &str, gdriveid: &str) -> StackString {
+    let path = Path::new(filename);
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => format_sstr!(
+            "{}~{gdriveid}.{}",
+            stem.to_string_lossy(),
+            ext.to_string_lossy()
+        ),
+        _ => format_sstr!("{filename}~{gdriveid}"),
+    }
+}
+
+/// Decoded last path segment of `urlname`
+/// # Errors
+/// Return error if `urlname` isn't a url
+pub fn url_filename(urlname: &str) -> Result<StackString, Error> {
+    let url: Url = urlname.parse()?;
+    let segment = url
+        .path_segments()
+        .and_then(Iterator::last)
+        .ok_or_else(|| format_err!("No filename in {urlname}"))?;
+    Ok(percent_decode_str(segment)
+        .decode_utf8_lossy()
+        .as_ref()
+        .into())
+}
+
+/// `urlname` with the file name disambiguated by `gdriveid`
+/// # Errors
+/// Return error if `urlname` isn't a url with a path
+pub fn disambiguated_url(urlname: &str, gdriveid: &str) -> Result<StackString, Error> {
+    let filename = url_filename(urlname)?;
+    let mut url: Url = urlname.parse()?;
+    url.path_segments_mut()
+        .map_err(|()| format_err!("No path in {urlname}"))?
+        .pop()
+        .push(&disambiguated_name(&filename, gdriveid));
+    Ok(url.as_str().into())
+}
+
+/// Changes that bring the recorded duplicates of a session in line with the
+/// paths currently shared by several files
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct DuplicatePlan {
+    /// Entries to move to their disambiguated path
+    pub added: Vec<GDriveDuplicate>,
+    /// Entries to move back to the path they no longer share, or that moved
+    pub removed: Vec<GDriveDuplicate>,
+}
+
+/// The file of a shared path that keeps the plain name: the one whose
+/// content was last synced to the path, then the one already cached under
+/// it, then the oldest.  Once chosen the others are disambiguated, so the
+/// choice sticks.
+fn keeper<'a>(files: &[&'a SharedPath]) -> Option<&'a SharedPath> {
+    files
+        .iter()
+        .min_by(|a, b| {
+            b.synced
+                .cmp(&a.synced)
+                .then(b.plain.cmp(&a.plain))
+                .then(a.created_at.cmp(&b.created_at))
+                .then(a.gdriveid.cmp(&b.gdriveid))
+        })
+        .copied()
+}
+
+/// One file of a shared path keeps the plain name (see `keeper`), so the
+/// copy synced under that name keeps its counterpart, every other file of
+/// the path is disambiguated.  A file whose path isn't shared anymore, or
+/// that became the keeper, gets its plain name back.
+/// # Errors
+/// Return error if a url can't be parsed
+pub fn plan_duplicates(
+    servicesession: &str,
+    shared: &[SharedPath],
+    existing: &HashMap<StackString, GDriveDuplicate>,
+) -> Result<DuplicatePlan, Error> {
+    let mut plan = DuplicatePlan::default();
+    let mut by_path: BTreeMap<&str, Vec<&SharedPath>> = BTreeMap::new();
+    for file in shared {
+        by_path.entry(file.urlname.as_str()).or_default().push(file);
+    }
+    let mut wanted: HashMap<&str, &str> = HashMap::new();
+    for files in by_path.values() {
+        let keep = keeper(files).map(|f| f.gdriveid.as_str());
+        for file in files {
+            if Some(file.gdriveid.as_str()) == keep {
+                continue;
+            }
+            wanted.insert(file.gdriveid.as_str(), file.urlname.as_str());
+            if existing
+                .get(&file.gdriveid)
+                .map_or(false, |d| d.urlname == file.urlname)
+            {
+                continue;
+            }
+            plan.added.push(GDriveDuplicate {
+                servicesession: servicesession.into(),
+                gdriveid: file.gdriveid.clone(),
+                urlname: file.urlname.clone(),
+                disambiguated_urlname: disambiguated_url(&file.urlname, &file.gdriveid)?,
+                created_at: DateTimeWrapper::now(),
+            });
+        }
+    }
+    for (gdriveid, duplicate) in existing {
+        if wanted.get(gdriveid.as_str()) != Some(&duplicate.urlname.as_str()) {
+            plan.removed.push(duplicate.clone());
+        }
+    }
+    plan.removed.sort_by(|a, b| a.gdriveid.cmp(&b.gdriveid));
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::collections::HashMap;
+    use time::{macros::datetime, Duration};
+
+    use crate::{
+        gdrive_duplicates::{disambiguated_name, disambiguated_url, plan_duplicates, url_filename},
+        models::SharedPath,
+    };
+
+    #[test]
+    fn test_disambiguated_name() -> Result<(), Error> {
+        assert_eq!(
+            disambiguated_name("report.pdf", "1AbC").as_str(),
+            "report~1AbC.pdf"
+        );
+        assert_eq!(disambiguated_name("notes", "1AbC").as_str(), "notes~1AbC");
+        assert_eq!(
+            disambiguated_name(".bashrc", "1AbC").as_str(),
+            ".bashrc~1AbC"
+        );
+        assert_eq!(
+            disambiguated_url("gdrive://user@gmail.com/My%20Drive/a%20b.txt", "1AbC")?.as_str(),
+            "gdrive://user@gmail.com/My%20Drive/a%20b~1AbC.txt"
+        );
+        assert_eq!(
+            url_filename("gdrive://user@gmail.com/My%20Drive/a%20b.txt")?.as_str(),
+            "a b.txt"
+        );
+        Ok(())
+    }
+
+    fn shared(gdriveid: &str, plain: bool, synced: bool, minutes: i64) -> SharedPath {
+        SharedPath {
+            gdriveid: gdriveid.into(),
+            urlname: "gdrive://user@gmail.com/My%20Drive/a.txt".into(),
+            plain,
+            synced,
+            created_at: (datetime!(2024-01-01 00:00:00 +00:00) + Duration::minutes(minutes)).into(),
+        }
+    }
+
+    #[test]
+    fn test_plan_duplicates() -> Result<(), Error> {
+        // the file already synced under the plain name keeps it
+        let files = vec![shared("id0", true, false, 5), shared("id1", true, true, 10)];
+        let plan = plan_duplicates("user@gmail.com", &files, &HashMap::new())?;
+        assert_eq!(plan.added.len(), 1);
+        assert!(plan.removed.is_empty());
+        assert_eq!(plan.added[0].gdriveid.as_str(), "id0");
+        assert_eq!(
+            plan.added[0].disambiguated_urlname.as_str(),
+            "gdrive://user@gmail.com/My%20Drive/a~id0.txt"
+        );
+
+        // the next listing finds id0 disambiguated, nothing changes
+        let existing: HashMap<_, _> = plan
+            .added
+            .into_iter()
+            .map(|d| (d.gdriveid.clone(), d))
+            .collect();
+        let files = vec![
+            shared("id0", false, false, 5),
+            shared("id1", true, true, 10),
+        ];
+        let plan = plan_duplicates("user@gmail.com", &files, &existing)?;
+        assert!(plan.added.is_empty() && plan.removed.is_empty());
+
+        // without any history the oldest file keeps the plain name
+        let files = vec![
+            shared("id2", false, false, 3),
+            shared("id1", false, false, 1),
+            shared("id3", false, false, 2),
+        ];
+        let plan = plan_duplicates("user@gmail.com", &files, &HashMap::new())?;
+        let added: Vec<_> = plan.added.iter().map(|d| d.gdriveid.as_str()).collect();
+        assert_eq!(added, ["id2", "id3"]);
+
+        // id1 was removed, id0 is alone at the path again
+        let plan = plan_duplicates("user@gmail.com", &[], &existing)?;
+        assert!(plan.added.is_empty());
+        assert_eq!(plan.removed.len(), 1);
+        Ok(())
+    }
+}
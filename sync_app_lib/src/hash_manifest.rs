@@ -0,0 +1,450 @@
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+use percent_encoding::percent_decode_str;
+use stack_string::{format_sstr, StackString};
+use std::{collections::BTreeMap, fmt, fs, str::FromStr};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use url::Url;
+
+use crate::{
+    file_info::{FileInfo, FileStat},
+    file_list::{remove_baseurl, FileListTrait},
+    file_service::FileService,
+    models::FileInfoCache,
+};
+
+/// Layout of a checksum manifest, `import-hashes` accepts any of them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashFormat {
+    /// `<md5>  <path>`, checked with `md5sum -c`
+    #[default]
+    Md5sum,
+    /// `<sha1>  <path>`, checked with `sha1sum -c`
+    Sha1sum,
+    /// `MD5 (<path>) = <md5>` and `SHA1 (<path>) = <sha1>` lines, as written
+    /// by BSD `md5` or `md5sum --tag`
+    Bsd,
+}
+
+impl HashFormat {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Md5sum => "md5sum",
+            Self::Sha1sum => "sha1sum",
+            Self::Bsd => "bsd",
+        }
+    }
+}
+
+impl fmt::Display for HashFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for HashFormat {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md5sum" | "md5" => Ok(Self::Md5sum),
+            "sha1sum" | "sha1" => Ok(Self::Sha1sum),
+            "bsd" | "tag" => Ok(Self::Bsd),
+            _ => Err(format_err!("Invalid hash format {s}")),
+        }
+    }
+}
+
+/// Checksums of one file of a manifest, `path` is relative to the url the
+/// manifest was exported from
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManifestEntry {
+    pub path: StackString,
+    pub md5sum: Option<StackString>,
+    pub sha1sum: Option<StackString>,
+}
+
+/// coreutils escapes file names containing a backslash or newline and marks
+/// the line with a leading backslash
+fn escape_path(path: &str) -> (bool, StackString) {
+    if path.contains(['\\', '\n', '\r']) {
+        let escaped = path
+            .replace('\\', r"\\")
+            .replace('\n', r"\n")
+            .replace('\r', r"\r");
+        (true, escaped.into())
+    } else {
+        (false, path.into())
+    }
+}
+
+fn unescape_path(path: &str) -> StackString {
+    let mut output = StackString::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => output.push('\n'),
+                Some('r') => output.push('\r'),
+                Some(c) => output.push(c),
+                None => output.push('\\'),
+            }
+        } else {
+            output.push(c);
+        }
+    }
+    output
+}
+
+/// Manifest lines for one file, nothing if it lacks the checksum(s) of
+/// `format`
+#[must_use]
+pub fn format_entry(format: HashFormat, entry: &ManifestEntry) -> Vec<StackString> {
+    let (escaped, path) = escape_path(&entry.path);
+    let prefix = if escaped { "\\" } else { "" };
+    let mut lines = Vec::new();
+    match format {
+        HashFormat::Md5sum => {
+            if let Some(md5sum) = &entry.md5sum {
+                lines.push(format_sstr!("{prefix}{md5sum}  {path}"));
+            }
+        }
+        HashFormat::Sha1sum => {
+            if let Some(sha1sum) = &entry.sha1sum {
+                lines.push(format_sstr!("{prefix}{sha1sum}  {path}"));
+            }
+        }
+        HashFormat::Bsd => {
+            if let Some(md5sum) = &entry.md5sum {
+                lines.push(format_sstr!("{prefix}MD5 ({path}) = {md5sum}"));
+            }
+            if let Some(sha1sum) = &entry.sha1sum {
+                lines.push(format_sstr!("{prefix}SHA1 ({path}) = {sha1sum}"));
+            }
+        }
+    }
+    lines
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Path and lowercase checksum of one manifest line, the algorithm is told
+/// apart by the checksum length in the coreutils formats
+fn parse_line(line: &str) -> Option<(StackString, StackString)> {
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (path, checksum) = if let Some(rest) = line
+        .strip_prefix("MD5 (")
+        .or_else(|| line.strip_prefix("SHA1 ("))
+    {
+        let (path, checksum) = rest.rsplit_once(") = ")?;
+        (path, checksum.trim_end())
+    } else {
+        let (checksum, rest) = line.split_once(' ')?;
+        let path = rest.strip_prefix([' ', '*'])?;
+        (path, checksum)
+    };
+    if !is_hex(checksum) || path.is_empty() {
+        return None;
+    }
+    let path = if escaped {
+        unescape_path(path)
+    } else {
+        path.into()
+    };
+    Some((path, checksum.to_lowercase().into()))
+}
+
+/// Parse a manifest in any of the `HashFormat`s, lines of a file given both
+/// as md5 and sha1 are merged into one entry
+/// # Errors
+/// Return error on a line that isn't a checksum line
+pub fn parse_manifest(text: &str) -> Result<Vec<ManifestEntry>, Error> {
+    let mut entries: BTreeMap<StackString, ManifestEntry> = BTreeMap::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (path, checksum) =
+            parse_line(line).ok_or_else(|| format_err!("Invalid line {}: {line}", number + 1))?;
+        let entry = entries
+            .entry(path.clone())
+            .or_insert_with(|| ManifestEntry {
+                path,
+                ..ManifestEntry::default()
+            });
+        match checksum.len() {
+            32 => entry.md5sum = Some(checksum),
+            40 => entry.sha1sum = Some(checksum),
+            _ => return Err(format_err!("Unknown checksum on line {}", number + 1)),
+        }
+    }
+    Ok(entries.into_values().collect())
+}
+
+/// Path of a cached file relative to the base url of `flist`
+fn relative_path(urlname: &str, baseurl: &Url) -> Result<StackString, Error> {
+    let url: Url = urlname.parse()?;
+    let relpath = remove_baseurl(&url, baseurl);
+    Ok(percent_decode_str(&relpath)
+        .decode_utf8_lossy()
+        .as_ref()
+        .into())
+}
+
+/// Url of a manifest path under `baseurl`
+fn manifest_url(path: &str, baseurl: &Url) -> Result<Url, Error> {
+    let baseurl = format_sstr!("{}/", baseurl.as_str().trim_end_matches('/'));
+    let path = path
+        .trim_start_matches('/')
+        .replace('%', "%25")
+        .replace('#', "%23")
+        .replace('?', "%3F");
+    Url::parse(&baseurl)?.join(&path).map_err(Into::into)
+}
+
+/// Write the cached checksums of the files under `flist` as a manifest,
+/// sorted by path, returns the number of files written
+/// # Errors
+/// Return error if db query fails or `out` can't be written
+pub async fn export_hashes(
+    flist: &dyn FileListTrait,
+    format: HashFormat,
+    out: &mut (dyn AsyncWrite + Unpin + Send),
+) -> Result<usize, Error> {
+    let baseurl = flist.get_baseurl();
+    let prefix = format_sstr!("{}/", baseurl.as_str().trim_end_matches('/'));
+    let mut entries: Vec<ManifestEntry> = FileInfoCache::get_by_prefix_sorted(
+        &prefix,
+        flist.get_servicesession().as_str(),
+        flist.get_servicetype().to_str(),
+        flist.get_pool(),
+    )
+    .await?
+    .map_err(Into::<Error>::into)
+    .and_then(|f| async move {
+        Ok(ManifestEntry {
+            path: relative_path(&f.urlname, baseurl)?,
+            md5sum: f.md5sum,
+            sha1sum: f.sha1sum,
+        })
+    })
+    .try_collect()
+    .await?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let mut written = 0;
+    for entry in &entries {
+        let lines = format_entry(format, entry);
+        if lines.is_empty() {
+            continue;
+        }
+        for line in lines {
+            out.write_all(line.as_bytes()).await?;
+            out.write_all(b"\n").await?;
+        }
+        written += 1;
+    }
+    out.flush().await?;
+    Ok(written)
+}
+
+/// Outcome of `import_hashes`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub imported: usize,
+    pub unchanged: usize,
+    pub missing: usize,
+    pub conflicts: usize,
+}
+
+impl fmt::Display for ImportSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "imported {} unchanged {} missing {} conflicts {}",
+            self.imported, self.unchanged, self.missing, self.conflicts
+        )
+    }
+}
+
+/// Cache entry of a local file that isn't indexed yet, with the size and
+/// mtime it has now so the next index takes the manifest checksums as
+/// current instead of hashing it
+fn local_cache_entry(
+    flist: &dyn FileListTrait,
+    url: &Url,
+    entry: &ManifestEntry,
+) -> Result<Option<FileInfoCache>, Error> {
+    let path = url
+        .to_file_path()
+        .map_err(|e| format_err!("Parse failure {e:?}"))?;
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Ok(None),
+    };
+    let filename = path
+        .file_name()
+        .ok_or_else(|| format_err!("Parse failure"))?
+        .to_string_lossy()
+        .into_owned()
+        .into();
+    let filestat = FileStat::new(metadata.modified()?.into(), metadata.len() as i64);
+    let servicesession = flist.get_servicesession().clone();
+    let finfo = FileInfo::new(
+        filename,
+        path.canonicalize()?.into(),
+        url.clone().into(),
+        entry.md5sum.as_ref().map(|s| s.parse()).transpose()?,
+        entry.sha1sum.as_ref().map(|s| s.parse()).transpose()?,
+        filestat,
+        servicesession.as_str().into(),
+        FileService::Local,
+        servicesession,
+    );
+    Ok(Some(finfo.into()))
+}
+
+/// Record the checksums of a manifest in the cache of `flist`.  Cached files
+/// get the checksums they lack, one whose cached checksum differs from the
+/// manifest is left alone and counted as a conflict.  Local files not
+/// indexed yet are added with their current size and mtime, the manifest is
+/// trusted to be current for them.
+/// # Errors
+/// Return error if db query fails or a checksum is invalid
+pub async fn import_hashes(
+    flist: &dyn FileListTrait,
+    entries: &[ManifestEntry],
+) -> Result<ImportSummary, Error> {
+    let pool = flist.get_pool();
+    let baseurl = flist.get_baseurl();
+    let servicesession = flist.get_servicesession().as_str();
+    let mut summary = ImportSummary::default();
+    for entry in entries {
+        let url = manifest_url(&entry.path, baseurl)?;
+        let cached = FileInfoCache::get_by_urlname(&url, servicesession, pool).await?;
+        let mut cached = match cached {
+            Some(cached) => cached,
+            None => {
+                if flist.get_servicetype() == FileService::Local {
+                    if let Some(cached) = local_cache_entry(flist, &url, entry)? {
+                        cached.insert(pool).await?;
+                        summary.imported += 1;
+                        continue;
+                    }
+                }
+                summary.missing += 1;
+                continue;
+            }
+        };
+        let differs = |cached: &Option<StackString>, new: &Option<StackString>| matches!((cached, new), (Some(a), Some(b)) if !a.eq_ignore_ascii_case(b));
+        if differs(&cached.md5sum, &entry.md5sum) || differs(&cached.sha1sum, &entry.sha1sum) {
+            summary.conflicts += 1;
+            continue;
+        }
+        let mut changed = false;
+        if cached.md5sum.is_none() && entry.md5sum.is_some() {
+            cached.md5sum.clone_from(&entry.md5sum);
+            changed = true;
+        }
+        if cached.sha1sum.is_none() && entry.sha1sum.is_some() {
+            cached.sha1sum.clone_from(&entry.sha1sum);
+            changed = true;
+        }
+        if changed {
+            cached.insert(pool).await?;
+            summary.imported += 1;
+        } else {
+            summary.unchanged += 1;
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::hash_manifest::{
+        format_entry, manifest_url, parse_manifest, relative_path, HashFormat, ManifestEntry,
+    };
+
+    const MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+    const SHA1: &str = "da39a3ee5e6b4b0d3255bfef95601890afd80709";
+
+    #[test]
+    fn test_format_entry() {
+        let entry = ManifestEntry {
+            path: "photos/a b.jpg".into(),
+            md5sum: Some(MD5.into()),
+            sha1sum: Some(SHA1.into()),
+        };
+        assert_eq!(
+            format_entry(HashFormat::Md5sum, &entry),
+            vec![format!("{MD5}  photos/a b.jpg")]
+        );
+        assert_eq!(
+            format_entry(HashFormat::Sha1sum, &entry),
+            vec![format!("{SHA1}  photos/a b.jpg")]
+        );
+        assert_eq!(
+            format_entry(HashFormat::Bsd, &entry),
+            vec![
+                format!("MD5 (photos/a b.jpg) = {MD5}"),
+                format!("SHA1 (photos/a b.jpg) = {SHA1}"),
+            ]
+        );
+        let entry = ManifestEntry {
+            path: "odd\\name\n".into(),
+            md5sum: Some(MD5.into()),
+            sha1sum: None,
+        };
+        assert_eq!(
+            format_entry(HashFormat::Md5sum, &entry),
+            vec![format!("\\{MD5}  odd\\\\name\\n")]
+        );
+        assert!(format_entry(HashFormat::Sha1sum, &entry).is_empty());
+    }
+
+    #[test]
+    fn test_parse_manifest() -> Result<(), Error> {
+        let text = format!(
+            "# comment\n{MD5}  a.txt\n{SHA1} *a.txt\nMD5 (dir/b (1).txt) = {}\n\\{MD5}  \
+             odd\\\\name\\n\n",
+            MD5.to_uppercase()
+        );
+        let entries = parse_manifest(&text)?;
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].path, "a.txt");
+        assert_eq!(entries[0].md5sum.as_deref(), Some(MD5));
+        assert_eq!(entries[0].sha1sum.as_deref(), Some(SHA1));
+        assert_eq!(entries[1].path, "dir/b (1).txt");
+        assert_eq!(entries[1].md5sum.as_deref(), Some(MD5));
+        assert_eq!(entries[2].path, "odd\\name\n");
+
+        for entry in &entries {
+            let lines = format_entry(HashFormat::Bsd, entry).join("\n");
+            assert_eq!(&parse_manifest(&lines)?[0], entry);
+        }
+        assert!(parse_manifest("not a checksum line").is_err());
+        assert!(parse_manifest(&format!("{MD5}x  a.txt")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_manifest_url() -> Result<(), Error> {
+        let baseurl: Url = "file:///home/me/photos/".parse()?;
+        let url = manifest_url("2024/a b#1.jpg", &baseurl)?;
+        assert_eq!(url.as_str(), "file:///home/me/photos/2024/a%20b%231.jpg");
+        assert_eq!(relative_path(url.as_str(), &baseurl)?, "2024/a b#1.jpg");
+        let baseurl: Url = "s3://bucket/prefix".parse()?;
+        let url = manifest_url("x/y.txt", &baseurl)?;
+        assert_eq!(url.as_str(), "s3://bucket/prefix/x/y.txt");
+        Ok(())
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use gdrive_lib::fault_injection::FaultInjector;
+use log::debug;
+use rand::{
+    distributions::{Distribution, Uniform},
+    thread_rng,
+};
+use reqwest::{
+    header::HeaderMap, redirect::Policy, Certificate, Client, NoProxy, Proxy, Response, Url,
+};
+use serde::Serialize;
+use std::{collections::HashMap, env::var, fs, future::Future, time::Duration};
+use tokio::time::sleep;
+
+use crate::config::ProxyConfig;
+
+/// Limit on a single request, retries get a fresh timeout
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// The configured proxy, otherwise `HTTPS_PROXY` / `https_proxy`.  Hosts in
+/// `NO_PROXY` bypass either.
+fn proxy(config: &ProxyConfig) -> Result<Option<Proxy>, Error> {
+    let proxy = if let Some(url) = &config.url {
+        debug!("using proxy {url}");
+        Proxy::all(url.clone())?
+    } else {
+        match var("HTTPS_PROXY").or_else(|_| var("https_proxy")) {
+            Ok(url) if !url.is_empty() => {
+                debug!("using proxy {url}");
+                Proxy::https(&url)?
+            }
+            _ => return Ok(None),
+        }
+    };
+    Ok(Some(proxy.no_proxy(NoProxy::from_env())))
+}
+
+/// Client with a cookie store, gzip, a request timeout, the proxy and the
+/// extra root certificates of `proxy`, shared by every service session
+/// # Errors
+/// Returns error if creation of client fails, the proxy url is invalid or
+/// the ca bundle can't be read
+pub fn build_client(allow_redirects: bool, proxy_config: &ProxyConfig) -> Result<Client, Error> {
+    let redirect_policy = if allow_redirects {
+        Policy::default()
+    } else {
+        Policy::none()
+    };
+    let mut builder = Client::builder()
+        .cookie_store(true)
+        .gzip(true)
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(redirect_policy);
+    if let Some(proxy) = proxy(proxy_config)? {
+        builder = builder.proxy(proxy);
+    }
+    if let Some(ca_bundle) = &proxy_config.ca_bundle {
+        let pem = fs::read(ca_bundle)
+            .map_err(|e| format_err!("Can't read {}: {e}", ca_bundle.display()))?;
+        for cert in Certificate::from_pem_bundle(&pem)? {
+            builder = builder.add_root_certificate(cert);
+        }
+    }
+    builder.build().map_err(Into::into)
+}
+
+/// Retry `f` with a randomized, growing delay until it succeeds or the delay
+/// reaches 64 seconds, faults configured with `SYNC_APP_FAULT_*` are injected
+/// into each attempt
+/// # Errors
+/// Returns the last error once the retries are exhausted
+pub async fn exponential_retry<T, U, V>(f: T) -> Result<U, Error>
+where
+    T: Fn() -> V,
+    V: Future<Output = Result<U, Error>>,
+{
+    let faults = FaultInjector::global();
+    let mut timeout: f64 = 1.0;
+    let range = Uniform::from(0..1000);
+    loop {
+        let resp = match faults {
+            Some(faults) => match faults.before("request").await {
+                Ok(_) => f().await,
+                Err(e) => Err(e),
+            },
+            None => f().await,
+        };
+        match resp {
+            Ok(x) => return Ok(x),
+            Err(e) => {
+                sleep(Duration::from_millis((timeout * 1000.0) as u64)).await;
+                timeout *= 4.0 * f64::from(range.sample(&mut thread_rng())) / 1000.0;
+                if timeout >= 64.0 {
+                    return Err(format_err!(e));
+                }
+            }
+        }
+    }
+}
+
+/// Session against an authenticated http api, implementors only provide the
+/// client (usually from `build_client`) and get requests with retries
+#[async_trait]
+pub trait AuthenticatedClient: Send + Sync {
+    fn client(&self) -> &Client;
+
+    /// # Errors
+    /// Return error if the request fails after retries
+    async fn get(&self, url: &Url, headers: &HeaderMap) -> Result<Response, Error> {
+        exponential_retry(|| async move {
+            self.client()
+                .get(url.clone())
+                .headers(headers.clone())
+                .send()
+                .await
+                .map_err(Into::into)
+        })
+        .await
+    }
+
+    /// # Errors
+    /// Return error if the request fails after retries
+    async fn post_empty(&self, url: &Url, headers: &HeaderMap) -> Result<Response, Error> {
+        exponential_retry(|| async move {
+            self.client()
+                .post(url.clone())
+                .headers(headers.clone())
+                .send()
+                .await
+                .map_err(Into::into)
+        })
+        .await
+    }
+
+    /// # Errors
+    /// Return error if the request fails after retries
+    async fn post<T>(
+        &self,
+        url: &Url,
+        headers: &HeaderMap,
+        form: &HashMap<&str, T>,
+    ) -> Result<Response, Error>
+    where
+        T: Serialize + Sync,
+    {
+        exponential_retry(|| async move {
+            self.client()
+                .post(url.clone())
+                .headers(headers.clone())
+                .json(form)
+                .send()
+                .await
+                .map_err(Into::into)
+        })
+        .await
+    }
+
+    /// # Errors
+    /// Return error if the request fails after retries
+    async fn delete(&self, url: &Url, headers: &HeaderMap) -> Result<Response, Error> {
+        exponential_retry(|| async move {
+            self.client()
+                .delete(url.clone())
+                .headers(headers.clone())
+                .send()
+                .await
+                .map_err(Into::into)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use crate::http_client::exponential_retry;
+
+    #[tokio::test]
+    async fn test_exponential_retry() -> Result<(), Error> {
+        let attempts = AtomicUsize::new(0);
+        let result = exponential_retry(|| async {
+            if attempts.fetch_add(1, Ordering::SeqCst) == 0 {
+                Err(format_err!("first attempt fails"))
+            } else {
+                Ok(42)
+            }
+        })
+        .await?;
+        assert_eq!(result, 42);
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
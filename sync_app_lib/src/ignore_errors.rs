@@ -0,0 +1,132 @@
+use anyhow::Error;
+use futures::TryStreamExt;
+use stack_string::StackString;
+use url::Url;
+
+use crate::{models::FileSyncConfig, pgpool::PgPool};
+
+/// A single suppression rule, either `error:<substring>` matched against the
+/// error message, or a glob (`*` and `?`) matched against the url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IgnoreRule {
+    ErrorClass(StackString),
+    Glob(StackString),
+}
+
+impl IgnoreRule {
+    #[must_use]
+    pub fn new(rule: &str) -> Self {
+        if let Some(class) = rule.strip_prefix("error:") {
+            Self::ErrorClass(class.into())
+        } else {
+            Self::Glob(rule.into())
+        }
+    }
+
+    #[must_use]
+    pub fn matches(&self, url: &Url, err: &Error) -> bool {
+        match self {
+            Self::ErrorClass(class) => err.to_string().contains(class.as_str()),
+            Self::Glob(pattern) => glob_match(pattern, url.as_str()),
+        }
+    }
+}
+
+/// Suppression rules for every config, keyed on the config's src/dst urls.
+#[derive(Debug, Default, Clone)]
+pub struct IgnoreRules(Vec<(StackString, IgnoreRule)>);
+
+impl IgnoreRules {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn from_db(pool: &PgPool) -> Result<Self, Error> {
+        let configs: Vec<FileSyncConfig> = FileSyncConfig::get_config_list(pool)
+            .await?
+            .try_collect()
+            .await?;
+        let rules = configs
+            .iter()
+            .flat_map(|conf| {
+                conf.ignore_errors.iter().flat_map(move |rule| {
+                    vec![
+                        (conf.src_url.clone(), IgnoreRule::new(rule)),
+                        (conf.dst_url.clone(), IgnoreRule::new(rule)),
+                    ]
+                })
+            })
+            .collect();
+        Ok(Self(rules))
+    }
+
+    #[must_use]
+    pub fn is_ignored(&self, url: &Url, err: &Error) -> bool {
+        self.0.iter().any(|(prefix, rule)| {
+            url.as_str().starts_with(prefix.as_str()) && rule.matches(url, err)
+        })
+    }
+}
+
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use url::Url;
+
+    use crate::ignore_errors::{glob_match, IgnoreRule, IgnoreRules};
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.gform", "gdrive://user@gmail.com/a/b.gform"));
+        assert!(glob_match(
+            "*/private/*",
+            "file:///home/user/private/file.txt"
+        ));
+        assert!(glob_match("file?.txt", "file1.txt"));
+        assert!(!glob_match("*.gform", "gdrive://user@gmail.com/a/b.txt"));
+        assert!(!glob_match("file?.txt", "file10.txt"));
+    }
+
+    #[test]
+    fn test_ignore_rules() -> Result<(), Error> {
+        let rules = IgnoreRules(vec![
+            (
+                "gdrive://user@gmail.com/".into(),
+                IgnoreRule::new("*.gform"),
+            ),
+            (
+                "file:///home/user/".into(),
+                IgnoreRule::new("error:Permission denied"),
+            ),
+        ]);
+        let err = format_err!("Permission denied (os error 13)");
+        let url: Url = "gdrive://user@gmail.com/a/b.gform".parse()?;
+        assert!(rules.is_ignored(&url, &err));
+        let url: Url = "file:///home/user/secret/file.txt".parse()?;
+        assert!(rules.is_ignored(&url, &err));
+        let url: Url = "file:///home/other/file.gform".parse()?;
+        assert!(!rules.is_ignored(&url, &err));
+        Ok(())
+    }
+}
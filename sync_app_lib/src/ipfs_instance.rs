@@ -0,0 +1,227 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use reqwest::{
+    multipart::{Form, Part},
+    Body, Client, Response, Url,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, path::Path};
+use tokio::fs::File;
+
+/// Minimal client for the kubo (go-ipfs) RPC api
+#[derive(Debug, Clone)]
+pub struct IpfsInstance {
+    client: Client,
+    api_url: Url,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct IpfsEntry {
+    pub name: StackString,
+    #[serde(rename = "Type")]
+    pub entry_type: u8,
+    pub size: i64,
+    pub hash: StackString,
+}
+
+impl IpfsEntry {
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.entry_type == 1
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IpfsAddResponse {
+    hash: StackString,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IpfsLsResponse {
+    entries: Option<Vec<IpfsEntry>>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct IpfsPinLsResponse {
+    keys: HashMap<StackString, serde_json::Value>,
+}
+
+impl IpfsInstance {
+    /// # Errors
+    /// Return error if the api url can't be constructed
+    pub fn new(host: &str, port: u16) -> Result<Self, Error> {
+        let api_url = format_sstr!("http://{host}:{port}/api/v0/");
+        Ok(Self {
+            client: Client::new(),
+            api_url: api_url.parse()?,
+        })
+    }
+
+    async fn post(&self, cmd: &str, args: &[(&str, &str)]) -> Result<Response, Error> {
+        let url = self.api_url.join(cmd)?;
+        debug!("ipfs {url} {args:?}");
+        let resp = self.client.post(url).query(args).send().await?;
+        if resp.status().is_success() {
+            Ok(resp)
+        } else {
+            let status = resp.status();
+            let text = resp.text().await?;
+            Err(format_err!("ipfs {cmd} failed {status} {text}"))
+        }
+    }
+
+    async fn post_json<T: DeserializeOwned>(
+        &self,
+        cmd: &str,
+        args: &[(&str, &str)],
+    ) -> Result<T, Error> {
+        self.post(cmd, args).await?.json().await.map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if the node isn't reachable
+    pub async fn probe(&self) -> Result<(), Error> {
+        self.post("version", &[]).await.map(|_| ())
+    }
+
+    /// Add and pin a local file, returns the CID
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn add(&self, local_path: &Path) -> Result<StackString, Error> {
+        let file = File::open(local_path).await?;
+        let filename = local_path
+            .file_name()
+            .map_or_else(|| "file".into(), |f| f.to_string_lossy().into_owned());
+        let form = Form::new().part("file", Part::stream(Body::from(file)).file_name(filename));
+        let url = self.api_url.join("add")?;
+        let resp = self
+            .client
+            .post(url)
+            .query(&[("pin", "true"), ("cid-version", "1")])
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()?;
+        let resp: IpfsAddResponse = resp.json().await?;
+        Ok(resp.hash)
+    }
+
+    /// Link `cid` into the mutable file system at `mfs_path`, replacing
+    /// whatever was there before, returns the CID of the replaced file (which
+    /// is still pinned)
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn files_cp(&self, cid: &str, mfs_path: &str) -> Result<Option<StackString>, Error> {
+        let replaced = self.files_stat(mfs_path).await.ok();
+        if replaced.is_some() {
+            self.files_rm(mfs_path).await?;
+        }
+        let src = format_sstr!("/ipfs/{cid}");
+        self.post(
+            "files/cp",
+            &[("arg", &src), ("arg", mfs_path), ("parents", "true")],
+        )
+        .await?;
+        Ok(replaced)
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn files_rm(&self, mfs_path: &str) -> Result<(), Error> {
+        self.post("files/rm", &[("arg", mfs_path), ("force", "true")])
+            .await
+            .map(|_| ())
+    }
+
+    /// # Errors
+    /// Return error if api call fails (including when the path doesn't exist)
+    pub async fn files_stat(&self, mfs_path: &str) -> Result<StackString, Error> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "PascalCase")]
+        struct Stat {
+            hash: StackString,
+        }
+        let stat: Stat = self.post_json("files/stat", &[("arg", mfs_path)]).await?;
+        Ok(stat.hash)
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn files_ls(&self, mfs_path: &str) -> Result<Vec<IpfsEntry>, Error> {
+        let resp: IpfsLsResponse = self
+            .post_json("files/ls", &[("arg", mfs_path), ("long", "true")])
+            .await?;
+        Ok(resp.entries.unwrap_or_default())
+    }
+
+    /// Recursively list every file below `mfs_path`, returns `(path, entry)`
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn files_ls_recursive(
+        &self,
+        mfs_path: &str,
+    ) -> Result<Vec<(StackString, IpfsEntry)>, Error> {
+        let mut output = Vec::new();
+        let mut stack: Vec<StackString> = vec![mfs_path.into()];
+        while let Some(dir) = stack.pop() {
+            for entry in self.files_ls(&dir).await? {
+                let path = format_sstr!("{}/{}", dir.trim_end_matches('/'), entry.name);
+                if entry.is_dir() {
+                    stack.push(path);
+                } else {
+                    output.push((path, entry));
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn is_pinned(&self, cid: &str) -> Result<bool, Error> {
+        match self
+            .post_json::<IpfsPinLsResponse>("pin/ls", &[("arg", cid), ("type", "recursive")])
+            .await
+        {
+            Ok(resp) => Ok(resp.keys.contains_key(cid)),
+            Err(e) if e.to_string().contains("is not pinned") => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn pin_rm(&self, cid: &str) -> Result<(), Error> {
+        self.post("pin/rm", &[("arg", cid)]).await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::ipfs_instance::IpfsLsResponse;
+
+    #[test]
+    fn test_ipfs_ls_response() -> Result<(), Error> {
+        let data = r#"{"Entries":[
+            {"Name":"a.txt","Type":0,"Size":12,"Hash":"bafkreia"},
+            {"Name":"dir","Type":1,"Size":0,"Hash":"bafybeib"}
+        ]}"#;
+        let resp: IpfsLsResponse = serde_json::from_str(data)?;
+        let entries = resp.entries.unwrap_or_default();
+        assert_eq!(entries.len(), 2);
+        assert!(!entries[0].is_dir());
+        assert!(entries[1].is_dir());
+        assert_eq!(entries[0].size, 12);
+
+        let resp: IpfsLsResponse = serde_json::from_str(r#"{"Entries":null}"#)?;
+        assert!(resp.entries.is_none());
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{fmt, str::FromStr};
+use time::OffsetDateTime;
+use url::Url;
+
+use crate::file_list::replace_baseurl;
+
+/// How the files of a config's source are laid out under its destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DestinationLayout {
+    /// Same relative path on both sides
+    #[default]
+    Flat,
+    /// `<YYYY>/<relative path>` by the source file's mtime
+    Yearly,
+    /// `<YYYY>/<MM>/<relative path>` by the source file's mtime
+    Monthly,
+}
+
+impl DestinationLayout {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Flat => "flat",
+            Self::Yearly => "yearly",
+            Self::Monthly => "monthly",
+        }
+    }
+
+    /// Directory prefix of a file last modified at `mtime`, `None` for the
+    /// flat layout
+    #[must_use]
+    pub fn partition(self, mtime: OffsetDateTime) -> Option<StackString> {
+        let date = mtime.date();
+        match self {
+            Self::Flat => None,
+            Self::Yearly => Some(format_sstr!("{:04}", date.year())),
+            Self::Monthly => Some(format_sstr!(
+                "{:04}/{:02}",
+                date.year(),
+                u8::from(date.month())
+            )),
+        }
+    }
+
+    /// Where `url0` under `baseurl0` goes under `baseurl1`
+    /// # Errors
+    /// Return error if the resulting url is invalid
+    pub fn destination_url(
+        self,
+        url0: &Url,
+        baseurl0: &Url,
+        baseurl1: &Url,
+        mtime: OffsetDateTime,
+    ) -> Result<Url, Error> {
+        match self.partition(mtime) {
+            Some(partition) => {
+                let baseurl1 = baseurl1.as_str().trim_end_matches('/');
+                let baseurl1: Url = format_sstr!("{baseurl1}/{partition}").parse()?;
+                replace_baseurl(url0, baseurl0, &baseurl1)
+            }
+            None => replace_baseurl(url0, baseurl0, baseurl1),
+        }
+    }
+}
+
+impl fmt::Display for DestinationLayout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for DestinationLayout {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flat" => Ok(Self::Flat),
+            "yearly" => Ok(Self::Yearly),
+            "monthly" => Ok(Self::Monthly),
+            _ => Err(format_err!("Invalid destination layout {s}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+    use url::Url;
+
+    use crate::layout::DestinationLayout;
+
+    #[test]
+    fn test_destination_layout() -> Result<(), Error> {
+        for layout in [
+            DestinationLayout::Flat,
+            DestinationLayout::Yearly,
+            DestinationLayout::Monthly,
+        ] {
+            let parsed: DestinationLayout = layout.to_str().parse()?;
+            assert_eq!(parsed, layout);
+        }
+        assert!("daily".parse::<DestinationLayout>().is_err());
+
+        let mtime = datetime!(2023-04-05 06:07:08 UTC);
+        assert_eq!(DestinationLayout::Flat.partition(mtime), None);
+        assert_eq!(
+            DestinationLayout::Yearly.partition(mtime).as_deref(),
+            Some("2023")
+        );
+        assert_eq!(
+            DestinationLayout::Monthly.partition(mtime).as_deref(),
+            Some("2023/04")
+        );
+
+        let baseurl0: Url = "file:///home/me/camera".parse()?;
+        let baseurl1: Url = "s3://archive/photos/".parse()?;
+        let url0: Url = "file:///home/me/camera/trip/IMG_0001.jpg".parse()?;
+        let url1 =
+            DestinationLayout::Monthly.destination_url(&url0, &baseurl0, &baseurl1, mtime)?;
+        assert_eq!(
+            url1.as_str(),
+            "s3://archive/photos/2023/04/trip/IMG_0001.jpg"
+        );
+        let url1 = DestinationLayout::Flat.destination_url(&url0, &baseurl0, &baseurl1, mtime)?;
+        assert_eq!(url1.as_str(), "s3://archive/photos/trip/IMG_0001.jpg");
+        Ok(())
+    }
+}
@@ -11,35 +11,83 @@
 // #![allow(clippy::missing_panics_doc)]
 // #![allow(clippy::return_self_not_must_use)]
 
+pub mod byte_accounting;
+pub mod cache_edit;
+pub mod calendar_ics;
 pub mod calendar_sync;
+pub mod cancellation;
+pub mod checksum_hook;
+pub mod compression;
 pub mod config;
+pub mod config_audit;
+pub mod conflict;
+pub mod cost_estimate;
+pub mod cron;
+pub mod delta;
+pub mod encryption;
+pub mod event_hook;
 pub mod file_info;
 pub mod file_info_gcs;
 pub mod file_info_gdrive;
+pub mod file_info_ipfs;
 pub mod file_info_local;
 pub mod file_info_s3;
+pub mod file_info_sftp;
+pub mod file_info_smb;
 pub mod file_info_ssh;
 pub mod file_list;
 pub mod file_list_gcs;
 pub mod file_list_gdrive;
+pub mod file_list_ipfs;
 pub mod file_list_local;
 pub mod file_list_s3;
+pub mod file_list_sftp;
+pub mod file_list_smb;
 pub mod file_list_ssh;
 pub mod file_service;
 pub mod file_sync;
 pub mod garmin_sync;
+pub mod gdrive_duplicates;
+pub mod hash_manifest;
+pub mod http_client;
+pub mod ignore_errors;
+pub mod ipfs_instance;
+pub mod layout;
+pub mod link_farm;
 pub mod local_session;
+pub mod log_routing;
+pub mod metadata_extract;
 pub mod models;
+pub mod move_detection;
 pub mod movie_sync;
+pub mod ownership;
+pub mod pagination;
+pub mod partial;
 pub mod path_buf_wrapper;
+pub mod path_validation;
 pub mod pgpool;
+pub mod progress;
+pub mod provider_health;
+pub mod query_stats;
 pub mod reqwest_session;
+pub mod retention;
+pub mod run_summary;
 pub mod s3_instance;
+pub mod schema;
 pub mod security_sync;
+pub mod self_test;
+pub mod sftp_instance;
+pub mod smb_instance;
 pub mod ssh_instance;
+pub mod storage_class;
 pub mod sync_client;
 pub mod sync_opts;
+pub mod trash;
 pub mod url_wrapper;
+pub mod usage_trend;
+pub mod verify;
+pub mod virtual_root;
+pub mod watch;
 pub mod weather_sync;
 
 use anyhow::Error;
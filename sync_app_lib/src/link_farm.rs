@@ -0,0 +1,287 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{
+    fmt,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+use time::{macros::format_description, OffsetDateTime, PrimitiveDateTime};
+use tokio::task::spawn_blocking;
+use walkdir::WalkDir;
+
+/// Generations kept by `prune_generations` when `--keep` isn't given
+pub const DEFAULT_GENERATIONS: usize = 7;
+
+/// Name of the generation directory started at `time`, these sort in
+/// chronological order
+#[must_use]
+pub fn generation_name(time: OffsetDateTime) -> StackString {
+    format_sstr!(
+        "{:04}-{:02}-{:02}T{:02}{:02}{:02}",
+        time.year(),
+        u8::from(time.month()),
+        time.day(),
+        time.hour(),
+        time.minute(),
+        time.second()
+    )
+}
+
+/// Start time of a generation directory, `None` for anything else found
+/// under the mirror
+#[must_use]
+pub fn generation_time(name: &str) -> Option<PrimitiveDateTime> {
+    PrimitiveDateTime::parse(
+        name,
+        format_description!("[year]-[month]-[day]T[hour][minute][second]"),
+    )
+    .ok()
+}
+
+/// Generation directories under `base`, oldest first
+/// # Errors
+/// Return error if `base` can't be read
+pub fn list_generations(base: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut generations = Vec::new();
+    if !base.exists() {
+        return Ok(generations);
+    }
+    for entry in fs::read_dir(base)? {
+        let entry = entry?;
+        let is_generation = entry
+            .file_name()
+            .to_str()
+            .and_then(generation_time)
+            .is_some();
+        if is_generation && entry.file_type()?.is_dir() {
+            generations.push(entry.path());
+        }
+    }
+    generations.sort();
+    Ok(generations)
+}
+
+/// Outcome of one `link_farm` run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LinkFarmSummary {
+    pub generation: PathBuf,
+    pub previous: Option<PathBuf>,
+    pub linked: usize,
+    pub copied: usize,
+    pub bytes_copied: u64,
+}
+
+impl fmt::Display for LinkFarmSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} linked {} copied {} ({} bytes)",
+            self.generation.display(),
+            self.linked,
+            self.copied,
+            self.bytes_copied
+        )
+    }
+}
+
+/// Unchanged means same size and modification time, like rsync's quick
+/// check
+fn is_unchanged(src: &fs::Metadata, previous: &Path) -> bool {
+    fs::metadata(previous).map_or(false, |prev| {
+        prev.is_file() && prev.len() == src.len() && prev.modified().ok() == src.modified().ok()
+    })
+}
+
+fn copy_with_mtime(src: &Path, dst: &Path, modified: SystemTime) -> Result<u64, Error> {
+    let bytes = fs::copy(src, dst)?;
+    File::options()
+        .write(true)
+        .open(dst)?
+        .set_modified(modified)?;
+    Ok(bytes)
+}
+
+/// Hard link or copy every file of `src` into `dst`, linking to `previous`
+/// where unchanged
+fn fill_generation(src: &Path, dst: &Path, summary: &mut LinkFarmSummary) -> Result<(), Error> {
+    for entry in WalkDir::new(src).min_depth(1) {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(src)?;
+        let path = dst.join(relative);
+        let file_type = entry.file_type();
+        if file_type.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        } else if !file_type.is_file() {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if let Some(previous) = &summary.previous {
+            let prev = previous.join(relative);
+            if is_unchanged(&metadata, &prev) {
+                fs::hard_link(&prev, &path)?;
+                summary.linked += 1;
+                continue;
+            }
+        }
+        summary.bytes_copied += copy_with_mtime(entry.path(), &path, metadata.modified()?)?;
+        summary.copied += 1;
+    }
+    Ok(())
+}
+
+fn mirror_blocking(src: &Path, base: &Path, now: OffsetDateTime) -> Result<LinkFarmSummary, Error> {
+    if !src.is_dir() {
+        return Err(format_err!("{} is not a directory", src.display()));
+    }
+    let previous = list_generations(base)?.pop();
+    let name = generation_name(now);
+    let generation = base.join(name.as_str());
+    if generation.exists() {
+        return Err(format_err!("{} already exists", generation.display()));
+    }
+    // built under a name `list_generations` skips and renamed once complete,
+    // so a failed run never becomes the next link-dest or counts for pruning
+    let partial = base.join(format_sstr!(".{name}.partial").as_str());
+    if partial.exists() {
+        fs::remove_dir_all(&partial)?;
+    }
+    fs::create_dir_all(&partial)?;
+    let mut summary = LinkFarmSummary {
+        generation,
+        previous,
+        linked: 0,
+        copied: 0,
+        bytes_copied: 0,
+    };
+    if let Err(e) = fill_generation(src, &partial, &mut summary) {
+        fs::remove_dir_all(&partial).ok();
+        return Err(e);
+    }
+    fs::rename(&partial, &summary.generation)?;
+    Ok(summary)
+}
+
+/// Mirror `src` into a new dated generation directory under `base`, files
+/// unchanged since the latest generation are hard linked to it rather than
+/// copied (like `rsync --link-dest`), so every generation is a complete
+/// tree while only the changes take up space
+/// # Errors
+/// Return error if `src` can't be read or the generation can't be written,
+/// `base` must be on the same filesystem as its generations
+pub async fn mirror_generation(
+    src: &Path,
+    base: &Path,
+    now: OffsetDateTime,
+) -> Result<LinkFarmSummary, Error> {
+    let src = src.to_path_buf();
+    let base = base.to_path_buf();
+    spawn_blocking(move || mirror_blocking(&src, &base, now)).await?
+}
+
+/// Remove all but the newest `keep` generations under `base`, returns the
+/// directories removed (or that would be with `dry_run`).  Files still
+/// linked from a newer generation keep their data.
+/// # Errors
+/// Return error if `keep` is zero or a generation can't be removed
+pub async fn prune_generations(
+    base: &Path,
+    keep: usize,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    if keep == 0 {
+        return Err(format_err!("Refusing to prune every generation"));
+    }
+    let base = base.to_path_buf();
+    spawn_blocking(move || {
+        let mut generations = list_generations(&base)?;
+        let expired = generations.len().saturating_sub(keep);
+        generations.truncate(expired);
+        if !dry_run {
+            for path in &generations {
+                fs::remove_dir_all(path)?;
+            }
+        }
+        Ok(generations)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use std::{env::temp_dir, fs};
+    use time::macros::datetime;
+    use uuid::Uuid;
+
+    #[cfg(unix)]
+    use std::os::unix::fs::MetadataExt;
+
+    use crate::link_farm::{
+        generation_name, generation_time, list_generations, mirror_generation, prune_generations,
+    };
+
+    #[test]
+    fn test_generation_name() {
+        let name = generation_name(datetime!(2024-03-09 04:05:06 UTC));
+        assert_eq!(name, "2024-03-09T040506");
+        assert_eq!(generation_time(&name), Some(datetime!(2024-03-09 04:05:06)));
+        assert_eq!(generation_time("latest"), None);
+    }
+
+    #[tokio::test]
+    async fn test_link_farm() -> Result<(), Error> {
+        let dir = temp_dir().join(format_sstr!("link_farm_{}", Uuid::new_v4()).as_str());
+        let src = dir.join("src");
+        let base = dir.join("mirror");
+        fs::create_dir_all(src.join("sub"))?;
+        fs::write(src.join("a.txt"), b"a")?;
+        fs::write(src.join("sub").join("b.txt"), b"b")?;
+
+        let first = mirror_generation(&src, &base, datetime!(2024-03-09 00:00:00 UTC)).await?;
+        assert_eq!((first.linked, first.copied), (0, 2));
+        assert_eq!(first.previous, None);
+
+        fs::write(src.join("a.txt"), b"changed")?;
+        let second = mirror_generation(&src, &base, datetime!(2024-03-10 00:00:00 UTC)).await?;
+        assert_eq!((second.linked, second.copied), (1, 1));
+        assert_eq!(second.previous.as_ref(), Some(&first.generation));
+        assert_eq!(fs::read(second.generation.join("a.txt"))?, b"changed");
+        assert_eq!(fs::read(first.generation.join("a.txt"))?, b"a");
+        #[cfg(unix)]
+        assert_eq!(
+            fs::metadata(second.generation.join("sub/b.txt"))?.ino(),
+            fs::metadata(first.generation.join("sub/b.txt"))?.ino()
+        );
+
+        assert!(
+            mirror_generation(&src, &base, datetime!(2024-03-10 00:00:00 UTC))
+                .await
+                .is_err()
+        );
+        fs::create_dir_all(base.join("notes"))?;
+        assert_eq!(list_generations(&base)?.len(), 2);
+
+        // what a killed run left behind is neither listed nor kept
+        let stale = base.join(".2024-03-11T000000.partial");
+        fs::create_dir_all(&stale)?;
+        fs::write(stale.join("stale.txt"), b"stale")?;
+        assert_eq!(list_generations(&base)?.len(), 2);
+        let third = mirror_generation(&src, &base, datetime!(2024-03-11 00:00:00 UTC)).await?;
+        assert!(!stale.exists());
+        assert!(!third.generation.join("stale.txt").exists());
+        fs::remove_dir_all(&third.generation)?;
+
+        let pruned = prune_generations(&base, 1, true).await?;
+        assert_eq!(pruned, vec![first.generation.clone()]);
+        assert!(first.generation.exists());
+        prune_generations(&base, 1, false).await?;
+        assert!(!first.generation.exists());
+        assert_eq!(fs::read(second.generation.join("sub/b.txt"))?, b"b");
+        assert!(prune_generations(&base, 0, false).await.is_err());
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,369 @@
+use anyhow::Error;
+use futures::{future, TryStreamExt};
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+use parking_lot::{const_mutex, Mutex};
+use stack_string::{format_sstr, StackString};
+use std::{
+    env::var,
+    fs::{create_dir_all, File, OpenOptions},
+    future::Future,
+    io::Write,
+    path::PathBuf,
+    sync::Arc,
+};
+use time::{macros::format_description, OffsetDateTime};
+use tokio::task_local;
+
+use crate::{config::Config, models::FileSyncConfig, pgpool::PgPool};
+
+task_local! {
+    static LOG_SCOPE: Arc<LogScope>;
+}
+
+/// Levels of the scopes currently running, `log::max_level` is the highest
+/// of them and `base`, the level it had before the first one started
+struct ScopeLevels {
+    base: LevelFilter,
+    active: Vec<LevelFilter>,
+}
+
+static SCOPE_LEVELS: Mutex<ScopeLevels> = const_mutex(ScopeLevels {
+    base: LevelFilter::Off,
+    active: Vec::new(),
+});
+
+/// Logging of the work done for one config: its `log_level` replaces the
+/// default level of `RUST_LOG` (per module levels still apply), and with
+/// `log_file_template` set its records are also appended to a file of its
+/// own for this run
+#[derive(Debug)]
+pub struct LogScope {
+    name: StackString,
+    level: Option<LevelFilter>,
+    filter: Option<env_filter::Filter>,
+    path: Option<PathBuf>,
+    file: Mutex<Option<File>>,
+}
+
+impl LogScope {
+    /// Scope of `conf` for the run started at `run`, `None` if it neither
+    /// overrides the level nor gets a log file
+    /// # Errors
+    /// Return error if the stored `log_level` is invalid
+    pub fn for_config(
+        conf: &FileSyncConfig,
+        config: &Config,
+        run: OffsetDateTime,
+    ) -> Result<Option<Arc<Self>>, Error> {
+        let level: Option<LevelFilter> = conf.log_level.as_ref().map(|l| l.parse()).transpose()?;
+        let name = conf
+            .name
+            .clone()
+            .unwrap_or_else(|| format_sstr!("{}", conf.id));
+        let path = config
+            .log_file_template
+            .as_ref()
+            .map(|template| log_file_path(template, &name, run));
+        if level.is_none() && path.is_none() {
+            return Ok(None);
+        }
+        let spec = var("RUST_LOG").ok();
+        Ok(Some(Arc::new(Self {
+            name,
+            level,
+            filter: level.map(|level| scope_filter(spec.as_deref(), level)),
+            path,
+            file: Mutex::new(None),
+        })))
+    }
+
+    fn enabled(&self, metadata: &Metadata, default: bool) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(default, |filter| filter.enabled(metadata))
+    }
+
+    /// Append to the log file, opened on the first record so that configs
+    /// with nothing to do don't leave empty files behind
+    fn write(&self, line: &str) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut file = self.file.lock();
+        if file.is_none() {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).ok();
+            }
+            *file = OpenOptions::new().create(true).append(true).open(path).ok();
+        }
+        if let Some(f) = file.as_mut() {
+            writeln!(f, "{line}").ok();
+        }
+    }
+}
+
+/// The `RUST_LOG` directives in `spec` with `level` in place of the default
+/// level, so that e.g. `hyper=warn` still quiets hyper
+fn scope_filter(spec: Option<&str>, level: LevelFilter) -> env_filter::Filter {
+    let mut builder = env_filter::Builder::new();
+    if let Some(spec) = spec {
+        builder.parse(spec);
+    }
+    builder.filter_level(level);
+    builder.build()
+}
+
+/// `template` with `{name}` replaced by the config name and `{run}` by the
+/// start time of the run
+fn log_file_path(template: &str, name: &str, run: OffsetDateTime) -> PathBuf {
+    let run = run
+        .format(format_description!(
+            "[year][month][day]T[hour][minute][second]"
+        ))
+        .unwrap_or_default();
+    let name = name.replace(['/', '\\'], "_");
+    template
+        .replace("{name}", &name)
+        .replace("{run}", &run)
+        .into()
+}
+
+/// Log scopes of the configs a batch of transfers may belong to, a url is
+/// matched to the config whose source or destination contains it
+#[derive(Debug, Default)]
+pub struct LogRoutes {
+    routes: Vec<(StackString, StackString, Arc<LogScope>)>,
+}
+
+impl LogRoutes {
+    /// Scopes of the enabled configs, with log files named after `run`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn from_db(
+        config: &Config,
+        run: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let mut routes = Vec::new();
+        let configs: Vec<FileSyncConfig> = FileSyncConfig::get_config_list(pool)
+            .await?
+            .try_filter(|v| future::ready(v.enabled))
+            .try_collect()
+            .await?;
+        for conf in configs {
+            if let Some(scope) = LogScope::for_config(&conf, config, run)? {
+                routes.push((conf.src_url, conf.dst_url, scope));
+            }
+        }
+        Ok(Self { routes })
+    }
+
+    #[must_use]
+    pub fn scope_for(&self, url: &str) -> Option<Arc<LogScope>> {
+        self.routes
+            .iter()
+            .find(|(src, dst, _)| is_under(url, src) || is_under(url, dst))
+            .map(|(_, _, scope)| scope.clone())
+    }
+}
+
+/// True if `url` is `base` or below it, `s3://bucket/photos` doesn't
+/// contain `s3://bucket/photos2`
+fn is_under(url: &str, base: &str) -> bool {
+    let base = base.trim_end_matches('/');
+    url.strip_prefix(base)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Raises `log::max_level` to the level of a scope while it runs, the
+/// level is lowered again once no running scope needs it
+struct ScopeLevel(LevelFilter);
+
+impl ScopeLevel {
+    fn enter(level: LevelFilter) -> Self {
+        let mut levels = SCOPE_LEVELS.lock();
+        if levels.active.is_empty() {
+            levels.base = log::max_level();
+        }
+        levels.active.push(level);
+        levels.apply();
+        Self(level)
+    }
+}
+
+impl Drop for ScopeLevel {
+    fn drop(&mut self) {
+        let mut levels = SCOPE_LEVELS.lock();
+        if let Some(idx) = levels.active.iter().position(|l| *l == self.0) {
+            levels.active.swap_remove(idx);
+        }
+        levels.apply();
+    }
+}
+
+impl ScopeLevels {
+    fn apply(&self) {
+        let level = self.active.iter().copied().fold(self.base, Ord::max);
+        log::set_max_level(level);
+    }
+}
+
+/// Run `f` with the logging of `scope`, tasks spawned by `f` log as usual
+pub async fn with_log_scope<F: Future>(scope: Option<Arc<LogScope>>, f: F) -> F::Output {
+    match scope {
+        Some(scope) => {
+            let _level = scope.level.map(ScopeLevel::enter);
+            LOG_SCOPE.scope(scope, f).await
+        }
+        None => f.await,
+    }
+}
+
+/// `env_logger` as configured by `RUST_LOG`, except inside `with_log_scope`
+struct RoutingLogger {
+    inner: env_logger::Logger,
+}
+
+fn format_record(record: &Record) -> StackString {
+    let now = OffsetDateTime::now_utc()
+        .format(format_description!(
+            "[year]-[month]-[day]T[hour]:[minute]:[second]Z"
+        ))
+        .unwrap_or_default();
+    format_sstr!(
+        "[{now} {:<5} {}] {}",
+        record.level(),
+        record.target(),
+        record.args()
+    )
+}
+
+impl Log for RoutingLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        let default = self.inner.enabled(metadata);
+        LOG_SCOPE
+            .try_with(|scope| scope.enabled(metadata, default))
+            .unwrap_or(default)
+    }
+
+    fn log(&self, record: &Record) {
+        let default = self.inner.matches(record);
+        let scoped = LOG_SCOPE.try_with(|scope| {
+            if !scope.enabled(record.metadata(), default) {
+                return false;
+            }
+            let line = format_record(record);
+            scope.write(&format_sstr!("{} {line}", scope.name));
+            if !default {
+                eprintln!("{line}");
+            }
+            true
+        });
+        if matches!(scoped, Ok(false)) {
+            return;
+        }
+        if default {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+        LOG_SCOPE
+            .try_with(|scope| {
+                if let Some(f) = scope.file.lock().as_mut() {
+                    f.flush().ok();
+                }
+            })
+            .ok();
+    }
+}
+
+/// Install the logger, in place of `env_logger::init`
+/// # Errors
+/// Return error if a logger is already installed
+pub fn init_logger() -> Result<(), SetLoggerError> {
+    let inner = env_logger::Builder::from_default_env().build();
+    let max_level = inner.filter();
+    log::set_boxed_logger(Box::new(RoutingLogger { inner }))?;
+    log::set_max_level(max_level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use log::{Level, LevelFilter, Metadata};
+    use parking_lot::{const_mutex, Mutex};
+    use std::{path::Path, sync::Arc};
+    use time::macros::datetime;
+
+    use crate::log_routing::{
+        is_under, log_file_path, scope_filter, with_log_scope, LogRoutes, LogScope,
+    };
+
+    #[test]
+    fn test_log_file_path() {
+        let run = datetime!(2024-03-05 06:07:08 +00:00);
+        assert_eq!(
+            log_file_path("/var/log/sync/{name}-{run}.log", "photos/raw", run),
+            Path::new("/var/log/sync/photos_raw-20240305T060708.log")
+        );
+    }
+
+    #[test]
+    fn test_log_scope() {
+        let scope = Arc::new(LogScope {
+            name: "photos".into(),
+            level: Some(LevelFilter::Debug),
+            filter: Some(scope_filter(Some("info,hyper=warn"), LevelFilter::Debug)),
+            path: None,
+            file: Mutex::new(None),
+        });
+        let debug = Metadata::builder()
+            .level(Level::Debug)
+            .target("sync_app_lib::file_sync")
+            .build();
+        let trace = Metadata::builder()
+            .level(Level::Trace)
+            .target("sync_app_lib::file_sync")
+            .build();
+        let hyper = Metadata::builder()
+            .level(Level::Info)
+            .target("hyper::client")
+            .build();
+        assert!(scope.enabled(&debug, false));
+        assert!(!scope.enabled(&trace, true));
+        assert!(!scope.enabled(&hyper, true));
+
+        let routes = LogRoutes {
+            routes: vec![(
+                "file:///data/photos".into(),
+                "s3://bucket/photos".into(),
+                scope,
+            )],
+        };
+        assert!(routes.scope_for("s3://bucket/photos/2024").is_some());
+        assert!(routes.scope_for("file:///data/photos/a.jpg").is_some());
+        assert!(routes.scope_for("s3://bucket/music").is_none());
+        assert!(routes.scope_for("s3://bucket/photos2/a.jpg").is_none());
+        assert!(is_under("s3://bucket/photos", "s3://bucket/photos/"));
+    }
+
+    #[tokio::test]
+    async fn test_with_log_scope_restores_max_level() {
+        let scope = Arc::new(LogScope {
+            name: "photos".into(),
+            level: Some(LevelFilter::Trace),
+            filter: None,
+            path: None,
+            file: Mutex::new(None),
+        });
+        log::set_max_level(LevelFilter::Warn);
+        with_log_scope(Some(scope), async {
+            assert_eq!(log::max_level(), LevelFilter::Trace);
+        })
+        .await;
+        assert_eq!(log::max_level(), LevelFilter::Warn);
+    }
+}
@@ -0,0 +1,752 @@
+use anyhow::{format_err, Error};
+use futures::{stream, StreamExt};
+use log::{debug, warn};
+use md5::{Digest, Md5};
+use serde_json::{Map, Value};
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashSet,
+    convert::TryInto,
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+use time::{macros::format_description, OffsetDateTime, PrimitiveDateTime};
+use tokio::{process::Command, task::spawn_blocking};
+
+use crate::{
+    config::Config,
+    file_list::FileListTrait,
+    file_service::FileService,
+    models::{FileInfoCache, FileMetadata},
+    pgpool::PgPool,
+};
+
+/// Bytes read from the start of each file, enough for the magic numbers and
+/// the EXIF block of a jpeg (each of its segments is at most 64KiB)
+const HEADER_SIZE: usize = 256 * 1024;
+
+/// Largest `moov` box read looking for the duration of a video
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Files looked up per query for extraction
+const EXTRACT_BATCH: usize = 1000;
+
+/// Seconds between the mp4 epoch (1904-01-01) and the unix epoch
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// What could be read from a file without decoding it
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MediaMetadata {
+    pub mime_type: Option<&'static str>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_secs: Option<f64>,
+    pub taken_at: Option<OffsetDateTime>,
+    pub camera: Option<StackString>,
+    /// EXIF tags by name
+    pub exif: Map<String, Value>,
+}
+
+/// Mime type from the magic number of `header`, or the extension of `path`
+/// for formats without one
+#[must_use]
+pub fn sniff_mime(header: &[u8], path: &Path) -> Option<&'static str> {
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+    let mime = if at(0, b"\xff\xd8\xff") {
+        "image/jpeg"
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        "image/png"
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        "image/gif"
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        "image/tiff"
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        "image/webp"
+    } else if at(0, b"RIFF") && at(8, b"WAVE") {
+        "audio/wav"
+    } else if at(4, b"ftypheic") || at(4, b"ftypheix") || at(4, b"ftypmif1") {
+        "image/heic"
+    } else if at(4, b"ftypqt") {
+        "video/quicktime"
+    } else if at(4, b"ftypM4A") {
+        "audio/mp4"
+    } else if at(4, b"ftyp") {
+        "video/mp4"
+    } else if at(0, b"\x1a\x45\xdf\xa3") {
+        "video/x-matroska"
+    } else if at(0, b"fLaC") {
+        "audio/flac"
+    } else if at(0, b"OggS") {
+        "audio/ogg"
+    } else if at(0, b"ID3") || at(0, b"\xff\xfb") || at(0, b"\xff\xf3") {
+        "audio/mpeg"
+    } else if at(0, b"%PDF") {
+        "application/pdf"
+    } else if at(0, b"PK\x03\x04") {
+        "application/zip"
+    } else if at(0, b"\x1f\x8b") {
+        "application/gzip"
+    } else {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "txt" | "log" => "text/plain",
+            "md" => "text/markdown",
+            "csv" => "text/csv",
+            "html" | "htm" => "text/html",
+            "json" => "application/json",
+            "xml" => "application/xml",
+            "svg" => "image/svg+xml",
+            _ => return None,
+        }
+    };
+    Some(mime)
+}
+
+/// Read the metadata of the file at `path`, blocking
+/// # Errors
+/// Return error if the file can't be read
+pub fn extract_file(path: &Path) -> Result<MediaMetadata, Error> {
+    let mut f = File::open(path)?;
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    (&mut f).take(HEADER_SIZE as u64).read_to_end(&mut header)?;
+    let mut meta = MediaMetadata {
+        mime_type: sniff_mime(&header, path),
+        ..MediaMetadata::default()
+    };
+    match meta.mime_type {
+        Some("image/jpeg") => jpeg_metadata(&header, &mut meta),
+        Some("image/tiff") => {
+            if let Some(tiff) = Tiff::new(&header) {
+                exif_metadata(&tiff, &mut meta);
+            }
+        }
+        Some("image/png") => {
+            meta.width = be_u32(&header, 16).map(|w| w as i32);
+            meta.height = be_u32(&header, 20).map(|h| h as i32);
+        }
+        Some("image/gif") => {
+            meta.width = le_u16(&header, 6).map(i32::from);
+            meta.height = le_u16(&header, 8).map(i32::from);
+        }
+        Some("video/mp4" | "video/quicktime" | "audio/mp4") => mp4_metadata(&mut f, &mut meta)?,
+        Some("audio/wav") => meta.duration_secs = wav_duration(&header),
+        Some("audio/flac") => meta.duration_secs = flac_duration(&header),
+        _ => {}
+    }
+    Ok(meta)
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn le_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn le_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Walk the segments of a jpeg up to the image data, the frame header gives
+/// the size and the APP1 segment the EXIF block
+fn jpeg_metadata(data: &[u8], meta: &mut MediaMetadata) {
+    let mut offset = 2;
+    while let (Some(0xff), Some(&marker)) = (data.get(offset), data.get(offset + 1)) {
+        let length = match be_u16(data, offset + 2) {
+            Some(length) => usize::from(length),
+            None => return,
+        };
+        let segment = offset + 4;
+        match marker {
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                meta.height = be_u16(data, segment + 1).map(i32::from);
+                meta.width = be_u16(data, segment + 3).map(i32::from);
+            }
+            0xe1 if data.get(segment..segment + 6) == Some(&b"Exif\0\0"[..]) => {
+                let end = (offset + 2 + length).min(data.len());
+                if let Some(tiff) = data.get(segment + 6..end).and_then(Tiff::new) {
+                    exif_metadata(&tiff, meta);
+                }
+            }
+            0xda => return,
+            _ => {}
+        }
+        offset += 2 + length;
+    }
+}
+
+/// A TIFF structure, as found in EXIF blocks and raw camera files
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+/// One IFD entry: tag, type, count and the offset of its value
+struct TiffEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    offset: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        if self.little_endian {
+            le_u16(self.data, offset)
+        } else {
+            be_u16(self.data, offset)
+        }
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        if self.little_endian {
+            le_u32(self.data, offset)
+        } else {
+            be_u32(self.data, offset)
+        }
+    }
+
+    fn entries(&self, ifd: usize) -> Vec<TiffEntry> {
+        let count = self.u16(ifd).map_or(0, usize::from);
+        (0..count)
+            .filter_map(|i| {
+                let entry = ifd + 2 + i * 12;
+                let tag = self.u16(entry)?;
+                let kind = self.u16(entry + 2)?;
+                let count = self.u32(entry + 4)?;
+                let size = match kind {
+                    3 => 2,
+                    4 | 9 => 4,
+                    5 | 10 => 8,
+                    _ => 1,
+                } * count as usize;
+                let offset = if size <= 4 {
+                    entry + 8
+                } else {
+                    self.u32(entry + 8)? as usize
+                };
+                Some(TiffEntry {
+                    tag,
+                    kind,
+                    count,
+                    offset,
+                })
+            })
+            .collect()
+    }
+
+    fn value(&self, entry: &TiffEntry) -> Option<Value> {
+        match entry.kind {
+            2 => {
+                let bytes = self
+                    .data
+                    .get(entry.offset..entry.offset + entry.count as usize)?;
+                let text = String::from_utf8_lossy(bytes);
+                Some(text.trim_end_matches('\0').trim().into())
+            }
+            3 => self.u16(entry.offset).map(Into::into),
+            4 => self.u32(entry.offset).map(Into::into),
+            5 => {
+                let numerator = self.u32(entry.offset)?;
+                let denominator = self.u32(entry.offset + 4)?;
+                if denominator == 0 {
+                    return None;
+                }
+                Value::from(f64::from(numerator) / f64::from(denominator)).into()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Name of the EXIF tags kept
+fn exif_tag_name(tag: u16) -> Option<&'static str> {
+    let name = match tag {
+        0x010f => "Make",
+        0x0110 => "Model",
+        0x0112 => "Orientation",
+        0x0132 => "DateTime",
+        0x829a => "ExposureTime",
+        0x829d => "FNumber",
+        0x8827 => "ISOSpeedRatings",
+        0x9003 => "DateTimeOriginal",
+        0x920a => "FocalLength",
+        0xa002 => "PixelXDimension",
+        0xa003 => "PixelYDimension",
+        0xa434 => "LensModel",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn exif_metadata(tiff: &Tiff, meta: &mut MediaMetadata) {
+    let ifd0 = match tiff.u32(4) {
+        Some(ifd0) => ifd0 as usize,
+        None => return,
+    };
+    // a corrupt file can point ifds at each other, each is read once
+    let mut visited = HashSet::new();
+    let mut ifds = vec![ifd0];
+    while let Some(ifd) = ifds.pop() {
+        if !visited.insert(ifd) {
+            continue;
+        }
+        for entry in tiff.entries(ifd) {
+            if entry.tag == 0x8769 {
+                // pointer to the exif sub ifd
+                if let Some(sub_ifd) = tiff.u32(entry.offset) {
+                    if !visited.contains(&(sub_ifd as usize)) {
+                        ifds.push(sub_ifd as usize);
+                    }
+                }
+                continue;
+            }
+            if let (Some(name), Some(value)) = (exif_tag_name(entry.tag), tiff.value(&entry)) {
+                meta.exif.insert(name.into(), value);
+            }
+        }
+    }
+    let exif = &meta.exif;
+    let text = |name: &str| exif.get(name).and_then(Value::as_str);
+    let number = |name: &str| exif.get(name).and_then(Value::as_u64);
+    meta.taken_at = text("DateTimeOriginal")
+        .or_else(|| text("DateTime"))
+        .and_then(parse_exif_datetime);
+    let camera = match (text("Make"), text("Model")) {
+        (Some(make), Some(model)) if model.starts_with(make) => Some(model.into()),
+        (Some(make), Some(model)) => Some(format_sstr!("{make} {model}")),
+        (Some(camera), None) | (None, Some(camera)) => Some(camera.into()),
+        (None, None) => None,
+    };
+    meta.camera = camera.filter(|c: &StackString| !c.is_empty());
+    if meta.width.is_none() {
+        meta.width = number("PixelXDimension").map(|w| w as i32);
+        meta.height = number("PixelYDimension").map(|h| h as i32);
+    }
+}
+
+/// EXIF dates (`2024:03:05 06:07:08`) carry no timezone, they're taken as utc
+fn parse_exif_datetime(s: &str) -> Option<OffsetDateTime> {
+    PrimitiveDateTime::parse(
+        s,
+        format_description!("[year]:[month]:[day] [hour]:[minute]:[second]"),
+    )
+    .ok()
+    .map(PrimitiveDateTime::assume_utc)
+}
+
+/// Duration (and creation time, size of the first video track) from the
+/// `moov` box, which may be at either end of the file
+fn mp4_metadata(f: &mut File, meta: &mut MediaMetadata) -> Result<(), Error> {
+    let file_size = f.metadata()?.len();
+    let mut offset = 0;
+    while offset + 8 <= file_size {
+        let mut header = [0u8; 16];
+        f.seek(SeekFrom::Start(offset))?;
+        f.read_exact(&mut header[..8])?;
+        let mut header_size = 8;
+        let mut size = u64::from(be_u32(&header, 0).unwrap_or(0));
+        if size == 1 {
+            f.read_exact(&mut header[8..])?;
+            size = be_u64(&header, 8).unwrap_or(0);
+            header_size = 16;
+        } else if size == 0 {
+            size = file_size - offset;
+        }
+        if size < header_size {
+            return Err(format_err!("Invalid box at {offset}"));
+        }
+        if &header[4..8] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                return Ok(());
+            }
+            let mut moov = Vec::with_capacity(size as usize);
+            f.by_ref().take(size - header_size).read_to_end(&mut moov)?;
+            moov_metadata(&moov, meta);
+            return Ok(());
+        }
+        offset += size;
+    }
+    Ok(())
+}
+
+/// Children of a box as (type, body)
+fn mp4_boxes(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut offset = 0;
+    while let Some(size) = be_u32(data, offset) {
+        let size = size as usize;
+        let (kind, body) = match (
+            data.get(offset + 4..offset + 8),
+            data.get(offset + 8..offset + size),
+        ) {
+            (Some(kind), Some(body)) if size >= 8 => (kind, body),
+            _ => break,
+        };
+        boxes.push((kind, body));
+        offset += size;
+    }
+    boxes
+}
+
+fn moov_metadata(moov: &[u8], meta: &mut MediaMetadata) {
+    for (kind, body) in mp4_boxes(moov) {
+        match kind {
+            b"mvhd" => {
+                let (created, timescale, duration) = if body.first() == Some(&1) {
+                    (be_u64(body, 4), be_u32(body, 20), be_u64(body, 24))
+                } else {
+                    (
+                        be_u32(body, 4).map(u64::from),
+                        be_u32(body, 12),
+                        be_u32(body, 16).map(u64::from),
+                    )
+                };
+                if let (Some(timescale), Some(duration)) = (timescale, duration) {
+                    if timescale > 0 {
+                        meta.duration_secs = Some(duration as f64 / f64::from(timescale));
+                    }
+                }
+                meta.taken_at = created
+                    .filter(|c| *c as i64 > MP4_EPOCH_OFFSET)
+                    .and_then(|c| {
+                        OffsetDateTime::from_unix_timestamp(c as i64 - MP4_EPOCH_OFFSET).ok()
+                    });
+            }
+            b"trak" if meta.width.is_none() => {
+                for (kind, tkhd) in mp4_boxes(body) {
+                    if kind != b"tkhd" {
+                        continue;
+                    }
+                    let base = if tkhd.first() == Some(&1) { 36 } else { 24 };
+                    // 16.16 fixed point, audio tracks are 0 x 0
+                    let width = be_u32(tkhd, base + 52).map(|w| (w >> 16) as i32);
+                    let height = be_u32(tkhd, base + 56).map(|h| (h >> 16) as i32);
+                    if width.unwrap_or(0) > 0 && height.unwrap_or(0) > 0 {
+                        meta.width = width;
+                        meta.height = height;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn wav_duration(data: &[u8]) -> Option<f64> {
+    let mut offset = 12;
+    let mut byte_rate = None;
+    while let Some(size) = le_u32(data, offset + 4) {
+        match data.get(offset..offset + 4)? {
+            b"fmt " => byte_rate = le_u32(data, offset + 16),
+            b"data" => {
+                let byte_rate = byte_rate.filter(|r| *r > 0)?;
+                return Some(f64::from(size) / f64::from(byte_rate));
+            }
+            _ => {}
+        }
+        offset += 8 + size as usize + (size as usize & 1);
+    }
+    None
+}
+
+/// From the STREAMINFO block that has to follow the `fLaC` marker
+fn flac_duration(data: &[u8]) -> Option<f64> {
+    if data.get(4).map(|b| b & 0x7f) != Some(0) {
+        return None;
+    }
+    let info = be_u64(data, 18)?;
+    let sample_rate = info >> 44;
+    let total_samples = info & 0xf_ffff_ffff;
+    if sample_rate == 0 || total_samples == 0 {
+        return None;
+    }
+    Some(total_samples as f64 / sample_rate as f64)
+}
+
+/// Run `thumbnail_command` (arguments split on whitespace, `{input}` and
+/// `{output}` substituted) for an image or video, the thumbnail is named
+/// after the file's md5 so identical files share one (after its url, size
+/// and mtime without an md5)
+async fn make_thumbnail(
+    config: &Config,
+    entry: &FileInfoCache,
+    mime_type: Option<&str>,
+) -> Result<Option<PathBuf>, Error> {
+    let (command, thumbnail_dir) = match (&config.thumbnail_command, &config.thumbnail_dir) {
+        (Some(command), Some(thumbnail_dir)) => (command, thumbnail_dir),
+        _ => return Ok(None),
+    };
+    if !mime_type.map_or(false, |m| {
+        m.starts_with("image/") || m.starts_with("video/")
+    }) {
+        return Ok(None);
+    }
+    // without an md5 the key changes with the file's size or mtime, so a
+    // modified file doesn't keep its old thumbnail
+    let key = match &entry.md5sum {
+        Some(md5sum) => md5sum.clone(),
+        None => format_sstr!(
+            "{:x}",
+            Md5::digest(
+                format_sstr!(
+                    "{} {} {}",
+                    entry.urlname,
+                    entry.filestat_st_size,
+                    entry.filestat_st_mtime.unix_timestamp()
+                )
+                .as_bytes()
+            )
+        ),
+    };
+    let output = thumbnail_dir.join(format_sstr!("{key}.jpg").as_str());
+    if output.exists() {
+        return Ok(Some(output));
+    }
+    tokio::fs::create_dir_all(thumbnail_dir).await?;
+    let output_str = output.to_string_lossy();
+    let mut args = command.split_whitespace().map(|arg| {
+        arg.replace("{input}", &entry.filepath)
+            .replace("{output}", &output_str)
+    });
+    let program = args
+        .next()
+        .ok_or_else(|| format_err!("Empty thumbnail command"))?;
+    let status = Command::new(program).args(args).status().await?;
+    if !status.success() {
+        return Err(format_err!("thumbnail command failed with {status}"));
+    }
+    Ok(Some(output))
+}
+
+async fn extract_entry(config: &Config, entry: FileInfoCache, pool: &PgPool) -> Result<(), Error> {
+    let path = PathBuf::from(entry.filepath.as_str());
+    let extracted = spawn_blocking(move || extract_file(&path)).await?;
+    let mut metadata = FileMetadata {
+        servicesession: entry.servicesession.clone(),
+        urlname: entry.urlname.clone(),
+        filestat_st_size: entry.filestat_st_size,
+        filestat_st_mtime: entry.filestat_st_mtime,
+        mime_type: None,
+        width: None,
+        height: None,
+        duration_secs: None,
+        taken_at: None,
+        camera: None,
+        exif: None,
+        thumbnail_path: None,
+        error: None,
+        extracted_at: OffsetDateTime::now_utc().into(),
+    };
+    match extracted {
+        Ok(meta) => {
+            match make_thumbnail(config, &entry, meta.mime_type).await {
+                Ok(thumbnail) => {
+                    metadata.thumbnail_path =
+                        thumbnail.map(|p| p.to_string_lossy().as_ref().into());
+                }
+                Err(e) => warn!("no thumbnail for {} {e}", entry.urlname),
+            }
+            metadata.mime_type = meta.mime_type.map(Into::into);
+            metadata.width = meta.width;
+            metadata.height = meta.height;
+            metadata.duration_secs = meta.duration_secs;
+            metadata.taken_at = meta.taken_at.map(Into::into);
+            metadata.camera = meta.camera;
+            if !meta.exif.is_empty() {
+                metadata.exif = Some(meta.exif.into());
+            }
+        }
+        // recorded so the file isn't retried until it changes
+        Err(e) => metadata.error = Some(format_sstr!("{e}")),
+    }
+    metadata.upsert(pool).await
+}
+
+/// Extract the metadata of the files of a local `flist` added or changed
+/// since they were last extracted, `metadata_concurrency` at a time, run
+/// after each index when `metadata_extraction` is set
+/// # Errors
+/// Return error if db query fails
+pub async fn extract_metadata<T>(flist: &T, pool: &PgPool) -> Result<usize, Error>
+where
+    T: FileListTrait + ?Sized,
+{
+    let config = flist.get_config();
+    if !config.metadata_extraction || flist.get_servicetype() != FileService::Local {
+        return Ok(0);
+    }
+    let servicesession = flist.get_servicesession().as_str();
+    let baseurl = flist.get_baseurl().as_str();
+    let mut extracted = 0;
+    loop {
+        let entries =
+            FileMetadata::get_pending(servicesession, baseurl, EXTRACT_BATCH, pool).await?;
+        if entries.is_empty() {
+            break;
+        }
+        let number = entries.len();
+        let futures = entries
+            .into_iter()
+            .map(|entry| extract_entry(config, entry, pool));
+        let results: Vec<Result<(), Error>> = stream::iter(futures)
+            .buffer_unordered(config.metadata_concurrency.max(1))
+            .collect()
+            .await;
+        for result in results {
+            result?;
+        }
+        extracted += number;
+    }
+    debug!("extracted metadata of {extracted} files under {baseurl}");
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use time::macros::datetime;
+
+    use crate::metadata_extract::{
+        exif_metadata, flac_duration, jpeg_metadata, parse_exif_datetime, sniff_mime, wav_duration,
+        MediaMetadata, Tiff,
+    };
+
+    #[test]
+    fn test_sniff_mime() {
+        let path = Path::new("file.bin");
+        assert_eq!(sniff_mime(b"\xff\xd8\xff\xe1", path), Some("image/jpeg"));
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n", path), Some("image/png"));
+        assert_eq!(sniff_mime(b"\0\0\0\x18ftypmp42", path), Some("video/mp4"));
+        assert_eq!(
+            sniff_mime(b"\0\0\0\x14ftypqt  ", path),
+            Some("video/quicktime")
+        );
+        assert_eq!(
+            sniff_mime(b"hello", Path::new("notes.TXT")),
+            Some("text/plain")
+        );
+        assert_eq!(sniff_mime(b"hello", path), None);
+    }
+
+    #[test]
+    fn test_parse_exif_datetime() {
+        assert_eq!(
+            parse_exif_datetime("2024:03:05 06:07:08"),
+            Some(datetime!(2024-03-05 06:07:08 +00:00))
+        );
+        assert_eq!(parse_exif_datetime("0000:00:00 00:00:00"), None);
+    }
+
+    #[test]
+    fn test_jpeg_metadata() {
+        // big endian tiff with Make, Model and DateTimeOriginal in ifd0
+        let mut tiff: Vec<u8> = b"MM\0*\0\0\0\x08".to_vec();
+        let strings = [
+            (0x010f_u16, b"Canon\0".to_vec()),
+            (0x0110, b"Canon EOS R5\0".to_vec()),
+            (0x9003, b"2024:03:05 06:07:08\0".to_vec()),
+        ];
+        let mut data_offset = 8 + 2 + 12 * strings.len() + 4;
+        let mut data = Vec::new();
+        tiff.extend_from_slice(&(strings.len() as u16).to_be_bytes());
+        for (tag, value) in &strings {
+            tiff.extend_from_slice(&tag.to_be_bytes());
+            tiff.extend_from_slice(&2u16.to_be_bytes());
+            tiff.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            tiff.extend_from_slice(&(data_offset as u32).to_be_bytes());
+            data_offset += value.len();
+            data.extend_from_slice(value);
+        }
+        tiff.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(&data);
+
+        let mut jpeg = b"\xff\xd8".to_vec();
+        jpeg.extend_from_slice(b"\xff\xe1");
+        jpeg.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        // baseline frame header, 480 x 640
+        jpeg.extend_from_slice(b"\xff\xc0\0\x11\x08\x01\xe0\x02\x80");
+        jpeg.extend_from_slice(&[0; 12]);
+        jpeg.extend_from_slice(b"\xff\xda\0\x02");
+
+        let mut meta = MediaMetadata::default();
+        jpeg_metadata(&jpeg, &mut meta);
+        assert_eq!(meta.width, Some(640));
+        assert_eq!(meta.height, Some(480));
+        assert_eq!(meta.camera.as_deref(), Some("Canon EOS R5"));
+        assert_eq!(meta.taken_at, Some(datetime!(2024-03-05 06:07:08 +00:00)));
+        assert_eq!(meta.exif["Make"], "Canon");
+    }
+
+    #[test]
+    fn test_exif_ifd_loop() {
+        // ifd0 and the exif sub ifd point at each other
+        let mut tiff: Vec<u8> = b"MM\0*\0\0\0\x08".to_vec();
+        for (offset, target) in [(8_u32, 26_u32), (26, 8)] {
+            assert_eq!(tiff.len(), offset as usize);
+            tiff.extend_from_slice(&1u16.to_be_bytes());
+            tiff.extend_from_slice(&0x8769_u16.to_be_bytes());
+            tiff.extend_from_slice(&4u16.to_be_bytes());
+            tiff.extend_from_slice(&1u32.to_be_bytes());
+            tiff.extend_from_slice(&target.to_be_bytes());
+            tiff.extend_from_slice(&[0; 4]);
+        }
+        let tiff = Tiff::new(&tiff).unwrap();
+        let mut meta = MediaMetadata::default();
+        exif_metadata(&tiff, &mut meta);
+        assert!(meta.exif.is_empty());
+    }
+
+    #[test]
+    fn test_audio_duration() {
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        // pcm, 2 channels, 44100Hz, 176400 bytes/s
+        wav.extend_from_slice(&[1, 0, 2, 0]);
+        wav.extend_from_slice(&44100u32.to_le_bytes());
+        wav.extend_from_slice(&176_400u32.to_le_bytes());
+        wav.extend_from_slice(&[4, 0, 16, 0]);
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&352_800u32.to_le_bytes());
+        assert_eq!(wav_duration(&wav), Some(2.0));
+
+        let mut flac = b"fLaC\0\0\0\x22".to_vec();
+        flac.extend_from_slice(&[0; 10]);
+        let info: u64 = (44100 << 44) | (1 << 41) | (15 << 36) | 441_000;
+        flac.extend_from_slice(&info.to_be_bytes());
+        assert_eq!(flac_duration(&flac), Some(10.0));
+    }
+}
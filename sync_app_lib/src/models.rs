@@ -1,16 +1,39 @@
-use anyhow::Error;
-use futures::{Stream, TryStreamExt};
+use anyhow::{format_err, Error};
+use futures::{future, Stream, TryStreamExt};
 use log::info;
 use postgres_query::{query, Error as PqError, FromSqlRow};
-use smallvec::{smallvec, SmallVec};
-use stack_string::StackString;
-use time::OffsetDateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::Metadata,
+    path::PathBuf,
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+use time::{Duration, OffsetDateTime};
 use url::Url;
 use uuid::Uuid;
 
 use gdrive_lib::{date_time_wrapper::DateTimeWrapper, directory_info::DirectoryInfo};
 
-use crate::pgpool::PgPool;
+use crate::{
+    byte_accounting::ByteUsage,
+    cancellation::is_cancelled,
+    config_audit::{config_changes, ConfigChange, VersionConflict},
+    conflict::ConflictPolicy,
+    cron::CronSchedule,
+    file_info::ServiceSession,
+    file_service::FileService,
+    file_sync::FileSyncAction,
+    layout::DestinationLayout,
+    pgpool::PgPool,
+    query_stats::{timed, timed_one, timed_stream},
+    trash::DeletionPolicy,
+    virtual_root::config_pairs,
+};
 
 #[derive(FromSqlRow, Clone, Debug)]
 pub struct FileInfoCache {
@@ -20,14 +43,21 @@ pub struct FileInfoCache {
     pub urlname: StackString,
     pub md5sum: Option<StackString>,
     pub sha1sum: Option<StackString>,
-    pub filestat_st_mtime: i32,
-    pub filestat_st_size: i32,
+    pub filestat_st_mtime: DateTimeWrapper,
+    pub filestat_st_size: i64,
     pub serviceid: StackString,
     pub servicetype: StackString,
     pub servicesession: StackString,
     pub created_at: DateTimeWrapper,
     pub deleted_at: Option<DateTimeWrapper>,
     pub modified_at: DateTimeWrapper,
+    /// S3 storage class of the object (`STANDARD`, `GLACIER` ...), `None`
+    /// for other backends
+    pub storage_class: Option<StackString>,
+    /// Server side encryption of an S3 object (`AES256`, `aws:kms`, `none`
+    /// when unencrypted), only known for objects that were looked up with a
+    /// HEAD
+    pub sse: Option<StackString>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -60,7 +90,7 @@ impl FileInfoKey {
             urlname = self.urlname,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("FileInfoKey::delete_cache_entry", query.execute(&conn)).await?;
         Ok(())
     }
 }
@@ -83,7 +113,30 @@ impl FileInfoCache {
             id = id,
         );
         let conn = pool.get().await?;
-        query.fetch_opt(&conn).await.map_err(Into::into)
+        timed("FileInfoCache::get_by_id", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Total size and file count of every session in the cache
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_session_sizes(pool: &PgPool) -> Result<Vec<SessionSize>, Error> {
+        let query = query!(
+            r#"
+                SELECT servicetype, servicesession,
+                       COALESCE(sum(filestat_st_size), 0)::bigint AS total_size,
+                       count(*) AS file_count
+                FROM file_info_cache
+                WHERE deleted_at IS NULL
+                GROUP BY servicetype, servicesession
+                ORDER BY servicetype, servicesession
+            "#
+        );
+        let conn = pool.get().await?;
+        timed("FileInfoCache::get_session_sizes", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     /// # Errors
@@ -106,7 +159,7 @@ impl FileInfoCache {
                 servicetype = servicetype,
             );
             let conn = pool.get().await?;
-            query.fetch_one(&conn).await?
+            timed_one("FileInfoCache::count_cached", query.fetch_one(&conn)).await?
         } else {
             let query = query!(
                 r#"
@@ -119,7 +172,7 @@ impl FileInfoCache {
                 servicetype = servicetype,
             );
             let conn = pool.get().await?;
-            query.fetch_one(&conn).await?
+            timed_one("FileInfoCache::count_cached", query.fetch_one(&conn)).await?
         };
         Ok(count)
     }
@@ -144,7 +197,12 @@ impl FileInfoCache {
                 servicetype = servicetype,
             );
             let conn = pool.get().await?;
-            query.fetch_streaming(&conn).await.map_err(Into::into)
+            timed_stream(
+                "FileInfoCache::get_all_cached",
+                query.fetch_streaming(&conn),
+            )
+            .await
+            .map_err(Into::into)
         } else {
             let query = query!(
                 r#"
@@ -157,10 +215,72 @@ impl FileInfoCache {
                 servicetype = servicetype,
             );
             let conn = pool.get().await?;
-            query.fetch_streaming(&conn).await.map_err(Into::into)
+            timed_stream(
+                "FileInfoCache::get_all_cached",
+                query.fetch_streaming(&conn),
+            )
+            .await
+            .map_err(Into::into)
         }
     }
 
+    /// Cached entries under `prefix` ordered byte-wise by urlname
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_prefix_sorted(
+        prefix: &str,
+        servicesession: &str,
+        servicetype: &str,
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM file_info_cache
+                WHERE servicesession=$servicesession
+                  AND servicetype=$servicetype
+                  AND starts_with(urlname, $prefix)
+                  AND deleted_at IS NULL
+                ORDER BY urlname COLLATE "C"
+            "#,
+            prefix = prefix,
+            servicesession = servicesession,
+            servicetype = servicetype,
+        );
+        let conn = pool.get().await?;
+        timed_stream(
+            "FileInfoCache::get_by_prefix_sorted",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_urlnames(
+        urlnames: &[StackString],
+        servicesession: &str,
+        servicetype: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM file_info_cache
+                WHERE servicesession=$servicesession
+                  AND servicetype=$servicetype
+                  AND urlname = ANY($urlnames::text[])
+                  AND deleted_at IS NULL
+            "#,
+            urlnames = urlnames,
+            servicesession = servicesession,
+            servicetype = servicetype,
+        );
+        let conn = pool.get().await?;
+        timed("FileInfoCache::get_by_urlnames", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn get_by_urlname(
@@ -182,7 +302,9 @@ impl FileInfoCache {
             servicesession = servicesession,
         );
         let conn = pool.get().await?;
-        query.fetch_opt(&conn).await.map_err(Into::into)
+        timed("FileInfoCache::get_by_urlname", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     #[must_use]
@@ -224,7 +346,9 @@ impl FileInfoCache {
             servicesession = self.servicesession,
         );
         let conn = pool.get().await?;
-        query.fetch_opt(&conn).await.map_err(Into::into)
+        timed("FileInfoCache::get_cache", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     /// # Errors
@@ -236,11 +360,11 @@ impl FileInfoCache {
                  INSERT INTO file_info_cache (
                      filename, filepath, urlname, md5sum, sha1sum, filestat_st_mtime,
                      filestat_st_size, serviceid, servicetype, servicesession, created_at,
-                     deleted_at, modified_at
+                     deleted_at, modified_at, storage_class, sse
                  ) VALUES (
                     $filename, $filepath, $urlname, $md5sum, $sha1sum, $filestat_st_mtime,
                     $filestat_st_size, $serviceid, $servicetype, $servicesession, now(),
-                    null, now()
+                    null, now(), $storage_class, $sse
                  ) ON CONFLICT (
                      filename,filepath,urlname,serviceid,servicetype,servicesession
                 ) DO UPDATE SET 
@@ -249,7 +373,9 @@ impl FileInfoCache {
                     filestat_st_mtime=EXCLUDED.filestat_st_mtime,
                     filestat_st_size=EXCLUDED.filestat_st_size,
                     deleted_at=null,
-                    modified_at=now()
+                    modified_at=now(),
+                    storage_class=COALESCE(EXCLUDED.storage_class, file_info_cache.storage_class),
+                    sse=COALESCE(EXCLUDED.sse, file_info_cache.sse)
             "#,
             filename = self.filename,
             filepath = self.filepath,
@@ -261,9 +387,11 @@ impl FileInfoCache {
             serviceid = self.serviceid,
             servicetype = self.servicetype,
             servicesession = self.servicesession,
+            storage_class = self.storage_class,
+            sse = self.sse,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("FileInfoCache::insert", query.execute(&conn)).await?;
         Ok(())
     }
 
@@ -275,6 +403,8 @@ impl FileInfoCache {
                 || existing.md5sum != self.md5sum
                 || existing.sha1sum != self.md5sum
                 || existing.filestat_st_size != self.filestat_st_size
+                || (self.storage_class.is_some() && existing.storage_class != self.storage_class)
+                || (self.sse.is_some() && existing.sse != self.sse)
             {
                 self.insert(pool).await?;
                 return Ok(1);
@@ -297,7 +427,7 @@ impl FileInfoCache {
             id = self.id,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("FileInfoCache::delete", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 
@@ -318,7 +448,7 @@ impl FileInfoCache {
             servicetype = servicetype,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("FileInfoCache::delete_all", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 
@@ -342,7 +472,102 @@ impl FileInfoCache {
             gdriveid = gdriveid,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("FileInfoCache::delete_by_id", query.execute(&conn)).await?;
+        Ok(n as usize)
+    }
+
+    /// Mark the entry of `serviceid` deleted without removing it, the
+    /// tombstone keeps the copy on the other side of a sync from being
+    /// copied back
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn tombstone_by_id(
+        serviceid: &str,
+        servicesession: &str,
+        servicetype: &str,
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        let query = query!(
+            r#"
+                UPDATE file_info_cache SET deleted_at=now(), modified_at=now()
+                WHERE servicesession=$servicesession
+                  AND servicetype=$servicetype
+                  AND serviceid=$serviceid
+                  AND deleted_at IS NULL
+            "#,
+            servicesession = servicesession,
+            servicetype = servicetype,
+            serviceid = serviceid,
+        );
+        let conn = pool.get().await?;
+        let n = timed("FileInfoCache::tombstone_by_id", query.execute(&conn)).await?;
+        Ok(n as usize)
+    }
+
+    /// Number of live entries of `serviceid` other than the one at `urlname`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn count_by_id(
+        serviceid: &str,
+        servicesession: &str,
+        servicetype: &str,
+        urlname: &str,
+        pool: &PgPool,
+    ) -> Result<i64, Error> {
+        let query = query!(
+            r#"
+                SELECT count(*) FROM file_info_cache
+                WHERE servicesession=$servicesession
+                  AND servicetype=$servicetype
+                  AND serviceid=$serviceid
+                  AND urlname != $urlname
+                  AND deleted_at IS NULL
+            "#,
+            servicesession = servicesession,
+            servicetype = servicetype,
+            serviceid = serviceid,
+            urlname = urlname,
+        );
+        let conn = pool.get().await?;
+        let (count,) = timed_one("FileInfoCache::count_by_id", query.fetch_one(&conn)).await?;
+        Ok(count)
+    }
+
+    /// Move the live entry of `serviceid` at `urlname` to `new_urlname`, the
+    /// last component of `filepath` becomes `new_filename`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn rename_by_id(
+        serviceid: &str,
+        servicesession: &str,
+        servicetype: &str,
+        urlname: &str,
+        new_urlname: &str,
+        new_filename: &str,
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        let query = query!(
+            r#"
+                UPDATE file_info_cache
+                SET urlname=$new_urlname,
+                    filepath=left(filepath, length(filepath) - length(filename)) || $new_filename,
+                    filename=$new_filename,
+                    modified_at=now()
+                WHERE servicesession=$servicesession
+                  AND servicetype=$servicetype
+                  AND serviceid=$serviceid
+                  AND urlname=$urlname
+                  AND deleted_at IS NULL
+            "#,
+            servicesession = servicesession,
+            servicetype = servicetype,
+            serviceid = serviceid,
+            urlname = urlname,
+            new_urlname = new_urlname,
+            new_filename = new_filename,
+        );
+        let conn = pool.get().await?;
+        let n = timed("FileInfoCache::rename_by_id", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 
@@ -363,16 +588,19 @@ impl FileInfoCache {
             servicetype = servicetype,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("FileInfoCache::clear_all", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 
+    /// Live entries of `servicesession0` under `baseurl0` with no entry
+    /// (live or tombstoned) of `servicesession1` under `baseurl1`
     /// # Errors
     /// Return error if db query fails
     pub async fn get_new_entries(
         baseurl0: &str,
         baseurl1: &str,
         servicesession0: &str,
+        servicesession1: &str,
         pool: &PgPool,
     ) -> Result<Vec<Self>, Error> {
         let query = query!(
@@ -381,6 +609,7 @@ impl FileInfoCache {
                 FROM file_info_cache f0
                 LEFT JOIN file_info_cache f1
                 ON replace(f0.urlname, $baseurl0, '') = replace(f1.urlname, $baseurl1, '')
+                   AND f1.servicesession = $servicesession1
                 WHERE f1.id IS NULL
                   AND position($baseurl0 in f0.urlname) = 1
                   AND f0.deleted_at IS NULL
@@ -389,9 +618,90 @@ impl FileInfoCache {
             baseurl0 = baseurl0,
             baseurl1 = baseurl1,
             servicesession0 = servicesession0,
+            servicesession1 = servicesession1,
+        );
+        let conn = pool.get().await?;
+        timed("FileInfoCache::get_new_entries", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Live entries under `baseurl1` whose counterpart under `baseurl0` has
+    /// been tombstoned
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_deleted_counterparts(
+        baseurl0: &str,
+        baseurl1: &str,
+        servicesession0: &str,
+        servicesession1: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT f1.*
+                FROM file_info_cache f0
+                JOIN file_info_cache f1
+                ON replace(f0.urlname, $baseurl0, '') = replace(f1.urlname, $baseurl1, '')
+                WHERE position($baseurl0 in f0.urlname) = 1
+                  AND position($baseurl1 in f1.urlname) = 1
+                  AND f0.deleted_at IS NOT NULL
+                  AND f1.deleted_at IS NULL
+                  AND f0.servicesession = $servicesession0
+                  AND f1.servicesession = $servicesession1
+            "#,
+            baseurl0 = baseurl0,
+            baseurl1 = baseurl1,
+            servicesession0 = servicesession0,
+            servicesession1 = servicesession1,
+        );
+        let conn = pool.get().await?;
+        timed(
+            "FileInfoCache::get_deleted_counterparts",
+            query.fetch(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Tombstoned entries under `baseurl0` whose counterpart under
+    /// `baseurl1` is still live (the other side of
+    /// `get_deleted_counterparts`), the latest tombstone of each url
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_deleted_with_counterparts(
+        baseurl0: &str,
+        baseurl1: &str,
+        servicesession0: &str,
+        servicesession1: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT DISTINCT ON (f0.urlname) f0.*
+                FROM file_info_cache f0
+                JOIN file_info_cache f1
+                ON replace(f0.urlname, $baseurl0, '') = replace(f1.urlname, $baseurl1, '')
+                WHERE position($baseurl0 in f0.urlname) = 1
+                  AND position($baseurl1 in f1.urlname) = 1
+                  AND f0.deleted_at IS NOT NULL
+                  AND f1.deleted_at IS NULL
+                  AND f0.servicesession = $servicesession0
+                  AND f1.servicesession = $servicesession1
+                ORDER BY f0.urlname, f0.deleted_at DESC
+            "#,
+            baseurl0 = baseurl0,
+            baseurl1 = baseurl1,
+            servicesession0 = servicesession0,
+            servicesession1 = servicesession1,
         );
         let conn = pool.get().await?;
-        query.fetch(&conn).await.map_err(Into::into)
+        timed(
+            "FileInfoCache::get_deleted_with_counterparts",
+            query.fetch(&conn),
+        )
+        .await
+        .map_err(Into::into)
     }
 
     /// # Errors
@@ -427,8 +737,121 @@ impl FileInfoCache {
             servicesession1 = servicesession1,
         );
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        timed_stream(
+            "FileInfoCache::get_copy_candidates",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_sessions(servicetype: &str, pool: &PgPool) -> Result<Vec<StackString>, Error> {
+        #[derive(FromSqlRow)]
+        struct Session {
+            servicesession: StackString,
+        }
+
+        let query = query!(
+            r#"
+                SELECT DISTINCT servicesession FROM file_info_cache
+                WHERE servicetype=$servicetype
+            "#,
+            servicetype = servicetype,
+        );
+        let conn = pool.get().await?;
+        let sessions: Vec<Session> =
+            timed("FileInfoCache::get_sessions", query.fetch(&conn)).await?;
+        Ok(sessions.into_iter().map(|s| s.servicesession).collect())
+    }
+
+    /// Fold the entries of `old_session` into `new_session` in one
+    /// transaction, dropping any entry whose urlname already exists in
+    /// `new_session`.  Local entries are moved from under the path of the
+    /// old session to under the path of the new one.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn merge_sessions(
+        old_session: &str,
+        new_session: &str,
+        servicetype: &str,
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        let (old_url, old_path) = session_prefixes(servicetype, old_session)?;
+        let (new_url, new_path) = session_prefixes(servicetype, new_session)?;
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let query = query!(
+            r#"
+                DELETE FROM file_info_cache f0
+                WHERE f0.servicesession=$old_session
+                  AND f0.servicetype=$servicetype
+                  AND EXISTS (
+                    SELECT 1 FROM file_info_cache f1
+                    WHERE f1.servicesession=$new_session
+                      AND f1.servicetype=$servicetype
+                      AND f1.urlname=CASE
+                        WHEN starts_with(f0.urlname, $old_url)
+                        THEN $new_url || substr(f0.urlname, char_length($old_url) + 1)
+                        ELSE f0.urlname
+                      END
+                  )
+            "#,
+            old_session = old_session,
+            new_session = new_session,
+            servicetype = servicetype,
+            old_url = old_url,
+            new_url = new_url,
+        );
+        let deleted = timed("FileInfoCache::merge_sessions", query.execute(&tran)).await?;
+        let query = query!(
+            r#"
+                UPDATE file_info_cache
+                SET servicesession=$new_session,
+                    serviceid=CASE WHEN serviceid=$old_session THEN $new_session ELSE serviceid END,
+                    urlname=CASE
+                        WHEN starts_with(urlname, $old_url)
+                        THEN $new_url || substr(urlname, char_length($old_url) + 1)
+                        ELSE urlname
+                    END,
+                    filepath=CASE
+                        WHEN starts_with(filepath, $old_path)
+                        THEN $new_path || substr(filepath, char_length($old_path) + 1)
+                        ELSE filepath
+                    END,
+                    modified_at=now()
+                WHERE servicesession=$old_session
+                  AND servicetype=$servicetype
+            "#,
+            old_session = old_session,
+            new_session = new_session,
+            servicetype = servicetype,
+            old_url = old_url,
+            new_url = new_url,
+            old_path = old_path,
+            new_path = new_path,
+        );
+        let updated = timed("FileInfoCache::merge_sessions", query.execute(&tran)).await?;
+        tran.commit().await?;
+        Ok((deleted + updated) as usize)
+    }
+}
+
+/// Url and path prefixes of the entries of a local session, e.g.
+/// `file:///home/me/` and `/home/me/` for `node:/home/me`, empty for other
+/// services so that rewriting them leaves the entries as they are
+fn session_prefixes(servicetype: &str, session: &str) -> Result<(StackString, StackString), Error> {
+    if servicetype != FileService::Local.to_str() {
+        return Ok((StackString::new(), StackString::new()));
     }
+    let session: ServiceSession = session.parse()?;
+    let path = session.local_parts().1.trim_end_matches('/');
+    let url = Url::from_file_path(path).map_err(|()| format_err!("Invalid path {path}"))?;
+    Ok((
+        format_sstr!("{}/", url.as_str().trim_end_matches('/')),
+        format_sstr!("{path}/"),
+    ))
 }
 
 #[derive(FromSqlRow, Clone)]
@@ -469,7 +892,9 @@ impl DirectoryInfoCache {
             servicetype = servicetype,
         );
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        timed_stream("DirectoryInfoCache::get_all", query.fetch_streaming(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     /// # Errors
@@ -491,7 +916,7 @@ impl DirectoryInfoCache {
             servicesession = self.servicesession,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("DirectoryInfoCache::insert", query.execute(&conn)).await?;
         Ok(())
     }
 
@@ -512,7 +937,7 @@ impl DirectoryInfoCache {
             servicetype = servicetype,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("DirectoryInfoCache::delete_all", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 
@@ -536,7 +961,7 @@ impl DirectoryInfoCache {
             gdriveid = gdriveid,
         );
         let conn = pool.get().await?;
-        let n = query.execute(&conn).await?;
+        let n = timed("DirectoryInfoCache::delete_by_id", query.execute(&conn)).await?;
         Ok(n as usize)
     }
 }
@@ -547,17 +972,147 @@ pub struct FileSyncCache {
     pub src_url: StackString,
     pub dst_url: StackString,
     pub created_at: DateTimeWrapper,
+    /// Entries with a higher priority are transferred first
+    pub priority: i32,
+    /// Node of the local side of the copy, only its workers take the entry,
+    /// `None` for copies between remote backends
+    pub node_id: Option<StackString>,
+    /// Copy of the file on the destination side to move to `dst_url`
+    /// instead of copying `src_url`, see `FileSync::follow_moves`
+    pub move_from: Option<StackString>,
+}
+
+/// `node_id` if either url is a local file, the node the copy (or the
+/// history of a pair) belongs to
+fn local_node<'a>(url0: &str, url1: &str, node_id: &'a str) -> Option<&'a str> {
+    if url0.starts_with("file://") || url1.starts_with("file://") {
+        Some(node_id)
+    } else {
+        None
+    }
 }
 
 impl FileSyncCache {
+    /// Copies queued by `sync` and other bulk runs
+    pub const PRIORITY_BULK: i32 = 0;
+    /// Copies queued from the web UI or by a requeue, these jump ahead of
+    /// any bulk entry
+    pub const PRIORITY_INTERACTIVE: i32 = 10;
+
+    #[must_use]
+    pub fn is_interactive(&self) -> bool {
+        self.priority >= Self::PRIORITY_INTERACTIVE
+    }
+
+    /// Whether a worker on `node_id` can copy the entry
+    #[must_use]
+    pub fn runs_on(&self, node_id: &str) -> bool {
+        self.node_id.as_ref().map_or(true, |n| n == node_id)
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn get_cache_list(
         pool: &PgPool,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let query = query!("SELECT * FROM file_sync_cache ORDER BY src_url");
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_cache
+                ORDER BY priority DESC, src_url COLLATE "C", dst_url COLLATE "C"
+            "#
+        );
+        let conn = pool.get().await?;
+        timed_stream(
+            "FileSyncCache::get_cache_list",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// `get_cache_list` from `offset`, at most `limit` entries
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_cache_list_window(
+        pool: &PgPool,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_cache
+                ORDER BY priority DESC, src_url COLLATE "C", dst_url COLLATE "C"
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed_stream(
+            "FileSyncCache::get_cache_list_window",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Queued copies with the cached size of their source, 0 if the source
+    /// isn't cached
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_pending_sizes(pool: &PgPool) -> Result<Vec<PendingTransfer>, Error> {
+        let query = query!(
+            r#"
+                SELECT c.src_url, c.dst_url,
+                       COALESCE(
+                           (SELECT f.filestat_st_size FROM file_info_cache f
+                            WHERE f.urlname = c.src_url AND f.deleted_at IS NULL
+                            LIMIT 1),
+                           0
+                       ) AS size
+                FROM file_sync_cache c
+                ORDER BY c.src_url
+            "#
+        );
+        let conn = pool.get().await?;
+        timed("FileSyncCache::get_pending_sizes", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Remove and return up to `limit` of the oldest entries of the highest
+    /// priority that can run on `node_id`, rows locked by another worker are
+    /// skipped so that concurrent workers never pick up the same entry
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn take_batch(
+        pool: &PgPool,
+        limit: Option<usize>,
+        node_id: &str,
+    ) -> Result<Vec<Self>, Error> {
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                DELETE FROM file_sync_cache
+                WHERE id IN (
+                    SELECT id FROM file_sync_cache
+                    WHERE node_id IS NULL OR node_id = $node_id
+                    ORDER BY priority DESC, created_at
+                    LIMIT $limit
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *
+            "#,
+            limit = limit,
+            node_id = node_id,
+        );
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        timed("FileSyncCache::take_batch", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     /// # Errors
@@ -565,7 +1120,9 @@ impl FileSyncCache {
     pub async fn get_by_id(pool: &PgPool, id: Uuid) -> Result<Option<Self>, Error> {
         let query = query!("SELECT * FROM file_sync_cache WHERE id=$id", id = id);
         let conn = pool.get().await?;
-        query.fetch_opt(&conn).await.map_err(Into::into)
+        timed("FileSyncCache::get_by_id", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
     }
 
     /// # Errors
@@ -573,7 +1130,7 @@ impl FileSyncCache {
     pub async fn delete_by_id(pool: &PgPool, id: Uuid) -> Result<(), Error> {
         let query = query!("DELETE FROM file_sync_cache WHERE id=$id", id = id);
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("FileSyncCache::delete_by_id", query.execute(&conn)).await?;
         Ok(())
     }
 
@@ -588,20 +1145,34 @@ impl FileSyncCache {
     pub async fn cache_sync_sync(&self, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
             r#"
-                INSERT INTO file_sync_cache (src_url, dst_url, created_at)
-                VALUES ($src_url, $dst_url, now())
+                INSERT INTO file_sync_cache (
+                    src_url, dst_url, created_at, priority, node_id, move_from
+                )
+                VALUES ($src_url, $dst_url, now(), $priority, $node_id, $move_from)
             "#,
             src_url = self.src_url,
             dst_url = self.dst_url,
+            priority = self.priority,
+            node_id = self.node_id,
+            move_from = self.move_from,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("FileSyncCache::cache_sync_sync", query.execute(&conn)).await?;
         Ok(())
     }
 
+    /// Queue a copy of `src_url` to `dst_url` in the `priority` lane, see
+    /// `PRIORITY_BULK` and `PRIORITY_INTERACTIVE`.  A copy from or to a local
+    /// file is only taken by workers on `node_id`.
     /// # Errors
     /// Return error if db query fails
-    pub async fn cache_sync(pool: &PgPool, src_url: &str, dst_url: &str) -> Result<(), Error> {
+    pub async fn cache_sync(
+        pool: &PgPool,
+        src_url: &str,
+        dst_url: &str,
+        priority: i32,
+        node_id: &str,
+    ) -> Result<(), Error> {
         let src_url: Url = src_url.parse()?;
         let dst_url: Url = dst_url.parse()?;
         let value = Self {
@@ -609,19 +1180,168 @@ impl FileSyncCache {
             src_url: src_url.as_str().into(),
             dst_url: dst_url.as_str().into(),
             created_at: DateTimeWrapper::now(),
+            priority,
+            node_id: local_node(src_url.as_str(), dst_url.as_str(), node_id).map(Into::into),
+            move_from: None,
         };
         value.cache_sync_sync(pool).await?;
         Ok(())
     }
-}
 
-#[derive(FromSqlRow, Clone, PartialEq, Eq)]
-pub struct FileSyncConfig {
-    pub id: Uuid,
-    pub src_url: StackString,
+    /// Queue a move of `move_from` to `dst_url`, both on the destination
+    /// side, for `src_url` moved there on the source side.  The copy of
+    /// `src_url` is queued in its place if the move fails.
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn cache_move(
+        pool: &PgPool,
+        src_url: &str,
+        dst_url: &str,
+        move_from: &str,
+        priority: i32,
+        node_id: &str,
+    ) -> Result<(), Error> {
+        let value = Self {
+            id: Uuid::new_v4(),
+            src_url: src_url.into(),
+            dst_url: dst_url.into(),
+            created_at: DateTimeWrapper::now(),
+            priority,
+            node_id: local_node(src_url, dst_url, node_id).map(Into::into),
+            move_from: Some(move_from.into()),
+        };
+        value.cache_sync_sync(pool).await
+    }
+
+    /// Moves waiting in the queue
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_moves(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM file_sync_cache WHERE move_from IS NOT NULL");
+        let conn = pool.get().await?;
+        timed("FileSyncCache::get_moves", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_by_ids(ids: &[Uuid], pool: &PgPool) -> Result<usize, Error> {
+        let ids = ids.to_vec();
+        let query = query!(
+            "DELETE FROM file_sync_cache WHERE id = ANY($ids)",
+            ids = ids,
+        );
+        let conn = pool.get().await?;
+        let n = timed("FileSyncCache::delete_by_ids", query.execute(&conn)).await?;
+        Ok(n as usize)
+    }
+
+    /// Move entries to the back of the interactive lane, ahead of every bulk
+    /// entry
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn requeue_by_ids(ids: &[Uuid], pool: &PgPool) -> Result<usize, Error> {
+        let ids = ids.to_vec();
+        let query = query!(
+            r#"
+                UPDATE file_sync_cache SET created_at=now(), priority=$priority
+                WHERE id = ANY($ids)
+            "#,
+            ids = ids,
+            priority = Self::PRIORITY_INTERACTIVE,
+        );
+        let conn = pool.get().await?;
+        let n = timed("FileSyncCache::requeue_by_ids", query.execute(&conn)).await?;
+        Ok(n as usize)
+    }
+
+    /// Remove and return the entries in `ids`, skipping rows another worker
+    /// has already locked
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn take_by_ids(ids: &[Uuid], pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let ids = ids.to_vec();
+        let query = query!(
+            r#"
+                DELETE FROM file_sync_cache
+                WHERE id IN (
+                    SELECT id FROM file_sync_cache
+                    WHERE id = ANY($ids)
+                    FOR UPDATE SKIP LOCKED
+                )
+                RETURNING *
+            "#,
+            ids = ids,
+        );
+        let conn = pool.get().await?;
+        timed("FileSyncCache::take_by_ids", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SessionSize {
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub total_size: i64,
+    pub file_count: i64,
+}
+
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct PendingTransfer {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+    pub size: i64,
+}
+
+#[derive(FromSqlRow, Clone, PartialEq, Eq)]
+pub struct FileSyncConfig {
+    pub id: Uuid,
+    pub src_url: StackString,
     pub dst_url: StackString,
     pub last_run: DateTimeWrapper,
     pub name: Option<StackString>,
+    pub tags: Vec<StackString>,
+    pub enabled: bool,
+    pub ignore_errors: Vec<StackString>,
+    pub ownership_map: Vec<StackString>,
+    /// Read-only snapshot of a local `src_url` (e.g.
+    /// `/data/.zfs/snapshot/nightly/photos` for `file:///data/photos`), files
+    /// are indexed and read from here but recorded under `src_url`
+    pub snapshot_path_prefix: Option<StackString>,
+    /// How files changed on both sides since their last sync are handled,
+    /// see `ConflictPolicy`
+    pub conflict_policy: Option<StackString>,
+    /// Codec files uploaded to a cloud `dst_url` are compressed with, see
+    /// `Codec`
+    pub compression: Option<StackString>,
+    /// Only files at least this large are compressed
+    pub compression_min_size: Option<i64>,
+    /// `sync` only re-indexes a side whose last complete index is older than
+    /// this many seconds, every side is re-indexed when unset
+    pub max_index_age: Option<i64>,
+    /// What happens to the copy of a file deleted on the other side, see
+    /// `DeletionPolicy`
+    pub deletion_policy: Option<StackString>,
+    /// How files are laid out under `dst_url`, see `DestinationLayout`
+    pub dst_layout: Option<StackString>,
+    /// Log level used while this config is indexed and synced in place of
+    /// the `RUST_LOG` one, see `log_routing`
+    pub log_level: Option<StackString>,
+    /// S3 storage class files uploaded to an s3 `dst_url` are written in,
+    /// e.g. `STANDARD_IA`, the bucket default when unset
+    pub storage_class: Option<StackString>,
+    /// Server side encryption of files uploaded to an s3 `dst_url`,
+    /// `AES256` or `aws:kms`, overrides the `[s3.<bucket>]` setting
+    pub sse: Option<StackString>,
+    /// KMS key ARN used with `aws:kms`
+    pub sse_kms_key_id: Option<StackString>,
+    /// Incremented by every change of the settings, `update_settings` only
+    /// applies if it's still the version the config was read at
+    pub version: i32,
+    pub updated_at: DateTimeWrapper,
 }
 
 impl FileSyncConfig {
@@ -632,23 +1352,63 @@ impl FileSyncConfig {
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
         let query = query!("SELECT * FROM file_sync_config");
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        timed_stream(
+            "FileSyncConfig::get_config_list",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
     }
 
+    /// Configs having any of `tags`, or all configs if `tags` is empty
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_url_list(pool: &PgPool) -> Result<Vec<Url>, Error> {
-        let proc_list: Result<Vec<SmallVec<[_; 2]>>, Error> = Self::get_config_list(pool)
-            .await?
-            .map_err(Into::into)
-            .and_then(|v| async move {
-                let u0: Url = v.src_url.parse()?;
-                let u1: Url = v.dst_url.parse()?;
-                Ok(smallvec![u0, u1])
-            })
-            .try_collect()
-            .await;
-        Ok(proc_list?.into_iter().flatten().collect())
+    pub async fn get_config_list_by_tags(
+        pool: &PgPool,
+        tags: &[StackString],
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let tags = tags.to_vec();
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_config
+                WHERE cardinality($tags::text[]) = 0 OR tags && $tags::text[]
+                ORDER BY name COLLATE "C", src_url COLLATE "C"
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            tags = tags,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed_stream(
+            "FileSyncConfig::get_config_list_by_tags",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_url_list(pool: &PgPool, tags: &[StackString]) -> Result<Vec<Url>, Error> {
+        let proc_list: Result<Vec<Vec<(Url, Url)>>, Error> =
+            Self::get_config_list_by_tags(pool, tags, None, None)
+                .await?
+                .map_err(Into::into)
+                .try_filter(|v| future::ready(v.enabled))
+                .and_then(|v| async move { config_pairs(&v.src_url, &v.dst_url, pool).await })
+                .try_collect()
+                .await;
+        Ok(proc_list?
+            .into_iter()
+            .flatten()
+            .flat_map(|(u0, u1)| [u0, u1])
+            .collect())
     }
 
     /// # Errors
@@ -659,64 +1419,3427 @@ impl FileSyncConfig {
             name = name
         );
         let conn = pool.get().await?;
-        query.fetch_opt(&conn).await.map_err(Into::into)
+        timed("FileSyncConfig::get_by_name", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
     }
 
+    /// Insert the config, recording its settings in `file_sync_config_audit`
     /// # Errors
     /// Return error if db query fails
-    pub async fn insert_config(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn insert_config(&self, changed_by: &str, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
             r#"
-                INSERT INTO file_sync_config (src_url, dst_url, last_run, name)
-                VALUES ($src_url, $dst_url, now(), $name)
+                WITH inserted AS (
+                    INSERT INTO file_sync_config (
+                        src_url, dst_url, last_run, name, tags, enabled, ignore_errors,
+                        ownership_map, snapshot_path_prefix, conflict_policy, compression,
+                        compression_min_size, max_index_age, deletion_policy, dst_layout,
+                        log_level, storage_class, sse, sse_kms_key_id
+                    )
+                    VALUES (
+                        $src_url, $dst_url, now(), $name, $tags, $enabled, $ignore_errors,
+                        $ownership_map, $snapshot_path_prefix, $conflict_policy, $compression,
+                        $compression_min_size, $max_index_age, $deletion_policy, $dst_layout,
+                        $log_level, $storage_class, $sse, $sse_kms_key_id
+                    )
+                    RETURNING *
+                )
+                INSERT INTO file_sync_config_audit (
+                    config_id, name, version, changed_by, old_values, new_values
+                )
+                SELECT inserted.id, inserted.name, inserted.version, $changed_by, '{}'::jsonb,
+                       to_jsonb(inserted) - '{last_run,version,updated_at}'::text[]
+                FROM inserted
             "#,
             src_url = self.src_url,
             dst_url = self.dst_url,
             name = self.name,
+            tags = self.tags,
+            enabled = self.enabled,
+            ignore_errors = self.ignore_errors,
+            ownership_map = self.ownership_map,
+            snapshot_path_prefix = self.snapshot_path_prefix,
+            conflict_policy = self.conflict_policy,
+            compression = self.compression,
+            compression_min_size = self.compression_min_size,
+            max_index_age = self.max_index_age,
+            deletion_policy = self.deletion_policy,
+            dst_layout = self.dst_layout,
+            log_level = self.log_level,
+            storage_class = self.storage_class,
+            sse = self.sse,
+            sse_kms_key_id = self.sse_kms_key_id,
+            changed_by = changed_by,
+        );
+        let conn = pool.get().await?;
+        timed("FileSyncConfig::insert_config", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Snapshot paths of every config that has one, keyed on the config's
+    /// `src_url`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_snapshot_paths(pool: &PgPool) -> Result<HashMap<Url, PathBuf>, Error> {
+        let query = query!("SELECT * FROM file_sync_config WHERE snapshot_path_prefix IS NOT NULL");
+        let conn = pool.get().await?;
+        let configs: Vec<Self> =
+            timed("FileSyncConfig::get_snapshot_paths", query.fetch(&conn)).await?;
+        let mut snapshots = HashMap::new();
+        for conf in configs {
+            if let Some(snapshot) = &conf.snapshot_path_prefix {
+                snapshots.insert(conf.src_url.parse()?, snapshot.as_str().into());
+            }
+        }
+        Ok(snapshots)
+    }
+
+    /// Config with compression whose `dst_url` contains `url`, the most
+    /// specific one if there are several
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_compressed_dst(url: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_config
+                WHERE compression IS NOT NULL
+                  AND starts_with($url, rtrim(dst_url, '/'))
+                ORDER BY length(dst_url) DESC
+                LIMIT 1
+            "#,
+            url = url,
+        );
+        let conn = pool.get().await?;
+        timed("FileSyncConfig::get_compressed_dst", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Config with a storage class or server side encryption whose
+    /// `dst_url` contains `url`, the most specific one if there are several
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_upload_options_dst(url: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_config
+                WHERE (storage_class IS NOT NULL OR sse IS NOT NULL)
+                  AND (
+                    $url = rtrim(dst_url, '/')
+                    OR starts_with($url, rtrim(dst_url, '/') || '/')
+                  )
+                ORDER BY length(dst_url) DESC
+                LIMIT 1
+            "#,
+            url = url,
+        );
+        let conn = pool.get().await?;
+        timed(
+            "FileSyncConfig::get_upload_options_dst",
+            query.fetch_opt(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Whether any config uploads under `prefix` with SSE-KMS
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn has_kms_dst(prefix: &str, pool: &PgPool) -> Result<bool, Error> {
+        #[derive(FromSqlRow)]
+        struct Count {
+            count: i64,
+        }
+
+        let query = query!(
+            r#"
+                SELECT count(*) FROM file_sync_config
+                WHERE starts_with(sse, 'aws:kms')
+                  AND starts_with(dst_url, $prefix)
+            "#,
+            prefix = prefix,
+        );
+        let conn = pool.get().await?;
+        let result: Count = timed("FileSyncConfig::has_kms_dst", query.fetch_one(&conn)).await?;
+        Ok(result.count > 0)
+    }
+
+    /// Conflict policy of the config, the default if it doesn't set one
+    /// # Errors
+    /// Return error if the stored policy is invalid
+    pub fn get_conflict_policy(&self) -> Result<ConflictPolicy, Error> {
+        match &self.conflict_policy {
+            Some(policy) => policy.parse(),
+            None => Ok(ConflictPolicy::default()),
+        }
+    }
+
+    /// Deletion policy of the config, the default if it doesn't set one
+    /// # Errors
+    /// Return error if the stored policy is invalid
+    pub fn get_deletion_policy(&self) -> Result<DeletionPolicy, Error> {
+        match &self.deletion_policy {
+            Some(policy) => policy.parse(),
+            None => Ok(DeletionPolicy::default()),
+        }
+    }
+
+    /// Destination layout of the config, flat if it doesn't set one
+    /// # Errors
+    /// Return error if the stored layout is invalid
+    pub fn get_dst_layout(&self) -> Result<DestinationLayout, Error> {
+        match &self.dst_layout {
+            Some(layout) => layout.parse(),
+            None => Ok(DestinationLayout::default()),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update_last_run(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "UPDATE file_sync_config SET last_run = now() WHERE id = $id",
+            id = self.id,
         );
         let conn = pool.get().await?;
-        query.execute(&conn).await?;
+        timed("FileSyncConfig::update_last_run", query.execute(&conn)).await?;
         Ok(())
     }
+
+    /// Write every setting of `self` if the stored config still has
+    /// `self.version`, recording the settings before and after in
+    /// `file_sync_config_audit`.  Returns the updated config.
+    /// # Errors
+    /// Return `VersionConflict` if the config was changed or removed since it
+    /// was read, or error if db query fails
+    pub async fn update_settings(&self, changed_by: &str, pool: &PgPool) -> Result<Self, Error> {
+        let query = query!(
+            r#"
+                WITH old AS (
+                    SELECT * FROM file_sync_config WHERE id = $id AND version = $version
+                ), updated AS (
+                    UPDATE file_sync_config
+                    SET src_url = $src_url,
+                        dst_url = $dst_url,
+                        name = $name,
+                        tags = $tags,
+                        enabled = $enabled,
+                        ignore_errors = $ignore_errors,
+                        ownership_map = $ownership_map,
+                        snapshot_path_prefix = $snapshot_path_prefix,
+                        conflict_policy = $conflict_policy,
+                        compression = $compression,
+                        compression_min_size = $compression_min_size,
+                        max_index_age = $max_index_age,
+                        deletion_policy = $deletion_policy,
+                        dst_layout = $dst_layout,
+                        log_level = $log_level,
+                        storage_class = $storage_class,
+                        sse = $sse,
+                        sse_kms_key_id = $sse_kms_key_id,
+                        version = version + 1,
+                        updated_at = now()
+                    WHERE id = $id AND version = $version
+                    RETURNING *
+                ), audit AS (
+                    INSERT INTO file_sync_config_audit (
+                        config_id, name, version, changed_by, old_values, new_values
+                    )
+                    SELECT updated.id, updated.name, updated.version, $changed_by,
+                           to_jsonb(old) - '{last_run,version,updated_at}'::text[],
+                           to_jsonb(updated) - '{last_run,version,updated_at}'::text[]
+                    FROM old, updated
+                )
+                SELECT * FROM updated
+            "#,
+            id = self.id,
+            version = self.version,
+            src_url = self.src_url,
+            dst_url = self.dst_url,
+            name = self.name,
+            tags = self.tags,
+            enabled = self.enabled,
+            ignore_errors = self.ignore_errors,
+            ownership_map = self.ownership_map,
+            snapshot_path_prefix = self.snapshot_path_prefix,
+            conflict_policy = self.conflict_policy,
+            compression = self.compression,
+            compression_min_size = self.compression_min_size,
+            max_index_age = self.max_index_age,
+            deletion_policy = self.deletion_policy,
+            dst_layout = self.dst_layout,
+            log_level = self.log_level,
+            storage_class = self.storage_class,
+            sse = self.sse,
+            sse_kms_key_id = self.sse_kms_key_id,
+            changed_by = changed_by,
+        );
+        let conn = pool.get().await?;
+        let updated: Option<Self> =
+            timed("FileSyncConfig::update_settings", query.fetch_opt(&conn)).await?;
+        if let Some(updated) = updated {
+            return Ok(updated);
+        }
+        let query = query!(
+            "SELECT version FROM file_sync_config WHERE id = $id",
+            id = self.id
+        );
+        let current: Option<ConfigVersion> =
+            timed("FileSyncConfig::get_version", query.fetch_opt(&conn)).await?;
+        Err(VersionConflict {
+            name: self.name.clone().unwrap_or_else(|| self.src_url.clone()),
+            expected: self.version,
+            current: current.map(|c| c.version),
+        }
+        .into())
+    }
 }
 
-#[derive(FromSqlRow, Clone, Debug)]
-pub struct AuthorizedUsers {
-    pub email: StackString,
-    pub created_at: OffsetDateTime,
+#[derive(FromSqlRow, Clone, Copy, Debug)]
+struct ConfigVersion {
+    version: i32,
 }
 
-impl AuthorizedUsers {
+/// Settings of a config before and after an edit, `old_values` and
+/// `new_values` are the rows as json without `last_run`
+#[derive(FromSqlRow, Clone, Debug, PartialEq)]
+pub struct FileSyncConfigAudit {
+    pub id: Uuid,
+    pub config_id: Uuid,
+    pub name: Option<StackString>,
+    /// Version of the config after the edit
+    pub version: i32,
+    pub changed_by: StackString,
+    pub old_values: Value,
+    pub new_values: Value,
+    pub changed_at: DateTimeWrapper,
+}
+
+impl FileSyncConfigAudit {
+    /// Edits of the config `config_id`, or of all configs, newest first
     /// # Errors
     /// Return error if db query fails
-    pub async fn get_authorized_users(
+    pub async fn get_recent(
+        pool: &PgPool,
+        config_id: Option<Uuid>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<Vec<Self>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT * FROM file_sync_config_audit
+                WHERE $config_id::uuid IS NULL OR config_id = $config_id
+                ORDER BY changed_at DESC
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            config_id = config_id,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("FileSyncConfigAudit::get_recent", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// The settings that differ between `old_values` and `new_values`
+    #[must_use]
+    pub fn changes(&self) -> Vec<ConfigChange> {
+        config_changes(&self.old_values, &self.new_values)
+    }
+}
+
+/// Marker for a listing that is still running, `continuation_token` is the
+/// page token (gdrive) or marker (s3) of the last page that was flushed into
+/// `file_info_cache`.  The row is removed once the listing completes.
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct IndexProgress {
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub baseurl: StackString,
+    pub continuation_token: Option<StackString>,
+    pub change_token: Option<StackString>,
+    pub number_flushed: i64,
+    pub started_at: DateTimeWrapper,
+    pub updated_at: DateTimeWrapper,
+}
+
+impl IndexProgress {
+    #[must_use]
+    pub fn new(servicetype: &str, servicesession: &str, baseurl: &str) -> Self {
+        Self {
+            servicetype: servicetype.into(),
+            servicesession: servicesession.into(),
+            baseurl: baseurl.into(),
+            continuation_token: None,
+            change_token: None,
+            number_flushed: 0,
+            started_at: DateTimeWrapper::now(),
+            updated_at: DateTimeWrapper::now(),
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(
+        servicetype: &str,
+        servicesession: &str,
+        baseurl: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM index_progress
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND baseurl=$baseurl
+            "#,
+            servicetype = servicetype,
+            servicesession = servicesession,
+            baseurl = baseurl,
+        );
+        let conn = pool.get().await?;
+        timed("IndexProgress::get", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(
         pool: &PgPool,
     ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
-        let query = query!("SELECT * FROM authorized_users WHERE deleted_at IS NULL");
+        let query = query!("SELECT * FROM index_progress ORDER BY started_at");
         let conn = pool.get().await?;
-        query.fetch_streaming(&conn).await.map_err(Into::into)
+        timed_stream("IndexProgress::get_all", query.fetch_streaming(&conn))
+            .await
+            .map_err(Into::into)
     }
 
+    /// Record the position reached so far
     /// # Errors
-    /// Returns error if db query fails
-    pub async fn get_most_recent(
+    /// Return error if db query fails
+    pub async fn flush(
+        &mut self,
+        continuation_token: Option<&str>,
+        number_flushed: usize,
         pool: &PgPool,
-    ) -> Result<(Option<OffsetDateTime>, Option<OffsetDateTime>), Error> {
-        #[derive(FromSqlRow)]
-        struct CreatedDeleted {
-            created_at: Option<OffsetDateTime>,
-            deleted_at: Option<OffsetDateTime>,
-        }
+    ) -> Result<(), Error> {
+        self.continuation_token = continuation_token.map(Into::into);
+        self.number_flushed += number_flushed as i64;
+        self.updated_at = DateTimeWrapper::now();
+        let query = query!(
+            r#"
+                INSERT INTO index_progress (
+                    servicetype, servicesession, baseurl, continuation_token, change_token,
+                    number_flushed, started_at, updated_at
+                ) VALUES (
+                    $servicetype, $servicesession, $baseurl, $continuation_token, $change_token,
+                    $number_flushed, $started_at, $updated_at
+                ) ON CONFLICT (servicetype, servicesession, baseurl) DO UPDATE
+                SET continuation_token=EXCLUDED.continuation_token,
+                    change_token=EXCLUDED.change_token,
+                    number_flushed=EXCLUDED.number_flushed,
+                    updated_at=EXCLUDED.updated_at
+            "#,
+            servicetype = self.servicetype,
+            servicesession = self.servicesession,
+            baseurl = self.baseurl,
+            continuation_token = self.continuation_token,
+            change_token = self.change_token,
+            number_flushed = self.number_flushed,
+            started_at = self.started_at,
+            updated_at = self.updated_at,
+        );
+        let conn = pool.get().await?;
+        timed("IndexProgress::flush", query.execute(&conn)).await?;
+        Ok(())
+    }
 
+    /// Listing finished, remove the marker
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn finish(&self, pool: &PgPool) -> Result<(), Error> {
         let query = query!(
-            "SELECT max(created_at) as created_at, max(deleted_at) as deleted_at FROM \
-             authorized_users"
+            r#"
+                DELETE FROM index_progress
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND baseurl=$baseurl
+            "#,
+            servicetype = self.servicetype,
+            servicesession = self.servicesession,
+            baseurl = self.baseurl,
         );
         let conn = pool.get().await?;
-        let result: Option<CreatedDeleted> = query.fetch_opt(&conn).await?;
-        match result {
-            Some(result) => Ok((result.created_at, result.deleted_at)),
-            None => Ok((None, None)),
-        }
+        timed("IndexProgress::finish", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// One pass of `update_file_cache`, `complete` is only set once the listing
+/// finished without error
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct IndexRun {
+    pub id: Uuid,
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub baseurl: StackString,
+    pub started_at: DateTimeWrapper,
+    pub finished_at: Option<DateTimeWrapper>,
+    pub complete: bool,
+    pub number_updated: i64,
+    pub number_cached: i64,
+}
+
+impl IndexRun {
+    /// True if this run completed less than `max_age` seconds before `now`
+    #[must_use]
+    pub fn is_fresh(&self, max_age: i64, now: OffsetDateTime) -> bool {
+        self.complete && now - self.started_at.to_offsetdatetime() <= Duration::seconds(max_age)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn start(
+        servicetype: &str,
+        servicesession: &str,
+        baseurl: &str,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let run = Self {
+            id: Uuid::new_v4(),
+            servicetype: servicetype.into(),
+            servicesession: servicesession.into(),
+            baseurl: baseurl.into(),
+            started_at: DateTimeWrapper::now(),
+            finished_at: None,
+            complete: false,
+            number_updated: 0,
+            number_cached: 0,
+        };
+        let query = query!(
+            r#"
+                INSERT INTO index_run (id, servicetype, servicesession, baseurl, started_at)
+                VALUES ($id, $servicetype, $servicesession, $baseurl, $started_at)
+            "#,
+            id = run.id,
+            servicetype = run.servicetype,
+            servicesession = run.servicesession,
+            baseurl = run.baseurl,
+            started_at = run.started_at,
+        );
+        let conn = pool.get().await?;
+        timed("IndexRun::start", query.execute(&conn)).await?;
+        Ok(run)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn finish(
+        &mut self,
+        complete: bool,
+        number_updated: usize,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        self.number_cached =
+            FileInfoCache::count_cached(&self.servicesession, &self.servicetype, pool, false)
+                .await?;
+        self.finished_at = Some(DateTimeWrapper::now());
+        self.complete = complete;
+        self.number_updated = number_updated as i64;
+        let query = query!(
+            r#"
+                UPDATE index_run
+                SET finished_at=$finished_at,
+                    complete=$complete,
+                    number_updated=$number_updated,
+                    number_cached=$number_cached
+                WHERE id=$id
+            "#,
+            id = self.id,
+            finished_at = self.finished_at,
+            complete = self.complete,
+            number_updated = self.number_updated,
+            number_cached = self.number_cached,
+        );
+        let conn = pool.get().await?;
+        timed("IndexRun::finish", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Most recent run covering `baseurl`, i.e. one whose baseurl is a prefix
+    /// of `baseurl`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_last(
+        servicetype: &str,
+        servicesession: &str,
+        baseurl: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM index_run
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND starts_with($baseurl, baseurl)
+                ORDER BY started_at DESC
+                LIMIT 1
+            "#,
+            servicetype = servicetype,
+            servicesession = servicesession,
+            baseurl = baseurl,
+        );
+        let conn = pool.get().await?;
+        timed("IndexRun::get_last", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Identity behind a `servicesession` string, cache rows reference it through
+/// `service_session_id` so a session can be renamed without orphaning them
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct ServiceSessionEntry {
+    pub id: Uuid,
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub account: StackString,
+    pub endpoint: StackString,
+    pub created_at: DateTimeWrapper,
+}
+
+impl ServiceSessionEntry {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM service_sessions ORDER BY servicetype, servicesession");
+        let conn = pool.get().await?;
+        timed("ServiceSessionEntry::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_session(
+        servicetype: &str,
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM service_sessions
+                WHERE servicetype=$servicetype AND servicesession=$servicesession
+            "#,
+            servicetype = servicetype,
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        timed(
+            "ServiceSessionEntry::get_by_session",
+            query.fetch_opt(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Insert the session if it isn't known yet, refreshing account and
+    /// endpoint otherwise
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_or_create(
+        servicetype: &str,
+        servicesession: &str,
+        account: &str,
+        endpoint: &str,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO service_sessions (servicetype, servicesession, account, endpoint)
+                VALUES ($servicetype, $servicesession, $account, $endpoint)
+                ON CONFLICT (servicetype, servicesession) DO UPDATE
+                SET account=EXCLUDED.account,
+                    endpoint=EXCLUDED.endpoint
+                RETURNING *
+            "#,
+            servicetype = servicetype,
+            servicesession = servicesession,
+            account = account,
+            endpoint = endpoint,
+        );
+        let conn = pool.get().await?;
+        timed_one("ServiceSessionEntry::get_or_create", query.fetch_one(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// `(servicetype, servicesession)` pairs in the cache tables that don't
+    /// reference a `service_sessions` row yet
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_unlinked(pool: &PgPool) -> Result<Vec<(StackString, StackString)>, Error> {
+        #[derive(FromSqlRow)]
+        struct Session {
+            servicetype: StackString,
+            servicesession: StackString,
+        }
+
+        let query = query!(
+            r#"
+                SELECT DISTINCT servicetype, servicesession FROM file_info_cache
+                WHERE service_session_id IS NULL
+                UNION
+                SELECT DISTINCT servicetype, servicesession FROM directory_info_cache
+                WHERE service_session_id IS NULL
+            "#
+        );
+        let conn = pool.get().await?;
+        let sessions: Vec<Session> =
+            timed("ServiceSessionEntry::get_unlinked", query.fetch(&conn)).await?;
+        Ok(sessions
+            .into_iter()
+            .map(|s| (s.servicetype, s.servicesession))
+            .collect())
+    }
+
+    /// Point every cache row of this session at `self.id`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn link_cache(&self, pool: &PgPool) -> Result<usize, Error> {
+        let conn = pool.get().await?;
+        let query = query!(
+            r#"
+                UPDATE file_info_cache
+                SET service_session_id=$id
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND service_session_id IS DISTINCT FROM $id
+            "#,
+            id = self.id,
+            servicetype = self.servicetype,
+            servicesession = self.servicesession,
+        );
+        let mut linked = timed("ServiceSessionEntry::link_cache", query.execute(&conn)).await?;
+        let query = query!(
+            r#"
+                UPDATE directory_info_cache
+                SET service_session_id=$id
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND service_session_id IS DISTINCT FROM $id
+            "#,
+            id = self.id,
+            servicetype = self.servicetype,
+            servicesession = self.servicesession,
+        );
+        linked += timed("ServiceSessionEntry::link_cache", query.execute(&conn)).await?;
+        Ok(linked as usize)
+    }
+
+    /// Rename the session in one transaction, rewriting the cache and index
+    /// rows that reference it, local entries are moved under the path of the
+    /// new session. The new name must not already be in use, merge those
+    /// instead.
+    /// # Errors
+    /// Return error if `new_session` exists or db query fails
+    pub async fn rename(
+        &mut self,
+        new_session: &str,
+        account: &str,
+        endpoint: &str,
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        if Self::get_by_session(&self.servicetype, new_session, pool)
+            .await?
+            .is_some()
+        {
+            return Err(format_err!(
+                "session {new_session} already exists for {}",
+                self.servicetype
+            ));
+        }
+        let old_session = self.servicesession.clone();
+        let (old_url, old_path) = session_prefixes(&self.servicetype, &old_session)?;
+        let (new_url, new_path) = session_prefixes(&self.servicetype, new_session)?;
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let query = query!(
+            r#"
+                UPDATE service_sessions
+                SET servicesession=$new_session,
+                    account=$account,
+                    endpoint=$endpoint
+                WHERE id=$id
+            "#,
+            id = self.id,
+            new_session = new_session,
+            account = account,
+            endpoint = endpoint,
+        );
+        timed("ServiceSessionEntry::rename", query.execute(&tran)).await?;
+        let query = query!(
+            r#"
+                UPDATE file_info_cache
+                SET servicesession=$new_session,
+                    serviceid=CASE WHEN serviceid=$old_session THEN $new_session ELSE serviceid END,
+                    urlname=CASE
+                        WHEN starts_with(urlname, $old_url)
+                        THEN $new_url || substr(urlname, char_length($old_url) + 1)
+                        ELSE urlname
+                    END,
+                    filepath=CASE
+                        WHEN starts_with(filepath, $old_path)
+                        THEN $new_path || substr(filepath, char_length($old_path) + 1)
+                        ELSE filepath
+                    END,
+                    modified_at=now()
+                WHERE service_session_id=$id
+            "#,
+            id = self.id,
+            old_session = old_session,
+            new_session = new_session,
+            old_url = old_url,
+            new_url = new_url,
+            old_path = old_path,
+            new_path = new_path,
+        );
+        let mut updated = timed("ServiceSessionEntry::rename", query.execute(&tran)).await?;
+        let query = query!(
+            r#"
+                UPDATE directory_info_cache
+                SET servicesession=$new_session
+                WHERE service_session_id=$id
+            "#,
+            id = self.id,
+            new_session = new_session,
+        );
+        updated += timed("ServiceSessionEntry::rename", query.execute(&tran)).await?;
+        let query = query!(
+            r#"
+                UPDATE index_progress
+                SET servicesession=$new_session,
+                    baseurl=CASE
+                        WHEN baseurl = rtrim($old_url, '/') THEN rtrim($new_url, '/')
+                        WHEN starts_with(baseurl, $old_url)
+                        THEN $new_url || substr(baseurl, char_length($old_url) + 1)
+                        ELSE baseurl
+                    END
+                WHERE servicetype=$servicetype AND servicesession=$old_session
+            "#,
+            servicetype = self.servicetype,
+            old_session = old_session,
+            new_session = new_session,
+            old_url = old_url,
+            new_url = new_url,
+        );
+        timed("ServiceSessionEntry::rename", query.execute(&tran)).await?;
+        let query = query!(
+            r#"
+                UPDATE index_run
+                SET servicesession=$new_session,
+                    baseurl=CASE
+                        WHEN baseurl = rtrim($old_url, '/') THEN rtrim($new_url, '/')
+                        WHEN starts_with(baseurl, $old_url)
+                        THEN $new_url || substr(baseurl, char_length($old_url) + 1)
+                        ELSE baseurl
+                    END
+                WHERE servicetype=$servicetype AND servicesession=$old_session
+            "#,
+            servicetype = self.servicetype,
+            old_session = old_session,
+            new_session = new_session,
+            old_url = old_url,
+            new_url = new_url,
+        );
+        timed("ServiceSessionEntry::rename", query.execute(&tran)).await?;
+        tran.commit().await?;
+        self.servicesession = new_session.into();
+        self.account = account.into();
+        self.endpoint = endpoint.into();
+        Ok(updated as usize)
+    }
+}
+
+/// Drive ids that `files.get` reported as missing, persisted so that the
+/// next run doesn't spend a retry loop rediscovering them
+pub struct GDriveMissingMetadata;
+
+impl GDriveMissingMetadata {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_recent(
+        servicesession: &str,
+        max_age_secs: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<StackString>, Error> {
+        #[derive(FromSqlRow)]
+        struct Missing {
+            gdriveid: StackString,
+        }
+
+        let query = query!(
+            r#"
+                SELECT gdriveid FROM gdrive_missing_metadata
+                WHERE servicesession=$servicesession
+                  AND created_at > now() - make_interval(secs => $max_age_secs)
+            "#,
+            servicesession = servicesession,
+            max_age_secs = max_age_secs as f64,
+        );
+        let conn = pool.get().await?;
+        let missing: Vec<Missing> =
+            timed("GDriveMissingMetadata::get_recent", query.fetch(&conn)).await?;
+        Ok(missing.into_iter().map(|m| m.gdriveid).collect())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(servicesession: &str, gdriveid: &str, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO gdrive_missing_metadata (servicesession, gdriveid)
+                VALUES ($servicesession, $gdriveid)
+                ON CONFLICT (servicesession, gdriveid) DO NOTHING
+            "#,
+            servicesession = servicesession,
+            gdriveid = gdriveid,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveMissingMetadata::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// Why a gdrive file is kept out of the cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusionReason {
+    /// google-native file (form, map) without any export format
+    Unexportable,
+    /// excluded by hand, e.g. imported from another host
+    Manual,
+}
+
+impl ExclusionReason {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Unexportable => "unexportable",
+            Self::Manual => "manual",
+        }
+    }
+}
+
+impl fmt::Display for ExclusionReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for ExclusionReason {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unexportable" => Ok(Self::Unexportable),
+            "manual" => Ok(Self::Manual),
+            _ => Err(format_err!("Invalid exclusion reason {s}")),
+        }
+    }
+}
+
+/// A gdrive file that indexing and copies skip, recorded once so that it
+/// isn't rediscovered and re-examined on every run
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GDriveExclusion {
+    pub servicesession: StackString,
+    pub gdriveid: StackString,
+    pub filename: StackString,
+    pub mime_type: Option<StackString>,
+    pub reason: StackString,
+    pub created_at: DateTimeWrapper,
+}
+
+impl GDriveExclusion {
+    #[must_use]
+    pub fn new(
+        servicesession: &str,
+        gdriveid: &str,
+        filename: &str,
+        mime_type: Option<&str>,
+        reason: ExclusionReason,
+    ) -> Self {
+        Self {
+            servicesession: servicesession.into(),
+            gdriveid: gdriveid.into(),
+            filename: filename.into(),
+            mime_type: mime_type.map(Into::into),
+            reason: reason.to_str().into(),
+            created_at: DateTimeWrapper::now(),
+        }
+    }
+
+    /// Exclusions of `servicesession`, or of every session
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(servicesession: Option<&str>, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM gdrive_exclusion
+                WHERE ($servicesession::text IS NULL OR servicesession=$servicesession)
+                ORDER BY servicesession, reason, filename
+            "#,
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveExclusion::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_ids(
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<HashSet<StackString>, Error> {
+        #[derive(FromSqlRow)]
+        struct Excluded {
+            gdriveid: StackString,
+        }
+
+        let query = query!(
+            "SELECT gdriveid FROM gdrive_exclusion WHERE servicesession=$servicesession",
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        let excluded: Vec<Excluded> = timed("GDriveExclusion::get_ids", query.fetch(&conn)).await?;
+        Ok(excluded.into_iter().map(|e| e.gdriveid).collect())
+    }
+
+    /// Whether `gdriveid` is excluded from `servicesession`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn is_excluded(
+        servicesession: &str,
+        gdriveid: &str,
+        pool: &PgPool,
+    ) -> Result<bool, Error> {
+        let query = query!(
+            r#"
+                SELECT EXISTS (
+                    SELECT 1 FROM gdrive_exclusion
+                    WHERE servicesession=$servicesession
+                      AND gdriveid=$gdriveid
+                )
+            "#,
+            servicesession = servicesession,
+            gdriveid = gdriveid,
+        );
+        let conn = pool.get().await?;
+        let (excluded,) = timed_one("GDriveExclusion::is_excluded", query.fetch_one(&conn)).await?;
+        Ok(excluded)
+    }
+
+    /// Insert, or update the reason of an existing exclusion
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO gdrive_exclusion (
+                    servicesession, gdriveid, filename, mime_type, reason, created_at
+                ) VALUES (
+                    $servicesession, $gdriveid, $filename, $mime_type, $reason, $created_at
+                )
+                ON CONFLICT (servicesession, gdriveid) DO UPDATE
+                    SET filename=EXCLUDED.filename,
+                        mime_type=EXCLUDED.mime_type,
+                        reason=EXCLUDED.reason
+            "#,
+            servicesession = self.servicesession,
+            gdriveid = self.gdriveid,
+            filename = self.filename,
+            mime_type = self.mime_type,
+            reason = self.reason,
+            created_at = self.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveExclusion::upsert", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// A gdrive file at a path shared with other files of its folder
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SharedPath {
+    pub gdriveid: StackString,
+    /// The shared path
+    pub urlname: StackString,
+    /// Cached under the shared path rather than a disambiguated one
+    pub plain: bool,
+    /// The last sync of the shared path copied this file's content
+    pub synced: bool,
+    pub created_at: DateTimeWrapper,
+}
+
+/// A gdrive file cached under `disambiguated_urlname` because another file
+/// of the same folder has the same name, `urlname` is the path they share
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct GDriveDuplicate {
+    pub servicesession: StackString,
+    pub gdriveid: StackString,
+    pub urlname: StackString,
+    pub disambiguated_urlname: StackString,
+    pub created_at: DateTimeWrapper,
+}
+
+impl GDriveDuplicate {
+    /// Duplicates of `servicesession`, or of every session
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(servicesession: Option<&str>, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM gdrive_duplicate
+                WHERE ($servicesession::text IS NULL OR servicesession=$servicesession)
+                ORDER BY servicesession, urlname, gdriveid
+            "#,
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveDuplicate::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Duplicates of `servicesession` keyed on the drive id
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_map(
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<HashMap<StackString, Self>, Error> {
+        Ok(Self::get_all(Some(servicesession), pool)
+            .await?
+            .into_iter()
+            .map(|d| (d.gdriveid.clone(), d))
+            .collect())
+    }
+
+    /// Live cache entries of `servicesession` that share their path with an
+    /// entry of another id, disambiguated entries count under the path they
+    /// share
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn find_shared_paths(
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<Vec<SharedPath>, Error> {
+        let query = query!(
+            r#"
+                WITH files AS (
+                    SELECT c.serviceid, COALESCE(d.urlname, c.urlname) AS urlname,
+                           d.urlname IS NULL AS plain, c.md5sum, c.created_at
+                    FROM file_info_cache c
+                    LEFT JOIN gdrive_duplicate d
+                        ON d.servicesession = c.servicesession
+                       AND d.gdriveid = c.serviceid
+                       AND d.disambiguated_urlname = c.urlname
+                    WHERE c.servicesession=$servicesession
+                      AND c.servicetype='gdrive'
+                      AND c.deleted_at IS NULL
+                ), shared AS (
+                    SELECT urlname FROM files
+                    GROUP BY urlname
+                    HAVING count(DISTINCT serviceid) > 1
+                )
+                SELECT DISTINCT ON (files.urlname, files.serviceid)
+                    files.serviceid AS gdriveid, files.urlname, files.plain,
+                    EXISTS (
+                        SELECT 1 FROM sync_history h
+                        WHERE (h.url0 = files.urlname OR h.url1 = files.urlname)
+                          AND h.md5sum = files.md5sum
+                    ) AS synced,
+                    files.created_at
+                FROM files JOIN shared USING (urlname)
+                ORDER BY files.urlname, files.serviceid, files.created_at
+            "#,
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveDuplicate::find_shared_paths", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO gdrive_duplicate (
+                    servicesession, gdriveid, urlname, disambiguated_urlname, created_at
+                ) VALUES (
+                    $servicesession, $gdriveid, $urlname, $disambiguated_urlname, $created_at
+                )
+                ON CONFLICT (servicesession, gdriveid) DO UPDATE
+                    SET urlname=EXCLUDED.urlname,
+                        disambiguated_urlname=EXCLUDED.disambiguated_urlname
+            "#,
+            servicesession = self.servicesession,
+            gdriveid = self.gdriveid,
+            urlname = self.urlname,
+            disambiguated_urlname = self.disambiguated_urlname,
+            created_at = self.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveDuplicate::upsert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                DELETE FROM gdrive_duplicate
+                WHERE servicesession=$servicesession AND gdriveid=$gdriveid
+            "#,
+            servicesession = self.servicesession,
+            gdriveid = self.gdriveid,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveDuplicate::delete", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// Content of a pair of files as of their last successful copy, keyed on
+/// the two urls in sorted order so that copies in either direction update
+/// the same row.  Pairs with a local file are also keyed on the node, pairs
+/// between remote backends have an empty `node_id`.
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SyncHistory {
+    pub url0: StackString,
+    pub url1: StackString,
+    pub md5sum: Option<StackString>,
+    pub filestat_st_size: i64,
+    pub synced_at: DateTimeWrapper,
+    pub node_id: StackString,
+}
+
+impl SyncHistory {
+    fn key<'a>(url0: &'a str, url1: &'a str) -> (&'a str, &'a str) {
+        if url0 <= url1 {
+            (url0, url1)
+        } else {
+            (url1, url0)
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(
+        url0: &str,
+        url1: &str,
+        node_id: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let (url0, url1) = Self::key(url0, url1);
+        let node_id = local_node(url0, url1, node_id).unwrap_or("");
+        let query = query!(
+            r#"
+                SELECT * FROM sync_history
+                WHERE url0 = $url0 AND url1 = $url1 AND node_id = $node_id
+            "#,
+            url0 = url0,
+            url1 = url1,
+            node_id = node_id,
+        );
+        let conn = pool.get().await?;
+        timed("SyncHistory::get", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Record that both urls now hold a file with `md5sum` and `size`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn record(
+        url0: &str,
+        url1: &str,
+        md5sum: Option<&str>,
+        size: i64,
+        node_id: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let (url0, url1) = Self::key(url0, url1);
+        let node_id = local_node(url0, url1, node_id).unwrap_or("");
+        let query = query!(
+            r#"
+                INSERT INTO sync_history (
+                    url0, url1, md5sum, filestat_st_size, synced_at, node_id
+                )
+                VALUES ($url0, $url1, $md5sum, $size, now(), $node_id)
+                ON CONFLICT (node_id, url0, url1) DO UPDATE
+                    SET md5sum=EXCLUDED.md5sum,
+                        filestat_st_size=EXCLUDED.filestat_st_size,
+                        synced_at=EXCLUDED.synced_at
+            "#,
+            url0 = url0,
+            url1 = url1,
+            md5sum = md5sum,
+            size = size,
+            node_id = node_id,
+        );
+        let conn = pool.get().await?;
+        timed("SyncHistory::record", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// A pair of files both changed since their last sync
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SyncConflict {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+    pub src_size: i64,
+    pub dst_size: i64,
+    pub src_mtime: DateTimeWrapper,
+    pub dst_mtime: DateTimeWrapper,
+    pub policy: StackString,
+    pub created_at: DateTimeWrapper,
+    pub resolved_at: Option<DateTimeWrapper>,
+    /// Where `KeepBoth` is copying the losing file, until the copy is done
+    pub copy_url: Option<StackString>,
+}
+
+impl fmt::Display for SyncConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ({} bytes {}) {} ({} bytes {})",
+            self.created_at,
+            self.policy,
+            self.src_url,
+            self.src_size,
+            self.src_mtime,
+            self.dst_url,
+            self.dst_size,
+            self.dst_mtime,
+        )
+    }
+}
+
+impl SyncConflict {
+    /// Insert or refresh the conflict, `resolved` if `policy` already
+    /// settled it
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO sync_conflict (
+                    src_url, dst_url, src_size, dst_size, src_mtime, dst_mtime, policy,
+                    created_at, resolved_at, copy_url
+                ) VALUES (
+                    $src_url, $dst_url, $src_size, $dst_size, $src_mtime, $dst_mtime, $policy,
+                    $created_at, $resolved_at, $copy_url
+                )
+                ON CONFLICT (src_url, dst_url) DO UPDATE
+                    SET src_size=EXCLUDED.src_size,
+                        dst_size=EXCLUDED.dst_size,
+                        src_mtime=EXCLUDED.src_mtime,
+                        dst_mtime=EXCLUDED.dst_mtime,
+                        policy=EXCLUDED.policy,
+                        resolved_at=EXCLUDED.resolved_at,
+                        copy_url=EXCLUDED.copy_url
+            "#,
+            src_url = self.src_url,
+            dst_url = self.dst_url,
+            src_size = self.src_size,
+            dst_size = self.dst_size,
+            src_mtime = self.src_mtime,
+            dst_mtime = self.dst_mtime,
+            policy = self.policy,
+            created_at = self.created_at,
+            resolved_at = self.resolved_at,
+            copy_url = self.copy_url,
+        );
+        let conn = pool.get().await?;
+        timed("SyncConflict::upsert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Note that the losing file of the conflict is being copied to
+    /// `copy_url`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_copy(
+        src_url: &str,
+        dst_url: &str,
+        copy_url: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_conflict SET copy_url = $copy_url
+                WHERE src_url = $src_url AND dst_url = $dst_url
+            "#,
+            src_url = src_url,
+            dst_url = dst_url,
+            copy_url = copy_url,
+        );
+        let conn = pool.get().await?;
+        timed("SyncConflict::set_copy", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// The conflict whose losing file `loser_url` has just been copied to
+    /// `copy_url`, the copy is cleared so that it's only returned once
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn take_copy(
+        loser_url: &str,
+        copy_url: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_conflict SET copy_url = NULL
+                WHERE copy_url = $copy_url
+                  AND (src_url = $loser_url OR dst_url = $loser_url)
+                RETURNING *
+            "#,
+            loser_url = loser_url,
+            copy_url = copy_url,
+        );
+        let conn = pool.get().await?;
+        timed("SyncConflict::take_copy", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Conflicts still waiting to be resolved by hand
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_unresolved(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM sync_conflict
+                WHERE resolved_at IS NULL
+                ORDER BY created_at
+            "#
+        );
+        let conn = pool.get().await?;
+        timed("SyncConflict::get_unresolved", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Mark the conflict of `src_url` and `dst_url` resolved, once the pair
+    /// has been synced again
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn resolve(src_url: &str, dst_url: &str, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_conflict SET resolved_at = now()
+                WHERE resolved_at IS NULL
+                  AND ((src_url = $src_url AND dst_url = $dst_url)
+                    OR (src_url = $dst_url AND dst_url = $src_url))
+            "#,
+            src_url = src_url,
+            dst_url = dst_url,
+        );
+        let conn = pool.get().await?;
+        timed("SyncConflict::resolve", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// A google-native gdrive file, the type it's exported as and the extension
+/// appended to its local name.  `source_modified` and `export_md5sum` are
+/// the Drive-side modifiedTime and the md5sum of the bytes of the last
+/// export, unset until it's first exported.
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct GDriveExport {
+    pub servicesession: StackString,
+    pub gdriveid: StackString,
+    pub filename: StackString,
+    pub mime_type: StackString,
+    pub export_mime_type: StackString,
+    pub extension: StackString,
+    pub last_modified: DateTimeWrapper,
+    pub source_modified: Option<DateTimeWrapper>,
+    pub export_md5sum: Option<StackString>,
+}
+
+impl GDriveExport {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_id(
+        servicesession: &str,
+        gdriveid: &str,
+        pool: &PgPool,
+    ) -> Result<Option<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM gdrive_export
+                WHERE servicesession=$servicesession AND gdriveid=$gdriveid
+            "#,
+            servicesession = servicesession,
+            gdriveid = gdriveid,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveExport::get_by_id", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Insert the exports of a listing in one transaction, rows already
+    /// recorded are only rewritten when their name or formats changed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert_all(exports: &[Self], pool: &PgPool) -> Result<(), Error> {
+        if exports.is_empty() {
+            return Ok(());
+        }
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        for export in exports {
+            let query = query!(
+                r#"
+                    INSERT INTO gdrive_export (
+                        servicesession, gdriveid, filename, mime_type, export_mime_type,
+                        extension, last_modified
+                    ) VALUES (
+                        $servicesession, $gdriveid, $filename, $mime_type, $export_mime_type,
+                        $extension, $last_modified
+                    )
+                    ON CONFLICT (servicesession, gdriveid) DO UPDATE
+                        SET filename=EXCLUDED.filename,
+                            mime_type=EXCLUDED.mime_type,
+                            export_mime_type=EXCLUDED.export_mime_type,
+                            extension=EXCLUDED.extension,
+                            last_modified=EXCLUDED.last_modified
+                        WHERE (
+                            gdrive_export.filename, gdrive_export.mime_type,
+                            gdrive_export.export_mime_type, gdrive_export.extension
+                        ) IS DISTINCT FROM (
+                            EXCLUDED.filename, EXCLUDED.mime_type,
+                            EXCLUDED.export_mime_type, EXCLUDED.extension
+                        )
+                "#,
+                servicesession = export.servicesession,
+                gdriveid = export.gdriveid,
+                filename = export.filename,
+                mime_type = export.mime_type,
+                export_mime_type = export.export_mime_type,
+                extension = export.extension,
+                last_modified = export.last_modified,
+            );
+            timed("GDriveExport::upsert_all", query.execute(&tran)).await?;
+        }
+        tran.commit().await?;
+        Ok(())
+    }
+
+    /// Whether the last export is still current: the file wasn't modified
+    /// on the drive since and `local_md5sum`, the md5sum of the local copy,
+    /// is still what was exported
+    #[must_use]
+    pub fn is_current(
+        &self,
+        source_modified: Option<DateTimeWrapper>,
+        local_md5sum: Option<&str>,
+    ) -> bool {
+        match (self.source_modified, source_modified, &self.export_md5sum) {
+            (Some(exported), Some(modified), Some(md5sum)) => {
+                modified <= exported && local_md5sum == Some(md5sum.as_str())
+            }
+            _ => false,
+        }
+    }
+
+    /// Record an export, the row is written by `upsert` when the file is
+    /// listed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_exported(
+        servicesession: &str,
+        gdriveid: &str,
+        source_modified: Option<DateTimeWrapper>,
+        export_md5sum: &str,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE gdrive_export
+                SET source_modified=$source_modified,
+                    export_md5sum=$export_md5sum
+                WHERE servicesession=$servicesession AND gdriveid=$gdriveid
+            "#,
+            servicesession = servicesession,
+            gdriveid = gdriveid,
+            source_modified = source_modified,
+            export_md5sum = export_md5sum,
+        );
+        let conn = pool.get().await?;
+        timed("GDriveExport::set_exported", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncJobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+    /// Stopped by `--max-runtime` with transfers left in the queue
+    Partial,
+}
+
+impl SyncJobStatus {
+    /// How a run ended, a failure inside a cancelled job is `Cancelled`
+    #[must_use]
+    pub fn of_run(failed: bool, partial: bool) -> Self {
+        if failed && is_cancelled() {
+            Self::Cancelled
+        } else if failed {
+            Self::Failed
+        } else if partial {
+            Self::Partial
+        } else {
+            Self::Succeeded
+        }
+    }
+
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Queued => "queued",
+            Self::Running => "running",
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+            Self::Cancelled => "cancelled",
+            Self::Partial => "partial",
+        }
+    }
+}
+
+impl fmt::Display for SyncJobStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for SyncJobStatus {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queued" => Ok(Self::Queued),
+            "running" => Ok(Self::Running),
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            "cancelled" => Ok(Self::Cancelled),
+            "partial" => Ok(Self::Partial),
+            _ => Err(format_err!("Invalid status {s}")),
+        }
+    }
+}
+
+/// A sync triggered over http, persisted so that jobs interrupted by a
+/// restart can be picked up again
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SyncJob {
+    pub id: Uuid,
+    pub job_type: StackString,
+    pub name: Option<StackString>,
+    pub email: Option<StackString>,
+    pub status: StackString,
+    pub created_at: DateTimeWrapper,
+    pub started_at: Option<DateTimeWrapper>,
+    pub finished_at: Option<DateTimeWrapper>,
+    pub error: Option<StackString>,
+}
+
+impl SyncJob {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn create(
+        job_type: &str,
+        name: Option<&str>,
+        email: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let job = Self {
+            id: Uuid::new_v4(),
+            job_type: job_type.into(),
+            name: name.map(Into::into),
+            email: email.map(Into::into),
+            status: SyncJobStatus::Queued.to_str().into(),
+            created_at: DateTimeWrapper::now(),
+            started_at: None,
+            finished_at: None,
+            error: None,
+        };
+        let query = query!(
+            r#"
+                INSERT INTO sync_jobs (id, job_type, name, email, status, created_at)
+                VALUES ($id, $job_type, $name, $email, $status, $created_at)
+            "#,
+            id = job.id,
+            job_type = job.job_type,
+            name = job.name,
+            email = job.email,
+            status = job.status,
+            created_at = job.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("SyncJob::create", query.execute(&conn)).await?;
+        Ok(job)
+    }
+
+    /// # Errors
+    /// Return error if status is invalid
+    pub fn get_status(&self) -> Result<SyncJobStatus, Error> {
+        self.status.parse()
+    }
+
+    /// Time between starting and finishing the job
+    #[must_use]
+    pub fn duration(&self) -> Option<Duration> {
+        let started_at = self.started_at?.to_offsetdatetime();
+        let finished_at = self.finished_at?.to_offsetdatetime();
+        Some(finished_at - started_at)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_running(&mut self, pool: &PgPool) -> Result<(), Error> {
+        self.status = SyncJobStatus::Running.to_str().into();
+        self.started_at = Some(DateTimeWrapper::now());
+        self.finished_at = None;
+        self.error = None;
+        self.update_status(pool).await
+    }
+
+    /// A job that ran out of time with work left is `Partial` unless it
+    /// failed
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_finished(
+        &mut self,
+        error: Option<StackString>,
+        partial: bool,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let status = if error.is_some() {
+            SyncJobStatus::Failed
+        } else if partial {
+            SyncJobStatus::Partial
+        } else {
+            SyncJobStatus::Succeeded
+        };
+        self.status = status.to_str().into();
+        self.finished_at = Some(DateTimeWrapper::now());
+        self.error = error;
+        self.update_status(pool).await
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_cancelled(&mut self, pool: &PgPool) -> Result<(), Error> {
+        self.status = SyncJobStatus::Cancelled.to_str().into();
+        self.finished_at = Some(DateTimeWrapper::now());
+        self.error = None;
+        self.update_status(pool).await
+    }
+
+    async fn update_status(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_jobs
+                SET status=$status,
+                    started_at=$started_at,
+                    finished_at=$finished_at,
+                    error=$error
+                WHERE id=$id
+            "#,
+            id = self.id,
+            status = self.status,
+            started_at = self.started_at,
+            finished_at = self.finished_at,
+            error = self.error,
+        );
+        let conn = pool.get().await?;
+        timed("SyncJob::update_status", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Jobs that were queued or running when the process went away
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_interrupted(
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM sync_jobs
+                WHERE status IN ('queued', 'running')
+                ORDER BY created_at
+            "#
+        );
+        let conn = pool.get().await?;
+        timed_stream("SyncJob::get_interrupted", query.fetch_streaming(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_recent(
+        pool: &PgPool,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT * FROM sync_jobs
+                ORDER BY created_at DESC
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed_stream("SyncJob::get_recent", query.fetch_streaming(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_id(id: Uuid, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!("SELECT * FROM sync_jobs WHERE id = $id", id = id);
+        let conn = pool.get().await?;
+        timed("SyncJob::get_by_id", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// The effective parameters of one run: the arguments it was invoked with,
+/// the config values that affect its behavior, the version and the host.
+/// Runs started through the http api point at their `sync_jobs` row.
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RunParameters {
+    pub id: Uuid,
+    pub job_id: Option<Uuid>,
+    pub action: StackString,
+    pub arguments: Value,
+    pub settings: Value,
+    pub version: StackString,
+    pub hostname: StackString,
+    pub created_at: DateTimeWrapper,
+    /// A `SyncJobStatus` once the run has ended, `None` while it runs
+    pub status: Option<StackString>,
+}
+
+impl RunParameters {
+    #[must_use]
+    pub fn new(
+        action: FileSyncAction,
+        job_id: Option<Uuid>,
+        arguments: Value,
+        settings: Value,
+    ) -> Self {
+        let hostname = hostname::get().map_or_else(
+            |_| StackString::from("unknown"),
+            |h| h.to_string_lossy().as_ref().into(),
+        );
+        Self {
+            id: Uuid::new_v4(),
+            job_id,
+            action: action.to_str().into(),
+            arguments,
+            settings,
+            version: env!("CARGO_PKG_VERSION").into(),
+            hostname,
+            created_at: DateTimeWrapper::now(),
+            status: None,
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO run_parameters (
+                    id, job_id, action, arguments, settings, version, hostname, created_at
+                ) VALUES (
+                    $id, $job_id, $action, $arguments, $settings, $version, $hostname,
+                    $created_at
+                )
+            "#,
+            id = self.id,
+            job_id = self.job_id,
+            action = self.action,
+            arguments = self.arguments,
+            settings = self.settings,
+            version = self.version,
+            hostname = self.hostname,
+            created_at = self.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("RunParameters::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Record how the run `id` ended
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_status(id: Uuid, status: SyncJobStatus, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            "UPDATE run_parameters SET status = $status WHERE id = $id",
+            id = id,
+            status = status.to_str(),
+        );
+        let conn = pool.get().await?;
+        timed("RunParameters::set_status", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// Most recent first, optionally only runs of `action`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_recent(
+        action: Option<&str>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT * FROM run_parameters
+                WHERE ($action::text IS NULL OR action=$action)
+                ORDER BY created_at DESC
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            action = action,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("RunParameters::get_recent", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Bytes a run moved to and from one backend, see `ByteUsage`
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RunTransferBytes {
+    pub run_id: Uuid,
+    pub servicetype: StackString,
+    pub logical_bytes: i64,
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+    pub requests: i64,
+    pub retries: i64,
+    pub created_at: DateTimeWrapper,
+}
+
+/// `run_transfer_bytes` summed per day and servicetype
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct DailyTransferBytes {
+    pub day: StackString,
+    pub servicetype: StackString,
+    pub logical_bytes: i64,
+    pub bytes_sent: i64,
+    pub bytes_received: i64,
+    pub requests: i64,
+    pub retries: i64,
+}
+
+impl RunTransferBytes {
+    #[must_use]
+    pub fn new(run_id: Uuid, servicetype: &str, usage: &ByteUsage) -> Self {
+        Self {
+            run_id,
+            servicetype: servicetype.into(),
+            logical_bytes: usage.logical_bytes as i64,
+            bytes_sent: usage.bytes_sent as i64,
+            bytes_received: usage.bytes_received as i64,
+            requests: usage.requests as i64,
+            retries: usage.retries as i64,
+            created_at: DateTimeWrapper::now(),
+        }
+    }
+
+    #[must_use]
+    pub fn usage(&self) -> ByteUsage {
+        ByteUsage {
+            logical_bytes: self.logical_bytes as u64,
+            bytes_sent: self.bytes_sent as u64,
+            bytes_received: self.bytes_received as u64,
+            requests: self.requests as u64,
+            retries: self.retries as u64,
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO run_transfer_bytes (
+                    run_id, servicetype, logical_bytes, bytes_sent, bytes_received, requests,
+                    retries, created_at
+                ) VALUES (
+                    $run_id, $servicetype, $logical_bytes, $bytes_sent, $bytes_received,
+                    $requests, $retries, $created_at
+                )
+                ON CONFLICT (run_id, servicetype) DO UPDATE
+                SET logical_bytes = run_transfer_bytes.logical_bytes + EXCLUDED.logical_bytes,
+                    bytes_sent = run_transfer_bytes.bytes_sent + EXCLUDED.bytes_sent,
+                    bytes_received = run_transfer_bytes.bytes_received + EXCLUDED.bytes_received,
+                    requests = run_transfer_bytes.requests + EXCLUDED.requests,
+                    retries = run_transfer_bytes.retries + EXCLUDED.retries
+            "#,
+            run_id = self.run_id,
+            servicetype = self.servicetype,
+            logical_bytes = self.logical_bytes,
+            bytes_sent = self.bytes_sent,
+            bytes_received = self.bytes_received,
+            requests = self.requests,
+            retries = self.retries,
+            created_at = self.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("RunTransferBytes::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_runs(ids: &[Uuid], pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let ids = ids.to_vec();
+        let query = query!(
+            r#"
+                SELECT * FROM run_transfer_bytes
+                WHERE run_id = ANY($ids)
+                ORDER BY servicetype
+            "#,
+            ids = ids,
+        );
+        let conn = pool.get().await?;
+        timed("RunTransferBytes::get_by_runs", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Totals per utc day and servicetype since `since`, newest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_daily(
+        since: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<Vec<DailyTransferBytes>, Error> {
+        let since: DateTimeWrapper = since.into();
+        let query = query!(
+            r#"
+                SELECT to_char(created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS day,
+                       servicetype,
+                       sum(logical_bytes)::bigint AS logical_bytes,
+                       sum(bytes_sent)::bigint AS bytes_sent,
+                       sum(bytes_received)::bigint AS bytes_received,
+                       sum(requests)::bigint AS requests,
+                       sum(retries)::bigint AS retries
+                FROM run_transfer_bytes
+                WHERE created_at >= $since
+                GROUP BY 1, 2
+                ORDER BY 1 DESC, 2
+            "#,
+            since = since,
+        );
+        let conn = pool.get().await?;
+        timed("RunTransferBytes::get_daily", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Total size / file count of a session's cache, recorded after each index
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SessionUsage {
+    pub id: Uuid,
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub total_size: i64,
+    pub file_count: i64,
+    pub created_at: DateTimeWrapper,
+}
+
+impl SessionUsage {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn record(
+        servicetype: &str,
+        servicesession: &str,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO session_usage (servicetype, servicesession, total_size, file_count)
+                SELECT $servicetype, $servicesession,
+                       COALESCE(sum(filestat_st_size), 0)::bigint,
+                       count(*)
+                FROM file_info_cache
+                WHERE servicetype=$servicetype
+                  AND servicesession=$servicesession
+                  AND deleted_at IS NULL
+                RETURNING *
+            "#,
+            servicetype = servicetype,
+            servicesession = servicesession,
+        );
+        let conn = pool.get().await?;
+        timed_one("SessionUsage::record", query.fetch_one(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// History since `since`, optionally restricted to one servicesession
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_history(
+        servicesession: Option<&str>,
+        since: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let since = DateTimeWrapper::from_offsetdatetime(since);
+        let query = query!(
+            r#"
+                SELECT * FROM session_usage
+                WHERE ($servicesession::text IS NULL OR servicesession=$servicesession)
+                  AND created_at >= $since
+                ORDER BY servicetype, servicesession, created_at
+            "#,
+            servicesession = servicesession,
+            since = since,
+        );
+        let conn = pool.get().await?;
+        timed("SessionUsage::get_history", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Outcome of one copy, the raw data behind the activity heatmap
+pub struct SyncEvent;
+
+impl SyncEvent {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(
+        src_url: &str,
+        dst_url: &str,
+        bytes: i64,
+        error: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO sync_event (src_url, dst_url, bytes, error)
+                VALUES ($src_url, $dst_url, $bytes, $error)
+            "#,
+            src_url = src_url,
+            dst_url = dst_url,
+            bytes = bytes,
+            error = error,
+        );
+        let conn = pool.get().await?;
+        timed("SyncEvent::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// Transfers of one config on one (UTC) day
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SyncActivity {
+    pub config: StackString,
+    pub day: StackString,
+    pub transfers: i64,
+    pub bytes: i64,
+    pub failures: i64,
+}
+
+impl SyncActivity {
+    /// Activity of the last `days` days, events are attributed to a config
+    /// when their urls fall under its src / dst urls in either direction
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_day(days: i32, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT COALESCE(c.name, c.src_url || ' ' || c.dst_url) AS config,
+                       to_char(e.created_at AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS day,
+                       count(*) AS transfers,
+                       COALESCE(sum(e.bytes) FILTER (WHERE e.error IS NULL), 0)::bigint AS bytes,
+                       count(*) FILTER (WHERE e.error IS NOT NULL) AS failures
+                FROM sync_event e
+                JOIN file_sync_config c
+                  ON (starts_with(e.src_url, c.src_url) AND starts_with(e.dst_url, c.dst_url))
+                  OR (starts_with(e.src_url, c.dst_url) AND starts_with(e.dst_url, c.src_url))
+                WHERE e.created_at > now() - make_interval(days => $days)
+                GROUP BY 1, 2
+                ORDER BY 1, 2
+            "#,
+            days = days,
+        );
+        let conn = pool.get().await?;
+        timed("SyncActivity::get_by_day", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Latest run of a config: the last `sync` job run under its name, the
+/// last success (the newer of its last succeeded job and its last copy
+/// without error, which covers cli runs), the transfers attributed to it
+/// since the latest run started and the next run of its unpaused
+/// `SyncSchedule`
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct ConfigRunSummary {
+    pub config_id: Uuid,
+    pub last_status: Option<StackString>,
+    pub last_started_at: Option<DateTimeWrapper>,
+    pub last_finished_at: Option<DateTimeWrapper>,
+    pub last_success: Option<DateTimeWrapper>,
+    pub files_copied: i64,
+    pub files_failed: i64,
+    pub next_run_at: Option<DateTimeWrapper>,
+}
+
+impl ConfigRunSummary {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_ids(ids: &[Uuid], pool: &PgPool) -> Result<HashMap<Uuid, Self>, Error> {
+        let ids = ids.to_vec();
+        let query = query!(
+            r#"
+                SELECT c.id AS config_id,
+                       j.status AS last_status,
+                       j.started_at AS last_started_at,
+                       j.finished_at AS last_finished_at,
+                       GREATEST(s.finished_at, e.last_copied) AS last_success,
+                       COALESCE(e.copied, 0) AS files_copied,
+                       COALESCE(e.failed, 0) AS files_failed,
+                       sc.next_run_at
+                FROM file_sync_config c
+                LEFT JOIN sync_schedule sc ON sc.name = c.name AND NOT sc.paused
+                LEFT JOIN LATERAL (
+                    SELECT status, started_at, finished_at FROM sync_jobs
+                    WHERE name = c.name AND job_type = $job_type
+                    ORDER BY created_at DESC
+                    LIMIT 1
+                ) j ON true
+                LEFT JOIN LATERAL (
+                    SELECT max(finished_at) AS finished_at FROM sync_jobs
+                    WHERE name = c.name AND job_type = $job_type AND status = $succeeded
+                ) s ON true
+                LEFT JOIN LATERAL (
+                    SELECT count(*) FILTER (
+                               WHERE error IS NULL
+                                 AND created_at >= COALESCE(j.started_at, c.last_run)
+                           ) AS copied,
+                           count(*) FILTER (
+                               WHERE error IS NOT NULL
+                                 AND created_at >= COALESCE(j.started_at, c.last_run)
+                           ) AS failed,
+                           max(created_at) FILTER (WHERE error IS NULL) AS last_copied
+                    FROM sync_event e
+                    WHERE (starts_with(e.src_url, c.src_url) AND starts_with(e.dst_url, c.dst_url))
+                       OR (starts_with(e.src_url, c.dst_url) AND starts_with(e.dst_url, c.src_url))
+                ) e ON true
+                WHERE c.id = ANY($ids)
+            "#,
+            ids = ids,
+            job_type = FileSyncAction::Sync.to_str(),
+            succeeded = SyncJobStatus::Succeeded.to_str(),
+        );
+        let conn = pool.get().await?;
+        let summaries: Vec<Self> =
+            timed("ConfigRunSummary::get_by_ids", query.fetch(&conn)).await?;
+        Ok(summaries.into_iter().map(|s| (s.config_id, s)).collect())
+    }
+
+    /// Time between starting and finishing the latest job
+    #[must_use]
+    pub fn duration(&self) -> Option<Duration> {
+        let started_at = self.last_started_at?.to_offsetdatetime();
+        let finished_at = self.last_finished_at?.to_offsetdatetime();
+        Some(finished_at - started_at)
+    }
+}
+
+#[derive(FromSqlRow, Clone, Debug)]
+pub struct MaintenanceMode {
+    pub id: Uuid,
+    pub enabled: bool,
+    pub created_at: DateTimeWrapper,
+}
+
+impl MaintenanceMode {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn is_enabled(pool: &PgPool) -> Result<bool, Error> {
+        let query = query!("SELECT * FROM maintenance_mode ORDER BY created_at DESC LIMIT 1");
+        let conn = pool.get().await?;
+        let current: Option<Self> =
+            timed("MaintenanceMode::is_enabled", query.fetch_opt(&conn)).await?;
+        Ok(current.map_or(false, |m| m.enabled))
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_enabled(pool: &PgPool, enabled: bool) -> Result<(), Error> {
+        let query = query!(
+            "INSERT INTO maintenance_mode (enabled, created_at) VALUES ($enabled, now())",
+            enabled = enabled,
+        );
+        let conn = pool.get().await?;
+        timed("MaintenanceMode::set_enabled", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+#[derive(FromSqlRow, Clone, Debug)]
+pub struct AuthorizedUsers {
+    pub email: StackString,
+    pub created_at: OffsetDateTime,
+}
+
+impl AuthorizedUsers {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_authorized_users(
+        pool: &PgPool,
+    ) -> Result<impl Stream<Item = Result<Self, PqError>>, Error> {
+        let query = query!("SELECT * FROM authorized_users WHERE deleted_at IS NULL");
+        let conn = pool.get().await?;
+        timed_stream(
+            "AuthorizedUsers::get_authorized_users",
+            query.fetch_streaming(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Returns error if db query fails
+    pub async fn get_most_recent(
+        pool: &PgPool,
+    ) -> Result<(Option<OffsetDateTime>, Option<OffsetDateTime>), Error> {
+        #[derive(FromSqlRow)]
+        struct CreatedDeleted {
+            created_at: Option<OffsetDateTime>,
+            deleted_at: Option<OffsetDateTime>,
+        }
+
+        let query = query!(
+            "SELECT max(created_at) as created_at, max(deleted_at) as deleted_at FROM \
+             authorized_users"
+        );
+        let conn = pool.get().await?;
+        let result: Option<CreatedDeleted> =
+            timed("AuthorizedUsers::get_most_recent", query.fetch_opt(&conn)).await?;
+        match result {
+            Some(result) => Ok((result.created_at, result.deleted_at)),
+            None => Ok((None, None)),
+        }
+    }
+}
+
+/// Last time the content of a cached file was checked against its md5sum
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct FileVerification {
+    pub cache_id: Uuid,
+    pub verified_at: DateTimeWrapper,
+}
+
+impl FileVerification {
+    /// Up to `limit` cached files of the session to verify: files never
+    /// verified or modified since they were verified come first (most
+    /// recently modified first), then the files verified longest ago, in
+    /// random order among equals, so that every file is eventually covered
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_sample(
+        servicesession: &str,
+        servicetype: &str,
+        limit: i64,
+        pool: &PgPool,
+    ) -> Result<Vec<FileInfoCache>, Error> {
+        let query = query!(
+            r#"
+                SELECT c.* FROM file_info_cache c
+                LEFT JOIN file_verification v ON v.cache_id = c.id
+                WHERE c.servicesession = $servicesession
+                  AND c.servicetype = $servicetype
+                  AND c.deleted_at IS NULL
+                ORDER BY COALESCE(v.verified_at >= c.modified_at, false),
+                         CASE WHEN v.verified_at >= c.modified_at THEN NULL
+                              ELSE c.modified_at END DESC NULLS LAST,
+                         v.verified_at,
+                         random()
+                LIMIT $limit
+            "#,
+            servicesession = servicesession,
+            servicetype = servicetype,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("FileVerification::get_sample", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn record(cache_id: Uuid, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO file_verification (cache_id, verified_at)
+                VALUES ($cache_id, now())
+                ON CONFLICT (cache_id) DO UPDATE SET verified_at = now()
+            "#,
+            cache_id = cache_id,
+        );
+        let conn = pool.get().await?;
+        timed("FileVerification::record", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// How much of a session has been verified since it last changed
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct VerifyCoverage {
+    pub total: i64,
+    pub verified: i64,
+    pub oldest_verified: Option<DateTimeWrapper>,
+}
+
+impl VerifyCoverage {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(
+        servicesession: &str,
+        servicetype: &str,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let query = query!(
+            r#"
+                SELECT count(*) AS total,
+                       count(*) FILTER (WHERE v.verified_at >= c.modified_at) AS verified,
+                       min(v.verified_at) AS oldest_verified
+                FROM file_info_cache c
+                LEFT JOIN file_verification v ON v.cache_id = c.id
+                WHERE c.servicesession = $servicesession
+                  AND c.servicetype = $servicetype
+                  AND c.deleted_at IS NULL
+            "#,
+            servicesession = servicesession,
+            servicetype = servicetype,
+        );
+        let conn = pool.get().await?;
+        timed_one("VerifyCoverage::get", query.fetch_one(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+impl fmt::Display for VerifyCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.total > 0 {
+            100.0 * self.verified as f64 / self.total as f64
+        } else {
+            100.0
+        };
+        write!(
+            f,
+            "{}/{} verified ({percent:.1}%)",
+            self.verified, self.total
+        )?;
+        if let Some(oldest) = self.oldest_verified {
+            write!(f, ", oldest {oldest}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Source `position` of the virtual root `name`, synced under `prefix` of
+/// the destination of every config using `virtual://<name>` as its source
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct VirtualRootMember {
+    pub name: StackString,
+    pub position: i32,
+    pub src_url: StackString,
+    pub prefix: StackString,
+}
+
+impl VirtualRootMember {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_name(name: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM virtual_root WHERE name = $name ORDER BY position",
+            name = name,
+        );
+        let conn = pool.get().await?;
+        timed("VirtualRootMember::get_by_name", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM virtual_root ORDER BY name, position");
+        let conn = pool.get().await?;
+        timed("VirtualRootMember::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Replace the members of the virtual root `name`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn replace(name: &str, members: &[Self], pool: &PgPool) -> Result<(), Error> {
+        let query = query!("DELETE FROM virtual_root WHERE name = $name", name = name);
+        let conn = pool.get().await?;
+        timed("VirtualRootMember::replace", query.execute(&conn)).await?;
+        for member in members {
+            let query = query!(
+                r#"
+                    INSERT INTO virtual_root (name, position, src_url, prefix)
+                    VALUES ($name, $position, $src_url, $prefix)
+                "#,
+                name = name,
+                position = member.position,
+                src_url = member.src_url,
+                prefix = member.prefix,
+            );
+            timed("VirtualRootMember::replace", query.execute(&conn)).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Object written encrypted by `Encryption::copy_to`, `clear_url` is where
+/// the file would have been written without encryption
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct EncryptedFile {
+    pub encrypted_url: StackString,
+    pub clear_url: StackString,
+    pub clear_md5sum: Option<StackString>,
+    pub clear_size: i64,
+    pub created_at: DateTimeWrapper,
+}
+
+impl EncryptedFile {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_encrypted_url(url: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM encrypted_file WHERE encrypted_url = $url",
+            url = url,
+        );
+        let conn = pool.get().await?;
+        timed(
+            "EncryptedFile::get_by_encrypted_url",
+            query.fetch_opt(&conn),
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_clear_url(url: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM encrypted_file WHERE clear_url = $url",
+            url = url,
+        );
+        let conn = pool.get().await?;
+        timed("EncryptedFile::get_by_clear_url", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO encrypted_file (encrypted_url, clear_url, clear_md5sum, clear_size)
+                VALUES ($encrypted_url, $clear_url, $clear_md5sum, $clear_size)
+                ON CONFLICT (clear_url) DO UPDATE
+                SET encrypted_url=EXCLUDED.encrypted_url,
+                    clear_md5sum=EXCLUDED.clear_md5sum,
+                    clear_size=EXCLUDED.clear_size,
+                    created_at=now()
+            "#,
+            encrypted_url = self.encrypted_url,
+            clear_url = self.clear_url,
+            clear_md5sum = self.clear_md5sum,
+            clear_size = self.clear_size,
+        );
+        let conn = pool.get().await?;
+        timed("EncryptedFile::upsert", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Object uploaded compressed, the original size and md5sum stand in for
+/// the cached ones while the object is still `compressed_size` bytes
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct CompressedFile {
+    pub url: StackString,
+    pub codec: StackString,
+    pub original_size: i64,
+    pub original_md5sum: Option<StackString>,
+    pub compressed_size: i64,
+    pub created_at: DateTimeWrapper,
+}
+
+impl CompressedFile {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_url(url: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!("SELECT * FROM compressed_file WHERE url = $url", url = url);
+        let conn = pool.get().await?;
+        timed("CompressedFile::get_by_url", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO compressed_file (
+                    url, codec, original_size, original_md5sum, compressed_size
+                )
+                VALUES ($url, $codec, $original_size, $original_md5sum, $compressed_size)
+                ON CONFLICT (url) DO UPDATE
+                SET codec=EXCLUDED.codec,
+                    original_size=EXCLUDED.original_size,
+                    original_md5sum=EXCLUDED.original_md5sum,
+                    compressed_size=EXCLUDED.compressed_size,
+                    created_at=now()
+            "#,
+            url = self.url,
+            codec = self.codec,
+            original_size = self.original_size,
+            original_md5sum = self.original_md5sum,
+            compressed_size = self.compressed_size,
+        );
+        let conn = pool.get().await?;
+        timed("CompressedFile::upsert", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Uploaded uncompressed since, the record no longer applies
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(url: &str, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!("DELETE FROM compressed_file WHERE url = $url", url = url);
+        let conn = pool.get().await?;
+        timed("CompressedFile::delete", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Where a file of a config with a partitioned `dst_layout` was placed, so
+/// that it keeps matching the same destination even if its mtime changes
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct LayoutMapping {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+    pub created_at: DateTimeWrapper,
+}
+
+impl LayoutMapping {
+    /// Destination of each file under `baseurl0` placed under `baseurl1`,
+    /// keyed by source url
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_mappings(
+        baseurl0: &str,
+        baseurl1: &str,
+        pool: &PgPool,
+    ) -> Result<HashMap<StackString, StackString>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM layout_mapping
+                WHERE position($baseurl0 in src_url) = 1
+                  AND position($baseurl1 in dst_url) = 1
+            "#,
+            baseurl0 = baseurl0,
+            baseurl1 = baseurl1,
+        );
+        let conn = pool.get().await?;
+        let mappings: Vec<Self> = timed("LayoutMapping::get_mappings", query.fetch(&conn)).await?;
+        Ok(mappings
+            .into_iter()
+            .map(|m| (m.src_url, m.dst_url))
+            .collect())
+    }
+
+    /// Record the destinations of `src_urls` (pairwise with `dst_urls`) in one
+    /// statement, placements already recorded are kept
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert_batch(
+        src_urls: &[StackString],
+        dst_urls: &[StackString],
+        pool: &PgPool,
+    ) -> Result<u64, Error> {
+        if src_urls.is_empty() {
+            return Ok(0);
+        }
+        let query = query!(
+            r#"
+                INSERT INTO layout_mapping (src_url, dst_url)
+                SELECT * FROM unnest($src_urls::text[], $dst_urls::text[])
+                ON CONFLICT DO NOTHING
+            "#,
+            src_urls = src_urls,
+            dst_urls = dst_urls,
+        );
+        let conn = pool.get().await?;
+        timed("LayoutMapping::insert_batch", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// `sync` of the config `name` run by `sync_app_http` on the cron schedule
+/// `cron` (see `CronSchedule`)
+#[derive(FromSqlRow, Clone, Debug, PartialEq, Eq)]
+pub struct SyncSchedule {
+    pub name: StackString,
+    pub cron: StackString,
+    pub paused: bool,
+    pub next_run_at: Option<DateTimeWrapper>,
+    pub last_run_at: Option<DateTimeWrapper>,
+    pub last_status: Option<StackString>,
+    pub last_error: Option<StackString>,
+    pub created_at: DateTimeWrapper,
+}
+
+impl fmt::Display for SyncSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} \"{}\"", self.name, self.cron)?;
+        if self.paused {
+            f.write_str(" paused")?;
+        } else if let Some(next_run_at) = self.next_run_at {
+            write!(f, " next {next_run_at}")?;
+        }
+        if let Some(last_run_at) = self.last_run_at {
+            write!(
+                f,
+                " last {last_run_at} {}",
+                self.last_status.as_deref().unwrap_or("")
+            )?;
+        }
+        if let Some(last_error) = &self.last_error {
+            write!(f, " {last_error}")?;
+        }
+        Ok(())
+    }
+}
+
+impl SyncSchedule {
+    #[must_use]
+    pub fn new(name: &str, cron: &CronSchedule, now: OffsetDateTime) -> Self {
+        Self {
+            name: name.into(),
+            cron: cron.to_string().into(),
+            paused: false,
+            next_run_at: cron.next_after(now).map(Into::into),
+            last_run_at: None,
+            last_status: None,
+            last_error: None,
+            created_at: now.into(),
+        }
+    }
+
+    /// First run of the schedule after `now`
+    /// # Errors
+    /// Return error if the stored expression is invalid
+    pub fn next_run(&self, now: OffsetDateTime) -> Result<Option<DateTimeWrapper>, Error> {
+        let cron: CronSchedule = self.cron.parse()?;
+        Ok(cron.next_after(now).map(Into::into))
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM sync_schedule ORDER BY name");
+        let conn = pool.get().await?;
+        timed("SyncSchedule::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_name(name: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM sync_schedule WHERE name = $name",
+            name = name
+        );
+        let conn = pool.get().await?;
+        timed("SyncSchedule::get_by_name", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Unpaused schedules whose next run is due at `now`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_due(now: OffsetDateTime, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM sync_schedule
+                WHERE NOT paused
+                  AND next_run_at IS NOT NULL
+                  AND next_run_at <= $now
+                ORDER BY next_run_at
+            "#,
+            now = now,
+        );
+        let conn = pool.get().await?;
+        timed("SyncSchedule::get_due", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Create the schedule or change its cron expression, a paused schedule
+    /// stays paused
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                INSERT INTO sync_schedule (name, cron, paused, next_run_at)
+                VALUES ($name, $cron, $paused, $next_run_at)
+                ON CONFLICT (name) DO UPDATE
+                SET cron=EXCLUDED.cron,
+                    next_run_at=EXCLUDED.next_run_at
+            "#,
+            name = self.name,
+            cron = self.cron,
+            paused = self.paused,
+            next_run_at = self.next_run_at,
+        );
+        let conn = pool.get().await?;
+        timed("SyncSchedule::upsert", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn set_paused(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_schedule
+                SET paused = $paused, next_run_at = $next_run_at
+                WHERE name = $name
+            "#,
+            name = self.name,
+            paused = self.paused,
+            next_run_at = self.next_run_at,
+        );
+        let conn = pool.get().await?;
+        timed("SyncSchedule::set_paused", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Store the outcome of the run started at `last_run_at` and when to run
+    /// next
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn record_run(&self, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!(
+            r#"
+                UPDATE sync_schedule
+                SET last_run_at = $last_run_at,
+                    last_status = $last_status,
+                    last_error = $last_error,
+                    next_run_at = $next_run_at
+                WHERE name = $name
+            "#,
+            name = self.name,
+            last_run_at = self.last_run_at,
+            last_status = self.last_status,
+            last_error = self.last_error,
+            next_run_at = self.next_run_at,
+        );
+        let conn = pool.get().await?;
+        timed("SyncSchedule::record_run", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// A cached file with a checksum, a row of the `checksum_paths` view read by
+/// external dedup and search tools
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumPath {
+    pub md5sum: Option<StackString>,
+    pub sha1sum: Option<StackString>,
+    pub size: i64,
+    pub mtime: DateTimeWrapper,
+    pub servicetype: StackString,
+    pub servicesession: StackString,
+    pub urlname: StackString,
+    pub modified_at: DateTimeWrapper,
+}
+
+/// An md5sum shared by more than one cached file
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumDuplicate {
+    pub md5sum: StackString,
+    pub size: i64,
+    pub count: i64,
+}
+
+impl ChecksumPath {
+    /// Files whose md5sum or sha1sum is `checksum`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_by_checksum(checksum: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!(
+            r#"
+                SELECT * FROM checksum_paths
+                WHERE md5sum = $checksum OR sha1sum = $checksum
+                ORDER BY urlname
+            "#,
+            checksum = checksum,
+        );
+        let conn = pool.get().await?;
+        timed("ChecksumPath::get_by_checksum", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Files added or changed after (`since`, `after_urlname`) in
+    /// (modified_at, urlname) order, for consumers following the cache
+    /// incrementally: the next page starts after the last entry's
+    /// `modified_at` and `urlname`, so files sharing a timestamp are neither
+    /// skipped nor repeated
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_since(
+        since: OffsetDateTime,
+        after_urlname: &str,
+        limit: usize,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let since: DateTimeWrapper = since.into();
+        let limit = limit as i64;
+        let query = query!(
+            r#"
+                SELECT * FROM checksum_paths
+                WHERE (modified_at, urlname) > ($since, $after_urlname)
+                ORDER BY modified_at, urlname
+                LIMIT $limit
+            "#,
+            since = since,
+            after_urlname = after_urlname,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("ChecksumPath::get_since", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// md5sums found more than once, most copies first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_duplicates(
+        offset: Option<usize>,
+        limit: Option<usize>,
+        pool: &PgPool,
+    ) -> Result<Vec<ChecksumDuplicate>, Error> {
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT md5sum, max(size) AS size, count(*) AS count
+                FROM checksum_paths
+                WHERE md5sum IS NOT NULL
+                GROUP BY md5sum
+                HAVING count(*) > 1
+                ORDER BY count(*) DESC, md5sum
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("ChecksumPath::get_duplicates", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+}
+
+/// Url POSTed the checksums that appear in the cache, see
+/// `checksum_hook::notify_checksum_webhooks`
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ChecksumWebhook {
+    pub url: StackString,
+    pub notified_until: DateTimeWrapper,
+    pub notified_urlname: StackString,
+    pub last_error: Option<StackString>,
+    pub created_at: DateTimeWrapper,
+}
+
+impl ChecksumWebhook {
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = query!("SELECT * FROM checksum_webhook ORDER BY url");
+        let conn = pool.get().await?;
+        timed("ChecksumWebhook::get_all", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Register `url`, it's sent the checksums added from now on
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(url: &str, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO checksum_webhook (url)
+                VALUES ($url)
+                ON CONFLICT (url) DO NOTHING
+            "#,
+            url = url,
+        );
+        let conn = pool.get().await?;
+        timed("ChecksumWebhook::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(url: &str, pool: &PgPool) -> Result<u64, Error> {
+        let query = query!("DELETE FROM checksum_webhook WHERE url = $url", url = url);
+        let conn = pool.get().await?;
+        timed("ChecksumWebhook::delete", query.execute(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn update(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                UPDATE checksum_webhook
+                SET notified_until = $notified_until,
+                    notified_urlname = $notified_urlname,
+                    last_error = $last_error
+                WHERE url = $url
+            "#,
+            url = self.url,
+            notified_until = self.notified_until,
+            notified_urlname = self.notified_urlname,
+            last_error = self.last_error,
+        );
+        let conn = pool.get().await?;
+        timed("ChecksumWebhook::update", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// Upload of `src_url` to `dst_url` left unfinished, `session_id` is the s3
+/// multipart upload id or the drive resumable session uri
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct ResumableTransfer {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+    pub servicetype: StackString,
+    pub session_id: StackString,
+    pub file_size: i64,
+    pub file_mtime: i64,
+    pub created_at: DateTimeWrapper,
+}
+
+fn metadata_mtime(metadata: &Metadata) -> i64 {
+    metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as i64)
+}
+
+impl ResumableTransfer {
+    /// Upload just started of the file `metadata` belongs to
+    #[must_use]
+    pub fn new(
+        src_url: &Url,
+        dst_url: &Url,
+        servicetype: &str,
+        session_id: &str,
+        metadata: &Metadata,
+    ) -> Self {
+        Self {
+            src_url: src_url.as_str().into(),
+            dst_url: dst_url.as_str().into(),
+            servicetype: servicetype.into(),
+            session_id: session_id.into(),
+            file_size: metadata.len() as i64,
+            file_mtime: metadata_mtime(metadata),
+            created_at: OffsetDateTime::now_utc().into(),
+        }
+    }
+
+    /// Whether the source file is unchanged since the upload started, a
+    /// changed file has to be uploaded from scratch
+    #[must_use]
+    pub fn is_current(&self, metadata: &Metadata) -> bool {
+        self.file_size == metadata.len() as i64 && self.file_mtime == metadata_mtime(metadata)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(src_url: &Url, dst_url: &Url, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = query!(
+            "SELECT * FROM resumable_transfer WHERE src_url = $src_url AND dst_url = $dst_url",
+            src_url = src_url.as_str(),
+            dst_url = dst_url.as_str(),
+        );
+        let conn = pool.get().await?;
+        timed("ResumableTransfer::get", query.fetch_opt(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Uploads to `servicetype` started before `created_before`
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_started_before(
+        servicetype: &str,
+        created_before: OffsetDateTime,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let created_before: DateTimeWrapper = created_before.into();
+        let query = query!(
+            r#"
+                SELECT * FROM resumable_transfer
+                WHERE servicetype = $servicetype AND created_at < $created_before
+                ORDER BY created_at
+            "#,
+            servicetype = servicetype,
+            created_before = created_before,
+        );
+        let conn = pool.get().await?;
+        timed("ResumableTransfer::get_started_before", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn insert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO resumable_transfer (
+                    src_url, dst_url, servicetype, session_id, file_size, file_mtime, created_at
+                ) VALUES (
+                    $src_url, $dst_url, $servicetype, $session_id, $file_size, $file_mtime,
+                    $created_at
+                )
+                ON CONFLICT (src_url, dst_url) DO UPDATE
+                SET servicetype = EXCLUDED.servicetype,
+                    session_id = EXCLUDED.session_id,
+                    file_size = EXCLUDED.file_size,
+                    file_mtime = EXCLUDED.file_mtime,
+                    created_at = EXCLUDED.created_at
+            "#,
+            src_url = self.src_url,
+            dst_url = self.dst_url,
+            servicetype = self.servicetype,
+            session_id = self.session_id,
+            file_size = self.file_size,
+            file_mtime = self.file_mtime,
+            created_at = self.created_at,
+        );
+        let conn = pool.get().await?;
+        timed("ResumableTransfer::insert", query.execute(&conn)).await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                DELETE FROM resumable_transfer
+                WHERE src_url = $src_url AND dst_url = $dst_url AND session_id = $session_id
+            "#,
+            src_url = self.src_url,
+            dst_url = self.dst_url,
+            session_id = self.session_id,
+        );
+        let conn = pool.get().await?;
+        timed("ResumableTransfer::delete", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+/// Metadata read from a local file by `extract_metadata`, for the size and
+/// mtime the file had at the time
+#[derive(FromSqlRow, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileMetadata {
+    pub servicesession: StackString,
+    pub urlname: StackString,
+    pub filestat_st_size: i64,
+    pub filestat_st_mtime: DateTimeWrapper,
+    pub mime_type: Option<StackString>,
+    pub width: Option<i32>,
+    pub height: Option<i32>,
+    pub duration_secs: Option<f64>,
+    pub taken_at: Option<DateTimeWrapper>,
+    pub camera: Option<StackString>,
+    pub exif: Option<Value>,
+    pub thumbnail_path: Option<StackString>,
+    pub error: Option<StackString>,
+    pub extracted_at: DateTimeWrapper,
+}
+
+impl FileMetadata {
+    /// Files under `baseurl` never extracted, or changed since they were
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get_pending(
+        servicesession: &str,
+        baseurl: &str,
+        limit: usize,
+        pool: &PgPool,
+    ) -> Result<Vec<FileInfoCache>, Error> {
+        let limit = limit as i64;
+        let query = query!(
+            r#"
+                SELECT c.* FROM file_info_cache c
+                LEFT JOIN file_metadata m
+                  ON m.servicesession = c.servicesession AND m.urlname = c.urlname
+                WHERE c.servicesession = $servicesession
+                  AND starts_with(c.urlname, $baseurl)
+                  AND c.deleted_at IS NULL
+                  AND (m.urlname IS NULL
+                       OR m.filestat_st_size != c.filestat_st_size
+                       OR m.filestat_st_mtime != c.filestat_st_mtime)
+                ORDER BY c.urlname
+                LIMIT $limit
+            "#,
+            servicesession = servicesession,
+            baseurl = baseurl,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("FileMetadata::get_pending", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Files of mime type starting with `mime_type`, by `camera` and taken
+    /// within `taken_after` .. `taken_before`, newest first
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn search(
+        mime_type: Option<&str>,
+        camera: Option<&str>,
+        taken_after: Option<OffsetDateTime>,
+        taken_before: Option<OffsetDateTime>,
+        offset: Option<usize>,
+        limit: Option<usize>,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let taken_after: Option<DateTimeWrapper> = taken_after.map(Into::into);
+        let taken_before: Option<DateTimeWrapper> = taken_before.map(Into::into);
+        let offset = offset.unwrap_or(0) as i64;
+        let limit = limit.map(|l| l as i64);
+        let query = query!(
+            r#"
+                SELECT * FROM file_metadata
+                WHERE error IS NULL
+                  AND ($mime_type::text IS NULL OR starts_with(mime_type, $mime_type))
+                  AND ($camera::text IS NULL OR camera=$camera)
+                  AND ($taken_after::timestamptz IS NULL OR taken_at >= $taken_after)
+                  AND ($taken_before::timestamptz IS NULL OR taken_at < $taken_before)
+                ORDER BY taken_at DESC NULLS LAST, urlname
+                OFFSET $offset
+                LIMIT $limit
+            "#,
+            mime_type = mime_type,
+            camera = camera,
+            taken_after = taken_after,
+            taken_before = taken_before,
+            offset = offset,
+            limit = limit,
+        );
+        let conn = pool.get().await?;
+        timed("FileMetadata::search", query.fetch(&conn))
+            .await
+            .map_err(Into::into)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = query!(
+            r#"
+                INSERT INTO file_metadata (
+                    servicesession, urlname, filestat_st_size, filestat_st_mtime, mime_type,
+                    width, height, duration_secs, taken_at, camera, exif, thumbnail_path, error,
+                    extracted_at
+                ) VALUES (
+                    $servicesession, $urlname, $filestat_st_size, $filestat_st_mtime, $mime_type,
+                    $width, $height, $duration_secs, $taken_at, $camera, $exif, $thumbnail_path,
+                    $error, $extracted_at
+                )
+                ON CONFLICT (servicesession, urlname) DO UPDATE
+                SET filestat_st_size = EXCLUDED.filestat_st_size,
+                    filestat_st_mtime = EXCLUDED.filestat_st_mtime,
+                    mime_type = EXCLUDED.mime_type,
+                    width = EXCLUDED.width,
+                    height = EXCLUDED.height,
+                    duration_secs = EXCLUDED.duration_secs,
+                    taken_at = EXCLUDED.taken_at,
+                    camera = EXCLUDED.camera,
+                    exif = EXCLUDED.exif,
+                    thumbnail_path = EXCLUDED.thumbnail_path,
+                    error = EXCLUDED.error,
+                    extracted_at = EXCLUDED.extracted_at
+            "#,
+            servicesession = self.servicesession,
+            urlname = self.urlname,
+            filestat_st_size = self.filestat_st_size,
+            filestat_st_mtime = self.filestat_st_mtime,
+            mime_type = self.mime_type,
+            width = self.width,
+            height = self.height,
+            duration_secs = self.duration_secs,
+            taken_at = self.taken_at,
+            camera = self.camera,
+            exif = self.exif,
+            thumbnail_path = self.thumbnail_path,
+            error = self.error,
+            extracted_at = self.extracted_at,
+        );
+        let conn = pool.get().await?;
+        timed("FileMetadata::upsert", query.execute(&conn)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{format_err, Error};
+    use stack_string::format_sstr;
+    use std::env::temp_dir;
+    use time::{macros::datetime, Duration};
+    use url::Url;
+    use uuid::Uuid;
+
+    use crate::models::{GDriveExport, ResumableTransfer};
+
+    #[test]
+    fn test_gdrive_export_is_current() {
+        let exported = datetime!(2024-03-09 12:00:00 +00:00);
+        let export = GDriveExport {
+            servicesession: "test_session".into(),
+            gdriveid: "test_id".into(),
+            filename: "report".into(),
+            mime_type: "application/vnd.google-apps.document".into(),
+            export_mime_type: "application/pdf".into(),
+            extension: "pdf".into(),
+            last_modified: exported.into(),
+            source_modified: Some(exported.into()),
+            export_md5sum: Some("d41d8cd98f00b204e9800998ecf8427e".into()),
+        };
+        let md5sum = Some("d41d8cd98f00b204e9800998ecf8427e");
+        assert!(export.is_current(Some(exported.into()), md5sum));
+        assert!(export.is_current(Some((exported - Duration::hours(1)).into()), md5sum));
+        assert!(!export.is_current(Some((exported + Duration::hours(1)).into()), md5sum));
+        assert!(!export.is_current(Some(exported.into()), Some("changed")));
+        assert!(!export.is_current(Some(exported.into()), None));
+        assert!(!export.is_current(None, md5sum));
+        let never_exported = GDriveExport {
+            source_modified: None,
+            export_md5sum: None,
+            ..export
+        };
+        assert!(!never_exported.is_current(Some(exported.into()), md5sum));
+    }
+
+    #[tokio::test]
+    async fn test_resumable_transfer_is_current() -> Result<(), Error> {
+        let dir = temp_dir().join(format_sstr!("resumable_{}", Uuid::new_v4()).as_str());
+        tokio::fs::create_dir_all(&dir).await?;
+        let file = dir.join("upload.txt");
+        tokio::fs::write(&file, b"upload").await?;
+        let src_url =
+            Url::from_file_path(&file).map_err(|()| format_err!("Invalid path {file:?}"))?;
+        let dst_url = Url::parse("s3://test_bucket/upload.txt")?;
+
+        let metadata = tokio::fs::metadata(&file).await?;
+        let transfer = ResumableTransfer::new(&src_url, &dst_url, "s3", "upload_id", &metadata);
+        assert!(transfer.is_current(&metadata));
+
+        let moved = ResumableTransfer {
+            file_mtime: transfer.file_mtime - 1,
+            ..transfer.clone()
+        };
+        assert!(!moved.is_current(&metadata));
+
+        tokio::fs::write(&file, b"upload, changed").await?;
+        let metadata = tokio::fs::metadata(&file).await?;
+        assert!(!transfer.is_current(&metadata));
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
     }
 }
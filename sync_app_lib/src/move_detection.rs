@@ -0,0 +1,142 @@
+use stack_string::StackString;
+use std::collections::{HashMap, HashSet};
+
+use crate::file_info::FileInfo;
+
+/// What identifies a file across a rename, a match on either is enough
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum MoveKey {
+    /// Id the backend keeps when a file is renamed, e.g. the gdrive file id
+    ServiceId(StackString),
+    /// Size and md5sum (sha1sum without an md5sum)
+    Content(i64, StackString),
+}
+
+/// Keys of `finfo` in the order they're tried, a serviceid that is just the
+/// session (as for local files) says nothing about the file
+fn move_keys(finfo: &FileInfo) -> Vec<MoveKey> {
+    let mut keys = Vec::new();
+    if !finfo.serviceid.is_empty() && finfo.serviceid.as_str() != finfo.servicesession.as_str() {
+        keys.push(MoveKey::ServiceId(finfo.serviceid.as_str().into()));
+    }
+    let checksum: Option<StackString> = match (&finfo.md5sum, &finfo.sha1sum) {
+        (Some(md5sum), _) => Some(md5sum.clone().into()),
+        (None, Some(sha1sum)) => Some(sha1sum.clone().into()),
+        (None, None) => None,
+    };
+    if let Some(checksum) = checksum {
+        keys.push(MoveKey::Content(finfo.filestat.st_size, checksum));
+    }
+    keys
+}
+
+/// Index of the one file holding each key, `None` for keys several files
+/// share
+fn unique_keys(finfos: &[FileInfo]) -> HashMap<MoveKey, Option<usize>> {
+    let mut keys = HashMap::new();
+    for (index, finfo) in finfos.iter().enumerate() {
+        for key in move_keys(finfo) {
+            keys.entry(key)
+                .and_modify(|i| *i = None)
+                .or_insert(Some(index));
+        }
+    }
+    keys
+}
+
+/// Pair files tombstoned at one path (`deleted`) with files new at another
+/// path (`new`) that are the same file, i.e. were renamed or moved.
+/// Returns `(index into deleted, index into new)`.  A key shared by several
+/// files on either side is ambiguous and isn't used, those files are still
+/// copied and deleted.
+#[must_use]
+pub fn match_moves(deleted: &[FileInfo], new: &[FileInfo]) -> Vec<(usize, usize)> {
+    let deleted_keys = unique_keys(deleted);
+    let new_keys = unique_keys(new);
+    let mut matched = HashSet::new();
+    let mut pairs = Vec::new();
+    for (index, finfo) in deleted.iter().enumerate() {
+        for key in move_keys(finfo) {
+            if let (Some(Some(d)), Some(Some(n))) = (deleted_keys.get(&key), new_keys.get(&key)) {
+                if *d == index && matched.insert(*n) {
+                    pairs.push((index, *n));
+                    break;
+                }
+            }
+        }
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use time::macros::datetime;
+
+    use crate::{
+        file_info::{fixture_info, FileInfo, FileStat},
+        file_service::FileService,
+        move_detection::match_moves,
+    };
+
+    fn finfo(
+        path: &str,
+        size: i64,
+        md5sum: Option<&str>,
+        serviceid: &str,
+    ) -> Result<FileInfo, Error> {
+        fixture_info(
+            &format_sstr!("file://{path}"),
+            path,
+            FileStat::new(datetime!(2024-01-01 00:00:00 +00:00), size),
+            md5sum,
+            serviceid,
+            FileService::Local,
+            "localhost",
+        )
+    }
+
+    #[test]
+    fn test_match_moves() -> Result<(), Error> {
+        let md5a = "0123456789abcdef0123456789abcdef";
+        let md5b = "fedcba9876543210fedcba9876543210";
+        let md5c = "00000000000000000000000000000000";
+
+        // directory `a` renamed to `b`
+        let deleted = vec![
+            finfo("/data/a/x.txt", 10, Some(md5a), "localhost")?,
+            finfo("/data/a/y.txt", 20, Some(md5b), "localhost")?,
+        ];
+        let new = vec![
+            finfo("/data/b/y.txt", 20, Some(md5b), "localhost")?,
+            finfo("/data/b/x.txt", 10, Some(md5a), "localhost")?,
+        ];
+        assert_eq!(match_moves(&deleted, &new), [(0, 1), (1, 0)]);
+
+        // same size, different content
+        let new = vec![finfo("/data/b/x.txt", 10, Some(md5c), "localhost")?];
+        assert!(match_moves(&deleted, &new).is_empty());
+
+        // two new copies of the same content are ambiguous
+        let new = vec![
+            finfo("/data/b/x.txt", 10, Some(md5a), "localhost")?,
+            finfo("/data/c/x.txt", 10, Some(md5a), "localhost")?,
+        ];
+        assert!(match_moves(&deleted, &new).is_empty());
+
+        // a backend id follows the file even when its content changed
+        let deleted = vec![finfo("/data/a/x.txt", 10, Some(md5a), "1AbC")?];
+        let new = vec![
+            finfo("/data/c/z.txt", 10, Some(md5a), "2DeF")?,
+            finfo("/data/b/x.txt", 12, Some(md5c), "1AbC")?,
+        ];
+        assert_eq!(match_moves(&deleted, &new), [(0, 1)]);
+
+        // nothing to go by without a checksum or id
+        let deleted = vec![finfo("/data/a/x.txt", 10, None, "localhost")?];
+        let new = vec![finfo("/data/b/x.txt", 10, None, "localhost")?];
+        assert!(match_moves(&deleted, &new).is_empty());
+        Ok(())
+    }
+}
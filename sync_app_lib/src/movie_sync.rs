@@ -8,12 +8,22 @@ use rust_decimal::Decimal;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use stack_string::{format_sstr, StackString};
 use std::{collections::HashMap, fmt::Debug};
+use stdout_channel::{MockStdout, StdoutChannel};
 use time::{format_description::well_known::Rfc3339, Date, Duration, OffsetDateTime};
+use url::Url;
 use uuid::Uuid;
 
 use gdrive_lib::date_time_wrapper::DateTimeWrapper;
 
-use crate::{config::Config, sync_client::SyncClient};
+use crate::{
+    cache_edit::CacheFilter,
+    config::Config,
+    file_list::{FileList, FileListTrait},
+    file_sync::FileSync,
+    models::FileSyncCache,
+    pgpool::PgPool,
+    sync_client::SyncClient,
+};
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct ImdbEpisodes {
@@ -117,6 +127,7 @@ impl Default for MusicCollection {
 
 pub struct MovieSync {
     client: SyncClient,
+    config: Config,
 }
 
 impl MovieSync {
@@ -124,13 +135,14 @@ impl MovieSync {
     /// Returns error if creation of client fails
     pub fn new(config: Config) -> Result<Self, Error> {
         Ok(Self {
-            client: SyncClient::new(config, "/usr/bin/movie-queue-cli")?,
+            client: SyncClient::new(config.clone(), "/usr/bin/movie-queue-cli")?,
+            config,
         })
     }
 
     /// # Errors
     /// Return error if db query fails
-    pub async fn run_sync(&self) -> Result<Vec<StackString>, Error> {
+    pub async fn run_sync(&self, pool: &PgPool) -> Result<Vec<StackString>, Error> {
         self.client.init("list", "movie-sync").await?;
         let mut output = Vec::new();
 
@@ -239,11 +251,72 @@ impl MovieSync {
             .await?;
         output.extend_from_slice(&results);
 
+        output.extend(self.sync_artwork(pool).await?);
+
         self.client.shutdown().await?;
 
         Ok(output)
     }
 
+    /// Sync `movie_artwork_local_url` and `movie_artwork_remote_url` in both
+    /// directions with the file sync engine, the copies are made right away
+    /// rather than left in the queue so the images match the metadata synced
+    /// in the same run
+    /// # Errors
+    /// Return error if indexing either side or any copy fails
+    pub async fn sync_artwork(&self, pool: &PgPool) -> Result<Vec<StackString>, Error> {
+        let mut output = Vec::new();
+        let (local, remote): (Url, Url) = match (
+            &self.config.movie_artwork_local_url,
+            &self.config.movie_artwork_remote_url,
+        ) {
+            (Some(local), Some(remote)) => (local.clone().into(), remote.clone().into()),
+            _ => return Ok(output),
+        };
+        let flist0 = FileList::from_url(&local, &self.config, pool).await?;
+        let flist1 = FileList::from_url(&remote, &self.config, pool).await?;
+        for flist in [&flist0, &flist1] {
+            let number_updated = flist.index().await?;
+            debug!("artwork {} updated {number_updated}", flist.get_baseurl());
+        }
+        let violations = FileSync::compare_lists(&(*flist0), &(*flist1), None, pool).await?;
+        output.extend(violations.iter().map(|v| format_sstr!("artwork {v}")));
+
+        // every copy queued by compare_lists has the local side as either
+        // source or destination, the trailing `/` keeps out sibling
+        // directories sharing the name as a prefix
+        let prefix = format_sstr!("{}/", flist0.get_baseurl().as_str().trim_end_matches('/'));
+        let filter = CacheFilter::new(&prefix)?;
+        let ids: Vec<Uuid> = filter
+            .get_matching(pool)
+            .await?
+            .into_iter()
+            .map(|entry| entry.id)
+            .collect();
+        let entries = FileSyncCache::take_by_ids(&ids, pool).await?;
+        output.push(format_sstr!(
+            "artwork {local} <-> {remote} {} copies",
+            entries.len()
+        ));
+        if entries.is_empty() {
+            return Ok(output);
+        }
+        let mock_stdout = MockStdout::new();
+        let stdout = StdoutChannel::with_mock_stdout(mock_stdout.clone(), mock_stdout.clone());
+        let result = FileSync::new(self.config.clone())
+            .process_cache_entries(entries, pool, &stdout)
+            .await;
+        stdout.close().await?;
+        let mut lines = Vec::new();
+        while let Some(line) = mock_stdout.lock().await.pop() {
+            lines.push(format_sstr!("artwork {line}"));
+        }
+        lines.reverse();
+        output.extend(lines);
+        result?;
+        Ok(output)
+    }
+
     async fn run_single_sync_activities<K, T, U>(
         &self,
         path: &str,
@@ -336,14 +409,15 @@ impl MovieSync {
 mod tests {
     use log::debug;
 
-    use crate::{config::Config, movie_sync::MovieSync};
+    use crate::{config::Config, movie_sync::MovieSync, pgpool::PgPool};
 
     #[tokio::test]
     #[ignore]
     async fn test_movie_sync() {
         let config = Config::init_config().unwrap();
+        let pool = PgPool::new(&config.database_url).unwrap();
         let s = MovieSync::new(config).unwrap();
-        let result = s.run_sync().await.unwrap();
+        let result = s.run_sync(&pool).await.unwrap();
         debug!("{:?}", result);
         assert!(result.len() > 0);
     }
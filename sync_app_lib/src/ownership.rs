@@ -0,0 +1,224 @@
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+use stack_string::StackString;
+use std::{collections::HashMap, fmt, str::FromStr};
+use url::Url;
+
+use crate::{models::FileSyncConfig, pgpool::PgPool};
+
+/// Name used as the source of a fallback mapping, e.g. `user:*=nobody`
+const FALLBACK: &str = "*";
+
+/// Owner of a file by name, `user:group`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owner {
+    pub user: StackString,
+    pub group: StackString,
+}
+
+impl FromStr for Owner {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, group) = s
+            .trim()
+            .split_once(':')
+            .ok_or_else(|| format_err!("Invalid owner {s}, expected user:group"))?;
+        if user.is_empty() || group.is_empty() {
+            return Err(format_err!("Invalid owner {s}, expected user:group"));
+        }
+        Ok(Self {
+            user: user.into(),
+            group: group.into(),
+        })
+    }
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.user, self.group)
+    }
+}
+
+/// Name based uid / gid translation for one config, built from entries of
+/// the form `user:<src>=<dst>` or `group:<src>=<dst>`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OwnershipMap {
+    users: HashMap<StackString, StackString>,
+    groups: HashMap<StackString, StackString>,
+}
+
+impl OwnershipMap {
+    /// # Errors
+    /// Return error if a rule can't be parsed
+    pub fn new(rules: &[StackString]) -> Result<Self, Error> {
+        let mut map = Self::default();
+        for rule in rules {
+            let (kind, mapping) = rule
+                .split_once(':')
+                .ok_or_else(|| format_err!("Invalid ownership rule {rule}"))?;
+            let (src, dst) = mapping
+                .split_once('=')
+                .ok_or_else(|| format_err!("Invalid ownership rule {rule}"))?;
+            let names = match kind {
+                "user" => &mut map.users,
+                "group" => &mut map.groups,
+                _ => return Err(format_err!("Invalid ownership rule {rule}")),
+            };
+            names.insert(src.into(), dst.into());
+        }
+        Ok(map)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty() && self.groups.is_empty()
+    }
+
+    /// Map for syncing in the opposite direction, fallbacks don't invert
+    #[must_use]
+    pub fn invert(&self) -> Self {
+        let invert = |names: &HashMap<StackString, StackString>| {
+            names
+                .iter()
+                .filter(|(src, _)| src.as_str() != FALLBACK)
+                .map(|(src, dst)| (dst.clone(), src.clone()))
+                .collect()
+        };
+        Self {
+            users: invert(&self.users),
+            groups: invert(&self.groups),
+        }
+    }
+
+    /// Owners to try on the destination in order: the mapped names, the
+    /// fallback names and finally the unchanged names
+    #[must_use]
+    pub fn translate(&self, owner: &Owner) -> Vec<Owner> {
+        let candidates = |names: &HashMap<StackString, StackString>, name: &StackString| {
+            let mut result: Vec<StackString> = Vec::new();
+            for candidate in [names.get(name), names.get(FALLBACK), Some(name)]
+                .iter()
+                .flatten()
+                .copied()
+            {
+                if !result.contains(candidate) {
+                    result.push(candidate.clone());
+                }
+            }
+            result
+        };
+        let users = candidates(&self.users, &owner.user);
+        let groups = candidates(&self.groups, &owner.group);
+        let mut owners: Vec<Owner> = Vec::new();
+        for i in 0..users.len().max(groups.len()) {
+            let owner = Owner {
+                user: users[i.min(users.len() - 1)].clone(),
+                group: groups[i.min(groups.len() - 1)].clone(),
+            };
+            if !owners.contains(&owner) {
+                owners.push(owner);
+            }
+        }
+        owners
+    }
+}
+
+/// Ownership maps for every config that has one, keyed on the config's
+/// src/dst urls.
+#[derive(Debug, Default, Clone)]
+pub struct OwnershipMaps(Vec<(StackString, StackString, OwnershipMap)>);
+
+impl OwnershipMaps {
+    /// # Errors
+    /// Return error if db query fails or a rule can't be parsed
+    pub async fn from_db(pool: &PgPool) -> Result<Self, Error> {
+        let configs: Vec<FileSyncConfig> = FileSyncConfig::get_config_list(pool)
+            .await?
+            .try_collect()
+            .await?;
+        let mut maps = Vec::new();
+        for conf in configs {
+            let map = OwnershipMap::new(&conf.ownership_map)?;
+            if !map.is_empty() {
+                maps.push((conf.src_url, conf.dst_url, map));
+            }
+        }
+        Ok(Self(maps))
+    }
+
+    /// Map to apply when copying `src` to `dst`, inverted if the copy runs
+    /// from the config's destination back to its source
+    #[must_use]
+    pub fn get(&self, src: &Url, dst: &Url) -> Option<OwnershipMap> {
+        self.0.iter().find_map(|(src_url, dst_url, map)| {
+            let (src, dst) = (src.as_str(), dst.as_str());
+            if src.starts_with(src_url.as_str()) && dst.starts_with(dst_url.as_str()) {
+                Some(map.clone())
+            } else if src.starts_with(dst_url.as_str()) && dst.starts_with(src_url.as_str()) {
+                Some(map.invert())
+            } else {
+                None
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::StackString;
+    use url::Url;
+
+    use crate::ownership::{Owner, OwnershipMap, OwnershipMaps};
+
+    #[test]
+    fn test_ownership_map() -> Result<(), Error> {
+        let rules: Vec<StackString> = vec![
+            "user:ddboline=ubuntu".into(),
+            "user:*=nobody".into(),
+            "group:ddboline=users".into(),
+        ];
+        let map = OwnershipMap::new(&rules)?;
+        let owner: Owner = "ddboline:ddboline".parse()?;
+        let owners: Vec<_> = map.translate(&owner).iter().map(Owner::to_string).collect();
+        assert_eq!(
+            owners,
+            vec!["ubuntu:users", "nobody:ddboline", "ddboline:ddboline"]
+        );
+
+        let owner: Owner = "root:root".parse()?;
+        let owners: Vec<_> = map.translate(&owner).iter().map(Owner::to_string).collect();
+        assert_eq!(owners, vec!["nobody:root", "root:root"]);
+
+        let owner: Owner = "ubuntu:users".parse()?;
+        let owners: Vec<_> = map
+            .invert()
+            .translate(&owner)
+            .iter()
+            .map(Owner::to_string)
+            .collect();
+        assert_eq!(owners, vec!["ddboline:ddboline", "ubuntu:users"]);
+
+        assert!(OwnershipMap::new(&["uid:1000=1001".into()]).is_err());
+        assert!("ddboline".parse::<Owner>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ownership_maps_direction() -> Result<(), Error> {
+        let map = OwnershipMap::new(&["user:ddboline=ubuntu".into()])?;
+        let maps = OwnershipMaps(vec![(
+            "file:///home/ddboline/".into(),
+            "ssh://ubuntu@cloud/home/ubuntu/".into(),
+            map.clone(),
+        )]);
+        let local: Url = "file:///home/ddboline/a.txt".parse()?;
+        let remote: Url = "ssh://ubuntu@cloud/home/ubuntu/a.txt".parse()?;
+        assert_eq!(maps.get(&local, &remote), Some(map.clone()));
+        assert_eq!(maps.get(&remote, &local), Some(map.invert()));
+        let other: Url = "file:///tmp/a.txt".parse()?;
+        assert_eq!(maps.get(&other, &remote), None);
+        Ok(())
+    }
+}
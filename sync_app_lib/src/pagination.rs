@@ -0,0 +1,260 @@
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use std::{fmt, str::FromStr};
+use time::OffsetDateTime;
+
+use crate::{
+    file_list::ListWindow,
+    models::{
+        FileInfoCache, FileSyncCache, FileSyncConfig, GDriveExclusion, RunParameters, SyncConflict,
+    },
+};
+
+/// `--sort` key of the listing actions
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortKey {
+    /// url / path
+    Path,
+    /// file or config name
+    Name,
+    /// modification time, `last_run` of configs, queue time of the cache
+    Mtime,
+    Size,
+}
+
+impl SortKey {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Path => "path",
+            Self::Name => "name",
+            Self::Mtime => "mtime",
+            Self::Size => "size",
+        }
+    }
+}
+
+impl fmt::Display for SortKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "path" | "url" => Ok(Self::Path),
+            "name" => Ok(Self::Name),
+            "mtime" | "time" => Ok(Self::Mtime),
+            "size" => Ok(Self::Size),
+            _ => Err(format_err!(
+                "Invalid sort key {s}, expected path, name, mtime or size"
+            )),
+        }
+    }
+}
+
+/// Value an entry is ordered by, entries of one listing all produce the
+/// same variant.  Text compares byte-wise, independent of the locale, so
+/// successive runs print the same order anywhere
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortValue {
+    Text(StackString),
+    Time(OffsetDateTime),
+    Size(i64),
+}
+
+/// Entries of a listing that `--sort` can order
+pub trait Sortable {
+    /// `None` if the entries can't be ordered by `key`
+    fn sort_value(&self, key: SortKey) -> Option<SortValue>;
+}
+
+impl Sortable for FileInfoCache {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Path => Some(SortValue::Text(self.urlname.clone())),
+            SortKey::Name => Some(SortValue::Text(self.filename.clone())),
+            SortKey::Mtime => Some(SortValue::Time(self.filestat_st_mtime.to_offsetdatetime())),
+            SortKey::Size => Some(SortValue::Size(self.filestat_st_size)),
+        }
+    }
+}
+
+impl Sortable for FileSyncConfig {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Path => Some(SortValue::Text(self.src_url.clone())),
+            SortKey::Name => Some(SortValue::Text(self.name.clone().unwrap_or_default())),
+            SortKey::Mtime => Some(SortValue::Time(self.last_run.to_offsetdatetime())),
+            SortKey::Size => None,
+        }
+    }
+}
+
+impl Sortable for FileSyncCache {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Path => Some(SortValue::Text(self.src_url.clone())),
+            SortKey::Mtime => Some(SortValue::Time(self.created_at.to_offsetdatetime())),
+            SortKey::Name | SortKey::Size => None,
+        }
+    }
+}
+
+impl Sortable for RunParameters {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Name => Some(SortValue::Text(self.action.clone())),
+            SortKey::Mtime => Some(SortValue::Time(self.created_at.to_offsetdatetime())),
+            SortKey::Path | SortKey::Size => None,
+        }
+    }
+}
+
+impl Sortable for SyncConflict {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Path => Some(SortValue::Text(self.src_url.clone())),
+            SortKey::Mtime => Some(SortValue::Time(self.created_at.to_offsetdatetime())),
+            SortKey::Size => Some(SortValue::Size(self.src_size)),
+            SortKey::Name => None,
+        }
+    }
+}
+
+impl Sortable for GDriveExclusion {
+    fn sort_value(&self, key: SortKey) -> Option<SortValue> {
+        match key {
+            SortKey::Name => Some(SortValue::Text(self.filename.clone())),
+            SortKey::Mtime => Some(SortValue::Time(self.created_at.to_offsetdatetime())),
+            SortKey::Path | SortKey::Size => None,
+        }
+    }
+}
+
+/// `--offset/--limit/--sort` of the listing actions (`list`, `show`,
+/// `show_config`, `ser`, `show_runs`, `conflicts`, `exclusions`), parsed
+/// once from `SyncOpts`.  Without a sort key
+/// entries come in the order of their source (`C` collation for queries)
+/// and queries apply the window themselves, with one everything is read,
+/// sorted and then windowed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Pagination {
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    pub sort: Option<SortKey>,
+}
+
+impl Pagination {
+    #[must_use]
+    pub fn new(offset: Option<usize>, limit: Option<usize>, sort: Option<SortKey>) -> Self {
+        Self {
+            offset,
+            limit,
+            sort,
+        }
+    }
+
+    /// Window for listings printed as they stream
+    #[must_use]
+    pub fn window(&self) -> ListWindow {
+        ListWindow::new(self.offset, self.limit)
+    }
+
+    /// Offset / limit a query can apply itself, none when the rows have to
+    /// be sorted first
+    #[must_use]
+    pub fn query_window(&self) -> (Option<usize>, Option<usize>) {
+        if self.sort.is_some() {
+            (None, None)
+        } else {
+            (self.offset, self.limit)
+        }
+    }
+
+    /// Sort `items` by the sort key (kept in order without one) and cut
+    /// out the window
+    /// # Errors
+    /// Return error if the entries can't be sorted by the key
+    pub fn apply<T: Sortable>(&self, mut items: Vec<T>) -> Result<Vec<T>, Error> {
+        if let Some(key) = self.sort {
+            if items.iter().any(|item| item.sort_value(key).is_none()) {
+                return Err(format_err!("Can't sort these entries by {key}"));
+            }
+            items.sort_by_cached_key(|item| item.sort_value(key));
+        }
+        Ok(items
+            .into_iter()
+            .skip(self.offset.unwrap_or(0))
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect())
+    }
+
+    /// `apply` for rows a query already windowed unless they're sorted
+    /// # Errors
+    /// Return error if the entries can't be sorted by the key
+    pub fn apply_queried<T: Sortable>(&self, items: Vec<T>) -> Result<Vec<T>, Error> {
+        if self.sort.is_some() {
+            self.apply(items)
+        } else {
+            Ok(items)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::{macros::datetime, Duration};
+    use uuid::Uuid;
+
+    use crate::{
+        models::FileSyncCache,
+        pagination::{Pagination, SortKey},
+    };
+
+    fn entry(src_url: &str, minutes: i64) -> FileSyncCache {
+        FileSyncCache {
+            id: Uuid::new_v4(),
+            src_url: src_url.into(),
+            dst_url: "s3://test_bucket/".into(),
+            created_at: (datetime!(2024-01-01 00:00:00 +00:00) + Duration::minutes(minutes)).into(),
+            priority: FileSyncCache::PRIORITY_BULK,
+            node_id: None,
+            move_from: None,
+        }
+    }
+
+    #[test]
+    fn test_pagination() -> Result<(), Error> {
+        let entries = || vec![entry("b", 0), entry("c", 2), entry("a", 1)];
+        let urls = |p: Pagination| -> Result<Vec<_>, Error> {
+            Ok(p.apply(entries())?
+                .into_iter()
+                .map(|e| e.src_url.to_string())
+                .collect())
+        };
+        assert_eq!(urls(Pagination::default())?, ["b", "c", "a"]);
+        assert_eq!(urls(Pagination::new(Some(1), Some(1), None))?, ["c"]);
+        assert_eq!(
+            urls(Pagination::new(None, None, Some(SortKey::Path)))?,
+            ["a", "b", "c"]
+        );
+        assert_eq!(
+            urls(Pagination::new(Some(1), None, Some(SortKey::Mtime)))?,
+            ["a", "c"]
+        );
+        assert!(Pagination::new(None, None, Some(SortKey::Size))
+            .apply(entries())
+            .is_err());
+        assert_eq!(
+            Pagination::new(Some(1), Some(2), Some(SortKey::Path)).query_window(),
+            (None, None)
+        );
+        assert_eq!("mtime".parse::<SortKey>()?, SortKey::Mtime);
+        assert!("owner".parse::<SortKey>().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::{
+    future::Future,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tokio::task_local;
+
+task_local! {
+    static PARTIAL: AtomicBool;
+}
+
+/// Run `f`, returning its output and whether it stopped with work left
+/// over, e.g. transfers queued again once `--max-runtime` ran out.  Tasks
+/// spawned by `f` can't mark it partial.
+pub async fn track_partial<F: Future>(f: F) -> (F::Output, bool) {
+    PARTIAL
+        .scope(AtomicBool::new(false), async {
+            let output = f.await;
+            let partial = PARTIAL.with(|p| p.load(Ordering::SeqCst));
+            (output, partial)
+        })
+        .await
+}
+
+/// Mark the run as partial, a no-op outside of `track_partial`
+pub fn mark_partial() {
+    PARTIAL.try_with(|p| p.store(true, Ordering::SeqCst)).ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partial::{mark_partial, track_partial};
+
+    #[tokio::test]
+    async fn test_track_partial() {
+        mark_partial();
+        let (output, partial) = track_partial(async { 1 }).await;
+        assert_eq!(output, 1);
+        assert!(!partial);
+        let ((), partial) = track_partial(async { mark_partial() }).await;
+        assert!(partial);
+        let ((), partial) = track_partial(async {
+            let ((), inner) = track_partial(async { mark_partial() }).await;
+            assert!(inner);
+        })
+        .await;
+        assert!(!partial);
+    }
+}
@@ -0,0 +1,420 @@
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, fmt};
+
+use crate::{file_info::FileInfo, file_service::FileService};
+
+/// Characters windows (and smb / onedrive) refuse in a file name
+const WINDOWS_RESERVED_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+
+/// Device names windows refuses with or without an extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest extension kept when truncating a name
+const MAX_EXTENSION_LEN: usize = 16;
+
+/// Path constraints of a destination backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathRules {
+    /// limit on the whole path / key
+    pub max_path: usize,
+    /// limit on each path component
+    pub max_name: usize,
+    /// limits count utf-8 bytes rather than characters
+    pub count_bytes: bool,
+    /// reject reserved characters, device names and trailing dots / spaces
+    pub windows: bool,
+    /// names differing only in case refer to the same file
+    pub case_insensitive: bool,
+}
+
+impl PathRules {
+    /// Rules for `servicetype`, local targets only get the windows rules
+    /// when `windows_safe_paths` is set
+    #[must_use]
+    pub fn for_service(servicetype: FileService, windows_safe_paths: bool) -> Option<Self> {
+        let unix = Self {
+            max_path: 4096,
+            max_name: 255,
+            count_bytes: true,
+            windows: false,
+            case_insensitive: false,
+        };
+        let windows = Self {
+            max_path: 260,
+            max_name: 255,
+            count_bytes: false,
+            windows: true,
+            case_insensitive: true,
+        };
+        match servicetype {
+            FileService::Local if windows_safe_paths => Some(windows),
+            // APFS / HFS+ are case-insensitive by default
+            FileService::Local => Some(Self {
+                case_insensitive: cfg!(target_os = "macos"),
+                ..unix
+            }),
+            FileService::SSH | FileService::SFTP => Some(unix),
+            FileService::SMB => Some(windows),
+            FileService::OneDrive => Some(Self {
+                max_path: 400,
+                ..windows
+            }),
+            FileService::S3 | FileService::GCS => Some(Self {
+                max_path: 1024,
+                max_name: 1024,
+                count_bytes: true,
+                windows: false,
+                case_insensitive: false,
+            }),
+            FileService::GDrive => Some(Self {
+                max_path: usize::MAX,
+                max_name: 255,
+                count_bytes: false,
+                windows: false,
+                case_insensitive: true,
+            }),
+            FileService::Extension(_) => None,
+        }
+    }
+
+    fn len(&self, s: &str) -> usize {
+        if self.count_bytes {
+            s.len()
+        } else {
+            s.chars().count()
+        }
+    }
+
+    fn unit(&self) -> &'static str {
+        if self.count_bytes {
+            "bytes"
+        } else {
+            "characters"
+        }
+    }
+
+    /// Every problem with `path`, empty if it's valid
+    #[must_use]
+    pub fn check(&self, path: &str) -> Vec<StackString> {
+        let mut reasons = Vec::new();
+        let length = self.len(path);
+        if length > self.max_path {
+            reasons.push(format_sstr!(
+                "path is {length} {}, limit {}",
+                self.unit(),
+                self.max_path
+            ));
+        }
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            let length = self.len(name);
+            if length > self.max_name {
+                reasons.push(format_sstr!(
+                    "name {name} is {length} {}, limit {}",
+                    self.unit(),
+                    self.max_name
+                ));
+            }
+            if name.chars().any(char::is_control) {
+                reasons.push(format_sstr!("name {name:?} contains control characters"));
+            }
+            if self.windows {
+                if name.contains(WINDOWS_RESERVED_CHARS) {
+                    reasons.push(format_sstr!("name {name} contains reserved characters"));
+                }
+                if name.ends_with('.') || name.ends_with(' ') {
+                    reasons.push(format_sstr!("name {name} ends with a dot or space"));
+                }
+                if is_reserved_name(name) {
+                    reasons.push(format_sstr!("name {name} is a reserved device name"));
+                }
+            }
+        }
+        reasons
+    }
+
+    /// Closest valid path, `None` if shortening the file name isn't enough
+    #[must_use]
+    pub fn suggest(&self, path: &str) -> Option<StackString> {
+        let names: Vec<String> = path
+            .split('/')
+            .map(|name| {
+                if name.is_empty() {
+                    String::new()
+                } else {
+                    self.sanitize_name(name).to_string()
+                }
+            })
+            .collect();
+        let mut suggestion = names.join("/");
+        let excess = self.len(&suggestion).saturating_sub(self.max_path);
+        if excess > 0 {
+            let (dir, name) = suggestion.rsplit_once('/').unwrap_or(("", &suggestion));
+            let limit = self.len(name).checked_sub(excess).filter(|l| *l > 0)?;
+            let name = self.truncate_name(name, limit);
+            suggestion = if dir.is_empty() && !suggestion.contains('/') {
+                name.to_string()
+            } else {
+                format!("{dir}/{name}")
+            };
+        }
+        if self.check(&suggestion).is_empty() {
+            Some(suggestion.into())
+        } else {
+            None
+        }
+    }
+
+    fn sanitize_name(&self, name: &str) -> StackString {
+        let mut name: String = name
+            .chars()
+            .map(|c| {
+                if c.is_control() || (self.windows && WINDOWS_RESERVED_CHARS.contains(&c)) {
+                    '_'
+                } else {
+                    c
+                }
+            })
+            .collect();
+        if self.windows {
+            while name.ends_with('.') || name.ends_with(' ') {
+                name.pop();
+            }
+            if name.is_empty() {
+                name.push('_');
+            }
+            if is_reserved_name(&name) {
+                name = match name.split_once('.') {
+                    Some((stem, ext)) => format!("{stem}_.{ext}"),
+                    None => format!("{name}_"),
+                };
+            }
+        }
+        self.truncate_name(&name, self.max_name)
+    }
+
+    /// Shorten the stem of `name`, keeping a short extension
+    fn truncate_name(&self, name: &str, limit: usize) -> StackString {
+        if self.len(name) <= limit {
+            return name.into();
+        }
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() && ext.len() <= MAX_EXTENSION_LEN => {
+                (stem, format_sstr!(".{ext}"))
+            }
+            _ => (name, StackString::new()),
+        };
+        let mut stem: String = stem.into();
+        while !stem.is_empty() && self.len(&stem) + self.len(&ext) > limit {
+            stem.pop();
+        }
+        format_sstr!("{stem}{ext}")
+    }
+}
+
+fn is_reserved_name(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// A queued copy whose destination the target backend would reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathViolation {
+    pub src_url: StackString,
+    pub dst_url: StackString,
+    pub reasons: Vec<StackString>,
+    pub suggestion: Option<StackString>,
+}
+
+impl PathViolation {
+    /// Validate the destination of copying `src` to `dst`
+    #[must_use]
+    pub fn check(src: &FileInfo, dst: &FileInfo, windows_safe_paths: bool) -> Option<Self> {
+        let rules = PathRules::for_service(dst.servicetype, windows_safe_paths)?;
+        let path = dst.filepath.to_string_lossy();
+        let path = match dst.servicetype {
+            FileService::S3 | FileService::GCS => path.trim_start_matches('/'),
+            _ => &*path,
+        };
+        let reasons = rules.check(path);
+        if reasons.is_empty() {
+            None
+        } else {
+            Some(Self {
+                src_url: src.urlname.as_str().into(),
+                dst_url: dst.urlname.as_str().into(),
+                reasons,
+                suggestion: rules.suggest(path),
+            })
+        }
+    }
+}
+
+/// Queued copies into a case-insensitive destination that would overwrite a
+/// different file: one already at the destination, or another queued copy,
+/// whose path differs only in case.  Returns the index of each colliding copy
+/// in `copies`, which need a rename before they can be copied.
+#[must_use]
+pub fn case_collisions<'a>(
+    copies: &[(FileInfo, FileInfo)],
+    existing: impl IntoIterator<Item = &'a str>,
+    windows_safe_paths: bool,
+) -> Vec<(usize, PathViolation)> {
+    let existing: HashMap<StackString, StackString> = existing
+        .into_iter()
+        .map(|url| (url.to_lowercase().into(), url.into()))
+        .collect();
+    let mut queued: HashMap<StackString, Vec<usize>> = HashMap::new();
+    for (index, (_, dst)) in copies.iter().enumerate() {
+        let case_insensitive = PathRules::for_service(dst.servicetype, windows_safe_paths)
+            .map_or(false, |rules| rules.case_insensitive);
+        if case_insensitive {
+            queued
+                .entry(dst.urlname.as_str().to_lowercase().into())
+                .or_default()
+                .push(index);
+        }
+    }
+    let mut collisions = Vec::new();
+    for (key, indexes) in queued {
+        for &index in &indexes {
+            let (src, dst) = &copies[index];
+            let dst_url = dst.urlname.as_str();
+            let other = match existing.get(&key) {
+                Some(url) if url == dst_url => continue,
+                Some(url) => Some(url.clone()),
+                None => indexes
+                    .iter()
+                    .map(|i| copies[*i].1.urlname.as_str())
+                    .find(|url| *url != dst_url)
+                    .map(Into::into),
+            };
+            if let Some(other) = other {
+                collisions.push((
+                    index,
+                    PathViolation {
+                        src_url: src.urlname.as_str().into(),
+                        dst_url: dst_url.into(),
+                        reasons: vec![format_sstr!(
+                            "collides with {other}, names differ only in case"
+                        )],
+                        suggestion: None,
+                    },
+                ));
+            }
+        }
+    }
+    collisions.sort_by_key(|(index, _)| *index);
+    collisions
+}
+
+impl fmt::Display for PathViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "invalid destination {} <- {}: {}",
+            self.dst_url,
+            self.src_url,
+            self.reasons
+                .iter()
+                .map(StackString::as_str)
+                .collect::<Vec<_>>()
+                .join("; ")
+        )?;
+        match &self.suggestion {
+            Some(suggestion) => write!(f, ", suggested rename {suggestion}"),
+            None => write!(f, ", no rename found"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::{
+        file_info::FileInfo,
+        file_service::FileService,
+        path_validation::{case_collisions, PathRules},
+    };
+
+    #[test]
+    fn test_windows_rules() {
+        let rules = PathRules::for_service(FileService::Local, true).unwrap();
+        assert!(rules.check("/home/ddboline/notes.txt").is_empty());
+        let path = "/home/ddboline/what?: a day.txt /con.tar.gz";
+        assert_eq!(rules.check(path).len(), 3);
+        assert_eq!(
+            rules.suggest(path).as_deref(),
+            Some("/home/ddboline/what__ a day.txt/con_.tar.gz")
+        );
+
+        let long = format!("/home/ddboline/{}.pdf", "a".repeat(300));
+        assert_eq!(rules.check(&long).len(), 2);
+        let suggestion = rules.suggest(&long).unwrap();
+        assert_eq!(suggestion.chars().count(), 260);
+        assert!(suggestion.ends_with("aa.pdf"));
+
+        let rules = PathRules::for_service(FileService::Local, false).unwrap();
+        assert!(rules.check("/home/ddboline/what?.txt").is_empty());
+    }
+
+    #[test]
+    fn test_key_rules() {
+        let rules = PathRules::for_service(FileService::S3, false).unwrap();
+        let key = format!("photos/{}.jpg", "é".repeat(600));
+        assert_eq!(rules.check(&key).len(), 2);
+        let suggestion = rules.suggest(&key).unwrap();
+        assert!(suggestion.len() <= 1024);
+        assert!(suggestion.starts_with("photos/é"));
+        assert!(suggestion.ends_with(".jpg"));
+        assert!(rules.check("photos/what?.jpg").is_empty());
+        assert!(PathRules::for_service(FileService::Extension("ipfs"), false).is_none());
+    }
+
+    #[test]
+    fn test_case_collisions() -> Result<(), Error> {
+        let copy = |src: &str, dst: &str| -> Result<(FileInfo, FileInfo), Error> {
+            let src: Url = src.parse()?;
+            let dst: Url = dst.parse()?;
+            Ok((FileInfo::from_url(&src)?, FileInfo::from_url(&dst)?))
+        };
+        let copies = vec![
+            copy(
+                "file:///data/Notes.txt",
+                "gdrive://me@gmail.com/My%20Drive/Notes.txt",
+            )?,
+            copy(
+                "file:///data/notes.txt",
+                "gdrive://me@gmail.com/My%20Drive/notes.txt",
+            )?,
+            copy(
+                "file:///data/a.txt",
+                "gdrive://me@gmail.com/My%20Drive/a.txt",
+            )?,
+            copy(
+                "file:///data/B.txt",
+                "gdrive://me@gmail.com/My%20Drive/B.txt",
+            )?,
+            copy("file:///data/c.txt", "s3://bucket/c.txt")?,
+            copy("file:///data/C.txt", "s3://bucket/C.txt")?,
+        ];
+        let existing = [
+            "gdrive://me@gmail.com/My%20Drive/a.txt",
+            "gdrive://me@gmail.com/My%20Drive/b.txt",
+        ];
+        let collisions = case_collisions(&copies, existing.iter().copied(), false);
+        let indexes: Vec<_> = collisions.iter().map(|(i, _)| *i).collect();
+        assert_eq!(indexes, vec![0, 1, 3]);
+        assert_eq!(
+            collisions[2].1.reasons[0].as_str(),
+            "collides with gdrive://me@gmail.com/My%20Drive/b.txt, names differ only in case"
+        );
+        Ok(())
+    }
+}
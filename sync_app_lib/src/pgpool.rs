@@ -29,7 +29,7 @@ impl PgPool {
 
         let mut config = Config::default();
 
-        if let tokio_postgres::config::Host::Tcp(s) = &pgconf.get_hosts()[0] {
+        if let Some(tokio_postgres::config::Host::Tcp(s)) = pgconf.get_hosts().first() {
             config.host.replace(s.to_string());
         }
         if let Some(u) = pgconf.get_user() {
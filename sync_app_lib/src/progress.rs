@@ -0,0 +1,138 @@
+use once_cell::sync::Lazy;
+use stack_string::StackString;
+use std::{fmt, io::Write, time::Duration};
+use tokio::{sync::watch, time::sleep};
+
+use crate::usage_trend::format_size;
+
+static PROGRESS: Lazy<ProgressChannel> = Lazy::new(ProgressChannel::new);
+
+/// Counters of everything indexed and transferred by this process so far
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub files_scanned: u64,
+    pub files_transferred: u64,
+    pub bytes_transferred: u64,
+    /// Url being indexed or copied most recently
+    pub current: Option<StackString>,
+}
+
+impl fmt::Display for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "scanned {} files, transferred {} files ({})",
+            self.files_scanned,
+            self.files_transferred,
+            format_size(self.bytes_transferred as i64),
+        )?;
+        if let Some(current) = &self.current {
+            write!(f, " {current}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Where index and sync runs report progress, updates are cheap and simply
+/// overwrite the counters when nobody is watching
+pub struct ProgressChannel {
+    sender: watch::Sender<Progress>,
+}
+
+impl fmt::Debug for ProgressChannel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProgressChannel")
+    }
+}
+
+impl Default for ProgressChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressChannel {
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(Progress::default());
+        Self { sender }
+    }
+
+    /// The channel shared by the whole process
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &PROGRESS
+    }
+
+    pub fn scanned(&self, files: usize) {
+        if files > 0 {
+            self.sender.send_modify(|p| p.files_scanned += files as u64);
+        }
+    }
+
+    pub fn current(&self, url: &str) {
+        self.sender.send_modify(|p| p.current = Some(url.into()));
+    }
+
+    pub fn transferred(&self, bytes: i64) {
+        self.sender.send_modify(|p| {
+            p.files_transferred += 1;
+            p.bytes_transferred += bytes.max(0) as u64;
+        });
+    }
+
+    #[must_use]
+    pub fn get(&self) -> Progress {
+        self.sender.borrow().clone()
+    }
+
+    #[must_use]
+    pub fn subscribe(&self) -> watch::Receiver<Progress> {
+        self.sender.subscribe()
+    }
+}
+
+/// Redraw a one line counter on stderr at most once per `interval` while
+/// progress is being made, runs until the task is aborted
+pub async fn render_progress(interval: Duration) {
+    let mut receiver = ProgressChannel::global().subscribe();
+    while receiver.changed().await.is_ok() {
+        let progress = receiver.borrow_and_update().clone();
+        let mut stderr = std::io::stderr();
+        let _ = write!(stderr, "\r{progress}\x1b[K");
+        let _ = stderr.flush();
+        sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::progress::{Progress, ProgressChannel};
+
+    #[tokio::test]
+    async fn test_progress_channel() {
+        let channel = ProgressChannel::new();
+        let mut receiver = channel.subscribe();
+        channel.scanned(0);
+        assert!(!receiver.has_changed().unwrap());
+        channel.scanned(10);
+        channel.current("s3://bucket/key");
+        channel.transferred(2048);
+        assert!(receiver.has_changed().unwrap());
+        let progress = receiver.borrow_and_update().clone();
+        assert_eq!(
+            progress,
+            Progress {
+                files_scanned: 10,
+                files_transferred: 1,
+                bytes_transferred: 2048,
+                current: Some("s3://bucket/key".into()),
+            }
+        );
+        assert_eq!(
+            progress.to_string(),
+            "scanned 10 files, transferred 1 files (2.0 KiB) s3://bucket/key"
+        );
+        assert_eq!(channel.get(), progress);
+    }
+}
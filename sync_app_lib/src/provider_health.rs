@@ -0,0 +1,330 @@
+use anyhow::Error;
+use log::{info, warn};
+use parking_lot::Mutex;
+use std::{
+    collections::HashMap,
+    fmt,
+    time::{Duration, Instant},
+};
+use tokio::time::sleep;
+
+use crate::{config::Config, file_service::FileService};
+
+/// How long a transfer waits before checking again for a free slot
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Errors that mean the provider itself is struggling (5xx, throttling), as
+/// opposed to errors about the file being transferred, matched in lower
+/// case with spaces and underscores removed
+const SERVER_ERRORS: [&str; 12] = [
+    "500internalservererror",
+    "502badgateway",
+    "503serviceunavailable",
+    "504gatewaytimeout",
+    "429toomanyrequests",
+    "internalerror",
+    "serviceunavailable",
+    "slowdown",
+    "throttl",
+    "ratelimitexceeded",
+    "backenderror",
+    "requesttimeout",
+];
+
+/// True if `e` (or anything in its chain) looks like a provider side error
+#[must_use]
+pub fn is_server_error(e: &Error) -> bool {
+    e.chain().any(|cause| {
+        let message = cause.to_string().to_lowercase().replace(['_', ' '], "");
+        SERVER_ERRORS
+            .iter()
+            .any(|pattern| message.contains(pattern))
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct BackendState {
+    limit: usize,
+    in_flight: usize,
+    consecutive_errors: usize,
+    successes: usize,
+    open_until: Option<Instant>,
+}
+
+/// Circuit breaker per remote backend: every server error halves the number
+/// of concurrent transfers allowed, `error_threshold` server errors in a
+/// row stop all transfers for `cooldown`, each `limit` successes in a row
+/// allow one more concurrent transfer, up to `max_concurrency` or the limit
+/// set for that backend
+pub struct ProviderHealth {
+    error_threshold: usize,
+    cooldown: Duration,
+    max_concurrency: usize,
+    limits: HashMap<FileService, usize>,
+    backends: Mutex<HashMap<FileService, BackendState>>,
+}
+
+impl Default for ProviderHealth {
+    fn default() -> Self {
+        Self::new(5, Duration::from_secs(30), 16)
+    }
+}
+
+impl fmt::Debug for ProviderHealth {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ProviderHealth")
+    }
+}
+
+impl ProviderHealth {
+    #[must_use]
+    pub fn new(error_threshold: usize, cooldown: Duration, max_concurrency: usize) -> Self {
+        Self {
+            error_threshold: error_threshold.max(1),
+            cooldown,
+            max_concurrency: max_concurrency.max(1),
+            limits: HashMap::new(),
+            backends: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Allow at most `max_concurrency` concurrent transfers on `service`
+    #[must_use]
+    pub fn with_limit(mut self, service: FileService, max_concurrency: usize) -> Self {
+        self.limits.insert(service, max_concurrency.max(1));
+        self
+    }
+
+    /// Limits of `[transfers.<service>]` sections naming an unknown service
+    /// are ignored with a warning
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        let mut health = Self::new(
+            config.provider_error_threshold,
+            Duration::from_secs(config.provider_cooldown_secs),
+            config.provider_max_concurrency,
+        );
+        for name in config.backends.transfers.keys() {
+            match name.parse() {
+                Ok(service) => health = health.with_limit(service, config.max_transfers(name)),
+                Err(_) => warn!("no such service {name} in [transfers]"),
+            }
+        }
+        health
+    }
+
+    fn max_concurrency(&self, service: FileService) -> usize {
+        self.limits
+            .get(&service)
+            .copied()
+            .unwrap_or(self.max_concurrency)
+    }
+
+    fn initial_state(&self, service: FileService) -> BackendState {
+        BackendState {
+            limit: self.max_concurrency(service),
+            in_flight: 0,
+            consecutive_errors: 0,
+            successes: 0,
+            open_until: None,
+        }
+    }
+
+    /// Wait until `service` accepts another transfer, local transfers are
+    /// never throttled
+    pub async fn acquire(&self, service: FileService) -> HealthPermit<'_> {
+        if service == FileService::Local {
+            return HealthPermit {
+                health: self,
+                service: None,
+            };
+        }
+        loop {
+            let wait = {
+                let mut backends = self.backends.lock();
+                let state = backends
+                    .entry(service)
+                    .or_insert_with(|| self.initial_state(service));
+                let now = Instant::now();
+                match state.open_until {
+                    Some(until) if until > now => until - now,
+                    _ => {
+                        state.open_until = None;
+                        if state.in_flight < state.limit {
+                            state.in_flight += 1;
+                            return HealthPermit {
+                                health: self,
+                                service: Some(service),
+                            };
+                        }
+                        POLL_INTERVAL
+                    }
+                }
+            };
+            sleep(wait).await;
+        }
+    }
+
+    /// Record the outcome of a transfer on `service`
+    pub fn record(&self, service: FileService, error: Option<&Error>) {
+        if service == FileService::Local {
+            return;
+        }
+        let mut backends = self.backends.lock();
+        let state = backends
+            .entry(service)
+            .or_insert_with(|| self.initial_state(service));
+        match error {
+            Some(e) if is_server_error(e) => {
+                state.successes = 0;
+                state.consecutive_errors += 1;
+                state.limit = (state.limit / 2).max(1);
+                if state.consecutive_errors >= self.error_threshold {
+                    warn!(
+                        "{service} returned {} errors in a row, pausing transfers for {}s",
+                        state.consecutive_errors,
+                        self.cooldown.as_secs()
+                    );
+                    state.consecutive_errors = 0;
+                    state.open_until = Some(Instant::now() + self.cooldown);
+                }
+            }
+            Some(_) => {}
+            None => {
+                state.consecutive_errors = 0;
+                state.successes += 1;
+                let max_concurrency = self.max_concurrency(service);
+                if state.successes >= state.limit && state.limit < max_concurrency {
+                    state.successes = 0;
+                    state.limit += 1;
+                    if state.limit == max_concurrency {
+                        info!("{service} recovered, {} concurrent transfers", state.limit);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Concurrent transfers currently allowed on `service`
+    #[must_use]
+    pub fn limit(&self, service: FileService) -> usize {
+        self.backends
+            .lock()
+            .get(&service)
+            .map_or_else(|| self.max_concurrency(service), |state| state.limit)
+    }
+
+    /// True while transfers on `service` are paused
+    #[must_use]
+    pub fn is_open(&self, service: FileService) -> bool {
+        self.backends
+            .lock()
+            .get(&service)
+            .and_then(|state| state.open_until)
+            .map_or(false, |until| until > Instant::now())
+    }
+}
+
+/// A transfer slot of `ProviderHealth`, released on drop
+pub struct HealthPermit<'a> {
+    health: &'a ProviderHealth,
+    service: Option<FileService>,
+}
+
+impl Drop for HealthPermit<'_> {
+    fn drop(&mut self) {
+        if let Some(service) = self.service {
+            if let Some(state) = self.health.backends.lock().get_mut(&service) {
+                state.in_flight = state.in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::format_err;
+    use std::time::Duration;
+
+    use crate::{
+        file_service::FileService,
+        provider_health::{is_server_error, ProviderHealth},
+    };
+
+    #[test]
+    fn test_is_server_error() {
+        assert!(is_server_error(&format_err!(
+            "Failed to download abc: 503 Service Unavailable"
+        )));
+        assert!(is_server_error(
+            &format_err!("SlowDown: Please reduce your request rate").context("service error")
+        ));
+        assert!(is_server_error(&format_err!("userRateLimitExceeded")));
+        assert!(!is_server_error(&format_err!("File doesn't exist")));
+        assert!(!is_server_error(&format_err!("404 Not Found")));
+    }
+
+    #[test]
+    fn test_provider_health_backoff() {
+        let health = ProviderHealth::new(3, Duration::from_secs(60), 8);
+        let s3 = FileService::S3;
+        let error = format_err!("503 Service Unavailable");
+        health.record(s3, Some(&error));
+        assert_eq!(health.limit(s3), 4);
+        health.record(s3, Some(&format_err!("File doesn't exist")));
+        assert_eq!(health.limit(s3), 4);
+        assert!(!health.is_open(s3));
+        health.record(s3, Some(&error));
+        health.record(s3, Some(&error));
+        assert_eq!(health.limit(s3), 1);
+        assert!(health.is_open(s3));
+        assert!(!health.is_open(FileService::GDrive));
+
+        // one success per allowed transfer ramps back up by one
+        health.record(s3, None);
+        assert_eq!(health.limit(s3), 2);
+        health.record(s3, None);
+        assert_eq!(health.limit(s3), 2);
+        health.record(s3, None);
+        assert_eq!(health.limit(s3), 3);
+
+        health.record(FileService::Local, Some(&error));
+        assert_eq!(health.limit(FileService::Local), 8);
+    }
+
+    #[tokio::test]
+    async fn test_provider_health_acquire() {
+        let health = ProviderHealth::new(5, Duration::from_secs(60), 2);
+        let gdrive = FileService::GDrive;
+        let first = health.acquire(gdrive).await;
+        let _second = health.acquire(gdrive).await;
+        assert!(
+            tokio::time::timeout(Duration::from_millis(250), health.acquire(gdrive))
+                .await
+                .is_err()
+        );
+        drop(first);
+        let _third = health.acquire(gdrive).await;
+    }
+
+    #[tokio::test]
+    async fn test_provider_health_service_limit() {
+        let health =
+            ProviderHealth::new(5, Duration::from_secs(60), 8).with_limit(FileService::GDrive, 1);
+        assert_eq!(health.limit(FileService::GDrive), 1);
+        assert_eq!(health.limit(FileService::S3), 8);
+        let _first = health.acquire(FileService::GDrive).await;
+        assert!(tokio::time::timeout(
+            Duration::from_millis(250),
+            health.acquire(FileService::GDrive)
+        )
+        .await
+        .is_err());
+        let _s3 = health.acquire(FileService::S3).await;
+
+        // ramping back up after errors stops at the backend's own limit
+        health.record(FileService::GDrive, None);
+        health.record(FileService::GDrive, None);
+        assert_eq!(health.limit(FileService::GDrive), 1);
+    }
+}
@@ -0,0 +1,229 @@
+use log::warn;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+static QUERY_STATS: Lazy<QueryStats> = Lazy::new(QueryStats::default);
+
+/// Queries taking at least this long are logged unless `slow_query_ms` says
+/// otherwise
+pub const DEFAULT_SLOW_QUERY_MS: u64 = 500;
+
+/// Number of rows a query returned or touched, `None` when it isn't known
+/// up front (streamed results)
+pub trait RowCount {
+    fn row_count(&self) -> Option<u64>;
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> Option<u64> {
+        Some(self.len() as u64)
+    }
+}
+
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> Option<u64> {
+        Some(u64::from(self.is_some()))
+    }
+}
+
+impl RowCount for u64 {
+    fn row_count(&self) -> Option<u64> {
+        Some(*self)
+    }
+}
+
+/// Totals for one named query since the process started
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueryStat {
+    pub calls: u64,
+    pub slow_calls: u64,
+    pub rows: u64,
+    pub total: Duration,
+    pub max: Duration,
+}
+
+impl QueryStat {
+    #[must_use]
+    pub fn mean(&self) -> Duration {
+        if self.calls == 0 {
+            Duration::ZERO
+        } else {
+            self.total / self.calls as u32
+        }
+    }
+}
+
+/// Timing of every query of the models layer, keyed by `Type::method`
+pub struct QueryStats {
+    slow_threshold_ms: AtomicU64,
+    stats: Mutex<HashMap<&'static str, QueryStat>>,
+}
+
+impl Default for QueryStats {
+    fn default() -> Self {
+        Self {
+            slow_threshold_ms: AtomicU64::new(DEFAULT_SLOW_QUERY_MS),
+            stats: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl fmt::Debug for QueryStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("QueryStats")
+    }
+}
+
+impl QueryStats {
+    #[must_use]
+    pub fn global() -> &'static Self {
+        &QUERY_STATS
+    }
+
+    pub fn set_slow_threshold(&self, threshold: Duration) {
+        self.slow_threshold_ms
+            .store(threshold.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn slow_threshold(&self) -> Duration {
+        Duration::from_millis(self.slow_threshold_ms.load(Ordering::Relaxed))
+    }
+
+    /// Add one call of `name`, a call slower than the threshold is logged
+    pub fn record(&self, name: &'static str, elapsed: Duration, rows: Option<u64>) {
+        let slow = elapsed >= self.slow_threshold();
+        if slow {
+            match rows {
+                Some(rows) => warn!("slow query {name} took {elapsed:?}, {rows} rows"),
+                None => warn!("slow query {name} took {elapsed:?}"),
+            }
+        }
+        let mut stats = self.stats.lock();
+        let stat = stats.entry(name).or_default();
+        stat.calls += 1;
+        stat.rows += rows.unwrap_or(0);
+        stat.total += elapsed;
+        stat.max = stat.max.max(elapsed);
+        if slow {
+            stat.slow_calls += 1;
+        }
+    }
+
+    /// Totals of every query seen so far, slowest in total first
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<(&'static str, QueryStat)> {
+        let mut stats: Vec<_> = self.stats.lock().iter().map(|(k, v)| (*k, *v)).collect();
+        stats.sort_by(|(n0, s0), (n1, s1)| s1.total.cmp(&s0.total).then(n0.cmp(n1)));
+        stats
+    }
+
+    /// One line per query, logged at the end of a run and served on
+    /// `/sync/query_stats`
+    #[must_use]
+    pub fn report(&self) -> Vec<StackString> {
+        self.snapshot()
+            .into_iter()
+            .map(|(name, stat)| {
+                format_sstr!(
+                    "{name} calls {} slow {} rows {} total {:?} mean {:?} max {:?}",
+                    stat.calls,
+                    stat.slow_calls,
+                    stat.rows,
+                    stat.total,
+                    stat.mean(),
+                    stat.max,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Time the query `name` (`Type::method`), the number of rows comes from the
+/// result
+/// # Errors
+/// Return the error of `query` unchanged
+pub async fn timed<T, E, F>(name: &'static str, query: F) -> Result<T, E>
+where
+    T: RowCount,
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = query.await;
+    let rows = result.as_ref().ok().and_then(RowCount::row_count);
+    QueryStats::global().record(name, start.elapsed(), rows);
+    result
+}
+
+/// Time a `fetch_one`, always one row
+/// # Errors
+/// Return the error of `query` unchanged
+pub async fn timed_one<T, E, F>(name: &'static str, query: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = query.await;
+    QueryStats::global().record(name, start.elapsed(), result.as_ref().ok().map(|_| 1));
+    result
+}
+
+/// Time a `fetch_streaming` up to the first row, the rows are consumed by
+/// the caller and aren't counted
+/// # Errors
+/// Return the error of `query` unchanged
+pub async fn timed_stream<T, E, F>(name: &'static str, query: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let result = query.await;
+    QueryStats::global().record(name, start.elapsed(), None);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::time::Duration;
+
+    use crate::query_stats::{timed, timed_one, QueryStats};
+
+    #[tokio::test]
+    async fn test_timed() -> Result<(), Error> {
+        let rows: Vec<i32> = timed("Test::fetch", async { Ok::<_, Error>(vec![1, 2, 3]) }).await?;
+        assert_eq!(rows.len(), 3);
+        timed("Test::fetch", async { Ok::<_, Error>(Some(1)) }).await?;
+        timed_one("Test::count", async { Ok::<_, Error>((5_i64,)) }).await?;
+        assert!(
+            timed("Test::fail", async { Err::<u64, _>(Error::msg("fail")) })
+                .await
+                .is_err()
+        );
+
+        let stats = QueryStats::global().snapshot();
+        let (_, fetch) = stats.iter().find(|(n, _)| *n == "Test::fetch").unwrap();
+        assert_eq!(fetch.calls, 2);
+        assert_eq!(fetch.rows, 4);
+        let (_, fail) = stats.iter().find(|(n, _)| *n == "Test::fail").unwrap();
+        assert_eq!(fail.rows, 0);
+
+        let stats = QueryStats::default();
+        stats.set_slow_threshold(Duration::from_millis(10));
+        stats.record("Test::slow", Duration::from_millis(20), Some(1));
+        stats.record("Test::slow", Duration::from_millis(1), Some(1));
+        let (_, slow) = stats.snapshot()[0];
+        assert_eq!(slow.slow_calls, 1);
+        assert_eq!(slow.max, Duration::from_millis(20));
+        assert_eq!(stats.report().len(), 1);
+        Ok(())
+    }
+}
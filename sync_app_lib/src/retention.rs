@@ -0,0 +1,247 @@
+use anyhow::{format_err, Error};
+use postgres_query::query;
+use stack_string::{format_sstr, StackString};
+use std::{fmt, str::FromStr};
+use stdout_channel::StdoutChannel;
+use time::{Duration, OffsetDateTime};
+
+use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+
+use crate::{
+    config::{Config, RetentionSection},
+    pgpool::PgPool,
+    query_stats::{timed, timed_one},
+};
+
+/// Auxiliary sync whose rows accumulate in postgres
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionService {
+    /// `weather_data`, aged by `created_at`
+    Weather,
+    /// `intrusion_log`, aged by `datetime`
+    Security,
+}
+
+impl RetentionService {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Weather => "weather",
+            Self::Security => "security",
+        }
+    }
+
+    #[must_use]
+    pub fn table(self) -> &'static str {
+        match self {
+            Self::Weather => "weather_data",
+            Self::Security => "intrusion_log",
+        }
+    }
+}
+
+impl fmt::Display for RetentionService {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for RetentionService {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "weather" => Ok(Self::Weather),
+            "security" => Ok(Self::Security),
+            _ => Err(format_err!("Invalid retention service {s}")),
+        }
+    }
+}
+
+/// Rows of `service` to keep: nothing older than `max_age_days` and at most
+/// the newest `max_rows`, either limit may be unset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub service: RetentionService,
+    pub max_age_days: Option<i64>,
+    pub max_rows: Option<i64>,
+}
+
+impl RetentionPolicy {
+    /// # Errors
+    /// Return error if the section names an unknown service
+    pub fn new(service: &str, section: &RetentionSection) -> Result<Self, Error> {
+        Ok(Self {
+            service: service.parse()?,
+            max_age_days: section.max_age_days,
+            max_rows: section.max_rows,
+        })
+    }
+
+    #[must_use]
+    pub fn cutoff(&self, now: OffsetDateTime) -> Option<DateTimeWrapper> {
+        self.max_age_days
+            .map(|days| DateTimeWrapper::from_offsetdatetime(now - Duration::days(days)))
+    }
+
+    /// Number of rows the policy would remove
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn count_expired(&self, pool: &PgPool) -> Result<i64, Error> {
+        let cutoff = self.cutoff(OffsetDateTime::now_utc());
+        let max_rows = self.max_rows;
+        let conn = pool.get().await?;
+        let (count,) = match self.service {
+            RetentionService::Weather => {
+                let query = query!(
+                    r#"
+                        SELECT count(*) FROM weather_data
+                        WHERE created_at < $cutoff
+                           OR id NOT IN (
+                               SELECT id FROM weather_data
+                               ORDER BY created_at DESC
+                               LIMIT $max_rows
+                           )
+                    "#,
+                    cutoff = cutoff,
+                    max_rows = max_rows,
+                );
+                timed_one("RetentionPolicy::count_expired", query.fetch_one(&conn)).await?
+            }
+            RetentionService::Security => {
+                let query = query!(
+                    r#"
+                        SELECT count(*) FROM intrusion_log
+                        WHERE datetime < $cutoff
+                           OR id NOT IN (
+                               SELECT id FROM intrusion_log
+                               ORDER BY datetime DESC
+                               LIMIT $max_rows
+                           )
+                    "#,
+                    cutoff = cutoff,
+                    max_rows = max_rows,
+                );
+                timed_one("RetentionPolicy::count_expired", query.fetch_one(&conn)).await?
+            }
+        };
+        Ok(count)
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn delete_expired(&self, pool: &PgPool) -> Result<u64, Error> {
+        let cutoff = self.cutoff(OffsetDateTime::now_utc());
+        let max_rows = self.max_rows;
+        let conn = pool.get().await?;
+        let deleted = match self.service {
+            RetentionService::Weather => {
+                let query = query!(
+                    r#"
+                        DELETE FROM weather_data
+                        WHERE created_at < $cutoff
+                           OR id NOT IN (
+                               SELECT id FROM weather_data
+                               ORDER BY created_at DESC
+                               LIMIT $max_rows
+                           )
+                    "#,
+                    cutoff = cutoff,
+                    max_rows = max_rows,
+                );
+                timed("RetentionPolicy::delete_expired", query.execute(&conn)).await?
+            }
+            RetentionService::Security => {
+                let query = query!(
+                    r#"
+                        DELETE FROM intrusion_log
+                        WHERE datetime < $cutoff
+                           OR id NOT IN (
+                               SELECT id FROM intrusion_log
+                               ORDER BY datetime DESC
+                               LIMIT $max_rows
+                           )
+                    "#,
+                    cutoff = cutoff,
+                    max_rows = max_rows,
+                );
+                timed("RetentionPolicy::delete_expired", query.execute(&conn)).await?
+            }
+        };
+        Ok(deleted)
+    }
+}
+
+/// Apply every `[retention.<service>]` policy, with `dry_run` only report
+/// how many rows each would remove.  A policy without a `database_url` runs
+/// against our own database.
+/// # Errors
+/// Return error if a section is invalid or db query fails
+pub async fn apply_retention(
+    config: &Config,
+    dry_run: bool,
+    stdout: &StdoutChannel<StackString>,
+) -> Result<(), Error> {
+    let mut sections: Vec<_> = config.backends.retention.iter().collect();
+    sections.sort_by_key(|(service, _)| *service);
+    for (service, section) in sections {
+        let policy = RetentionPolicy::new(service, section)?;
+        if policy.max_age_days.is_none() && policy.max_rows.is_none() {
+            continue;
+        }
+        let database_url = section
+            .database_url
+            .as_ref()
+            .unwrap_or(&config.database_url);
+        let pool = PgPool::new(database_url)?;
+        let table = policy.service.table();
+        if dry_run {
+            let count = policy.count_expired(&pool).await?;
+            stdout.send(format_sstr!(
+                "{service} would remove {count} rows from {table}"
+            ));
+        } else {
+            let deleted = policy.delete_expired(&pool).await?;
+            stdout.send(format_sstr!(
+                "{service} removed {deleted} rows from {table}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use time::macros::datetime;
+
+    use crate::{
+        config::BackendSections,
+        retention::{RetentionPolicy, RetentionService},
+    };
+
+    #[test]
+    fn test_retention_policy() -> Result<(), Error> {
+        let contents = r#"
+            [retention.weather]
+            max_age_days = 30
+            max_rows = 100000
+
+            [retention.security]
+            max_rows = 5000
+            database_url = "postgresql://user@localhost/security_logs"
+        "#;
+        let backends = BackendSections::from_toml(contents, Vec::new())?;
+        let policy = RetentionPolicy::new("weather", &backends.retention["weather"])?;
+        assert_eq!(policy.service, RetentionService::Weather);
+        assert_eq!(policy.service.table(), "weather_data");
+        let cutoff = policy.cutoff(datetime!(2023-03-31 00:00 UTC));
+        assert_eq!(cutoff.map(|c| *c), Some(datetime!(2023-03-01 00:00 UTC)));
+
+        let policy = RetentionPolicy::new("security", &backends.retention["security"])?;
+        assert_eq!(policy.max_rows, Some(5000));
+        assert_eq!(policy.cutoff(datetime!(2023-03-31 00:00 UTC)), None);
+
+        assert!(RetentionPolicy::new("garmin", &backends.retention["weather"]).is_err());
+        Ok(())
+    }
+}
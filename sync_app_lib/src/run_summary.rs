@@ -0,0 +1,111 @@
+use stack_string::{format_sstr, StackString};
+use time::{Duration, OffsetDateTime};
+
+use crate::models::ConfigRunSummary;
+
+/// `age` in its largest whole unit, e.g. `3h`
+#[must_use]
+pub fn format_age(age: Duration) -> StackString {
+    let secs = age.whole_seconds().max(0);
+    if secs < 60 {
+        format_sstr!("{secs}s")
+    } else if secs < 3600 {
+        format_sstr!("{}m", secs / 60)
+    } else if secs < 86400 {
+        format_sstr!("{}h", secs / 3600)
+    } else {
+        format_sstr!("{}d", secs / 86400)
+    }
+}
+
+/// Status column of `show_config`: status and duration of the latest run,
+/// files copied / failed since it started and the age of the last successful
+/// run, `never run` without any job or success
+#[must_use]
+pub fn status_column(summary: Option<&ConfigRunSummary>, now: OffsetDateTime) -> StackString {
+    let summary = match summary {
+        Some(summary) if summary.last_status.is_some() || summary.last_success.is_some() => summary,
+        _ => return "never run".into(),
+    };
+    let status = summary
+        .last_status
+        .as_ref()
+        .map_or("-", StackString::as_str);
+    let duration = summary.duration().map_or_else(
+        || StackString::from("-"),
+        |d| format_sstr!("{:.1}s", d.as_seconds_f64()),
+    );
+    let success = summary.last_success.map_or_else(
+        || StackString::from("never"),
+        |t| format_sstr!("{} ago", format_age(now - t.to_offsetdatetime())),
+    );
+    format_sstr!(
+        "{status:9} {:>8} {:>5} copied {:>4} failed, ok {success}",
+        duration.as_str(),
+        summary.files_copied,
+        summary.files_failed,
+    )
+}
+
+/// Next run column of `show_config`: time until the next scheduled run,
+/// `unscheduled` without an unpaused schedule
+#[must_use]
+pub fn next_run_column(summary: Option<&ConfigRunSummary>, now: OffsetDateTime) -> StackString {
+    match summary.and_then(|s| s.next_run_at) {
+        Some(next_run_at) => format_sstr!(
+            "next in {}",
+            format_age(next_run_at.to_offsetdatetime() - now)
+        ),
+        None => "unscheduled".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{macros::datetime, Duration};
+    use uuid::Uuid;
+
+    use crate::{
+        models::ConfigRunSummary,
+        run_summary::{format_age, next_run_column, status_column},
+    };
+
+    #[test]
+    fn test_status_column() {
+        assert_eq!(format_age(Duration::seconds(42)).as_str(), "42s");
+        assert_eq!(format_age(Duration::minutes(90)).as_str(), "1h");
+        assert_eq!(format_age(Duration::days(3)).as_str(), "3d");
+        assert_eq!(format_age(Duration::seconds(-5)).as_str(), "0s");
+
+        let now = datetime!(2024-03-01 12:00 UTC);
+        let summary = ConfigRunSummary {
+            config_id: Uuid::new_v4(),
+            last_status: Some("failed".into()),
+            last_started_at: Some(datetime!(2024-03-01 11:00 UTC).into()),
+            last_finished_at: Some(datetime!(2024-03-01 11:00:12.5 UTC).into()),
+            last_success: Some(datetime!(2024-02-28 12:00 UTC).into()),
+            files_copied: 120,
+            files_failed: 3,
+            next_run_at: Some(datetime!(2024-03-01 12:30 UTC).into()),
+        };
+        assert_eq!(
+            status_column(Some(&summary), now).as_str(),
+            "failed       12.5s   120 copied    3 failed, ok 2d ago"
+        );
+        assert_eq!(status_column(None, now).as_str(), "never run");
+        assert_eq!(next_run_column(Some(&summary), now).as_str(), "next in 30m");
+        assert_eq!(next_run_column(None, now).as_str(), "unscheduled");
+        let never = ConfigRunSummary {
+            last_status: None,
+            last_started_at: None,
+            last_finished_at: None,
+            last_success: None,
+            files_copied: 0,
+            files_failed: 0,
+            next_run_at: None,
+            ..summary
+        };
+        assert_eq!(status_column(Some(&never), now).as_str(), "never run");
+        assert_eq!(next_run_column(Some(&never), now).as_str(), "unscheduled");
+    }
+}
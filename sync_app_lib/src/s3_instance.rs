@@ -1,26 +1,126 @@
 use anyhow::{format_err, Error};
 use aws_config::SdkConfig;
 use aws_sdk_s3::{
+    config::Builder as S3ConfigBuilder,
     operation::list_objects::ListObjectsOutput,
-    primitives::ByteStream,
-    types::{Bucket, Object},
+    primitives::{ByteStream, Length},
+    types::{
+        Bucket, CompletedMultipartUpload, CompletedPart, GlacierJobParameters, MetadataDirective,
+        MultipartUpload, Object, Part, RequestPayer, RestoreRequest, ServerSideEncryption,
+        StorageClass, Tier,
+    },
     Client as S3Client,
 };
+use checksums::{hash_file, Algorithm};
+use futures::{future, stream, StreamExt, TryStreamExt};
+use log::warn;
+use md5::{Digest, Md5};
 use once_cell::sync::Lazy;
 use parking_lot::{Mutex, MutexGuard};
-use std::{fmt, path::Path};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::SeekFrom,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncSeekExt, AsyncWriteExt},
+    task::spawn_blocking,
+};
 use url::Url;
 
 static S3INSTANCE_TEST_MUTEX: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
 
-use stack_string::StackString;
+use stack_string::{format_sstr, StackString};
 
 use gdrive_lib::exponential_retry;
 
+use crate::{byte_accounting::ByteCounter, storage_class::RestoreState};
+
+/// Smallest part s3 accepts (except for the last one)
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+/// Most parts in one multipart upload
+const MAX_PARTS: u64 = 10_000;
+/// Largest object `copy_object` accepts
+const MAX_COPY_SIZE: u64 = 5 * 1024 * 1024 * 1024;
+
+/// User metadata (`x-amz-meta-md5`) holding the md5 of a multipart upload
+/// or an SSE-KMS object, whose etag isn't the md5 of the object
+pub const MD5_METADATA_KEY: &str = "md5";
+
+/// Storage class and server side encryption of uploaded objects, the
+/// bucket's defaults for anything `None`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadOptions {
+    pub storage_class: Option<StackString>,
+    /// `AES256` (SSE-S3), `aws:kms` or `aws:kms:dsse` (SSE-KMS)
+    pub sse: Option<StackString>,
+    /// KMS key ARN, only sent with SSE-KMS
+    pub sse_kms_key_id: Option<StackString>,
+}
+
+impl UploadOptions {
+    #[must_use]
+    pub fn is_kms(&self) -> bool {
+        self.sse.as_deref().map_or(false, is_kms_sse)
+    }
+
+    fn server_side_encryption(&self) -> Option<ServerSideEncryption> {
+        self.sse.as_deref().map(ServerSideEncryption::from)
+    }
+
+    fn kms_key_id(&self) -> Option<String> {
+        if self.is_kms() {
+            self.sse_kms_key_id.as_ref().map(Into::into)
+        } else {
+            None
+        }
+    }
+}
+
+/// Cached `sse` of an object a HEAD found unencrypted, so that it isn't
+/// looked up again on every index
+pub const SSE_NONE: &str = "none";
+
+/// Objects encrypted with a KMS key have etags that aren't the md5 of their
+/// contents
+#[must_use]
+pub fn is_kms_sse(sse: &str) -> bool {
+    sse.starts_with("aws:kms")
+}
+
+/// Check that `s` is a server side encryption s3 accepts, e.g. `aws:kms`
+/// # Errors
+/// Return error if `s` isn't a known algorithm
+pub fn parse_sse(s: &str) -> Result<StackString, Error> {
+    if ServerSideEncryption::values().contains(&s) {
+        Ok(s.into())
+    } else {
+        Err(format_err!(
+            "Invalid server side encryption {s}, expected one of {}",
+            ServerSideEncryption::values().join(", ")
+        ))
+    }
+}
+
+/// Called with the key, bytes transferred so far and the total size as each
+/// part of a transfer completes
+pub type ProgressCallback = Arc<dyn Fn(&str, u64, u64) + Send + Sync>;
+
 #[derive(Clone)]
 pub struct S3Instance {
     s3_client: S3Client,
     max_keys: Option<i32>,
+    multipart_threshold: u64,
+    part_size: u64,
+    concurrency: usize,
+    progress: Option<ProgressCallback>,
+    request_payer: Option<RequestPayer>,
 }
 
 impl fmt::Debug for S3Instance {
@@ -32,12 +132,75 @@ impl fmt::Debug for S3Instance {
 impl S3Instance {
     #[must_use]
     pub fn new(sdk_config: &SdkConfig) -> Self {
+        Self::from_builder(S3ConfigBuilder::from(sdk_config))
+    }
+
+    /// Client addressing buckets as `<endpoint>/<bucket>` rather than
+    /// `<bucket>.<endpoint>`, as most S3 compatible providers (MinIO ...)
+    /// expect
+    #[must_use]
+    pub fn new_path_style(sdk_config: &SdkConfig) -> Self {
+        Self::from_builder(S3ConfigBuilder::from(sdk_config).force_path_style(true))
+    }
+
+    fn from_builder(builder: S3ConfigBuilder) -> Self {
         Self {
-            s3_client: S3Client::from_conf(sdk_config.into()),
+            s3_client: S3Client::from_conf(builder.interceptor(ByteCounter::new("s3")).build()),
             max_keys: None,
+            multipart_threshold: u64::MAX,
+            part_size: MIN_PART_SIZE,
+            concurrency: 1,
+            progress: None,
+            request_payer: None,
         }
     }
 
+    /// Acknowledge that requests to a requester-pays bucket are billed to
+    /// this account, s3 refuses them otherwise
+    #[must_use]
+    pub fn with_requester_pays(mut self, requester_pays: bool) -> Self {
+        self.request_payer = if requester_pays {
+            Some(RequestPayer::Requester)
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Files larger than `threshold` are uploaded in parts of `part_size`
+    /// and downloaded in ranges of `part_size`, `concurrency` parts at a time
+    #[must_use]
+    pub fn with_multipart(mut self, threshold: u64, part_size: u64, concurrency: usize) -> Self {
+        self.multipart_threshold = threshold;
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    #[must_use]
+    pub fn with_progress(mut self, progress: ProgressCallback) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    fn report_progress(&self, key_name: &str, done: u64, total: u64) {
+        if let Some(progress) = &self.progress {
+            progress(key_name, done, total);
+        }
+    }
+
+    /// Whether `upload` sends a file of `size` bytes as a multipart upload
+    #[must_use]
+    pub fn is_multipart(&self, size: u64) -> bool {
+        size > self.multipart_threshold
+    }
+
+    /// Part size used for an object of `size` bytes, grown if needed to stay
+    /// within `MAX_PARTS`
+    fn part_size_for(&self, size: u64) -> u64 {
+        self.part_size.max((size + MAX_PARTS - 1) / MAX_PARTS)
+    }
+
     pub fn get_instance_lock() -> MutexGuard<'static, ()> {
         S3INSTANCE_TEST_MUTEX.lock()
     }
@@ -48,6 +211,17 @@ impl S3Instance {
         self
     }
 
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn head_bucket(&self, bucket_name: &str) -> Result<(), Error> {
+        self.s3_client
+            .head_bucket()
+            .bucket(bucket_name)
+            .send()
+            .await?;
+        Ok(())
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn get_list_of_buckets(&self) -> Result<Vec<Bucket>, Error> {
@@ -100,6 +274,7 @@ impl S3Instance {
         exponential_retry(|| async move {
             self.s3_client
                 .delete_object()
+                .set_request_payer(self.request_payer.clone())
                 .bucket(bucket_name)
                 .key(key_name)
                 .send()
@@ -123,6 +298,7 @@ impl S3Instance {
             async move {
                 self.s3_client
                     .copy_object()
+                    .set_request_payer(self.request_payer.clone())
                     .copy_source(copy_source)
                     .bucket(bucket_to)
                     .key(key_to)
@@ -135,6 +311,8 @@ impl S3Instance {
         .map(|x| x.copy_object_result.and_then(|s| s.e_tag))
     }
 
+    /// Upload `fname` with the storage class and encryption of `options`,
+    /// SSE-KMS uploads also store the md5 as metadata
     /// # Errors
     /// Return error if db query fails
     pub async fn upload(
@@ -142,24 +320,300 @@ impl S3Instance {
         fname: &str,
         bucket_name: &str,
         key_name: &str,
+        options: &UploadOptions,
     ) -> Result<(), Error> {
         let fname = Path::new(fname);
         if !fname.exists() {
             return Err(format_err!("File doesn't exist {fname:?}"));
         }
+        let size = tokio::fs::metadata(fname).await?.len();
+        if size > self.multipart_threshold {
+            return self
+                .multipart_upload(fname, size, bucket_name, key_name, options)
+                .await;
+        }
+        let md5sum = if options.is_kms() {
+            Some(file_md5sum(fname).await?)
+        } else {
+            None
+        };
+        let md5sum = &md5sum;
         exponential_retry(|| async move {
             let body = ByteStream::read_from().path(fname).build().await?;
             self.s3_client
                 .put_object()
+                .set_request_payer(self.request_payer.clone())
                 .bucket(bucket_name)
                 .key(key_name)
+                .set_storage_class(options.storage_class.as_deref().map(StorageClass::from))
+                .set_server_side_encryption(options.server_side_encryption())
+                .set_ssekms_key_id(options.kms_key_id())
+                .set_metadata(md5sum.as_ref().map(|m| {
+                    [(MD5_METADATA_KEY.into(), m.as_str().into())]
+                        .into_iter()
+                        .collect()
+                }))
                 .body(body)
                 .send()
                 .await
                 .map(|_| ())
                 .map_err(Into::into)
         })
-        .await
+        .await?;
+        self.report_progress(key_name, size, size);
+        Ok(())
+    }
+
+    /// Upload `fname` in parts streamed from disk, the upload is aborted if
+    /// any part fails so that no orphaned parts are left (and billed)
+    async fn multipart_upload(
+        &self,
+        fname: &Path,
+        size: u64,
+        bucket_name: &str,
+        key_name: &str,
+        options: &UploadOptions,
+    ) -> Result<(), Error> {
+        let upload_id = self
+            .create_multipart_upload(fname, bucket_name, key_name, options)
+            .await?;
+        match self
+            .finish_multipart_upload(fname, size, bucket_name, key_name, &upload_id, &[])
+            .await
+        {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                if let Err(abort_error) = self
+                    .abort_multipart_upload(bucket_name, key_name, &upload_id)
+                    .await
+                {
+                    warn!("failed to abort upload of {key_name} {abort_error}");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Start a multipart upload of `fname`, its parts are sent by
+    /// `finish_multipart_upload`
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn create_multipart_upload(
+        &self,
+        fname: &Path,
+        bucket_name: &str,
+        key_name: &str,
+        options: &UploadOptions,
+    ) -> Result<StackString, Error> {
+        let md5sum = file_md5sum(fname).await?;
+        self.s3_client
+            .create_multipart_upload()
+            .set_request_payer(self.request_payer.clone())
+            .bucket(bucket_name)
+            .key(key_name)
+            .set_storage_class(options.storage_class.as_deref().map(StorageClass::from))
+            .set_server_side_encryption(options.server_side_encryption())
+            .set_ssekms_key_id(options.kms_key_id())
+            .metadata(MD5_METADATA_KEY, md5sum.as_str())
+            .send()
+            .await?
+            .upload_id
+            .map(Into::into)
+            .ok_or_else(|| format_err!("No upload id for {key_name}"))
+    }
+
+    /// Send the parts of `fname` missing from `uploaded` (as returned by
+    /// `list_parts`) and complete the upload, on error the parts sent are
+    /// kept for a later attempt
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn finish_multipart_upload(
+        &self,
+        fname: &Path,
+        size: u64,
+        bucket_name: &str,
+        key_name: &str,
+        upload_id: &str,
+        uploaded: &[Part],
+    ) -> Result<(), Error> {
+        let parts = self
+            .upload_parts(fname, size, bucket_name, key_name, upload_id, uploaded)
+            .await?;
+        let upload = CompletedMultipartUpload::builder()
+            .set_parts(Some(parts))
+            .build();
+        self.s3_client
+            .complete_multipart_upload()
+            .set_request_payer(self.request_payer.clone())
+            .bucket(bucket_name)
+            .key(key_name)
+            .upload_id(upload_id)
+            .multipart_upload(upload)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn abort_multipart_upload(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        upload_id: &str,
+    ) -> Result<(), Error> {
+        self.s3_client
+            .abort_multipart_upload()
+            .set_request_payer(self.request_payer.clone())
+            .bucket(bucket_name)
+            .key(key_name)
+            .upload_id(upload_id)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Parts received so far by an upload, fails if the upload was completed
+    /// or aborted
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_parts(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        upload_id: &str,
+    ) -> Result<Vec<Part>, Error> {
+        let mut parts = Vec::new();
+        let mut marker: Option<String> = None;
+        loop {
+            let output = exponential_retry(|| {
+                let marker = marker.clone();
+                async move {
+                    self.s3_client
+                        .list_parts()
+                        .set_request_payer(self.request_payer.clone())
+                        .bucket(bucket_name)
+                        .key(key_name)
+                        .upload_id(upload_id)
+                        .set_part_number_marker(marker)
+                        .send()
+                        .await
+                        .map_err(Into::into)
+                }
+            })
+            .await?;
+            parts.extend(output.parts.unwrap_or_default());
+            if output.is_truncated != Some(true) {
+                return Ok(parts);
+            }
+            marker = output.next_part_number_marker;
+        }
+    }
+
+    /// Multipart uploads of keys under `prefix` started but neither
+    /// completed nor aborted
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_multipart_uploads(
+        &self,
+        bucket_name: &str,
+        prefix: Option<&str>,
+    ) -> Result<Vec<MultipartUpload>, Error> {
+        let mut uploads = Vec::new();
+        let mut markers: (Option<String>, Option<String>) = (None, None);
+        loop {
+            let output = exponential_retry(|| {
+                let (key_marker, upload_id_marker) = markers.clone();
+                async move {
+                    self.s3_client
+                        .list_multipart_uploads()
+                        .set_request_payer(self.request_payer.clone())
+                        .bucket(bucket_name)
+                        .set_prefix(prefix.map(Into::into))
+                        .set_key_marker(key_marker)
+                        .set_upload_id_marker(upload_id_marker)
+                        .send()
+                        .await
+                        .map_err(Into::into)
+                }
+            })
+            .await?;
+            uploads.extend(output.uploads.unwrap_or_default());
+            if output.is_truncated != Some(true) {
+                return Ok(uploads);
+            }
+            markers = (output.next_key_marker, output.next_upload_id_marker);
+        }
+    }
+
+    async fn upload_parts(
+        &self,
+        fname: &Path,
+        size: u64,
+        bucket_name: &str,
+        key_name: &str,
+        upload_id: &str,
+        uploaded: &[Part],
+    ) -> Result<Vec<CompletedPart>, Error> {
+        let part_size = self.part_size_for(size);
+        let uploaded: HashMap<i32, (i64, &str)> = uploaded
+            .iter()
+            .filter_map(|p| Some((p.part_number?, (p.size?, p.e_tag.as_deref()?))))
+            .collect();
+        let done = AtomicU64::new(0);
+        let futures = part_ranges(size, part_size).map(|(index, offset, length)| {
+            let done = &done;
+            let uploaded = &uploaded;
+            async move {
+                let part_number = index as i32 + 1;
+                if let Some((_, e_tag)) = uploaded
+                    .get(&part_number)
+                    .filter(|(part_size, _)| *part_size as u64 == length)
+                {
+                    let transferred = done.fetch_add(length, Ordering::SeqCst) + length;
+                    self.report_progress(key_name, transferred, size);
+                    return Ok(CompletedPart::builder()
+                        .part_number(part_number)
+                        .e_tag(*e_tag)
+                        .build());
+                }
+                let e_tag = exponential_retry(|| async move {
+                    let body = ByteStream::read_from()
+                        .path(fname)
+                        .offset(offset)
+                        .length(Length::Exact(length))
+                        .build()
+                        .await?;
+                    self.s3_client
+                        .upload_part()
+                        .set_request_payer(self.request_payer.clone())
+                        .bucket(bucket_name)
+                        .key(key_name)
+                        .upload_id(upload_id)
+                        .part_number(part_number)
+                        .body(body)
+                        .send()
+                        .await?
+                        .e_tag
+                        .ok_or_else(|| format_err!("No etag for part {part_number}"))
+                })
+                .await?;
+                let transferred = done.fetch_add(length, Ordering::SeqCst) + length;
+                self.report_progress(key_name, transferred, size);
+                Ok::<_, Error>(
+                    CompletedPart::builder()
+                        .part_number(part_number)
+                        .e_tag(e_tag)
+                        .build(),
+                )
+            }
+        });
+        let mut parts: Vec<CompletedPart> = stream::iter(futures)
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        parts.sort_by_key(|p| p.part_number);
+        Ok(parts)
     }
 
     /// # Errors
@@ -175,25 +629,256 @@ impl S3Instance {
             let resp = self
                 .s3_client
                 .get_object()
+                .set_request_payer(self.request_payer.clone())
+                .bucket(bucket_name)
+                .key(key_name)
+                .send()
+                .await?;
+            let e_tag = resp.e_tag.ok_or_else(|| format_err!("No etag"))?;
+            let size = resp.content_length.unwrap_or(0).max(0) as u64;
+            if size > self.multipart_threshold {
+                // the body is dropped unread, the ranges are fetched in
+                // parallel instead
+                self.ranged_download(bucket_name, key_name, fname, size, &e_tag)
+                    .await?;
+            } else {
+                tokio::io::copy(
+                    &mut resp.body.into_async_read(),
+                    &mut File::create(fname).await?,
+                )
+                .await?;
+                self.report_progress(key_name, size, size);
+            }
+            Ok(e_tag.trim_matches('"').into())
+        })
+        .await
+    }
+
+    /// Fetch `size` bytes of `key_name` in ranges written straight to their
+    /// offset in `fname`, `e_tag` makes sure that all ranges come from the
+    /// same version of the object
+    async fn ranged_download(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        fname: &Path,
+        size: u64,
+        e_tag: &str,
+    ) -> Result<(), Error> {
+        File::create(fname).await?.set_len(size).await?;
+        let part_size = self.part_size_for(size);
+        let done = AtomicU64::new(0);
+        let futures = part_ranges(size, part_size).map(|(_, offset, length)| {
+            let done = &done;
+            async move {
+                exponential_retry(|| async move {
+                    let resp = self
+                        .s3_client
+                        .get_object()
+                        .set_request_payer(self.request_payer.clone())
+                        .bucket(bucket_name)
+                        .key(key_name)
+                        .range(format!("bytes={offset}-{}", offset + length - 1))
+                        .if_match(e_tag)
+                        .send()
+                        .await?;
+                    let mut f = OpenOptions::new().write(true).open(fname).await?;
+                    f.seek(SeekFrom::Start(offset)).await?;
+                    tokio::io::copy(&mut resp.body.into_async_read(), &mut f).await?;
+                    f.flush().await?;
+                    Ok(())
+                })
+                .await?;
+                let transferred = done.fetch_add(length, Ordering::SeqCst) + length;
+                self.report_progress(key_name, transferred, size);
+                Ok(())
+            }
+        });
+        stream::iter(futures)
+            .buffer_unordered(self.concurrency)
+            .try_for_each(|()| future::ready(Ok(())))
+            .await
+    }
+
+    /// The md5 stored as object metadata by `upload` or `set_md5_metadata`,
+    /// `None` for objects written by other tools, and the object's server
+    /// side encryption
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn get_md5_metadata(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+    ) -> Result<(Option<StackString>, Option<StackString>), Error> {
+        exponential_retry(|| async move {
+            let resp = self
+                .s3_client
+                .head_object()
+                .set_request_payer(self.request_payer.clone())
                 .bucket(bucket_name)
                 .key(key_name)
                 .send()
                 .await?;
-            let etag = resp
-                .e_tag
-                .ok_or_else(|| format_err!("No etag"))?
-                .trim_matches('"')
+            let sse = resp
+                .server_side_encryption
+                .as_ref()
+                .map(|s| s.as_str().into());
+            let md5sum = resp
+                .metadata
+                .and_then(|m| m.get(MD5_METADATA_KEY).map(|s| s.as_str().into()));
+            Ok((md5sum, sse))
+        })
+        .await
+    }
+
+    /// Storage class of an object (`STANDARD` if s3 doesn't report one) and
+    /// the state of its restore if it's archived
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn get_restore_state(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+    ) -> Result<(StackString, RestoreState), Error> {
+        exponential_retry(|| async move {
+            let resp = self
+                .s3_client
+                .head_object()
+                .set_request_payer(self.request_payer.clone())
+                .bucket(bucket_name)
+                .key(key_name)
+                .send()
+                .await?;
+            let storage_class = resp
+                .storage_class
+                .as_ref()
+                .map_or("STANDARD", StorageClass::as_str)
                 .into();
-            tokio::io::copy(
-                &mut resp.body.into_async_read(),
-                &mut tokio::fs::File::create(fname).await?,
-            )
-            .await?;
-            Ok(etag)
+            Ok((
+                storage_class,
+                RestoreState::from_header(resp.restore.as_deref()),
+            ))
+        })
+        .await
+    }
+
+    /// Request a temporary copy of an archived object, readable for `days`
+    /// once the restore completes.  `tier` is `Expedited`, `Standard` or
+    /// `Bulk`.
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn restore_object(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        days: i32,
+        tier: &str,
+    ) -> Result<(), Error> {
+        let job_parameters = GlacierJobParameters::builder()
+            .tier(Tier::from(tier))
+            .build()?;
+        let request = RestoreRequest::builder()
+            .days(days)
+            .glacier_job_parameters(job_parameters)
+            .build();
+        exponential_retry(|| {
+            let request = request.clone();
+            async move {
+                self.s3_client
+                    .restore_object()
+                    .set_request_payer(self.request_payer.clone())
+                    .bucket(bucket_name)
+                    .key(key_name)
+                    .restore_request(request)
+                    .send()
+                    .await
+                    .map(|_| ())
+                    .map_err(Into::into)
+            }
         })
         .await
     }
 
+    /// Store `md5sum` as object metadata by copying the object onto itself,
+    /// the content type, other user metadata, storage class and encryption
+    /// are kept
+    /// # Errors
+    /// Return error if api call fails or the object is too large to copy
+    pub async fn set_md5_metadata(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        size: u64,
+        md5sum: &str,
+    ) -> Result<(), Error> {
+        if size > MAX_COPY_SIZE {
+            return Err(format_err!(
+                "{key_name} is too large to copy in place ({size} bytes)"
+            ));
+        }
+        exponential_retry(|| async move {
+            // a copy is written in STANDARD with the bucket's encryption, and
+            // with only the metadata it is given, unless told otherwise
+            let head = self
+                .s3_client
+                .head_object()
+                .set_request_payer(self.request_payer.clone())
+                .bucket(bucket_name)
+                .key(key_name)
+                .send()
+                .await?;
+            self.s3_client
+                .copy_object()
+                .set_request_payer(self.request_payer.clone())
+                .copy_source(format!("{bucket_name}/{key_name}"))
+                .bucket(bucket_name)
+                .key(key_name)
+                .metadata_directive(MetadataDirective::Replace)
+                .set_content_type(head.content_type)
+                .set_metadata(head.metadata)
+                .metadata(MD5_METADATA_KEY, md5sum)
+                .set_storage_class(head.storage_class)
+                .set_server_side_encryption(head.server_side_encryption)
+                .set_ssekms_key_id(head.ssekms_key_id)
+                .send()
+                .await
+                .map(|_| ())
+                .map_err(Into::into)
+        })
+        .await
+    }
+
+    /// md5 of the whole object, the ranges are fetched one after another and
+    /// hashed in memory so nothing is written to disk
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn compute_md5(
+        &self,
+        bucket_name: &str,
+        key_name: &str,
+        size: u64,
+    ) -> Result<StackString, Error> {
+        let mut hasher = Md5::new();
+        for (_, offset, length) in part_ranges(size, self.part_size_for(size)) {
+            let data = exponential_retry(|| async move {
+                let resp = self
+                    .s3_client
+                    .get_object()
+                    .set_request_payer(self.request_payer.clone())
+                    .bucket(bucket_name)
+                    .key(key_name)
+                    .range(format!("bytes={offset}-{}", offset + length - 1))
+                    .send()
+                    .await?;
+                Ok(resp.body.collect().await?.into_bytes())
+            })
+            .await?;
+            hasher.update(&data);
+            self.report_progress(key_name, offset + length, size);
+        }
+        Ok(format_sstr!("{:x}", hasher.finalize()))
+    }
+
     async fn list_keys(
         &self,
         bucket: &str,
@@ -201,7 +886,11 @@ impl S3Instance {
         marker: Option<impl AsRef<str>>,
         max_keys: Option<i32>,
     ) -> Result<ListObjectsOutput, Error> {
-        let mut builder = self.s3_client.list_objects().bucket(bucket);
+        let mut builder = self
+            .s3_client
+            .list_objects()
+            .set_request_payer(self.request_payer.clone())
+            .bucket(bucket);
         if let Some(prefix) = prefix {
             builder = builder.prefix(prefix);
         }
@@ -249,6 +938,37 @@ impl S3Instance {
         .await
     }
 
+    #[must_use]
+    pub fn get_max_keys(&self) -> Option<i32> {
+        self.max_keys
+    }
+
+    /// Fetch a single page of keys starting after `marker`, returns the
+    /// objects along with the marker for the next page (if any)
+    /// # Errors
+    /// Return error if api call fails
+    pub async fn list_keys_page(
+        &self,
+        bucket: &str,
+        prefix: Option<&str>,
+        marker: Option<&str>,
+        max_keys: Option<i32>,
+    ) -> Result<(Vec<Object>, Option<String>), Error> {
+        exponential_retry(|| async move {
+            let mut output = self.list_keys(bucket, prefix, marker, max_keys).await?;
+            let contents = output.contents.take().unwrap_or_default();
+            let next_marker = if output.is_truncated == Some(true) {
+                contents.last().and_then(|last| last.key.clone())
+            } else {
+                None
+            };
+            Ok((contents, next_marker))
+        })
+        .await
+    }
+
+    /// Run `callback` on each key as the pages come in, listing stops once
+    /// `callback` returns `false`
     /// # Errors
     /// Return error if db query fails
     pub async fn process_list_of_keys<T>(
@@ -258,7 +978,7 @@ impl S3Instance {
         callback: T,
     ) -> Result<(), Error>
     where
-        T: Fn(&Object) -> Result<(), Error> + Send + Sync,
+        T: Fn(&Object) -> Result<bool, Error> + Send + Sync,
     {
         let mut marker: Option<String> = None;
         let mut max_keys = self.max_keys;
@@ -276,7 +996,9 @@ impl S3Instance {
                     max_keys.replace(n - contents.len() as i32);
                 }
                 for object in &contents {
-                    callback(object)?;
+                    if !callback(object)? {
+                        return Ok(());
+                    }
                 }
             }
             if output.is_truncated == Some(false) || output.is_truncated.is_none() {
@@ -286,3 +1008,57 @@ impl S3Instance {
         Ok(())
     }
 }
+
+async fn file_md5sum(fname: &Path) -> Result<StackString, Error> {
+    let fname = fname.to_path_buf();
+    let md5sum = spawn_blocking(move || hash_file(&fname, Algorithm::MD5).to_lowercase()).await?;
+    Ok(md5sum.into())
+}
+
+/// `(index, offset, length)` of each part of an object of `size` bytes
+fn part_ranges(size: u64, part_size: u64) -> impl Iterator<Item = (u64, u64, u64)> {
+    let part_size = part_size.max(1);
+    (0..(size + part_size - 1) / part_size).map(move |index| {
+        let offset = index * part_size;
+        (index, offset, part_size.min(size - offset))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::s3_instance::{is_kms_sse, parse_sse, part_ranges, UploadOptions};
+
+    #[test]
+    fn test_part_ranges() {
+        let parts: Vec<_> = part_ranges(25, 10).collect();
+        assert_eq!(parts, vec![(0, 0, 10), (1, 10, 10), (2, 20, 5)]);
+        let parts: Vec<_> = part_ranges(20, 10).collect();
+        assert_eq!(parts, vec![(0, 0, 10), (1, 10, 10)]);
+        assert_eq!(part_ranges(0, 10).count(), 0);
+    }
+
+    #[test]
+    fn test_upload_options() {
+        assert_eq!(parse_sse("aws:kms").unwrap().as_str(), "aws:kms");
+        assert!(parse_sse("kms").is_err());
+        assert!(is_kms_sse("aws:kms:dsse"));
+        assert!(!is_kms_sse("AES256"));
+
+        let options = UploadOptions {
+            sse: Some("AES256".into()),
+            sse_kms_key_id: Some("arn:aws:kms:us-east-1:123456789012:key/0a1b".into()),
+            ..UploadOptions::default()
+        };
+        assert!(!options.is_kms());
+        assert_eq!(options.kms_key_id(), None);
+        let options = UploadOptions {
+            sse: Some("aws:kms".into()),
+            ..options
+        };
+        assert!(options.is_kms());
+        assert_eq!(
+            options.kms_key_id().as_deref(),
+            Some("arn:aws:kms:us-east-1:123456789012:key/0a1b")
+        );
+    }
+}
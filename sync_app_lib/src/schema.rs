@@ -0,0 +1,125 @@
+use anyhow::{format_err, Error};
+use log::{info, warn};
+use refinery::embed_migrations;
+use std::fmt;
+
+use crate::pgpool::PgPool;
+
+embed_migrations!("../migrations");
+
+/// Newest migration compiled into this binary
+#[must_use]
+pub fn latest_version() -> i64 {
+    migrations::runner()
+        .get_migrations()
+        .iter()
+        .map(|m| i64::from(m.version()))
+        .max()
+        .unwrap_or(0)
+}
+
+/// How the schema of the database compares to the migrations of this binary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchemaStatus {
+    Current(i64),
+    /// Migrations `applied + 1..=latest` still need to run
+    Behind {
+        applied: i64,
+        latest: i64,
+    },
+    /// The database was migrated by a newer binary
+    Ahead {
+        applied: i64,
+        latest: i64,
+    },
+}
+
+impl SchemaStatus {
+    #[must_use]
+    pub fn new(applied: Option<i64>, latest: i64) -> Self {
+        let applied = applied.unwrap_or(0);
+        if applied < latest {
+            Self::Behind { applied, latest }
+        } else if applied > latest {
+            Self::Ahead { applied, latest }
+        } else {
+            Self::Current(applied)
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(pool: &PgPool) -> Result<Self, Error> {
+        let mut client = pool.get().await?;
+        let applied = migrations::runner()
+            .get_last_applied_migration_async(&mut **client)
+            .await?
+            .map(|m| i64::from(m.version()));
+        Ok(Self::new(applied, latest_version()))
+    }
+}
+
+impl fmt::Display for SchemaStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Current(v) => write!(f, "schema is current at V{v}"),
+            Self::Behind { applied, latest } => {
+                write!(f, "schema is at V{applied}, this binary expects V{latest}")
+            }
+            Self::Ahead { applied, latest } => write!(
+                f,
+                "schema is at V{applied} but this binary only knows up to V{latest}, upgrade \
+                 sync-app-rust before using this database"
+            ),
+        }
+    }
+}
+
+/// Run pending migrations before anything touches the database, with
+/// `migrate` false they are only reported.  A database migrated by a newer
+/// binary is always an error, its tables may no longer match our queries.
+/// # Errors
+/// Return error if db query fails, migrations fail or the schema is newer
+/// than this binary
+pub async fn ensure_schema(pool: &PgPool, migrate: bool) -> Result<SchemaStatus, Error> {
+    let status = SchemaStatus::get(pool).await?;
+    match status {
+        SchemaStatus::Current(_) => {}
+        SchemaStatus::Ahead { .. } => return Err(format_err!("{status}")),
+        SchemaStatus::Behind { .. } if migrate => {
+            info!("{status}, migrating");
+            run_migrations(pool).await?;
+        }
+        SchemaStatus::Behind { .. } => warn!("{status}, run `run-migrations`"),
+    }
+    Ok(status)
+}
+
+/// # Errors
+/// Return error if db query fails or a migration fails
+pub async fn run_migrations(pool: &PgPool) -> Result<(), Error> {
+    let mut client = pool.get().await?;
+    migrations::runner().run_async(&mut **client).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::schema::{latest_version, SchemaStatus};
+
+    #[test]
+    fn test_schema_status() {
+        let latest = latest_version();
+        assert!(latest >= 31);
+        assert_eq!(
+            SchemaStatus::new(Some(latest), latest),
+            SchemaStatus::Current(latest)
+        );
+        assert_eq!(
+            SchemaStatus::new(None, latest),
+            SchemaStatus::Behind { applied: 0, latest }
+        );
+        let ahead = SchemaStatus::new(Some(latest + 1), latest);
+        assert!(ahead.to_string().contains("upgrade"));
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{format_err, Error};
+use futures::TryStreamExt;
+use log::info;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashMap, convert::TryInto, path::PathBuf};
+use stdout_channel::StdoutChannel;
+use tokio::fs::{create_dir_all, remove_dir_all, write};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    file_info::FileInfo,
+    file_list::{FileList, FileListTrait},
+    file_sync::FileSync,
+    models::FileSyncCache,
+    pgpool::PgPool,
+};
+
+const FILE_A: &str = "selftest_a.txt";
+const FILE_B: &str = "selftest_b.txt";
+
+/// End to end smoke test of one backend: a temporary local directory is
+/// synced against a temporary prefix under the given url, going through
+/// index → sync → modify → sync → delete → sync and checking the remote
+/// listing after each step.
+pub struct SelfTest {
+    config: Config,
+    pool: PgPool,
+    local_dir: PathBuf,
+    local_url: Url,
+    remote_url: Url,
+}
+
+impl SelfTest {
+    /// # Errors
+    /// Return error if the urls can't be constructed
+    pub fn new(config: Config, pool: PgPool, baseurl: &Url) -> Result<Self, Error> {
+        let name = format_sstr!("sync-app-selftest-{}", Uuid::new_v4());
+        let local_dir = std::env::temp_dir().join(name.as_str());
+        let local_url = Url::from_directory_path(&local_dir)
+            .map_err(|e| format_err!("Failed to parse url {e:?}"))?;
+        let mut remote_url = baseurl.clone();
+        if !remote_url.path().ends_with('/') {
+            let path = format_sstr!("{}/", remote_url.path());
+            remote_url.set_path(&path);
+        }
+        let remote_url = remote_url.join(&format_sstr!("{name}/"))?;
+        Ok(Self {
+            config,
+            pool,
+            local_dir,
+            local_url,
+            remote_url,
+        })
+    }
+
+    /// Run the cycle, always cleaning up the temporary files and cache
+    /// entries afterwards
+    /// # Errors
+    /// Return error if any step fails verification
+    pub async fn run(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+        stdout.send(format_sstr!(
+            "selftest {} <-> {}",
+            self.local_url,
+            self.remote_url
+        ));
+        let result = self.run_cycle(stdout).await;
+        let cleanup = self.cleanup().await;
+        match (result, cleanup) {
+            (Err(e), _) | (Ok(()), Err(e)) => {
+                stdout.send(format_sstr!("selftest FAILED: {e}"));
+                Err(e)
+            }
+            (Ok(()), Ok(())) => {
+                stdout.send("selftest passed");
+                Ok(())
+            }
+        }
+    }
+
+    async fn run_cycle(&self, stdout: &StdoutChannel<StackString>) -> Result<(), Error> {
+        create_dir_all(&self.local_dir).await?;
+        write(self.local_dir.join(FILE_A), b"sync-app-rust selftest\n").await?;
+        write(self.local_dir.join(FILE_B), b"sync-app-rust selftest b\n").await?;
+        let local = FileList::from_url(&self.local_url, &self.config, &self.pool).await?;
+        let remote = FileList::from_url(&self.remote_url, &self.config, &self.pool).await?;
+        remote.probe().await?;
+
+        self.sync(&(*local), &(*remote), stdout).await?;
+        let files = self.remote_files(&(*remote)).await?;
+        for name in &[FILE_A, FILE_B] {
+            if !files.contains_key(*name) {
+                return Err(format_err!("initial sync: {name} missing on remote"));
+            }
+        }
+        stdout.send("initial sync ok");
+
+        let modified = b"sync-app-rust selftest, modified contents\n";
+        write(self.local_dir.join(FILE_A), modified).await?;
+        self.sync(&(*local), &(*remote), stdout).await?;
+        let files = self.remote_files(&(*remote)).await?;
+        let size = files
+            .get(FILE_A)
+            .map(|f| f.filestat.st_size)
+            .ok_or_else(|| format_err!("modify: {FILE_A} missing on remote"))?;
+        if size != modified.len() as i64 {
+            return Err(format_err!(
+                "modify: expected {} bytes on remote, found {size}",
+                modified.len()
+            ));
+        }
+        stdout.send("modify sync ok");
+
+        let finfo = files
+            .get(FILE_B)
+            .ok_or_else(|| format_err!("delete: {FILE_B} missing on remote"))?;
+        remote.delete(finfo).await?;
+        remote.index().await?;
+        if self.remote_files(&(*remote)).await?.contains_key(FILE_B) {
+            return Err(format_err!("delete: {FILE_B} still listed on remote"));
+        }
+        self.sync(&(*local), &(*remote), stdout).await?;
+        if !self.remote_files(&(*remote)).await?.contains_key(FILE_B) {
+            return Err(format_err!("delete: {FILE_B} was not restored"));
+        }
+        stdout.send("delete sync ok");
+        Ok(())
+    }
+
+    /// index both sides, queue the differences and copy only the queue
+    /// entries belonging to this test
+    async fn sync(
+        &self,
+        local: &dyn FileListTrait,
+        remote: &dyn FileListTrait,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        local.index().await?;
+        remote.index().await?;
+        let violations = FileSync::compare_lists(local, remote, None, &self.pool).await?;
+        if let Some(violation) = violations.first() {
+            return Err(format_err!("{violation}"));
+        }
+        let mut entries = Vec::new();
+        let queued: Vec<FileSyncCache> = FileSyncCache::get_cache_list(&self.pool)
+            .await?
+            .try_collect()
+            .await?;
+        for entry in queued {
+            if self.is_ours(&entry.src_url) || self.is_ours(&entry.dst_url) {
+                entry.delete_cache_entry(&self.pool).await?;
+                entries.push(entry);
+            }
+        }
+        info!("selftest copying {} entries", entries.len());
+        let fsync = FileSync::new(self.config.clone());
+        fsync
+            .process_cache_entries(entries, &self.pool, stdout)
+            .await?;
+        remote.index().await?;
+        Ok(())
+    }
+
+    /// every queue entry of the test has the temporary remote prefix on one
+    /// side
+    fn is_ours(&self, url: &str) -> bool {
+        url.starts_with(self.remote_url.as_str())
+    }
+
+    async fn remote_files(
+        &self,
+        remote: &dyn FileListTrait,
+    ) -> Result<HashMap<StackString, FileInfo>, Error> {
+        remote
+            .load_file_list(false)
+            .await?
+            .into_iter()
+            .filter(|f| self.is_ours(&f.urlname))
+            .map(|f| {
+                let finfo: FileInfo = f.try_into()?;
+                Ok((finfo.filename.clone(), finfo))
+            })
+            .collect()
+    }
+
+    async fn cleanup(&self) -> Result<(), Error> {
+        let remote = FileList::from_url(&self.remote_url, &self.config, &self.pool).await?;
+        for finfo in self.remote_files(&(*remote)).await?.values() {
+            remote.delete(finfo).await?;
+        }
+        let local = FileList::from_url(&self.local_url, &self.config, &self.pool).await?;
+        for flist in &[&local, &remote] {
+            let baseurl = flist.get_baseurl().as_str();
+            for entry in flist.load_file_list(true).await? {
+                if entry.urlname.starts_with(baseurl) {
+                    entry.delete(&self.pool).await?;
+                }
+            }
+        }
+        if self.local_dir.exists() {
+            remove_dir_all(&self.local_dir).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, Session, Sftp};
+use stack_string::StackString;
+use std::{
+    fmt,
+    fs::File,
+    io,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::task::spawn_blocking;
+
+/// Timeout of any single sftp operation
+const SFTP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Size and mtime (seconds since the epoch) of a file found by
+/// `SftpInstance::walk`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SftpStat {
+    pub size: u64,
+    pub mtime: u64,
+}
+
+/// Access to a remote host over sftp (libssh2).  Unlike `SSHInstance` it
+/// doesn't shell out to ssh / scp and doesn't need anything installed on the
+/// remote beyond the sftp subsystem.  The connection is opened on first use
+/// and reopened after a failure.
+#[derive(Clone)]
+pub struct SftpInstance {
+    pub user: StackString,
+    pub host: StackString,
+    pub port: u16,
+    pub identity_file: Option<PathBuf>,
+    sftp: Arc<Mutex<Option<Sftp>>>,
+}
+
+impl fmt::Debug for SftpInstance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SftpInstance")
+            .field("user", &self.user)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("identity_file", &self.identity_file)
+            .finish()
+    }
+}
+
+impl SftpInstance {
+    #[must_use]
+    pub fn new(user: &str, host: &str, port: u16, identity_file: Option<PathBuf>) -> Self {
+        Self {
+            user: user.into(),
+            host: host.into(),
+            port,
+            identity_file,
+            sftp: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn connect(&self) -> Result<Sftp, Error> {
+        debug!("sftp connect {}@{}:{}", self.user, self.host, self.port);
+        let tcp = TcpStream::connect((self.host.as_str(), self.port))?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(SFTP_TIMEOUT.as_millis() as u32);
+        session.handshake()?;
+        self.check_host_key(&session)?;
+        if let Some(identity_file) = &self.identity_file {
+            session.userauth_pubkey_file(&self.user, None, identity_file, None)?;
+        } else {
+            session.userauth_agent(&self.user)?;
+        }
+        if !session.authenticated() {
+            return Err(format_err!(
+                "Authentication failed for {}@{}",
+                self.user,
+                self.host
+            ));
+        }
+        session.sftp().map_err(Into::into)
+    }
+
+    /// Only hosts already in `~/.ssh/known_hosts` are accepted, the same as
+    /// `ssh -o BatchMode=yes`
+    fn check_host_key(&self, session: &Session) -> Result<(), Error> {
+        let known_hosts_file = dirs::home_dir()
+            .ok_or_else(|| format_err!("No home directory"))?
+            .join(".ssh")
+            .join("known_hosts");
+        let mut known_hosts = session.known_hosts()?;
+        if known_hosts_file.exists() {
+            known_hosts.read_file(&known_hosts_file, KnownHostFileKind::OpenSSH)?;
+        }
+        let (key, _) = session
+            .host_key()
+            .ok_or_else(|| format_err!("No host key from {}", self.host))?;
+        match known_hosts.check_port(&self.host, self.port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::NotFound => Err(format_err!(
+                "{} is not in {}, connect with ssh once to add it",
+                self.host,
+                known_hosts_file.display()
+            )),
+            CheckResult::Mismatch => Err(format_err!("Host key of {} has changed", self.host)),
+            CheckResult::Failure => Err(format_err!("Failed to check host key of {}", self.host)),
+        }
+    }
+
+    /// Run `f` against the sftp session on the blocking pool, a failed call
+    /// drops the session so that the next one reconnects
+    async fn with_sftp<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Sftp) -> Result<T, Error> + Send + 'static,
+    {
+        let instance = self.clone();
+        spawn_blocking(move || {
+            let mut sftp = instance
+                .sftp
+                .lock()
+                .map_err(|e| format_err!("sftp lock poisoned {e}"))?;
+            if sftp.is_none() {
+                *sftp = Some(instance.connect()?);
+            }
+            let result = sftp
+                .as_ref()
+                .ok_or_else(|| format_err!("No sftp session"))
+                .and_then(f);
+            if result.is_err() {
+                *sftp = None;
+            }
+            result
+        })
+        .await?
+    }
+
+    /// # Errors
+    /// Return error if the connection fails or `path` doesn't exist
+    pub async fn probe(&self, path: &Path) -> Result<(), Error> {
+        let path = path.to_path_buf();
+        self.with_sftp(move |sftp| {
+            sftp.stat(&path)?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Every regular file below `path`
+    /// # Errors
+    /// Return error if the connection fails or a directory can't be read
+    pub async fn walk(&self, path: &Path) -> Result<Vec<(PathBuf, SftpStat)>, Error> {
+        let path = path.to_path_buf();
+        self.with_sftp(move |sftp| {
+            let mut files = Vec::new();
+            let mut directories = vec![path];
+            while let Some(directory) = directories.pop() {
+                for (entry, stat) in sftp.readdir(&directory)? {
+                    if stat.is_dir() {
+                        directories.push(entry);
+                    } else if stat.is_file() {
+                        let stat = SftpStat {
+                            size: stat.size.unwrap_or(0),
+                            mtime: stat.mtime.unwrap_or(0),
+                        };
+                        files.push((entry, stat));
+                    }
+                }
+            }
+            Ok(files)
+        })
+        .await
+    }
+
+    /// Entries directly inside `path`
+    /// # Errors
+    /// Return error if the connection fails or `path` can't be read
+    pub async fn list_directory(&self, path: &Path) -> Result<Vec<PathBuf>, Error> {
+        let path = path.to_path_buf();
+        self.with_sftp(move |sftp| {
+            Ok(sftp
+                .readdir(&path)?
+                .into_iter()
+                .map(|(entry, _)| entry)
+                .collect())
+        })
+        .await
+    }
+
+    /// Copy `remote` to `local`, keeping the remote mtime
+    /// # Errors
+    /// Return error if the connection or either file fails
+    pub async fn download(&self, remote: &Path, local: &Path) -> Result<(), Error> {
+        let remote = remote.to_path_buf();
+        let local = local.to_path_buf();
+        self.with_sftp(move |sftp| {
+            let mut remote_file = sftp.open(&remote)?;
+            let mtime = remote_file.stat()?.mtime;
+            let mut local_file = File::create(&local)?;
+            io::copy(&mut remote_file, &mut local_file)?;
+            if let Some(mtime) = mtime {
+                local_file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime))?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// Copy `local` to `remote`, creating missing parent directories and
+    /// keeping the local mtime
+    /// # Errors
+    /// Return error if the connection or either file fails
+    pub async fn upload(&self, local: &Path, remote: &Path) -> Result<(), Error> {
+        let remote = remote.to_path_buf();
+        let local = local.to_path_buf();
+        self.with_sftp(move |sftp| {
+            if let Some(parent) = remote.parent() {
+                create_dir_all(sftp, parent)?;
+            }
+            let mut local_file = File::open(&local)?;
+            let mtime = local_file
+                .metadata()?
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs());
+            let mut remote_file = sftp.create(&remote)?;
+            io::copy(&mut local_file, &mut remote_file)?;
+            if let Some(mtime) = mtime {
+                let stat = FileStat {
+                    size: None,
+                    uid: None,
+                    gid: None,
+                    perm: None,
+                    atime: Some(mtime),
+                    mtime: Some(mtime),
+                };
+                sftp.setstat(&remote, stat)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    /// # Errors
+    /// Return error if the connection fails or a directory can't be created
+    pub async fn create_dir_all(&self, path: &Path) -> Result<(), Error> {
+        let path = path.to_path_buf();
+        self.with_sftp(move |sftp| create_dir_all(sftp, &path))
+            .await
+    }
+
+    /// # Errors
+    /// Return error if the connection or the rename fails
+    pub async fn rename(&self, src: &Path, dst: &Path) -> Result<(), Error> {
+        let src = src.to_path_buf();
+        let dst = dst.to_path_buf();
+        self.with_sftp(move |sftp| {
+            if let Some(parent) = dst.parent() {
+                create_dir_all(sftp, parent)?;
+            }
+            sftp.rename(&src, &dst, None).map_err(Into::into)
+        })
+        .await
+    }
+
+    /// Remove `path`, a file that is already gone isn't an error
+    /// # Errors
+    /// Return error if the connection or the delete fails
+    pub async fn remove_file(&self, path: &Path) -> Result<(), Error> {
+        let path = path.to_path_buf();
+        self.with_sftp(move |sftp| {
+            if sftp.stat(&path).is_ok() {
+                sftp.unlink(&path)?;
+            }
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn create_dir_all(sftp: &Sftp, path: &Path) -> Result<(), Error> {
+    let mut missing = Vec::new();
+    let mut current = Some(path);
+    while let Some(p) = current {
+        if p.as_os_str().is_empty() || sftp.stat(p).is_ok() {
+            break;
+        }
+        missing.push(p);
+        current = p.parent();
+    }
+    for p in missing.into_iter().rev() {
+        sftp.mkdir(p, 0o755)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use percent_encoding::percent_decode_str;
+use stack_string::{format_sstr, StackString};
+use std::{
+    convert::TryInto,
+    env,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+};
+use tokio::process::Command;
+use url::Url;
+
+use crate::url_wrapper::SmbUrl;
+
+/// `$XDG_RUNTIME_DIR`, or `/run/user/<uid>` of the user running the process
+/// when it's unset, gvfs mounts shares below it
+fn runtime_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        return Ok(dir.into());
+    }
+    let uid = std::fs::metadata("/proc/self")
+        .map_err(|e| format_err!("XDG_RUNTIME_DIR is unset and the uid is unknown: {e}"))?
+        .uid();
+    Ok(Path::new("/run/user").join(format_sstr!("{uid}").as_str()))
+}
+
+/// Access to an smb share through a gvfs mount managed with `gio mount`, so
+/// that shares don't need to be mounted ahead of time
+#[derive(Debug, Clone)]
+pub struct SmbInstance {
+    pub server: StackString,
+    pub share: StackString,
+    pub user: Option<StackString>,
+}
+
+impl SmbInstance {
+    /// # Errors
+    /// Return error if url isn't a valid smb url
+    pub fn from_url(url: &Url) -> Result<Self, Error> {
+        let url: SmbUrl = url.clone().try_into()?;
+        let share = percent_decode_str(url.share()).decode_utf8_lossy();
+        let user = Some(url.username())
+            .filter(|u| !u.is_empty())
+            .map(Into::into);
+        Ok(Self {
+            server: url.server().into(),
+            share: share.as_ref().into(),
+            user,
+        })
+    }
+
+    /// `smb://[<user>@]<server>/<share>`, the url passed to `gio mount`
+    #[must_use]
+    pub fn share_url(&self) -> StackString {
+        match &self.user {
+            Some(user) => format_sstr!("smb://{user}@{}/{}", self.server, self.share),
+            None => format_sstr!("smb://{}/{}", self.server, self.share),
+        }
+    }
+
+    /// gvfs lowercases server and share names when creating the mount point
+    /// # Errors
+    /// Return error if the runtime directory of the current user is unknown
+    pub fn mount_point(&self) -> Result<PathBuf, Error> {
+        let runtime_dir = runtime_dir()?;
+        let mut name = format_sstr!(
+            "smb-share:server={},share={}",
+            self.server.to_lowercase(),
+            self.share.to_lowercase()
+        );
+        if let Some(user) = &self.user {
+            name.push_str(&format_sstr!(",user={user}"));
+        }
+        Ok(runtime_dir.join("gvfs").join(name.as_str()))
+    }
+
+    /// Mount the share unless it's already mounted, returns the mount point
+    /// # Errors
+    /// Return error if `gio mount` fails
+    pub async fn mount(&self) -> Result<PathBuf, Error> {
+        let mount_point = self.mount_point()?;
+        if !mount_point.exists() {
+            let share_url = self.share_url();
+            debug!("gio mount {share_url}");
+            let status = Command::new("gio")
+                .args(["mount", share_url.as_str()])
+                .status()
+                .await?;
+            if !status.success() || !mount_point.exists() {
+                return Err(format_err!("Failed to mount {share_url}"));
+            }
+        }
+        Ok(mount_point)
+    }
+
+    /// Path of `url` below the mount point
+    /// # Errors
+    /// Return error if `url` isn't on this share
+    pub fn local_path(&self, url: &Url) -> Result<PathBuf, Error> {
+        let url: SmbUrl = url.clone().try_into()?;
+        let share = percent_decode_str(url.share()).decode_utf8_lossy();
+        if !url.server().eq_ignore_ascii_case(&self.server) || share != self.share.as_str() {
+            return Err(format_err!("{url} is not on {}", self.share_url()));
+        }
+        let path = percent_decode_str(url.path_in_share()).decode_utf8_lossy();
+        Ok(self.mount_point()?.join(path.as_ref()))
+    }
+
+    /// Inverse of `local_path`
+    /// # Errors
+    /// Return error if `path` is not below the mount point
+    pub fn url_from_local_path(&self, path: &Path) -> Result<Url, Error> {
+        let mount_point = self.mount_point()?;
+        let relpath = path.strip_prefix(&mount_point)?;
+        let mut url: Url = self.share_url().parse()?;
+        let share_path = format_sstr!("/{}/{}", self.share, relpath.to_string_lossy());
+        url.set_path(&share_path);
+        Ok(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::smb_instance::SmbInstance;
+
+    #[test]
+    fn test_smb_instance_paths() -> Result<(), Error> {
+        let url: Url = "smb://user@fileserver/Media/Movies/a%20b.mkv".parse()?;
+        let smb = SmbInstance::from_url(&url)?;
+        assert_eq!(smb.share_url(), "smb://user@fileserver/Media");
+        let mount_point = smb.mount_point()?;
+        assert!(mount_point.ends_with("gvfs/smb-share:server=fileserver,share=media,user=user"));
+        let path = smb.local_path(&url)?;
+        assert_eq!(path, mount_point.join("Movies/a b.mkv"));
+        assert_eq!(smb.url_from_local_path(&path)?, url);
+
+        let other: Url = "smb://user@fileserver/Other/a.txt".parse()?;
+        assert!(smb.local_path(&other).is_err());
+        Ok(())
+    }
+}
@@ -1,19 +1,30 @@
 use anyhow::{format_err, Error};
 use log::{debug, error, info};
 use once_cell::sync::Lazy;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use smallvec::{smallvec, SmallVec};
 use stack_string::{format_sstr, StackString};
 use std::{collections::HashMap, process::Stdio};
+use time::{Duration, OffsetDateTime};
 use tokio::{
     io::{stdout, AsyncBufReadExt, AsyncWriteExt, BufReader},
     process::Command,
     sync::{Mutex, RwLock},
 };
-use url::Url;
+use url::{Host, Url};
+
+/// Characters left unescaped in the user part of a url
+const USERINFO: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
 
 static LOCK_CACHE: Lazy<RwLock<HashMap<StackString, Mutex<()>>>> =
     Lazy::new(|| RwLock::new(HashMap::new()));
 
+/// `user` is the decoded user name (empty for the ssh default), `host` a
+/// hostname or a bare ip address, ipv6 addresses without brackets
 #[derive(Debug, Clone)]
 pub struct SSHInstance {
     pub user: StackString,
@@ -34,32 +45,101 @@ impl SSHInstance {
     /// # Errors
     /// Return error if db query fails
     pub async fn from_url(url: &Url) -> Result<Self, Error> {
-        let host = url.host_str().ok_or_else(|| format_err!("Parse error"))?;
-        let port = url.port().unwrap_or(22);
-        let user = url.username();
-        Ok(Self::new(user, host, port).await)
+        let (user, host, port) = ssh_url_parts(url)?;
+        Ok(Self::new(&user, &host, port.unwrap_or(22)).await)
+    }
+
+    /// Host as written in an scp `host:path` argument, ipv6 addresses are
+    /// bracketed so the colons aren't taken for the path separator
+    fn scp_host(&self) -> StackString {
+        if self.host.contains(':') {
+            format_sstr!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        }
     }
 
+    /// `user@host:port` part of an `ssh://` url for this instance
     #[must_use]
-    pub fn get_ssh_str(&self, path: &str) -> StackString {
-        if self.port == 22 {
-            format_sstr!("{}@{}:{}", self.user, self.host, path)
+    pub fn get_url_authority(&self) -> StackString {
+        let user = if self.user.is_empty() {
+            StackString::new()
         } else {
-            format_sstr!("-p {} {}@{}:{}", self.port, self.user, self.host, path)
+            format_sstr!("{}@", utf8_percent_encode(&self.user, USERINFO))
+        };
+        let port = if self.port == 22 {
+            StackString::new()
+        } else {
+            format_sstr!(":{}", self.port)
+        };
+        format_sstr!("{user}{}{port}", self.scp_host())
+    }
+
+    /// Remote `host:path` argument of scp, the user and port are passed as
+    /// options by `run_scp`
+    #[must_use]
+    pub fn get_ssh_str(&self, path: &str) -> StackString {
+        format_sstr!("{}:{path}", self.scp_host())
+    }
+
+    /// Arguments of ssh up to and including the destination host, the user
+    /// goes through `-l` so names containing `@` or `:` survive
+    #[must_use]
+    pub fn get_ssh_username_host(&self) -> SmallVec<[StackString; 6]> {
+        let mut args: SmallVec<[StackString; 6]> = smallvec!["-C".into()];
+        if self.port != 22 {
+            args.push("-p".into());
+            args.push(format_sstr!("{}", self.port));
+        }
+        if !self.user.is_empty() {
+            args.push("-l".into());
+            args.push(self.user.clone());
         }
+        args.push(self.host.clone());
+        args
     }
 
+    /// Options of scp, which takes the port as `-P`
     #[must_use]
-    pub fn get_ssh_username_host(&self) -> SmallVec<[StackString; 4]> {
-        let user_str = format_sstr!("{}@{}", self.user, self.host);
-        let port_str = format_sstr!("{}", self.port);
-        if self.port == 22 {
-            smallvec!["-C".into(), user_str,]
+    pub fn get_scp_options(&self) -> SmallVec<[StackString; 4]> {
+        let mut args: SmallVec<[StackString; 4]> = SmallVec::new();
+        if self.port != 22 {
+            args.push("-P".into());
+            args.push(format_sstr!("{}", self.port));
+        }
+        if !self.user.is_empty() {
+            args.push("-o".into());
+            args.push(format_sstr!("User={}", self.user));
+        }
+        args
+    }
+
+    /// # Errors
+    /// Return error if the remote host doesn't respond
+    pub async fn probe(&self) -> Result<(), Error> {
+        let output = self.run_command_stream_stdout("echo ok").await?;
+        if output.trim() == "ok" {
+            Ok(())
         } else {
-            smallvec!["-C".into(), "-p".into(), port_str, user_str,]
+            Err(format_err!(
+                "Unexpected response from {}: {output}",
+                self.host
+            ))
         }
     }
 
+    /// Offset of the remote clock relative to the local clock, the remote
+    /// `date +%s%N` is compared to the midpoint of the local request times
+    /// # Errors
+    /// Return error if ssh command fails or the output can't be parsed
+    pub async fn clock_offset(&self) -> Result<Duration, Error> {
+        let t0 = OffsetDateTime::now_utc();
+        let output = self.run_command_stream_stdout("date +%s%N").await?;
+        let t1 = OffsetDateTime::now_utc();
+        let remote = parse_remote_timestamp(&output)?;
+        Ok(remote - (t0 + (t1 - t0) / 2))
+    }
+
     /// # Errors
     /// Return error if db query fails
     pub async fn run_command_stream_stdout(&self, cmd: &str) -> Result<StackString, Error> {
@@ -67,7 +147,7 @@ impl SSHInstance {
             let _guard = host_lock.lock().await;
             info!("cmd {}", cmd);
             let user_host = self.get_ssh_username_host();
-            let mut args: SmallVec<[&str; 5]> = user_host.iter().map(StackString::as_str).collect();
+            let mut args: SmallVec<[&str; 7]> = user_host.iter().map(StackString::as_str).collect();
             args.push(cmd);
             let process = Command::new("ssh").args(&args).output().await?;
             if process.status.success() {
@@ -88,7 +168,7 @@ impl SSHInstance {
             let _guard = host_lock.lock();
             debug!("run_command_print_stdout cmd {}", cmd);
             let user_host = self.get_ssh_username_host();
-            let mut args: SmallVec<[&str; 4]> = user_host.iter().map(StackString::as_str).collect();
+            let mut args: SmallVec<[&str; 7]> = user_host.iter().map(StackString::as_str).collect();
             args.push(cmd);
             let mut command = Command::new("ssh")
                 .args(&args)
@@ -101,12 +181,12 @@ impl SSHInstance {
                 .ok_or_else(|| format_err!("No stdout"))?;
             let mut reader = BufReader::new(stdout_handle);
 
+            let authority = self.get_url_authority();
             let mut line = String::new();
             let mut stdout = stdout();
             while let Ok(bytes) = reader.read_line(&mut line).await {
                 if bytes > 0 {
-                    let user_host = &user_host[user_host.len() - 1];
-                    let buf = format_sstr!("ssh://{user_host}{line}");
+                    let buf = format_sstr!("ssh://{authority}{line}");
                     stdout.write_all(buf.as_bytes()).await?;
                 } else {
                     break;
@@ -122,7 +202,7 @@ impl SSHInstance {
     /// Return error if db query fails
     pub async fn run_command_ssh(&self, cmd: &str) -> Result<(), Error> {
         let user_host = self.get_ssh_username_host();
-        let mut args: SmallVec<[&str; 4]> = user_host.iter().map(StackString::as_str).collect();
+        let mut args: SmallVec<[&str; 7]> = user_host.iter().map(StackString::as_str).collect();
         args.push(cmd);
         if let Some(host_lock) = LOCK_CACHE.read().await.get(&self.host) {
             let _guard = host_lock.lock().await;
@@ -156,6 +236,127 @@ impl SSHInstance {
     /// # Errors
     /// Return error if db query fails
     pub async fn run_scp(&self, arg0: &str, arg1: &str) -> Result<(), Error> {
-        self.run_command("scp", &["-B", "-q", arg0, arg1]).await
+        let options = self.get_scp_options();
+        let mut args: SmallVec<[&str; 8]> = smallvec!["-B", "-q"];
+        args.extend(options.iter().map(StackString::as_str));
+        args.push(arg0);
+        args.push(arg1);
+        self.run_command("scp", &args).await
+    }
+}
+
+/// Decoded user (empty if the url has none), host without ipv6 brackets and
+/// port of an `ssh://` url
+/// # Errors
+/// Return error if the url has no host or the user isn't valid utf-8
+pub fn ssh_url_parts(url: &Url) -> Result<(StackString, StackString, Option<u16>), Error> {
+    let host: StackString = match url.host() {
+        Some(Host::Ipv6(addr)) => format_sstr!("{addr}"),
+        Some(Host::Ipv4(addr)) => format_sstr!("{addr}"),
+        Some(Host::Domain(domain)) => domain.into(),
+        None => return Err(format_err!("No host in {url}")),
+    };
+    let user = percent_decode_str(url.username()).decode_utf8()?;
+    Ok((user.as_ref().into(), host, url.port()))
+}
+
+/// Parse the output of `date +%s%N`, falling back to whole seconds for
+/// `date` implementations (busybox) that don't support `%N`
+fn parse_remote_timestamp(output: &str) -> Result<OffsetDateTime, Error> {
+    let output = output.trim();
+    if let Some(secs) = output
+        .strip_suffix("%N")
+        .or_else(|| output.strip_suffix('N'))
+    {
+        let secs: i64 = secs.parse()?;
+        OffsetDateTime::from_unix_timestamp(secs).map_err(Into::into)
+    } else {
+        let nanos: i128 = output.parse()?;
+        OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::StackString;
+    use time::macros::datetime;
+    use url::Url;
+
+    use crate::ssh_instance::{parse_remote_timestamp, ssh_url_parts, SSHInstance};
+
+    async fn instance(url: &str) -> Result<SSHInstance, Error> {
+        let url: Url = url.parse()?;
+        SSHInstance::from_url(&url).await
+    }
+
+    fn joined(args: &[StackString]) -> String {
+        args.iter()
+            .map(StackString::as_str)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    #[tokio::test]
+    async fn test_ssh_arguments() -> Result<(), Error> {
+        let ssh = instance("ssh://ubuntu@cloud.ddboline.net/home/ubuntu").await?;
+        assert_eq!(
+            joined(&ssh.get_ssh_username_host()),
+            "-C -l ubuntu cloud.ddboline.net"
+        );
+        assert_eq!(joined(&ssh.get_scp_options()), "-o User=ubuntu");
+        assert_eq!(
+            ssh.get_ssh_str("/tmp/a b").as_str(),
+            "cloud.ddboline.net:/tmp/a b"
+        );
+        assert_eq!(
+            ssh.get_url_authority().as_str(),
+            "ubuntu@cloud.ddboline.net"
+        );
+
+        let ssh = instance("ssh://admin@[2001:db8::1]:2222/srv").await?;
+        assert_eq!(ssh.host.as_str(), "2001:db8::1");
+        assert_eq!(
+            joined(&ssh.get_ssh_username_host()),
+            "-C -p 2222 -l admin 2001:db8::1"
+        );
+        assert_eq!(joined(&ssh.get_scp_options()), "-P 2222 -o User=admin");
+        assert_eq!(ssh.get_ssh_str("/srv/x").as_str(), "[2001:db8::1]:/srv/x");
+        assert_eq!(ssh.get_url_authority().as_str(), "admin@[2001:db8::1]:2222");
+
+        let ssh = instance("ssh://first.last%40corp.com@10.0.0.5:2200/data").await?;
+        assert_eq!(ssh.user.as_str(), "first.last@corp.com");
+        assert_eq!(ssh.host.as_str(), "10.0.0.5");
+        assert_eq!(
+            joined(&ssh.get_ssh_username_host()),
+            "-C -p 2200 -l first.last@corp.com 10.0.0.5"
+        );
+        assert_eq!(
+            ssh.get_url_authority().as_str(),
+            "first.last%40corp.com@10.0.0.5:2200"
+        );
+
+        let ssh = instance("ssh://[::1]/tmp").await?;
+        assert_eq!(joined(&ssh.get_ssh_username_host()), "-C ::1");
+        assert!(ssh.get_scp_options().is_empty());
+        assert_eq!(ssh.get_url_authority().as_str(), "[::1]");
+
+        let url: Url = "ssh:/no/host".parse()?;
+        assert!(ssh_url_parts(&url).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_remote_timestamp() -> Result<(), Error> {
+        assert_eq!(
+            parse_remote_timestamp("1700000000123456789\n")?,
+            datetime!(2023-11-14 22:13:20.123456789 UTC)
+        );
+        assert_eq!(
+            parse_remote_timestamp("1700000000N\n")?,
+            datetime!(2023-11-14 22:13:20 UTC)
+        );
+        assert!(parse_remote_timestamp("garbage").is_err());
+        Ok(())
     }
 }
@@ -0,0 +1,182 @@
+use anyhow::{format_err, Error};
+use aws_sdk_s3::types::StorageClass;
+use stack_string::StackString;
+use std::fmt;
+
+/// Storage classes whose objects can't be read until a temporary copy has
+/// been restored with `RestoreObject`
+pub const ARCHIVE_STORAGE_CLASSES: [&str; 2] = ["GLACIER", "DEEP_ARCHIVE"];
+
+/// Retrieval tiers accepted by `RestoreObject`
+pub const RESTORE_TIERS: [&str; 3] = ["Expedited", "Standard", "Bulk"];
+
+/// True for objects that have to be restored before they can be downloaded,
+/// `GLACIER_IR` is read like any other class
+#[must_use]
+pub fn is_archived(storage_class: &str) -> bool {
+    ARCHIVE_STORAGE_CLASSES.contains(&storage_class)
+}
+
+/// Check that `s` is a storage class s3 accepts on upload, e.g.
+/// `STANDARD_IA`
+/// # Errors
+/// Return error if `s` isn't a known storage class
+pub fn parse_storage_class(s: &str) -> Result<StackString, Error> {
+    if StorageClass::values().contains(&s) {
+        Ok(s.into())
+    } else {
+        Err(format_err!(
+            "Invalid storage class {s}, expected one of {}",
+            StorageClass::values().join(", ")
+        ))
+    }
+}
+
+/// Check that `s` is a retrieval tier `RestoreObject` accepts, e.g. `Bulk`
+/// # Errors
+/// Return error if `s` isn't one of `RESTORE_TIERS`
+pub fn parse_restore_tier(s: &str) -> Result<StackString, Error> {
+    if RESTORE_TIERS.contains(&s) {
+        Ok(s.into())
+    } else {
+        Err(format_err!(
+            "Invalid restore tier {s}, expected one of {}",
+            RESTORE_TIERS.join(", ")
+        ))
+    }
+}
+
+/// Restore status of an archived object, from the `x-amz-restore` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreState {
+    /// No restore was requested, or the restored copy has expired
+    NotRequested,
+    /// A restore was requested and hasn't finished yet
+    InProgress,
+    /// A temporary copy is readable until it expires
+    Restored,
+}
+
+impl RestoreState {
+    /// `ongoing-request="true"` while a restore runs, `ongoing-request="false",
+    /// expiry-date="..."` once the copy is readable, no header otherwise
+    #[must_use]
+    pub fn from_header(restore: Option<&str>) -> Self {
+        match restore {
+            Some(header) if header.contains(r#"ongoing-request="true""#) => Self::InProgress,
+            Some(header) if header.contains(r#"ongoing-request="false""#) => Self::Restored,
+            _ => Self::NotRequested,
+        }
+    }
+
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::NotRequested => "not restored",
+            Self::InProgress => "restore in progress",
+            Self::Restored => "restored",
+        }
+    }
+}
+
+impl fmt::Display for RestoreState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Returned by `copy_from` for an object in an archive storage class that
+/// can't be read yet.  `FileSync` queues the copy again while a restore is
+/// pending and skips it otherwise, neither is reported as a failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchivedObject {
+    pub url: StackString,
+    pub storage_class: StackString,
+    pub state: RestoreState,
+}
+
+impl ArchivedObject {
+    /// Whether a restore was requested, in this run or an earlier one, so
+    /// that the object becomes readable later
+    #[must_use]
+    pub fn is_pending(&self) -> bool {
+        self.state == RestoreState::InProgress
+    }
+}
+
+impl fmt::Display for ArchivedObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} is in {}, {}",
+            self.url, self.storage_class, self.state
+        )
+    }
+}
+
+impl std::error::Error for ArchivedObject {}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::storage_class::{
+        is_archived, parse_restore_tier, parse_storage_class, ArchivedObject, RestoreState,
+    };
+
+    #[test]
+    fn test_is_archived() {
+        assert!(is_archived("GLACIER"));
+        assert!(is_archived("DEEP_ARCHIVE"));
+        assert!(!is_archived("GLACIER_IR"));
+        assert!(!is_archived("STANDARD_IA"));
+    }
+
+    #[test]
+    fn test_parse_storage_class() {
+        assert_eq!(
+            parse_storage_class("STANDARD_IA").unwrap().as_str(),
+            "STANDARD_IA"
+        );
+        assert!(parse_storage_class("standard_ia").is_err());
+        assert!(parse_storage_class("COLD").is_err());
+    }
+
+    #[test]
+    fn test_parse_restore_tier() {
+        assert_eq!(parse_restore_tier("Bulk").unwrap().as_str(), "Bulk");
+        assert!(parse_restore_tier("bulk").is_err());
+        assert!(parse_restore_tier("Cheap").is_err());
+    }
+
+    #[test]
+    fn test_restore_state() {
+        assert_eq!(RestoreState::from_header(None), RestoreState::NotRequested);
+        assert_eq!(
+            RestoreState::from_header(Some(r#"ongoing-request="true""#)),
+            RestoreState::InProgress
+        );
+        assert_eq!(
+            RestoreState::from_header(Some(
+                r#"ongoing-request="false", expiry-date="Fri, 21 Dec 2012 00:00:00 GMT""#
+            )),
+            RestoreState::Restored
+        );
+    }
+
+    #[test]
+    fn test_archived_object_downcast() {
+        let archived = ArchivedObject {
+            url: "s3://bucket/key".into(),
+            storage_class: "GLACIER".into(),
+            state: RestoreState::InProgress,
+        };
+        let e: Error = archived.clone().into();
+        assert_eq!(
+            e.to_string(),
+            "s3://bucket/key is in GLACIER, restore in progress"
+        );
+        assert_eq!(e.downcast_ref::<ArchivedObject>(), Some(&archived));
+        assert!(archived.is_pending());
+    }
+}
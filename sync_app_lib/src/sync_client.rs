@@ -12,7 +12,10 @@ use time::{Date, OffsetDateTime};
 use tokio::{task::spawn_blocking, time::timeout};
 use uuid::Uuid;
 
-use crate::{config::Config, local_session::LocalSession, reqwest_session::ReqwestSession};
+use crate::{
+    config::Config, http_client::AuthenticatedClient, local_session::LocalSession,
+    reqwest_session::ReqwestSession,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone, Copy)]
 pub struct Pagination {
@@ -39,7 +42,7 @@ impl SyncClient {
     /// Returns error if creation of client fails
     pub fn new<T: AsRef<Path>>(config: Config, exe_path: T) -> Result<Self, Error> {
         Ok(Self {
-            remote_session: ReqwestSession::new(true)?,
+            remote_session: ReqwestSession::new(true, &config.proxy_config("sync_client"))?,
             local_session: LocalSession::new(exe_path),
             config,
         })
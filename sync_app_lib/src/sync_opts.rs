@@ -1,15 +1,27 @@
 use anyhow::{format_err, Error};
 use clap::Parser;
-use futures::{future::try_join_all, TryStreamExt};
-use itertools::Itertools;
-use log::{debug, info};
-use refinery::embed_migrations;
+use futures::{
+    future::{self, try_join_all},
+    stream, StreamExt, TryStreamExt,
+};
+use log::{debug, info, warn, LevelFilter};
+use serde_json::{json, Value};
 use stack_string::{format_sstr, StackString};
-use std::{convert::TryInto, path::PathBuf};
+use std::{
+    collections::HashMap,
+    convert::TryInto,
+    env,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Instant,
+};
 use stdout_channel::StdoutChannel;
+use time::{macros::format_description, Date, Duration as TimeDuration, OffsetDateTime};
 use tokio::{
-    fs::File,
+    fs::{read_to_string, File},
     io::{stdout as tokio_stdout, AsyncWrite, AsyncWriteExt},
+    task::spawn,
+    time::{timeout, Duration},
 };
 use url::Url;
 use uuid::Uuid;
@@ -17,30 +29,161 @@ use uuid::Uuid;
 use gdrive_lib::date_time_wrapper::DateTimeWrapper;
 
 use crate::{
+    byte_accounting::RunMeter,
+    cache_edit::{BulkAction, CacheFilter},
     calendar_sync::CalendarSync,
+    checksum_hook::notify_checksum_webhooks,
+    compression::Codec,
     config::Config,
-    file_info::FileInfo,
+    config_audit::cli_user,
+    conflict::ConflictPolicy,
+    cost_estimate::CostEstimate,
+    delta::{file_delta, file_signature, patch_file, Signature},
+    encryption::Encryption,
+    event_hook::EventHook,
+    file_info::{FileInfo, ServiceSession},
     file_list::{group_urls, FileList},
+    file_list_gdrive::GDriveSessions,
+    file_list_local::canonical_basepath,
+    file_list_s3::FileListS3,
     file_service::FileService,
     file_sync::{FileSync, FileSyncAction},
     garmin_sync::GarminSync,
-    models::{FileInfoCache, FileSyncCache, FileSyncConfig},
+    hash_manifest::{export_hashes, import_hashes, parse_manifest, HashFormat},
+    layout::DestinationLayout,
+    link_farm::{mirror_generation, prune_generations, DEFAULT_GENERATIONS},
+    log_routing::{with_log_scope, LogScope},
+    models::{
+        ConfigRunSummary, ExclusionReason, FileInfoCache, FileSyncCache, FileSyncConfig,
+        GDriveDuplicate, GDriveExclusion, MaintenanceMode, ResumableTransfer, RunParameters,
+        RunTransferBytes, ServiceSessionEntry, SessionUsage, SyncConflict, SyncJobStatus,
+        VirtualRootMember,
+    },
     movie_sync::MovieSync,
+    ownership::OwnershipMap,
+    pagination::{Pagination, SortKey},
+    partial::track_partial,
     pgpool::PgPool,
+    progress::{render_progress, ProgressChannel},
+    query_stats::QueryStats,
+    retention::apply_retention,
+    run_summary::{next_run_column, status_column},
+    s3_instance::{is_kms_sse, parse_sse},
+    schema::{ensure_schema, run_migrations, SchemaStatus},
     security_sync::SecuritySync,
+    self_test::SelfTest,
+    storage_class::parse_storage_class,
+    trash::{purge_local_trash, purge_s3_trash, DeletionPolicy},
+    url_wrapper::validate_url,
+    usage_trend::UsageTrend,
+    verify::{fsck, verify_sample},
+    virtual_root::{config_pairs, virtual_url, VirtualRoot},
+    watch::watch_configs,
     weather_sync::WeatherSync,
 };
 
-embed_migrations!("../migrations");
-
 fn action_from_str(s: &str) -> Result<FileSyncAction, String> {
     s.parse().map_err(|e| format!("{e}"))
 }
 
 fn url_from_str(s: &str) -> Result<Url, String> {
+    let url: Url = s.parse().map_err(|e| format!("{e}"))?;
+    validate_url(url).map_err(|e| format!("{e}"))
+}
+
+fn conflict_policy_from_str(s: &str) -> Result<ConflictPolicy, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn deletion_policy_from_str(s: &str) -> Result<DeletionPolicy, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn dst_layout_from_str(s: &str) -> Result<DestinationLayout, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn log_level_from_str(s: &str) -> Result<LevelFilter, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn storage_class_from_str(s: &str) -> Result<StackString, String> {
+    parse_storage_class(s).map_err(|e| format!("{e}"))
+}
+
+fn sort_key_from_str(s: &str) -> Result<SortKey, String> {
+    s.parse().map_err(|e| format!("{e}"))
+}
+
+fn sse_from_str(s: &str) -> Result<StackString, String> {
+    parse_sse(s).map_err(|e| format!("{e}"))
+}
+
+fn hash_format_from_str(s: &str) -> Result<HashFormat, String> {
     s.parse().map_err(|e| format!("{e}"))
 }
 
+fn compression_from_str(s: &str) -> Result<StackString, String> {
+    if s != "none" {
+        s.parse::<Codec>().map_err(|e| format!("{e}"))?;
+    }
+    Ok(s.into())
+}
+
+/// `None` for `none`, otherwise the codec named by `--compression`
+fn compression_codec(s: &str) -> Result<Option<Codec>, Error> {
+    if s == "none" {
+        Ok(None)
+    } else {
+        s.parse().map(Some)
+    }
+}
+
+/// Path of a `file://` url, link farms and delta transfers only work on
+/// local disk
+fn local_path(url: &Url) -> Result<PathBuf, Error> {
+    if url.scheme() != "file" {
+        return Err(format_err!("Not a local path {url}"));
+    }
+    url.to_file_path()
+        .map_err(|e| format_err!("Parse failure {e:?}"))
+}
+
+/// Seconds, or a number followed by `s`, `m` or `h` (e.g. `90m`, `1h30m`)
+fn runtime_from_str(s: &str) -> Result<Duration, String> {
+    if s.is_empty() {
+        return Err("Empty runtime".into());
+    }
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            _ => return Err(format!("Invalid runtime {s}")),
+        };
+        let n: u64 = number.parse().map_err(|_| format!("Invalid runtime {s}"))?;
+        secs += n * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("Invalid runtime {s}"));
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn date_from_str(s: &str) -> Result<Date, String> {
+    Date::parse(s, format_description!("[year]-[month]-[day]")).map_err(|e| format!("{e}"))
+}
+
 #[derive(Parser, Debug)]
 pub struct SyncOpts {
     #[clap(value_parser = action_from_str)]
@@ -48,7 +191,20 @@ pub struct SyncOpts {
     /// `list` or `ls`, `delete` or `rm`, `move` or `mv`, `ser` or
     /// `serialize`, `add` or `add_config`, `show`, `show_cache`
     /// `sync_garmin`, `sync_movie`, `sync_calendar`, `show_config`,
-    /// `sync_all`, `run-migrations`, `sync_weather`
+    /// `sync_all`, `run-migrations`, `sync_weather`, `restore`,
+    /// `dedup_cache`, `tag` or `tag_config`, `enable`, `disable`,
+    /// `maintenance_on`, `maintenance_off`, `requeue`, `ignore_errors`,
+    /// `transfer-worker`, `migrate_sessions`, `rename_session`, `selftest`,
+    /// `map_owner`, `trend`, `cost-estimate`, `exclusions`,
+    /// `export_exclusions`, `import_exclusions`, `cache_delete`,
+    /// `cache_approve`, `cache_requeue`, `retention`, `show_runs`,
+    /// `snapshot`, `conflict_policy`, `conflicts`, `resolve_conflict`,
+    /// `backfill_checksums`, `verify`, `virtual_root`, `scope_sessions`,
+    /// `purge_trash`, `compression`, `watch`, `max_index_age`,
+    /// `deletion_policy`, `link_farm`, `prune_generations`, `dst_layout`,
+    /// `signature`, `delta`, `patch`, `export-hashes`, `import-hashes`,
+    /// `abort-stale-uploads`, `log_level`, `storage_class`, `fsck`, `sse`,
+    /// `gdrive_duplicates`
     pub action: FileSyncAction,
     #[clap(short = 'u', long = "urls", value_parser = url_from_str)]
     pub urls: Vec<Url>,
@@ -56,12 +212,135 @@ pub struct SyncOpts {
     pub offset: Option<usize>,
     #[clap(short = 'l', long = "limit")]
     pub limit: Option<usize>,
+    /// Order of `list`, `show`, `show_config`, `ser`, `show_runs`,
+    /// `conflicts` and `exclusions` output: `path`,
+    /// `name`, `mtime` or `size`, applied before `--offset/--limit`.  Paths
+    /// compare byte-wise, `list` and `ser` default to `path`.  `list` reads
+    /// the cache rather than the backend for any other key
+    #[clap(long, value_parser = sort_key_from_str)]
+    pub sort: Option<SortKey>,
     #[clap(short = 'n', long = "name")]
     pub name: Option<StackString>,
     #[clap(short = 'd', long)]
     pub show_deleted: bool,
     #[clap(short = 'f', long)]
     pub filename: Option<PathBuf>,
+    /// Restore files as of this date (YYYY-MM-DD), with `trend` the start
+    /// of the report (default 90 days ago)
+    #[clap(long = "as-of", value_parser = date_from_str)]
+    pub as_of: Option<Date>,
+    #[clap(long)]
+    pub dry_run: bool,
+    /// Tag(s) used to select configs, or to set on `add`/`tag`
+    #[clap(short = 't', long = "tag")]
+    pub tags: Vec<StackString>,
+    /// Error suppression rule(s) to set on `add`/`ignore_errors`, either a
+    /// url glob or `error:<message substring>`
+    #[clap(long = "ignore")]
+    pub ignore_errors: Vec<StackString>,
+    /// Ownership mapping(s) to set on `add`/`map_owner`, `user:<src>=<dst>`
+    /// or `group:<src>=<dst>`, `*` as the source name is the fallback
+    #[clap(long = "owner")]
+    pub ownership_map: Vec<StackString>,
+    /// Read-only snapshot of the local source directory to set on
+    /// `add`/`snapshot` (e.g. `/data/.zfs/snapshot/nightly/photos`), files
+    /// are read from the snapshot but recorded under the source url,
+    /// `snapshot` without it clears the setting
+    #[clap(long = "snapshot")]
+    pub snapshot_path_prefix: Option<StackString>,
+    /// Conflict policy to set on `add`/`conflict_policy`: `newer-wins`,
+    /// `larger-wins`, `keep-both` or `manual` (the default, conflicts are
+    /// listed by `conflicts` and settled with `resolve_conflict`)
+    #[clap(long = "conflict-policy", value_parser = conflict_policy_from_str)]
+    pub conflict_policy: Option<ConflictPolicy>,
+    /// With `transfer-worker`, exit once the queue is empty
+    #[clap(long)]
+    pub once: bool,
+    /// Url prefix or glob selecting queue entries for `cache_delete`,
+    /// `cache_approve` and `cache_requeue`, with `--dry-run` only the
+    /// matching entries are counted
+    #[clap(long)]
+    pub pattern: Option<StackString>,
+    /// Show a live count of the files scanned and transferred on stderr
+    #[clap(long)]
+    pub progress: bool,
+    /// With `delete`, remove files permanently instead of moving them to
+    /// the trash
+    #[clap(long)]
+    pub hard: bool,
+    /// Don't apply pending database migrations on startup (also set by
+    /// `NO_MIGRATE`), a schema newer than this binary is still an error
+    #[clap(long)]
+    pub no_migrate: bool,
+    /// Codec to set on `add`/`compression`, `zstd`, `gzip` or `none`, files
+    /// uploaded to the config's cloud destination are compressed with it
+    #[clap(long, value_parser = compression_from_str)]
+    pub compression: Option<StackString>,
+    /// Smallest file compressed with `--compression`, in bytes
+    #[clap(long)]
+    pub compression_min_size: Option<i64>,
+    /// With `copy`, copy every file under the first url to the second,
+    /// comparing both listings in memory
+    #[clap(short = 'r', long)]
+    pub recursive: bool,
+    /// Run without postgres, only `copy` and `list` are available and
+    /// nothing is cached or recorded
+    #[clap(long)]
+    pub no_db: bool,
+    /// Seconds, with `add`/`max_index_age` `sync` skips indexing a side of
+    /// the config indexed more recently than this
+    #[clap(long)]
+    pub max_index_age: Option<i64>,
+    /// Deletion policy to set on `add`/`deletion_policy`: `keep` (the
+    /// default), `trash` or `delete`, applied by `sync` to the copy of a file
+    /// deleted or trashed on the other side
+    #[clap(long = "deletion-policy", value_parser = deletion_policy_from_str)]
+    pub deletion_policy: Option<DeletionPolicy>,
+    /// With `prune_generations`, the number of `link_farm` generations to
+    /// keep (default 7)
+    #[clap(long)]
+    pub keep: Option<usize>,
+    /// Destination layout to set on `add`/`dst_layout`: `flat` (the
+    /// default), `yearly` or `monthly`, the latter file new copies under
+    /// `YYYY/` or `YYYY/MM/` by the source file's modification time
+    #[clap(long = "dst-layout", value_parser = dst_layout_from_str)]
+    pub dst_layout: Option<DestinationLayout>,
+    /// Manifest format of `export-hashes`: `md5sum` (the default), `sha1sum`
+    /// or `bsd`, `import-hashes` reads any of them
+    #[clap(long = "hash-format", value_parser = hash_format_from_str)]
+    pub hash_format: Option<HashFormat>,
+    /// Log level to set on `add`/`log_level` (`error` ... `trace`), used for
+    /// the config instead of `RUST_LOG` while it is indexed and synced
+    #[clap(long = "log-level", value_parser = log_level_from_str)]
+    pub log_level: Option<LevelFilter>,
+    /// Time budget of `proc` and `transfer-worker`, e.g. `6h` or `90m`: no
+    /// transfer is started after it runs out, running ones are finished, the
+    /// rest stay queued for the next run and the run ends as `partial`
+    #[clap(long = "max-runtime", value_parser = runtime_from_str)]
+    pub max_runtime: Option<Duration>,
+    /// S3 storage class to set on `add`/`storage_class`, e.g. `STANDARD_IA`
+    /// or `GLACIER`, files uploaded to the config's s3 destination are
+    /// written in it, `storage_class` without it reverts to the bucket
+    /// default
+    #[clap(long = "storage-class", value_parser = storage_class_from_str)]
+    pub storage_class: Option<StackString>,
+    /// Server side encryption to set on `add`/`sse`, `AES256` or `aws:kms`,
+    /// in place of the `[s3.<bucket>]` one, `sse` without it reverts to
+    /// that
+    #[clap(long, value_parser = sse_from_str)]
+    pub sse: Option<StackString>,
+    /// KMS key ARN used with `--sse aws:kms`
+    #[clap(long = "sse-kms-key-id")]
+    pub sse_kms_key_id: Option<StackString>,
+    /// With `fsck`, remove the entries of missing files and lift the
+    /// tombstones of files back on disk unchanged, everything else is left
+    /// for the next index
+    #[clap(long)]
+    pub repair: bool,
+    /// Service session (e.g. `user@gmail.com`) `gdrive_duplicates` is
+    /// limited to, every session without it
+    #[clap(long)]
+    pub session: Option<StackString>,
 }
 
 impl Default for SyncOpts {
@@ -71,9 +350,38 @@ impl Default for SyncOpts {
             urls: Vec::new(),
             offset: None,
             limit: None,
+            sort: None,
             name: None,
             show_deleted: false,
             filename: None,
+            as_of: None,
+            dry_run: false,
+            tags: Vec::new(),
+            ignore_errors: Vec::new(),
+            ownership_map: Vec::new(),
+            snapshot_path_prefix: None,
+            conflict_policy: None,
+            once: false,
+            pattern: None,
+            progress: false,
+            hard: false,
+            no_migrate: false,
+            compression: None,
+            compression_min_size: None,
+            recursive: false,
+            no_db: false,
+            max_index_age: None,
+            deletion_policy: None,
+            keep: None,
+            dst_layout: None,
+            hash_format: None,
+            log_level: None,
+            max_runtime: None,
+            storage_class: None,
+            sse: None,
+            sse_kms_key_id: None,
+            repair: false,
+            session: None,
         }
     }
 }
@@ -95,24 +403,261 @@ impl SyncOpts {
         let opts = Self::parse();
         let config = Config::init_config()?;
         let pool = PgPool::new(&config.database_url)?;
+        QueryStats::global().set_slow_threshold(Duration::from_millis(config.slow_query_ms));
 
-        if opts.action == FileSyncAction::SyncAll {
-            for action in &[
-                FileSyncAction::Sync,
-                FileSyncAction::SyncGarmin,
-                FileSyncAction::SyncMovie,
-                FileSyncAction::SyncCalendar,
-                FileSyncAction::SyncSecurity,
-                FileSyncAction::SyncWeather,
-            ] {
-                Self::new(*action, &[])
-                    .process_sync_opts(&config, &pool, &stdout)
-                    .await?;
+        if opts.no_db {
+            if !opts.action.is_stateless() {
+                return Err(format_err!(
+                    "{} needs the database, drop --no-db",
+                    opts.action.to_str()
+                ));
             }
+        } else if opts.action != FileSyncAction::RunMigrations {
+            ensure_schema(&pool, !(opts.no_migrate || config.no_migrate)).await?;
+        }
+        let run_id = if opts.action != FileSyncAction::ShowRuns && !opts.no_db {
+            let mut arguments = opts.arguments();
+            arguments["command_line"] = json!(env::args().collect::<Vec<_>>());
+            opts.record_run(&config, None, arguments, &pool).await
         } else {
-            opts.process_sync_opts(&config, &pool, &stdout).await?;
+            None
+        };
+        let run_meter = run_id.map(RunMeter::start);
+
+        let renderer = opts
+            .progress
+            .then(|| spawn(render_progress(Duration::from_millis(500))));
+        let (result, partial) = track_partial(GDriveSessions::scope(async {
+            if opts.action == FileSyncAction::SyncAll {
+                for action in &[
+                    FileSyncAction::Sync,
+                    FileSyncAction::SyncGarmin,
+                    FileSyncAction::SyncMovie,
+                    FileSyncAction::SyncCalendar,
+                    FileSyncAction::SyncSecurity,
+                    FileSyncAction::SyncWeather,
+                ] {
+                    Self::new(*action, &[])
+                        .process_sync_opts(&config, &pool, &stdout)
+                        .await?;
+                }
+                Ok(())
+            } else {
+                opts.process_sync_opts(&config, &pool, &stdout).await
+            }
+        }))
+        .await;
+        if let Some(renderer) = renderer {
+            renderer.abort();
+            eprintln!("\r{}", ProgressChannel::global().get());
+        }
+        EventHook::close().await;
+        if let Some(run_meter) = run_meter {
+            if let Err(e) = run_meter.finish(&pool).await {
+                warn!("failed to record transfer bytes {e}");
+            }
+        }
+        if let Some(run_id) = run_id {
+            let status = SyncJobStatus::of_run(result.is_err(), partial);
+            if let Err(e) = RunParameters::set_status(run_id, status, &pool).await {
+                warn!("failed to record run status {e}");
+            }
+        }
+        for line in QueryStats::global().report() {
+            debug!("{line}");
+        }
+        result.map(|()| stdout)
+    }
+
+    /// The options as recorded in `run_parameters`
+    #[must_use]
+    pub fn arguments(&self) -> Value {
+        json!({
+            "action": self.action.to_str(),
+            "urls": self.urls.iter().map(Url::as_str).collect::<Vec<_>>(),
+            "offset": self.offset,
+            "limit": self.limit,
+            "sort": self.sort.map(SortKey::to_str),
+            "name": self.name,
+            "show_deleted": self.show_deleted,
+            "filename": self.filename,
+            "as_of": self.as_of.map(|d| d.to_string()),
+            "dry_run": self.dry_run,
+            "tags": self.tags,
+            "ignore_errors": self.ignore_errors,
+            "ownership_map": self.ownership_map,
+            "snapshot_path_prefix": self.snapshot_path_prefix,
+            "conflict_policy": self.conflict_policy.map(ConflictPolicy::to_str),
+            "once": self.once,
+            "pattern": self.pattern,
+            "progress": self.progress,
+            "hard": self.hard,
+            "no_migrate": self.no_migrate,
+            "compression": self.compression,
+            "compression_min_size": self.compression_min_size,
+            "recursive": self.recursive,
+            "no_db": self.no_db,
+            "max_index_age": self.max_index_age,
+            "deletion_policy": self.deletion_policy.map(DeletionPolicy::to_str),
+            "keep": self.keep,
+            "dst_layout": self.dst_layout.map(DestinationLayout::to_str),
+            "hash_format": self.hash_format.map(HashFormat::to_str),
+            "log_level": self.log_level.map(LevelFilter::as_str),
+            "max_runtime": self.max_runtime.map(|d| d.as_secs()),
+            "storage_class": self.storage_class,
+            "sse": self.sse,
+            "sse_kms_key_id": self.sse_kms_key_id,
+            "repair": self.repair,
+        })
+    }
+
+    #[must_use]
+    pub fn pagination(&self) -> Pagination {
+        Pagination::new(self.offset, self.limit, self.sort)
+    }
+
+    /// `--sse` only applies to s3 destinations, and a key only to SSE-KMS
+    fn check_sse(&self, dst_url: &str) -> Result<(), Error> {
+        if self.sse.is_some() && !dst_url.starts_with("s3://") {
+            return Err(format_err!(
+                "Server side encryption is only supported for s3 destinations"
+            ));
+        }
+        if self.sse_kms_key_id.is_some() && !self.sse.as_deref().map_or(false, is_kms_sse) {
+            return Err(format_err!("--sse-kms-key-id needs --sse aws:kms"));
+        }
+        Ok(())
+    }
+
+    /// End of the `--max-runtime` budget, counted from now
+    fn deadline(&self) -> Option<Instant> {
+        self.max_runtime.map(|d| Instant::now() + d)
+    }
+
+    /// The urls given, or the local sides of every enabled config
+    async fn local_urls(&self, pool: &PgPool) -> Result<Vec<Url>, Error> {
+        if !self.urls.is_empty() {
+            return Ok(self.urls.clone());
+        }
+        let configs: Vec<_> = FileSyncConfig::get_config_list(pool)
+            .await?
+            .try_filter(|v| future::ready(v.enabled))
+            .try_collect()
+            .await?;
+        let mut urls: Vec<Url> = Vec::new();
+        for v in configs {
+            urls.push(v.src_url.parse()?);
+            urls.push(v.dst_url.parse()?);
+        }
+        urls.retain(|u| u.scheme() == "file");
+        urls.sort();
+        urls.dedup();
+        Ok(urls)
+    }
+
+    /// `list`: the urls in byte-wise order, so that successive runs print
+    /// the same lines.  Object stores list their keys in that order and are
+    /// printed as they stream, other backends are read from the cache in the
+    /// db's `C` collation.
+    async fn list_by_path(
+        &self,
+        pagination: &Pagination,
+        config: &Config,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        let window = pagination.window();
+        'outer: for urls in group_urls(&self.urls).values() {
+            let mut flist = FileList::from_url(&urls[0], config, pool).await?;
+            for url in urls {
+                if window.is_done() {
+                    break 'outer;
+                }
+                flist.set_baseurl(url.clone());
+                if flist.get_servicetype().lists_in_byte_order() {
+                    flist.print_list(stdout, &window).await?;
+                    continue;
+                }
+                let mut entries = Box::pin(
+                    FileInfoCache::get_by_prefix_sorted(
+                        url.as_str(),
+                        flist.get_servicesession().as_str(),
+                        flist.get_servicetype().to_str(),
+                        pool,
+                    )
+                    .await?,
+                );
+                while let Some(entry) = entries.try_next().await? {
+                    if window.admit() {
+                        stdout.send(entry.urlname);
+                    }
+                    if window.is_done() {
+                        break 'outer;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `list --sort mtime|size|name`: the cached entries under the urls,
+    /// sorted and windowed, as the backends only list paths
+    async fn list_sorted(
+        &self,
+        pagination: &Pagination,
+        config: &Config,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        for urls in group_urls(&self.urls).values() {
+            let flist = FileList::from_url(&urls[0], config, pool).await?;
+            entries.extend(
+                flist
+                    .load_file_list(self.show_deleted)
+                    .await?
+                    .into_iter()
+                    .filter(|f| urls.iter().any(|u| f.urlname.starts_with(u.as_str()))),
+            );
+        }
+        for entry in pagination.apply(entries)? {
+            stdout.send(entry.urlname);
+        }
+        Ok(())
+    }
+
+    /// Source and mirror of `link_farm`, those of config `--name` or the
+    /// urls given
+    async fn link_farm_urls(&self, pool: &PgPool) -> Result<Vec<Url>, Error> {
+        if let Some(name) = &self.name {
+            let conf = FileSyncConfig::get_by_name(pool, name)
+                .await?
+                .ok_or_else(|| format_err!("Name does not exist"))?;
+            Ok(vec![conf.src_url.parse()?, conf.dst_url.parse()?])
+        } else {
+            Ok(self.urls.clone())
+        }
+    }
+
+    /// Store the effective parameters of this run, a failure is only logged
+    /// so that it never stops the run itself (e.g. before `run-migrations`).
+    /// Returns the id of the `run_parameters` row.
+    pub async fn record_run(
+        &self,
+        config: &Config,
+        job_id: Option<Uuid>,
+        arguments: Value,
+        pool: &PgPool,
+    ) -> Option<Uuid> {
+        let parameters =
+            RunParameters::new(self.action, job_id, arguments, config.behavior_settings());
+        match parameters.insert(pool).await {
+            Ok(()) => Some(parameters.id),
+            Err(e) => {
+                warn!("failed to record run parameters {e}");
+                None
+            }
         }
-        Ok(stdout)
     }
 
     /// # Errors
@@ -125,34 +670,52 @@ impl SyncOpts {
         pool: &PgPool,
         stdout: &StdoutChannel<StackString>,
     ) -> Result<(), Error> {
+        if self.action.is_automated() && MaintenanceMode::is_enabled(pool).await? {
+            stdout.send(format_sstr!(
+                "Maintenance mode enabled, skipping {:?}",
+                self.action
+            ));
+            return Ok(());
+        }
         match self.action {
             FileSyncAction::Index => {
                 let url_list: Vec<_>;
                 let urls = if self.urls.is_empty() {
-                    url_list = FileSyncConfig::get_url_list(pool).await?;
+                    url_list = FileSyncConfig::get_url_list(pool, &self.tags).await?;
                     &url_list
                 } else {
                     &self.urls
                 };
                 info!("urls: {:?}", urls);
+                let snapshots = FileSyncConfig::get_snapshot_paths(pool).await?;
+                let snapshots = &snapshots;
                 let futures = urls.iter().map(|url| {
                     let pool = pool.clone();
                     async move {
-                        let flist = FileList::from_url(url, config, &pool).await?;
-                        let number_updated = flist.update_file_cache().await?;
+                        let flist =
+                            FileList::from_url_with_snapshots(url, snapshots, config, &pool)
+                                .await?;
+                        let number_updated = flist.index().await?;
                         info!("indexed {url} updated {number_updated}");
                         Ok(())
                     }
                 });
                 let result: Result<Vec<()>, Error> = try_join_all(futures).await;
                 result?;
+                if let Err(e) = notify_checksum_webhooks(config, pool).await {
+                    warn!("checksum webhooks failed {e}");
+                }
                 Ok(())
             }
             FileSyncAction::Sync => {
-                let urls = if self.urls.is_empty() || self.name.is_some() {
+                let configs = if self.urls.is_empty() || self.name.is_some() {
+                    // entries queued on other nodes are theirs to clear
+                    let node_id = config.node_id();
+                    let node_id = &node_id;
                     let result: Result<(), Error> = FileSyncCache::get_cache_list(pool)
                         .await?
                         .map_err(Into::into)
+                        .try_filter(|v| future::ready(v.runs_on(node_id)))
                         .try_for_each(|v| async move {
                             v.delete_cache_entry(pool).await?;
                             Ok(())
@@ -163,37 +726,142 @@ impl SyncOpts {
                         let v = FileSyncConfig::get_by_name(pool, name)
                             .await?
                             .ok_or_else(|| format_err!("Name does not exist"))?;
-                        let u0: Url = v.src_url.parse()?;
-                        let u1: Url = v.dst_url.parse()?;
-                        vec![u0, u1]
+                        if !v.enabled {
+                            return Err(format_err!("Config {name} is disabled"));
+                        }
+                        Some(vec![v])
                     } else {
-                        FileSyncConfig::get_url_list(pool).await?
+                        let configs: Vec<_> =
+                            FileSyncConfig::get_config_list_by_tags(pool, &self.tags, None, None)
+                                .await?
+                                .try_filter(|v| future::ready(v.enabled))
+                                .try_collect()
+                                .await?;
+                        Some(configs)
+                    }
+                } else {
+                    None
+                };
+                // one entry per src / dst pair, urls given on the command line
+                // are always indexed
+                let mut pair_configs: Vec<Option<&FileSyncConfig>> = Vec::new();
+                let mut log_scopes: Vec<Option<Arc<LogScope>>> = Vec::new();
+                let urls = if let Some(configs) = &configs {
+                    let run = OffsetDateTime::now_utc();
+                    let mut urls: Vec<Url> = Vec::new();
+                    for v in configs {
+                        let log_scope = LogScope::for_config(v, config, run)?;
+                        for (src, dst) in config_pairs(&v.src_url, &v.dst_url, pool).await? {
+                            urls.push(src);
+                            urls.push(dst);
+                            pair_configs.push(Some(v));
+                            log_scopes.push(log_scope.clone());
+                        }
                     }
+                    urls
                 } else {
                     self.urls.clone()
                 };
                 debug!("Check 0");
 
+                let probe_timeout = Duration::from_secs(config.probe_timeout);
+                let snapshots = FileSyncConfig::get_snapshot_paths(pool).await?;
+                let snapshots = &snapshots;
                 let futures = urls.into_iter().map(|url| {
                     let pool = pool.clone();
                     async move {
-                        let flist = FileList::from_url(&url, config, &pool).await?;
-                        debug!("start {url}");
-                        let number_updated = flist.update_file_cache().await?;
-                        debug!("cached {url} updated {number_updated}");
-                        Ok(flist)
+                        let flist =
+                            FileList::from_url_with_snapshots(&url, snapshots, config, &pool)
+                                .await?;
+                        let reachable = match timeout(probe_timeout, flist.probe()).await {
+                            Ok(Ok(())) => true,
+                            Ok(Err(e)) => {
+                                warn!("{url} is unreachable {e}");
+                                false
+                            }
+                            Err(_) => {
+                                warn!("{url} probe timed out");
+                                false
+                            }
+                        };
+                        Ok((flist, reachable))
                     }
                 });
                 let results: Result<Vec<_>, Error> = try_join_all(futures).await;
                 let flists = results?;
                 debug!("Check 1");
-                let futures = flists.chunks(2).map(|f| async move {
-                    if f.len() == 2 {
-                        FileSync::compare_lists(&(*f[0]), &(*f[1]), pool).await?;
-                    }
-                    Ok(())
+                let pair_configs = &pair_configs;
+                let log_scopes = &log_scopes;
+                let futures = flists.chunks(2).enumerate().map(|(i, f)| {
+                    let log_scope = log_scopes.get(i).cloned().flatten();
+                    with_log_scope(log_scope, async move {
+                        let conf = pair_configs.get(i).copied().flatten();
+                        let max_index_age = conf.and_then(|c| c.max_index_age);
+                        match f {
+                            [(flist0, true), (flist1, true)] => {
+                                for flist in [flist0, flist1] {
+                                    if !FileSync::needs_index(&(**flist), max_index_age, pool)
+                                        .await?
+                                    {
+                                        stdout.send(format_sstr!(
+                                            "skipping index of {}, indexed within {}s",
+                                            flist.get_baseurl(),
+                                            max_index_age.unwrap_or(0)
+                                        ));
+                                        continue;
+                                    }
+                                    debug!("start {}", flist.get_baseurl());
+                                    let number_updated = flist.index().await?;
+                                    debug!(
+                                        "cached {} updated {number_updated}",
+                                        flist.get_baseurl()
+                                    );
+                                }
+                                FileSync::follow_moves(
+                                    &(**flist0),
+                                    &(**flist1),
+                                    conf,
+                                    pool,
+                                    stdout,
+                                )
+                                .await?;
+                                FileSync::propagate_deletions(
+                                    &(**flist0),
+                                    &(**flist1),
+                                    conf,
+                                    pool,
+                                    stdout,
+                                )
+                                .await?;
+                                let violations =
+                                    FileSync::compare_lists(&(**flist0), &(**flist1), conf, pool)
+                                        .await?;
+                                for violation in &violations {
+                                    stdout.send(format_sstr!("{violation}"));
+                                }
+                                if !violations.is_empty() {
+                                    stdout.send(format_sstr!(
+                                        "skipped {} files with invalid destination paths",
+                                        violations.len()
+                                    ));
+                                }
+                            }
+                            [(flist0, _), (flist1, _)] => {
+                                stdout.send(format_sstr!(
+                                    "skipping {} {}, endpoint unreachable",
+                                    flist0.get_baseurl(),
+                                    flist1.get_baseurl()
+                                ));
+                            }
+                            _ => {}
+                        }
+                        Ok(())
+                    })
                 });
-                let results: Result<Vec<()>, Error> = try_join_all(futures).await;
+                let results: Result<Vec<()>, Error> = stream::iter(futures)
+                    .buffer_unordered(config.sync_parallel_pairs.max(1))
+                    .try_collect()
+                    .await;
                 results?;
                 debug!("Check 2");
                 let mut stream = Box::pin(FileSyncCache::get_cache_list(pool).await?);
@@ -201,21 +869,38 @@ impl SyncOpts {
                     let buf = format_sstr!("{} {}", entry.src_url, entry.dst_url);
                     stdout.send(buf);
                 }
+                for v in configs.iter().flatten() {
+                    v.update_last_run(pool).await?;
+                }
+                if let Err(e) = notify_checksum_webhooks(config, pool).await {
+                    warn!("checksum webhooks failed {e}");
+                }
                 Ok(())
             }
             FileSyncAction::Copy => {
                 if self.urls.len() < 2 {
                     Err(format_err!("Need 2 Urls"))
+                } else if self.recursive {
+                    let flist0 = FileList::from_url(&self.urls[0], config, pool).await?;
+                    let flist1 = FileList::from_url(&self.urls[1], config, pool).await?;
+                    let copied = FileSync::copy_stateless(&(*flist0), &(*flist1), stdout).await?;
+                    stdout.send(format_sstr!("copied {copied} files"));
+                    Ok(())
                 } else {
                     let finfo0 = FileInfo::from_url(&self.urls[0])?;
                     let finfo1 = FileInfo::from_url(&self.urls[1])?;
 
-                    if finfo1.servicetype == FileService::Local {
-                        let flist = FileList::from_url(&self.urls[0], config, pool).await?;
-                        FileSync::copy_object(&(*flist), &finfo0, &finfo1).await?;
+                    let flist = if finfo1.servicetype == FileService::Local {
+                        FileList::from_url(&self.urls[0], config, pool).await?
                     } else {
-                        let flist = FileList::from_url(&self.urls[1], config, pool).await?;
-                        FileSync::copy_object(&(*flist), &finfo0, &finfo1).await?;
+                        FileList::from_url(&self.urls[1], config, pool).await?
+                    };
+                    if self.no_db {
+                        FileSync::copy_plain(&(*flist), &finfo0, &finfo1).await?;
+                    } else {
+                        let encryption = Encryption::from_config(config)?;
+                        FileSync::copy_object(&(*flist), &finfo0, &finfo1, encryption.as_ref())
+                            .await?;
                     }
                     Ok(())
                 }
@@ -224,27 +909,128 @@ impl SyncOpts {
                 if self.urls.is_empty() {
                     Err(format_err!("Need at least 1 Url"))
                 } else {
-                    for urls in group_urls(&self.urls).values() {
-                        let mut flist = FileList::from_url(&urls[0], config, pool).await?;
-                        for url in urls {
-                            flist.set_baseurl(url.clone());
-                            flist.print_list(stdout).await?;
-                        }
+                    let pagination = Pagination {
+                        sort: Some(self.sort.unwrap_or(SortKey::Path)),
+                        ..self.pagination()
+                    };
+                    if pagination.sort == Some(SortKey::Path) {
+                        self.list_by_path(&pagination, config, pool, stdout).await
+                    } else {
+                        self.list_sorted(&pagination, config, pool, stdout).await
                     }
+                }
+            }
+            FileSyncAction::Requeue => {
+                if self.urls.len() == 2 {
+                    FileSyncCache::cache_sync(
+                        pool,
+                        self.urls[0].as_str(),
+                        self.urls[1].as_str(),
+                        FileSyncCache::PRIORITY_INTERACTIVE,
+                        &config.node_id(),
+                    )
+                    .await?;
+                    stdout.send(format_sstr!("{} {}", self.urls[0], self.urls[1]));
                     Ok(())
+                } else {
+                    Err(format_err!("Need exactly 2 Urls"))
                 }
             }
+            FileSyncAction::CacheDelete
+            | FileSyncAction::CacheApprove
+            | FileSyncAction::CacheRequeue => {
+                let pattern = self
+                    .pattern
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need --pattern"))?;
+                let action = match self.action {
+                    FileSyncAction::CacheDelete => BulkAction::Delete,
+                    FileSyncAction::CacheApprove => BulkAction::Approve,
+                    _ => BulkAction::Requeue,
+                };
+                CacheFilter::new(pattern)?
+                    .apply(action, self.dry_run, config, pool, stdout)
+                    .await?;
+                Ok(())
+            }
             FileSyncAction::Process => {
-                let fsync = FileSync::new(config.clone());
-                fsync.process_sync_cache(pool).await?;
+                let fsync = FileSync::new(config.clone()).with_deadline(self.deadline());
+                fsync.process_sync_cache(pool, stdout).await?;
+                Ok(())
+            }
+            FileSyncAction::MigrateSessions => {
+                for (servicetype, servicesession) in ServiceSessionEntry::get_unlinked(pool).await?
+                {
+                    let session: ServiceSession = servicesession.parse()?;
+                    let (account, endpoint) = session.identity(servicetype.parse()?);
+                    let entry = ServiceSessionEntry::get_or_create(
+                        &servicetype,
+                        &servicesession,
+                        &account,
+                        &endpoint,
+                        pool,
+                    )
+                    .await?;
+                    let linked = entry.link_cache(pool).await?;
+                    stdout.send(format_sstr!(
+                        "{servicetype} {servicesession} {account} {endpoint} {linked}"
+                    ));
+                }
                 Ok(())
             }
+            FileSyncAction::RenameSession => {
+                if self.urls.len() == 2 {
+                    let flist0 = FileList::from_url(&self.urls[0], config, pool).await?;
+                    let flist1 = FileList::from_url(&self.urls[1], config, pool).await?;
+                    let servicetype = flist0.get_servicetype();
+                    if servicetype != flist1.get_servicetype() {
+                        return Err(format_err!("Can't rename across service types"));
+                    }
+                    let old_session = flist0.get_servicesession();
+                    let new_session = flist1.get_servicesession();
+                    let mut entry = ServiceSessionEntry::get_by_session(
+                        servicetype.to_str(),
+                        old_session.as_str(),
+                        pool,
+                    )
+                    .await?
+                    .ok_or_else(|| {
+                        format_err!("Unknown session {old_session:?}, run migrate_sessions")
+                    })?;
+                    let (account, endpoint) = new_session.identity(servicetype);
+                    let updated = entry
+                        .rename(new_session.as_str(), &account, &endpoint, pool)
+                        .await?;
+                    stdout.send(format_sstr!(
+                        "{} -> {} {updated}",
+                        old_session.as_str(),
+                        new_session.as_str()
+                    ));
+                    Ok(())
+                } else {
+                    Err(format_err!("Need exactly 2 Urls"))
+                }
+            }
+            FileSyncAction::SelfTest => {
+                if self.urls.len() == 1 {
+                    let test = SelfTest::new(config.clone(), pool.clone(), &self.urls[0])?;
+                    test.run(stdout).await
+                } else {
+                    Err(format_err!("Need exactly 1 Url"))
+                }
+            }
+            FileSyncAction::TransferWorker => {
+                let fsync = FileSync::new(config.clone()).with_deadline(self.deadline());
+                fsync.run_transfer_worker(pool, stdout, self.once).await
+            }
             FileSyncAction::Delete => {
                 if self.urls.is_empty() {
                     Err(format_err!("Need at least 1 Url"))
                 } else {
                     let fsync = FileSync::new(config.clone());
-                    fsync.delete_files(&self.urls, pool).await?;
+                    fsync
+                        .delete_files(&self.urls, self.hard, pool, stdout)
+                        .await?;
                     Ok(())
                 }
             }
@@ -298,25 +1084,16 @@ impl SyncOpts {
                         } else {
                             Box::new(tokio_stdout())
                         };
+                    let pagination = Pagination {
+                        sort: Some(self.sort.unwrap_or(SortKey::Path)),
+                        ..self.pagination()
+                    };
                     for url in &self.urls {
                         let flist = FileList::from_url(url, config, pool).await?;
-                        let list: Result<Vec<FileInfo>, Error> = flist
-                            .load_file_list(self.show_deleted)
-                            .await?
-                            .into_iter()
-                            .map(TryInto::try_into)
-                            .collect();
-                        let list = list?;
-
-                        let offset = self.offset.unwrap_or(0);
-                        let limit = self.limit.unwrap_or(list.len());
-
-                        for finfo in list
-                            .iter()
-                            .sorted_by_key(|finfo| finfo.filepath.as_path())
-                            .skip(offset)
-                            .take(limit)
-                        {
+                        let list =
+                            pagination.apply(flist.load_file_list(self.show_deleted).await?)?;
+                        for entry in list {
+                            let finfo: FileInfo = entry.try_into()?;
                             file.write_all(&serde_json::to_vec(finfo.inner())?).await?;
                             file.write_all(b"\n").await?;
                         }
@@ -332,34 +1109,684 @@ impl SyncOpts {
                         dst_url: self.urls[1].as_str().into(),
                         last_run: DateTimeWrapper::now(),
                         name: self.name.clone(),
+                        tags: self.tags.clone(),
+                        enabled: true,
+                        ignore_errors: self.ignore_errors.clone(),
+                        ownership_map: self.ownership_map.clone(),
+                        snapshot_path_prefix: self.snapshot_path_prefix.clone(),
+                        conflict_policy: self.conflict_policy.map(|p| p.to_str().into()),
+                        compression: match &self.compression {
+                            Some(s) => compression_codec(s)?.map(|c| c.to_str().into()),
+                            None => None,
+                        },
+                        compression_min_size: self.compression_min_size,
+                        max_index_age: self.max_index_age,
+                        deletion_policy: self.deletion_policy.map(|p| p.to_str().into()),
+                        dst_layout: self.dst_layout.map(|l| l.to_str().into()),
+                        log_level: self.log_level.map(|l| l.as_str().into()),
+                        storage_class: self.storage_class.clone(),
+                        sse: self.sse.clone(),
+                        sse_kms_key_id: self.sse_kms_key_id.clone(),
+                        version: 0,
+                        updated_at: DateTimeWrapper::now(),
                     };
-                    conf.insert_config(pool).await?;
+                    OwnershipMap::new(&conf.ownership_map)?;
+                    if conf.snapshot_path_prefix.is_some() && self.urls[0].scheme() != "file" {
+                        return Err(format_err!(
+                            "Snapshots are only supported for local sources"
+                        ));
+                    }
+                    if conf.storage_class.is_some() && self.urls[1].scheme() != "s3" {
+                        return Err(format_err!(
+                            "Storage classes are only supported for s3 destinations"
+                        ));
+                    }
+                    self.check_sse(&conf.dst_url)?;
+                    conf.insert_config(&cli_user(), pool).await?;
                     Ok(())
                 } else {
                     Err(format_err!("Need exactly 2 Urls"))
                 }
             }
-            FileSyncAction::ShowConfig => {
-                let entries: Vec<_> = FileSyncConfig::get_config_list(pool)
+            FileSyncAction::TagConfig => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
                     .await?
-                    .map_ok(|v| {
-                        format_sstr!("{} {} {}", v.src_url, v.dst_url, v.name.unwrap_or_default())
-                    })
-                    .try_collect()
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.tags.clone_from(&self.tags);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::IgnoreErrors => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.ignore_errors.clone_from(&self.ignore_errors);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::MapOwner => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                OwnershipMap::new(&self.ownership_map)?;
+                conf.ownership_map.clone_from(&self.ownership_map);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::Snapshot => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                if self.snapshot_path_prefix.is_some() && !conf.src_url.starts_with("file://") {
+                    return Err(format_err!(
+                        "Snapshots are only supported for local sources"
+                    ));
+                }
+                conf.snapshot_path_prefix
+                    .clone_from(&self.snapshot_path_prefix);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::ConflictPolicy => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.conflict_policy = self.conflict_policy.map(|p| p.to_str().into());
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::Compression => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                if let Some(s) = &self.compression {
+                    conf.compression = compression_codec(s)?.map(|c| c.to_str().into());
+                }
+                if self.compression_min_size.is_some() {
+                    conf.compression_min_size = self.compression_min_size;
+                }
+                conf.update_settings(&cli_user(), pool).await?;
+                stdout.send(format_sstr!(
+                    "{name} {} {}",
+                    conf.compression.as_deref().unwrap_or("none"),
+                    conf.compression_min_size.unwrap_or(0)
+                ));
+                Ok(())
+            }
+            FileSyncAction::MaxIndexAge => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.max_index_age = self.max_index_age;
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::DeletionPolicy => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.deletion_policy = self.deletion_policy.map(|p| p.to_str().into());
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::DstLayout => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.dst_layout = self.dst_layout.map(|l| l.to_str().into());
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::LogLevel => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.log_level = self.log_level.map(|l| l.as_str().into());
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::StorageClass => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                if self.storage_class.is_some() && !conf.dst_url.starts_with("s3://") {
+                    return Err(format_err!(
+                        "Storage classes are only supported for s3 destinations"
+                    ));
+                }
+                conf.storage_class.clone_from(&self.storage_class);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::Sse => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                self.check_sse(&conf.dst_url)?;
+                conf.sse.clone_from(&self.sse);
+                conf.sse_kms_key_id.clone_from(&self.sse_kms_key_id);
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::LinkFarm => {
+                let urls = self.link_farm_urls(pool).await?;
+                if urls.len() < 2 {
+                    return Err(format_err!("Need source and mirror urls"));
+                }
+                let src = local_path(&urls[0])?;
+                let base = local_path(&urls[1])?;
+                let summary = mirror_generation(&src, &base, OffsetDateTime::now_utc()).await?;
+                stdout.send(format_sstr!("{summary}"));
+                Ok(())
+            }
+            FileSyncAction::PruneGenerations => {
+                let urls = self.link_farm_urls(pool).await?;
+                let base = urls
+                    .last()
+                    .ok_or_else(|| format_err!("Need mirror url"))
+                    .and_then(local_path)?;
+                let keep = self.keep.unwrap_or(DEFAULT_GENERATIONS);
+                for path in prune_generations(&base, keep, self.dry_run).await? {
+                    stdout.send(format_sstr!("prune {}", path.display()));
+                }
+                Ok(())
+            }
+            FileSyncAction::Signature => {
+                let path = self
+                    .urls
+                    .first()
+                    .ok_or_else(|| format_err!("Need 1 Url"))
+                    .and_then(local_path)?;
+                let signature = file_signature(&path).await?;
+                stdout.send(serde_json::to_string(&signature)?);
+                Ok(())
+            }
+            FileSyncAction::Delta => {
+                if self.urls.len() != 2 {
+                    return Err(format_err!("Need source and delta urls"));
+                }
+                let filename = self
+                    .filename
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need signature file"))?;
+                let signature: Signature = serde_json::from_str(&read_to_string(filename).await?)?;
+                let src = local_path(&self.urls[0])?;
+                let delta = local_path(&self.urls[1])?;
+                let summary = file_delta(signature, &src, &delta).await?;
+                stdout.send(format_sstr!("{summary}"));
+                Ok(())
+            }
+            FileSyncAction::Patch => {
+                let path = self
+                    .urls
+                    .first()
+                    .ok_or_else(|| format_err!("Need 1 Url"))
+                    .and_then(local_path)?;
+                let filename = self
+                    .filename
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need delta file"))?;
+                let len = patch_file(&path, filename).await?;
+                stdout.send(format_sstr!("patched {} {len} bytes", path.display()));
+                Ok(())
+            }
+            FileSyncAction::Watch => {
+                let configs: Vec<_> = if let Some(name) = &self.name {
+                    let conf = FileSyncConfig::get_by_name(pool, name)
+                        .await?
+                        .ok_or_else(|| format_err!("Name does not exist"))?;
+                    vec![conf]
+                } else {
+                    FileSyncConfig::get_config_list(pool)
+                        .await?
+                        .try_filter(|v| {
+                            future::ready(v.enabled && v.src_url.starts_with("file://"))
+                        })
+                        .try_collect()
+                        .await?
+                };
+                let debounce = Duration::from_millis(config.watch_debounce_ms);
+                watch_configs(configs, config, pool, stdout, debounce).await
+            }
+            FileSyncAction::Conflicts => {
+                let conflicts = SyncConflict::get_unresolved(pool).await?;
+                for conflict in self.pagination().apply(conflicts)? {
+                    stdout.send(format_sstr!("{conflict}"));
+                }
+                Ok(())
+            }
+            FileSyncAction::ResolveConflict => {
+                if self.urls.len() == 2 {
+                    let (winner, loser) = (&self.urls[0], &self.urls[1]);
+                    if SyncConflict::resolve(winner.as_str(), loser.as_str(), pool).await? == 0 {
+                        return Err(format_err!(
+                            "No unresolved conflict between {winner} {loser}"
+                        ));
+                    }
+                    FileSyncCache::cache_sync(
+                        pool,
+                        winner.as_str(),
+                        loser.as_str(),
+                        FileSyncCache::PRIORITY_INTERACTIVE,
+                        &config.node_id(),
+                    )
                     .await?;
+                    stdout.send(format_sstr!("queued {winner} -> {loser}"));
+                    Ok(())
+                } else {
+                    Err(format_err!(
+                        "Need exactly 2 Urls, the version to keep first"
+                    ))
+                }
+            }
+            FileSyncAction::BackfillChecksums => {
+                if self.urls.is_empty() {
+                    return Err(format_err!("Need at least 1 s3 Url"));
+                }
+                for url in &self.urls {
+                    if url.scheme() != "s3" {
+                        return Err(format_err!("{url} is not an s3 url"));
+                    }
+                    let flist = FileListS3::from_url(url, config, pool).await?;
+                    let number_updated = flist.backfill_checksums(stdout).await?;
+                    stdout.send(format_sstr!("{url} {number_updated} checksums"));
+                }
+                Ok(())
+            }
+            FileSyncAction::AbortStaleUploads => {
+                let urls = if self.urls.is_empty() {
+                    let configs: Vec<_> = FileSyncConfig::get_config_list(pool)
+                        .await?
+                        .try_collect()
+                        .await?;
+                    let mut urls: Vec<Url> = Vec::new();
+                    for v in configs {
+                        for url in [&v.src_url, &v.dst_url] {
+                            let url: Url = url.parse()?;
+                            if url.scheme() == "s3" {
+                                let bucket =
+                                    url.host_str().ok_or_else(|| format_err!("No bucket"))?;
+                                urls.push(format_sstr!("s3://{bucket}").parse()?);
+                            }
+                        }
+                    }
+                    urls.sort();
+                    urls.dedup();
+                    urls
+                } else {
+                    self.urls.clone()
+                };
+                let started_before =
+                    OffsetDateTime::now_utc() - TimeDuration::days(config.stale_upload_days.into());
+                for url in &urls {
+                    if url.scheme() != "s3" {
+                        return Err(format_err!("{url} is not an s3 url"));
+                    }
+                    let flist = FileListS3::from_url(url, config, pool).await?;
+                    for upload in flist
+                        .abort_stale_uploads(started_before, self.dry_run)
+                        .await?
+                    {
+                        stdout.send(format_sstr!("abort {upload}"));
+                    }
+                }
+                // drive drops its upload sessions after a week by itself
+                if !self.dry_run {
+                    for transfer in ResumableTransfer::get_started_before(
+                        FileService::GDrive.to_str(),
+                        started_before,
+                        pool,
+                    )
+                    .await?
+                    {
+                        transfer.delete(pool).await?;
+                    }
+                }
+                Ok(())
+            }
+            FileSyncAction::Verify => {
+                for url in &self.local_urls(pool).await? {
+                    if url.scheme() != "file" {
+                        return Err(format_err!("Only local urls can be verified {url}"));
+                    }
+                    let flist = FileList::from_url(url, config, pool).await?;
+                    let coverage =
+                        verify_sample(&*flist, config.verify_sample_percent, pool, stdout).await?;
+                    stdout.send(format_sstr!("{url} {coverage}"));
+                }
+                Ok(())
+            }
+            FileSyncAction::Fsck => {
+                let mut issues = 0;
+                for url in &self.local_urls(pool).await? {
+                    if url.scheme() != "file" {
+                        return Err(format_err!("Only local urls can be checked {url}"));
+                    }
+                    let flist = FileList::from_url(url, config, pool).await?;
+                    issues += fsck(&*flist, self.repair, pool, stdout).await?.issues();
+                }
+                stdout.send(format_sstr!("{issues} issues"));
+                Ok(())
+            }
+            FileSyncAction::VirtualRoot => {
+                if let Some(name) = &self.name {
+                    if !self.urls.is_empty() {
+                        VirtualRoot::from_urls(name, &self.urls)?.save(pool).await?;
+                    }
+                    let root = VirtualRoot::get(name, pool)
+                        .await?
+                        .ok_or_else(|| format_err!("Virtual root {name} does not exist"))?;
+                    stdout.send(format_sstr!("{} {root}", virtual_url(name)));
+                } else {
+                    for member in VirtualRootMember::get_all(pool).await? {
+                        stdout.send(format_sstr!(
+                            "{} {} {} -> {}/",
+                            virtual_url(&member.name),
+                            member.position,
+                            member.src_url,
+                            member.prefix
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            FileSyncAction::ScopeSessions => {
+                let node = self.name.clone().unwrap_or_else(|| config.node_id());
+                let servicetype = FileService::Local.to_str();
+                for session in FileInfoCache::get_sessions(servicetype, pool).await? {
+                    let old_session: ServiceSession = session.parse()?;
+                    let path = match old_session.local_parts() {
+                        (None, path) => path,
+                        (Some(_), _) => continue,
+                    };
+                    // only claim directories that exist on this machine
+                    if !Path::new(path).exists() {
+                        stdout.send(format_sstr!("skip {session}, not on {node}"));
+                        continue;
+                    }
+                    let new_session = ServiceSession::local(&node, path)?;
+                    if self.dry_run {
+                        stdout.send(format_sstr!("{session} -> {}", new_session.as_str()));
+                        continue;
+                    }
+                    let existing =
+                        ServiceSessionEntry::get_by_session(servicetype, &session, pool).await?;
+                    let taken = ServiceSessionEntry::get_by_session(
+                        servicetype,
+                        new_session.as_str(),
+                        pool,
+                    )
+                    .await?
+                    .is_some();
+                    let updated = match existing {
+                        Some(mut entry) if !taken => {
+                            let (account, endpoint) = new_session.identity(FileService::Local);
+                            entry
+                                .rename(new_session.as_str(), &account, &endpoint, pool)
+                                .await?
+                        }
+                        _ => {
+                            FileInfoCache::merge_sessions(
+                                &session,
+                                new_session.as_str(),
+                                servicetype,
+                                pool,
+                            )
+                            .await?
+                        }
+                    };
+                    stdout.send(format_sstr!(
+                        "{session} -> {} {updated}",
+                        new_session.as_str()
+                    ));
+                }
+                Ok(())
+            }
+            FileSyncAction::PurgeTrash => {
+                let today = OffsetDateTime::now_utc().date();
+                let days = config.trash_retention_days;
+                for path in purge_local_trash(&config.trash_dir, today, days, self.dry_run).await? {
+                    stdout.send(format_sstr!("purge {}", path.display()));
+                }
+                for url in &self.urls {
+                    if url.scheme() != "s3" {
+                        return Err(format_err!("Only s3 buckets have a trash to purge {url}"));
+                    }
+                    let bucket = url.host_str().ok_or_else(|| format_err!("No bucket"))?;
+                    let flist = FileListS3::from_url(url, config, pool).await?;
+                    for key in purge_s3_trash(&flist.s3, bucket, today, days, self.dry_run).await? {
+                        stdout.send(format_sstr!("purge s3://{bucket}/{key}"));
+                    }
+                }
+                Ok(())
+            }
+            FileSyncAction::Trend => {
+                let since = self.as_of.map_or_else(
+                    || OffsetDateTime::now_utc() - TimeDuration::days(90),
+                    |d| d.midnight().assume_utc(),
+                );
+                let history = SessionUsage::get_history(
+                    self.name.as_ref().map(StackString::as_str),
+                    since,
+                    pool,
+                )
+                .await?;
+                for trend in UsageTrend::from_history(&history) {
+                    stdout.send(format_sstr!("{trend}"));
+                }
+                Ok(())
+            }
+            FileSyncAction::CostEstimate => {
+                let estimate = CostEstimate::from_db(config, pool).await?;
+                for line in estimate.report() {
+                    stdout.send(line);
+                }
+                Ok(())
+            }
+            FileSyncAction::Exclusions => {
+                let exclusions =
+                    GDriveExclusion::get_all(self.name.as_ref().map(StackString::as_str), pool)
+                        .await?;
+                let total = exclusions.len();
+                for e in self.pagination().apply(exclusions)? {
+                    stdout.send(format_sstr!(
+                        "{} {} {} {} {}",
+                        e.servicesession,
+                        e.gdriveid,
+                        e.reason,
+                        e.mime_type.as_deref().unwrap_or(""),
+                        e.filename,
+                    ));
+                }
+                stdout.send(format_sstr!("{total} excluded files"));
+                Ok(())
+            }
+            FileSyncAction::GDriveDuplicates => {
+                let duplicates =
+                    GDriveDuplicate::get_all(self.session.as_ref().map(StackString::as_str), pool)
+                        .await?;
+                for d in &duplicates {
+                    stdout.send(format_sstr!(
+                        "{} {} {} {}",
+                        d.servicesession,
+                        d.gdriveid,
+                        d.urlname,
+                        d.disambiguated_urlname,
+                    ));
+                }
+                stdout.send(format_sstr!("{} duplicate files", duplicates.len()));
+                Ok(())
+            }
+            FileSyncAction::ExportExclusions => {
+                let mut file: Box<dyn AsyncWrite + Unpin + Send> =
+                    if let Some(filename) = &self.filename {
+                        Box::new(File::create(&filename).await?)
+                    } else {
+                        Box::new(tokio_stdout())
+                    };
+                for e in GDriveExclusion::get_all(self.name.as_ref().map(StackString::as_str), pool)
+                    .await?
+                {
+                    file.write_all(&serde_json::to_vec(&e)?).await?;
+                    file.write_all(b"\n").await?;
+                }
+                Ok(())
+            }
+            FileSyncAction::ImportExclusions => {
+                let filename = self
+                    .filename
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need --filename"))?;
+                let mut imported = 0;
+                for line in read_to_string(filename).await?.lines() {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let mut exclusion: GDriveExclusion = serde_json::from_str(line)?;
+                    exclusion.reason.parse::<ExclusionReason>()?;
+                    if let Some(name) = &self.name {
+                        exclusion.servicesession.clone_from(name);
+                    }
+                    exclusion.upsert(pool).await?;
+                    imported += 1;
+                }
+                stdout.send(format_sstr!("imported {imported} exclusions"));
+                Ok(())
+            }
+            FileSyncAction::ExportHashes => {
+                let url = self.urls.first().ok_or_else(|| format_err!("Need 1 Url"))?;
+                let flist = FileList::from_url(url, config, pool).await?;
+                let mut file: Box<dyn AsyncWrite + Unpin + Send> =
+                    if let Some(filename) = &self.filename {
+                        Box::new(File::create(&filename).await?)
+                    } else {
+                        Box::new(tokio_stdout())
+                    };
+                let format = self.hash_format.unwrap_or_default();
+                let written = export_hashes(flist.as_ref(), format, &mut file).await?;
+                debug!("exported {written} {format} checksums under {url}");
+                Ok(())
+            }
+            FileSyncAction::ImportHashes => {
+                let url = self.urls.first().ok_or_else(|| format_err!("Need 1 Url"))?;
+                let filename = self
+                    .filename
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need --filename"))?;
+                let entries = parse_manifest(&read_to_string(filename).await?)?;
+                let flist = FileList::from_url(url, config, pool).await?;
+                let summary = import_hashes(flist.as_ref(), &entries).await?;
+                stdout.send(format_sstr!("{summary}"));
+                Ok(())
+            }
+            FileSyncAction::EnableConfig | FileSyncAction::DisableConfig => {
+                let name = self
+                    .name
+                    .as_ref()
+                    .ok_or_else(|| format_err!("Need config name"))?;
+                let mut conf = FileSyncConfig::get_by_name(pool, name)
+                    .await?
+                    .ok_or_else(|| format_err!("Name does not exist"))?;
+                conf.enabled = self.action == FileSyncAction::EnableConfig;
+                conf.update_settings(&cli_user(), pool).await?;
+                Ok(())
+            }
+            FileSyncAction::MaintenanceOn | FileSyncAction::MaintenanceOff => {
+                MaintenanceMode::set_enabled(pool, self.action == FileSyncAction::MaintenanceOn)
+                    .await
+            }
+            FileSyncAction::ShowConfig => {
+                let pagination = self.pagination();
+                let (offset, limit) = pagination.query_window();
+                let configs: Vec<_> =
+                    FileSyncConfig::get_config_list_by_tags(pool, &self.tags, offset, limit)
+                        .await?
+                        .try_collect()
+                        .await?;
+                let configs = pagination.apply_queried(configs)?;
+                let ids: Vec<_> = configs.iter().map(|v| v.id).collect();
+                let summaries = ConfigRunSummary::get_by_ids(&ids, pool).await?;
+                let now = OffsetDateTime::now_utc();
+                let entries: Vec<_> = configs
+                    .iter()
+                    .map(|v| {
+                        format_sstr!(
+                            "{:20} {:8} {:20} {:25} {:52} {:12} {} {}",
+                            v.name.as_deref().unwrap_or(""),
+                            if v.enabled { "enabled" } else { "disabled" },
+                            v.tags.join(","),
+                            v.last_run.to_string(),
+                            status_column(summaries.get(&v.id), now).as_str(),
+                            next_run_column(summaries.get(&v.id), now).as_str(),
+                            v.src_url,
+                            v.dst_url,
+                        )
+                    })
+                    .collect();
                 let clist = entries.join("\n");
                 stdout.send(clist);
                 Ok(())
             }
             FileSyncAction::ShowCache => {
-                let clist: Vec<_> = FileSyncCache::get_cache_list(pool)
+                let pagination = self.pagination();
+                let (offset, limit) = pagination.query_window();
+                let clist: Vec<_> = FileSyncCache::get_cache_list_window(pool, offset, limit)
                     .await?
-                    .map_ok(|v| {
-                        let buf = format_sstr!("{} {}", v.src_url, v.dst_url);
-                        buf
-                    })
                     .try_collect()
                     .await?;
+                let clist: Vec<_> = pagination
+                    .apply_queried(clist)?
+                    .into_iter()
+                    .map(|v| format_sstr!("{} {}", v.src_url, v.dst_url))
+                    .collect();
                 let clist = clist.join("\n");
                 stdout.send(clist);
                 Ok(())
@@ -373,7 +1800,7 @@ impl SyncOpts {
             }
             FileSyncAction::SyncMovie => {
                 let sync = MovieSync::new(config.clone())?;
-                for line in sync.run_sync().await? {
+                for line in sync.run_sync(pool).await? {
                     stdout.send(line);
                 }
                 Ok(())
@@ -400,11 +1827,86 @@ impl SyncOpts {
                 Ok(())
             }
             FileSyncAction::SyncAll => Ok(()),
+            FileSyncAction::Retention => apply_retention(config, self.dry_run, stdout).await,
+            FileSyncAction::ShowRuns => {
+                let action = self.name.as_ref().map(StackString::as_str);
+                let pagination = self.pagination();
+                let pagination = Pagination {
+                    limit: Some(pagination.limit.unwrap_or(20)),
+                    ..pagination
+                };
+                let (offset, limit) = pagination.query_window();
+                let runs = RunParameters::get_recent(action, offset, limit, pool).await?;
+                let runs = pagination.apply_queried(runs)?;
+                let ids: Vec<Uuid> = runs.iter().map(|run| run.id).collect();
+                let mut transfer_bytes: HashMap<Uuid, Vec<RunTransferBytes>> = HashMap::new();
+                for bytes in RunTransferBytes::get_by_runs(&ids, pool).await? {
+                    transfer_bytes.entry(bytes.run_id).or_default().push(bytes);
+                }
+                for run in runs {
+                    let job_id = run
+                        .job_id
+                        .map_or_else(|| "cli".into(), |id| format_sstr!("{id}"));
+                    let status = run.status.as_ref().map_or("running", StackString::as_str);
+                    stdout.send(format_sstr!(
+                        "{} {} {} {} {job_id} {status}\n  arguments {}\n  settings {}",
+                        run.created_at,
+                        run.action,
+                        run.hostname,
+                        run.version,
+                        run.arguments,
+                        run.settings,
+                    ));
+                    for bytes in transfer_bytes.get(&run.id).into_iter().flatten() {
+                        stdout.send(format_sstr!(
+                            "  bytes {} {}",
+                            bytes.servicetype,
+                            bytes.usage()
+                        ));
+                    }
+                }
+                Ok(())
+            }
             FileSyncAction::RunMigrations => {
-                let mut client = pool.get().await?;
-                migrations::runner().run_async(&mut **client).await?;
+                run_migrations(pool).await?;
+                stdout.send(format_sstr!("{}", SchemaStatus::get(pool).await?));
                 Ok(())
             }
+            FileSyncAction::DedupCache => {
+                let servicetype = FileService::Local.to_str();
+                for session in FileInfoCache::get_sessions(servicetype, pool).await? {
+                    let parsed: ServiceSession = session.parse()?;
+                    let (node, path) = parsed.local_parts();
+                    let canonical = canonical_basepath(Path::new(path));
+                    let canonical = canonical.to_string_lossy();
+                    let canonical = match node {
+                        Some(node) => ServiceSession::local(node, &canonical)?.into(),
+                        None => StackString::from(canonical.as_ref()),
+                    };
+                    if canonical != session {
+                        let merged =
+                            FileInfoCache::merge_sessions(&session, &canonical, servicetype, pool)
+                                .await?;
+                        stdout.send(format_sstr!("{session} -> {canonical} {merged}"));
+                    }
+                }
+                Ok(())
+            }
+            FileSyncAction::Restore => {
+                if self.urls.len() == 2 {
+                    let as_of = self.as_of.ok_or_else(|| format_err!("Need --as-of date"))?;
+                    let fsync = FileSync::new(config.clone());
+                    for (u0, u1) in fsync
+                        .restore_as_of(&self.urls[0], &self.urls[1], as_of, self.dry_run, pool)
+                        .await?
+                    {
+                        stdout.send(format_sstr!("{u0} {u1}"));
+                    }
+                    Ok(())
+                } else {
+                    Err(format_err!("Need exactly 2 Urls"))
+                }
+            }
         }
     }
 }
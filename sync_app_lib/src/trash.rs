@@ -0,0 +1,301 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+use time::{macros::format_description, Date, Duration};
+use tokio::fs::{copy, create_dir_all, read_dir, remove_dir_all, remove_file, rename};
+
+use crate::s3_instance::S3Instance;
+
+/// Key prefix of deleted objects within their own bucket
+pub const S3_TRASH_PREFIX: &str = "trash";
+
+/// What `sync` does with the copy of a file that was deleted (or moved to
+/// the gdrive trash) on the other side of a config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DeletionPolicy {
+    /// The copy is left alone, the deleted side's tombstone only keeps it
+    /// from being copied back
+    #[default]
+    Keep,
+    /// The copy is moved to its backend's trash
+    Trash,
+    /// The copy is removed permanently
+    Delete,
+}
+
+impl DeletionPolicy {
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Keep => "keep",
+            Self::Trash => "trash",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+impl fmt::Display for DeletionPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+impl FromStr for DeletionPolicy {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "trash" => Ok(Self::Trash),
+            "delete" => Ok(Self::Delete),
+            _ => Err(format_err!("Invalid deletion policy {s}")),
+        }
+    }
+}
+
+/// Deleted files are kept under a directory / prefix named after the day
+/// they were deleted, `YYYY-MM-DD`
+#[must_use]
+pub fn trash_date(name: &str) -> Option<Date> {
+    Date::parse(name, format_description!("[year]-[month]-[day]")).ok()
+}
+
+fn date_str(date: Date) -> StackString {
+    format_sstr!("{date}")
+}
+
+/// True once a trash entry from `date` is older than `retention_days`
+#[must_use]
+pub fn is_expired(date: Date, today: Date, retention_days: i64) -> bool {
+    date < today - Duration::days(retention_days)
+}
+
+/// `<trash_dir>/<date>/<filepath>`, the full original path is kept so
+/// files can be put back by hand
+#[must_use]
+pub fn local_trash_path(trash_dir: &Path, date: Date, filepath: &Path) -> PathBuf {
+    let relative: PathBuf = filepath
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+    trash_dir.join(date_str(date).as_str()).join(relative)
+}
+
+/// `trash/<date>/<key>` in the same bucket
+#[must_use]
+pub fn s3_trash_key(date: Date, key: &str) -> StackString {
+    format_sstr!(
+        "{S3_TRASH_PREFIX}/{}/{}",
+        date_str(date),
+        key.trim_start_matches('/')
+    )
+}
+
+/// Whether `key` is in the trash of its bucket, such keys aren't indexed
+/// unless the trash itself is
+#[must_use]
+pub fn is_s3_trash_key(key: &str) -> bool {
+    key.trim_start_matches('/')
+        .strip_prefix(S3_TRASH_PREFIX)
+        .map_or(false, |rest| rest.starts_with('/'))
+}
+
+/// `key`, or `key.1`, `key.2`... the first one for which `exists` is false,
+/// so a file trashed twice on the same day keeps both copies
+#[must_use]
+pub fn unused_trash_key(key: &str, exists: impl Fn(&str) -> bool) -> StackString {
+    let mut candidate: StackString = key.into();
+    let mut n = 0;
+    while exists(&candidate) {
+        n += 1;
+        candidate = format_sstr!("{key}.{n}");
+    }
+    candidate
+}
+
+/// Move `filepath` into the trash, copying instead if the trash is on
+/// another filesystem.  A file already trashed on `date` is kept, the new
+/// one gets a `.1`, `.2`... suffix.
+/// # Errors
+/// Return error if the file can't be moved
+pub async fn move_to_local_trash(
+    trash_dir: &Path,
+    date: Date,
+    filepath: &Path,
+) -> Result<PathBuf, Error> {
+    let first = local_trash_path(trash_dir, date, filepath);
+    let mut trash_path = first.clone();
+    let mut n = 0;
+    while trash_path.exists() {
+        n += 1;
+        let mut name = first.clone().into_os_string();
+        name.push(format_sstr!(".{n}").as_str());
+        trash_path = name.into();
+    }
+    if let Some(parent) = trash_path.parent() {
+        create_dir_all(parent).await?;
+    }
+    if rename(filepath, &trash_path).await.is_err() {
+        copy(filepath, &trash_path).await?;
+        remove_file(filepath).await?;
+    }
+    Ok(trash_path)
+}
+
+/// Remove the days of `trash_dir` older than `retention_days`, returns the
+/// directories removed (or that would be with `dry_run`)
+/// # Errors
+/// Return error if the trash directory can't be read or removed
+pub async fn purge_local_trash(
+    trash_dir: &Path,
+    today: Date,
+    retention_days: i64,
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, Error> {
+    let mut purged = Vec::new();
+    if !trash_dir.exists() {
+        return Ok(purged);
+    }
+    let mut entries = read_dir(trash_dir).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        let name = entry.file_name();
+        let expired = name
+            .to_str()
+            .and_then(trash_date)
+            .map_or(false, |date| is_expired(date, today, retention_days));
+        if expired && entry.file_type().await?.is_dir() {
+            if !dry_run {
+                remove_dir_all(entry.path()).await?;
+            }
+            purged.push(entry.path());
+        }
+    }
+    purged.sort();
+    Ok(purged)
+}
+
+/// Delete the keys under `trash/` of `bucket` older than `retention_days`,
+/// returns the keys removed (or that would be with `dry_run`)
+/// # Errors
+/// Return error if api call fails
+pub async fn purge_s3_trash(
+    s3: &S3Instance,
+    bucket: &str,
+    today: Date,
+    retention_days: i64,
+    dry_run: bool,
+) -> Result<Vec<StackString>, Error> {
+    let prefix = format_sstr!("{S3_TRASH_PREFIX}/");
+    let mut purged = Vec::new();
+    for object in s3.get_list_of_keys(bucket, Some(&prefix)).await? {
+        let key = match object.key {
+            Some(key) => key,
+            None => continue,
+        };
+        let expired = key
+            .trim_start_matches(prefix.as_str())
+            .split('/')
+            .next()
+            .and_then(trash_date)
+            .map_or(false, |date| is_expired(date, today, retention_days));
+        if expired {
+            if !dry_run {
+                s3.delete_key(bucket, &key).await?;
+            }
+            purged.push(key.into());
+        }
+    }
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use stack_string::format_sstr;
+    use std::{env::temp_dir, path::Path};
+    use time::macros::date;
+    use uuid::Uuid;
+
+    use crate::trash::{
+        is_expired, is_s3_trash_key, local_trash_path, move_to_local_trash, purge_local_trash,
+        s3_trash_key, trash_date, unused_trash_key, DeletionPolicy,
+    };
+
+    #[test]
+    fn test_deletion_policy() -> Result<(), Error> {
+        for policy in [
+            DeletionPolicy::Keep,
+            DeletionPolicy::Trash,
+            DeletionPolicy::Delete,
+        ] {
+            let parsed: DeletionPolicy = policy.to_str().parse()?;
+            assert_eq!(parsed, policy);
+        }
+        assert_eq!(DeletionPolicy::default(), DeletionPolicy::Keep);
+        assert!("remove".parse::<DeletionPolicy>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_trash_paths() {
+        let day = date!(2024 - 03 - 09);
+        assert_eq!(
+            local_trash_path(Path::new("/var/trash"), day, Path::new("/home/me/a.txt")),
+            Path::new("/var/trash/2024-03-09/home/me/a.txt")
+        );
+        assert_eq!(
+            s3_trash_key(day, "/photos/a.jpg"),
+            "trash/2024-03-09/photos/a.jpg"
+        );
+        assert!(is_s3_trash_key("trash/2024-03-09/photos/a.jpg"));
+        assert!(is_s3_trash_key("/trash/2024-03-09/photos/a.jpg"));
+        assert!(!is_s3_trash_key("trashcan/a.jpg"));
+        assert!(!is_s3_trash_key("photos/trash/a.jpg"));
+        let existing = ["trash/2024-03-09/a.jpg", "trash/2024-03-09/a.jpg.1"];
+        assert_eq!(
+            unused_trash_key("trash/2024-03-09/a.jpg", |k| existing.contains(&k)),
+            "trash/2024-03-09/a.jpg.2"
+        );
+        assert_eq!(
+            unused_trash_key("trash/2024-03-09/b.jpg", |k| existing.contains(&k)),
+            "trash/2024-03-09/b.jpg"
+        );
+        assert_eq!(trash_date("2024-03-09"), Some(day));
+        assert_eq!(trash_date("photos"), None);
+        assert!(is_expired(day, date!(2024 - 04 - 09), 30));
+        assert!(!is_expired(day, date!(2024 - 04 - 08), 30));
+    }
+
+    #[tokio::test]
+    async fn test_local_trash() -> Result<(), Error> {
+        let dir = temp_dir().join(format_sstr!("trash_{}", Uuid::new_v4()).as_str());
+        tokio::fs::create_dir_all(&dir).await?;
+        let trash_dir = dir.join("trash");
+        let file = dir.join("notes.txt");
+        tokio::fs::write(&file, b"notes").await?;
+
+        let day = date!(2024 - 03 - 09);
+        let trashed = move_to_local_trash(&trash_dir, day, &file).await?;
+        assert!(!file.exists());
+        assert_eq!(tokio::fs::read(&trashed).await?, b"notes");
+
+        tokio::fs::write(&file, b"more notes").await?;
+        let again = move_to_local_trash(&trash_dir, day, &file).await?;
+        assert_eq!(again.file_name(), Some("notes.txt.1".as_ref()));
+        assert_eq!(tokio::fs::read(&trashed).await?, b"notes");
+        assert_eq!(tokio::fs::read(&again).await?, b"more notes");
+
+        let purged = purge_local_trash(&trash_dir, date!(2024 - 03 - 20), 30, false).await?;
+        assert!(purged.is_empty());
+        let purged = purge_local_trash(&trash_dir, date!(2024 - 05 - 01), 30, true).await?;
+        assert_eq!(purged, vec![trash_dir.join("2024-03-09")]);
+        assert!(trashed.exists());
+        purge_local_trash(&trash_dir, date!(2024 - 05 - 01), 30, false).await?;
+        assert!(!trashed.exists());
+        tokio::fs::remove_dir_all(&dir).await?;
+        Ok(())
+    }
+}
@@ -1,6 +1,11 @@
+use anyhow::{format_err, Error};
 use derive_more::{AsRef, Deref, DerefMut, Display, From, Into};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
-use std::str::FromStr;
+use std::{
+    convert::{TryFrom, TryInto},
+    net::Ipv4Addr,
+    str::FromStr,
+};
 use url::Url;
 
 #[derive(Debug, Clone, From, Into, PartialEq, Eq, Deref, DerefMut, Display, AsRef)]
@@ -30,3 +35,360 @@ impl<'de> Deserialize<'de> for UrlWrapper {
         String::deserialize(deserializer).and_then(|s| s.parse().map_err(serde::de::Error::custom))
     }
 }
+
+/// Validate and normalize a url according to the rules of its scheme.
+/// # Errors
+/// Return error if the url is not valid for its scheme
+pub fn validate_url(url: Url) -> Result<Url, Error> {
+    match url.scheme() {
+        "s3" => S3Url::try_from(url).map(Into::into),
+        "gs" => {
+            if url.host_str().map_or(true, str::is_empty) {
+                Err(format_err!("No bucket in {url}"))
+            } else {
+                Ok(normalize_path(url))
+            }
+        }
+        "gdrive" => GDriveUrl::try_from(url).map(Into::into),
+        "ssh" | "sftp" => SshUrl::try_from(url).map(Into::into),
+        "smb" => SmbUrl::try_from(url).map(Into::into),
+        "file" => LocalUrl::try_from(url).map(Into::into),
+        "ipfs" => {
+            if url.host_str().map_or(true, str::is_empty) {
+                Err(format_err!("No ipfs node in {url}"))
+            } else {
+                Ok(normalize_path(url))
+            }
+        }
+        "virtual" => {
+            if url.host_str().map_or(true, str::is_empty) {
+                Err(format_err!("No virtual root name in {url}"))
+            } else if url.path().len() > 1 {
+                Err(format_err!("Virtual root {url} can't have a path"))
+            } else {
+                Ok(url)
+            }
+        }
+        scheme => Err(format_err!("Unsupported scheme {scheme} in {url}")),
+    }
+}
+
+fn normalize_path(mut url: Url) -> Url {
+    let path = url.path();
+    if path.len() > 1 && path.ends_with('/') {
+        let path = path.trim_end_matches('/').to_string();
+        url.set_path(&path);
+    }
+    url
+}
+
+fn validate_bucket_name(bucket: &str) -> Result<(), Error> {
+    if bucket.len() < 3 || bucket.len() > 63 {
+        return Err(format_err!(
+            "Bucket name {bucket} must be between 3 and 63 characters"
+        ));
+    }
+    if !bucket
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '.' || c == '-')
+    {
+        return Err(format_err!(
+            "Bucket name {bucket} may only contain lowercase letters, digits, '.' and '-'"
+        ));
+    }
+    let is_alnum = |c: Option<char>| c.map_or(false, |c| c.is_ascii_alphanumeric());
+    if !is_alnum(bucket.chars().next()) || !is_alnum(bucket.chars().last()) {
+        return Err(format_err!(
+            "Bucket name {bucket} must begin and end with a letter or digit"
+        ));
+    }
+    if bucket.contains("..") {
+        return Err(format_err!(
+            "Bucket name {bucket} must not contain adjacent periods"
+        ));
+    }
+    if bucket.parse::<Ipv4Addr>().is_ok() {
+        return Err(format_err!(
+            "Bucket name {bucket} must not be formatted as an IP address"
+        ));
+    }
+    Ok(())
+}
+
+/// `s3://<bucket>/<prefix>`
+#[derive(Debug, Clone, Into, PartialEq, Eq, Deref, Display, AsRef)]
+pub struct S3Url(Url);
+
+impl S3Url {
+    #[must_use]
+    pub fn bucket(&self) -> &str {
+        self.0.host_str().unwrap_or("")
+    }
+}
+
+impl TryFrom<Url> for S3Url {
+    type Error = Error;
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "s3" {
+            return Err(format_err!("Expected s3:// url, got {url}"));
+        }
+        let bucket = url
+            .host_str()
+            .ok_or_else(|| format_err!("No bucket in {url}"))?;
+        validate_bucket_name(bucket)?;
+        Ok(Self(normalize_path(url)))
+    }
+}
+
+impl FromStr for S3Url {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: Url = s.parse()?;
+        url.try_into()
+    }
+}
+
+/// `gdrive://<user>@<domain>/<path>`, the user and domain identify the
+/// drive session
+#[derive(Debug, Clone, Into, PartialEq, Eq, Deref, Display, AsRef)]
+pub struct GDriveUrl(Url);
+
+impl TryFrom<Url> for GDriveUrl {
+    type Error = Error;
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "gdrive" {
+            return Err(format_err!("Expected gdrive:// url, got {url}"));
+        }
+        if url.username().is_empty() {
+            return Err(format_err!(
+                "No user in {url}, expected gdrive://<user>@<domain>/<path>"
+            ));
+        }
+        if url.host_str().map_or(true, str::is_empty) {
+            return Err(format_err!(
+                "No domain in {url}, expected gdrive://<user>@<domain>/<path>"
+            ));
+        }
+        if url.port().is_some() || url.password().is_some() {
+            return Err(format_err!("Unexpected port or password in {url}"));
+        }
+        Ok(Self(normalize_path(url)))
+    }
+}
+
+impl FromStr for GDriveUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: Url = s.parse()?;
+        url.try_into()
+    }
+}
+
+/// `ssh://[<user>@]<host>[:<port>]/<path>` (or `sftp://`), an explicit
+/// default port is removed so that equivalent urls compare equal
+#[derive(Debug, Clone, Into, PartialEq, Eq, Deref, Display, AsRef)]
+pub struct SshUrl(Url);
+
+impl SshUrl {
+    pub const DEFAULT_PORT: u16 = 22;
+
+    #[must_use]
+    pub fn port(&self) -> u16 {
+        self.0.port().unwrap_or(Self::DEFAULT_PORT)
+    }
+}
+
+impl TryFrom<Url> for SshUrl {
+    type Error = Error;
+    fn try_from(mut url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "ssh" && url.scheme() != "sftp" {
+            return Err(format_err!("Expected ssh:// or sftp:// url, got {url}"));
+        }
+        if url.host_str().map_or(true, str::is_empty) {
+            return Err(format_err!("No host in {url}"));
+        }
+        if url.password().is_some() {
+            return Err(format_err!("Passwords are not supported in ssh urls"));
+        }
+        if url.port() == Some(Self::DEFAULT_PORT) {
+            url.set_port(None)
+                .map_err(|()| format_err!("Failed to set port for {url}"))?;
+        }
+        if url.path().is_empty() || url.path() == "/" {
+            return Err(format_err!("No remote path in {url}"));
+        }
+        Ok(Self(normalize_path(url)))
+    }
+}
+
+impl FromStr for SshUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: Url = s.parse()?;
+        url.try_into()
+    }
+}
+
+/// `smb://[<user>@]<server>/<share>[/<path>]`
+#[derive(Debug, Clone, Into, PartialEq, Eq, Deref, Display, AsRef)]
+pub struct SmbUrl(Url);
+
+impl SmbUrl {
+    #[must_use]
+    pub fn server(&self) -> &str {
+        self.0.host_str().unwrap_or("")
+    }
+
+    #[must_use]
+    pub fn share(&self) -> &str {
+        self.0
+            .path_segments()
+            .and_then(|mut s| s.next())
+            .unwrap_or("")
+    }
+
+    /// Path below the share, without a leading `/`
+    #[must_use]
+    pub fn path_in_share(&self) -> &str {
+        let path = self.0.path().trim_start_matches('/');
+        path.strip_prefix(self.share())
+            .unwrap_or(path)
+            .trim_start_matches('/')
+    }
+}
+
+impl TryFrom<Url> for SmbUrl {
+    type Error = Error;
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "smb" {
+            return Err(format_err!("Expected smb:// url, got {url}"));
+        }
+        if url.host_str().map_or(true, str::is_empty) {
+            return Err(format_err!("No server in {url}"));
+        }
+        if url.password().is_some() {
+            return Err(format_err!("Passwords are not supported in smb urls"));
+        }
+        if url.path().trim_matches('/').is_empty() {
+            return Err(format_err!(
+                "No share in {url}, expected smb://<server>/<share>/<path>"
+            ));
+        }
+        Ok(Self(normalize_path(url)))
+    }
+}
+
+impl FromStr for SmbUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: Url = s.parse()?;
+        url.try_into()
+    }
+}
+
+/// `file:///<absolute path>`
+#[derive(Debug, Clone, Into, PartialEq, Eq, Deref, Display, AsRef)]
+pub struct LocalUrl(Url);
+
+impl TryFrom<Url> for LocalUrl {
+    type Error = Error;
+    fn try_from(url: Url) -> Result<Self, Self::Error> {
+        if url.scheme() != "file" {
+            return Err(format_err!("Expected file:// url, got {url}"));
+        }
+        if url
+            .host_str()
+            .map_or(false, |h| !h.is_empty() && h != "localhost")
+        {
+            return Err(format_err!("Remote host not allowed in {url}"));
+        }
+        url.to_file_path()
+            .map_err(|()| format_err!("{url} is not a valid local path"))?;
+        Ok(Self(normalize_path(url)))
+    }
+}
+
+impl FromStr for LocalUrl {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url: Url = s.parse()?;
+        url.try_into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::url_wrapper::{validate_url, GDriveUrl, LocalUrl, S3Url, SmbUrl, SshUrl};
+
+    #[test]
+    fn test_s3_url() -> Result<(), Error> {
+        let url: S3Url = "s3://my-bucket.test/some/prefix/".parse()?;
+        assert_eq!(url.bucket(), "my-bucket.test");
+        assert_eq!(url.as_str(), "s3://my-bucket.test/some/prefix");
+        assert!("s3://ab/".parse::<S3Url>().is_err());
+        assert!("s3://My_Bucket/".parse::<S3Url>().is_err());
+        assert!("s3://-bucket/".parse::<S3Url>().is_err());
+        assert!("s3://my..bucket/".parse::<S3Url>().is_err());
+        assert!("s3://192.168.1.1/".parse::<S3Url>().is_err());
+        assert!("gs://my-bucket/".parse::<S3Url>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_gdrive_url() -> Result<(), Error> {
+        let url: GDriveUrl = "gdrive://user@gmail.com/My Drive/".parse()?;
+        assert_eq!(url.as_str(), "gdrive://user@gmail.com/My%20Drive");
+        assert!("gdrive://gmail.com/My Drive".parse::<GDriveUrl>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_ssh_url() -> Result<(), Error> {
+        let url: SshUrl = "ssh://user@host.example.com:22/home/user/".parse()?;
+        assert_eq!(url.as_str(), "ssh://user@host.example.com/home/user");
+        assert_eq!(url.port(), 22);
+        let url: SshUrl = "ssh://user@host.example.com:2222/home/user".parse()?;
+        assert_eq!(url.port(), 2222);
+        assert!("ssh://user@host.example.com".parse::<SshUrl>().is_err());
+        let url: SshUrl = "sftp://user@host.example.com:22/home/user".parse()?;
+        assert_eq!(url.as_str(), "sftp://user@host.example.com/home/user");
+        Ok(())
+    }
+
+    #[test]
+    fn test_smb_url() -> Result<(), Error> {
+        let url: SmbUrl = "smb://user@fileserver/Media/Movies/".parse()?;
+        assert_eq!(url.as_str(), "smb://user@fileserver/Media/Movies");
+        assert_eq!(url.server(), "fileserver");
+        assert_eq!(url.share(), "Media");
+        assert_eq!(url.path_in_share(), "Movies");
+        let url: SmbUrl = "smb://fileserver/Media".parse()?;
+        assert_eq!(url.path_in_share(), "");
+        assert!("smb://fileserver/".parse::<SmbUrl>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_local_url() -> Result<(), Error> {
+        let url: LocalUrl = "file:///tmp/./a/../b/".parse()?;
+        assert_eq!(url.as_str(), "file:///tmp/b");
+        assert!("file://remote/tmp".parse::<LocalUrl>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_url() -> Result<(), Error> {
+        let url: Url = "ftp://example.com/file".parse()?;
+        assert!(validate_url(url).is_err());
+        let url: Url = "file:///".parse()?;
+        assert_eq!(validate_url(url)?.as_str(), "file:///");
+        let url: Url = "virtual://home".parse()?;
+        assert_eq!(validate_url(url)?.as_str(), "virtual://home");
+        let url: Url = "virtual://home/Documents".parse()?;
+        assert!(validate_url(url).is_err());
+        Ok(())
+    }
+}
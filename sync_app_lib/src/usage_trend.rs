@@ -0,0 +1,147 @@
+use stack_string::{format_sstr, StackString};
+use std::fmt;
+
+use crate::models::SessionUsage;
+
+/// Growth of one servicesession between its first and last recorded usage
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsageTrend {
+    pub first: SessionUsage,
+    pub last: SessionUsage,
+}
+
+impl UsageTrend {
+    /// One trend per servicetype / servicesession, largest growth first.
+    /// `history` has to be ordered by session and time as returned by
+    /// `SessionUsage::get_history`
+    #[must_use]
+    pub fn from_history(history: &[SessionUsage]) -> Vec<Self> {
+        let mut trends: Vec<Self> = Vec::new();
+        for usage in history {
+            match trends.last_mut() {
+                Some(trend)
+                    if trend.last.servicetype == usage.servicetype
+                        && trend.last.servicesession == usage.servicesession =>
+                {
+                    trend.last = usage.clone();
+                }
+                _ => trends.push(Self {
+                    first: usage.clone(),
+                    last: usage.clone(),
+                }),
+            }
+        }
+        trends.sort_by_key(|t| -t.size_growth());
+        trends
+    }
+
+    #[must_use]
+    pub fn size_growth(&self) -> i64 {
+        self.last.total_size - self.first.total_size
+    }
+
+    #[must_use]
+    pub fn count_growth(&self) -> i64 {
+        self.last.file_count - self.first.file_count
+    }
+}
+
+impl fmt::Display for UsageTrend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let percent = if self.first.total_size > 0 {
+            format_sstr!(
+                " ({:+.1}%)",
+                100.0 * self.size_growth() as f64 / self.first.total_size as f64
+            )
+        } else {
+            StackString::new()
+        };
+        write!(
+            f,
+            "{} {} {} {} / {} files -> {} {} / {} files, {}{percent} / {:+} files",
+            self.last.servicetype,
+            self.last.servicesession,
+            self.first.created_at,
+            format_size(self.first.total_size),
+            self.first.file_count,
+            self.last.created_at,
+            format_size(self.last.total_size),
+            self.last.file_count,
+            format_signed_size(self.size_growth()),
+            self.count_growth(),
+        )
+    }
+}
+
+/// Bytes in the largest binary unit that keeps the value above 1
+#[must_use]
+pub fn format_size(bytes: i64) -> StackString {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value.abs() >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format_sstr!("{bytes} B")
+    } else {
+        format_sstr!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+fn format_signed_size(bytes: i64) -> StackString {
+    if bytes < 0 {
+        format_sstr!("-{}", format_size(-bytes))
+    } else {
+        format_sstr!("+{}", format_size(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gdrive_lib::date_time_wrapper::DateTimeWrapper;
+    use uuid::Uuid;
+
+    use crate::{
+        models::SessionUsage,
+        usage_trend::{format_size, UsageTrend},
+    };
+
+    fn usage(servicesession: &str, total_size: i64, file_count: i64) -> SessionUsage {
+        SessionUsage {
+            id: Uuid::new_v4(),
+            servicetype: "s3".into(),
+            servicesession: servicesession.into(),
+            total_size,
+            file_count,
+            created_at: DateTimeWrapper::now(),
+        }
+    }
+
+    #[test]
+    fn test_usage_trend() {
+        let history = vec![
+            usage("backup-a", 1_000, 10),
+            usage("backup-a", 1_500, 12),
+            usage("backup-b", 1 << 30, 100),
+            usage("backup-b", 3 << 30, 300),
+        ];
+        let trends = UsageTrend::from_history(&history);
+        assert_eq!(trends.len(), 2);
+        assert_eq!(trends[0].last.servicesession.as_str(), "backup-b");
+        assert_eq!(trends[0].size_growth(), 2 << 30);
+        assert_eq!(trends[1].size_growth(), 500);
+        assert_eq!(trends[1].count_growth(), 2);
+        assert!(trends[0]
+            .to_string()
+            .contains("+2.0 GiB (+200.0%) / +200 files"));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512).as_str(), "512 B");
+        assert_eq!(format_size(1536).as_str(), "1.5 KiB");
+        assert_eq!(format_size(5 << 40).as_str(), "5.0 TiB");
+    }
+}
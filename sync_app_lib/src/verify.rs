@@ -0,0 +1,440 @@
+use anyhow::{format_err, Error};
+use checksums::{hash_file, Algorithm};
+use futures::TryStreamExt;
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashSet, fmt, path::Path};
+use stdout_channel::StdoutChannel;
+use tokio::task::spawn_blocking;
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::{
+    file_info::FileStat,
+    file_list::{FileListTrait, CACHED_ENTRIES_BATCH},
+    models::{FileInfoCache, FileVerification, SyncEvent, VerifyCoverage},
+    pgpool::PgPool,
+};
+
+/// Result of checking one cached file against what is on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyOutcome {
+    /// Content matches the cached md5sum
+    Verified,
+    /// Size or mtime changed since it was indexed, left for the next index
+    Stale,
+    /// The file is cached but no longer on disk
+    Missing,
+    /// Same size and mtime as when indexed but different content
+    Mismatch,
+}
+
+impl VerifyOutcome {
+    /// Compare `entry` with the `current` stat and md5sum of its file
+    #[must_use]
+    pub fn check(entry: &FileInfoCache, current: Option<(FileStat, &str)>) -> Self {
+        match current {
+            None => Self::Missing,
+            Some((stat, md5sum)) => {
+                if stat != cached_stat(entry) {
+                    Self::Stale
+                } else if entry.md5sum.as_ref().map_or(true, |m| m.as_str() == md5sum) {
+                    Self::Verified
+                } else {
+                    Self::Mismatch
+                }
+            }
+        }
+    }
+
+    /// Anomalies are recorded as failed transfers
+    #[must_use]
+    pub fn is_anomaly(self) -> bool {
+        matches!(self, Self::Missing | Self::Mismatch)
+    }
+
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Verified => "verified",
+            Self::Stale => "stale",
+            Self::Missing => "missing",
+            Self::Mismatch => "checksum mismatch",
+        }
+    }
+}
+
+impl fmt::Display for VerifyOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+fn cached_stat(entry: &FileInfoCache) -> FileStat {
+    FileStat::new(
+        entry.filestat_st_mtime.to_offsetdatetime(),
+        entry.filestat_st_size,
+    )
+}
+
+/// Number of files checked per run when verifying `percent` of `total`, at
+/// least one file if there are any
+#[must_use]
+pub fn sample_size(total: i64, percent: f64) -> i64 {
+    if total <= 0 {
+        return 0;
+    }
+    let n = (total as f64 * percent.clamp(0.0, 100.0) / 100.0).ceil() as i64;
+    n.clamp(1, total)
+}
+
+/// Re-hash a sample of `percent` of the files of the local session of
+/// `flist` (see `FileVerification::get_sample`), anomalies are recorded in
+/// `sync_event` with the file's url as both source and destination
+/// # Errors
+/// Return error if db query fails
+pub async fn verify_sample(
+    flist: &dyn FileListTrait,
+    percent: f64,
+    pool: &PgPool,
+    stdout: &StdoutChannel<StackString>,
+) -> Result<VerifyCoverage, Error> {
+    let servicesession = flist.get_servicesession().as_str();
+    let servicetype = flist.get_servicetype().to_str();
+    let coverage = VerifyCoverage::get(servicesession, servicetype, pool).await?;
+    let limit = sample_size(coverage.total, percent);
+    let sample = FileVerification::get_sample(servicesession, servicetype, limit, pool).await?;
+    let mut anomalies = 0;
+    for entry in &sample {
+        let current = current_state(Path::new(entry.filepath.as_str())).await?;
+        let outcome = VerifyOutcome::check(
+            entry,
+            current
+                .as_ref()
+                .map(|(stat, md5sum)| (*stat, md5sum.as_str())),
+        );
+        if outcome == VerifyOutcome::Verified {
+            FileVerification::record(entry.id, pool).await?;
+        } else if outcome.is_anomaly() {
+            anomalies += 1;
+            let error = format_sstr!("verify: {outcome}");
+            SyncEvent::insert(&entry.urlname, &entry.urlname, 0, Some(&error), pool).await?;
+            stdout.send(format_sstr!("{outcome} {}", entry.urlname));
+        }
+    }
+    stdout.send(format_sstr!(
+        "{} checked {} files, {anomalies} anomalies",
+        flist.get_baseurl(),
+        sample.len()
+    ));
+    VerifyCoverage::get(servicesession, servicetype, pool).await
+}
+
+/// Drift between the cache of a local session and the filesystem found by
+/// `fsck` from stat data alone
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FsckIssue {
+    /// A live entry whose file is gone
+    Missing,
+    /// A live entry whose file's size or mtime changed, rehashed by the next
+    /// index
+    Changed,
+    /// A file on disk without a live entry, hashed by the next index
+    Untracked,
+    /// A deleted entry whose file is back on disk
+    StaleTombstone,
+}
+
+impl FsckIssue {
+    /// Compare the cached `entry` with the `current` stat of its file
+    #[must_use]
+    pub fn check(entry: &FileInfoCache, current: Option<FileStat>) -> Option<Self> {
+        match (entry.deleted_at.is_some(), current) {
+            (false, None) => Some(Self::Missing),
+            (false, Some(stat)) if stat != cached_stat(entry) => Some(Self::Changed),
+            (true, Some(_)) => Some(Self::StaleTombstone),
+            _ => None,
+        }
+    }
+
+    /// Whether `fsck --repair` can fix the issue without hashing: missing
+    /// entries are removed like the index does, tombstones are lifted when
+    /// the file has the size and mtime it had when it was deleted
+    #[must_use]
+    pub fn is_repairable(self, entry: &FileInfoCache, current: Option<FileStat>) -> bool {
+        match self {
+            Self::Missing => true,
+            Self::StaleTombstone => current == Some(cached_stat(entry)),
+            Self::Changed | Self::Untracked => false,
+        }
+    }
+
+    #[must_use]
+    pub fn to_str(self) -> &'static str {
+        match self {
+            Self::Missing => "missing",
+            Self::Changed => "changed",
+            Self::Untracked => "untracked",
+            Self::StaleTombstone => "stale tombstone",
+        }
+    }
+}
+
+impl fmt::Display for FsckIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.to_str())
+    }
+}
+
+/// Counts of the issues found by `fsck`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FsckReport {
+    pub checked: usize,
+    pub missing: usize,
+    pub changed: usize,
+    pub untracked: usize,
+    pub stale_tombstones: usize,
+    pub repaired: usize,
+}
+
+impl FsckReport {
+    fn add(&mut self, issue: FsckIssue) {
+        match issue {
+            FsckIssue::Missing => self.missing += 1,
+            FsckIssue::Changed => self.changed += 1,
+            FsckIssue::Untracked => self.untracked += 1,
+            FsckIssue::StaleTombstone => self.stale_tombstones += 1,
+        }
+    }
+
+    #[must_use]
+    pub fn issues(&self) -> usize {
+        self.missing + self.changed + self.untracked + self.stale_tombstones
+    }
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "checked {} entries, {} missing, {} changed, {} untracked, {} stale tombstones, {} \
+             repaired",
+            self.checked,
+            self.missing,
+            self.changed,
+            self.untracked,
+            self.stale_tombstones,
+            self.repaired
+        )
+    }
+}
+
+/// Cross-check the cache of the local session of `flist` against the
+/// filesystem using only stat data, much faster than `verify_sample` or an
+/// index since nothing is hashed.  Every issue is written to `stdout`, with
+/// `repair` the ones `FsckIssue::is_repairable` says are safe are fixed,
+/// the rest are left for the next index.
+/// # Errors
+/// Return error if db query fails or the directory can't be read
+pub async fn fsck(
+    flist: &dyn FileListTrait,
+    repair: bool,
+    pool: &PgPool,
+    stdout: &StdoutChannel<StackString>,
+) -> Result<FsckReport, Error> {
+    let servicesession = flist.get_servicesession().as_str();
+    let servicetype = flist.get_servicetype().to_str();
+    let mut report = FsckReport::default();
+    let mut tombstoned: HashSet<StackString> = HashSet::new();
+    for get_deleted in [false, true] {
+        let mut entries = Box::pin(
+            FileInfoCache::get_all_cached(servicesession, servicetype, pool, get_deleted).await?,
+        );
+        while let Some(entry) = entries.try_next().await? {
+            report.checked += 1;
+            let current = current_stat(Path::new(entry.filepath.as_str())).await?;
+            let issue = match FsckIssue::check(&entry, current) {
+                Some(issue) => issue,
+                None => continue,
+            };
+            report.add(issue);
+            stdout.send(format_sstr!("{issue} {}", entry.urlname));
+            if issue == FsckIssue::StaleTombstone {
+                tombstoned.insert(entry.urlname.clone());
+            }
+            if repair && issue.is_repairable(&entry, current) {
+                match issue {
+                    FsckIssue::Missing => {
+                        entry.delete(pool).await?;
+                    }
+                    _ => entry.insert(pool).await?,
+                }
+                report.repaired += 1;
+            }
+        }
+    }
+    let basepath = flist.get_basepath().to_path_buf();
+    let files = spawn_blocking(move || list_local_urls(&basepath)).await??;
+    for batch in files.chunks(CACHED_ENTRIES_BATCH) {
+        let tracked: HashSet<StackString> =
+            FileInfoCache::get_by_urlnames(batch, servicesession, servicetype, pool)
+                .await?
+                .into_iter()
+                .map(|f| f.urlname)
+                .collect();
+        for url in batch {
+            if !tracked.contains(url) && !tombstoned.contains(url) {
+                report.add(FsckIssue::Untracked);
+                stdout.send(format_sstr!("{} {url}", FsckIssue::Untracked));
+            }
+        }
+    }
+    stdout.send(format_sstr!("{} {report}", flist.get_baseurl()));
+    Ok(report)
+}
+
+/// Urls of the files under `basepath` as the index records them
+fn list_local_urls(basepath: &Path) -> Result<Vec<StackString>, Error> {
+    let mut urls = Vec::new();
+    for entry in WalkDir::new(basepath).same_file_system(true) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            continue;
+        }
+        let filepath = entry.path().canonicalize()?;
+        if filepath.is_dir() {
+            continue;
+        }
+        let url =
+            Url::from_file_path(&filepath).map_err(|e| format_err!("Failed to parse url {e:?}"))?;
+        urls.push(url.as_str().into());
+    }
+    Ok(urls)
+}
+
+async fn current_stat(path: &Path) -> Result<Option<FileStat>, Error> {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) => Ok(Some(FileStat::new(
+            metadata.modified()?.into(),
+            metadata.len() as i64,
+        ))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+async fn current_state(path: &Path) -> Result<Option<(FileStat, StackString)>, Error> {
+    let metadata = match tokio::fs::metadata(path).await {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let stat = FileStat::new(metadata.modified()?.into(), metadata.len() as i64);
+    let path = path.to_path_buf();
+    let md5sum = spawn_blocking(move || hash_file(&path, Algorithm::MD5).to_lowercase()).await?;
+    Ok(Some((stat, md5sum.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use time::macros::datetime;
+    use uuid::Uuid;
+
+    use crate::{
+        file_info::FileStat,
+        models::FileInfoCache,
+        verify::{sample_size, FsckIssue, VerifyOutcome},
+    };
+
+    #[test]
+    fn test_sample_size() {
+        assert_eq!(sample_size(0, 1.0), 0);
+        assert_eq!(sample_size(10, 1.0), 1);
+        assert_eq!(sample_size(1000, 2.5), 25);
+        assert_eq!(sample_size(10, 500.0), 10);
+    }
+
+    #[test]
+    fn test_verify_outcome() {
+        let mtime = datetime!(2024-01-01 00:00:00 UTC);
+        let entry = FileInfoCache {
+            id: Uuid::new_v4(),
+            filename: "notes.txt".into(),
+            filepath: "/tmp/notes.txt".into(),
+            urlname: "file:///tmp/notes.txt".into(),
+            md5sum: Some("6f90ebdaabef92a9f76be131037f593b".into()),
+            sha1sum: None,
+            filestat_st_mtime: mtime.into(),
+            filestat_st_size: 100,
+            serviceid: "/tmp".into(),
+            servicetype: "local".into(),
+            servicesession: "/tmp".into(),
+            created_at: mtime.into(),
+            deleted_at: None,
+            modified_at: mtime.into(),
+            storage_class: None,
+            sse: None,
+        };
+        let stat = FileStat::new(mtime, 100);
+        let check = |current| VerifyOutcome::check(&entry, current);
+        assert_eq!(
+            check(Some((stat, "6f90ebdaabef92a9f76be131037f593b"))),
+            VerifyOutcome::Verified
+        );
+        assert_eq!(
+            check(Some((stat, "d41d8cd98f00b204e9800998ecf8427e"))),
+            VerifyOutcome::Mismatch
+        );
+        assert_eq!(
+            check(Some((
+                FileStat::new(mtime, 50),
+                "d41d8cd98f00b204e9800998ecf8427e"
+            ))),
+            VerifyOutcome::Stale
+        );
+        assert_eq!(check(None), VerifyOutcome::Missing);
+        assert!(VerifyOutcome::Mismatch.is_anomaly());
+        assert!(!VerifyOutcome::Stale.is_anomaly());
+    }
+
+    #[test]
+    fn test_fsck_issue() {
+        let mtime = datetime!(2024-01-01 00:00:00 UTC);
+        let mut entry = FileInfoCache {
+            id: Uuid::new_v4(),
+            filename: "notes.txt".into(),
+            filepath: "/tmp/notes.txt".into(),
+            urlname: "file:///tmp/notes.txt".into(),
+            md5sum: Some("6f90ebdaabef92a9f76be131037f593b".into()),
+            sha1sum: None,
+            filestat_st_mtime: mtime.into(),
+            filestat_st_size: 100,
+            serviceid: "/tmp".into(),
+            servicetype: "local".into(),
+            servicesession: "/tmp".into(),
+            created_at: mtime.into(),
+            deleted_at: None,
+            modified_at: mtime.into(),
+            storage_class: None,
+            sse: None,
+        };
+        let stat = FileStat::new(mtime, 100);
+        let changed = FileStat::new(mtime, 50);
+        assert_eq!(FsckIssue::check(&entry, Some(stat)), None);
+        assert_eq!(
+            FsckIssue::check(&entry, Some(changed)),
+            Some(FsckIssue::Changed)
+        );
+        assert_eq!(FsckIssue::check(&entry, None), Some(FsckIssue::Missing));
+        assert!(FsckIssue::Missing.is_repairable(&entry, None));
+        assert!(!FsckIssue::Changed.is_repairable(&entry, Some(changed)));
+
+        entry.deleted_at = Some(mtime.into());
+        assert_eq!(FsckIssue::check(&entry, None), None);
+        assert_eq!(
+            FsckIssue::check(&entry, Some(changed)),
+            Some(FsckIssue::StaleTombstone)
+        );
+        assert!(FsckIssue::StaleTombstone.is_repairable(&entry, Some(stat)));
+        assert!(!FsckIssue::StaleTombstone.is_repairable(&entry, Some(changed)));
+    }
+}
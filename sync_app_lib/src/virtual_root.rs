@@ -0,0 +1,180 @@
+use anyhow::{format_err, Error};
+use stack_string::{format_sstr, StackString};
+use std::{collections::HashSet, fmt};
+use url::Url;
+
+use crate::{models::VirtualRootMember, pgpool::PgPool};
+
+/// Scheme of config sources that name a virtual root, `virtual://<name>`
+pub const VIRTUAL_SCHEME: &str = "virtual";
+
+/// Named, ordered list of sources synced to one destination as a single
+/// config, each source under its own prefix of the destination
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualRoot {
+    pub name: StackString,
+    pub members: Vec<VirtualRootMember>,
+}
+
+impl VirtualRoot {
+    /// Members in the order of `urls`, the prefix of each is the url's
+    /// fragment (`file:///home/me/Documents#docs`) or else the last segment
+    /// of its path
+    /// # Errors
+    /// Return error if a prefix can't be determined or is used twice
+    pub fn from_urls(name: &str, urls: &[Url]) -> Result<Self, Error> {
+        let mut prefixes = HashSet::new();
+        let mut members = Vec::new();
+        for (position, url) in urls.iter().enumerate() {
+            if url.scheme() == VIRTUAL_SCHEME {
+                return Err(format_err!("Virtual roots can't be nested {url}"));
+            }
+            let prefix: StackString = match url.fragment() {
+                Some(fragment) if !fragment.is_empty() => fragment.into(),
+                _ => url
+                    .path_segments()
+                    .and_then(|s| s.filter(|s| !s.is_empty()).last())
+                    .ok_or_else(|| format_err!("No prefix for {url}, append #<prefix>"))?
+                    .into(),
+            };
+            if prefix.contains('/') {
+                return Err(format_err!("Prefix {prefix} may not contain '/'"));
+            }
+            if !prefixes.insert(prefix.clone()) {
+                return Err(format_err!("Prefix {prefix} is used twice in {name}"));
+            }
+            let mut src_url = url.clone();
+            src_url.set_fragment(None);
+            members.push(VirtualRootMember {
+                name: name.into(),
+                position: position as i32,
+                src_url: src_url.as_str().into(),
+                prefix,
+            });
+        }
+        if members.is_empty() {
+            return Err(format_err!("Virtual root {name} needs at least 1 Url"));
+        }
+        Ok(Self {
+            name: name.into(),
+            members,
+        })
+    }
+
+    /// The pair synced for each member: the member's url and its prefix
+    /// under `dst`
+    /// # Errors
+    /// Return error if a url doesn't parse or `dst` can't hold a path
+    pub fn expand(&self, dst: &Url) -> Result<Vec<(Url, Url)>, Error> {
+        self.members
+            .iter()
+            .map(|member| {
+                let src: Url = member.src_url.parse()?;
+                let mut url = dst.clone();
+                url.path_segments_mut()
+                    .map_err(|()| format_err!("{dst} has no path"))?
+                    .pop_if_empty()
+                    .push(&member.prefix);
+                Ok((src, url))
+            })
+            .collect()
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn get(name: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let members = VirtualRootMember::get_by_name(name, pool).await?;
+        if members.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(Self {
+                name: name.into(),
+                members,
+            }))
+        }
+    }
+
+    /// # Errors
+    /// Return error if db query fails
+    pub async fn save(&self, pool: &PgPool) -> Result<(), Error> {
+        VirtualRootMember::replace(&self.name, &self.members, pool).await
+    }
+}
+
+impl fmt::Display for VirtualRoot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:", self.name)?;
+        for member in &self.members {
+            write!(f, "\n  {} -> {}/", member.src_url, member.prefix)?;
+        }
+        Ok(())
+    }
+}
+
+/// The pairs synced by a config, a `virtual://<name>` source is expanded
+/// into one pair per member of the virtual root
+/// # Errors
+/// Return error if db query fails, a url doesn't parse or the virtual root
+/// doesn't exist
+pub async fn config_pairs(
+    src_url: &str,
+    dst_url: &str,
+    pool: &PgPool,
+) -> Result<Vec<(Url, Url)>, Error> {
+    let src: Url = src_url.parse()?;
+    let dst: Url = dst_url.parse()?;
+    if src.scheme() == VIRTUAL_SCHEME {
+        let name = src
+            .host_str()
+            .ok_or_else(|| format_err!("No name in {src}"))?;
+        VirtualRoot::get(name, pool)
+            .await?
+            .ok_or_else(|| format_err!("Virtual root {name} does not exist"))?
+            .expand(&dst)
+    } else {
+        Ok(vec![(src, dst)])
+    }
+}
+
+/// `virtual://<name>`
+#[must_use]
+pub fn virtual_url(name: &str) -> StackString {
+    format_sstr!("{VIRTUAL_SCHEME}://{name}")
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use url::Url;
+
+    use crate::virtual_root::{virtual_url, VirtualRoot};
+
+    #[test]
+    fn test_virtual_root_expand() -> Result<(), Error> {
+        let urls: Vec<Url> = vec![
+            "file:///home/me/Documents".parse()?,
+            "file:///home/me/Pictures/#photos".parse()?,
+        ];
+        let root = VirtualRoot::from_urls("home", &urls)?;
+        assert_eq!(root.members[0].prefix, "Documents");
+        assert_eq!(root.members[1].prefix, "photos");
+        assert_eq!(root.members[1].src_url, "file:///home/me/Pictures/");
+        assert_eq!(root.members[1].position, 1);
+
+        let dst: Url = "s3://backup-bucket/home/".parse()?;
+        let pairs = root.expand(&dst)?;
+        assert_eq!(pairs.len(), 2);
+        assert_eq!(pairs[0].0.as_str(), "file:///home/me/Documents");
+        assert_eq!(pairs[0].1.as_str(), "s3://backup-bucket/home/Documents");
+        assert_eq!(pairs[1].1.as_str(), "s3://backup-bucket/home/photos");
+
+        let dup: Vec<Url> = vec![
+            "file:///a/Documents".parse()?,
+            "file:///b/Documents".parse()?,
+        ];
+        assert!(VirtualRoot::from_urls("dup", &dup).is_err());
+        assert!(VirtualRoot::from_urls("empty", &[]).is_err());
+        assert_eq!(virtual_url("home"), "virtual://home");
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use anyhow::{format_err, Error};
+use log::{debug, error, info};
+use notify::{recommended_watcher, Event, EventKind, RecursiveMode, Watcher};
+use stack_string::{format_sstr, StackString};
+use std::{
+    collections::BTreeSet,
+    mem::take,
+    path::{Path, PathBuf},
+    time::Duration as StdDuration,
+};
+use stdout_channel::StdoutChannel;
+use time::Duration;
+use tokio::{sync::mpsc::unbounded_channel, time::timeout};
+use url::Url;
+use walkdir::WalkDir;
+
+use crate::{
+    config::Config,
+    conflict::ConflictPolicy,
+    encryption::Encryption,
+    file_info::{FileInfo, FileInfoTrait},
+    file_info_local::FileInfoLocal,
+    file_list::{replace_baseurl, FileList, FileListTrait},
+    file_list_local::FileListLocal,
+    file_sync::FileSync,
+    models::{FileInfoCache, FileSyncConfig, SyncConflict, SyncHistory},
+    pgpool::PgPool,
+};
+
+/// Paths changed since the last push, repeated events for a path collapse
+/// into one entry
+#[derive(Debug, Default)]
+pub struct ChangeBatch {
+    paths: BTreeSet<PathBuf>,
+}
+
+impl ChangeBatch {
+    /// Only events that can change file contents or existence are kept,
+    /// access events are ignored
+    pub fn add(&mut self, event: Event) {
+        if matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(_) | EventKind::Remove(_)
+        ) {
+            self.paths.extend(event.paths);
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    pub fn take(&mut self) -> BTreeSet<PathBuf> {
+        take(&mut self.paths)
+    }
+}
+
+/// A config being watched, its local source and remote destination
+struct WatchedPair {
+    config: FileSyncConfig,
+    flist0: FileListLocal,
+    flist1: Box<dyn FileListTrait>,
+    clock_skew: Duration,
+    policy: ConflictPolicy,
+    encryption: Option<Encryption>,
+}
+
+impl WatchedPair {
+    async fn new(config: FileSyncConfig, conf: &Config, pool: &PgPool) -> Result<Self, Error> {
+        let src_url: Url = config.src_url.parse()?;
+        let dst_url: Url = config.dst_url.parse()?;
+        if src_url.scheme() != "file" {
+            return Err(format_err!(
+                "Can only watch a local src_url, not {}",
+                config.src_url
+            ));
+        }
+        let flist0 = FileListLocal::from_url(&src_url, conf, pool)?;
+        let flist1 = FileList::from_url(&dst_url, conf, pool).await?;
+        let clock_skew = flist0.clock_offset().await? - flist1.clock_offset().await?;
+        let policy = config.get_conflict_policy()?;
+        let encryption = Encryption::from_config(conf)?;
+        Ok(Self {
+            config,
+            flist0,
+            flist1,
+            clock_skew,
+            policy,
+            encryption,
+        })
+    }
+
+    /// Push `path`, or every file under it if it's a directory that was just
+    /// created or moved in
+    async fn push_path(
+        &self,
+        path: &Path,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        if !path.is_dir() {
+            return self.push_change(path, pool, stdout).await;
+        }
+        for entry in WalkDir::new(path).min_depth(1) {
+            let entry = entry?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            if let Err(e) = self.push_change(entry.path(), pool, stdout).await {
+                error!("failed to push {} {e}", entry.path().display());
+            }
+        }
+        Ok(())
+    }
+
+    /// Update the cache entry of `path` and resolve it against the
+    /// destination the way a sync would (see `FileSync::resolve_pair`),
+    /// removed files are only dropped from the cache
+    async fn push_change(
+        &self,
+        path: &Path,
+        pool: &PgPool,
+        stdout: &StdoutChannel<StackString>,
+    ) -> Result<(), Error> {
+        let session = self.flist0.get_servicesession();
+        if !path.exists() {
+            let url = Url::from_file_path(path).map_err(|e| format_err!("Bad path {e:?}"))?;
+            if let Some(entry) = FileInfoCache::get_by_urlname(&url, session.as_str(), pool).await?
+            {
+                entry.delete(pool).await?;
+                debug!("{url} removed");
+            }
+            return Ok(());
+        }
+        let finfo0 =
+            FileInfoLocal::from_path(path, Some(session.as_str().into()), Some(session.clone()))?
+                .into_finfo();
+        let cache_entry: FileInfoCache = finfo0.clone().into();
+        cache_entry.upsert(pool).await?;
+
+        let url1 = replace_baseurl(
+            &finfo0.urlname,
+            self.flist0.get_baseurl(),
+            self.flist1.get_baseurl(),
+        )?;
+        let session1 = self.flist1.get_servicesession().as_str();
+        let mut list_a_not_b = Vec::new();
+        let mut list_b_not_a = Vec::new();
+        match FileInfo::from_database(pool, &url1, session1).await? {
+            Some(finfo1) => {
+                let mtime_tolerance =
+                    Duration::milliseconds(self.flist0.get_config().mtime_tolerance_ms);
+                let node_id = self.flist0.get_config().node_id();
+                let resolution = FileSync::resolve_pair(
+                    &finfo0,
+                    &finfo1,
+                    self.policy,
+                    mtime_tolerance,
+                    self.clock_skew,
+                    &node_id,
+                    pool,
+                )
+                .await?;
+                FileSync::push_resolution(
+                    resolution,
+                    finfo0,
+                    finfo1,
+                    &mut list_a_not_b,
+                    &mut list_b_not_a,
+                    pool,
+                )
+                .await?;
+            }
+            None => list_a_not_b.push((finfo0, FileInfo::from_url(&url1)?)),
+        }
+        for (src, dst) in list_a_not_b.iter().chain(list_b_not_a.iter()) {
+            self.copy(src, dst, pool).await?;
+            stdout.send(format_sstr!("{} {}", src.urlname, dst.urlname));
+        }
+        Ok(())
+    }
+
+    /// Copy `src` over `dst`, and record the copy like the transfer step of a
+    /// sync
+    async fn copy(&self, src: &FileInfo, dst: &FileInfo, pool: &PgPool) -> Result<(), Error> {
+        // the remote side does the transfer whichever way it goes
+        let encryption = self.encryption.as_ref();
+        FileSync::copy_object(&(*self.flist1), src, dst, encryption).await?;
+        self.flist1.cleanup()?;
+        let node_id = self.flist0.get_config().node_id();
+        SyncHistory::record(
+            src.urlname.as_str(),
+            dst.urlname.as_str(),
+            src.md5sum.as_ref().map(|m| m.as_str()),
+            src.filestat.st_size,
+            &node_id,
+            pool,
+        )
+        .await?;
+        SyncConflict::resolve(src.urlname.as_str(), dst.urlname.as_str(), pool).await?;
+        if let Some(conflict) =
+            SyncConflict::take_copy(src.urlname.as_str(), dst.urlname.as_str(), pool).await?
+        {
+            SyncHistory::record(
+                conflict.src_url.as_str(),
+                conflict.dst_url.as_str(),
+                src.md5sum.as_ref().map(|m| m.as_str()),
+                src.filestat.st_size,
+                &node_id,
+                pool,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+}
+
+/// Watch the local `src_url` of each config and push changed files to its
+/// `dst_url`.  Events are collected until none arrive for `debounce`, so a
+/// file being written is only copied once it has settled.  Runs until the
+/// watcher goes away.
+/// # Errors
+/// Return error if a config can't be watched, watcher errors and failures
+/// pushing single files are only logged
+pub async fn watch_configs(
+    configs: Vec<FileSyncConfig>,
+    conf: &Config,
+    pool: &PgPool,
+    stdout: &StdoutChannel<StackString>,
+    debounce: StdDuration,
+) -> Result<(), Error> {
+    if configs.is_empty() {
+        return Err(format_err!("No configs to watch"));
+    }
+    let mut pairs = Vec::with_capacity(configs.len());
+    for config in configs {
+        pairs.push(WatchedPair::new(config, conf, pool).await?);
+    }
+
+    let (send, mut recv) = unbounded_channel();
+    let mut watcher = recommended_watcher(move |event: notify::Result<Event>| {
+        send.send(event).ok();
+    })?;
+    for pair in &pairs {
+        let basepath = pair.flist0.get_basepath();
+        watcher.watch(basepath, RecursiveMode::Recursive)?;
+        info!(
+            "watching {} for {}",
+            basepath.display(),
+            pair.config.dst_url
+        );
+    }
+
+    let mut batch = ChangeBatch::default();
+    while let Some(event) = recv.recv().await {
+        add_event(&mut batch, event);
+        loop {
+            match timeout(debounce, recv.recv()).await {
+                Ok(Some(event)) => add_event(&mut batch, event),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        if batch.is_empty() {
+            continue;
+        }
+        let paths = batch.take();
+        debug!("{} paths changed", paths.len());
+        for path in paths {
+            let pair = pairs
+                .iter()
+                .find(|pair| path.starts_with(pair.flist0.get_basepath()));
+            if let Some(pair) = pair {
+                if let Err(e) = pair.push_path(&path, pool, stdout).await {
+                    error!("failed to push {} {e}", path.display());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// A watcher error (e.g. an overflowed event queue) loses events, but
+/// shouldn't stop the watch
+fn add_event(batch: &mut ChangeBatch, event: notify::Result<Event>) {
+    match event {
+        Ok(event) => batch.add(event),
+        Err(e) => error!("watch error {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use notify::{
+        event::{AccessKind, CreateKind, ModifyKind, RemoveKind},
+        Event, EventKind,
+    };
+    use std::path::PathBuf;
+
+    use crate::watch::ChangeBatch;
+
+    #[test]
+    fn test_change_batch() {
+        let mut batch = ChangeBatch::default();
+        batch.add(Event::new(EventKind::Create(CreateKind::File)).add_path("/data/a".into()));
+        batch.add(Event::new(EventKind::Modify(ModifyKind::Any)).add_path("/data/a".into()));
+        batch.add(Event::new(EventKind::Access(AccessKind::Any)).add_path("/data/b".into()));
+        batch.add(Event::new(EventKind::Remove(RemoveKind::File)).add_path("/data/c".into()));
+        assert!(!batch.is_empty());
+        let paths: Vec<_> = batch.take().into_iter().collect();
+        assert_eq!(
+            paths,
+            vec![PathBuf::from("/data/a"), PathBuf::from("/data/c")]
+        );
+        assert!(batch.is_empty());
+    }
+}
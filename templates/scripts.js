@@ -41,14 +41,88 @@
         let url = encodeURI('/sync/search?text=' + text_form.value);
         updateMainArticle(url);
     }
+    let eventSource = null;
+    function followEvents() {
+        if (eventSource) {
+            eventSource.close();
+        }
+        eventSource = new EventSource('/sync/api/events');
+        let article = document.getElementById("main_article");
+        article.textContent = "";
+        eventSource.addEventListener("log", function append(e) {
+            let line = document.createElement("div");
+            line.textContent = JSON.parse(e.data).message;
+            article.appendChild(line);
+        });
+        eventSource.addEventListener("progress", function progress(e) {
+            document.getElementById("garminconnectoutput").textContent = JSON.parse(e.data).message;
+        });
+        eventSource.addEventListener("status", function status(e) {
+            document.getElementById("garminconnectoutput").textContent = JSON.parse(e.data).message;
+        });
+    }
+    function followSync(url) {
+        followEvents();
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.onload = function f() {
+            eventSource.close();
+            eventSource = null;
+            document.getElementById("garminconnectoutput").textContent = "done";
+        }
+        xmlhttp.open("POST", url, true);
+        xmlhttp.send(null);
+        document.getElementById("garminconnectoutput").textContent = "syncing..."
+    }
     function syncAll() {
-        updateMainArticle('/sync/sync', method="POST");
-        document.getElementById("garminconnectoutput").innerHTML = "syncing..."
+        followSync('/sync/sync');
     }
     function syncName(name) {
-        let url = '/sync/sync/' + name;
-        updateMainArticle(url, method="POST");
-        document.getElementById("garminconnectoutput").innerHTML = "syncing..."
+        followSync('/sync/sync/' + name);
+    }
+    function listJobs() {
+        let url = '/sync/list_sync_jobs';
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.open('GET', url, true);
+        xmlhttp.onload = function see_result() {
+            document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        }
+        xmlhttp.send(null);
+    }
+    function cancelJob(id) {
+        let url = '/sync/api/job/' + id + '/cancel';
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.open('POST', url, true);
+        xmlhttp.onload = function see_result() {
+            listJobs();
+        }
+        xmlhttp.send(null);
+    }
+    function listActivity() {
+        let url = '/sync/activity';
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.open('GET', url, true);
+        xmlhttp.onload = function see_result() {
+            document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        }
+        xmlhttp.send(null);
+    }
+    function listTrend() {
+        let url = '/sync/trend';
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.open('GET', url, true);
+        xmlhttp.onload = function see_result() {
+            document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        }
+        xmlhttp.send(null);
+    }
+    function listAudit() {
+        let url = '/sync/config_audit';
+        let xmlhttp = new XMLHttpRequest();
+        xmlhttp.open('GET', url, true);
+        xmlhttp.onload = function see_result() {
+            document.getElementById("main_article").innerHTML = xmlhttp.responseText;
+        }
+        xmlhttp.send(null);
     }
     function processAll() {
         updateMainArticle('/sync/proc_all', method="POST");